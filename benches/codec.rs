@@ -0,0 +1,117 @@
+//! Throughput of the message codecs, run with `cargo bench`.
+
+#[macro_use]
+extern crate criterion;
+extern crate oath2;
+
+use std::convert::TryFrom;
+
+use criterion::{black_box, Criterion, Throughput};
+use oath2::ds::codec::OfEncode;
+use oath2::ds::flow_match::{Match, MatchPayload, PayloadInPort, TlvMatch};
+use oath2::ds::flow_mod::FlowMod;
+use oath2::ds::flow_parser::parse_flow_mod;
+use oath2::ds::flow_stats::FlowStats;
+use oath2::ds::multipart::{MultipartReply, RepPayload};
+use oath2::ds::packet_in::{InReason, PacketIn};
+use oath2::ds::packet_out::BufferId;
+use oath2::ds::ports::PortNumber;
+use oath2::ds::{OfMsg, OfPayload};
+
+/// packet in of a full sized ethernet frame
+fn packet_in() -> OfMsg {
+    let packet_in = PacketIn {
+        buffer_id: BufferId::NoBuffer,
+        total_len: 1500,
+        reason: InReason::NoMatch,
+        table_id: 0,
+        cookie: 0,
+        mmatch: Match::new(vec![TlvMatch::new(MatchPayload::InPort(PayloadInPort {
+            ingress_port: PortNumber::NormalPort(1),
+        }))]),
+        ethernet_frame: (0..1500).map(|byte| byte as u8).collect(),
+    };
+    OfMsg::generate(1, OfPayload::PacketIn(packet_in))
+}
+
+/// flow mod with 10 match fields and 5 actions
+fn flow_mod() -> FlowMod {
+    parse_flow_mod(
+        "priority=100,in_port=1,eth_src=00:00:00:00:00:01,eth_dst=00:00:00:00:00:02,\
+         dl_vlan=10,tcp,nw_src=10.0.0.1,nw_dst=10.0.0.0/8,tcp_src=1024,tcp_dst=80,\
+         actions=pop_vlan,set_field:00:00:00:00:00:03->eth_dst,\
+         set_field:10.0.0.2->ipv4_dst,dec_ttl,output:2",
+    )
+    .unwrap()
+}
+
+/// body of a flow stats reply with 1000 flows like flow_mod, too long for a
+/// single message, switches split it into several replies
+fn flow_stats_reply() -> MultipartReply {
+    let flow_mod = flow_mod();
+    let stats = (0..1000)
+        .map(|i| FlowStats {
+            table_id: 0,
+            duration_sec: 60,
+            duration_nsec: 0,
+            priority: i,
+            idle_timeout: 0,
+            hard_timeout: 0,
+            flags: flow_mod.flags,
+            cookie: u64::from(i),
+            packet_count: 1000,
+            byte_count: 1_500_000,
+            mmatch: flow_mod.mmatch.clone(),
+            instructions: flow_mod.instructions.clone(),
+        })
+        .collect();
+    MultipartReply::new(RepPayload::Flow(stats))
+}
+
+fn bench_packet_in(c: &mut Criterion) {
+    let bytes = packet_in().encode().unwrap();
+    let mut group = c.benchmark_group("packet_in");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.bench_function("decode", |b| {
+        b.iter(|| OfMsg::try_from(black_box(&bytes[..])).unwrap())
+    });
+    group.bench_function("encode", |b| {
+        b.iter(|| black_box(packet_in()).encode().unwrap())
+    });
+    group.finish();
+}
+
+fn bench_flow_mod(c: &mut Criterion) {
+    let bytes: Vec<u8> = flow_mod().into();
+    let mut group = c.benchmark_group("flow_mod");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    // flow mods are only sent by controllers, OfMsg does not decode them
+    group.bench_function("decode", |b| {
+        b.iter(|| FlowMod::try_from(black_box(&bytes[..])).unwrap())
+    });
+    let flow_mod = flow_mod();
+    group.bench_function("encode", |b| {
+        b.iter(|| Into::<Vec<u8>>::into(black_box(flow_mod.clone())))
+    });
+    group.finish();
+}
+
+fn bench_flow_stats_reply(c: &mut Criterion) {
+    let reply = flow_stats_reply();
+    let bytes = reply.to_bytes();
+    let mut group = c.benchmark_group("flow_stats_reply");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.bench_function("decode", |b| {
+        b.iter(|| MultipartReply::try_from(black_box(&bytes[..])).unwrap())
+    });
+    group.bench_function("encode", |b| b.iter(|| black_box(&reply).to_bytes()));
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_packet_in,
+    bench_flow_mod,
+    bench_flow_stats_reply
+);
+criterion_main!(benches);
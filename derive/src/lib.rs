@@ -0,0 +1,286 @@
+//! Derive macros of the wire codec of oath2.
+//!
+//! `#[derive(OfWire)]` generates `OfEncode`, `TryFrom<&[u8]>` and the
+//! deprecated `Into<Vec<u8>>` of a struct whose fields are encoded one after
+//! the other, so encoding and decoding always agree on the layout. The
+//! generated code uses the paths of oath2 (`::ds`, `::err`), the macro only
+//! works inside of it, see `ds::wire` for the attributes and field types.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use syn::{
+    Attribute, Data, DeriveInput, Fields, GenericArgument, Ident, Lit, Meta, NestedMeta, Path,
+    PathArguments, Type,
+};
+
+#[proc_macro_derive(OfWire, attributes(pad, len_of, tlv, big_endian, little_endian))]
+pub fn derive_of_wire(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match of_wire(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// How a field is written.
+enum Kind {
+    /// a value of a type that implements OfField
+    Value,
+    /// the encoded length of the list field with the name
+    LenOf(Ident),
+    /// entries of a fixed length, their length is in the field with the name
+    List(Ident),
+    /// entries with their own length field that fill the rest of the message
+    Tlv(Path),
+}
+
+struct Field {
+    name: Ident,
+    ty: Type,
+    kind: Kind,
+    byte_order: TokenStream2,
+    /// zero bytes after the field
+    pad: usize,
+}
+
+fn of_wire(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => return Err(error(input, "OfWire needs a struct with named fields")),
+        },
+        _ => return Err(error(input, "OfWire can only be derived for structs")),
+    };
+    let default_order = byte_order(&input.attrs)?.unwrap_or_else(big_endian);
+
+    let mut wire_fields: Vec<Field> = Vec::new();
+    for field in fields {
+        let name = field.ident.clone().unwrap();
+        let mut kind = Kind::Value;
+        let mut pad = 0;
+        for attr in &field.attrs {
+            if attr.path.is_ident("pad") {
+                pad = match single_arg(attr)? {
+                    NestedMeta::Lit(Lit::Int(ref int)) => int.base10_parse::<usize>()?,
+                    _ => return Err(error(attr, "expected #[pad(<bytes>)]")),
+                };
+            } else if attr.path.is_ident("len_of") {
+                kind = Kind::LenOf(arg_ident(attr, "expected #[len_of(<field>)]")?);
+            } else if attr.path.is_ident("tlv") {
+                kind = match single_arg(attr)? {
+                    NestedMeta::Meta(Meta::Path(path)) => Kind::Tlv(path),
+                    _ => return Err(error(attr, "expected #[tlv(<LengthField>)]")),
+                };
+            }
+        }
+        let byte_order = byte_order(&field.attrs)?.unwrap_or_else(|| default_order.clone());
+        wire_fields.push(Field {
+            name: name,
+            ty: field.ty.clone(),
+            kind: kind,
+            byte_order: byte_order,
+            pad: pad,
+        });
+    }
+
+    // the lists are decoded after their length field
+    for i in 0..wire_fields.len() {
+        let list = match wire_fields[i].kind {
+            Kind::LenOf(ref list) => list.clone(),
+            _ => continue,
+        };
+        let position = wire_fields.iter().position(|field| field.name == list);
+        match position {
+            Some(j) if j > i => wire_fields[j].kind = Kind::List(wire_fields[i].name.clone()),
+            _ => {
+                return Err(error(
+                    &list,
+                    "len_of needs a field that comes after the length field",
+                ))
+            }
+        }
+    }
+    for (i, field) in wire_fields.iter().enumerate() {
+        if let Kind::Tlv(_) = field.kind {
+            if i + 1 != wire_fields.len() || field.pad != 0 {
+                return Err(error(&field.name, "tlv lists have to be the last field"));
+            }
+        }
+    }
+
+    let name = &input.ident;
+    let lens = wire_fields.iter().map(encoded_len);
+    let encodes = wire_fields.iter().map(encode);
+    let decodes = wire_fields.iter().map(decode);
+    let names = wire_fields.iter().map(|field| &field.name);
+    Ok(quote! {
+        impl ::ds::codec::OfEncode for #name {
+            fn encoded_len(&self) -> usize {
+                0 #(+ #lens)*
+            }
+
+            fn encode_into(&self, buf: &mut Vec<u8>) {
+                #(#encodes)*
+            }
+        }
+
+        impl<'a> ::std::convert::TryFrom<&'a [u8]> for #name {
+            type Error = ::err::Error;
+            fn try_from(bytes: &'a [u8]) -> ::err::Result<Self> {
+                let mut cursor = ::std::io::Cursor::new(bytes);
+                #(#decodes)*
+                Ok(#name { #(#names),* })
+            }
+        }
+
+        impl ::std::convert::Into<Vec<u8>> for #name {
+            fn into(self) -> Vec<u8> {
+                ::ds::codec::OfEncode::to_bytes(&self)
+            }
+        }
+    })
+}
+
+fn encoded_len(field: &Field) -> TokenStream2 {
+    let name = &field.name;
+    let len = match field.kind {
+        Kind::Value | Kind::LenOf(_) => quote!(::ds::wire::OfField::wire_len(&self.#name)),
+        Kind::List(_) => quote!(::ds::wire::list_len(&self.#name)),
+        Kind::Tlv(_) => quote!(::ds::codec::OfEncode::encoded_len(&self.#name)),
+    };
+    match field.pad {
+        0 => len,
+        pad => quote!((#len + #pad)),
+    }
+}
+
+fn encode(field: &Field) -> TokenStream2 {
+    let name = &field.name;
+    let ty = &field.ty;
+    let order = &field.byte_order;
+    let pad = field.pad;
+    let write = match field.kind {
+        Kind::Value => quote! {
+            ::ds::wire::OfField::write_field::<#order>(&self.#name, buf);
+        },
+        // the length is taken from the list, whatever the field says
+        Kind::LenOf(ref list) => quote! {
+            let len = ::ds::wire::list_len(&self.#list) as #ty;
+            ::ds::wire::OfField::write_field::<#order>(&len, buf);
+        },
+        Kind::List(_) => quote! {
+            ::ds::wire::write_list::<#order, _>(&self.#name, buf);
+        },
+        Kind::Tlv(_) => quote! {
+            ::ds::codec::OfEncode::encode_into(&self.#name, buf);
+        },
+    };
+    if pad == 0 {
+        return write;
+    }
+    quote! {
+        #write
+        buf.extend_from_slice(&[0u8; #pad]);
+    }
+}
+
+fn decode(field: &Field) -> TokenStream2 {
+    let name = &field.name;
+    let ty = &field.ty;
+    let order = &field.byte_order;
+    let pad = field.pad;
+    let read = match field.kind {
+        Kind::Value | Kind::LenOf(_) => quote! {
+            let #name = <#ty as ::ds::wire::OfField>::read_field::<#order>(&mut cursor)?;
+        },
+        Kind::List(ref len) => quote! {
+            let #name = ::ds::wire::read_list::<#order, _>(
+                &mut cursor,
+                #len as usize,
+                stringify!(#ty),
+            )?;
+        },
+        Kind::Tlv(ref length_field) => {
+            let entry = entry_type(ty);
+            quote! {
+                let #name = ::ds::tlv::decode_list(
+                    bytes,
+                    cursor.position() as usize,
+                    #length_field,
+                    stringify!(#entry),
+                )?;
+            }
+        }
+    };
+    if pad == 0 {
+        return read;
+    }
+    quote! {
+        #read
+        ::ds::wire::skip(&mut cursor, #pad);
+    }
+}
+
+/// the byte order set by #[big_endian] or #[little_endian], None if there is none
+fn byte_order(attrs: &[Attribute]) -> syn::Result<Option<TokenStream2>> {
+    let mut order = None;
+    for attr in attrs {
+        let tokens = if attr.path.is_ident("big_endian") {
+            big_endian()
+        } else if attr.path.is_ident("little_endian") {
+            quote!(::byteorder::LittleEndian)
+        } else {
+            continue;
+        };
+        if order.is_some() {
+            return Err(error(attr, "only one byte order can be set"));
+        }
+        order = Some(tokens);
+    }
+    Ok(order)
+}
+
+fn big_endian() -> TokenStream2 {
+    quote!(::byteorder::BigEndian)
+}
+
+/// T of a Vec<T>, the type itself otherwise
+fn entry_type(ty: &Type) -> &Type {
+    if let Type::Path(ref path) = *ty {
+        if let Some(segment) = path.path.segments.last() {
+            if let PathArguments::AngleBracketed(ref args) = segment.arguments {
+                if let Some(GenericArgument::Type(ref entry)) = args.args.first() {
+                    return entry;
+                }
+            }
+        }
+    }
+    ty
+}
+
+fn single_arg(attr: &Attribute) -> syn::Result<NestedMeta> {
+    match attr.parse_meta()? {
+        Meta::List(ref list) if list.nested.len() == 1 => Ok(list.nested[0].clone()),
+        _ => Err(error(attr, "expected a single argument")),
+    }
+}
+
+fn arg_ident(attr: &Attribute, message: &str) -> syn::Result<Ident> {
+    match single_arg(attr)? {
+        NestedMeta::Meta(Meta::Path(ref path)) => match path.get_ident() {
+            Some(ident) => Ok(ident.clone()),
+            None => Err(error(attr, message)),
+        },
+        _ => Err(error(attr, message)),
+    }
+}
+
+fn error<T: quote::ToTokens>(tokens: &T, message: &str) -> syn::Error {
+    syn::Error::new_spanned(tokens, message)
+}
@@ -0,0 +1,24 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate num_traits;
+extern crate oath2;
+
+use num_traits::FromPrimitive;
+use oath2::ds;
+
+// the first two bytes select version and type, the rest is the message body
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let version = match ds::Version::from_u8(data[0]) {
+        Some(version) => version,
+        None => return,
+    };
+    let ttype = match ds::Type::from_u8(data[1]) {
+        Some(ttype) => ttype,
+        None => return,
+    };
+    let _ = ds::decode_any(version, &ttype, &data[2..]);
+});
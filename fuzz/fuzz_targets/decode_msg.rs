@@ -0,0 +1,12 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate oath2;
+
+use oath2::ds;
+use std::convert::TryFrom;
+
+// complete messages as they are read from a switch connection
+fuzz_target!(|data: &[u8]| {
+    let _ = ds::OfMsg::try_from(data);
+});
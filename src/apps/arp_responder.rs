@@ -0,0 +1,216 @@
+//! Answers arp requests from the controller instead of flooding them.
+//!
+//! The responder knows the mac address of IPv4 addresses, either configured
+//! with `insert` or learned from the arp packets it sees. Requests for known
+//! addresses are answered with a crafted reply sent out of the port the
+//! request came from, other packets are left to the rest of the handler:
+//!
+//! ```ignore
+//! if responder.handle(&switch, &packet_in)? { return; }
+//! // flood or forward the packet
+//! ```
+//!
+//! `install_suppression` installs flows that send requests for the known
+//! addresses to the controller, so the switch does not flood them before.
+
+use std::collections::HashMap;
+use std::net;
+use std::sync::Mutex;
+
+use super::super::ctl::SwitchHandle;
+use super::super::ds;
+use super::super::ds::addr::MacAddr;
+use super::super::ds::flow_match::ArpOp;
+use super::super::ds::flow_mod::FlowMod;
+use super::super::ds::flow_parser::parse_flow_mod;
+use super::super::ds::packet::Arp;
+use super::super::ds::packet_in::PacketIn;
+use super::super::ds::packet_out::PacketOut;
+use super::super::err::*;
+
+/// Priority of the suppression flows, above the usual flooding flows.
+pub const DEFAULT_SUPPRESSION_PRIORITY: u16 = 40000;
+
+/// Mac addresses by IPv4 address, see the module documentation.
+#[derive(Debug, Default)]
+pub struct ArpResponder {
+    table: Mutex<HashMap<net::Ipv4Addr, MacAddr>>,
+    learn: bool,
+}
+
+impl ArpResponder {
+    /// answers only for the addresses added with insert
+    pub fn new() -> Self {
+        ArpResponder::default()
+    }
+
+    /// also learns the addresses of the senders of arp packets
+    pub fn learning() -> Self {
+        ArpResponder {
+            table: Mutex::new(HashMap::new()),
+            learn: true,
+        }
+    }
+
+    /// returns the mac the address had before
+    pub fn insert(&self, ip: net::Ipv4Addr, mac: MacAddr) -> Option<MacAddr> {
+        self.table.lock().unwrap().insert(ip, mac)
+    }
+
+    pub fn remove(&self, ip: &net::Ipv4Addr) -> Option<MacAddr> {
+        self.table.lock().unwrap().remove(ip)
+    }
+
+    pub fn lookup(&self, ip: &net::Ipv4Addr) -> Option<MacAddr> {
+        self.table.lock().unwrap().get(ip).cloned()
+    }
+
+    /// reply to the arp packet, None if it is no request or the address is unknown
+    /// learns the sender if the responder is learning
+    pub fn answer(&self, arp: &Arp) -> Option<Arp> {
+        let mut table = self.table.lock().unwrap();
+        // gratuitous and probing packets have no usable sender address
+        if self.learn && !arp.sender_ip.is_unspecified() && arp.sender_ip != arp.target_ip {
+            table.insert(arp.sender_ip, arp.sender_mac);
+        }
+        match arp.op {
+            ArpOp::Request => table.get(&arp.target_ip).map(|mac| arp.reply(*mac)),
+            _ => None,
+        }
+    }
+
+    /// answers the packet in if it is an arp request for a known address
+    /// returns false if the packet is left to the caller (no arp request, unknown address)
+    pub fn handle(&self, switch: &SwitchHandle, packet_in: &PacketIn) -> Result<bool> {
+        let arp = match Arp::from_frame(&packet_in.ethernet_frame) {
+            Ok(arp) => arp,
+            Err(_) => return Ok(false),
+        };
+        let (reply, in_port) = match (self.answer(&arp), packet_in.in_port()) {
+            (Some(reply), Some(in_port)) => (reply, in_port),
+            _ => return Ok(false),
+        };
+        debug!(
+            "Answering arp request for {} with {}.",
+            arp.target_ip, reply.sender_mac
+        );
+        let packet_out = PacketOut::output(in_port, reply.to_frame());
+        switch.send_balanced(ds::OfMsg::generate_for(
+            switch.version(),
+            switch.next_xid(),
+            ds::OfPayload::PacketOut(packet_out),
+        )?)?;
+        Ok(true)
+    }
+
+    /// flow that sends arp requests for the address to the controller
+    pub fn suppression_flow(ip: net::Ipv4Addr, priority: u16) -> FlowMod {
+        parse_flow_mod(&format!(
+            "priority={},arp,arp_op=1,arp_tpa={},actions=controller",
+            priority, ip
+        ))
+        .unwrap()
+    }
+
+    /// installs a suppression flow for every known address
+    /// returns the number of flows sent
+    pub fn install_suppression(&self, switch: &SwitchHandle, priority: u16) -> Result<usize> {
+        let ips: Vec<net::Ipv4Addr> = self.table.lock().unwrap().keys().cloned().collect();
+        for ip in &ips {
+            let flow_mod = ArpResponder::suppression_flow(*ip, priority);
+            switch.send_payload(ds::OfPayload::FlowMod(flow_mod))?;
+        }
+        Ok(ips.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ctl::version::NegotiatedVersion;
+    use super::super::super::ctl::{Metrics, ReplyRouter, XidAllocator};
+    use super::super::super::ds::factory;
+    use super::super::super::ds::flow_match::Match;
+    use super::super::super::ds::flow_parser::parse_match;
+    use super::super::super::ds::packet_in::InReason;
+    use super::super::super::ds::packet_out::BufferId;
+    use super::super::super::ds::ports::PortNumber;
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+
+    fn packet_in(arp: &Arp, mmatch: Match) -> PacketIn {
+        let frame = arp.to_frame();
+        PacketIn {
+            buffer_id: BufferId::NoBuffer,
+            total_len: frame.len() as u16,
+            reason: InReason::NoMatch,
+            table_id: 0,
+            cookie: 0,
+            mmatch: mmatch,
+            ethernet_frame: frame,
+        }
+    }
+
+    #[test]
+    fn answers_known_addresses() {
+        let (send, recv) = channel();
+        let switch = SwitchHandle::new(
+            factory::features_reply(1),
+            send,
+            Arc::new(XidAllocator::new()),
+            Arc::new(NegotiatedVersion::with_max(ds::Version::V1_3)),
+            Arc::new(ReplyRouter::new()),
+            Metrics::connection(&Arc::new(Metrics::new())),
+        );
+
+        let responder = ArpResponder::learning();
+        let host = MacAddr([2, 0, 0, 0, 0, 1]);
+        let gateway = MacAddr([2, 0, 0, 0, 0, 0xfe]);
+        responder.insert(net::Ipv4Addr::new(10, 0, 0, 254), gateway);
+        let in_port = parse_match("in_port=3").unwrap();
+
+        // unknown address, the sender is learned
+        let request = Arp::request(
+            host,
+            net::Ipv4Addr::new(10, 0, 0, 1),
+            net::Ipv4Addr::new(10, 0, 0, 2),
+        );
+        assert!(!responder
+            .handle(&switch, &packet_in(&request, in_port.clone()))
+            .unwrap());
+        assert_eq!(
+            responder.lookup(&net::Ipv4Addr::new(10, 0, 0, 1)),
+            Some(host)
+        );
+        assert!(recv.try_recv().is_err());
+
+        let request = Arp::request(
+            host,
+            net::Ipv4Addr::new(10, 0, 0, 1),
+            net::Ipv4Addr::new(10, 0, 0, 254),
+        );
+        assert!(responder
+            .handle(&switch, &packet_in(&request, in_port))
+            .unwrap());
+        let msg = recv.try_recv().unwrap();
+        match *msg.payload() {
+            ds::OfPayload::PacketOut(ref packet_out) => {
+                let reply = Arp::from_frame(&packet_out.data).unwrap();
+                assert_eq!(reply, request.reply(gateway));
+                assert_eq!(
+                    packet_out.actions,
+                    PacketOut::output(PortNumber::NormalPort(3), Vec::new()).actions
+                );
+            }
+            ref payload => panic!("unexpected payload {:?}", payload),
+        }
+
+        assert_eq!(
+            responder
+                .install_suppression(&switch, DEFAULT_SUPPRESSION_PRIORITY)
+                .unwrap(),
+            2
+        );
+        assert_eq!(recv.try_iter().count(), 2);
+    }
+}
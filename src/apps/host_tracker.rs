@@ -0,0 +1,321 @@
+//! Locations of the hosts in the network.
+//!
+//! The tracker learns the switch port of a host from the source addresses of
+//! the packet ins it sends. A host seen at another port moved, which is
+//! reported so that routes to it can follow (see `Router::host_moved`):
+//!
+//! ```ignore
+//! let location = SwitchPort::new(datapath_id, in_port);
+//! // packets between switches carry the addresses of remote hosts
+//! if !router.is_link(&location) {
+//!     if let Some(HostEvent::Moved { host, from }) = tracker.learn(datapath_id, &packet_in) {
+//!         send_flow_mods(&registry, router.host_moved(from, host.location))?;
+//!     }
+//! }
+//! ```
+//!
+//! Hosts that were not seen for the maximum age are forgotten by `expire`,
+//! hosts on ports that went down or switches that disconnected by `handle_event`.
+
+use std::collections::{BTreeSet, HashMap};
+use std::net;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::super::ctl::events::ControllerEvent;
+use super::super::ds::addr::MacAddr;
+use super::super::ds::packet;
+use super::super::ds::packet_in::PacketIn;
+use super::super::ds::port_status::PortReason;
+use super::super::ds::ports::{PortNumber, PortState};
+use super::routing::SwitchPort;
+
+/// Time after which a host that sent no packets is forgotten (5 minutes).
+pub const DEFAULT_HOST_MAX_AGE: Duration = Duration::from_secs(300);
+
+/// A host and where it is attached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Host {
+    pub mac: MacAddr,
+    pub location: SwitchPort,
+    /// ip addresses the host sent packets from
+    pub ips: BTreeSet<net::IpAddr>,
+    pub last_seen: Instant,
+}
+
+/// Change of the known hosts caused by a packet.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostEvent {
+    /// the host was not known before
+    Added(Host),
+    /// the host was seen at another port than before
+    Moved { host: Host, from: SwitchPort },
+}
+
+/// Hosts by mac address, see the module documentation.
+/// Methods ending in `_at` take the current time to make them testable,
+/// the others use `Instant::now()`.
+#[derive(Debug)]
+pub struct HostTracker {
+    hosts: Mutex<HashMap<MacAddr, Host>>,
+    max_age: Duration,
+}
+
+impl Default for HostTracker {
+    fn default() -> Self {
+        HostTracker::new(DEFAULT_HOST_MAX_AGE)
+    }
+}
+
+impl HostTracker {
+    pub fn new(max_age: Duration) -> Self {
+        HostTracker {
+            hosts: Mutex::new(HashMap::new()),
+            max_age: max_age,
+        }
+    }
+
+    /// learns the sender of the packet in of the switch
+    /// None if nothing changed or the packet has no usable source (eg. lldp or
+    /// multicast source address)
+    pub fn learn(&self, datapath_id: u64, packet_in: &PacketIn) -> Option<HostEvent> {
+        if packet_in.class() == packet::PacketClass::Lldp {
+            return None;
+        }
+        let in_port = match packet_in.in_port() {
+            Some(PortNumber::NormalPort(in_port)) => in_port,
+            _ => return None,
+        };
+        let mac = packet::src_mac(&packet_in.ethernet_frame)?;
+        let ip = packet::src_ip(&packet_in.ethernet_frame);
+        self.learn_at(
+            SwitchPort::new(datapath_id, in_port),
+            mac,
+            ip,
+            Instant::now(),
+        )
+    }
+
+    pub fn learn_at(
+        &self,
+        location: SwitchPort,
+        mac: MacAddr,
+        ip: Option<net::IpAddr>,
+        now: Instant,
+    ) -> Option<HostEvent> {
+        if mac.is_multicast() {
+            return None;
+        }
+        let mut hosts = self.hosts.lock().unwrap();
+        if let Some(ip) = ip {
+            // the address moved to another host (eg. a reassigned dhcp lease)
+            for host in hosts.values_mut().filter(|host| host.mac != mac) {
+                host.ips.remove(&ip);
+            }
+        }
+        let event = match hosts.get_mut(&mac) {
+            Some(host) => {
+                host.last_seen = now;
+                host.ips.extend(ip);
+                if host.location == location {
+                    return None;
+                }
+                let from = host.location;
+                host.location = location;
+                HostEvent::Moved {
+                    host: host.clone(),
+                    from: from,
+                }
+            }
+            None => HostEvent::Added(Host {
+                mac: mac,
+                location: location,
+                ips: ip.into_iter().collect(),
+                last_seen: now,
+            }),
+        };
+        if let HostEvent::Added(ref host) = event {
+            hosts.insert(mac, host.clone());
+        }
+        Some(event)
+    }
+
+    /// port the host is attached to
+    pub fn where_is(&self, mac: &MacAddr) -> Option<SwitchPort> {
+        self.host(mac).map(|host| host.location)
+    }
+
+    pub fn host(&self, mac: &MacAddr) -> Option<Host> {
+        self.hosts.lock().unwrap().get(mac).cloned()
+    }
+
+    /// host that sent packets from the ip address
+    pub fn with_ip(&self, ip: &net::IpAddr) -> Option<Host> {
+        let hosts = self.hosts.lock().unwrap();
+        hosts.values().find(|host| host.ips.contains(ip)).cloned()
+    }
+
+    /// hosts attached to the port of the switch
+    pub fn hosts_on(&self, datapath_id: u64, port_no: u32) -> Vec<Host> {
+        let location = SwitchPort::new(datapath_id, port_no);
+        let hosts = self.hosts.lock().unwrap();
+        hosts
+            .values()
+            .filter(|host| host.location == location)
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.hosts.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// all known hosts
+    pub fn hosts(&self) -> Vec<Host> {
+        self.hosts.lock().unwrap().values().cloned().collect()
+    }
+
+    /// adds or replaces the host, eg. one learned by another controller
+    pub fn insert(&self, host: Host) {
+        self.hosts.lock().unwrap().insert(host.mac, host);
+    }
+
+    /// forgets the host, eg. because it was configured somewhere else
+    pub fn remove(&self, mac: &MacAddr) -> Option<Host> {
+        self.hosts.lock().unwrap().remove(mac)
+    }
+
+    /// forgets and returns the hosts not seen for the maximum age
+    pub fn expire(&self) -> Vec<Host> {
+        self.expire_at(Instant::now())
+    }
+
+    pub fn expire_at(&self, now: Instant) -> Vec<Host> {
+        let max_age = self.max_age;
+        self.remove_where(|host| host.last_seen + max_age <= now)
+    }
+
+    /// forgets the hosts on ports that were deleted or went down and on switches
+    /// that disconnected, returns the forgotten hosts
+    pub fn handle_event(&self, event: &ControllerEvent) -> Vec<Host> {
+        match *event {
+            ControllerEvent::SwitchDisconnected {
+                datapath_id,
+                auxiliary_id: 0,
+                ..
+            } => self.remove_where(|host| host.location.datapath_id == datapath_id),
+            ControllerEvent::PortChanged {
+                datapath_id,
+                ref reason,
+                ref port,
+            } => {
+                let down =
+                    *reason == PortReason::Delete || port.state().contains(PortState::LINK_DOWN);
+                match *port.port_no() {
+                    PortNumber::NormalPort(port_no) if down => {
+                        let location = SwitchPort::new(datapath_id, port_no);
+                        self.remove_where(|host| host.location == location)
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn remove_where<F>(&self, remove: F) -> Vec<Host>
+    where
+        F: Fn(&Host) -> bool,
+    {
+        let mut hosts = self.hosts.lock().unwrap();
+        let removed: Vec<Host> = hosts
+            .values()
+            .filter(|host| remove(host))
+            .cloned()
+            .collect();
+        for host in &removed {
+            hosts.remove(&host.mac);
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ds::flow_parser::parse_match;
+    use super::super::super::ds::packet::Arp;
+    use super::super::super::ds::packet_in::InReason;
+    use super::super::super::ds::packet_out::BufferId;
+    use super::*;
+
+    fn arp_packet_in(mac: MacAddr, ip: net::Ipv4Addr, in_port: u32) -> PacketIn {
+        let frame = Arp::request(mac, ip, net::Ipv4Addr::new(10, 0, 0, 254)).to_frame();
+        PacketIn {
+            buffer_id: BufferId::NoBuffer,
+            total_len: frame.len() as u16,
+            reason: InReason::NoMatch,
+            table_id: 0,
+            cookie: 0,
+            mmatch: parse_match(&format!("in_port={}", in_port)).unwrap(),
+            ethernet_frame: frame,
+        }
+    }
+
+    #[test]
+    fn learns_moves_and_expires_hosts() {
+        let tracker = HostTracker::new(Duration::from_secs(60));
+        let mac = MacAddr([2, 0, 0, 0, 0, 1]);
+        let ip = net::Ipv4Addr::new(10, 0, 0, 1);
+
+        let host = match tracker.learn(1, &arp_packet_in(mac, ip, 3)) {
+            Some(HostEvent::Added(host)) => host,
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert_eq!(host.location, SwitchPort::new(1, 3));
+        assert!(host.ips.contains(&net::IpAddr::V4(ip)));
+        assert_eq!(tracker.learn(1, &arp_packet_in(mac, ip, 3)), None);
+        assert_eq!(tracker.with_ip(&ip.into()).map(|host| host.mac), Some(mac));
+
+        match tracker.learn(2, &arp_packet_in(mac, ip, 5)) {
+            Some(HostEvent::Moved { host, from }) => {
+                assert_eq!(from, SwitchPort::new(1, 3));
+                assert_eq!(host.location, SwitchPort::new(2, 5));
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert_eq!(tracker.where_is(&mac), Some(SwitchPort::new(2, 5)));
+        assert!(tracker.hosts_on(1, 3).is_empty());
+        assert_eq!(tracker.hosts_on(2, 5).len(), 1);
+
+        // the address is taken over by another host
+        let other = MacAddr([2, 0, 0, 0, 0, 2]);
+        let now = Instant::now();
+        tracker.learn_at(
+            SwitchPort::new(2, 6),
+            other,
+            Some(ip.into()),
+            now + Duration::from_secs(30),
+        );
+        assert_eq!(
+            tracker.with_ip(&ip.into()).map(|host| host.mac),
+            Some(other)
+        );
+
+        let expired = tracker.expire_at(now + Duration::from_secs(61));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].mac, mac);
+        assert_eq!(tracker.len(), 1);
+
+        let disconnected = ControllerEvent::SwitchDisconnected {
+            datapath_id: 2,
+            auxiliary_id: 0,
+            peer: None,
+        };
+        assert_eq!(tracker.handle_event(&disconnected).len(), 1);
+        assert!(tracker.is_empty());
+    }
+}
@@ -0,0 +1,14 @@
+//! Building blocks for controller applications.
+//!
+//! Each app is driven by the handler of the controller: it looks at the
+//! messages it is interested in and answers them through the switch handle.
+
+pub mod arp_responder;
+pub mod host_tracker;
+pub mod replication;
+pub mod routing;
+
+pub use self::arp_responder::ArpResponder;
+pub use self::host_tracker::{Host, HostEvent, HostTracker};
+pub use self::replication::{Replica, Replicator, StateEvent};
+pub use self::routing::{Router, SwitchPort};
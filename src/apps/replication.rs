@@ -0,0 +1,598 @@
+//! State sync between an active and a standby controller.
+//!
+//! The active controller publishes the changes of its state (connected
+//! switches, the flows it installed and the known hosts) as `StateEvent`s.
+//! A standby that connects first receives a snapshot of the whole state,
+//! followed by `StateEvent::Synced` and the changes from then on. It applies
+//! them to a `Replica`, so it can take over without relearning everything:
+//! after becoming master (see `DatapathDecision::AcceptWithRole`) it restores
+//! the hosts into its tracker and the flows into its flow timers.
+//!
+//! ```ignore
+//! // active controller
+//! let replicator = Arc::new(Replicator::new());
+//! let (send, recv) = channel();
+//! replicator.add_standby(send, replication::snapshot(&registry, &flows, &tracker));
+//! thread::spawn(move || replication::forward(recv, TcpStream::connect(standby)?));
+//! // in the handler
+//! replicator.publish(StateEvent::FlowMod(datapath_id, flow_mod.clone()));
+//!
+//! // standby controller
+//! let mut replica = Replica::new();
+//! for event in StateReader::new(listener.accept()?.0)? {
+//!     replica.apply(event?);
+//! }
+//! ```
+//!
+//! The stream starts with a 12 byte header (8 bytes magic `OATH2SYN`, u16
+//! version, 2 bytes reserved) followed by records of the form
+//! - u8 kind of the event
+//! - u32 length of the body followed by the body
+//!
+//! Switches are sent as their features reply, flows as the flow mod (or flow
+//! removed) after the datapath id, hosts as their mac address, location,
+//! milliseconds since they were last seen and their ip addresses.
+//! All values are big endian, bodies longer than `ds::MAX_MESSAGE_LENGTH`
+//! are rejected.
+
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
+use std::convert::{Into, TryFrom};
+use std::io::{Cursor, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::super::ctl::events::ControllerEvent;
+use super::super::ctl::expiry::FlowTimers;
+use super::super::ctl::registry::SwitchRegistry;
+use super::super::ds::addr::MacAddr;
+use super::super::ds::features::SwitchFeatures;
+use super::super::ds::flow_mod::FlowMod;
+use super::super::ds::flow_removed::FlowRemoved;
+use super::super::ds::MAX_MESSAGE_LENGTH;
+use super::super::err::*;
+use super::host_tracker::{Host, HostEvent, HostTracker};
+use super::routing::SwitchPort;
+
+/// magic bytes at the start of every state stream
+pub const SYNC_MAGIC: &[u8; 8] = b"OATH2SYN";
+/// current version of the stream format
+pub const SYNC_VERSION: u16 = 1;
+
+/// A change of the state of the active controller.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateEvent {
+    /// the main connection of the switch was registered
+    SwitchConnected(SwitchFeatures),
+    SwitchDisconnected(u64),
+    /// a flow mod the controller sent to the datapath
+    FlowMod(u64, FlowMod),
+    /// the datapath reported the flow as removed
+    FlowRemoved(u64, FlowRemoved),
+    /// the host was learned or moved
+    Host(Host),
+    HostRemoved(MacAddr),
+    /// the snapshot is complete, the replica is up to date from here on
+    Synced,
+}
+
+impl StateEvent {
+    fn kind(&self) -> u8 {
+        match *self {
+            StateEvent::SwitchConnected(_) => 0,
+            StateEvent::SwitchDisconnected(_) => 1,
+            StateEvent::FlowMod(..) => 2,
+            StateEvent::FlowRemoved(..) => 3,
+            StateEvent::Host(_) => 4,
+            StateEvent::HostRemoved(_) => 5,
+            StateEvent::Synced => 6,
+        }
+    }
+
+    fn body(self) -> Vec<u8> {
+        let mut body = Vec::new();
+        match self {
+            StateEvent::SwitchConnected(features) => body = features.into(),
+            StateEvent::SwitchDisconnected(datapath_id) => {
+                body.write_u64::<BigEndian>(datapath_id).unwrap();
+            }
+            StateEvent::FlowMod(datapath_id, flow_mod) => {
+                body.write_u64::<BigEndian>(datapath_id).unwrap();
+                body.extend_from_slice(&Into::<Vec<u8>>::into(flow_mod)[..]);
+            }
+            StateEvent::FlowRemoved(datapath_id, flow_removed) => {
+                body.write_u64::<BigEndian>(datapath_id).unwrap();
+                body.extend_from_slice(&Into::<Vec<u8>>::into(flow_removed)[..]);
+            }
+            StateEvent::Host(host) => {
+                body.extend_from_slice(&host.mac.0[..]);
+                body.write_u64::<BigEndian>(host.location.datapath_id)
+                    .unwrap();
+                body.write_u32::<BigEndian>(host.location.port_no).unwrap();
+                // instants can not be sent, the age is the same on both sides
+                let age = host.last_seen.elapsed();
+                let millis = age.as_secs() * 1000 + u64::from(age.subsec_millis());
+                body.write_u64::<BigEndian>(millis).unwrap();
+                for ip in host.ips {
+                    match ip {
+                        IpAddr::V4(ip) => {
+                            body.write_u8(4).unwrap();
+                            body.extend_from_slice(&ip.octets()[..]);
+                        }
+                        IpAddr::V6(ip) => {
+                            body.write_u8(6).unwrap();
+                            body.extend_from_slice(&ip.octets()[..]);
+                        }
+                    }
+                }
+            }
+            StateEvent::HostRemoved(mac) => body.extend_from_slice(&mac.0[..]),
+            StateEvent::Synced => (),
+        }
+        body
+    }
+
+    /// decodes the body of a record of the given kind
+    fn decode(kind: u8, body: &[u8]) -> Result<StateEvent> {
+        let mut cursor = Cursor::new(body);
+        Ok(match kind {
+            0 => StateEvent::SwitchConnected(SwitchFeatures::try_from(body)?),
+            1 => StateEvent::SwitchDisconnected(cursor.read_u64::<BigEndian>()?),
+            2 => {
+                let datapath_id = cursor.read_u64::<BigEndian>()?;
+                StateEvent::FlowMod(datapath_id, FlowMod::try_from(&body[8..])?)
+            }
+            3 => {
+                let datapath_id = cursor.read_u64::<BigEndian>()?;
+                StateEvent::FlowRemoved(datapath_id, FlowRemoved::try_from(&body[8..])?)
+            }
+            4 => {
+                let mut mac = [0; 6];
+                cursor.read_exact(&mut mac)?;
+                let datapath_id = cursor.read_u64::<BigEndian>()?;
+                let port_no = cursor.read_u32::<BigEndian>()?;
+                let age = Duration::from_millis(cursor.read_u64::<BigEndian>()?);
+                let mut host = Host {
+                    mac: MacAddr(mac),
+                    location: SwitchPort::new(datapath_id, port_no),
+                    ips: Default::default(),
+                    last_seen: seen_before(age),
+                };
+                while (cursor.position() as usize) < body.len() {
+                    let ip = match cursor.read_u8()? {
+                        4 => {
+                            let mut octets = [0; 4];
+                            cursor.read_exact(&mut octets)?;
+                            IpAddr::V4(Ipv4Addr::from(octets))
+                        }
+                        6 => {
+                            let mut octets = [0; 16];
+                            cursor.read_exact(&mut octets)?;
+                            IpAddr::V6(Ipv6Addr::from(octets))
+                        }
+                        val => bail!(ErrorKind::UnknownValue(val as u64, stringify!(IpAddr))),
+                    };
+                    host.ips.insert(ip);
+                }
+                StateEvent::Host(host)
+            }
+            5 => {
+                let mut mac = [0; 6];
+                cursor.read_exact(&mut mac)?;
+                StateEvent::HostRemoved(MacAddr(mac))
+            }
+            6 => StateEvent::Synced,
+            kind => bail!(ErrorKind::UnknownValue(kind as u64, stringify!(StateEvent))),
+        })
+    }
+}
+
+/// the instant the given time ago, now if the clock does not go back that far
+fn seen_before(age: Duration) -> Instant {
+    let now = Instant::now();
+    now.checked_sub(age).unwrap_or(now)
+}
+
+/// the whole record, kind and length included
+impl Into<Vec<u8>> for StateEvent {
+    fn into(self) -> Vec<u8> {
+        let kind = self.kind();
+        let body = self.body();
+        let mut res = Vec::with_capacity(body.len() + 5);
+        res.write_u8(kind).unwrap();
+        res.write_u32::<BigEndian>(body.len() as u32).unwrap();
+        res.extend_from_slice(&body[..]);
+        res
+    }
+}
+
+/// Writes state events in the stream format.
+pub struct StateWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> StateWriter<W> {
+    /// writes the stream header to the given writer
+    pub fn new(inner: W) -> Result<Self> {
+        let mut inner = inner;
+        inner.write_all(&SYNC_MAGIC[..])?;
+        inner.write_u16::<BigEndian>(SYNC_VERSION)?;
+        inner.write_u16::<BigEndian>(0)?; // reserved
+        inner.flush()?;
+        Ok(StateWriter { inner: inner })
+    }
+
+    pub fn write(&mut self, event: StateEvent) -> Result<()> {
+        let record: Vec<u8> = event.into();
+        self.inner.write_all(&record[..])?;
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    /// returns the underlying writer
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Reads the state events of a stream, ends when the stream is closed.
+pub struct StateReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> StateReader<R> {
+    /// reads and checks the stream header
+    pub fn new(inner: R) -> Result<Self> {
+        let mut inner = inner;
+        let mut header = [0; 12];
+        inner.read_exact(&mut header)?;
+        if header[..SYNC_MAGIC.len()] != SYNC_MAGIC[..] {
+            let magic = BigEndian::read_u64(&header[..SYNC_MAGIC.len()]);
+            bail!(ErrorKind::UnknownValue(magic, stringify!(SYNC_MAGIC)));
+        }
+        let version = BigEndian::read_u16(&header[SYNC_MAGIC.len()..]);
+        if version != SYNC_VERSION {
+            bail!(ErrorKind::UnsupportedValue(
+                version as u64,
+                stringify!(SYNC_VERSION)
+            ));
+        }
+        Ok(StateReader { inner: inner })
+    }
+
+    /// the next event, None if the stream ended between two records
+    pub fn read(&mut self) -> Result<Option<StateEvent>> {
+        let mut kind = [0; 1];
+        if self.inner.read(&mut kind)? == 0 {
+            return Ok(None);
+        }
+        let len = self.inner.read_u32::<BigEndian>()? as usize;
+        // the length comes from the peer, no record is longer than a message
+        if len > MAX_MESSAGE_LENGTH {
+            bail!(ErrorKind::MessageTooLong(len));
+        }
+        let mut body = vec![0; len];
+        self.inner.read_exact(&mut body)?;
+        StateEvent::decode(kind[0], &body).map(Some)
+    }
+}
+
+impl<R: Read> Iterator for StateReader<R> {
+    type Item = Result<StateEvent>;
+
+    fn next(&mut self) -> Option<Result<StateEvent>> {
+        match self.read() {
+            Ok(event) => event.map(Ok),
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+/// writes the events of the channel to the writer (eg. the connection to a
+/// standby) until the channel is closed or writing fails
+pub fn forward<W: Write>(events: Receiver<StateEvent>, writer: W) -> Result<()> {
+    let mut writer = StateWriter::new(writer)?;
+    for event in events {
+        writer.write(event)?;
+    }
+    Ok(())
+}
+
+/// events that recreate the state of the controller on an empty replica,
+/// flows are the ones tracked per datapath
+pub fn snapshot(
+    registry: &SwitchRegistry,
+    flows: &HashMap<u64, FlowTimers>,
+    hosts: &HostTracker,
+) -> Vec<StateEvent> {
+    let mut events: Vec<StateEvent> = registry
+        .switches()
+        .iter()
+        .map(|handle| StateEvent::SwitchConnected(handle.features().clone()))
+        .collect();
+    for (datapath_id, timers) in flows {
+        for flow_mod in timers.flow_mods() {
+            events.push(StateEvent::FlowMod(*datapath_id, flow_mod.clone()));
+        }
+    }
+    events.extend(hosts.hosts().into_iter().map(StateEvent::Host));
+    events
+}
+
+/// Sends the state events of the active controller to its standbys.
+#[derive(Debug, Default)]
+pub struct Replicator {
+    standbys: Mutex<Vec<Sender<StateEvent>>>,
+}
+
+impl Replicator {
+    pub fn new() -> Self {
+        Replicator::default()
+    }
+
+    /// adds a standby, it receives the snapshot and Synced before any
+    /// published event
+    pub fn add_standby(&self, standby: Sender<StateEvent>, snapshot: Vec<StateEvent>) {
+        // holding the lock keeps publish from overtaking the snapshot
+        let mut standbys = self.standbys.lock().unwrap();
+        for event in snapshot.into_iter().chain(Some(StateEvent::Synced)) {
+            if standby.send(event).is_err() {
+                return;
+            }
+        }
+        standbys.push(standby);
+    }
+
+    /// number of standbys
+    pub fn len(&self) -> usize {
+        self.standbys.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// sends the event to every standby, standbys whose receiver was dropped
+    /// are removed, returns the number of standbys that received it
+    pub fn publish(&self, event: StateEvent) -> usize {
+        let mut standbys = self.standbys.lock().unwrap();
+        standbys.retain(|standby| standby.send(event.clone()).is_ok());
+        standbys.len()
+    }
+
+    /// publishes the connects and disconnects of main connections, the
+    /// features of connected switches are taken from the registry
+    pub fn handle_event(&self, registry: &SwitchRegistry, event: &ControllerEvent) {
+        match *event {
+            ControllerEvent::SwitchConnected {
+                datapath_id,
+                auxiliary_id: 0,
+                ..
+            } => {
+                if let Some(handle) = registry.get(datapath_id) {
+                    self.publish(StateEvent::SwitchConnected(handle.features().clone()));
+                }
+            }
+            ControllerEvent::SwitchDisconnected {
+                datapath_id,
+                auxiliary_id: 0,
+                ..
+            } => {
+                self.publish(StateEvent::SwitchDisconnected(datapath_id));
+            }
+            _ => (),
+        }
+    }
+
+    /// publishes a learned or moved host
+    pub fn handle_host_event(&self, event: &HostEvent) {
+        let host = match *event {
+            HostEvent::Added(ref host) => host,
+            HostEvent::Moved { ref host, .. } => host,
+        };
+        self.publish(StateEvent::Host(host.clone()));
+    }
+}
+
+/// State of the active controller as seen by a standby.
+#[derive(Debug, Default)]
+pub struct Replica {
+    switches: HashMap<u64, SwitchFeatures>,
+    flows: HashMap<u64, FlowTimers>,
+    hosts: HashMap<MacAddr, Host>,
+    synced: bool,
+}
+
+impl Replica {
+    pub fn new() -> Self {
+        Replica::default()
+    }
+
+    pub fn apply(&mut self, event: StateEvent) {
+        match event {
+            StateEvent::SwitchConnected(features) => {
+                self.switches.insert(features.datapath_id, features);
+            }
+            StateEvent::SwitchDisconnected(datapath_id) => {
+                self.switches.remove(&datapath_id);
+            }
+            StateEvent::FlowMod(datapath_id, flow_mod) => {
+                self.flows
+                    .entry(datapath_id)
+                    .or_default()
+                    .track(&flow_mod, false);
+            }
+            StateEvent::FlowRemoved(datapath_id, flow_removed) => {
+                if let Some(timers) = self.flows.get_mut(&datapath_id) {
+                    timers.removed(&flow_removed);
+                }
+            }
+            StateEvent::Host(host) => {
+                self.hosts.insert(host.mac, host);
+            }
+            StateEvent::HostRemoved(mac) => {
+                self.hosts.remove(&mac);
+            }
+            StateEvent::Synced => self.synced = true,
+        }
+    }
+
+    /// true once the snapshot of the active controller was received
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// features of the switches connected to the active controller
+    pub fn switches(&self) -> Vec<&SwitchFeatures> {
+        self.switches.values().collect()
+    }
+
+    /// flows the active controller installed on the datapath
+    pub fn flows(&self, datapath_id: u64) -> Vec<&FlowMod> {
+        match self.flows.get(&datapath_id) {
+            Some(timers) => timers.flow_mods(),
+            None => Vec::new(),
+        }
+    }
+
+    /// the flows of every datapath, eg. to continue tracking their timeouts
+    /// after taking over
+    pub fn into_flows(self) -> HashMap<u64, FlowTimers> {
+        self.flows
+    }
+
+    pub fn hosts(&self) -> Vec<&Host> {
+        self.hosts.values().collect()
+    }
+
+    /// adds the hosts to the tracker, eg. after taking over
+    pub fn restore_hosts(&self, tracker: &HostTracker) {
+        for host in self.hosts.values() {
+            tracker.insert(host.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ds::factory;
+    use super::super::super::ds::features::Capabilities;
+    use super::super::super::ds::flow_mod::FlowModCommand;
+    use super::super::super::ds::flow_parser::parse_flow_mod;
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    fn features(datapath_id: u64) -> SwitchFeatures {
+        let mut features = factory::features_reply(datapath_id);
+        features.n_buffers = 256;
+        features.n_tables = 4;
+        features.capabilities = Capabilities::FLOW_STATS;
+        features
+    }
+
+    fn host(mac: u8) -> Host {
+        Host {
+            mac: MacAddr([0x02, 0, 0, 0, 0, mac]),
+            location: SwitchPort::new(1, 2),
+            ips: vec!["10.0.0.1".parse().unwrap(), "fe80::1".parse().unwrap()]
+                .into_iter()
+                .collect(),
+            last_seen: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn events_round_trip_through_stream() {
+        let flow_mod = parse_flow_mod("priority=10,in_port=1,actions=output:2").unwrap();
+        let events = vec![
+            StateEvent::SwitchConnected(features(1)),
+            StateEvent::FlowMod(1, flow_mod),
+            StateEvent::HostRemoved(MacAddr([0x02, 0, 0, 0, 0, 9])),
+            StateEvent::SwitchDisconnected(1),
+            StateEvent::Synced,
+        ];
+        let mut writer = StateWriter::new(Vec::new()).unwrap();
+        for event in events.clone() {
+            writer.write(event).unwrap();
+        }
+        writer.write(StateEvent::Host(host(1))).unwrap();
+        let bytes = writer.into_inner();
+
+        let read: Vec<StateEvent> = StateReader::new(&bytes[..])
+            .unwrap()
+            .map(|event| event.unwrap())
+            .collect();
+        assert_eq!(&read[..events.len()], &events[..]);
+        match read[events.len()] {
+            StateEvent::Host(ref read) => {
+                let sent = host(1);
+                assert_eq!((read.mac, read.location), (sent.mac, sent.location));
+                assert_eq!(read.ips, sent.ips);
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+
+        // cut in the middle of a record
+        let mut reader = StateReader::new(&bytes[..bytes.len() - 1]).unwrap();
+        let err = reader.find(|event| event.is_err()).unwrap().unwrap_err();
+        match *err.kind() {
+            ErrorKind::Io(_) => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+        assert!(StateReader::new(&b"OATH2CAP\x00\x01\x00\x00"[..]).is_err());
+
+        // the length is checked before the body is allocated
+        let mut bytes = StateWriter::new(Vec::new()).unwrap().into_inner();
+        bytes.extend_from_slice(&[2, 0xff, 0xff, 0xff, 0xff]);
+        let err = StateReader::new(&bytes[..]).unwrap().read().unwrap_err();
+        match *err.kind() {
+            ErrorKind::MessageTooLong(0xffff_ffff) => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn standby_receives_snapshot_before_changes() {
+        let replicator = Replicator::new();
+        let tracker = HostTracker::default();
+        tracker.insert(host(1));
+        let mut timers = FlowTimers::new();
+        timers.track(&parse_flow_mod("priority=10,actions=drop").unwrap(), false);
+        let mut flows = HashMap::new();
+        flows.insert(1, timers);
+        let registry = SwitchRegistry::new();
+
+        let (send, recv) = channel();
+        replicator.add_standby(send, snapshot(&registry, &flows, &tracker));
+        assert_eq!(replicator.len(), 1);
+        replicator.publish(StateEvent::SwitchConnected(features(1)));
+
+        let mut replica = Replica::new();
+        for event in recv.try_iter() {
+            // the snapshot comes first
+            if let StateEvent::SwitchConnected(_) = event {
+                assert!(replica.is_synced());
+            }
+            replica.apply(event);
+        }
+        assert!(replica.is_synced());
+        assert_eq!(replica.switches().len(), 1);
+        assert_eq!(replica.flows(1).len(), 1);
+        assert_eq!(replica.hosts().len(), 1);
+
+        let mut delete = parse_flow_mod("priority=10,actions=drop").unwrap();
+        delete.command = FlowModCommand::DeleteStrict;
+        replica.apply(StateEvent::FlowMod(1, delete));
+        assert!(replica.flows(1).is_empty());
+        let restored = HostTracker::default();
+        replica.restore_hosts(&restored);
+        assert!(restored.host(&host(1).mac).is_some());
+
+        // gone standbys are removed
+        drop(recv);
+        assert_eq!(replicator.publish(StateEvent::SwitchDisconnected(1)), 0);
+        assert!(replicator.is_empty());
+    }
+}
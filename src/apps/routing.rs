@@ -0,0 +1,490 @@
+//! Shortest path routing between switch ports.
+//!
+//! The router knows the links between switches (eg. from topology discovery
+//! or configuration) and computes the path with the fewest links between two
+//! switch ports. A route installs a path end to end: every switch on it gets a
+//! flow that matches the packets of the route arriving at its in port and
+//! outputs them to the next link. With vlan tagging the first switch pushes the
+//! vlan, the switches in between match on it and the last one pops it again.
+//!
+//! Routes are repaired when a link goes down: their old flows are deleted and
+//! a new path is installed if one exists. Routes without a path are installed
+//! again once a link comes up that connects them.
+//!
+//! Methods return the flow mods and the switch they are for, `send_flow_mods`
+//! sends them through the registry.
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use super::super::ctl::events::ControllerEvent;
+use super::super::ctl::SwitchRegistry;
+use super::super::ds;
+use super::super::ds::flow_match::{
+    Match, MatchPayload, PayloadInPort, PayloadVlanVId, TlvMatch, VID_PRESENT,
+};
+use super::super::ds::flow_mod::{FlowMod, FlowModCommand};
+use super::super::ds::flow_parser::{parse_flow_mod, parse_instructions};
+use super::super::ds::port_status::PortReason;
+use super::super::ds::ports::{PortNumber, PortState};
+use super::super::err::*;
+
+/// A port of a switch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SwitchPort {
+    pub datapath_id: u64,
+    pub port_no: u32,
+}
+
+impl SwitchPort {
+    pub fn new(datapath_id: u64, port_no: u32) -> Self {
+        SwitchPort {
+            datapath_id: datapath_id,
+            port_no: port_no,
+        }
+    }
+}
+
+/// Forwarding of a path in one switch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hop {
+    pub datapath_id: u64,
+    pub in_port: u32,
+    pub out_port: u32,
+}
+
+/// How the packets of a route are marked between the switches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tagging {
+    /// every switch matches the match of the route
+    None,
+    /// the first switch pushes a vlan with the vid (12 bits), the last one pops it
+    Vlan(u16),
+}
+
+/// A route installed by the router.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub src: SwitchPort,
+    pub dst: SwitchPort,
+    /// packets of the route, the in port is set by the router
+    pub mmatch: Match,
+    pub priority: u16,
+    pub tagging: Tagging,
+    /// current path, empty while there is none
+    pub hops: Vec<Hop>,
+}
+
+/// Links and installed routes, see the module documentation.
+#[derive(Debug, Default)]
+pub struct Router {
+    /// both directions of every link
+    links: BTreeMap<SwitchPort, SwitchPort>,
+    routes: HashMap<usize, Route>,
+    next_route: usize,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router::default()
+    }
+
+    /// adds a link between both ports and installs the routes that had no path
+    pub fn add_link(&mut self, a: SwitchPort, b: SwitchPort) -> Vec<(u64, FlowMod)> {
+        self.links.insert(a, b);
+        self.links.insert(b, a);
+        let unrouted: Vec<usize> = self
+            .routes
+            .iter()
+            .filter(|&(_, route)| route.hops.is_empty())
+            .map(|(id, _)| *id)
+            .collect();
+        let mut flow_mods = Vec::new();
+        for id in unrouted {
+            flow_mods.extend(self.reroute(id));
+        }
+        flow_mods
+    }
+
+    /// removes the link of the port (eg. link down or port deleted) and
+    /// repairs the routes that used it
+    pub fn remove_link(&mut self, port: SwitchPort) -> Vec<(u64, FlowMod)> {
+        let peer = match self.links.remove(&port) {
+            Some(peer) => peer,
+            None => return Vec::new(),
+        };
+        self.links.remove(&peer);
+        let broken: Vec<usize> = self
+            .routes
+            .iter()
+            .filter(|&(_, route)| uses_link(&route.hops, port, peer))
+            .map(|(id, _)| *id)
+            .collect();
+        let mut flow_mods = Vec::new();
+        for id in broken {
+            flow_mods.extend(self.reroute(id));
+        }
+        flow_mods
+    }
+
+    /// repairs the routes if the event is about a port that went down or was deleted
+    pub fn handle_event(&mut self, event: &ControllerEvent) -> Vec<(u64, FlowMod)> {
+        match *event {
+            ControllerEvent::PortChanged {
+                datapath_id,
+                ref reason,
+                ref port,
+            } => {
+                let down =
+                    *reason == PortReason::Delete || port.state().contains(PortState::LINK_DOWN);
+                match *port.port_no() {
+                    PortNumber::NormalPort(port_no) if down => {
+                        self.remove_link(SwitchPort::new(datapath_id, port_no))
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// path with the fewest links from src to dst, None if they are not connected
+    /// paths through the same switch prefer the lowest port numbers
+    pub fn shortest_path(&self, src: SwitchPort, dst: SwitchPort) -> Option<Vec<Hop>> {
+        // breadth first search over the switches, remembering the link a switch was reached by
+        let mut reached_by: HashMap<u64, Option<(SwitchPort, SwitchPort)>> = HashMap::new();
+        reached_by.insert(src.datapath_id, None);
+        let mut queue = VecDeque::new();
+        queue.push_back(src.datapath_id);
+        while let Some(datapath_id) = queue.pop_front() {
+            if datapath_id == dst.datapath_id {
+                break;
+            }
+            let first = SwitchPort::new(datapath_id, 0);
+            let last = SwitchPort::new(datapath_id, u32::MAX);
+            for (out, peer) in self.links.range(first..=last) {
+                if let Entry::Vacant(entry) = reached_by.entry(peer.datapath_id) {
+                    entry.insert(Some((*out, *peer)));
+                    queue.push_back(peer.datapath_id);
+                }
+            }
+        }
+
+        let mut hops = Vec::new();
+        let mut out_port = dst.port_no;
+        let mut datapath_id = dst.datapath_id;
+        loop {
+            match *reached_by.get(&datapath_id)? {
+                Some((out, peer)) => {
+                    hops.push(Hop {
+                        datapath_id: datapath_id,
+                        in_port: peer.port_no,
+                        out_port: out_port,
+                    });
+                    out_port = out.port_no;
+                    datapath_id = out.datapath_id;
+                }
+                None => {
+                    hops.push(Hop {
+                        datapath_id: datapath_id,
+                        in_port: src.port_no,
+                        out_port: out_port,
+                    });
+                    break;
+                }
+            }
+        }
+        hops.reverse();
+        Some(hops)
+    }
+
+    /// installs a route from src to dst for the packets of the match
+    /// returns the id of the route and the flow mods of its path, which are empty
+    /// if there is no path yet
+    pub fn add_route(
+        &mut self,
+        src: SwitchPort,
+        dst: SwitchPort,
+        mmatch: Match,
+        priority: u16,
+        tagging: Tagging,
+    ) -> (usize, Vec<(u64, FlowMod)>) {
+        let id = self.next_route;
+        self.next_route += 1;
+        self.routes.insert(
+            id,
+            Route {
+                src: src,
+                dst: dst,
+                mmatch: mmatch,
+                priority: priority,
+                tagging: tagging,
+                hops: Vec::new(),
+            },
+        );
+        (id, self.reroute(id))
+    }
+
+    /// removes the route and returns the flow mods that delete its flows
+    pub fn remove_route(&mut self, id: usize) -> Vec<(u64, FlowMod)> {
+        match self.routes.remove(&id) {
+            Some(route) => flow_mods(&route, FlowModCommand::DeleteStrict),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn route(&self, id: usize) -> Option<&Route> {
+        self.routes.get(&id)
+    }
+
+    /// checks if the port connects to another switch
+    pub fn is_link(&self, port: &SwitchPort) -> bool {
+        self.links.contains_key(port)
+    }
+
+    /// moves the routes starting or ending at the old port of a host to its new
+    /// port, eg. when the host tracker reports a move
+    pub fn host_moved(&mut self, from: SwitchPort, to: SwitchPort) -> Vec<(u64, FlowMod)> {
+        let moved: Vec<usize> = self
+            .routes
+            .iter()
+            .filter(|&(_, route)| route.src == from || route.dst == from)
+            .map(|(id, _)| *id)
+            .collect();
+        let mut flows = Vec::new();
+        for id in moved {
+            // the old flows match the old port, delete them before the endpoints change
+            let route = self.routes.get_mut(&id).unwrap();
+            flows.extend(flow_mods(route, FlowModCommand::DeleteStrict));
+            route.hops.clear();
+            if route.src == from {
+                route.src = to;
+            }
+            if route.dst == from {
+                route.dst = to;
+            }
+            flows.extend(self.reroute(id));
+        }
+        flows
+    }
+
+    /// deletes the flows of the current path and installs the shortest path
+    fn reroute(&mut self, id: usize) -> Vec<(u64, FlowMod)> {
+        let hops = {
+            let route = &self.routes[&id];
+            self.shortest_path(route.src, route.dst).unwrap_or_default()
+        };
+        let route = self.routes.get_mut(&id).unwrap();
+        let mut flows = flow_mods(route, FlowModCommand::DeleteStrict);
+        route.hops = hops;
+        flows.extend(flow_mods(route, FlowModCommand::Add));
+        flows
+    }
+}
+
+/// checks if the path crosses the link between both ports (in either direction)
+fn uses_link(hops: &[Hop], a: SwitchPort, b: SwitchPort) -> bool {
+    hops.windows(2).any(|pair| {
+        let out = SwitchPort::new(pair[0].datapath_id, pair[0].out_port);
+        let next = SwitchPort::new(pair[1].datapath_id, pair[1].in_port);
+        (out, next) == (a, b) || (out, next) == (b, a)
+    })
+}
+
+/// flow mods of every hop of the route
+fn flow_mods(route: &Route, command: FlowModCommand) -> Vec<(u64, FlowMod)> {
+    let last = route.hops.len().saturating_sub(1);
+    route
+        .hops
+        .iter()
+        .enumerate()
+        .map(|(index, hop)| {
+            let vid = match route.tagging {
+                // a single switch does not need a tag
+                Tagging::Vlan(vid) if last > 0 => Some(vid & 0x0fff),
+                _ => None,
+            };
+            let mut matches: Vec<TlvMatch> = route
+                .mmatch
+                .matches()
+                .iter()
+                .filter(|field| !matches!(*field.payload(), MatchPayload::InPort(_)))
+                .cloned()
+                .collect();
+            matches.push(TlvMatch::new(MatchPayload::InPort(PayloadInPort {
+                ingress_port: PortNumber::NormalPort(hop.in_port),
+            })));
+            let mut actions = String::new();
+            match vid {
+                Some(vid) if index == 0 => {
+                    actions += &format!(
+                        "push_vlan:0x8100,set_field:{}->vlan_vid,",
+                        vid | VID_PRESENT
+                    )
+                }
+                Some(vid) => {
+                    matches.push(TlvMatch::new(MatchPayload::VlanVId(PayloadVlanVId {
+                        vlan_id: vid | VID_PRESENT,
+                    })));
+                    if index == last {
+                        actions += "pop_vlan,";
+                    }
+                }
+                None => (),
+            }
+            actions += &format!("output:{}", hop.out_port);
+
+            let mut flow_mod = parse_flow_mod("actions=drop").unwrap();
+            flow_mod.command = command.clone();
+            flow_mod.priority = route.priority;
+            flow_mod.mmatch = Match::new(matches);
+            flow_mod.instructions = match command {
+                FlowModCommand::Add => parse_instructions(&actions).unwrap(),
+                _ => Vec::new(),
+            };
+            (hop.datapath_id, flow_mod)
+        })
+        .collect()
+}
+
+/// sends every flow mod to its switch
+/// fails with the first switch that is not connected or rejects a message
+pub fn send_flow_mods(registry: &SwitchRegistry, flow_mods: Vec<(u64, FlowMod)>) -> Result<()> {
+    for (datapath_id, flow_mod) in flow_mods {
+        let switch = match registry.get(datapath_id) {
+            Some(switch) => switch,
+            None => bail!(ErrorKind::ConnectionClosed(datapath_id)),
+        };
+        switch.send_payload(ds::OfPayload::FlowMod(flow_mod))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ds::flow_parser::{format_flow_mod, parse_match};
+    use super::*;
+
+    fn port(datapath_id: u64, port_no: u32) -> SwitchPort {
+        SwitchPort::new(datapath_id, port_no)
+    }
+
+    /// 1 - 2 - 3 and a longer way 1 - 4 - 5 - 3
+    fn router() -> Router {
+        let mut router = Router::new();
+        router.add_link(port(1, 2), port(2, 1));
+        router.add_link(port(2, 2), port(3, 1));
+        router.add_link(port(1, 3), port(4, 1));
+        router.add_link(port(4, 2), port(5, 1));
+        router.add_link(port(5, 2), port(3, 2));
+        router
+    }
+
+    fn formatted(flow_mods: &[(u64, FlowMod)]) -> Vec<(u64, String)> {
+        flow_mods
+            .iter()
+            .map(|&(datapath_id, ref flow_mod)| (datapath_id, format_flow_mod(flow_mod)))
+            .collect()
+    }
+
+    #[test]
+    fn shortest_path_between_ports() {
+        let router = router();
+        let hops = router.shortest_path(port(1, 10), port(3, 20)).unwrap();
+        let expected = vec![
+            Hop {
+                datapath_id: 1,
+                in_port: 10,
+                out_port: 2,
+            },
+            Hop {
+                datapath_id: 2,
+                in_port: 1,
+                out_port: 2,
+            },
+            Hop {
+                datapath_id: 3,
+                in_port: 1,
+                out_port: 20,
+            },
+        ];
+        assert_eq!(hops, expected);
+        let hops = router.shortest_path(port(1, 10), port(1, 11)).unwrap();
+        assert_eq!(
+            hops,
+            vec![Hop {
+                datapath_id: 1,
+                in_port: 10,
+                out_port: 11
+            }]
+        );
+        assert_eq!(router.shortest_path(port(1, 10), port(6, 1)), None);
+    }
+
+    #[test]
+    fn installs_and_repairs_tagged_routes() {
+        let mut router = router();
+        let mmatch = parse_match("ip,nw_dst=10.0.0.3").unwrap();
+        let (id, flow_mods) =
+            router.add_route(port(1, 10), port(3, 20), mmatch, 100, Tagging::Vlan(10));
+        let prefix = "priority=100,eth_type=0x0800,ipv4_dst=10.0.0.3";
+        let expected = vec![
+            (
+                1,
+                format!(
+                    "{},in_port=10,actions=push_vlan:0x8100,set_field:0x100a->vlan_vid,output:2",
+                    prefix
+                ),
+            ),
+            (
+                2,
+                format!("{},in_port=1,vlan_vid=0x100a,actions=output:2", prefix),
+            ),
+            (
+                3,
+                format!(
+                    "{},in_port=1,vlan_vid=0x100a,actions=pop_vlan,output:20",
+                    prefix
+                ),
+            ),
+        ];
+        assert_eq!(formatted(&flow_mods), expected);
+
+        // the link 2 - 3 goes down, the route moves to 1 - 4 - 5 - 3
+        let flow_mods = router.remove_link(port(3, 1));
+        let commands: Vec<(u64, FlowModCommand)> = flow_mods
+            .iter()
+            .map(|&(datapath_id, ref flow_mod)| (datapath_id, flow_mod.command.clone()))
+            .collect();
+        assert_eq!(
+            commands[..3].to_vec(),
+            vec![
+                (1, FlowModCommand::DeleteStrict),
+                (2, FlowModCommand::DeleteStrict),
+                (3, FlowModCommand::DeleteStrict),
+            ]
+        );
+        let path: Vec<u64> = router
+            .route(id)
+            .unwrap()
+            .hops
+            .iter()
+            .map(|hop| hop.datapath_id)
+            .collect();
+        assert_eq!(path, vec![1, 4, 5, 3]);
+        assert_eq!(flow_mods.len(), 3 + 4);
+
+        // no path left, the route is installed again once the link is back
+        router.remove_link(port(5, 2));
+        assert!(router.route(id).unwrap().hops.is_empty());
+        let flow_mods = router.add_link(port(2, 2), port(3, 1));
+        assert_eq!(flow_mods.len(), 3);
+
+        // the destination host moved to switch 2
+        let flow_mods = router.host_moved(port(3, 20), port(2, 7));
+        assert_eq!(flow_mods.len(), 3 + 2);
+        assert_eq!(router.route(id).unwrap().dst, port(2, 7));
+        assert!(router.is_link(&port(2, 2)));
+        assert_eq!(router.remove_route(id).len(), 2);
+    }
+}
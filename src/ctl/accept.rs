@@ -0,0 +1,292 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::super::ds::features::SwitchFeatures;
+use super::super::ds::role::ControllerRole;
+
+/// Open files kept for everything but switch connections (eg. logs and captures).
+pub const RESERVED_FILES: usize = 64;
+/// Open files of a switch connection, the stream and its clone for the output thread.
+pub const FILES_PER_CONNECTION: usize = 2;
+
+/// Decides which incoming tcp connections are admitted as switches.
+/// The default policy admits everyone.
+#[derive(Debug, Clone, Default)]
+pub struct AcceptPolicy {
+    /// if not empty only these peers are admitted
+    pub allow: Vec<IpAddr>,
+    /// these peers are never admitted, takes precedence over `allow`
+    pub deny: Vec<IpAddr>,
+    /// max number of switch connections open at the same time, connections
+    /// over it are shed: they get a hello and an error before they are closed
+    /// see `within_open_files_limit` to derive it from the limits of the process
+    pub max_switches: Option<usize>,
+    /// max number of connections admitted per peer ip in a time window
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl AcceptPolicy {
+    /// lowers max_switches to the connections that fit into the open files
+    /// limit of the process (see `connection_capacity`), switches over it are
+    /// shed instead of failing once no file descriptor is left
+    pub fn within_open_files_limit(mut self) -> Self {
+        if let Some(capacity) = connection_capacity() {
+            self.max_switches = Some(self.max_switches.map_or(capacity, |max| max.min(capacity)));
+        }
+        self
+    }
+}
+
+/// soft limit of open files of the process (RLIMIT_NOFILE), None if it is
+/// unlimited or unknown, it is only known on Linux (from /proc/self/limits)
+pub fn open_files_limit() -> Option<usize> {
+    let limits = fs::read_to_string("/proc/self/limits").ok()?;
+    parse_open_files_limit(&limits)
+}
+
+/// the soft limit of the "Max open files" line of /proc/self/limits
+fn parse_open_files_limit(limits: &str) -> Option<usize> {
+    let line = limits
+        .lines()
+        .find(|line| line.starts_with("Max open files"))?;
+    line.split_whitespace().nth(3)?.parse().ok()
+}
+
+/// number of switch connections that fit into the open files limit,
+/// RESERVED_FILES are kept for the rest of the process, None if the limit is unknown
+pub fn connection_capacity() -> Option<usize> {
+    open_files_limit().map(|limit| limit.saturating_sub(RESERVED_FILES) / FILES_PER_CONNECTION)
+}
+
+/// Allows `max_connections` connections per peer ip within `per`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub max_connections: usize,
+    pub per: Duration,
+}
+
+/// Why a connection was not admitted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RejectReason {
+    /// the peer is on the deny list
+    Denied,
+    /// the allow list is used and the peer is not on it
+    NotAllowed,
+    /// max_switches connections are already open
+    TooManySwitches,
+    /// the peer connected too often within the rate limit window
+    RateLimited,
+}
+
+/// Applies an AcceptPolicy and keeps the state needed for it.
+pub struct AcceptFilter {
+    policy: AcceptPolicy,
+    active: Arc<AtomicUsize>,
+    recent: HashMap<IpAddr, VecDeque<Instant>>,
+}
+
+impl AcceptFilter {
+    pub fn new(policy: AcceptPolicy) -> Self {
+        AcceptFilter {
+            policy: policy,
+            active: Arc::new(AtomicUsize::new(0)),
+            recent: HashMap::new(),
+        }
+    }
+
+    /// checks if the peer may connect now
+    /// on success the connection is counted until the returned guard is dropped
+    pub fn admit(&mut self, peer: IpAddr) -> ::std::result::Result<ActiveConnection, RejectReason> {
+        self.admit_at(peer, Instant::now())
+    }
+
+    fn admit_at(
+        &mut self,
+        peer: IpAddr,
+        now: Instant,
+    ) -> ::std::result::Result<ActiveConnection, RejectReason> {
+        if self.policy.deny.contains(&peer) {
+            return Err(RejectReason::Denied);
+        }
+        if !self.policy.allow.is_empty() && !self.policy.allow.contains(&peer) {
+            return Err(RejectReason::NotAllowed);
+        }
+        if let Some(max_switches) = self.policy.max_switches {
+            if self.active.load(Ordering::SeqCst) >= max_switches {
+                return Err(RejectReason::TooManySwitches);
+            }
+        }
+        if let Some(rate_limit) = self.policy.rate_limit {
+            // forget connections that left the window
+            for attempts in self.recent.values_mut() {
+                while attempts
+                    .front()
+                    .is_some_and(|at| now.duration_since(*at) >= rate_limit.per)
+                {
+                    attempts.pop_front();
+                }
+            }
+            self.recent.retain(|_, attempts| !attempts.is_empty());
+
+            let attempts = self.recent.entry(peer).or_default();
+            if attempts.len() >= rate_limit.max_connections {
+                return Err(RejectReason::RateLimited);
+            }
+            attempts.push_back(now);
+        }
+        self.active.fetch_add(1, Ordering::SeqCst);
+        Ok(ActiveConnection {
+            active: self.active.clone(),
+        })
+    }
+
+    /// checks if a connection without peer ip (eg. a Unix domain socket) may
+    /// connect now, only max_switches applies to it
+    pub fn admit_local(&mut self) -> ::std::result::Result<ActiveConnection, RejectReason> {
+        if let Some(max_switches) = self.policy.max_switches {
+            if self.active.load(Ordering::SeqCst) >= max_switches {
+                return Err(RejectReason::TooManySwitches);
+            }
+        }
+        self.active.fetch_add(1, Ordering::SeqCst);
+        Ok(ActiveConnection {
+            active: self.active.clone(),
+        })
+    }
+
+    /// number of admitted connections that are still open
+    pub fn active_connections(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+}
+
+/// Marks an admitted connection as open until it is dropped.
+pub struct ActiveConnection {
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// What happens to a connection once the switch sent its features reply.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DatapathDecision {
+    /// the connection is registered
+    Accept,
+    /// the connection is registered after requesting the role from the switch
+    AcceptWithRole {
+        role: ControllerRole,
+        generation_id: u64,
+    },
+    /// the connection is closed without registering it
+    Reject,
+}
+
+/// Decides by datapath id and features which switches are managed,
+/// see ControllerConfig::datapath_policy.
+pub type DatapathPolicy = Arc<dyn Fn(u64, &SwitchFeatures) -> DatapathDecision + Send + Sync>;
+
+/// policy that only accepts the given datapaths
+pub fn allow_datapaths(datapath_ids: Vec<u64>) -> DatapathPolicy {
+    Arc::new(move |datapath_id, _| {
+        if datapath_ids.contains(&datapath_id) {
+            DatapathDecision::Accept
+        } else {
+            DatapathDecision::Reject
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ds::factory;
+    use super::*;
+
+    #[test]
+    fn allow_datapaths_policy() {
+        let policy = allow_datapaths(vec![1, 2]);
+        let mut features = factory::features_reply(2);
+        assert_eq!(policy(2, &features), DatapathDecision::Accept);
+        features.datapath_id = 3;
+        assert_eq!(policy(3, &features), DatapathDecision::Reject);
+    }
+
+    #[test]
+    fn allow_and_deny() {
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+        let mut filter = AcceptFilter::new(AcceptPolicy {
+            allow: vec![a, b],
+            deny: vec![b],
+            ..AcceptPolicy::default()
+        });
+        assert!(filter.admit(a).is_ok());
+        assert_eq!(filter.admit(b).err(), Some(RejectReason::Denied));
+        assert_eq!(
+            filter.admit("10.0.0.3".parse().unwrap()).err(),
+            Some(RejectReason::NotAllowed)
+        );
+    }
+
+    #[test]
+    fn max_switches_and_rate_limit() {
+        let peer: IpAddr = "10.0.0.1".parse().unwrap();
+        let mut filter = AcceptFilter::new(AcceptPolicy {
+            max_switches: Some(1),
+            rate_limit: Some(RateLimit {
+                max_connections: 2,
+                per: Duration::from_secs(10),
+            }),
+            ..AcceptPolicy::default()
+        });
+        let start = Instant::now();
+        let first = filter.admit_at(peer, start).unwrap();
+        assert_eq!(
+            filter.admit_at(peer, start).err(),
+            Some(RejectReason::TooManySwitches)
+        );
+        // local connections count towards max_switches too
+        assert_eq!(
+            filter.admit_local().err(),
+            Some(RejectReason::TooManySwitches)
+        );
+        drop(first);
+        assert_eq!(filter.active_connections(), 0);
+
+        let second = filter.admit_at(peer, start).unwrap();
+        drop(second);
+        assert_eq!(
+            filter.admit_at(peer, start).err(),
+            Some(RejectReason::RateLimited)
+        );
+        assert!(filter
+            .admit_at(peer, start + Duration::from_secs(10))
+            .is_ok());
+    }
+
+    #[test]
+    fn open_files_limit_of_proc_limits() {
+        let limits = concat!(
+            "Limit                     Soft Limit           Hard Limit           Units\n",
+            "Max processes             63704                63704                processes\n",
+            "Max open files            1024                 1048576              files\n",
+        );
+        assert_eq!(parse_open_files_limit(limits), Some(1024));
+        let unlimited = "Max open files            unlimited            unlimited            files";
+        assert_eq!(parse_open_files_limit(unlimited), None);
+        assert_eq!(parse_open_files_limit(""), None);
+
+        let policy = AcceptPolicy {
+            max_switches: Some(1),
+            ..AcceptPolicy::default()
+        };
+        assert_eq!(policy.within_open_files_limit().max_switches, Some(1));
+    }
+}
@@ -0,0 +1,206 @@
+//! Checks flow mods against the flows already installed before sending them.
+//!
+//! A switch only reports overlapping flows if the flow mod has the
+//! CHECK_OVERLAP flag and never reports flows that can not match any packet
+//! because a flow with a higher priority matches all of them. `check_flow_mod`
+//! finds both on the controller, using the flows known to the controller
+//! (eg. `FlowTimers::flow_mods`). Matches are compared field by field,
+//! prerequisites between fields (eg. tcp ports need ip_proto=6) are not considered.
+
+use std::collections::HashMap;
+
+use super::super::ds::flow_match::{Match, MatchPayload, TlvMatch};
+use super::super::ds::flow_mod::FlowMod;
+
+/// Problem of a flow mod with an installed flow (the flow in the variant).
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlowConflict {
+    /// same table, priority and match, adding the flow replaces it
+    Duplicate(FlowMod),
+    /// same table and priority and some packets match both flows,
+    /// with CHECK_OVERLAP the switch rejects the flow mod
+    Overlap(FlowMod),
+    /// higher priority flow that matches every packet of the new flow,
+    /// the new flow would never match
+    ShadowedBy(FlowMod),
+    /// lower priority flow whose packets all match the new flow,
+    /// it would never match again
+    Shadows(FlowMod),
+}
+
+/// checks the flow mod against the installed flows of the switch
+/// flows of other tables are ignored, the result is empty if there are no conflicts
+pub fn check_flow_mod<'a, I>(candidate: &FlowMod, installed: I) -> Vec<FlowConflict>
+where
+    I: IntoIterator<Item = &'a FlowMod>,
+{
+    let fields = MatchFields::of(&candidate.mmatch);
+    let mut conflicts = Vec::new();
+    for flow in installed {
+        if flow.table_id != candidate.table_id {
+            continue;
+        }
+        let other = MatchFields::of(&flow.mmatch);
+        let conflict = if flow.priority == candidate.priority {
+            if other == fields {
+                Some(FlowConflict::Duplicate(flow.clone()))
+            } else if other.overlaps(&fields) {
+                Some(FlowConflict::Overlap(flow.clone()))
+            } else {
+                None
+            }
+        } else if flow.priority > candidate.priority && other.covers(&fields) {
+            Some(FlowConflict::ShadowedBy(flow.clone()))
+        } else if flow.priority < candidate.priority && fields.covers(&other) {
+            Some(FlowConflict::Shadows(flow.clone()))
+        } else {
+            None
+        };
+        if let Some(conflict) = conflict {
+            conflicts.push(conflict);
+        }
+    }
+    conflicts
+}
+
+/// identifies a match field, the experimenter id only for experimenter fields
+type FieldKey = (u32, u32, Option<u32>);
+
+/// masked values of a match by field, the order of the fields does not matter
+#[derive(Debug, PartialEq)]
+struct MatchFields(HashMap<FieldKey, (Vec<u8>, Vec<u8>)>);
+
+impl MatchFields {
+    fn of(mmatch: &Match) -> Self {
+        MatchFields(mmatch.matches().iter().map(field).collect())
+    }
+
+    /// true if there is a packet that matches both
+    /// fields present in only one of the matches do not restrict the other
+    fn overlaps(&self, other: &MatchFields) -> bool {
+        self.0.iter().all(|(key, (value, mask))| {
+            match other.0.get(key) {
+                // the values have to agree in the bits both masks check,
+                // values are already masked with their own mask
+                Some((other_value, other_mask)) => {
+                    value.len() != other_value.len()
+                        || value
+                            .iter()
+                            .zip(other_value)
+                            .zip(mask.iter().zip(other_mask))
+                            .all(|((v, o), (m, om))| v & om == o & m)
+                }
+                None => true,
+            }
+        })
+    }
+
+    /// true if every packet matching other also matches self
+    fn covers(&self, other: &MatchFields) -> bool {
+        self.0.iter().all(|(key, (value, mask))| {
+            match other.0.get(key) {
+                // other has to check at least the bits self checks, with the same values
+                Some((other_value, other_mask)) => {
+                    value.len() == other_value.len()
+                        && mask.iter().zip(other_mask).all(|(m, o)| m & !o == 0)
+                        && agree(value, other_value, mask)
+                }
+                None => false,
+            }
+        })
+    }
+}
+
+/// value and mask of a field, fields without mask have all bits set in the mask
+fn field(tlv_match: &TlvMatch) -> (FieldKey, (Vec<u8>, Vec<u8>)) {
+    let header = &tlv_match.tlv_header;
+    let (experimenter, value) = match *tlv_match.payload() {
+        MatchPayload::Experimenter(ref payload) => {
+            (Some(payload.experimenter), payload.data.clone())
+        }
+        ref payload => (None, payload.clone().into()),
+    };
+    let mask = match *tlv_match.mask() {
+        Some(ref mask) => mask.clone(),
+        None => vec![0xff; value.len()],
+    };
+    // bits outside of the mask do not matter
+    let value = value.iter().zip(&mask).map(|(v, m)| v & m).collect();
+    (
+        (header.get_oxm_class(), header.get_oxm_field(), experimenter),
+        (value, mask),
+    )
+}
+
+/// true if both values are the same in the bits of the mask
+fn agree(value: &[u8], other: &[u8], mask: &[u8]) -> bool {
+    value.len() == other.len()
+        && value
+            .iter()
+            .zip(other)
+            .zip(mask)
+            .all(|((v, o), m)| v & m == o & m)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ds::flow_parser::parse_flow_mod;
+    use super::*;
+
+    fn flow(flow: &str) -> FlowMod {
+        parse_flow_mod(flow).unwrap()
+    }
+
+    #[test]
+    fn finds_duplicates_and_overlaps() {
+        let installed = vec![
+            flow("priority=10,in_port=1,eth_type=0x0800,actions=drop"),
+            flow("priority=10,ipv4_dst=10.0.0.0/8,eth_type=0x0800,actions=drop"),
+            flow("priority=10,in_port=2,actions=drop"),
+            flow("table=1,priority=10,in_port=1,actions=drop"),
+        ];
+        // same fields in another order
+        let candidate = flow("priority=10,eth_type=0x0800,in_port=1,actions=output:2");
+        assert_eq!(
+            check_flow_mod(&candidate, &installed),
+            vec![
+                FlowConflict::Duplicate(installed[0].clone()),
+                FlowConflict::Overlap(installed[1].clone()),
+            ]
+        );
+
+        // masks that disagree in a checked bit do not overlap
+        let candidate = flow("priority=10,ipv4_dst=11.0.0.0/8,eth_type=0x0800,actions=drop");
+        assert_eq!(
+            check_flow_mod(&candidate, &installed),
+            vec![
+                FlowConflict::Overlap(installed[0].clone()),
+                FlowConflict::Overlap(installed[2].clone()),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_shadowed_flows() {
+        let installed = vec![
+            flow("priority=20,eth_type=0x0800,ipv4_dst=10.0.0.0/8,actions=drop"),
+            flow("priority=5,eth_type=0x0800,ipv4_dst=10.1.2.3,in_port=3,actions=drop"),
+            flow("priority=30,eth_type=0x0806,actions=drop"),
+        ];
+        let candidate = flow("priority=10,eth_type=0x0800,ipv4_dst=10.1.0.0/16,actions=drop");
+        assert_eq!(
+            check_flow_mod(&candidate, &installed),
+            vec![
+                FlowConflict::ShadowedBy(installed[0].clone()),
+                FlowConflict::Shadows(installed[1].clone()),
+            ]
+        );
+        // the match all flow covers everything
+        let table_miss = flow("priority=0,actions=controller");
+        assert_eq!(check_flow_mod(&table_miss, &installed[..1]), Vec::new());
+        assert_eq!(
+            check_flow_mod(&flow("priority=100,actions=drop"), &installed[2..]),
+            vec![FlowConflict::Shadows(installed[2].clone())]
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Audit trail of the messages that change the state of switches.
+//!
+//! With `ControllerConfig::audit_log` set, every flow mod, group mod, meter
+//! mod, port mod, table mod and config or role change sent to a switch is
+//! recorded with the time, the datapath and the xid. An error the switch
+//! answers with is added to the entry of the message it refers to, so the log
+//! tells who installed a flow and when, and whether the switch accepted it:
+//!
+//! ```
+//! use oath2::ctl::audit::AuditLog;
+//! use oath2::ctl::ControllerConfig;
+//! use std::sync::Arc;
+//!
+//! let audit_log = Arc::new(AuditLog::new(1024));
+//! let mut config = ControllerConfig::default();
+//! config.audit_log = Some(audit_log.clone());
+//! // later, eg. from a debug endpoint
+//! for entry in audit_log.filter(|entry| entry.summary.contains("nw_dst=10.0.0.1")) {
+//!     println!("{:?} {}", entry.time, entry.summary);
+//! }
+//! println!("{}", audit_log.to_json());
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::super::ds::error_msg::ErrorMsg;
+use super::super::ds::flow_parser::format_flow_mod;
+use super::super::ds::{OfPayload, Type};
+
+/// Number of entries kept by default.
+pub const DEFAULT_AUDIT_ENTRIES: usize = 4096;
+
+/// A state changing message sent to a switch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub time: SystemTime,
+    /// None if the switch did not send its features reply yet
+    pub datapath_id: Option<u64>,
+    pub xid: u32,
+    pub ttype: Type,
+    /// the message in short, flow mods in the syntax of ds::flow_parser
+    pub summary: String,
+    /// the error the switch answered with, None if it did not (yet)
+    pub error: Option<ErrorMsg>,
+}
+
+/// Ring buffer of the last state changing messages, see the module documentation.
+#[derive(Debug)]
+pub struct AuditLog {
+    entries: Mutex<VecDeque<AuditEntry>>,
+    capacity: usize,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        AuditLog::new(DEFAULT_AUDIT_ENTRIES)
+    }
+}
+
+impl AuditLog {
+    /// keeps the last capacity entries
+    pub fn new(capacity: usize) -> Self {
+        AuditLog {
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: capacity,
+        }
+    }
+
+    /// records the message if it changes the state of the switch,
+    /// returns whether it was recorded
+    pub fn record(&self, datapath_id: Option<u64>, xid: u32, payload: &OfPayload) -> bool {
+        let summary = match summary(payload) {
+            Some(summary) => summary,
+            None => return false,
+        };
+        if self.capacity == 0 {
+            return true;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(AuditEntry {
+            time: SystemTime::now(),
+            datapath_id: datapath_id,
+            xid: xid,
+            ttype: payload.ttype(),
+            summary: summary,
+            error: None,
+        });
+        true
+    }
+
+    /// adds the error to the latest entry of the datapath with the xid,
+    /// returns false if there is none (eg. the error is about another message)
+    pub fn record_error(&self, datapath_id: Option<u64>, xid: u32, error: &ErrorMsg) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries
+            .iter_mut()
+            .rev()
+            .find(|entry| entry.datapath_id == datapath_id && entry.xid == xid);
+        match entry {
+            Some(entry) => {
+                entry.error = Some(error.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// the kept entries, oldest first
+    pub fn entries(&self) -> Vec<AuditEntry> {
+        self.filter(|_| true)
+    }
+
+    /// the kept entries of the datapath, oldest first
+    pub fn for_datapath(&self, datapath_id: u64) -> Vec<AuditEntry> {
+        self.filter(|entry| entry.datapath_id == Some(datapath_id))
+    }
+
+    /// the kept entries the predicate is true for, oldest first
+    pub fn filter<F>(&self, predicate: F) -> Vec<AuditEntry>
+    where
+        F: Fn(&AuditEntry) -> bool,
+    {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .filter(|entry| predicate(entry))
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// the kept entries as a json array, oldest first, eg.
+    /// `[{"time_ms":1700000000000,"datapath_id":"0x2a","xid":7,"type":"FlowMod",
+    /// "summary":"Add priority=100,actions=drop","error":null}]`
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("[");
+        for (i, entry) in self.entries().iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let time_ms = entry
+                .time
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs() * 1000 + u64::from(since.subsec_millis()))
+                .unwrap_or(0);
+            let _ = write!(json, "{{\"time_ms\":{},\"datapath_id\":", time_ms);
+            match entry.datapath_id {
+                // as string, json numbers do not hold every u64
+                Some(datapath_id) => {
+                    let _ = write!(json, "\"{:#x}\"", datapath_id);
+                }
+                None => json.push_str("null"),
+            }
+            let _ = write!(
+                json,
+                ",\"xid\":{},\"type\":\"{:?}\",\"summary\":",
+                entry.xid, entry.ttype
+            );
+            push_json_string(&mut json, &entry.summary);
+            json.push_str(",\"error\":");
+            match entry.error {
+                Some(ref error) => {
+                    json.push_str("{\"type\":");
+                    match error.error_type() {
+                        Some(ttype) => {
+                            let _ = write!(json, "\"{:?}\"", ttype);
+                        }
+                        None => {
+                            let _ = write!(json, "{}", error.ttype);
+                        }
+                    }
+                    let _ = write!(json, ",\"code\":{}}}", error.code);
+                }
+                None => json.push_str("null"),
+            }
+            json.push('}');
+        }
+        json.push(']');
+        json
+    }
+}
+
+/// the message in short, None if it does not change the state of the switch
+pub fn summary(payload: &OfPayload) -> Option<String> {
+    Some(match *payload {
+        OfPayload::FlowMod(ref flow_mod) => {
+            format!("{:?} {}", flow_mod.command, format_flow_mod(flow_mod))
+        }
+        OfPayload::GroupMod(ref group_mod) => format!(
+            "{:?} group_id={} type={:?} buckets={}",
+            group_mod.command(),
+            group_mod.group_id(),
+            group_mod.ttype(),
+            group_mod.buckets().len()
+        ),
+        OfPayload::MeterMod(ref meter_mod) => format!(
+            "{:?} meter_id={} flags={:?} bands={}",
+            meter_mod.command,
+            meter_mod.meter_id,
+            meter_mod.flags,
+            meter_mod.bands.len()
+        ),
+        OfPayload::PortMod(ref port_mod) => format!(
+            "port={} config={:?} mask={:?}",
+            port_mod.port_no(),
+            port_mod.config(),
+            port_mod.mask()
+        ),
+        OfPayload::TableMod(ref table_mod) => format!(
+            "table={} config={:?}",
+            table_mod.table_id(),
+            table_mod.config()
+        ),
+        OfPayload::SetConfig(ref config) => format!("{:?}", config),
+        OfPayload::SetAsync(ref config) => format!("{:?}", config),
+        OfPayload::RoleRequest(ref role) => format!("{:?}", role),
+        // the message is applied when the bundle is committed
+        OfPayload::BundleAddMessage(ref add) => format!(
+            "bundle={} {}",
+            add.bundle_id,
+            summary(add.message.payload())?
+        ),
+        _ => return None,
+    })
+}
+
+/// appends the string in quotes with the characters json requires escaped
+fn push_json_string(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ds::error_msg::ErrorType;
+    use super::super::super::ds::flow_parser::parse_flow_mod;
+    use super::*;
+
+    #[test]
+    fn records_state_changes_and_their_errors() {
+        let log = AuditLog::new(2);
+        let flow_mod = OfPayload::FlowMod(parse_flow_mod("priority=100,actions=drop").unwrap());
+        assert!(log.record(Some(0x2a), 7, &flow_mod));
+        assert!(!log.record(Some(0x2a), 8, &OfPayload::BarrierRequest));
+        assert!(log.record(Some(0x2b), 7, &flow_mod));
+
+        // table full
+        let error = ErrorMsg::new(ErrorType::FlowModFailed, 1, &[]);
+        assert!(log.record_error(Some(0x2a), 7, &error));
+        assert!(!log.record_error(Some(0x2a), 8, &error));
+        let entries = log.for_datapath(0x2a);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].summary, "Add priority=100,actions=drop");
+        assert_eq!(entries[0].error, Some(error));
+        assert!(log.for_datapath(0x2b)[0].error.is_none());
+
+        // the oldest entry is dropped
+        assert!(log.record(None, 9, &flow_mod));
+        assert_eq!(log.entries().len(), 2);
+        assert!(log.for_datapath(0x2a).is_empty());
+    }
+
+    #[test]
+    fn exports_json() {
+        let log = AuditLog::default();
+        assert_eq!(log.to_json(), "[]");
+        let flow_mod = parse_flow_mod("tcp,actions=output:1").unwrap();
+        log.record(Some(0x2a), 1, &OfPayload::FlowMod(flow_mod));
+        log.record_error(
+            Some(0x2a),
+            1,
+            &ErrorMsg::new(ErrorType::FlowModFailed, 5, &[]),
+        );
+        let json = log.to_json();
+        assert!(json.starts_with("[{\"time_ms\":"));
+        assert!(json.ends_with(
+            ",\"datapath_id\":\"0x2a\",\"xid\":1,\"type\":\"FlowMod\",\
+             \"summary\":\"Add eth_type=0x0800,ip_proto=6,actions=output:1\",\
+             \"error\":{\"type\":\"FlowModFailed\",\"code\":5}}]"
+        ));
+
+        let mut escaped = String::new();
+        push_json_string(&mut escaped, "a\"b\\c\n\u{1}");
+        assert_eq!(escaped, "\"a\\\"b\\\\c\\n\\u0001\"");
+    }
+}
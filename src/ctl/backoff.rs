@@ -0,0 +1,164 @@
+use std::cmp;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Exponential backoff used between reconnection attempts.
+/// The delay starts at `initial` and doubles after every failed attempt
+/// until it reaches `max`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    /// every delay is randomly shortened by up to this fraction (0.0 - 1.0)
+    /// so that switches lost at the same time do not reconnect at the same time
+    pub jitter: f64,
+    /// give up after this many failed attempts in a row, None retries forever
+    pub max_retries: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            jitter: 0.2,
+            max_retries: None,
+        }
+    }
+}
+
+impl Backoff {
+    /// starts a new sequence of delays
+    pub fn delays(&self) -> BackoffDelays {
+        // seed does not need to be good, it only spreads reconnects
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.subsec_nanos() as u64 ^ now.as_secs())
+            .unwrap_or(0);
+        self.delays_with_seed(seed)
+    }
+
+    /// same as delays, but the jitter is taken from the given seed,
+    /// so the delays can be reproduced
+    pub fn delays_with_seed(&self, seed: u64) -> BackoffDelays {
+        BackoffDelays {
+            backoff: *self,
+            next: self.initial,
+            attempts: 0,
+            rng_state: seed | 1,
+        }
+    }
+}
+
+/// State of a running backoff.
+#[derive(Debug, Clone)]
+pub struct BackoffDelays {
+    backoff: Backoff,
+    next: Duration,
+    attempts: u32,
+    rng_state: u64,
+}
+
+impl BackoffDelays {
+    /// returns the delay to wait now and doubles the following one
+    /// returns None if max_retries is reached
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if let Some(max_retries) = self.backoff.max_retries {
+            if self.attempts >= max_retries {
+                return None;
+            }
+        }
+        self.attempts += 1;
+        let delay = self.next;
+        self.next = cmp::min(self.next * 2, self.backoff.max);
+        Some(self.apply_jitter(delay))
+    }
+
+    /// number of delays handed out since the last reset
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// starts over at the initial delay, eg. after a successful connection
+    pub fn reset(&mut self) {
+        self.next = self.backoff.initial;
+        self.attempts = 0;
+    }
+
+    fn apply_jitter(&mut self, delay: Duration) -> Duration {
+        let jitter = self.backoff.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return delay;
+        }
+        // xorshift64
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 7;
+        self.rng_state ^= self.rng_state << 17;
+        let random = (self.rng_state >> 11) as f64 / (1u64 << 53) as f64;
+
+        let nanos = delay.as_secs() as f64 * 1e9 + delay.subsec_nanos() as f64;
+        let nanos = nanos * (1.0 - jitter * random);
+        Duration::new((nanos / 1e9) as u64, (nanos % 1e9) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backoff(jitter: f64, max_retries: Option<u32>) -> Backoff {
+        Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_millis(1000),
+            jitter: jitter,
+            max_retries: max_retries,
+        }
+    }
+
+    #[test]
+    fn delays_double_up_to_the_cap() {
+        let mut delays = backoff(0.0, None).delays();
+        let millis: Vec<u64> = (0..6)
+            .map(|_| delays.next_delay().unwrap().as_millis() as u64)
+            .collect();
+        assert_eq!(millis, vec![100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(delays.attempts(), 6);
+
+        delays.reset();
+        assert_eq!(delays.attempts(), 0);
+        assert_eq!(delays.next_delay(), Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn jitter_only_shortens_delays() {
+        let backoff = backoff(0.5, None);
+        for seed in 0..100 {
+            let mut delays = backoff.delays_with_seed(seed);
+            let mut undisturbed = backoff.initial;
+            for _ in 0..6 {
+                let delay = delays.next_delay().unwrap();
+                assert!(delay <= undisturbed);
+                assert!(delay >= undisturbed / 2);
+                undisturbed = cmp::min(undisturbed * 2, backoff.max);
+            }
+        }
+        // the same seed gives the same delays
+        let mut first = backoff.delays_with_seed(42);
+        let mut second = backoff.delays_with_seed(42);
+        for _ in 0..6 {
+            assert_eq!(first.next_delay(), second.next_delay());
+        }
+    }
+
+    #[test]
+    fn gives_up_after_max_retries() {
+        let mut delays = backoff(0.0, Some(2)).delays();
+        assert!(delays.next_delay().is_some());
+        assert!(delays.next_delay().is_some());
+        assert_eq!(delays.next_delay(), None);
+        assert_eq!(delays.attempts(), 2);
+
+        // a successful connection starts over
+        delays.reset();
+        assert!(delays.next_delay().is_some());
+    }
+}
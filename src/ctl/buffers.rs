@@ -0,0 +1,265 @@
+//! Replies to packet ins that work the same for switches with and without buffers.
+//!
+//! A switch with buffers (n_buffers > 0) keeps the packet it sends to the
+//! controller and replies refer to it by its buffer id. A switch without buffers
+//! sends the whole frame and it has to be sent back in the packet out.
+//! `PacketBuffers` picks the right one for every packet in and keeps frames
+//! on the controller if applications want to reply later.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use super::super::ds::actions::ActionHeader;
+use super::super::ds::features::SwitchFeatures;
+use super::super::ds::flow_mod::FlowMod;
+use super::super::ds::packet_in::PacketIn;
+use super::super::ds::packet_out::{BufferId, PacketOut};
+use super::super::ds::ports::{PortNo, PortNumber};
+use super::super::err::*;
+
+/// Frames kept on the controller by default.
+pub const DEFAULT_CONTROLLER_BUFFERS: usize = 256;
+
+/// Packet kept for a later reply, see `PacketBuffers::hold`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BufferedPacket {
+    /// buffered by the switch
+    Switch { buffer_id: u32, in_port: PortNumber },
+    /// frame kept by the controller under the given id
+    Controller(u32),
+}
+
+/// Packet buffers of a switch, see the module documentation.
+#[derive(Debug)]
+pub struct PacketBuffers {
+    switch_buffers: bool,
+    capacity: usize,
+    frames: Mutex<ControllerFrames>,
+}
+
+/// frames of held packets, the oldest ones are dropped when full
+#[derive(Debug, Default)]
+struct ControllerFrames {
+    next_id: u32,
+    frames: HashMap<u32, (PortNumber, Vec<u8>)>,
+    order: VecDeque<u32>,
+}
+
+impl PacketBuffers {
+    pub fn new(features: &SwitchFeatures) -> Self {
+        PacketBuffers::with_capacity(features, DEFAULT_CONTROLLER_BUFFERS)
+    }
+
+    /// keeps at most capacity frames on the controller
+    pub fn with_capacity(features: &SwitchFeatures, capacity: usize) -> Self {
+        PacketBuffers {
+            switch_buffers: features.n_buffers > 0,
+            capacity: capacity.max(1),
+            frames: Mutex::new(ControllerFrames::default()),
+        }
+    }
+
+    /// true if the switch buffers packets itself
+    pub fn switch_buffers(&self) -> bool {
+        self.switch_buffers
+    }
+
+    /// number of frames kept on the controller
+    pub fn held(&self) -> usize {
+        self.frames.lock().unwrap().frames.len()
+    }
+
+    /// buffer id of the packet in the switch, None if the whole frame was sent
+    fn switch_buffer_id(&self, packet_in: &PacketIn) -> Option<u32> {
+        if self.switch_buffers {
+            packet_in.buffer_id.buffered()
+        } else {
+            None
+        }
+    }
+
+    /// packet out applying the actions to the packet of the packet in
+    pub fn packet_out(&self, packet_in: &PacketIn, actions: Vec<ActionHeader>) -> PacketOut {
+        let in_port = in_port(packet_in);
+        match self.switch_buffer_id(packet_in) {
+            Some(buffer_id) => PacketOut::from_buffered(buffer_id, in_port, actions),
+            None => PacketOut::new(
+                BufferId::NoBuffer,
+                in_port,
+                actions,
+                packet_in.ethernet_frame.clone(),
+            ),
+        }
+    }
+
+    /// flow mod that also applies to the packet of the packet in
+    /// switches only apply new flows to packets they buffered, for other
+    /// packets the returned packet out sends the frame through the flow tables
+    /// and has to be sent after the flow mod on the same connection
+    pub fn flow_mod(
+        &self,
+        packet_in: &PacketIn,
+        mut flow_mod: FlowMod,
+    ) -> (FlowMod, Option<PacketOut>) {
+        match self.switch_buffer_id(packet_in) {
+            Some(buffer_id) => {
+                flow_mod.buffer_id = BufferId::from(buffer_id);
+                (flow_mod, None)
+            }
+            None => {
+                flow_mod.buffer_id = BufferId::NoBuffer;
+                let packet_out =
+                    PacketOut::to_table(packet_in.ethernet_frame.clone(), in_port(packet_in));
+                (flow_mod, Some(packet_out))
+            }
+        }
+    }
+
+    /// keeps the packet for a reply sent later with release
+    /// frames of packets the switch did not buffer are kept on the controller,
+    /// the oldest frame is dropped if more than capacity frames are held
+    pub fn hold(&self, packet_in: &PacketIn) -> BufferedPacket {
+        if let Some(buffer_id) = self.switch_buffer_id(packet_in) {
+            return BufferedPacket::Switch {
+                buffer_id: buffer_id,
+                in_port: in_port(packet_in),
+            };
+        }
+        let mut frames = self.frames.lock().unwrap();
+        if frames.order.len() >= self.capacity {
+            if let Some(oldest) = frames.order.pop_front() {
+                frames.frames.remove(&oldest);
+            }
+        }
+        let id = frames.next_id;
+        frames.next_id = frames.next_id.wrapping_add(1);
+        frames
+            .frames
+            .insert(id, (in_port(packet_in), packet_in.ethernet_frame.clone()));
+        frames.order.push_back(id);
+        BufferedPacket::Controller(id)
+    }
+
+    /// packet out applying the actions to a held packet
+    /// fails if the frame was dropped because too many packets were held
+    pub fn release(
+        &self,
+        packet: &BufferedPacket,
+        actions: Vec<ActionHeader>,
+    ) -> Result<PacketOut> {
+        match *packet {
+            BufferedPacket::Switch {
+                buffer_id,
+                ref in_port,
+            } => Ok(PacketOut::from_buffered(
+                buffer_id,
+                in_port.clone(),
+                actions,
+            )),
+            BufferedPacket::Controller(id) => match self.take(id) {
+                Some((in_port, frame)) => {
+                    Ok(PacketOut::new(BufferId::NoBuffer, in_port, actions, frame))
+                }
+                None => bail!(ErrorKind::UnknownValue(
+                    id as u64,
+                    stringify!(BufferedPacket)
+                )),
+            },
+        }
+    }
+
+    /// forgets a held packet without sending it
+    /// packets buffered by the switch stay there until the switch drops them
+    pub fn discard(&self, packet: &BufferedPacket) {
+        if let BufferedPacket::Controller(id) = *packet {
+            self.take(id);
+        }
+    }
+
+    fn take(&self, id: u32) -> Option<(PortNumber, Vec<u8>)> {
+        let mut frames = self.frames.lock().unwrap();
+        frames.order.retain(|&held| held != id);
+        frames.frames.remove(&id)
+    }
+}
+
+/// ingress port of the packet in, the controller port if the switch did not report it
+fn in_port(packet_in: &PacketIn) -> PortNumber {
+    packet_in
+        .in_port()
+        .unwrap_or_else(|| PortNo::Controller.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ds::factory;
+    use super::super::super::ds::flow_parser::{parse_actions, parse_flow_mod};
+    use super::*;
+
+    fn features(n_buffers: u32) -> SwitchFeatures {
+        let mut features = factory::features_reply(1);
+        features.n_buffers = n_buffers;
+        features
+    }
+
+    fn packet_in(buffer_id: BufferId) -> PacketIn {
+        factory::packet_in(buffer_id, 3, &[1, 2, 3, 4])
+    }
+
+    fn output() -> Vec<ActionHeader> {
+        parse_actions("output:1").unwrap()
+    }
+
+    #[test]
+    fn uses_switch_buffers_if_available() {
+        let buffers = PacketBuffers::new(&features(256));
+        let packet_out = buffers.packet_out(&packet_in(BufferId::Buffered(7)), output());
+        assert_eq!(
+            packet_out,
+            PacketOut::from_buffered(7, PortNumber::NormalPort(3), output())
+        );
+
+        let flow_mod = parse_flow_mod("in_port=3,actions=output:1").unwrap();
+        let (flow_mod, packet_out) = buffers.flow_mod(&packet_in(BufferId::Buffered(7)), flow_mod);
+        assert_eq!(flow_mod.buffer_id, BufferId::Buffered(7));
+        assert_eq!(packet_out, None);
+    }
+
+    #[test]
+    fn sends_frames_of_unbuffered_switches() {
+        let buffers = PacketBuffers::new(&features(0));
+        let packet_out = buffers.packet_out(&packet_in(BufferId::NoBuffer), output());
+        assert!(!packet_out.is_buffered());
+        assert_eq!(packet_out.data, vec![1, 2, 3, 4]);
+        assert_eq!(packet_out.in_port, PortNumber::NormalPort(3));
+
+        let flow_mod = parse_flow_mod("in_port=3,actions=output:1").unwrap();
+        let (flow_mod, packet_out) = buffers.flow_mod(&packet_in(BufferId::NoBuffer), flow_mod);
+        assert_eq!(flow_mod.buffer_id, BufferId::NoBuffer);
+        assert_eq!(
+            packet_out,
+            Some(PacketOut::to_table(
+                vec![1, 2, 3, 4],
+                PortNumber::NormalPort(3)
+            ))
+        );
+    }
+
+    #[test]
+    fn holds_frames_on_the_controller() {
+        let buffers = PacketBuffers::with_capacity(&features(0), 2);
+        let first = buffers.hold(&packet_in(BufferId::NoBuffer));
+        let second = buffers.hold(&packet_in(BufferId::NoBuffer));
+        let third = buffers.hold(&packet_in(BufferId::NoBuffer));
+        assert_eq!(buffers.held(), 2);
+
+        // the oldest frame was dropped
+        assert!(buffers.release(&first, output()).is_err());
+        let packet_out = buffers.release(&second, output()).unwrap();
+        assert_eq!(packet_out.data, vec![1, 2, 3, 4]);
+        assert!(buffers.release(&second, output()).is_err());
+
+        buffers.discard(&third);
+        assert_eq!(buffers.held(), 0);
+    }
+}
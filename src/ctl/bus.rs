@@ -0,0 +1,375 @@
+//! Event bus for applications made of several components.
+//!
+//! The controller passes every message to a single handler. With a bus as the
+//! handler, components (eg. topology, learning switch and a stats collector)
+//! subscribe to the messages they care about and each get their own stream:
+//!
+//! ```
+//! use std::sync::Arc;
+//! use oath2::ctl::bus::{EventBus, Filter};
+//! use oath2::ds::Type;
+//!
+//! let bus = Arc::new(EventBus::new());
+//! let arp = bus.subscribe(Filter::ttype(Type::PacketIn).eth_type(0x0806));
+//! let ports = bus.subscribe(Filter::switch(1).and_ttype(Type::PortStatus));
+//! // oath2::ctl::start_controller("127.0.0.1:6653", bus.handler());
+//! # drop((arp, ports));
+//! ```
+//!
+//! Messages are shared between the subscribers, a message no subscriber
+//! matches is dropped. Subscribers whose receiver was dropped are removed.
+//! Controller events are delivered the same way, see `subscribe_events`.
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::super::ds;
+use super::events::ControllerEvent;
+use super::middleware::SwitchContext;
+use super::switch::IncomingMsg;
+
+/// A message delivered to a subscriber.
+#[derive(Clone, Debug)]
+pub struct Delivery {
+    /// connection the message came from, use it to reply
+    pub ctx: SwitchContext,
+    /// shared with the other subscribers of the message
+    pub msg: Arc<ds::OfMsg>,
+}
+
+/// A condition of a filter, see `Filter::matching`.
+pub type Predicate = Arc<dyn Fn(&ds::OfMsg, &SwitchContext) -> bool + Send + Sync>;
+
+/// Decides which messages a subscriber receives.
+/// All conditions of a filter have to match.
+#[derive(Clone, Default)]
+pub struct Filter {
+    datapath_id: Option<u64>,
+    ttype: Option<ds::Type>,
+    eth_type: Option<u16>,
+    predicates: Vec<Predicate>,
+}
+
+impl Filter {
+    /// matches every message
+    pub fn all() -> Self {
+        Filter::default()
+    }
+
+    /// only messages of the switch
+    pub fn switch(datapath_id: u64) -> Self {
+        Filter::all().and_switch(datapath_id)
+    }
+
+    /// only messages of the type
+    pub fn ttype(ttype: ds::Type) -> Self {
+        Filter::all().and_ttype(ttype)
+    }
+
+    pub fn and_switch(mut self, datapath_id: u64) -> Self {
+        self.datapath_id = Some(datapath_id);
+        self
+    }
+
+    pub fn and_ttype(mut self, ttype: ds::Type) -> Self {
+        self.ttype = Some(ttype);
+        self
+    }
+
+    /// only packet ins whose frame has the ether type (eg. 0x0806 for arp)
+    pub fn eth_type(mut self, eth_type: u16) -> Self {
+        self.eth_type = Some(eth_type);
+        self
+    }
+
+    /// only messages the predicate accepts
+    pub fn matching<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&ds::OfMsg, &SwitchContext) -> bool + Send + Sync + 'static,
+    {
+        self.predicates.push(Arc::new(predicate));
+        self
+    }
+
+    pub fn matches(&self, msg: &ds::OfMsg, ctx: &SwitchContext) -> bool {
+        if self.datapath_id.is_some() && ctx.datapath_id() != self.datapath_id {
+            return false;
+        }
+        if let Some(ref ttype) = self.ttype {
+            if msg.header().ttype() != ttype {
+                return false;
+            }
+        }
+        if let Some(eth_type) = self.eth_type {
+            match *msg.payload() {
+                ds::OfPayload::PacketIn(ref packet_in)
+                    if packet_in.eth_type() == Some(eth_type) => {}
+                _ => return false,
+            }
+        }
+        self.predicates.iter().all(|predicate| predicate(msg, ctx))
+    }
+}
+
+impl fmt::Debug for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Filter")
+            .field("datapath_id", &self.datapath_id)
+            .field("ttype", &self.ttype)
+            .field("eth_type", &self.eth_type)
+            .field("predicates", &self.predicates.len())
+            .finish()
+    }
+}
+
+struct Subscriber<T> {
+    id: usize,
+    filter: Arc<dyn Fn(&T) -> bool + Send + Sync>,
+    sender: Sender<T>,
+}
+
+/// Receiver of the messages of a subscription.
+pub struct Subscription<T> {
+    pub id: usize,
+    pub receiver: Receiver<T>,
+}
+
+impl<T> fmt::Debug for Subscription<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Subscription")
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+/// Delivers messages and controller events to the subscribers whose filter matches.
+#[derive(Default)]
+pub struct EventBus {
+    next_id: AtomicUsize,
+    messages: Mutex<Vec<Subscriber<Delivery>>>,
+    events: Mutex<Vec<Subscriber<ControllerEvent>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus::default()
+    }
+
+    /// receives the messages matching the filter
+    pub fn subscribe(&self, filter: Filter) -> Subscription<Delivery> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (sender, receiver) = channel();
+        self.messages.lock().unwrap().push(Subscriber {
+            id: id,
+            filter: Arc::new(move |delivery: &Delivery| {
+                filter.matches(&delivery.msg, &delivery.ctx)
+            }),
+            sender: sender,
+        });
+        Subscription {
+            id: id,
+            receiver: receiver,
+        }
+    }
+
+    /// receives the controller events the filter accepts
+    pub fn subscribe_events<F>(&self, filter: F) -> Subscription<ControllerEvent>
+    where
+        F: Fn(&ControllerEvent) -> bool + Send + Sync + 'static,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (sender, receiver) = channel();
+        self.events.lock().unwrap().push(Subscriber {
+            id: id,
+            filter: Arc::new(filter),
+            sender: sender,
+        });
+        Subscription {
+            id: id,
+            receiver: receiver,
+        }
+    }
+
+    /// stops the delivery to the subscription with the id
+    pub fn unsubscribe(&self, id: usize) {
+        self.messages.lock().unwrap().retain(|sub| sub.id != id);
+        self.events.lock().unwrap().retain(|sub| sub.id != id);
+    }
+
+    /// number of message and event subscriptions
+    pub fn len(&self) -> usize {
+        self.messages.lock().unwrap().len() + self.events.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// delivers the message to every matching subscriber
+    /// returns the number of subscribers that received it
+    pub fn publish(&self, msg: IncomingMsg) -> usize {
+        let delivery = Delivery {
+            ctx: SwitchContext::new(&msg),
+            msg: Arc::new(msg.msg),
+        };
+        deliver(&self.messages, delivery)
+    }
+
+    /// delivers the event to every subscriber whose filter accepts it
+    /// returns the number of subscribers that received it
+    pub fn publish_event(&self, event: ControllerEvent) -> usize {
+        deliver(&self.events, event)
+    }
+
+    /// handler for start_controller and friends that publishes every message
+    pub fn handler(self: &Arc<Self>) -> impl Fn(IncomingMsg) + Send + Sync + 'static {
+        let bus = self.clone();
+        move |msg| {
+            if bus.publish(msg) == 0 {
+                debug!("No subscriber for message.");
+            }
+        }
+    }
+
+    /// sender for ControllerConfig::event_ch that publishes the events on the bus
+    /// the events are forwarded by a thread that ends when every sender is dropped
+    pub fn event_sender(self: &Arc<Self>) -> Sender<ControllerEvent> {
+        let bus = self.clone();
+        let (sender, receiver) = channel();
+        let forward = thread::Builder::new()
+            .name("Event-Bus".to_string())
+            .spawn(move || {
+                for event in receiver {
+                    bus.publish_event(event);
+                }
+            });
+        if let Err(err) = forward {
+            error!("Could not start event bus thread: {}", err);
+        }
+        sender
+    }
+}
+
+impl fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EventBus")
+            .field("messages", &self.messages.lock().unwrap().len())
+            .field("events", &self.events.lock().unwrap().len())
+            .finish()
+    }
+}
+
+/// sends the value to the matching subscribers and removes the ones that are gone
+fn deliver<T: Clone>(subscribers: &Mutex<Vec<Subscriber<T>>>, value: T) -> usize {
+    let mut subscribers = subscribers.lock().unwrap();
+    let mut delivered = 0;
+    subscribers.retain(|sub| {
+        if !(sub.filter)(&value) {
+            return true;
+        }
+        let alive = sub.sender.send(value.clone()).is_ok();
+        if alive {
+            delivered += 1;
+        }
+        alive
+    });
+    delivered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ds::flow_match::Match;
+    use super::super::super::ds::packet_in::{InReason, PacketIn};
+    use super::super::super::ds::packet_out::BufferId;
+    use super::super::xid::XidAllocator;
+    use super::*;
+
+    fn incoming(payload: ds::OfPayload) -> IncomingMsg {
+        let (send, _) = channel();
+        IncomingMsg {
+            reply_ch: send,
+            msg: ds::OfMsg::generate(7, payload),
+            switch: None,
+            xids: Arc::new(XidAllocator::new()),
+            version: ds::MAX_VERSION,
+            peer: None,
+            local: None,
+            event_ch: None,
+        }
+    }
+
+    fn packet_in(eth_type: u16) -> ds::OfPayload {
+        let mut frame = vec![0xff; 12];
+        // vlan tag in front of the ether type
+        frame.extend_from_slice(&[0x81, 0x00, 0, 1]);
+        frame.extend_from_slice(&[(eth_type >> 8) as u8, eth_type as u8]);
+        ds::OfPayload::PacketIn(PacketIn {
+            buffer_id: BufferId::NoBuffer,
+            total_len: frame.len() as u16,
+            reason: InReason::NoMatch,
+            table_id: 0,
+            cookie: 0,
+            mmatch: Match::new(Vec::new()),
+            ethernet_frame: frame,
+        })
+    }
+
+    #[test]
+    fn delivers_to_matching_subscribers() {
+        let bus = Arc::new(EventBus::new());
+        let all = bus.subscribe(Filter::all());
+        let arp = bus.subscribe(Filter::ttype(ds::Type::PacketIn).eth_type(0x0806));
+        let barriers = bus.subscribe(
+            Filter::all().matching(|msg, _| *msg.header().ttype() == ds::Type::BarrierReply),
+        );
+        // no switch is known before the features reply
+        let switch = bus.subscribe(Filter::switch(1));
+
+        let handler = bus.handler();
+        handler(incoming(packet_in(0x0806)));
+        handler(incoming(packet_in(0x0800)));
+        assert_eq!(bus.publish(incoming(ds::OfPayload::BarrierReply)), 2);
+
+        assert_eq!(all.receiver.try_iter().count(), 3);
+        let delivered: Vec<_> = arp.receiver.try_iter().collect();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(*delivered[0].msg.header().xid(), 7);
+        assert_eq!(barriers.receiver.try_iter().count(), 1);
+        assert!(switch.receiver.try_recv().is_err());
+
+        // dropped and unsubscribed subscribers are removed
+        drop(all);
+        bus.unsubscribe(arp.id);
+        assert_eq!(bus.publish(incoming(ds::OfPayload::BarrierReply)), 1);
+        assert_eq!(bus.len(), 2);
+    }
+
+    #[test]
+    fn forwards_controller_events() {
+        let bus = Arc::new(EventBus::new());
+        let disconnects = bus.subscribe_events(|event| match *event {
+            ControllerEvent::SwitchDisconnected { .. } => true,
+            _ => false,
+        });
+        let event_ch = bus.event_sender();
+        event_ch
+            .send(ControllerEvent::SwitchConnected {
+                datapath_id: 1,
+                auxiliary_id: 0,
+                peer: None,
+            })
+            .unwrap();
+        let disconnected = ControllerEvent::SwitchDisconnected {
+            datapath_id: 1,
+            auxiliary_id: 0,
+            peer: None,
+        };
+        event_ch.send(disconnected.clone()).unwrap();
+        drop(event_ch);
+        assert_eq!(disconnects.receiver.recv().unwrap(), disconnected);
+        assert!(disconnects.receiver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::super::ds;
+use super::super::ds::flags::FlagsPolicy;
+use super::accept::{AcceptPolicy, DatapathPolicy};
+use super::audit::AuditLog;
+use super::backoff::Backoff;
+use super::diagnostics::BadFrames;
+use super::events::ControllerEvent;
+use super::handle::SwitchHandle;
+use super::metrics::Metrics;
+use super::middleware::MiddlewareChain;
+use super::registry::SwitchRegistry;
+use super::socket::SocketOptions;
+use super::tap::Tap;
+
+/// Called with the handle of a switch every time its main connection is
+/// registered, including reconnects.
+pub type ConnectCallback = Arc<dyn Fn(&Arc<SwitchHandle>) + Send + Sync>;
+
+/// Configuration of the controller.
+/// Start with `ControllerConfig::default()` and adjust the fields as needed.
+#[derive(Clone, Default)]
+pub struct ControllerConfig {
+    /// if set, every OpenFlow message sent or received by the controller
+    /// is recorded to this file (see ds::capture for the format)
+    pub capture_file: Option<PathBuf>,
+    /// decides which switches may connect
+    pub accept_policy: AcceptPolicy,
+    /// decides which datapaths are managed once their features reply was
+    /// received, None accepts all
+    pub datapath_policy: Option<DatapathPolicy>,
+    /// if set, controller events (eg. rejected connections) are sent here
+    pub event_ch: Option<Sender<ControllerEvent>>,
+    /// connected switches are registered here
+    /// keep a clone of it to access switches from outside of the handler
+    pub registry: Arc<SwitchRegistry>,
+    /// delays between attempts of connect_to_switch
+    pub reconnect_backoff: Backoff,
+    /// used to (re)program flows whenever a switch (re)connects
+    /// it runs on the input thread of the connection before any other message
+    /// of the switch is handled, so it should not block for long
+    pub on_connect: Option<ConnectCallback>,
+    /// traffic counters of all connections
+    /// keep a clone of it to take snapshots
+    pub metrics: Arc<Metrics>,
+    /// a SlowConsumer event is sent if more messages wait to be sent to a
+    /// connection, None to never warn
+    pub queue_warning_threshold: Option<usize>,
+    /// highest OpenFlow version negotiated with switches, None for ds::MAX_VERSION
+    pub max_version: Option<ds::Version>,
+    /// tcp options of the connections to the switches (nodelay, keepalive, timeouts)
+    pub socket_options: SocketOptions,
+    /// runs before the handler on every message it would receive
+    pub middleware: MiddlewareChain,
+    /// if set, sent to every switch speaking OpenFlow 1.3 before on_connect runs
+    /// (eg. `ds::async::Async::listen_only()`), None keeps the config of the switch
+    pub async_config: Option<ds::async::Async>,
+    /// if set, switch handles refuse to send messages that break rules of the
+    /// specification (see `ds::validate`) with ErrorKind::InvalidMessage
+    pub validate_messages: bool,
+    /// if set, frames that could not be decoded are logged as a hexdump and the
+    /// last ones are kept here with their error (see `diagnostics`)
+    pub bad_frames: Option<Arc<BadFrames>>,
+    /// if set, an echo request is sent to every registered switch at this
+    /// interval, their replies measure the round trip time
+    /// (see `ConnectionStats::echo_rtt`)
+    pub echo_interval: Option<Duration>,
+    /// if set, the config of every switch is requested after the handshake
+    /// and replaced by this one if it differs, None keeps the config of the switch
+    pub switch_config: Option<ds::switch_config::SwitchConfig>,
+    /// if set, a connection is closed and reported with a WriteStalled event
+    /// if writing the queued messages takes longer (eg. the switch stopped
+    /// reading), this limits the whole write while SocketOptions::write_timeout
+    /// limits every system call, transports without write timeouts only check
+    /// it between writes
+    pub write_deadline: Option<Duration>,
+    /// if set, the state changing messages sent to switches are recorded here
+    /// together with the errors the switches answer them with (see `audit`)
+    pub audit_log: Option<Arc<AuditLog>>,
+    /// whether messages of switches with unknown bits in their flags fail to
+    /// decode (Strict) or are handled without the bits (Truncate), dropped bits
+    /// are reported with an UnknownFlags event (see `ds::flags`)
+    pub flags_policy: FlagsPolicy,
+    /// if set, every message decoded from a switch or sent to one is copied to
+    /// the subscribers of the tap (see `tap`)
+    pub tap: Option<Arc<Tap>>,
+}
+
+impl fmt::Debug for ControllerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ControllerConfig")
+            .field("capture_file", &self.capture_file)
+            .field("accept_policy", &self.accept_policy)
+            .field("datapath_policy", &self.datapath_policy.is_some())
+            .field("event_ch", &self.event_ch)
+            .field("registry", &self.registry)
+            .field("reconnect_backoff", &self.reconnect_backoff)
+            .field("on_connect", &self.on_connect.is_some())
+            .field("metrics", &self.metrics)
+            .field("queue_warning_threshold", &self.queue_warning_threshold)
+            .field("max_version", &self.max_version)
+            .field("socket_options", &self.socket_options)
+            .field("middleware", &self.middleware)
+            .field("async_config", &self.async_config)
+            .field("validate_messages", &self.validate_messages)
+            .field("bad_frames", &self.bad_frames)
+            .field("echo_interval", &self.echo_interval)
+            .field("switch_config", &self.switch_config)
+            .field("write_deadline", &self.write_deadline)
+            .field("audit_log", &self.audit_log)
+            .field("flags_policy", &self.flags_policy)
+            .field("tap", &self.tap)
+            .finish()
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::super::err::*;
+
+/// Number of high cookie bits used for the namespace by default, allows
+/// 65535 namespaces with 2^48 cookies each.
+pub const DEFAULT_NAMESPACE_BITS: u32 = 16;
+
+/// Hands out flow cookies that are unique across the applications of a
+/// controller.
+///
+/// The high bits of a cookie tell the namespace (eg. an application or a
+/// module) it belongs to, the low bits are counted up within the namespace.
+/// Namespace 0 is used by `next`, cookie 0 is never handed out as it is the
+/// cookie of flows installed without one.
+/// Flows of a namespace are selected with its prefix and mask, eg. to delete
+/// only the flows of one application.
+#[derive(Debug)]
+pub struct CookieAllocator {
+    namespace_bits: u32,
+    default: CookieNamespace,
+    namespaces: Mutex<HashMap<String, CookieNamespace>>,
+}
+
+impl Default for CookieAllocator {
+    fn default() -> Self {
+        // the default is in range
+        CookieAllocator::new(DEFAULT_NAMESPACE_BITS).unwrap()
+    }
+}
+
+impl CookieAllocator {
+    /// uses the given number of high bits for the namespace (1 to 63)
+    /// fails if the number is out of range
+    pub fn new(namespace_bits: u32) -> Result<Self> {
+        if namespace_bits == 0 || namespace_bits >= 64 {
+            bail!(ErrorKind::IllegalValue(
+                namespace_bits as u64,
+                stringify!(namespace_bits)
+            ));
+        }
+        Ok(CookieAllocator {
+            namespace_bits: namespace_bits,
+            default: CookieNamespace::new(String::new(), 0, namespace_bits),
+            namespaces: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// next cookie of namespace 0
+    pub fn next(&self) -> u64 {
+        self.default.next()
+    }
+
+    /// the namespace with the given name, created on first use
+    /// fails if all namespaces are taken
+    pub fn namespace(&self, name: &str) -> Result<CookieNamespace> {
+        let mut namespaces = self.namespaces.lock().unwrap();
+        if let Some(namespace) = namespaces.get(name) {
+            return Ok(namespace.clone());
+        }
+        let id = namespaces.len() as u64 + 1;
+        if id >= 1 << self.namespace_bits {
+            bail!(ErrorKind::CookiesExhausted(name.to_string()));
+        }
+        let namespace = CookieNamespace::new(name.to_string(), id, self.namespace_bits);
+        namespaces.insert(name.to_string(), namespace.clone());
+        Ok(namespace)
+    }
+
+    /// namespace a cookie belongs to, None for namespace 0 and unknown namespaces
+    pub fn namespace_of(&self, cookie: u64) -> Option<CookieNamespace> {
+        self.namespaces
+            .lock()
+            .unwrap()
+            .values()
+            .find(|namespace| namespace.contains(cookie))
+            .cloned()
+    }
+}
+
+/// Cookies sharing the same high bits, see `CookieAllocator`.
+/// Clones hand out cookies from the same counter.
+#[derive(Debug, Clone)]
+pub struct CookieNamespace {
+    name: String,
+    prefix: u64,
+    mask: u64,
+    next: Arc<AtomicU64>,
+}
+
+impl CookieNamespace {
+    fn new(name: String, id: u64, namespace_bits: u32) -> Self {
+        let shift = 64 - namespace_bits;
+        CookieNamespace {
+            name: name,
+            prefix: id << shift,
+            mask: !0 << shift,
+            next: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// high bits of all cookies of the namespace, use it as cookie of deletes
+    pub fn prefix(&self) -> u64 {
+        self.prefix
+    }
+
+    /// selects the namespace bits of a cookie, use it as cookie_mask of deletes
+    pub fn mask(&self) -> u64 {
+        self.mask
+    }
+
+    /// next unique cookie of the namespace, starts over once all were handed out
+    pub fn next(&self) -> u64 {
+        let counter = !self.mask;
+        // skips 0 so namespace 0 never hands out cookie 0
+        let count = self.next.fetch_add(1, Ordering::SeqCst) % counter;
+        self.prefix | (count + 1)
+    }
+
+    pub fn contains(&self, cookie: u64) -> bool {
+        cookie & self.mask == self.prefix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespaces_partition_cookies() {
+        let cookies = CookieAllocator::new(8).unwrap();
+        assert_eq!(cookies.next(), 1);
+        assert_eq!(cookies.next(), 2);
+
+        let routing = cookies.namespace("routing").unwrap();
+        let acl = cookies.namespace("acl").unwrap();
+        assert_eq!(routing.mask(), 0xff00_0000_0000_0000);
+        assert_eq!(routing.prefix(), 0x0100_0000_0000_0000);
+        assert_eq!(acl.prefix(), 0x0200_0000_0000_0000);
+
+        let cookie = routing.next();
+        assert_eq!(cookie, 0x0100_0000_0000_0001);
+        assert!(routing.contains(cookie));
+        assert!(!acl.contains(cookie));
+        // the same name returns the same namespace and counter
+        assert_eq!(cookies.namespace("routing").unwrap().next(), cookie + 1);
+        assert_eq!(cookies.namespace_of(cookie).unwrap().name(), "routing");
+        assert!(cookies.namespace_of(1).is_none());
+    }
+
+    #[test]
+    fn namespaces_run_out() {
+        let cookies = CookieAllocator::new(2).unwrap();
+        for name in &["a", "b", "c"] {
+            cookies.namespace(name).unwrap();
+        }
+        assert!(cookies.namespace("d").is_err());
+        assert!(cookies.namespace("a").is_ok());
+    }
+
+    #[test]
+    fn namespace_bits_have_to_be_in_range() {
+        assert!(CookieAllocator::new(0).is_err());
+        assert!(CookieAllocator::new(64).is_err());
+        assert!(CookieAllocator::new(63).is_ok());
+    }
+}
@@ -0,0 +1,179 @@
+//! Frames the controller could not decode, kept to diagnose decoder bugs.
+//!
+//! The error of a failed decode only keeps a few bytes of the message. With
+//! `ControllerConfig::bad_frames` set, the whole frame is logged as a hexdump
+//! and the last frames are kept together with the error:
+//!
+//! ```
+//! use oath2::ctl::diagnostics::BadFrames;
+//! use oath2::ctl::ControllerConfig;
+//! use std::sync::Arc;
+//!
+//! let bad_frames = Arc::new(BadFrames::new(16));
+//! let mut config = ControllerConfig::default();
+//! config.bad_frames = Some(bad_frames.clone());
+//! // later, eg. from a debug endpoint
+//! for frame in bad_frames.recent() {
+//!     println!("{}\n{}", frame.error, frame.hexdump());
+//! }
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use super::super::err::MessageContext;
+
+/// Number of bad frames kept by default.
+pub const DEFAULT_BAD_FRAMES: usize = 32;
+
+/// A frame that could not be decoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BadFrame {
+    pub received: SystemTime,
+    /// None if the switch did not send its features reply yet
+    pub datapath_id: Option<u64>,
+    pub peer: Option<SocketAddr>,
+    /// the decode error
+    pub error: String,
+    /// where the decoding failed, the offset is relative to the payload
+    /// unless the header could not be decoded
+    pub context: MessageContext,
+    /// the whole frame including its header
+    pub bytes: Vec<u8>,
+}
+
+impl BadFrame {
+    pub fn hexdump(&self) -> String {
+        hexdump(&self.bytes)
+    }
+}
+
+/// Ring buffer of the last bad frames, see the module documentation.
+#[derive(Debug)]
+pub struct BadFrames {
+    frames: Mutex<VecDeque<BadFrame>>,
+    capacity: usize,
+    total: AtomicUsize,
+}
+
+impl Default for BadFrames {
+    fn default() -> Self {
+        BadFrames::new(DEFAULT_BAD_FRAMES)
+    }
+}
+
+impl BadFrames {
+    /// keeps the last capacity frames
+    pub fn new(capacity: usize) -> Self {
+        BadFrames {
+            frames: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: capacity,
+            total: AtomicUsize::new(0),
+        }
+    }
+
+    /// keeps the frame, the oldest one is dropped if the buffer is full
+    pub fn record(&self, frame: BadFrame) {
+        self.total.fetch_add(1, Ordering::SeqCst);
+        if self.capacity == 0 {
+            return;
+        }
+        let mut frames = self.frames.lock().unwrap();
+        if frames.len() == self.capacity {
+            frames.pop_front();
+        }
+        frames.push_back(frame);
+    }
+
+    /// the kept frames, oldest first
+    pub fn recent(&self) -> Vec<BadFrame> {
+        self.frames.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// number of frames recorded so far, including the dropped ones
+    pub fn total(&self) -> usize {
+        self.total.load(Ordering::SeqCst)
+    }
+
+    pub fn clear(&self) {
+        self.frames.lock().unwrap().clear();
+    }
+}
+
+/// the bytes as lines of 16 in hex and ascii with their offset, eg.
+/// `0000  04 0a 00 0c 00 00 00 05  01 02 03 04              |............|`
+pub fn hexdump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        if line > 0 {
+            dump.push('\n');
+        }
+        let _ = write!(dump, "{:04x} ", line * 16);
+        for i in 0..16 {
+            if i == 8 {
+                dump.push(' ');
+            }
+            match chunk.get(i) {
+                Some(byte) => {
+                    let _ = write!(dump, " {:02x}", byte);
+                }
+                None => dump.push_str("   "),
+            }
+        }
+        dump.push_str("  |");
+        for &byte in chunk {
+            let printable = byte.is_ascii_graphic() || byte == b' ';
+            dump.push(if printable { byte as char } else { '.' });
+        }
+        dump.push('|');
+    }
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(byte: u8) -> BadFrame {
+        BadFrame {
+            received: SystemTime::now(),
+            datapath_id: Some(1),
+            peer: None,
+            error: "bad".to_string(),
+            context: MessageContext::default(),
+            bytes: vec![byte],
+        }
+    }
+
+    #[test]
+    fn keeps_the_last_frames() {
+        let frames = BadFrames::new(2);
+        for byte in 0..3 {
+            frames.record(frame(byte));
+        }
+        let bytes: Vec<u8> = frames.recent().iter().map(|frame| frame.bytes[0]).collect();
+        assert_eq!(bytes, vec![1, 2]);
+        assert_eq!(frames.total(), 3);
+        frames.clear();
+        assert!(frames.recent().is_empty());
+    }
+
+    #[test]
+    fn dumps_hex_and_ascii() {
+        let bytes: Vec<u8> = b"OpenFlow message!".to_vec();
+        assert_eq!(
+            hexdump(&bytes),
+            "0000  4f 70 65 6e 46 6c 6f 77  20 6d 65 73 73 61 67 65  |OpenFlow message|\n\
+             0010  21                                                |!|"
+        );
+        assert_eq!(
+            hexdump(&[0x04, 0x0a, 0x00]),
+            "0000  04 0a 00                                          |...|"
+        );
+        assert_eq!(hexdump(&[]), "");
+    }
+}
@@ -0,0 +1,200 @@
+//! Handlers for single message types.
+//!
+//! Instead of one handler that matches on the payload of every message, a
+//! `Dispatcher` calls the handlers registered for the type of a message with
+//! the message itself:
+//!
+//! ```
+//! use oath2::ctl::dispatch::Dispatcher;
+//! use oath2::ds::packet_in::PacketIn;
+//! use oath2::ds::port_status::PortStatus;
+//!
+//! let dispatcher = Dispatcher::new()
+//!     .on::<PacketIn>(|ctx, packet_in| {
+//!         println!("{:?} from {:?}", packet_in.eth_type(), ctx.datapath_id());
+//!     })
+//!     .on::<PortStatus>(|_ctx, port_status| println!("{:?}", port_status));
+//! // oath2::ctl::start_controller("127.0.0.1:6653", dispatcher.handler());
+//! ```
+//!
+//! Handlers of the same type are called in the order they were registered.
+//! Messages of types without a handler are dropped.
+
+use std::fmt;
+
+use super::super::ds;
+use super::super::ds::async::Async;
+use super::super::ds::bundle::BundleCtrl;
+use super::super::ds::error_msg::ErrorMsg;
+use super::super::ds::features::SwitchFeatures;
+use super::super::ds::flow_removed::FlowRemoved;
+use super::super::ds::multipart::MultipartReply;
+use super::super::ds::packet_in::PacketIn;
+use super::super::ds::port_status::PortStatus;
+use super::super::ds::queue_config::QueueGetConfigReply;
+use super::super::ds::role::Role;
+use super::super::ds::switch_config::SwitchConfig;
+use super::middleware::SwitchContext;
+use super::switch::IncomingMsg;
+
+/// A message a switch sends to the controller, see `Dispatcher::on`.
+pub trait Message: Send + Sync + 'static {
+    /// type of the messages with this payload
+    const TYPE: ds::Type;
+
+    /// the message of the payload, None if the payload is another message
+    fn from_payload(payload: &ds::OfPayload) -> Option<&Self>;
+}
+
+macro_rules! message {
+    ($message:ty, $variant:ident, $as_message:ident) => {
+        impl Message for $message {
+            const TYPE: ds::Type = ds::Type::$variant;
+
+            fn from_payload(payload: &ds::OfPayload) -> Option<&Self> {
+                payload.$as_message()
+            }
+        }
+    };
+}
+
+message!(ErrorMsg, Error, as_error);
+message!(SwitchFeatures, FeaturesReply, as_features_reply);
+message!(SwitchConfig, GetConfigReply, as_get_config_reply);
+message!(PacketIn, PacketIn, as_packet_in);
+message!(FlowRemoved, FlowRemoved, as_flow_removed);
+message!(PortStatus, PortStatus, as_port_status);
+message!(MultipartReply, MultipartReply, as_multipart_reply);
+message!(
+    QueueGetConfigReply,
+    QueueGetConfigReply,
+    as_queue_get_config_reply
+);
+message!(Role, RoleReply, as_role_reply);
+message!(Async, GetAsyncReply, as_get_async_reply);
+message!(BundleCtrl, BundleControl, as_bundle_control);
+
+type Handler = Box<dyn Fn(&SwitchContext, &ds::OfPayload) + Send + Sync>;
+
+/// Calls the handlers of the type of each message, see the module documentation.
+#[derive(Default)]
+pub struct Dispatcher {
+    handlers: Vec<(ds::Type, Handler)>,
+}
+
+impl Dispatcher {
+    pub fn new() -> Self {
+        Dispatcher::default()
+    }
+
+    /// calls the handler with the messages of type T, eg. `on::<PacketIn>(|ctx, packet_in| ..)`
+    pub fn on<T: Message>(
+        mut self,
+        handler: impl Fn(&SwitchContext, &T) + Send + Sync + 'static,
+    ) -> Self {
+        let handler = move |ctx: &SwitchContext, payload: &ds::OfPayload| {
+            if let Some(message) = T::from_payload(payload) {
+                handler(ctx, message);
+            }
+        };
+        self.handlers.push((T::TYPE, Box::new(handler)));
+        self
+    }
+
+    /// number of registered handlers
+    pub fn len(&self) -> usize {
+        self.handlers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    /// calls the handlers of the type of the message
+    /// returns the number of handlers called, 0 if the message was dropped
+    pub fn dispatch(&self, msg: &IncomingMsg) -> usize {
+        let ttype = msg.msg.header().ttype();
+        let ctx = SwitchContext::new(msg);
+        let mut called = 0;
+        for (handler_type, handler) in &self.handlers {
+            if handler_type == ttype {
+                handler(&ctx, msg.msg.payload());
+                called += 1;
+            }
+        }
+        called
+    }
+
+    /// handler for start_controller and friends that dispatches every message
+    pub fn handler(self) -> impl Fn(IncomingMsg) + Send + Sync + 'static {
+        move |msg| {
+            if self.dispatch(&msg) == 0 {
+                debug!("No handler for {:?}, dropped.", msg.msg.header().ttype());
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Dispatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let types: Vec<&ds::Type> = self.handlers.iter().map(|(ttype, _)| ttype).collect();
+        f.debug_struct("Dispatcher")
+            .field("handlers", &types)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ds::error_msg::ErrorType;
+    use super::super::super::ds::factory;
+    use super::super::xid::XidAllocator;
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Mutex};
+
+    fn incoming(payload: ds::OfPayload) -> IncomingMsg {
+        let (reply_ch, _) = channel();
+        IncomingMsg {
+            reply_ch: reply_ch,
+            msg: ds::OfMsg::generate(1, payload),
+            switch: None,
+            xids: Arc::new(XidAllocator::new()),
+            version: ds::Version::V1_3,
+            peer: None,
+            local: None,
+            event_ch: None,
+        }
+    }
+
+    #[test]
+    fn calls_the_handlers_of_the_type() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let (errors, features) = (seen.clone(), seen.clone());
+        let dispatcher = Dispatcher::new()
+            .on::<ErrorMsg>(move |_ctx, error| {
+                errors.lock().unwrap().push(format!("error {}", error.code));
+            })
+            .on::<SwitchFeatures>(move |_ctx, reply| {
+                features
+                    .lock()
+                    .unwrap()
+                    .push(format!("features {}", reply.datapath_id));
+            });
+        assert_eq!(dispatcher.len(), 2);
+
+        let error = ErrorMsg::new(ErrorType::BadRequest, 3, &[]);
+        assert_eq!(
+            dispatcher.dispatch(&incoming(ds::OfPayload::Error(error))),
+            1
+        );
+        let reply = ds::OfPayload::FeaturesReply(factory::features_reply(0x2a));
+        assert_eq!(dispatcher.dispatch(&incoming(reply)), 1);
+        // no handler
+        assert_eq!(
+            dispatcher.dispatch(&incoming(ds::OfPayload::BarrierReply)),
+            0
+        );
+        assert_eq!(*seen.lock().unwrap(), vec!["error 3", "features 42"]);
+    }
+}
@@ -0,0 +1,121 @@
+use std::net::SocketAddr;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use super::super::ds::error_msg::ErrorMsg;
+use super::super::ds::port_status::PortReason;
+use super::super::ds::ports::Port;
+use super::super::ds::Type;
+use super::accept::RejectReason;
+
+/// Events about the state of the controller itself (not OpenFlow messages).
+/// Set `ControllerConfig::event_ch` to receive them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControllerEvent {
+    /// an incoming connection was closed right away because of the accept policy
+    ConnectionRejected {
+        peer: SocketAddr,
+        reason: RejectReason,
+    },
+    /// an incoming connection over AcceptPolicy::max_switches got a hello and a
+    /// hello failed error before it was closed
+    ConnectionShed {
+        peer: Option<SocketAddr>,
+        /// number of admitted connections that were open
+        active: usize,
+    },
+    /// a connection sent its features reply and was registered
+    SwitchConnected {
+        datapath_id: u64,
+        auxiliary_id: u8,
+        peer: Option<SocketAddr>,
+    },
+    /// a connection was closed after its features reply because of
+    /// ControllerConfig::datapath_policy
+    SwitchRejected {
+        datapath_id: u64,
+        peer: Option<SocketAddr>,
+    },
+    /// a registered connection was closed or its input thread failed
+    SwitchDisconnected {
+        datapath_id: u64,
+        auxiliary_id: u8,
+        peer: Option<SocketAddr>,
+    },
+    /// a thread of a connection panicked (eg. in the handshake or the datapath
+    /// policy), the connection was closed, a registered connection is also
+    /// reported as SwitchDisconnected
+    /// panics of the handler are reported for the switch of the message, its
+    /// connection stays open and the handler gets the next message
+    ConnectionPanicked {
+        /// None if the switch did not send its features reply yet
+        datapath_id: Option<u64>,
+        peer: Option<SocketAddr>,
+        /// name of the thread, eg. `Switch-In 127.0.0.1:43210`
+        thread: String,
+        message: String,
+    },
+    /// a message had bits set in its flags that oath2 does not know and was
+    /// handled without them because of ControllerConfig::flags_policy
+    UnknownFlags {
+        datapath_id: Option<u64>,
+        peer: Option<SocketAddr>,
+        /// type of the message
+        ttype: Type,
+        /// name of the flags type, eg. `FlowModFlags`
+        flags: &'static str,
+        /// only the dropped bits
+        bits: u64,
+    },
+    /// more messages than ControllerConfig::queue_warning_threshold were waiting
+    /// to be sent to a connection, sent again after the queue went below the threshold
+    SlowConsumer {
+        datapath_id: Option<u64>,
+        peer: Option<SocketAddr>,
+        queue_depth: usize,
+    },
+    /// writing to a connection took longer than ControllerConfig::write_deadline
+    /// or SocketOptions::write_timeout (eg. the switch stopped reading), the
+    /// connection was closed with the bytes still pending
+    WriteStalled {
+        datapath_id: Option<u64>,
+        peer: Option<SocketAddr>,
+        pending: usize,
+    },
+    /// a switch reported a port that was added, removed or changed
+    /// the port table of the switch handle is already updated
+    PortChanged {
+        datapath_id: u64,
+        reason: PortReason,
+        port: Port,
+    },
+    /// a message of the switch was not understood and answered with the error,
+    /// the message is not passed to the handler
+    MessageRejected {
+        datapath_id: Option<u64>,
+        peer: Option<SocketAddr>,
+        xid: u32,
+        error: ErrorMsg,
+    },
+    /// connect_to_switch will try again after the delay
+    ReconnectScheduled {
+        addrs: Vec<SocketAddr>,
+        attempt: u32,
+        delay: Duration,
+    },
+    /// connect_to_switch gave up after the configured number of retries
+    ReconnectAbandoned {
+        addrs: Vec<SocketAddr>,
+        attempts: u32,
+    },
+}
+
+/// sends an event if someone is listening
+/// a receiver that went away is not an error for the controller
+pub fn emit(event_ch: &Option<Sender<ControllerEvent>>, event: ControllerEvent) {
+    if let Some(ref event_ch) = *event_ch {
+        if event_ch.send(event).is_err() {
+            debug!("Controller event receiver is gone.");
+        }
+    }
+}
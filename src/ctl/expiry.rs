@@ -0,0 +1,336 @@
+//! Controller side view of when installed flows expire.
+//!
+//! The switch only reports an expired flow with a flow removed message (and
+//! only if the flow was installed with SEND_FLOW_REM). `FlowTimers` follows the
+//! idle and hard timeouts of the flows the controller sent, so applications can
+//! ask how long a flow has left and refresh soft state before it expires.
+//! The idle timer of a flow restarts with every packet the switch matches, which
+//! the controller does not see, so the remaining time is the earliest possible
+//! expiry unless `touch` is called (eg. when flow stats show new packets).
+
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+
+use super::super::ds::flow_match::Match;
+use super::super::ds::flow_mod::{FlowMod, FlowModBuilder, FlowModCommand};
+use super::super::ds::flow_removed::FlowRemoved;
+use super::super::err::*;
+use super::cookie::CookieNamespace;
+
+/// Identifies a flow entry in a switch (table, priority and match).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FlowKey {
+    pub table_id: u8,
+    pub priority: u16,
+    /// encoded normalized match, see `Match::normalized`
+    mmatch: Vec<u8>,
+}
+
+impl FlowKey {
+    pub fn new(table_id: u8, priority: u16, mmatch: &Match) -> Self {
+        FlowKey {
+            table_id: table_id,
+            priority: priority,
+            mmatch: mmatch.normalized().into(),
+        }
+    }
+
+    pub fn of(flow_mod: &FlowMod) -> Self {
+        FlowKey::new(flow_mod.table_id, flow_mod.priority, &flow_mod.mmatch)
+    }
+
+    pub fn of_removed(flow_removed: &FlowRemoved) -> Self {
+        FlowKey::new(
+            *flow_removed.table_id(),
+            *flow_removed.priority(),
+            flow_removed.mmatch(),
+        )
+    }
+}
+
+#[derive(Debug)]
+struct TimedFlow {
+    flow_mod: FlowMod,
+    installed: Instant,
+    last_hit: Instant,
+    refresh: bool,
+    deadline: Option<Instant>,
+}
+
+impl TimedFlow {
+    /// time the first of both timeouts runs out, None for permanent flows
+    fn compute_deadline(&self) -> Option<Instant> {
+        let idle = match self.flow_mod.idle_timeout {
+            0 => None,
+            secs => Some(self.last_hit + Duration::from_secs(u64::from(secs))),
+        };
+        let hard = match self.flow_mod.hard_timeout {
+            0 => None,
+            secs => Some(self.installed + Duration::from_secs(u64::from(secs))),
+        };
+        match (idle, hard) {
+            (Some(idle), Some(hard)) => Some(::std::cmp::min(idle, hard)),
+            (idle, None) => idle,
+            (None, hard) => hard,
+        }
+    }
+}
+
+/// Timeouts of the flows installed by the controller, ordered by expiry.
+/// Methods ending in `_at` take the current time to make them testable,
+/// the others use `Instant::now()`.
+#[derive(Debug, Default)]
+pub struct FlowTimers {
+    flows: HashMap<FlowKey, TimedFlow>,
+    deadlines: BTreeSet<(Instant, FlowKey)>,
+}
+
+impl FlowTimers {
+    pub fn new() -> Self {
+        FlowTimers::default()
+    }
+
+    /// number of tracked flows
+    pub fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flows.is_empty()
+    }
+
+    /// flow mods of all tracked flows, eg. to check new flows with analysis::check_flow_mod
+    pub fn flow_mods(&self) -> Vec<&FlowMod> {
+        self.flows.values().map(|flow| &flow.flow_mod).collect()
+    }
+
+    /// records a flow mod sent to the switch
+    /// if refresh is set the flow is returned by due_refreshes before it expires
+    pub fn track(&mut self, flow_mod: &FlowMod, refresh: bool) {
+        self.track_at(flow_mod, refresh, Instant::now())
+    }
+
+    /// adds start the timeouts of their flow, modifies only replace the instructions,
+    /// deletes stop tracking flows with the same match
+    /// (non strict deletes of wider matches are not followed)
+    pub fn track_at(&mut self, flow_mod: &FlowMod, refresh: bool, now: Instant) {
+        let key = FlowKey::of(flow_mod);
+        match flow_mod.command {
+            FlowModCommand::Add => {
+                self.schedule(
+                    key,
+                    TimedFlow {
+                        flow_mod: flow_mod.clone(),
+                        installed: now,
+                        last_hit: now,
+                        refresh: refresh,
+                        deadline: None,
+                    },
+                );
+            }
+            FlowModCommand::Modify | FlowModCommand::ModifyStrict => {
+                if let Some(flow) = self.flows.get_mut(&key) {
+                    flow.flow_mod.instructions = flow_mod.instructions.clone();
+                    flow.refresh = refresh;
+                }
+            }
+            FlowModCommand::DeleteStrict => {
+                self.remove(&key);
+            }
+            FlowModCommand::Delete => {
+                let keys: Vec<FlowKey> = self
+                    .flows
+                    .keys()
+                    .filter(|other| {
+                        other.mmatch == key.mmatch
+                            && (flow_mod.table_id == ALL_TABLES || other.table_id == key.table_id)
+                    })
+                    .cloned()
+                    .collect();
+                for key in keys {
+                    self.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// builds the flow with SEND_FLOW_REM and the next cookie of the namespace
+    /// and tracks it, returns the flow mod to send
+    /// fails if the flow can not be built, see `FlowModBuilder::build`
+    pub fn add_tracked(
+        &mut self,
+        builder: FlowModBuilder,
+        cookies: &CookieNamespace,
+        refresh: bool,
+    ) -> Result<FlowMod> {
+        let flow_mod = builder.tracked(cookies.next()).build()?;
+        self.track(&flow_mod, refresh);
+        Ok(flow_mod)
+    }
+
+    /// the switch reported the flow as removed
+    pub fn removed(&mut self, flow_removed: &FlowRemoved) {
+        self.remove(&FlowKey::of_removed(flow_removed));
+    }
+
+    /// the flow matched packets, restarts its idle timeout
+    pub fn touch(&mut self, key: &FlowKey) {
+        self.touch_at(key, Instant::now())
+    }
+
+    pub fn touch_at(&mut self, key: &FlowKey, now: Instant) {
+        if let Some(mut flow) = self.remove(key) {
+            flow.last_hit = now;
+            self.schedule(key.clone(), flow);
+        }
+    }
+
+    /// time until the flow expires
+    /// None if the flow is not tracked or has no timeout
+    pub fn remaining(&self, key: &FlowKey) -> Option<Duration> {
+        self.remaining_at(key, Instant::now())
+    }
+
+    pub fn remaining_at(&self, key: &FlowKey, now: Instant) -> Option<Duration> {
+        self.flows
+            .get(key)
+            .and_then(|flow| flow.deadline)
+            .map(|deadline| deadline.checked_duration_since(now).unwrap_or_default())
+    }
+
+    /// stops tracking all flows that expired and returns them
+    pub fn expired(&mut self) -> Vec<FlowMod> {
+        self.expired_at(Instant::now())
+    }
+
+    pub fn expired_at(&mut self, now: Instant) -> Vec<FlowMod> {
+        self.due(now)
+            .into_iter()
+            .filter_map(|key| self.remove(&key))
+            .map(|flow| flow.flow_mod)
+            .collect()
+    }
+
+    /// flows marked for refresh that expire within margin
+    /// returns the flow mods to send again, their timeouts start over
+    pub fn due_refreshes(&mut self, margin: Duration) -> Vec<FlowMod> {
+        self.due_refreshes_at(margin, Instant::now())
+    }
+
+    pub fn due_refreshes_at(&mut self, margin: Duration, now: Instant) -> Vec<FlowMod> {
+        let mut refreshes = Vec::new();
+        for key in self.due(now + margin) {
+            let refresh = self.flows.get(&key).map(|flow| flow.refresh) == Some(true);
+            if !refresh {
+                continue;
+            }
+            if let Some(mut flow) = self.remove(&key) {
+                // adding the same flow again replaces it and restarts both timeouts
+                flow.installed = now;
+                flow.last_hit = now;
+                refreshes.push(flow.flow_mod.clone());
+                self.schedule(key, flow);
+            }
+        }
+        refreshes
+    }
+
+    /// keys of all flows expiring until the given time
+    fn due(&self, until: Instant) -> Vec<FlowKey> {
+        self.deadlines
+            .iter()
+            .take_while(|&&(deadline, _)| deadline <= until)
+            .map(|(_, key)| key.clone())
+            .collect()
+    }
+
+    fn schedule(&mut self, key: FlowKey, mut flow: TimedFlow) {
+        self.remove(&key);
+        flow.deadline = flow.compute_deadline();
+        if let Some(deadline) = flow.deadline {
+            self.deadlines.insert((deadline, key.clone()));
+        }
+        self.flows.insert(key, flow);
+    }
+
+    fn remove(&mut self, key: &FlowKey) -> Option<TimedFlow> {
+        let flow = self.flows.remove(key);
+        if let Some(deadline) = flow.as_ref().and_then(|flow| flow.deadline) {
+            self.deadlines.remove(&(deadline, key.clone()));
+        }
+        flow
+    }
+}
+
+/// table id of flow mods that apply to all tables (OFPTT_ALL)
+const ALL_TABLES: u8 = 0xff;
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ds::flow_mod::FlowModFlags;
+    use super::super::super::ds::flow_parser::{parse_flow_mod, parse_match};
+    use super::super::cookie::CookieAllocator;
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn tracks_idle_and_hard_timeouts() {
+        let start = Instant::now();
+        let mut timers = FlowTimers::new();
+        let soft =
+            parse_flow_mod("idle_timeout=10,hard_timeout=30,in_port=1,actions=drop").unwrap();
+        let permanent = parse_flow_mod("in_port=2,actions=drop").unwrap();
+        timers.track_at(&soft, false, start);
+        timers.track_at(&permanent, false, start);
+
+        let key = FlowKey::of(&soft);
+        assert_eq!(timers.remaining_at(&key, start + secs(4)), Some(secs(6)));
+        assert_eq!(timers.remaining_at(&FlowKey::of(&permanent), start), None);
+
+        // traffic restarts the idle timeout, the hard timeout still applies
+        timers.touch_at(&key, start + secs(25));
+        assert_eq!(timers.remaining_at(&key, start + secs(25)), Some(secs(5)));
+
+        assert!(timers.expired_at(start + secs(29)).is_empty());
+        assert_eq!(timers.expired_at(start + secs(30)), vec![soft]);
+        assert_eq!(timers.len(), 1);
+    }
+
+    #[test]
+    fn refreshes_before_expiry() {
+        let start = Instant::now();
+        let mut timers = FlowTimers::new();
+        let refreshed = parse_flow_mod("hard_timeout=10,in_port=1,actions=drop").unwrap();
+        let other = parse_flow_mod("hard_timeout=10,in_port=2,actions=drop").unwrap();
+        timers.track_at(&refreshed, true, start);
+        timers.track_at(&other, false, start);
+
+        assert!(timers.due_refreshes_at(secs(2), start + secs(7)).is_empty());
+        assert_eq!(
+            timers.due_refreshes_at(secs(2), start + secs(8)),
+            vec![refreshed.clone()]
+        );
+        let key = FlowKey::of(&refreshed);
+        assert_eq!(timers.remaining_at(&key, start + secs(8)), Some(secs(10)));
+
+        let mut delete = refreshed.clone();
+        delete.command = FlowModCommand::DeleteStrict;
+        timers.track_at(&delete, false, start + secs(9));
+        assert_eq!(timers.remaining_at(&key, start + secs(9)), None);
+    }
+
+    #[test]
+    fn adds_tracked_flows() {
+        let cookies = CookieAllocator::new(16).unwrap().namespace("app").unwrap();
+        let mut timers = FlowTimers::new();
+        let builder = FlowModBuilder::add(parse_match("in_port=1").unwrap()).idle(30);
+        let flow_mod = timers.add_tracked(builder, &cookies, false).unwrap();
+        assert!(flow_mod.flags.contains(FlowModFlags::SEND_FLOW_REM));
+        assert!(cookies.contains(flow_mod.cookie));
+        let remaining = timers.remaining(&FlowKey::of(&flow_mod)).unwrap();
+        assert!(remaining > secs(29) && remaining <= secs(30));
+    }
+}
@@ -0,0 +1,184 @@
+use byteorder::{BigEndian, ByteOrder};
+use std::io::{self, Read};
+
+use super::super::ds::HEADER_LENGTH;
+
+/// Size of the read buffer, it grows if a single message is bigger.
+pub const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Splits a stream into OpenFlow messages.
+/// Every read fills as much of the buffer as the stream has available,
+/// all complete messages in it are returned without reading again.
+#[derive(Debug)]
+pub struct FrameReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    /// size the buffer returns to when it is compacted
+    capacity: usize,
+    /// first byte not returned yet
+    start: usize,
+    /// end of the bytes read so far
+    end: usize,
+    reads: u64,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(inner: R) -> Self {
+        FrameReader::with_capacity(READ_BUFFER_SIZE, inner)
+    }
+
+    pub fn with_capacity(capacity: usize, inner: R) -> Self {
+        let capacity = ::std::cmp::max(capacity, HEADER_LENGTH);
+        FrameReader {
+            inner: inner,
+            buf: vec![0u8; capacity],
+            capacity: capacity,
+            start: 0,
+            end: 0,
+            reads: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// number of reads done on the stream so far
+    pub fn reads(&self) -> u64 {
+        self.reads
+    }
+
+    /// returns the next message including its header
+    /// returns None if the stream was closed, a partial message at the end is dropped
+    pub fn next_frame(&mut self) -> io::Result<Option<&[u8]>> {
+        loop {
+            let needed = match self.frame_len()? {
+                Some(len) if self.end - self.start >= len => {
+                    let start = self.start;
+                    self.start += len;
+                    return Ok(Some(&self.buf[start..start + len]));
+                }
+                Some(len) => len,
+                None => HEADER_LENGTH,
+            };
+            self.make_room(needed);
+            match self.inner.read(&mut self.buf[self.end..]) {
+                Ok(0) => return Ok(None),
+                Ok(n) => {
+                    self.end += n;
+                    self.reads += 1;
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// length of the next message, None if its header is not complete yet
+    fn frame_len(&self) -> io::Result<Option<usize>> {
+        if self.end - self.start < HEADER_LENGTH {
+            return Ok(None);
+        }
+        let len = BigEndian::read_u16(&self.buf[self.start + 2..self.start + 4]) as usize;
+        if len < HEADER_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid OpenFlow message length {}", len),
+            ));
+        }
+        Ok(Some(len))
+    }
+
+    /// makes sure a message of the given length fits behind start
+    fn make_room(&mut self, needed: usize) {
+        if self.start == self.end {
+            self.start = 0;
+            self.end = 0;
+        }
+        if self.buf.len() - self.start >= needed && self.end < self.buf.len() {
+            return;
+        }
+        // move the partial message to the front
+        self.buf.drain(..self.start);
+        self.end -= self.start;
+        self.start = 0;
+        let len = ::std::cmp::max(needed, self.capacity);
+        self.buf.resize(len, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// returns at most `chunk` bytes per read
+    struct Chunked {
+        data: Vec<u8>,
+        pos: usize,
+        chunk: usize,
+    }
+
+    impl Read for Chunked {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = *[buf.len(), self.chunk, self.data.len() - self.pos]
+                .iter()
+                .min()
+                .unwrap();
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    fn frame(len: u16, fill: u8) -> Vec<u8> {
+        let mut frame = vec![4, 0, (len >> 8) as u8, len as u8, 0, 0, 0, fill];
+        frame.resize(len as usize, fill);
+        frame
+    }
+
+    fn frames() -> Vec<u8> {
+        let mut data = frame(8, 1);
+        data.extend(frame(100, 2));
+        data.extend(frame(3000, 3));
+        data
+    }
+
+    #[test]
+    fn reads_many_frames_at_once() {
+        let mut reader = FrameReader::new(Chunked {
+            data: frames(),
+            pos: 0,
+            chunk: usize::MAX,
+        });
+        assert_eq!(reader.next_frame().unwrap().unwrap(), &frame(8, 1)[..]);
+        assert_eq!(reader.next_frame().unwrap().unwrap(), &frame(100, 2)[..]);
+        assert_eq!(reader.next_frame().unwrap().unwrap(), &frame(3000, 3)[..]);
+        assert_eq!(reader.reads(), 1);
+        assert_eq!(reader.next_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn reads_frames_split_across_reads() {
+        let mut reader = FrameReader::with_capacity(
+            16,
+            Chunked {
+                data: frames(),
+                pos: 0,
+                chunk: 5,
+            },
+        );
+        assert_eq!(reader.next_frame().unwrap().unwrap(), &frame(8, 1)[..]);
+        assert_eq!(reader.next_frame().unwrap().unwrap(), &frame(100, 2)[..]);
+        assert_eq!(reader.next_frame().unwrap().unwrap(), &frame(3000, 3)[..]);
+        assert_eq!(reader.next_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_short_length() {
+        let mut reader = FrameReader::new(&[4u8, 0, 0, 4, 0, 0, 0, 0][..]);
+        assert_eq!(
+            reader.next_frame().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
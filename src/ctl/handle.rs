@@ -0,0 +1,1650 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::super::ds;
+use super::super::ds::actions::{self, ActionHeader};
+use super::super::ds::async::Async;
+use super::super::ds::bundle::{BundleAdd, BundleCtrl, BundleCtrlType, BundleFlags};
+use super::super::ds::error_msg::{BadRequestCode, BundleFailedCode, ErrorMsg, ErrorType};
+use super::super::ds::flow_mod::FlowMod;
+use super::super::ds::flow_stats::FlowStatsRequest;
+use super::super::ds::group_mod::{GroupFeatures, GroupMod};
+use super::super::ds::meter_mod::MeterFeatures;
+use super::super::ds::packet;
+use super::super::ds::multipart::{
+    MultipartFlags, MultipartReply, MultipartRequest, RepDesc, RepPayload, ReqPayload,
+};
+use super::super::ds::features::SwitchFeatures;
+use super::super::ds::packet_in::PacketIn;
+use super::super::ds::packet_out::PacketOut;
+use super::super::ds::port_mod::{PortAdminState, PortMod};
+use super::super::ds::port_stats::{PortStats, PortStatsRequest};
+use super::super::ds::port_status::PortReason;
+use super::super::ds::ports::{Port, PortConfig, PortNo, PortNumber};
+use super::super::ds::queue_config::{QueueConfig, QueueGetConfigRequest};
+use super::super::ds::role::Role;
+use super::super::ds::stream::{self, FlowDescIter, FlowFilter, FlowStatsIter};
+use super::super::ds::switch_config::{ConfigFlags, SwitchConfig};
+use super::super::ds::table_mod::{TableConfig, TableMod, TABLE_ALL};
+use super::super::err::*;
+use super::buffers::PacketBuffers;
+use super::metrics::{ConnectionMetrics, ConnectionStats};
+use super::pipeline::check_table;
+use super::replies::ReplyRouter;
+use super::version::NegotiatedVersion;
+use super::xid::XidAllocator;
+
+/// Flow mods sent by install_flows between two barriers.
+pub const DEFAULT_BARRIER_INTERVAL: usize = 256;
+/// Batches install_flows sends before waiting for the oldest barrier reply.
+pub const MAX_BATCHES_IN_FLIGHT: usize = 2;
+/// Time to wait for a barrier reply before giving up.
+pub const BARRIER_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time to wait for the reply of a request (eg. get_queues) before giving up.
+pub const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Result of installing flows, available once all flows were processed by the switch.
+#[derive(Debug, Default, PartialEq)]
+pub struct FlowInstallReport {
+    /// number of flow mods sent
+    pub sent: usize,
+    /// errors the switch replied with and the index of the flow mod that caused them
+    pub errors: Vec<(usize, ErrorMsg)>,
+}
+
+impl FlowInstallReport {
+    pub fn is_success(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// What `SwitchHandle::send_frame` does with frames longer than the MTU of the port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MtuPolicy {
+    /// fails with ErrorKind::ExceedsMtu
+    Refuse,
+    /// sends IPv4 packets as fragments, fails like Refuse for other frames
+    Fragment,
+}
+
+/// Flow and group mods staged by `SwitchHandle::atomic`, in the order they are applied.
+#[derive(Debug, Default)]
+pub struct BundleTxn {
+    messages: Vec<ds::OfPayload>,
+}
+
+impl BundleTxn {
+    pub fn flow_mod(&mut self, flow_mod: FlowMod) -> &mut Self {
+        self.messages.push(ds::OfPayload::FlowMod(flow_mod));
+        self
+    }
+
+    /// group mods are only available up to OpenFlow 1.4
+    pub fn group_mod(&mut self, group_mod: GroupMod) -> &mut Self {
+        self.messages.push(ds::OfPayload::GroupMod(group_mod));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+}
+
+/// Handle to a connected switch (datapath).
+/// Owns the main connection and all auxiliary connections of the datapath.
+#[derive(Debug)]
+pub struct SwitchHandle {
+    features: SwitchFeatures,
+    main: Sender<ds::OfMsg>,
+    auxiliaries: Mutex<Vec<(u8, Sender<ds::OfMsg>)>>,
+    next_channel: AtomicUsize,
+    xids: Arc<XidAllocator>,
+    version: Arc<NegotiatedVersion>,
+    replies: Arc<ReplyRouter>,
+    buffers: PacketBuffers,
+    metrics: ConnectionMetrics,
+    /// replies of get_queues by port
+    queues: Mutex<HashMap<u32, Vec<QueueConfig>>>,
+    /// requested automatically after the handshake
+    description: Mutex<Option<RepDesc>>,
+    /// requested automatically after the handshake, group and meter mods are
+    /// checked against them
+    group_features: Mutex<Option<GroupFeatures>>,
+    meter_features: Mutex<Option<MeterFeatures>>,
+    /// ports by number, filled by the port description request sent after the handshake
+    /// and kept up to date by port status messages
+    ports: Mutex<HashMap<u32, Port>>,
+    /// role of the controller from the last role reply
+    role: Mutex<Option<Role>>,
+    /// MTUs by port number, see set_port_mtu
+    mtus: Mutex<HashMap<u32, u16>>,
+    /// id of the next bundle opened by atomic
+    next_bundle_id: AtomicUsize,
+    /// cleared once the switch rejects opening a bundle, see supports_bundles
+    bundles: AtomicBool,
+    /// see set_validation
+    validate: AtomicBool,
+}
+
+impl SwitchHandle {
+    /// creates a handle for a main connection (auxiliary_id 0)
+    /// xids of the main connection are used for all messages sent through the handle
+    pub fn new(
+        features: SwitchFeatures,
+        main: Sender<ds::OfMsg>,
+        xids: Arc<XidAllocator>,
+        version: Arc<NegotiatedVersion>,
+        replies: Arc<ReplyRouter>,
+        metrics: ConnectionMetrics,
+    ) -> Self {
+        SwitchHandle {
+            buffers: PacketBuffers::new(&features),
+            features: features,
+            main: main,
+            auxiliaries: Mutex::new(Vec::new()),
+            next_channel: AtomicUsize::new(0),
+            xids: xids,
+            version: version,
+            replies: replies,
+            metrics: metrics,
+            queues: Mutex::new(HashMap::new()),
+            description: Mutex::new(None),
+            group_features: Mutex::new(None),
+            meter_features: Mutex::new(None),
+            ports: Mutex::new(HashMap::new()),
+            role: Mutex::new(None),
+            mtus: Mutex::new(HashMap::new()),
+            next_bundle_id: AtomicUsize::new(1),
+            bundles: AtomicBool::new(true),
+            validate: AtomicBool::new(false),
+        }
+    }
+
+    pub fn datapath_id(&self) -> u64 {
+        self.features.datapath_id
+    }
+
+    /// features reported on the main connection
+    pub fn features(&self) -> &SwitchFeatures {
+        &self.features
+    }
+
+    /// number of currently open auxiliary connections
+    pub fn auxiliary_count(&self) -> usize {
+        self.auxiliaries.lock().unwrap().len()
+    }
+
+    /// version negotiated on the main connection
+    pub fn version(&self) -> ds::Version {
+        self.version.get()
+    }
+
+    /// returns a xid that is not used by any other message sent to this switch
+    pub fn next_xid(&self) -> u32 {
+        self.xids.next()
+    }
+
+    /// sends the payload with a new xid over the main connection
+    /// fails if the payload is not available in the negotiated version
+    /// returns the xid to match replies
+    pub fn send_payload(&self, payload: ds::OfPayload) -> Result<u32> {
+        let xid = self.next_xid();
+        self.send(ds::OfMsg::generate_for(self.version(), xid, payload)?)?;
+        Ok(xid)
+    }
+
+    /// traffic counters of the main connection
+    pub fn stats(&self) -> ConnectionStats {
+        self.metrics.stats()
+    }
+
+    /// buffers to reply to packet ins of this switch
+    pub fn buffers(&self) -> &PacketBuffers {
+        &self.buffers
+    }
+
+    /// vendor, hardware and software of the switch
+    /// None until the switch answered the description request sent after the handshake
+    pub fn description(&self) -> Option<RepDesc> {
+        self.description.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_description(&self, description: RepDesc) {
+        *self.description.lock().unwrap() = Some(description);
+    }
+
+    /// group types, capabilities and limits of the switch
+    /// None until the switch answered the group features request sent after the handshake
+    pub fn group_features(&self) -> Option<GroupFeatures> {
+        self.group_features.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_group_features(&self, features: GroupFeatures) {
+        *self.group_features.lock().unwrap() = Some(features);
+    }
+
+    /// band types, capabilities and limits of the meters of the switch
+    /// None until the switch answered the meter features request sent after the handshake
+    pub fn meter_features(&self) -> Option<MeterFeatures> {
+        self.meter_features.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_meter_features(&self, features: MeterFeatures) {
+        *self.meter_features.lock().unwrap() = Some(features);
+    }
+
+    /// ports of the switch ordered by number
+    /// empty until the switch answered the port description request sent after the handshake
+    pub fn ports(&self) -> Vec<Port> {
+        let mut ports: Vec<Port> = self.ports.lock().unwrap().values().cloned().collect();
+        ports.sort_by_key(|port| Into::<u32>::into(port.port_no().clone()));
+        ports
+    }
+
+    /// the port with the number, None if the switch did not report it
+    pub fn port(&self, port_no: &PortNumber) -> Option<Port> {
+        let port_no: u32 = port_no.clone().into();
+        self.ports.lock().unwrap().get(&port_no).cloned()
+    }
+
+    /// role of the controller on the switch and its generation id
+    /// None until the switch answered a role request
+    pub fn role(&self) -> Option<Role> {
+        self.role.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_role(&self, role: Role) {
+        *self.role.lock().unwrap() = Some(role);
+    }
+
+    /// sets the flags of mask to their value in config
+    /// the hardware address is taken from the port table, the table itself is
+    /// updated once the switch reports the change with a port status
+    /// only available up to OpenFlow 1.3
+    /// fails if the port is not in the port table
+    pub fn set_port_config(
+        &self,
+        port_no: PortNumber,
+        config: PortConfig,
+        mask: PortConfig,
+    ) -> Result<u32> {
+        self.send_port_mod(port_no, |port| PortMod::for_port(port, config, mask))
+    }
+
+    /// brings the port administratively up or down
+    pub fn set_port_state(&self, port_no: PortNumber, state: PortAdminState) -> Result<u32> {
+        self.send_port_mod(port_no, |port| PortMod::admin_state(port, state))
+    }
+
+    /// enables or disables packet ins for packets received on the port
+    pub fn set_port_packet_in(&self, port_no: PortNumber, enabled: bool) -> Result<u32> {
+        self.send_port_mod(port_no, |port| {
+            PortMod::enable(port, PortConfig::NO_PACKET_IN, enabled)
+        })
+    }
+
+    /// enables or disables receiving packets on the port
+    pub fn set_port_receive(&self, port_no: PortNumber, enabled: bool) -> Result<u32> {
+        self.send_port_mod(port_no, |port| PortMod::enable(port, PortConfig::NO_RECV, enabled))
+    }
+
+    /// enables or disables forwarding packets to the port
+    /// there is no separate flood flag since OpenFlow 1.2, this also
+    /// excludes the port from floods
+    pub fn set_port_forward(&self, port_no: PortNumber, enabled: bool) -> Result<u32> {
+        self.send_port_mod(port_no, |port| PortMod::enable(port, PortConfig::NO_FWD, enabled))
+    }
+
+    /// builds the port mod from the cached port and sends it
+    fn send_port_mod<F>(&self, port_no: PortNumber, port_mod: F) -> Result<u32>
+    where
+        F: FnOnce(&Port) -> PortMod,
+    {
+        let port = match self.port(&port_no) {
+            Some(port) => port,
+            None => bail!(ErrorKind::UnknownPort(port_no.into())),
+        };
+        self.send_payload(ds::OfPayload::PortMod(port_mod(&port)))
+    }
+
+    /// adds ports of a port description reply, replaces known ports with the same number
+    pub(crate) fn insert_ports(&self, ports: Vec<Port>) {
+        let mut table = self.ports.lock().unwrap();
+        for port in ports {
+            table.insert(port.port_no().clone().into(), port);
+        }
+    }
+
+    /// applies a port status message to the port table
+    pub(crate) fn update_port(&self, reason: &PortReason, port: &Port) {
+        let port_no: u32 = port.port_no().clone().into();
+        let mut table = self.ports.lock().unwrap();
+        match *reason {
+            PortReason::Delete => table.remove(&port_no),
+            PortReason::Add | PortReason::Modifiy => table.insert(port_no, port.clone()),
+        };
+    }
+
+    /// sets the MTU of the port, the longest packet it sends after the ethernet header
+    /// OpenFlow does not report MTUs, send_frame only checks ports with an MTU set
+    pub fn set_port_mtu(&self, port_no: PortNumber, mtu: u16) {
+        self.mtus.lock().unwrap().insert(port_no.into(), mtu);
+    }
+
+    /// the MTU set with set_port_mtu, None if it is not known
+    pub fn port_mtu(&self, port_no: &PortNumber) -> Option<u16> {
+        let port_no: u32 = port_no.clone().into();
+        self.mtus.lock().unwrap().get(&port_no).cloned()
+    }
+
+    /// sends a frame crafted by the controller (eg. an arp reply or a probe) out of the port
+    /// frames longer than the MTU of the port (see `packet::payload_len`) are
+    /// refused or fragmented as the policy says, frames to ports without an
+    /// MTU are sent as they are
+    /// the packet outs are sent balanced over the connections of the switch
+    /// returns the xids of the packet outs, one per fragment
+    pub fn send_frame(
+        &self,
+        port_no: PortNumber,
+        frame: Vec<u8>,
+        policy: MtuPolicy,
+    ) -> Result<Vec<u32>> {
+        let frames = match self.port_mtu(&port_no) {
+            Some(mtu) if packet::payload_len(&frame) > mtu as usize => {
+                let fragments = match policy {
+                    MtuPolicy::Refuse => None,
+                    MtuPolicy::Fragment => packet::fragment_ipv4(&frame, mtu as usize),
+                };
+                match fragments {
+                    Some(fragments) => fragments,
+                    None => bail!(ErrorKind::ExceedsMtu(
+                        packet::payload_len(&frame),
+                        port_no.into(),
+                        mtu
+                    )),
+                }
+            }
+            _ => vec![frame],
+        };
+        frames
+            .into_iter()
+            .map(|frame| {
+                let xid = self.next_xid();
+                let packet_out = PacketOut::output(port_no.clone(), frame);
+                self.send_balanced(ds::OfMsg::generate_for(
+                    self.version(),
+                    xid,
+                    ds::OfPayload::PacketOut(packet_out),
+                )?)?;
+                Ok(xid)
+            })
+            .collect()
+    }
+
+    /// applies the actions to the packet of the packet in
+    /// refers to the buffer of the switch or sends the frame back if the switch has no buffers
+    /// the packet out is sent balanced over the connections of the switch
+    pub fn reply_packet_out(
+        &self,
+        packet_in: &PacketIn,
+        actions: Vec<ActionHeader>,
+    ) -> Result<u32> {
+        let xid = self.next_xid();
+        let packet_out = self.buffers.packet_out(packet_in, actions);
+        self.send_balanced(ds::OfMsg::generate_for(
+            self.version(),
+            xid,
+            ds::OfPayload::PacketOut(packet_out),
+        )?)?;
+        Ok(xid)
+    }
+
+    /// installs the flow and applies it to the packet of the packet in
+    /// for packets the switch did not buffer the frame is sent to the flow tables
+    /// after the flow mod, both are sent over the main connection
+    /// returns the xid of the flow mod
+    pub fn reply_flow_mod(&self, packet_in: &PacketIn, flow_mod: FlowMod) -> Result<u32> {
+        let (flow_mod, packet_out) = self.buffers.flow_mod(packet_in, flow_mod);
+        let xid = self.send_payload(ds::OfPayload::FlowMod(flow_mod))?;
+        if let Some(packet_out) = packet_out {
+            self.send_payload(ds::OfPayload::PacketOut(packet_out))?;
+        }
+        Ok(xid)
+    }
+
+    /// configures which asynchronous messages the switch sends to this controller
+    /// returns the xid of the set async message
+    pub fn set_async_config(&self, config: Async) -> Result<u32> {
+        self.send_payload(ds::OfPayload::SetAsync(config))
+    }
+
+    /// asks the switch for its fragment handling and miss_send_len
+    /// fails if the switch replies with an error or not within REPLY_TIMEOUT
+    pub fn get_config(&self) -> Result<SwitchConfig> {
+        let reply = self.request(ds::OfPayload::GetConfigRequest)?;
+        match *reply.payload() {
+            ds::OfPayload::GetConfigReply(ref config) => Ok(config.clone()),
+            _ => bail!(unexpected_reply(&reply)),
+        }
+    }
+
+    /// sets the fragment handling and the number of bytes of packets sent to
+    /// the controller on a table miss
+    /// returns the xid of the set config message
+    pub fn set_config(&self, flags: ConfigFlags, miss_send_len: u16) -> Result<u32> {
+        let config = SwitchConfig {
+            flags: flags,
+            miss_send_len: miss_send_len,
+        };
+        self.send_payload(ds::OfPayload::SetConfig(config))
+    }
+
+    /// configures the table, or every table with TABLE_ALL
+    /// eviction and vacancy events need OpenFlow 1.4
+    /// returns the xid of the table mod
+    pub fn set_table_config(&self, table_id: u8, config: TableConfig) -> Result<u32> {
+        if table_id != TABLE_ALL {
+            check_table(table_id, self.features())?;
+        }
+        let since_1_4 = TableConfig::EVICTION | TableConfig::VACANCY_EVENTS;
+        if config.intersects(since_1_4) && self.version() < ds::Version::V1_4 {
+            bail!(ErrorKind::UnsupportedValue(
+                config.bits() as u64,
+                stringify!(TableConfig)
+            ));
+        }
+        self.send_payload(ds::OfPayload::TableMod(TableMod::new(table_id, config)))
+    }
+
+    /// deletes the flows of the table (or every table with TABLE_ALL) whose cookie
+    /// matches under cookie_mask and that output to out_port (PortNo::Any for all)
+    /// eg. `delete_flows(namespace.prefix(), namespace.mask(), TABLE_ALL, PortNo::Any.into())`
+    /// returns the xid of the flow mod, call barrier to wait until the flows are gone
+    pub fn delete_flows(
+        &self,
+        cookie: u64,
+        cookie_mask: u64,
+        table_id: u8,
+        out_port: PortNumber,
+    ) -> Result<u32> {
+        if table_id != TABLE_ALL {
+            check_table(table_id, self.features())?;
+        }
+        let flow_mod = FlowMod::delete(cookie, cookie_mask, table_id, out_port);
+        self.send_payload(ds::OfPayload::FlowMod(flow_mod))
+    }
+
+    /// deletes every flow of every table
+    /// returns the xid of the flow mod, call barrier to wait until the flows are gone
+    pub fn flush_all_flows(&self) -> Result<u32> {
+        self.delete_flows(0, 0, TABLE_ALL, PortNo::Any.into())
+    }
+
+    /// the flows matching the request as adds (see `FlowStats::flow_mod`) from all
+    /// parts of the reply, eg. `flows(FlowStatsRequest::all())` for every flow
+    /// fails if the switch replies with an error or a part takes longer than REPLY_TIMEOUT
+    pub fn flows(&self, request: FlowStatsRequest) -> Result<Vec<FlowMod>> {
+        let mut flows = Vec::new();
+        self.for_each_flow(request, FlowFilter::all(), |flow| flows.push(flow))?;
+        Ok(flows)
+    }
+
+    /// calls f with every flow of the reply to the request that passes the filter
+    /// as an add, the parts of the reply are decoded one entry at a time (see
+    /// `ds::stream`), so only the flows f keeps stay in memory
+    /// fails if the switch replies with an error or a part takes longer than REPLY_TIMEOUT
+    pub fn for_each_flow<F>(
+        &self,
+        request: FlowStatsRequest,
+        filter: FlowFilter,
+        mut f: F,
+    ) -> Result<()>
+    where
+        F: FnMut(FlowMod),
+    {
+        let payload =
+            ds::OfPayload::MultipartRequest(MultipartRequest::new(ReqPayload::Flow(request)));
+        let (frame_s, frame_r) = channel();
+        let xid = self.next_xid();
+        let msg = ds::OfMsg::generate_for(self.version(), xid, payload)?;
+        self.replies.subscribe_frames(xid, frame_s);
+        if let Err(err) = self.send(msg) {
+            self.replies.unsubscribe(xid);
+            return Err(err);
+        }
+        // a part with an invalid entry may not be the last one
+        let result = self.decode_flows(xid, &frame_r, &filter, &mut f);
+        self.replies.unsubscribe(xid);
+        result
+    }
+
+    /// decodes the parts of the reply to the flow stats request with the xid
+    fn decode_flows<F>(
+        &self,
+        xid: u32,
+        frame_r: &Receiver<Vec<u8>>,
+        filter: &FlowFilter,
+        f: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(FlowMod),
+    {
+        loop {
+            let frame = match frame_r.recv_timeout(REPLY_TIMEOUT) {
+                Ok(frame) => frame,
+                Err(_) => bail!(ErrorKind::ReplyTimeout(xid)),
+            };
+            let more = match stream::peek_multipart_reply(&frame) {
+                Ok((ref header, _, flags)) if *header.version() >= ds::Version::V1_5 => {
+                    for desc in FlowDescIter::new(&frame)?.with_filter(filter.clone()) {
+                        f(desc?.flow_mod());
+                    }
+                    flags.contains(MultipartFlags::MORE)
+                }
+                Ok((_, _, flags)) => {
+                    for stats in FlowStatsIter::new(&frame)?.with_filter(filter.clone()) {
+                        f(stats?.flow_mod());
+                    }
+                    flags.contains(MultipartFlags::MORE)
+                }
+                Err(_) => bail!(unexpected_reply(&ds::OfMsg::try_from(&frame[..])?)),
+            };
+            if !more {
+                return Ok(());
+            }
+        }
+    }
+
+    /// the counters of the requested ports from all parts of the reply,
+    /// eg. `port_stats(PortStatsRequest::all())` for every port
+    /// only available up to OpenFlow 1.3
+    /// fails if the switch replies with an error or a part takes longer than REPLY_TIMEOUT
+    pub fn port_stats(&self, request: PortStatsRequest) -> Result<Vec<PortStats>> {
+        let mut stats = Vec::new();
+        for reply in self.request_multipart(ReqPayload::PortStats(request))? {
+            match *reply.payload() {
+                ds::OfPayload::MultipartReply(MultipartReply {
+                    payload: RepPayload::PortStats(ref ports),
+                    ..
+                }) => stats.extend(ports.iter().cloned()),
+                _ => bail!(unexpected_reply(&reply)),
+            }
+        }
+        Ok(stats)
+    }
+
+    /// blocks until the switch processed all messages sent before over the main connection
+    /// fails if the barrier reply takes longer than REPLY_TIMEOUT
+    pub fn barrier(&self) -> Result<()> {
+        let reply = self.request(ds::OfPayload::BarrierRequest)?;
+        match *reply.payload() {
+            ds::OfPayload::BarrierReply => Ok(()),
+            _ => bail!(unexpected_reply(&reply)),
+        }
+    }
+
+    /// asks the switch for the queues of the port and their rates
+    /// the reply is cached for enqueue
+    /// only available up to OpenFlow 1.3
+    /// fails if the switch replies with an error or not within REPLY_TIMEOUT
+    pub fn get_queues(&self, port: PortNumber) -> Result<Vec<QueueConfig>> {
+        let port_no: u32 = port.clone().into();
+        let request = QueueGetConfigRequest { port: port };
+        let reply = self.request(ds::OfPayload::QueueGetConfigRequest(request))?;
+        let queues = match *reply.payload() {
+            ds::OfPayload::QueueGetConfigReply(ref reply) => reply.queue_configs(),
+            _ => bail!(unexpected_reply(&reply)),
+        };
+        self.queues.lock().unwrap().insert(port_no, queues.clone());
+        Ok(queues)
+    }
+
+    /// queues of the port from the last get_queues, None if never requested
+    pub fn cached_queues(&self, port: &PortNumber) -> Option<Vec<QueueConfig>> {
+        let port_no: u32 = port.clone().into();
+        self.queues.lock().unwrap().get(&port_no).cloned()
+    }
+
+    /// actions that send packets out of the port through the queue
+    /// the queues of the port are requested if they are not cached yet
+    /// fails if the port has no such queue
+    pub fn enqueue(&self, port: PortNumber, queue_id: u32) -> Result<Vec<ActionHeader>> {
+        let queues = match self.cached_queues(&port) {
+            Some(queues) => queues,
+            None => self.get_queues(port.clone())?,
+        };
+        if !queues.iter().any(|queue| queue.queue_id == queue_id) {
+            bail!(ErrorKind::UnknownQueue(port.into(), queue_id));
+        }
+        Ok(actions::enqueue(port, queue_id))
+    }
+
+    /// stages the flow and group mods added by build in a bundle and commits it,
+    /// the switch applies all of them in order or none
+    /// needs bundle support (see supports_bundles), nothing is sent if build stages nothing
+    /// errors of single messages reach the handler, the commit fails with them
+    /// fails if the switch rejects the bundle or does not reply within REPLY_TIMEOUT,
+    /// a bundle that fails after it was opened is discarded
+    pub fn atomic<F>(&self, build: F) -> Result<()>
+    where
+        F: FnOnce(&mut BundleTxn),
+    {
+        let version = self.version();
+        if !self.supports_bundles() {
+            bail!(ErrorKind::UnsupportedBySwitch(
+                self.datapath_id(),
+                format!("bundles with version '{:?}'", version)
+            ));
+        }
+        let mut txn = BundleTxn::default();
+        build(&mut txn);
+        if txn.is_empty() {
+            return Ok(());
+        }
+        // checked before the bundle is opened, the embedded message uses the
+        // xid of its bundle add
+        let messages = txn.messages
+            .into_iter()
+            .map(|payload| ds::OfMsg::generate_for(version, self.next_xid(), payload))
+            .collect::<Result<Vec<_>>>()?;
+
+        let bundle_id = self.next_bundle_id.fetch_add(1, Ordering::SeqCst) as u32;
+        let flags = BundleFlags::ATOMIC | BundleFlags::ORDERED;
+        self.bundle_control(bundle_id, BundleCtrlType::OpenRequest, flags)?;
+        let committed = self.commit_bundle(version, bundle_id, flags, messages);
+        if committed.is_err() {
+            // the switch keeps the messages added so far until the bundle is discarded
+            let discarded = self.bundle_control(bundle_id, BundleCtrlType::DiscardRequest, flags);
+            if let Err(err) = discarded {
+                warn!(
+                    "Could not discard bundle {} of {}: {}",
+                    bundle_id,
+                    self.datapath_id(),
+                    err
+                );
+            }
+        }
+        committed
+    }
+
+    /// adds the messages to the open bundle and commits it
+    fn commit_bundle(
+        &self,
+        version: ds::Version,
+        bundle_id: u32,
+        flags: BundleFlags,
+        messages: Vec<ds::OfMsg>,
+    ) -> Result<()> {
+        for message in messages {
+            let xid = *message.header().xid();
+            let add = BundleAdd::new(bundle_id, flags, message);
+            self.send(ds::OfMsg::generate_for(
+                version,
+                xid,
+                ds::OfPayload::BundleAddMessage(add),
+            )?)?;
+        }
+        self.bundle_control(bundle_id, BundleCtrlType::CommitRequest, flags)
+    }
+
+    /// true if the negotiated version has bundles (1.4 or newer) and the switch
+    /// has not rejected opening one yet
+    pub fn supports_bundles(&self) -> bool {
+        self.version() >= ds::Version::V1_4 && self.bundles.load(Ordering::SeqCst)
+    }
+
+    /// sends the bundle control request and waits for its reply
+    /// a switch that does not know bundles or atomic ordered bundles rejects the open request
+    fn bundle_control(
+        &self,
+        bundle_id: u32,
+        ctrl_type: BundleCtrlType,
+        flags: BundleFlags,
+    ) -> Result<()> {
+        let expected = ctrl_type.reply();
+        let request = BundleCtrl::new(bundle_id, ctrl_type, flags);
+        let reply = self.request(ds::OfPayload::BundleControl(request))?;
+        match *reply.payload() {
+            ds::OfPayload::BundleControl(ref ctrl)
+                if ctrl.bundle_id == bundle_id && Some(ctrl.ctrl_type) == expected =>
+            {
+                Ok(())
+            }
+            ds::OfPayload::Error(ref error)
+                if ctrl_type == BundleCtrlType::OpenRequest && rejects_bundles(error) =>
+            {
+                self.bundles.store(false, Ordering::SeqCst);
+                bail!(ErrorKind::UnsupportedBySwitch(
+                    self.datapath_id(),
+                    "bundles".to_string()
+                ))
+            }
+            _ => bail!(unexpected_reply(&reply)),
+        }
+    }
+
+    /// sends the request over the main connection and waits for its reply
+    fn request(&self, payload: ds::OfPayload) -> Result<ds::OfMsg> {
+        let (xid, reply_r) = self.send_request(payload)?;
+        self.next_reply(xid, &reply_r)
+    }
+
+    /// sends the multipart request over the main connection and waits for all
+    /// parts of its reply, fails if a reply is not a multipart reply
+    fn request_multipart(&self, request: ReqPayload) -> Result<Vec<ds::OfMsg>> {
+        let payload = ds::OfPayload::MultipartRequest(MultipartRequest::new(request));
+        let (xid, reply_r) = self.send_request(payload)?;
+        let mut parts = Vec::new();
+        loop {
+            let reply = self.next_reply(xid, &reply_r)?;
+            let more = match *reply.payload() {
+                ds::OfPayload::MultipartReply(ref part) => {
+                    part.flags.contains(MultipartFlags::MORE)
+                }
+                _ => bail!(unexpected_reply(&reply)),
+            };
+            parts.push(reply);
+            if !more {
+                return Ok(parts);
+            }
+        }
+    }
+
+    /// sends the request with a new xid, its replies are sent to the receiver
+    fn send_request(&self, payload: ds::OfPayload) -> Result<(u32, Receiver<ds::OfMsg>)> {
+        let (reply_s, reply_r) = channel();
+        let xid = self.next_xid();
+        let msg = ds::OfMsg::generate_for(self.version(), xid, payload)?;
+        self.replies.subscribe(xid, reply_s);
+        if let Err(err) = self.send(msg) {
+            self.replies.unsubscribe(xid);
+            return Err(err);
+        }
+        Ok((xid, reply_r))
+    }
+
+    /// waits for the next reply to the request with the xid
+    fn next_reply(&self, xid: u32, reply_r: &Receiver<ds::OfMsg>) -> Result<ds::OfMsg> {
+        match reply_r.recv_timeout(REPLY_TIMEOUT) {
+            Ok(reply) => Ok(reply),
+            Err(_) => {
+                self.replies.unsubscribe(xid);
+                bail!(ErrorKind::ReplyTimeout(xid))
+            }
+        }
+    }
+
+    /// sends the flow mods over the main connection with a barrier every
+    /// DEFAULT_BARRIER_INTERVAL flow mods, see install_flows_batched
+    pub fn install_flows(&self, flows: Vec<FlowMod>) -> Result<FlowInstallReport> {
+        self.install_flows_batched(flows, DEFAULT_BARRIER_INTERVAL)
+    }
+
+    /// sends the flow mods over the main connection with a barrier request after
+    /// every barrier_interval flow mods and blocks until the switch processed all of them
+    /// errors the switch replies with are reported with the index of their flow mod
+    /// fails if the connection is closed or a barrier reply takes longer than BARRIER_TIMEOUT
+    pub fn install_flows_batched(
+        &self,
+        flows: Vec<FlowMod>,
+        barrier_interval: usize,
+    ) -> Result<FlowInstallReport> {
+        let barrier_interval = barrier_interval.max(1);
+        let (reply_s, reply_r) = channel();
+        let mut batch = FlowBatch::new(&self.replies);
+        let mut in_flight = VecDeque::new();
+        let mut report = FlowInstallReport::default();
+        let version = self.version();
+        let total = flows.len();
+
+        for (index, flow) in flows.into_iter().enumerate() {
+            let xid = self.next_xid();
+            batch.add(xid, index, reply_s.clone());
+            self.send(ds::OfMsg::generate_for(
+                version,
+                xid,
+                ds::OfPayload::FlowMod(flow),
+            )?)?;
+            report.sent += 1;
+
+            if report.sent % barrier_interval == 0 || report.sent == total {
+                let barrier_xid = self.next_xid();
+                batch.add_barrier(barrier_xid, reply_s.clone());
+                self.send(ds::OfMsg::generate_for(
+                    version,
+                    barrier_xid,
+                    ds::OfPayload::BarrierRequest,
+                )?)?;
+                in_flight.push_back((barrier_xid, batch));
+                batch = FlowBatch::new(&self.replies);
+
+                if in_flight.len() >= MAX_BATCHES_IN_FLIGHT {
+                    let (barrier_xid, done) = in_flight.pop_front().unwrap();
+                    self.await_barrier(barrier_xid, &done, &reply_r, &mut report)?;
+                }
+            }
+        }
+        while let Some((barrier_xid, done)) = in_flight.pop_front() {
+            self.await_barrier(barrier_xid, &done, &reply_r, &mut report)?;
+        }
+        Ok(report)
+    }
+
+    /// collects errors until the barrier reply arrives
+    /// replies are forwarded in the order they were received, so all errors
+    /// of flow mods sent before the barrier have been received at that point
+    fn await_barrier(
+        &self,
+        barrier_xid: u32,
+        batch: &FlowBatch,
+        replies: &Receiver<ds::OfMsg>,
+        report: &mut FlowInstallReport,
+    ) -> Result<()> {
+        loop {
+            let reply = match replies.recv_timeout(BARRIER_TIMEOUT) {
+                Ok(reply) => reply,
+                Err(_) => {
+                    self.replies.unsubscribe(barrier_xid);
+                    bail!(ErrorKind::ReplyTimeout(barrier_xid))
+                }
+            };
+            let xid = *reply.header().xid();
+            match reply.payload() {
+                ds::OfPayload::BarrierReply if xid == barrier_xid => return Ok(()),
+                ds::OfPayload::Error(error_msg) => match batch.index_of(xid) {
+                    Some(index) => report.errors.push((index, error_msg.clone())),
+                    None => warn!("Error reply for unknown flow mod xid {}.", xid),
+                },
+                _ => (),
+            }
+        }
+    }
+
+    /// sends a message over the main connection
+    /// fails if the negotiated version, the capabilities or the group and meter
+    /// features of the switch do not support the message or if the data of a
+    /// packet out does not fit the message (see `PacketOut::check_len`),
+    /// nothing is sent then
+    pub fn send(&self, msg: ds::OfMsg) -> Result<()> {
+        self.check_supported(&msg)?;
+        self.main
+            .send(msg)
+            .map_err(|_| ErrorKind::ConnectionClosed(self.datapath_id()).into())
+    }
+
+    /// sends a message over the main connection or one of the auxiliary connections
+    /// connections are used round robin, use this for packet outs
+    /// messages that depend on each other should use `send` instead,
+    /// the spec does not guarantee ordering between connections
+    pub fn send_balanced(&self, msg: ds::OfMsg) -> Result<()> {
+        self.check_supported(&msg)?;
+        let auxiliaries = self.auxiliaries.lock().unwrap();
+        let channel = self.next_channel.fetch_add(1, Ordering::SeqCst) % (auxiliaries.len() + 1);
+        let sender = match channel {
+            0 => &self.main,
+            n => &auxiliaries[n - 1].1,
+        };
+        sender
+            .send(msg)
+            .map_err(|_| ErrorKind::ConnectionClosed(self.datapath_id()).into())
+    }
+
+    /// checks the payload against the negotiated version and the advertised capabilities
+    fn check_supported(&self, msg: &ds::OfMsg) -> Result<()> {
+        let (ttype, payload) = (msg.header().ttype(), msg.payload());
+        if let ds::OfPayload::PacketOut(ref packet_out) = *payload {
+            packet_out.check_len()?;
+        }
+        let version = self.version();
+        if !payload.available_in(version) {
+            bail!(ErrorKind::UnsupportedBySwitch(
+                self.datapath_id(),
+                format!("'{:?}' messages with version '{:?}'", ttype, version)
+            ));
+        }
+        let missing = payload.required_capabilities() - self.features.capabilities;
+        if !missing.is_empty() {
+            bail!(ErrorKind::UnsupportedBySwitch(
+                self.datapath_id(),
+                format!("'{:?}' without capabilities '{:?}'", ttype, missing)
+            ));
+        }
+        let unsupported = match *payload {
+            ds::OfPayload::GroupMod(ref group_mod) => self
+                .group_features()
+                .and_then(|features| features.unsupported(group_mod)),
+            ds::OfPayload::MeterMod(ref meter_mod) => self
+                .meter_features()
+                .and_then(|features| features.unsupported(meter_mod)),
+            _ => None,
+        };
+        if let Some(what) = unsupported {
+            bail!(ErrorKind::UnsupportedBySwitch(self.datapath_id(), what));
+        }
+        if self.validate.load(Ordering::SeqCst) {
+            let violations = msg.validate();
+            if !violations.is_empty() {
+                bail!(ErrorKind::InvalidMessage(violations));
+            }
+        }
+        Ok(())
+    }
+
+    /// if enabled, messages that break rules of the specification (see
+    /// `ds::validate`) fail to send instead of being rejected by the switch
+    /// set from ControllerConfig::validate_messages when the switch connects
+    pub fn set_validation(&self, enabled: bool) {
+        self.validate.store(enabled, Ordering::SeqCst);
+    }
+
+    /// replies to requests sent over the main connection
+    #[cfg(test)]
+    pub(crate) fn replies(&self) -> &Arc<ReplyRouter> {
+        &self.replies
+    }
+
+    pub(crate) fn add_auxiliary(&self, auxiliary_id: u8, sender: Sender<ds::OfMsg>) {
+        let mut auxiliaries = self.auxiliaries.lock().unwrap();
+        // a reconnecting auxiliary replaces its old connection
+        auxiliaries.retain(|&(id, _)| id != auxiliary_id);
+        auxiliaries.push((auxiliary_id, sender));
+    }
+
+    pub(crate) fn remove_auxiliary(&self, auxiliary_id: u8) {
+        self.auxiliaries
+            .lock()
+            .unwrap()
+            .retain(|&(id, _)| id != auxiliary_id);
+    }
+}
+
+/// true if the error rejects bundle control messages or the flags atomic bundles use
+fn rejects_bundles(error: &ErrorMsg) -> bool {
+    match error.error_type() {
+        Some(ErrorType::BadRequest) => error.code == BadRequestCode::BadType as u16,
+        Some(ErrorType::BundleFailed) => error.code == BundleFailedCode::BadFlags as u16,
+        _ => false,
+    }
+}
+
+/// error for a reply of the wrong type, error replies are logged
+fn unexpected_reply(reply: &ds::OfMsg) -> ErrorKind {
+    if let ds::OfPayload::Error(ref error_msg) = *reply.payload() {
+        warn!("Request {} failed: {:?}", reply.header().xid(), error_msg);
+    }
+    ErrorKind::UnexpectedReply(*reply.header().xid(), reply.header().ttype().clone())
+}
+
+/// Flow mods sent between two barriers by their xid.
+/// Stops waiting for their errors when dropped.
+struct FlowBatch<'a> {
+    replies: &'a ReplyRouter,
+    indices: HashMap<u32, usize>,
+    /// xid of the barrier request that ends the batch
+    barrier: Option<u32>,
+}
+
+impl<'a> FlowBatch<'a> {
+    fn new(replies: &'a ReplyRouter) -> Self {
+        FlowBatch {
+            replies: replies,
+            indices: HashMap::new(),
+            barrier: None,
+        }
+    }
+
+    fn add(&mut self, xid: u32, index: usize, sender: Sender<ds::OfMsg>) {
+        self.replies.subscribe(xid, sender);
+        self.indices.insert(xid, index);
+    }
+
+    fn add_barrier(&mut self, xid: u32, sender: Sender<ds::OfMsg>) {
+        self.replies.subscribe(xid, sender);
+        self.barrier = Some(xid);
+    }
+
+    fn index_of(&self, xid: u32) -> Option<usize> {
+        self.indices.get(&xid).cloned()
+    }
+}
+
+impl<'a> Drop for FlowBatch<'a> {
+    fn drop(&mut self) {
+        // also if installing the flows failed before the barrier reply arrived
+        for xid in self.indices.keys().chain(self.barrier.iter()) {
+            self.replies.unsubscribe(*xid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ds::factory;
+    use super::super::super::ds::features::Capabilities;
+    use super::super::super::ds::flow_parser::parse_flow_mod;
+    use super::super::super::ds::flow_stats::FlowStats;
+    use super::super::super::ds::packet_queue::PacketQueue;
+    use super::super::super::ds::queue_config::QueueGetConfigReply;
+    use super::super::metrics::Metrics;
+    use super::*;
+    use std::thread;
+
+    fn handle() -> (Arc<SwitchHandle>, ::std::sync::mpsc::Receiver<ds::OfMsg>) {
+        handle_with(Capabilities::empty())
+    }
+
+    fn handle_with(
+        capabilities: Capabilities,
+    ) -> (Arc<SwitchHandle>, ::std::sync::mpsc::Receiver<ds::OfMsg>) {
+        let (send, recv) = channel();
+        let mut features = factory::features_reply(1);
+        features.capabilities = capabilities;
+        let handle = SwitchHandle::new(
+            features,
+            send,
+            Arc::new(XidAllocator::new()),
+            Arc::new(NegotiatedVersion::new()),
+            Arc::new(ReplyRouter::new()),
+            Metrics::connection(&Arc::new(Metrics::new())),
+        );
+        (Arc::new(handle), recv)
+    }
+
+    #[test]
+    fn install_flows_reports_errors_by_index() {
+        let (handle, recv) = handle();
+        let replies = handle.replies().clone();
+        // fake switch rejecting every third flow mod
+        let switch = thread::spawn(move || {
+            let (mut flows, mut barriers) = (0, 0);
+            for msg in recv.iter() {
+                let xid = *msg.header().xid();
+                let reply = match msg.payload() {
+                    ds::OfPayload::FlowMod(_) => {
+                        flows += 1;
+                        if flows % 3 != 0 {
+                            continue;
+                        }
+                        ds::OfPayload::Error(ErrorMsg {
+                            ttype: 5,
+                            code: 0,
+                            data: Vec::new(),
+                        })
+                    }
+                    ds::OfPayload::BarrierRequest => {
+                        barriers += 1;
+                        ds::OfPayload::BarrierReply
+                    }
+                    _ => continue,
+                };
+                assert!(replies.route(ds::OfMsg::generate(xid, reply)).is_none());
+            }
+            barriers
+        });
+
+        let flows = (0..10)
+            .map(|_| parse_flow_mod("actions=drop").unwrap())
+            .collect();
+        let report = handle.install_flows_batched(flows, 4).unwrap();
+        assert_eq!(report.sent, 10);
+        let indices: Vec<usize> = report.errors.iter().map(|&(index, _)| index).collect();
+        assert_eq!(indices, vec![2, 5, 8]);
+        assert_eq!(handle.replies().pending(), 0);
+
+        drop(handle);
+        assert_eq!(switch.join().unwrap(), 3);
+    }
+
+    #[test]
+    fn install_flows_unsubscribes_when_a_send_fails() {
+        let (handle, _recv) = handle();
+        handle.set_validation(true);
+        let mut flows: Vec<FlowMod> = (0..5)
+            .map(|_| parse_flow_mod("priority=10,actions=drop").unwrap())
+            .collect();
+        // non-strict deletes ignore the priority
+        flows[3].command = ds::flow_mod::FlowModCommand::Delete;
+        match *handle.install_flows_batched(flows, 2).unwrap_err().kind() {
+            ErrorKind::InvalidMessage(_) => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+        // the barrier of the first batch was sent before the failing flow mod
+        assert_eq!(handle.replies().pending(), 0);
+    }
+
+    #[test]
+    fn set_table_config_checks_table_and_version() {
+        let (handle, recv) = handle();
+        handle.version.negotiate(ds::Version::V1_3);
+        assert!(handle.set_table_config(1, TableConfig::empty()).is_err());
+        assert!(handle.set_table_config(0, TableConfig::EVICTION).is_err());
+        let xid = handle.set_table_config(TABLE_ALL, TableConfig::empty()).unwrap();
+        let msg = recv.try_recv().unwrap();
+        assert_eq!(*msg.header().xid(), xid);
+        match *msg.payload() {
+            ds::OfPayload::TableMod(ref table_mod) => assert_eq!(*table_mod.table_id(), TABLE_ALL),
+            ref payload => panic!("unexpected payload {:?}", payload),
+        }
+        assert!(recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn send_frame_refuses_or_fragments_above_the_mtu() {
+        let (handle, recv) = handle();
+        handle.version.negotiate(ds::Version::V1_3);
+        // udp packet of 1500 bytes
+        let mut frame = vec![0xff; 12];
+        frame.extend_from_slice(&[0x08, 0x00, 0x45, 0, 0x05, 0xdc, 0, 1, 0, 0, 64, 17, 0, 0]);
+        frame.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        frame.resize(14 + 1500, 0);
+        let port = PortNumber::NormalPort(2);
+
+        // the MTU is not known
+        let xids = handle
+            .send_frame(port.clone(), frame.clone(), MtuPolicy::Refuse)
+            .unwrap();
+        assert_eq!(xids.len(), 1);
+        assert!(recv.try_recv().is_ok());
+
+        handle.set_port_mtu(port.clone(), 1000);
+        assert_eq!(handle.port_mtu(&port), Some(1000));
+        match *handle
+            .send_frame(port.clone(), frame.clone(), MtuPolicy::Refuse)
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::ExceedsMtu(1500, 2, 1000) => (),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        assert!(recv.try_recv().is_err());
+
+        let xids = handle
+            .send_frame(port.clone(), frame.clone(), MtuPolicy::Fragment)
+            .unwrap();
+        assert_eq!(xids.len(), 2);
+        for xid in xids {
+            let msg = recv.try_recv().unwrap();
+            assert_eq!(*msg.header().xid(), xid);
+            match *msg.payload() {
+                ds::OfPayload::PacketOut(ref packet_out) => {
+                    assert!(packet::payload_len(&packet_out.data) <= 1000)
+                }
+                ref payload => panic!("unexpected payload {:?}", payload),
+            }
+        }
+
+        // packet outs longer than the message length are not sent
+        let packet_out = PacketOut::flood(vec![0; ds::MAX_MESSAGE_LENGTH]);
+        match *handle
+            .send_payload(ds::OfPayload::PacketOut(packet_out))
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::PacketOutTooLong(..) => (),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        assert!(recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn send_checks_version_and_capabilities() {
+        let (handle, recv) = handle();
+        let request = MultipartRequest::new(ReqPayload::Flow(FlowStatsRequest::all()));
+        let payload = ds::OfPayload::MultipartRequest(request);
+        match *handle.send_payload(payload).unwrap_err().kind() {
+            ErrorKind::UnsupportedBySwitch(1, ref what) => assert!(what.contains("FLOW_STATS")),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+
+        handle.version.negotiate(ds::Version::V1_0);
+        let flow_mod = parse_flow_mod("in_port=1,actions=drop").unwrap();
+        let msg = ds::OfMsg::generate(1, ds::OfPayload::FlowMod(flow_mod));
+        match *handle.send_balanced(msg).unwrap_err().kind() {
+            ErrorKind::UnsupportedBySwitch(1, ref what) => assert!(what.contains("V1_0")),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        assert!(recv.try_recv().is_err());
+        handle
+            .send_payload(ds::OfPayload::EchoRequest(Vec::new()))
+            .unwrap();
+        assert!(recv.try_recv().is_ok());
+
+        handle.version.negotiate(ds::Version::V1_3);
+        handle.set_validation(true);
+        let flow_mod = parse_flow_mod("tcp_dst=80,actions=drop").unwrap();
+        match *handle.send_payload(ds::OfPayload::FlowMod(flow_mod)).unwrap_err().kind() {
+            ErrorKind::InvalidMessage(ref violations) => assert_eq!(violations.len(), 1),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        assert!(recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn send_checks_group_and_meter_features() {
+        use super::super::super::ds::group_mod::{
+            Bucket, GroupCapabilities, GroupModCommand, GroupNo, GroupNumber, GroupType,
+        };
+        use super::super::super::ds::meter_mod::{
+            MeterBandDrop, MeterBandHeader, MeterBandPayload, MeterFlags, MeterId, MeterMod,
+            MeterModCommand,
+        };
+
+        let (handle, recv) = handle();
+        handle.version.negotiate(ds::Version::V1_3);
+        let bucket = Bucket::new(
+            0,
+            PortNumber::Reserved(PortNo::Any),
+            GroupNo::Any.into(),
+            actions::enqueue(PortNumber::NormalPort(1), 1),
+        );
+        let group_mod = |command, ttype| {
+            ds::OfPayload::GroupMod(GroupMod::new(
+                command,
+                ttype,
+                GroupNumber::Group(1),
+                vec![bucket.clone()],
+            ))
+        };
+        // nothing is checked until the features are known
+        assert!(handle
+            .send_payload(group_mod(GroupModCommand::Add, GroupType::Select))
+            .is_ok());
+
+        handle.set_group_features(GroupFeatures {
+            types: 1 << 0,
+            capabilities: GroupCapabilities::empty(),
+            max_groups: [16, 0, 0, 0],
+            actions: [1 << 0, 0, 0, 0],
+        });
+        let unsupported = |payload| match *handle.send_payload(payload).unwrap_err().kind() {
+            ErrorKind::UnsupportedBySwitch(1, ref what) => what.clone(),
+            ref kind => panic!("unexpected error {:?}", kind),
+        };
+        assert!(unsupported(group_mod(GroupModCommand::Add, GroupType::Select)).contains("Select"));
+        assert!(unsupported(group_mod(GroupModCommand::Add, GroupType::All)).contains("SetQueue"));
+        assert!(handle
+            .send_payload(group_mod(GroupModCommand::Delete, GroupType::Select))
+            .is_ok());
+
+        let band = MeterBandHeader::new(1000, 0, MeterBandPayload::Drop(MeterBandDrop {}));
+        let meter_mod = |meter_id| {
+            ds::OfPayload::MeterMod(MeterMod {
+                command: MeterModCommand::Add,
+                flags: MeterFlags::KBPS | MeterFlags::STATS,
+                meter_id: MeterId::Meter(meter_id),
+                bands: vec![band.clone()],
+            })
+        };
+        handle.set_meter_features(MeterFeatures {
+            max_meter: 8,
+            band_types: 1 << 1,
+            capabilities: MeterFlags::KBPS,
+            max_bands: 1,
+            max_color: 0,
+        });
+        assert!(unsupported(meter_mod(9)).contains("maximum"));
+        assert!(unsupported(meter_mod(1)).contains("STATS"));
+        handle.set_meter_features(MeterFeatures {
+            capabilities: MeterFlags::all(),
+            ..handle.meter_features().unwrap()
+        });
+        assert!(handle.send_payload(meter_mod(1)).is_ok());
+        assert_eq!(recv.try_iter().count(), 3);
+    }
+
+    #[test]
+    fn port_helpers_use_port_table() {
+        let (handle, recv) = handle();
+        handle.version.negotiate(ds::Version::V1_3);
+        let hw_addr = ds::addr::MacAddr([2, 0, 0, 0, 0, 1]);
+        let name = ds::ports::PortName::new("eth1").unwrap();
+        let port = Port::new(PortNumber::NormalPort(1), hw_addr, name);
+        assert!(handle.set_port_state(PortNumber::NormalPort(1), PortAdminState::Down).is_err());
+        assert!(recv.try_recv().is_err());
+
+        handle.insert_ports(vec![port]);
+        handle.set_port_state(PortNumber::NormalPort(1), PortAdminState::Down).unwrap();
+        handle.set_port_packet_in(PortNumber::NormalPort(1), true).unwrap();
+        let expected = vec![
+            (PortConfig::PORT_DOWN, PortConfig::PORT_DOWN),
+            (PortConfig::empty(), PortConfig::NO_PACKET_IN),
+        ];
+        for (config, mask) in expected {
+            match *recv.try_recv().unwrap().payload() {
+                ds::OfPayload::PortMod(ref port_mod) => {
+                    assert_eq!(*port_mod.hw_addr(), hw_addr);
+                    assert_eq!(*port_mod.config(), config);
+                    assert_eq!(*port_mod.mask(), mask);
+                }
+                ref payload => panic!("unexpected payload {:?}", payload),
+            }
+        }
+    }
+
+    #[test]
+    fn flows_collects_all_parts_of_the_reply() {
+        let (handle, recv) = handle_with(Capabilities::FLOW_STATS);
+        let replies = handle.replies().clone();
+        // fake switch replying with one flow per part
+        let switch = thread::spawn(move || {
+            for msg in recv.iter() {
+                let xid = *msg.header().xid();
+                for priority in 1..4 {
+                    let flow_mod = parse_flow_mod("ip,actions=drop").unwrap();
+                    let stats = FlowStats {
+                        table_id: 0,
+                        duration_sec: 1,
+                        duration_nsec: 0,
+                        priority: priority,
+                        idle_timeout: 0,
+                        hard_timeout: 0,
+                        flags: ds::flow_mod::FlowModFlags::empty(),
+                        cookie: 0,
+                        packet_count: 0,
+                        byte_count: 0,
+                        mmatch: flow_mod.mmatch,
+                        instructions: flow_mod.instructions,
+                    };
+                    let mut reply = MultipartReply::new(RepPayload::Flow(vec![stats]));
+                    if priority < 3 {
+                        reply.flags = MultipartFlags::MORE;
+                    }
+                    let reply = ds::OfMsg::generate(xid, ds::OfPayload::MultipartReply(reply));
+                    assert!(replies.route(reply).is_none());
+                }
+            }
+        });
+
+        let flows = handle.flows(FlowStatsRequest::all()).unwrap();
+        let priorities: Vec<u16> = flows.iter().map(|flow| flow.priority).collect();
+        assert_eq!(priorities, vec![1, 2, 3]);
+        let expected = parse_flow_mod("actions=drop").unwrap();
+        assert_eq!(flows[0].instructions, expected.instructions);
+        assert_eq!(handle.replies().pending(), 0);
+        drop(handle);
+        switch.join().unwrap();
+    }
+
+    #[test]
+    fn for_each_flow_decodes_the_frames_of_the_reply() {
+        let (handle, recv) = handle_with(Capabilities::FLOW_STATS);
+        let replies = handle.replies().clone();
+        // fake switch replying with the flows of two tables, then with an error
+        let switch = thread::spawn(move || {
+            for (request, msg) in recv.iter().enumerate() {
+                let xid = *msg.header().xid();
+                let payload = if request == 0 {
+                    let flow_mod = parse_flow_mod("ip,actions=drop").unwrap();
+                    let stats = (0..4)
+                        .map(|priority| FlowStats {
+                            table_id: priority as u8 % 2,
+                            duration_sec: 1,
+                            duration_nsec: 0,
+                            priority: priority,
+                            idle_timeout: 0,
+                            hard_timeout: 0,
+                            flags: ds::flow_mod::FlowModFlags::empty(),
+                            cookie: 0,
+                            packet_count: 0,
+                            byte_count: 0,
+                            mmatch: flow_mod.mmatch.clone(),
+                            instructions: flow_mod.instructions.clone(),
+                        })
+                        .collect();
+                    ds::OfPayload::MultipartReply(MultipartReply::new(RepPayload::Flow(stats)))
+                } else {
+                    ds::OfPayload::Error(ErrorMsg {
+                        ttype: 1,
+                        code: 0,
+                        data: Vec::new(),
+                    })
+                };
+                let frame = ds::OfMsg::generate(xid, payload).encode().unwrap();
+                assert!(replies.route_frame(&frame));
+            }
+        });
+
+        let mut priorities = Vec::new();
+        let filter = FlowFilter::all().table(1);
+        handle
+            .for_each_flow(FlowStatsRequest::all(), filter, |flow| {
+                priorities.push(flow.priority)
+            })
+            .unwrap();
+        assert_eq!(priorities, vec![1, 3]);
+        assert!(handle
+            .for_each_flow(FlowStatsRequest::all(), FlowFilter::all(), |_| ())
+            .is_err());
+        assert_eq!(handle.replies().pending(), 0);
+        drop(handle);
+        switch.join().unwrap();
+    }
+
+    #[test]
+    fn flush_all_flows_and_wait() {
+        let (handle, recv) = handle();
+        let replies = handle.replies().clone();
+        // fake switch answering barriers
+        let switch = thread::spawn(move || {
+            let mut received = Vec::new();
+            for msg in recv.iter() {
+                if let ds::OfPayload::BarrierRequest = *msg.payload() {
+                    let xid = *msg.header().xid();
+                    let reply = ds::OfMsg::generate(xid, ds::OfPayload::BarrierReply);
+                    assert!(replies.route(reply).is_none());
+                }
+                received.push(msg);
+            }
+            received
+        });
+
+        handle.flush_all_flows().unwrap();
+        assert!(handle.delete_flows(0x100, 0xf00, 1, PortNo::Any.into()).is_err());
+        handle.barrier().unwrap();
+        drop(handle);
+
+        let received = switch.join().unwrap();
+        assert_eq!(received.len(), 2);
+        match *received[0].payload() {
+            ds::OfPayload::FlowMod(ref flow_mod) => {
+                assert_eq!(flow_mod.command, ds::flow_mod::FlowModCommand::Delete);
+                assert_eq!(flow_mod.table_id, TABLE_ALL);
+                assert_eq!(flow_mod.cookie_mask, 0);
+                assert!(flow_mod.mmatch.matches().is_empty());
+            }
+            ref payload => panic!("unexpected payload {:?}", payload),
+        }
+    }
+
+    #[test]
+    fn atomic_commits_a_bundle() {
+        let (handle, recv) = handle();
+        let replies = handle.replies().clone();
+        // fake switch answering bundle control requests, fails the second commit
+        // and rejects the third bundle
+        let switch = thread::spawn(move || {
+            let (mut received, mut commits, mut opens) = (Vec::new(), 0, 0);
+            for msg in recv.iter() {
+                let xid = *msg.header().xid();
+                if let ds::OfPayload::BundleControl(ref ctrl) = *msg.payload() {
+                    let mut reply = ctrl.clone();
+                    reply.ctrl_type = ctrl.ctrl_type.reply().unwrap();
+                    let reply = match ctrl.ctrl_type {
+                        BundleCtrlType::CommitRequest if commits > 0 => {
+                            ds::OfPayload::Error(ErrorMsg {
+                                ttype: 17,
+                                code: 0,
+                                data: Vec::new(),
+                            })
+                        }
+                        BundleCtrlType::OpenRequest if opens > 1 => {
+                            ds::OfPayload::Error(ErrorMsg {
+                                ttype: 17,
+                                code: BundleFailedCode::BadFlags as u16,
+                                data: Vec::new(),
+                            })
+                        }
+                        BundleCtrlType::OpenRequest => {
+                            opens += 1;
+                            ds::OfPayload::BundleControl(reply)
+                        }
+                        BundleCtrlType::CommitRequest => {
+                            commits += 1;
+                            ds::OfPayload::BundleControl(reply)
+                        }
+                        _ => ds::OfPayload::BundleControl(reply),
+                    };
+                    assert!(replies.route(ds::OfMsg::generate(xid, reply)).is_none());
+                }
+                received.push(msg);
+            }
+            received
+        });
+
+        handle
+            .atomic(|txn| {
+                txn.flow_mod(parse_flow_mod("in_port=1,actions=drop").unwrap())
+                    .flow_mod(parse_flow_mod("in_port=2,actions=drop").unwrap());
+            })
+            .unwrap();
+        // nothing staged, nothing sent
+        handle.atomic(|_| ()).unwrap();
+        let err = handle
+            .atomic(|txn| {
+                txn.flow_mod(parse_flow_mod("actions=drop").unwrap());
+            })
+            .unwrap_err();
+        match *err.kind() {
+            ErrorKind::UnexpectedReply(_, ds::Type::Error) => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+        // the switch does not support atomic bundles, later ones are not sent
+        assert!(handle.supports_bundles());
+        for _ in 0..2 {
+            let err = handle
+                .atomic(|txn| {
+                    txn.flow_mod(parse_flow_mod("actions=drop").unwrap());
+                })
+                .unwrap_err();
+            match *err.kind() {
+                ErrorKind::UnsupportedBySwitch(..) => (),
+                ref kind => panic!("unexpected error kind {:?}", kind),
+            }
+        }
+        assert!(!handle.supports_bundles());
+        // bundles need 1.4
+        handle.version.negotiate(ds::Version::V1_3);
+        assert!(handle.atomic(|_| ()).is_err());
+        drop(handle);
+
+        let received = switch.join().unwrap();
+        assert_eq!(received.len(), 9);
+        let bundle_ids: Vec<u32> = received[..4]
+            .iter()
+            .map(|msg| match *msg.payload() {
+                ds::OfPayload::BundleControl(ref ctrl) => ctrl.bundle_id,
+                ds::OfPayload::BundleAddMessage(ref add) => {
+                    // the embedded message has the xid of the bundle add
+                    assert_eq!(add.message.header().xid(), msg.header().xid());
+                    assert_eq!(*add.message.header().ttype(), ds::Type::FlowMod);
+                    add.bundle_id
+                }
+                ref payload => panic!("unexpected payload {:?}", payload),
+            })
+            .collect();
+        assert_eq!(bundle_ids, vec![1; 4]);
+        match *received[3].payload() {
+            ds::OfPayload::BundleControl(ref ctrl) => {
+                assert_eq!(ctrl.ctrl_type, BundleCtrlType::CommitRequest);
+                assert_eq!(ctrl.flags, BundleFlags::ATOMIC | BundleFlags::ORDERED);
+            }
+            ref payload => panic!("unexpected payload {:?}", payload),
+        }
+        // the failed commit discards its bundle
+        match *received[7].payload() {
+            ds::OfPayload::BundleControl(ref ctrl) => {
+                assert_eq!(ctrl.ctrl_type, BundleCtrlType::DiscardRequest);
+                assert_eq!(ctrl.bundle_id, 2);
+            }
+            ref payload => panic!("unexpected payload {:?}", payload),
+        }
+        match *received[8].payload() {
+            ds::OfPayload::BundleControl(ref ctrl) => {
+                assert_eq!(ctrl.ctrl_type, BundleCtrlType::OpenRequest);
+            }
+            ref payload => panic!("unexpected payload {:?}", payload),
+        }
+    }
+
+    #[test]
+    fn gets_and_sets_config() {
+        let (handle, recv) = handle();
+        let replies = handle.replies().clone();
+        let config = SwitchConfig {
+            flags: ConfigFlags::FRAG_DROP,
+            miss_send_len: 128,
+        };
+        let current = config.clone();
+        let switch = thread::spawn(move || {
+            let msg = recv.recv().unwrap();
+            match msg.payload() {
+                ds::OfPayload::GetConfigRequest => (),
+                payload => panic!("unexpected payload {:?}", payload),
+            }
+            let reply = ds::OfPayload::GetConfigReply(current);
+            let reply = ds::OfMsg::generate(*msg.header().xid(), reply);
+            assert!(replies.route(reply).is_none());
+            recv
+        });
+        assert_eq!(handle.get_config().unwrap(), config);
+        let recv = switch.join().unwrap();
+
+        let xid = handle.set_config(ConfigFlags::FRAG_NORMAL, 0xffff).unwrap();
+        let msg = recv.try_recv().unwrap();
+        assert_eq!(*msg.header().xid(), xid);
+        match *msg.payload() {
+            ds::OfPayload::SetConfig(ref config) => {
+                assert_eq!(config.flags, ConfigFlags::FRAG_NORMAL);
+                assert_eq!(config.miss_send_len, 0xffff);
+            }
+            ref payload => panic!("unexpected payload {:?}", payload),
+        }
+    }
+
+    #[test]
+    fn enqueue_checks_queues_of_port() {
+        let (handle, recv) = handle();
+        // queue config messages were replaced by multipart requests in 1.4
+        handle.version.negotiate(ds::Version::V1_3);
+        let replies = handle.replies().clone();
+        // fake switch with queue 1 on every port, answers only one request
+        let switch = thread::spawn(move || {
+            let msg = recv.recv().unwrap();
+            let port = match msg.payload() {
+                ds::OfPayload::QueueGetConfigRequest(request) => request.port.clone(),
+                _ => panic!("expected a queue config request"),
+            };
+            let queue = PacketQueue::new(1, port.clone(), Vec::new());
+            let reply = QueueGetConfigReply::new(port, vec![queue]);
+            let reply = ds::OfPayload::QueueGetConfigReply(reply);
+            let reply = ds::OfMsg::generate(*msg.header().xid(), reply);
+            assert!(replies.route(reply).is_none());
+        });
+
+        let port = PortNumber::NormalPort(2);
+        let actions = handle.enqueue(port.clone(), 1).unwrap();
+        assert_eq!(actions, actions::enqueue(port.clone(), 1));
+        switch.join().unwrap();
+        // answered from the cache
+        assert!(handle.cached_queues(&port).is_some());
+        assert!(handle.enqueue(port, 2).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+//! Tcp listeners of the controller.
+//!
+//! A controller can listen on several addresses at once (eg. one per
+//! interface or one for IPv4 and one for IPv6), see `start_controller_on`.
+//! IPv6 listeners only accept IPv6 connections, so the same port can be bound
+//! for both address families:
+//!
+//! ```no_run
+//! use oath2::ctl::listener;
+//!
+//! let addrs = ["0.0.0.0:6653", "[::]:6653"];
+//! let listeners = listener::bind_all(&addrs).unwrap();
+//! assert_eq!(listeners.len(), 2);
+//! ```
+
+#[cfg(unix)]
+use std::fs;
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+
+use socket2::{Domain, Socket, Type};
+
+use super::super::err::*;
+
+/// number of connections waiting to be accepted before the os refuses new ones
+pub const BACKLOG: i32 = 128;
+
+/// binds a listener to the address, IPv6 listeners do not accept IPv4
+/// connections (mapped addresses)
+pub fn bind(addr: &SocketAddr) -> Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, None)?;
+    // restarting the controller does not wait for old connections to time out
+    socket.set_reuse_address(true)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.bind(&(*addr).into())?;
+    socket.listen(BACKLOG)?;
+    Ok(socket.into())
+}
+
+/// binds a listener to every address the given ones resolve to
+/// (eg. "localhost:6653" to 127.0.0.1 and ::1), fails if one of them can not
+/// be bound or there are none
+pub fn bind_all<A: ToSocketAddrs>(addrs: &[A]) -> Result<Vec<TcpListener>> {
+    let mut resolved: Vec<SocketAddr> = Vec::new();
+    for addr in addrs {
+        for addr in addr.to_socket_addrs()? {
+            // the same address may be listed twice (eg. in /etc/hosts)
+            if !resolved.contains(&addr) {
+                resolved.push(addr);
+            }
+        }
+    }
+    if resolved.is_empty() {
+        bail!(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no addresses to listen on"
+        ));
+    }
+    resolved.iter().map(bind).collect()
+}
+
+/// binds a Unix domain socket at the path, a socket left behind by a
+/// controller that is gone is replaced, one that is still in use or any other
+/// file at the path is not
+#[cfg(unix)]
+pub fn bind_unix<P: AsRef<Path>>(path: P) -> Result<UnixListener> {
+    let path = path.as_ref();
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        // nobody accepts connections anymore
+        if metadata.file_type().is_socket() && UnixStream::connect(path).is_err() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(UnixListener::bind(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpStream;
+
+    #[test]
+    fn binds_all_addresses() {
+        let addrs = ["127.0.0.1:0", "localhost:0", "127.0.0.1:0"];
+        let listeners = bind_all(&addrs).unwrap();
+        // localhost may also resolve to ::1, duplicates are bound once
+        assert!(!listeners.is_empty() && listeners.len() <= 2);
+        for listener in &listeners {
+            let addr = listener.local_addr().unwrap();
+            let stream = TcpStream::connect(addr).unwrap();
+            let (_, peer) = listener.accept().unwrap();
+            assert_eq!(peer, stream.local_addr().unwrap());
+        }
+
+        let empty: [SocketAddr; 0] = [];
+        assert!(bind_all(&empty).is_err());
+    }
+
+    #[test]
+    fn ipv6_listener_leaves_ipv4_port_free() {
+        // skipped where the host has no IPv6
+        let ipv6 = match bind(&"[::]:0".parse().unwrap()) {
+            Ok(ipv6) => ipv6,
+            Err(_) => return,
+        };
+        let port = ipv6.local_addr().unwrap().port();
+        let ipv4 = bind(&SocketAddr::from(([0, 0, 0, 0], port))).unwrap();
+        assert_eq!(ipv4.local_addr().unwrap().port(), port);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replaces_stale_unix_socket() {
+        let path = ::std::env::temp_dir().join(format!("oath2-{}.sock", ::std::process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = bind_unix(&path).unwrap();
+        // still in use
+        assert!(bind_unix(&path).is_err());
+        drop(listener);
+        // the file stays after the listener is closed
+        assert!(path.exists());
+        let listener = bind_unix(&path).unwrap();
+        assert!(UnixStream::connect(&path).is_ok());
+        drop(listener);
+        fs::remove_file(&path).unwrap();
+    }
+}
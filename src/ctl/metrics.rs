@@ -0,0 +1,637 @@
+//! Counters about the traffic of the controller.
+//! The controller records into the `Metrics` of its configuration,
+//! `Metrics::snapshot` returns the current values, which can be printed in
+//! the prometheus text format with `MetricsSnapshot::to_prometheus`.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use byteorder::{BigEndian, ByteOrder};
+use num_traits::{FromPrimitive, ToPrimitive};
+
+use super::super::ds::Type;
+
+/// Marks the data of the echo requests sent to measure the round trip time,
+/// followed by the time they were sent.
+pub const ECHO_MARKER: &[u8; 8] = b"oath2rtt";
+
+/// Upper bounds (in microseconds) of the buckets of the handler latency histogram.
+pub const LATENCY_BUCKETS_US: [u64; 7] = [10, 100, 1_000, 10_000, 100_000, 1_000_000, 10_000_000];
+
+/// Histogram with the buckets of LATENCY_BUCKETS_US, the last count is for everything above.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LatencyHistogram {
+    pub counts: [u64; 8],
+    pub sum: Duration,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        self.counts[latency_bucket(latency)] += 1;
+        self.sum += latency;
+    }
+
+    /// number of recorded latencies
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+/// index of the bucket of the latency in LatencyHistogram::counts
+fn latency_bucket(latency: Duration) -> usize {
+    let us = latency.as_secs() * 1_000_000 + u64::from(latency.subsec_micros());
+    LATENCY_BUCKETS_US
+        .iter()
+        .position(|bound| us <= *bound)
+        .unwrap_or(LATENCY_BUCKETS_US.len())
+}
+
+/// Counters of one datapath, summed over all its connections.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SwitchMetrics {
+    pub received: HashMap<Type, u64>,
+    pub sent: HashMap<Type, u64>,
+    pub decode_errors: u64,
+    /// most messages that were waiting to be sent at once
+    pub max_queue_depth: usize,
+    /// time the main connection is up, None while it is disconnected
+    pub uptime: Option<Duration>,
+    /// number of times the main connection was registered
+    pub connects: u64,
+}
+
+/// Counters of a single connection, see `SwitchHandle::stats`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ConnectionStats {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub messages_in: u64,
+    pub messages_out: u64,
+    pub decode_errors: u64,
+    /// time since the last echo request or reply of the switch, None before the first one
+    pub since_last_echo: Option<Duration>,
+    /// messages waiting to be sent, including the ones that are being written
+    pub queue_depth: usize,
+    /// round trip time of the last answered echo request of the controller,
+    /// None before the first reply (see `ControllerConfig::echo_interval`)
+    pub echo_rtt: Option<Duration>,
+}
+
+/// Counters that the connection threads update without locks.
+#[derive(Debug)]
+struct Counters {
+    /// by the type byte of the header
+    received: Vec<AtomicU64>,
+    sent: Vec<AtomicU64>,
+    decode_errors: AtomicU64,
+    max_queue_depth: AtomicUsize,
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        let by_type = || (0..256).map(|_| AtomicU64::new(0)).collect();
+        Counters {
+            received: by_type(),
+            sent: by_type(),
+            decode_errors: AtomicU64::new(0),
+            max_queue_depth: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Counters {
+    fn count(counts: &[AtomicU64], ttype: &Type) {
+        counts[ttype.to_usize().unwrap()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// the types that were counted at least once
+    fn by_type(counts: &[AtomicU64]) -> HashMap<Type, u64> {
+        counts
+            .iter()
+            .enumerate()
+            .filter_map(|(ttype, count)| match count.load(Ordering::Relaxed) {
+                0 => None,
+                count => Type::from_usize(ttype).map(|ttype| (ttype, count)),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Default)]
+struct ConnectionState {
+    stats: ConnectionStats,
+    last_echo: Option<Instant>,
+    /// the datapath and its counters, None until the features reply
+    switch: Option<(u64, Arc<Counters>)>,
+}
+
+#[derive(Debug, Default)]
+struct SwitchState {
+    counters: Arc<Counters>,
+    connected_at: Option<Instant>,
+    connects: u64,
+}
+
+/// Traffic counters of the controller, shared by all connections.
+/// Messages are counted with atomics, the lock of the switches is only taken
+/// when a switch connects or disconnects and for snapshots.
+#[derive(Debug)]
+pub struct Metrics {
+    started: Instant,
+    counters: Counters,
+    /// counts of the buckets of LATENCY_BUCKETS_US and the sum in nanoseconds
+    handler_latency: Vec<AtomicU64>,
+    handler_latency_sum: AtomicU64,
+    switches: Mutex<HashMap<u64, SwitchState>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            started: Instant::now(),
+            counters: Counters::default(),
+            handler_latency: (0..LATENCY_BUCKETS_US.len() + 1)
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            handler_latency_sum: AtomicU64::new(0),
+            switches: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// counters for a new connection, its datapath is not known until the features reply
+    pub fn connection(metrics: &Arc<Metrics>) -> ConnectionMetrics {
+        ConnectionMetrics {
+            metrics: metrics.clone(),
+            connection: Arc::new(Mutex::new(ConnectionState::default())),
+            epoch: Instant::now(),
+        }
+    }
+
+    pub fn record_handler_latency(&self, latency: Duration) {
+        self.handler_latency[latency_bucket(latency)].fetch_add(1, Ordering::Relaxed);
+        let nanos = latency.as_secs() * 1_000_000_000 + u64::from(latency.subsec_nanos());
+        self.handler_latency_sum.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// the main connection of the datapath was registered
+    pub fn switch_connected(&self, datapath_id: u64) {
+        let mut switches = self.switches.lock().unwrap();
+        let switch = switches.entry(datapath_id).or_default();
+        switch.connected_at = Some(Instant::now());
+        switch.connects += 1;
+    }
+
+    /// the main connection of the datapath was closed
+    pub fn switch_disconnected(&self, datapath_id: u64) {
+        let mut switches = self.switches.lock().unwrap();
+        if let Some(switch) = switches.get_mut(&datapath_id) {
+            switch.connected_at = None;
+        }
+    }
+
+    /// current values of all counters
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let switches = self.switches.lock().unwrap();
+        let now = Instant::now();
+        let mut handler_latency = LatencyHistogram::default();
+        for (count, bucket) in handler_latency
+            .counts
+            .iter_mut()
+            .zip(self.handler_latency.iter())
+        {
+            *count = bucket.load(Ordering::Relaxed);
+        }
+        let nanos = self.handler_latency_sum.load(Ordering::Relaxed);
+        handler_latency.sum = Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32);
+        MetricsSnapshot {
+            uptime: now.duration_since(self.started),
+            received: Counters::by_type(&self.counters.received),
+            sent: Counters::by_type(&self.counters.sent),
+            decode_errors: self.counters.decode_errors.load(Ordering::Relaxed),
+            handler_latency: handler_latency,
+            switches: switches
+                .iter()
+                .map(|(datapath_id, switch)| {
+                    let counters = &switch.counters;
+                    let metrics = SwitchMetrics {
+                        received: Counters::by_type(&counters.received),
+                        sent: Counters::by_type(&counters.sent),
+                        decode_errors: counters.decode_errors.load(Ordering::Relaxed),
+                        max_queue_depth: counters.max_queue_depth.load(Ordering::Relaxed),
+                        uptime: switch.connected_at.map(|at| now.duration_since(at)),
+                        connects: switch.connects,
+                    };
+                    (*datapath_id, metrics)
+                })
+                .collect(),
+        }
+    }
+
+    /// counters of the datapath, created on first use
+    fn switch_counters(&self, datapath_id: u64) -> Arc<Counters> {
+        let mut switches = self.switches.lock().unwrap();
+        switches.entry(datapath_id).or_default().counters.clone()
+    }
+}
+
+/// Records the traffic of one connection, cheap to clone for its threads.
+#[derive(Debug, Clone)]
+pub struct ConnectionMetrics {
+    metrics: Arc<Metrics>,
+    connection: Arc<Mutex<ConnectionState>>,
+    /// echo requests carry the time since this instant
+    epoch: Instant,
+}
+
+impl ConnectionMetrics {
+    /// counts everything after this call for the datapath too
+    pub fn set_datapath_id(&self, datapath_id: u64) {
+        let counters = self.metrics.switch_counters(datapath_id);
+        self.connection.lock().unwrap().switch = Some((datapath_id, counters));
+    }
+
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    /// None until the features reply was received
+    pub fn datapath_id(&self) -> Option<u64> {
+        let connection = self.connection.lock().unwrap();
+        connection
+            .switch
+            .as_ref()
+            .map(|&(datapath_id, _)| datapath_id)
+    }
+
+    /// counters of this connection only
+    pub fn stats(&self) -> ConnectionStats {
+        let connection = self.connection.lock().unwrap();
+        let mut stats = connection.stats.clone();
+        stats.since_last_echo = connection.last_echo.map(|at| at.elapsed());
+        stats
+    }
+
+    pub fn record_received(&self, ttype: &Type) {
+        let mut connection = self.connection.lock().unwrap();
+        connection.stats.messages_in += 1;
+        if *ttype == Type::EchoRequest || *ttype == Type::EchoReply {
+            connection.last_echo = Some(Instant::now());
+        }
+        self.update(&connection, |counters| {
+            Counters::count(&counters.received, ttype)
+        });
+    }
+
+    pub fn record_sent(&self, ttype: &Type) {
+        let mut connection = self.connection.lock().unwrap();
+        connection.stats.messages_out += 1;
+        self.update(&connection, |counters| {
+            Counters::count(&counters.sent, ttype)
+        });
+    }
+
+    pub fn record_decode_error(&self) {
+        let mut connection = self.connection.lock().unwrap();
+        connection.stats.decode_errors += 1;
+        self.update(&connection, |counters| {
+            counters.decode_errors.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+
+    /// number of messages that were waiting to be sent
+    pub fn record_queue_depth(&self, depth: usize) {
+        let mut connection = self.connection.lock().unwrap();
+        connection.stats.queue_depth = depth;
+        if let Some((_, ref counters)) = connection.switch {
+            counters.max_queue_depth.fetch_max(depth, Ordering::Relaxed);
+        }
+    }
+
+    /// applies f to the counters of the controller and of the datapath of the connection
+    fn update<F: Fn(&Counters)>(&self, connection: &ConnectionState, f: F) {
+        f(&self.metrics.counters);
+        if let Some((_, ref counters)) = connection.switch {
+            f(counters);
+        }
+    }
+
+    /// data of an echo request sent now, the reply carries it back to
+    /// record_echo_reply
+    pub fn echo_payload(&self) -> Vec<u8> {
+        let elapsed = self.epoch.elapsed();
+        let nanos = elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos());
+        let mut data = ECHO_MARKER.to_vec();
+        data.resize(ECHO_MARKER.len() + 8, 0);
+        BigEndian::write_u64(&mut data[ECHO_MARKER.len()..], nanos);
+        data
+    }
+
+    /// records the round trip time if the data of the echo reply was made by
+    /// echo_payload of this connection, returns None for other echo replies
+    pub fn record_echo_reply(&self, data: &[u8]) -> Option<Duration> {
+        if data.len() != ECHO_MARKER.len() + 8 || !data.starts_with(ECHO_MARKER) {
+            return None;
+        }
+        let nanos = BigEndian::read_u64(&data[ECHO_MARKER.len()..]);
+        let sent = Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32);
+        // not sent by this connection
+        let rtt = self.epoch.elapsed().checked_sub(sent)?;
+        self.connection.lock().unwrap().stats.echo_rtt = Some(rtt);
+        Some(rtt)
+    }
+
+    /// bytes read from the connection
+    pub fn record_bytes_in(&self, bytes: usize) {
+        self.connection.lock().unwrap().stats.bytes_in += bytes as u64;
+    }
+
+    /// bytes written to the connection
+    pub fn record_bytes_out(&self, bytes: usize) {
+        self.connection.lock().unwrap().stats.bytes_out += bytes as u64;
+    }
+}
+
+/// Values of the counters at the time of `Metrics::snapshot`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricsSnapshot {
+    /// time since the metrics were created
+    pub uptime: Duration,
+    pub received: HashMap<Type, u64>,
+    pub sent: HashMap<Type, u64>,
+    pub decode_errors: u64,
+    /// time the handler needed per message (hellos and echos are not counted)
+    pub handler_latency: LatencyHistogram,
+    pub switches: HashMap<u64, SwitchMetrics>,
+}
+
+impl MetricsSnapshot {
+    /// packet ins per second received since an earlier snapshot
+    pub fn packet_in_rate(&self, earlier: &MetricsSnapshot) -> f64 {
+        let count = |snapshot: &MetricsSnapshot| {
+            snapshot.received.get(&Type::PacketIn).cloned().unwrap_or(0)
+        };
+        let elapsed = self.uptime.checked_sub(earlier.uptime).unwrap_or_default();
+        let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        if secs == 0.0 {
+            return 0.0;
+        }
+        count(self).saturating_sub(count(earlier)) as f64 / secs
+    }
+
+    /// the snapshot in the prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "# TYPE oath2_uptime_seconds gauge").unwrap();
+        writeln!(out, "oath2_uptime_seconds {}", self.uptime.as_secs()).unwrap();
+
+        writeln!(out, "# TYPE oath2_messages_received_total counter").unwrap();
+        write_by_type(
+            &mut out,
+            "oath2_messages_received_total",
+            "",
+            &self.received,
+        );
+        writeln!(out, "# TYPE oath2_messages_sent_total counter").unwrap();
+        write_by_type(&mut out, "oath2_messages_sent_total", "", &self.sent);
+        writeln!(out, "# TYPE oath2_decode_errors_total counter").unwrap();
+        writeln!(out, "oath2_decode_errors_total {}", self.decode_errors).unwrap();
+
+        writeln!(out, "# TYPE oath2_handler_latency_seconds histogram").unwrap();
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS_US
+            .iter()
+            .zip(self.handler_latency.counts.iter())
+        {
+            cumulative += count;
+            writeln!(
+                out,
+                "oath2_handler_latency_seconds_bucket{{le=\"{}\"}} {}",
+                *bound as f64 / 1e6,
+                cumulative
+            )
+            .unwrap();
+        }
+        let count = self.handler_latency.count();
+        writeln!(
+            out,
+            "oath2_handler_latency_seconds_bucket{{le=\"+Inf\"}} {}",
+            count
+        )
+        .unwrap();
+        let sum = self.handler_latency.sum;
+        writeln!(
+            out,
+            "oath2_handler_latency_seconds_sum {}",
+            sum.as_secs() as f64 + f64::from(sum.subsec_nanos()) / 1e9
+        )
+        .unwrap();
+        writeln!(out, "oath2_handler_latency_seconds_count {}", count).unwrap();
+
+        // every family is followed by the samples of all switches
+        let mut switches: Vec<(&u64, &SwitchMetrics)> = self.switches.iter().collect();
+        switches.sort_by_key(|&(datapath_id, _)| *datapath_id);
+        let label = |datapath_id: &u64| format!("datapath_id=\"{:#x}\"", datapath_id);
+        writeln!(out, "# TYPE oath2_switch_messages_received_total counter").unwrap();
+        for &(datapath_id, switch) in &switches {
+            write_by_type(
+                &mut out,
+                "oath2_switch_messages_received_total",
+                &label(datapath_id),
+                &switch.received,
+            );
+        }
+        writeln!(out, "# TYPE oath2_switch_messages_sent_total counter").unwrap();
+        for &(datapath_id, switch) in &switches {
+            write_by_type(
+                &mut out,
+                "oath2_switch_messages_sent_total",
+                &label(datapath_id),
+                &switch.sent,
+            );
+        }
+        writeln!(out, "# TYPE oath2_switch_decode_errors_total counter").unwrap();
+        for &(datapath_id, switch) in &switches {
+            writeln!(
+                out,
+                "oath2_switch_decode_errors_total{{{}}} {}",
+                label(datapath_id),
+                switch.decode_errors
+            )
+            .unwrap();
+        }
+        writeln!(out, "# TYPE oath2_switch_max_queue_depth gauge").unwrap();
+        for &(datapath_id, switch) in &switches {
+            writeln!(
+                out,
+                "oath2_switch_max_queue_depth{{{}}} {}",
+                label(datapath_id),
+                switch.max_queue_depth
+            )
+            .unwrap();
+        }
+        writeln!(out, "# TYPE oath2_switch_uptime_seconds gauge").unwrap();
+        for &(datapath_id, switch) in &switches {
+            if let Some(uptime) = switch.uptime {
+                writeln!(
+                    out,
+                    "oath2_switch_uptime_seconds{{{}}} {}",
+                    label(datapath_id),
+                    uptime.as_secs()
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+}
+
+/// one line per message type, sorted by type name
+fn write_by_type(out: &mut String, name: &str, labels: &str, counts: &HashMap<Type, u64>) {
+    let mut lines: Vec<(String, u64)> = counts
+        .iter()
+        .map(|(ttype, count)| (format!("{:?}", ttype), *count))
+        .collect();
+    lines.sort();
+    let separator = if labels.is_empty() { "" } else { "," };
+    for (ttype, count) in lines {
+        writeln!(
+            out,
+            "{}{{{}{}type=\"{}\"}} {}",
+            name, labels, separator, ttype, count
+        )
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_per_switch_after_features_reply() {
+        let metrics = Arc::new(Metrics::new());
+        let connection = Metrics::connection(&metrics);
+        connection.record_received(&Type::Hello);
+        connection.set_datapath_id(0x1);
+        metrics.switch_connected(0x1);
+        connection.record_received(&Type::PacketIn);
+        connection.record_received(&Type::PacketIn);
+        connection.record_sent(&Type::PacketOut);
+        connection.record_decode_error();
+        connection.record_queue_depth(3);
+        connection.record_queue_depth(1);
+        metrics.record_handler_latency(Duration::from_millis(2));
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.received[&Type::Hello], 1);
+        assert_eq!(snapshot.received[&Type::PacketIn], 2);
+        assert_eq!(snapshot.decode_errors, 1);
+        assert_eq!(snapshot.handler_latency.counts[3], 1);
+
+        let switch = &snapshot.switches[&0x1];
+        assert_eq!(switch.received.get(&Type::Hello), None);
+        assert_eq!(switch.received[&Type::PacketIn], 2);
+        assert_eq!(switch.sent[&Type::PacketOut], 1);
+        assert_eq!(switch.max_queue_depth, 3);
+        assert_eq!(switch.connects, 1);
+        assert!(switch.uptime.is_some());
+
+        metrics.switch_disconnected(0x1);
+        assert_eq!(metrics.snapshot().switches[&0x1].uptime, None);
+
+        let text = snapshot.to_prometheus();
+        assert!(text.contains("oath2_messages_received_total{type=\"PacketIn\"} 2\n"));
+        assert!(text.contains(
+            "oath2_switch_messages_sent_total{datapath_id=\"0x1\",type=\"PacketOut\"} 1\n"
+        ));
+        assert!(text.contains("oath2_handler_latency_seconds_bucket{le=\"0.01\"} 1\n"));
+    }
+
+    #[test]
+    fn groups_the_samples_of_all_switches_by_family() {
+        let metrics = Arc::new(Metrics::new());
+        for datapath_id in 1..3 {
+            let connection = Metrics::connection(&metrics);
+            connection.set_datapath_id(datapath_id);
+            metrics.switch_connected(datapath_id);
+            connection.record_sent(&Type::PacketOut);
+            connection.record_decode_error();
+        }
+        let text = metrics.snapshot().to_prometheus();
+        let lines: Vec<&str> = text
+            .lines()
+            .filter(|line| line.contains("oath2_switch_"))
+            .collect();
+        // no family starts twice and the samples follow their type line
+        let mut family = "";
+        let mut seen = Vec::new();
+        for line in lines {
+            if line.starts_with("# TYPE ") {
+                family = line.split(' ').nth(2).unwrap();
+                assert!(!seen.contains(&family));
+                seen.push(family);
+            } else {
+                assert!(line.starts_with(family), "{} after {}", line, family);
+            }
+        }
+        assert_eq!(seen.len(), 5);
+        assert!(text.contains(
+            "oath2_switch_decode_errors_total{datapath_id=\"0x1\"} 1\n\
+             oath2_switch_decode_errors_total{datapath_id=\"0x2\"} 1\n"
+        ));
+        assert_eq!(metrics.snapshot().sent[&Type::PacketOut], 2);
+    }
+
+    #[test]
+    fn counts_per_connection() {
+        let metrics = Arc::new(Metrics::new());
+        let connection = Metrics::connection(&metrics);
+        let other = Metrics::connection(&metrics);
+        assert_eq!(connection.stats().since_last_echo, None);
+
+        connection.record_received(&Type::Hello);
+        connection.record_received(&Type::EchoRequest);
+        connection.record_bytes_in(16);
+        connection.record_sent(&Type::EchoReply);
+        connection.record_bytes_out(8);
+        connection.record_decode_error();
+        connection.record_queue_depth(5);
+        other.record_received(&Type::Hello);
+
+        let stats = connection.stats();
+        assert_eq!(stats.messages_in, 2);
+        assert_eq!(stats.messages_out, 1);
+        assert_eq!((stats.bytes_in, stats.bytes_out), (16, 8));
+        assert_eq!(stats.decode_errors, 1);
+        assert_eq!(stats.queue_depth, 5);
+        assert!(stats.since_last_echo.is_some());
+        assert_eq!(other.stats().messages_in, 1);
+    }
+
+    #[test]
+    fn measures_echo_round_trip() {
+        let metrics = Arc::new(Metrics::new());
+        let connection = Metrics::connection(&metrics);
+        assert_eq!(connection.stats().echo_rtt, None);
+
+        let data = connection.echo_payload();
+        assert!(data.starts_with(ECHO_MARKER));
+        ::std::thread::sleep(Duration::from_millis(2));
+        let rtt = connection.record_echo_reply(&data).unwrap();
+        assert!(rtt >= Duration::from_millis(2));
+        assert_eq!(connection.stats().echo_rtt, Some(rtt));
+
+        // echo data of somebody else
+        assert_eq!(connection.record_echo_reply(b"ping"), None);
+        assert_eq!(connection.stats().echo_rtt, Some(rtt));
+    }
+}
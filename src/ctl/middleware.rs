@@ -0,0 +1,271 @@
+//! Ordered filters that see every message before the handler.
+//!
+//! Middleware can rate limit packet ins per switch, drop messages another
+//! component already took care of or audit messages without touching the
+//! handler. The layers run in the order they were pushed, the first one that
+//! does not continue decides what happens to the message:
+//!
+//! ```
+//! use oath2::ctl::middleware::Decision;
+//! use oath2::ctl::ControllerConfig;
+//! use oath2::ds::Type;
+//!
+//! let mut config = ControllerConfig::default();
+//! config.middleware.push(|msg, _ctx| match *msg.header().ttype() {
+//!     Type::PortStatus => Decision::Drop,
+//!     _ => Decision::Continue,
+//! });
+//! ```
+//!
+//! Hellos and echo requests are answered by the controller, they never reach
+//! the middleware.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+use super::super::ds;
+use super::super::ds::features::Capabilities;
+use super::super::ds::ports::Port;
+use super::super::ds::role::Role;
+use super::handle::SwitchHandle;
+use super::switch::IncomingMsg;
+use super::xid::XidAllocator;
+
+/// What happens to a message after a middleware looked at it.
+#[derive(Debug)]
+pub enum Decision {
+    /// pass the message to the next middleware, after the last one to the handler
+    Continue,
+    /// the message is dropped silently
+    Drop,
+    /// the message is answered with the given message instead of the handler
+    Reply(ds::OfMsg),
+}
+
+/// Connection a message came from.
+///
+/// Passed to middleware and handlers (see `with_state`, `Dispatcher` and
+/// `EventBus`) so they do not need their own tables of the switches: the
+/// datapath, its capabilities, ports and the role of the controller are read
+/// from the handle of the switch once its features reply was received.
+#[derive(Clone)]
+pub struct SwitchContext {
+    /// None until the features reply of the switch was received
+    pub switch: Option<Arc<SwitchHandle>>,
+    /// version negotiated on the connection
+    pub version: ds::Version,
+    /// xids of the connection, use them for new requests
+    pub xids: Arc<XidAllocator>,
+    /// messages sent here are written to the connection
+    pub reply_ch: Sender<ds::OfMsg>,
+    /// address of the switch, see IncomingMsg::peer
+    pub peer: Option<SocketAddr>,
+    /// address of the controller the switch connected to, see IncomingMsg::local
+    pub local: Option<SocketAddr>,
+}
+
+impl SwitchContext {
+    pub fn new(msg: &IncomingMsg) -> Self {
+        SwitchContext {
+            switch: msg.switch.clone(),
+            version: msg.version,
+            xids: msg.xids.clone(),
+            reply_ch: msg.reply_ch.clone(),
+            peer: msg.peer,
+            local: msg.local,
+        }
+    }
+
+    /// scope id (interface index) of a link local IPv6 peer address,
+    /// None for IPv4 and addresses without scope
+    pub fn scope_id(&self) -> Option<u32> {
+        match self.peer {
+            Some(SocketAddr::V6(ref peer)) if peer.scope_id() != 0 => Some(peer.scope_id()),
+            _ => None,
+        }
+    }
+
+    /// datapath id of the switch, None until its features reply was received
+    pub fn datapath_id(&self) -> Option<u64> {
+        self.switch.as_ref().map(|switch| switch.datapath_id())
+    }
+
+    /// capabilities from the features reply of the switch, None until it was received
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.switch
+            .as_ref()
+            .map(|switch| switch.features().capabilities)
+    }
+
+    /// the current ports of the switch ordered by number, see SwitchHandle::ports
+    /// empty until the features reply of the switch was received
+    pub fn ports(&self) -> Vec<Port> {
+        match self.switch {
+            Some(ref switch) => switch.ports(),
+            None => Vec::new(),
+        }
+    }
+
+    /// role of the controller on the switch, see SwitchHandle::role
+    pub fn role(&self) -> Option<Role> {
+        self.switch.as_ref().and_then(|switch| switch.role())
+    }
+}
+
+impl fmt::Debug for SwitchContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SwitchContext")
+            .field("datapath_id", &self.datapath_id())
+            .field("version", &self.version)
+            .field("peer", &self.peer)
+            .field("local", &self.local)
+            .field("role", &self.role())
+            .finish()
+    }
+}
+
+/// A single middleware, see the module documentation.
+pub type Middleware = Arc<dyn Fn(&ds::OfMsg, &SwitchContext) -> Decision + Send + Sync>;
+
+/// Middleware in the order they run.
+#[derive(Clone, Default)]
+pub struct MiddlewareChain {
+    layers: Vec<Middleware>,
+}
+
+impl MiddlewareChain {
+    pub fn new() -> Self {
+        MiddlewareChain::default()
+    }
+
+    /// adds a middleware that runs after all previously added ones
+    pub fn push<F>(&mut self, middleware: F)
+    where
+        F: Fn(&ds::OfMsg, &SwitchContext) -> Decision + Send + Sync + 'static,
+    {
+        self.layers.push(Arc::new(middleware));
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// runs the middleware until one of them does not continue
+    pub fn run(&self, msg: &ds::OfMsg, ctx: &SwitchContext) -> Decision {
+        for layer in &self.layers {
+            match layer(msg, ctx) {
+                Decision::Continue => (),
+                decision => return decision,
+            }
+        }
+        Decision::Continue
+    }
+
+    /// runs the middleware on an incoming message and applies the decision
+    /// returns true if the message should still be passed to the handler
+    pub fn filter(&self, msg: &IncomingMsg) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        match self.run(&msg.msg, &SwitchContext::new(msg)) {
+            Decision::Continue => true,
+            Decision::Drop => {
+                info!("Middleware dropped msg: {:?}.", msg.msg);
+                false
+            }
+            Decision::Reply(reply) => {
+                info!("Middleware replied to msg: {:?}.", msg.msg);
+                if let Err(err) = msg.reply_ch.send(reply) {
+                    error!("Could not send middleware reply: {}", err);
+                }
+                false
+            }
+        }
+    }
+}
+
+impl fmt::Debug for MiddlewareChain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MiddlewareChain")
+            .field("layers", &self.layers.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::channel;
+
+    fn incoming(payload: ds::OfPayload) -> (IncomingMsg, ::std::sync::mpsc::Receiver<ds::OfMsg>) {
+        let (send, recv) = channel();
+        let msg = IncomingMsg {
+            reply_ch: send,
+            msg: ds::OfMsg::generate(7, payload),
+            switch: None,
+            xids: Arc::new(XidAllocator::new()),
+            version: ds::MAX_VERSION,
+            peer: None,
+            local: None,
+            event_ch: None,
+        };
+        (msg, recv)
+    }
+
+    #[test]
+    fn runs_middleware_in_order() {
+        let seen = Arc::new(AtomicUsize::new(0));
+        let seen_by_last = seen.clone();
+        let mut chain = MiddlewareChain::new();
+        chain.push(|msg, _| match *msg.header().ttype() {
+            ds::Type::BarrierReply => Decision::Drop,
+            _ => Decision::Continue,
+        });
+        chain.push(|msg, _| match *msg.header().ttype() {
+            ds::Type::EchoReply => Decision::Reply(ds::OfMsg::generate(
+                *msg.header().xid(),
+                ds::OfPayload::Hello,
+            )),
+            _ => Decision::Continue,
+        });
+        chain.push(move |_, _| {
+            seen_by_last.fetch_add(1, Ordering::SeqCst);
+            Decision::Continue
+        });
+
+        let (msg, replies) = incoming(ds::OfPayload::BarrierReply);
+        assert!(!chain.filter(&msg));
+        let (msg, replies_echo) = incoming(ds::OfPayload::EchoReply(Vec::new()));
+        assert!(!chain.filter(&msg));
+        let (msg, _) = incoming(ds::OfPayload::FeaturesRequest);
+        assert!(chain.filter(&msg));
+
+        // only the message that passed the first two reached the last one
+        assert_eq!(seen.load(Ordering::SeqCst), 1);
+        assert!(replies.try_recv().is_err());
+        let reply = replies_echo.try_recv().unwrap();
+        assert_eq!(*reply.header().ttype(), ds::Type::Hello);
+        assert_eq!(*reply.header().xid(), 7);
+    }
+
+    #[test]
+    fn context_has_peer_scope() {
+        use std::net::{Ipv6Addr, SocketAddrV6};
+
+        let (mut msg, _) = incoming(ds::OfPayload::BarrierReply);
+        assert_eq!(SwitchContext::new(&msg).scope_id(), None);
+        let link_local = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        msg.peer = Some(SocketAddr::V6(SocketAddrV6::new(link_local, 40000, 0, 3)));
+        msg.local = Some("[fe80::2]:6653".parse().unwrap());
+        let ctx = SwitchContext::new(&msg);
+        assert_eq!(ctx.scope_id(), Some(3));
+        assert_eq!(ctx.local.unwrap().port(), 6653);
+    }
+}
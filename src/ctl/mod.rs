@@ -1,11 +1,83 @@
-use std::net::{TcpListener, ToSocketAddrs};
-use std::sync::mpsc::channel;
+use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use super::ds;
+use super::ds::error_msg::{ErrorMsg, HelloFailedCode};
 use super::err::*;
 
+pub mod accept;
+pub mod analysis;
+pub mod audit;
+pub mod backoff;
+pub mod buffers;
+pub mod bus;
+pub mod config;
+pub mod cookie;
+pub mod diagnostics;
+pub mod dispatch;
+pub mod events;
+pub mod expiry;
+pub mod framing;
+pub mod handle;
+pub mod listener;
+pub mod metrics;
+pub mod middleware;
+pub mod output;
+pub mod pipeline;
+pub mod priority;
+pub mod rates;
+pub mod reconcile;
+pub mod registry;
+pub mod replies;
+pub mod snapshot;
+pub mod socket;
 pub mod switch;
+pub mod tap;
+pub mod testing;
+pub mod transport;
+pub mod version;
+pub mod xid;
+
+pub use self::accept::{AcceptPolicy, DatapathDecision, DatapathPolicy, RateLimit, RejectReason};
+pub use self::analysis::{check_flow_mod, FlowConflict};
+pub use self::audit::{AuditEntry, AuditLog};
+pub use self::backoff::Backoff;
+pub use self::buffers::{BufferedPacket, PacketBuffers};
+pub use self::bus::{EventBus, Filter};
+pub use self::config::{ConnectCallback, ControllerConfig};
+pub use self::cookie::{CookieAllocator, CookieNamespace};
+pub use self::diagnostics::{BadFrame, BadFrames};
+pub use self::dispatch::Dispatcher;
+pub use self::events::ControllerEvent;
+pub use self::expiry::{FlowKey, FlowTimers};
+pub use self::handle::{FlowInstallReport, MtuPolicy, SwitchHandle};
+pub use self::metrics::{ConnectionStats, Metrics, MetricsSnapshot};
+pub use self::middleware::{Decision, MiddlewareChain, SwitchContext};
+pub use self::pipeline::{Pipeline, PipelineTable, TableMiss};
+pub use self::priority::{PriorityAllocator, PriorityBand};
+pub use self::rates::{PortDirection, PortRate, Rate, RateTracker, Sample};
+pub use self::reconcile::{ReconcilePlan, Reconciler};
+pub use self::registry::SwitchRegistry;
+pub use self::replies::ReplyRouter;
+pub use self::snapshot::FlowSnapshot;
+pub use self::socket::SocketOptions;
+pub use self::transport::{Accept, Transport};
+pub use self::xid::XidAllocator;
+
+/// Time a shed connection gets to take the hello and the error before it is closed.
+pub const SHED_TIMEOUT: Duration = Duration::from_secs(1);
 
 /// starts the controller at the given address (eg. "127.0.0.1:6653")
 /// the given handler function will not receive hellos or echo requests or similar messages
@@ -14,6 +86,49 @@ pub mod switch;
 /// unknown messages to the controller automatically on connection setup
 /// this function does not return
 pub fn start_controller<A, F>(addr: A, handler: F) -> Result<()>
+where
+    A: ToSocketAddrs,
+    F: Fn(switch::IncomingMsg) + Send + 'static,
+{
+    start_controller_with_config(addr, ControllerConfig::default(), handler)
+}
+
+/// same as start_controller but passes the state and the switch the message
+/// came from to the handler, see `with_state`
+pub fn start_controller_with_state<A, S, F>(addr: A, state: S, handler: F) -> Result<()>
+where
+    A: ToSocketAddrs,
+    S: Send + Sync + 'static,
+    F: Fn(&S, &SwitchContext, ds::OfMsg) + Send + 'static,
+{
+    let handler = with_state(Arc::new(state), handler);
+    start_controller_with_config(addr, ControllerConfig::default(), handler)
+}
+
+/// turns a handler that takes application state (eg. mac tables or the topology)
+/// into one for start_controller_with_config or connect_to_switch
+/// the state is dropped with the handler, keep a clone of the Arc to use it
+/// outside of the handler, eg. in on_connect callbacks or other threads
+pub fn with_state<S, F>(
+    state: Arc<S>,
+    handler: F,
+) -> impl Fn(switch::IncomingMsg) + Send + 'static
+where
+    S: Send + Sync + 'static,
+    F: Fn(&S, &SwitchContext, ds::OfMsg) + Send + 'static,
+{
+    move |msg| {
+        let ctx = SwitchContext::new(&msg);
+        handler(&state, &ctx, msg.msg)
+    }
+}
+
+/// same as start_controller but uses the given configuration
+pub fn start_controller_with_config<A, F>(
+    addr: A,
+    config: ControllerConfig,
+    handler: F,
+) -> Result<()>
 where
     A: ToSocketAddrs,
     F: Fn(switch::IncomingMsg) + Send + 'static,
@@ -25,62 +140,512 @@ where
         "Tcp listener successfully started at {:?}.",
         tcp_listener.local_addr()
     );
+    serve(vec![tcp_listener], config, handler)
+}
+
+/// same as start_controller_with_config but listens on all given addresses
+/// (eg. `&["0.0.0.0:6653", "[::]:6653"]` for IPv4 and IPv6), each with its own
+/// accept thread, all switches are handled by the same handler
+/// fails if one of the addresses can not be bound, see `listener::bind_all`
+pub fn start_controller_on<A, F>(addrs: &[A], config: ControllerConfig, handler: F) -> Result<()>
+where
+    A: ToSocketAddrs,
+    F: Fn(switch::IncomingMsg) + Send + 'static,
+{
+    info!("Starting tcp listeners.");
+    let listeners = listener::bind_all(addrs)?;
+    for tcp_listener in &listeners {
+        info!(
+            "Tcp listener successfully started at {:?}.",
+            tcp_listener.local_addr()
+        );
+    }
+    serve(listeners, config, handler)
+}
+
+/// same as start_controller_with_config but listens on a Unix domain socket at
+/// the path, eg. for Open vSwitch on the same host
+/// (`ovs-vsctl set-controller br0 unix:<path>`), see `listener::bind_unix`
+#[cfg(unix)]
+pub fn start_controller_unix<P, F>(path: P, config: ControllerConfig, handler: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: Fn(switch::IncomingMsg) + Send + 'static,
+{
+    info!("Starting unix listener at {:?}.", path.as_ref());
+    let unix_listener = listener::bind_unix(path)?;
+    serve(vec![unix_listener], config, handler)
+}
+
+/// same as start_controller_with_config but serves the switches connecting
+/// through the listener, eg. one that wraps accepted tcp streams in tls
+/// see `transport::Accept`
+pub fn start_controller_with_listener<L, F>(
+    listener: L,
+    config: ControllerConfig,
+    handler: F,
+) -> Result<()>
+where
+    L: transport::Accept,
+    F: Fn(switch::IncomingMsg) + Send + 'static,
+{
+    serve(vec![listener], config, handler)
+}
+
+/// accepts switches on the listeners until all of them fail
+fn serve<L, F>(listeners: Vec<L>, config: ControllerConfig, handler: F) -> Result<()>
+where
+    L: transport::Accept,
+    F: Fn(switch::IncomingMsg) + Send + 'static,
+{
+    // open capture file if traffic should be recorded
+    let capture = open_capture(&config)?;
+
+    let context = switch::ConnectionContext {
+        ctl_ch: start_handler(
+            handler,
+            config.metrics.clone(),
+            config.middleware.clone(),
+            config.event_ch.clone(),
+        )?,
+        capture: capture,
+        registry: config.registry.clone(),
+        event_ch: config.event_ch.clone(),
+        on_connect: config.on_connect.clone(),
+        datapath_policy: config.datapath_policy.clone(),
+        metrics: config.metrics.clone(),
+        queue_warning_threshold: config.queue_warning_threshold,
+        max_version: config.max_version.unwrap_or(ds::MAX_VERSION),
+        socket_options: config.socket_options,
+        async_config: config.async_config.clone(),
+        validate_messages: config.validate_messages,
+        bad_frames: config.bad_frames.clone(),
+        echo_interval: config.echo_interval,
+        switch_config: config.switch_config.clone(),
+        write_deadline: config.write_deadline,
+        audit_log: config.audit_log.clone(),
+        flags_policy: config.flags_policy,
+        tap: config.tap.clone(),
+    };
+
+    // the accept policy (eg. max_switches) applies to all listeners together
+    let accept_filter = Arc::new(Mutex::new(accept::AcceptFilter::new(
+        config.accept_policy.clone(),
+    )));
+    let mut accept_threads = Vec::with_capacity(listeners.len());
+    for listener in listeners {
+        let context = context.clone();
+        let accept_filter = accept_filter.clone();
+        let name = format!("Accept {}", listener.name());
+        accept_threads.push(
+            thread::Builder::new()
+                .name(name)
+                .spawn(move || accept_switches(listener, context, &accept_filter))?,
+        );
+    }
+    for accept_thread in accept_threads {
+        if accept_thread.join().is_err() {
+            error!("Accept thread panicked.");
+        }
+    }
 
+    // should never happen
+    // but makes the compiler happy :)
+    Ok(())
+}
+
+/// endless loop -> accept incoming switches
+fn accept_switches<L>(
+    listener: L,
+    context: switch::ConnectionContext,
+    accept_filter: &Mutex<accept::AcceptFilter>,
+) where
+    L: transport::Accept,
+{
+    info!("Starting accept on {}.", listener.name());
+    loop {
+        // try to open connection
+        // silently fail
+        let stream = match listener.accept() {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        info!("Connection from: {}.", stream.name());
+        // check accept policy, rejected streams are closed by dropping them
+        // connections without peer address are local (eg. Unix domain sockets)
+        let peer = stream.peer_addr();
+        let admitted = match peer {
+            Some(peer) => accept_filter.lock().unwrap().admit(peer.ip()),
+            None => accept_filter.lock().unwrap().admit_local(),
+        };
+        let active = match admitted {
+            Ok(active) => active,
+            Err(accept::RejectReason::TooManySwitches) => {
+                let active = accept_filter.lock().unwrap().active_connections();
+                warn!(
+                    "Shedding connection from {}, {} connections are open.",
+                    stream.name(),
+                    active
+                );
+                let name = stream.name();
+                if let Err(err) = shed(stream, context.max_version) {
+                    warn!("Could not shed connection from {}: {}", name, err);
+                }
+                events::emit(
+                    &context.event_ch,
+                    ControllerEvent::ConnectionShed {
+                        peer: peer,
+                        active: active,
+                    },
+                );
+                continue;
+            }
+            Err(reason) => {
+                warn!("Rejected connection from {}: {:?}.", stream.name(), reason);
+                if let Some(peer) = peer {
+                    events::emit(
+                        &context.event_ch,
+                        ControllerEvent::ConnectionRejected {
+                            peer: peer,
+                            reason: reason,
+                        },
+                    );
+                }
+                continue;
+            }
+        };
+        // start new connection to switch
+        // give copy of the context to inform handler of new messages
+        if let Err(err) = switch::start_switch_connection(stream, context.clone(), Some(active)) {
+            error!("{}", err);
+        }
+    }
+}
+
+/// tells a switch over the accept policy that it is not served before closing
+/// its connection, unlike a silently closed connection this shows up in the
+/// logs of the switch and makes it retry later instead of right away
+fn shed<T: transport::Transport>(stream: T, version: ds::Version) -> Result<()> {
+    let mut stream = stream;
+    // a switch that does not read must not block the accept loop
+    stream.set_write_timeout(Some(SHED_TIMEOUT))?;
+    let error = ErrorMsg::hello_failed(HelloFailedCode::EPerm, b"too many connections");
+    for payload in [ds::OfPayload::Hello, ds::OfPayload::Error(error)] {
+        let bytes = ds::OfMsg::generate_for(version, 0, payload)?.encode()?;
+        stream.write_all(&bytes[..])?;
+    }
+    stream.shutdown()?;
+    Ok(())
+}
+
+/// connects to a switch that listens for controllers at the given address
+/// (eg. "10.0.0.2:6653") instead of waiting for the switch to connect
+/// the connection is handled the same way as connections accepted by start_controller
+/// if the connection can not be established or is closed it is retried
+/// using config.reconnect_backoff
+/// this function only returns if config.reconnect_backoff.max_retries is reached
+pub fn connect_to_switch<A, F>(addr: A, config: ControllerConfig, handler: F) -> Result<()>
+where
+    A: ToSocketAddrs,
+    F: Fn(switch::IncomingMsg) + Send + 'static,
+{
+    // resolve once, all addresses are tried on every attempt
+    let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+    let target = format!("{:?}", addrs);
+    let connect_addrs = addrs.clone();
+    keep_connected(&target, addrs, config, handler, move || {
+        TcpStream::connect(&connect_addrs[..])
+    })
+}
+
+/// same as connect_to_switch but connects to the Unix domain socket at the path,
+/// eg. the control channel of Open vSwitch on the same host
+/// (`ovs-vsctl set-controller br0 punix:<path>`)
+/// the addresses of the reconnect events are empty
+#[cfg(unix)]
+pub fn connect_to_switch_unix<P, F>(path: P, config: ControllerConfig, handler: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: Fn(switch::IncomingMsg) + Send + 'static,
+{
+    let path = path.as_ref().to_path_buf();
+    let target = format!("unix:{}", path.display());
+    keep_connected(&target, Vec::new(), config, handler, move || {
+        UnixStream::connect(&path)
+    })
+}
+
+/// connects to the switch and reconnects whenever the connection is closed,
+/// see connect_to_switch
+fn keep_connected<F, C, T>(
+    target: &str,
+    addrs: Vec<SocketAddr>,
+    config: ControllerConfig,
+    handler: F,
+    connect: C,
+) -> Result<()>
+where
+    F: Fn(switch::IncomingMsg) + Send + 'static,
+    C: Fn() -> io::Result<T>,
+    T: transport::Transport,
+{
+    let context = switch::ConnectionContext {
+        ctl_ch: start_handler(
+            handler,
+            config.metrics.clone(),
+            config.middleware.clone(),
+            config.event_ch.clone(),
+        )?,
+        capture: open_capture(&config)?,
+        registry: config.registry.clone(),
+        event_ch: config.event_ch.clone(),
+        on_connect: config.on_connect.clone(),
+        datapath_policy: config.datapath_policy.clone(),
+        metrics: config.metrics.clone(),
+        queue_warning_threshold: config.queue_warning_threshold,
+        max_version: config.max_version.unwrap_or(ds::MAX_VERSION),
+        socket_options: config.socket_options,
+        async_config: config.async_config.clone(),
+        validate_messages: config.validate_messages,
+        bad_frames: config.bad_frames.clone(),
+        echo_interval: config.echo_interval,
+        switch_config: config.switch_config.clone(),
+        write_deadline: config.write_deadline,
+        audit_log: config.audit_log.clone(),
+        flags_policy: config.flags_policy,
+        tap: config.tap.clone(),
+    };
+
+    let mut delays = config.reconnect_backoff.delays();
+    loop {
+        info!("Connecting to switch at {}.", target);
+        match connect() {
+            Ok(stream) => {
+                info!("Connection to: {}.", stream.name());
+                delays.reset();
+                // wait until the connection is closed
+                match switch::start_switch_connection(stream, context.clone(), None) {
+                    Ok(input_thread) => {
+                        if input_thread.join().is_err() {
+                            error!("Input thread of switch at {} panicked.", target);
+                        }
+                        info!("Connection to switch at {} closed.", target);
+                    }
+                    Err(err) => error!("{}", err),
+                }
+            }
+            Err(err) => warn!("Could not connect to switch at {}: {}", target, err),
+        }
+        let delay = match delays.next_delay() {
+            Some(delay) => delay,
+            None => {
+                error!(
+                    "Giving up connecting to switch at {} after {} attempts.",
+                    target,
+                    delays.attempts()
+                );
+                events::emit(
+                    &config.event_ch,
+                    ControllerEvent::ReconnectAbandoned {
+                        addrs: addrs.clone(),
+                        attempts: delays.attempts(),
+                    },
+                );
+                bail!(ErrorKind::RetriesExhausted(delays.attempts()));
+            }
+        };
+        info!("Reconnecting to switch at {} in {:?}.", target, delay);
+        events::emit(
+            &config.event_ch,
+            ControllerEvent::ReconnectScheduled {
+                addrs: addrs.clone(),
+                attempt: delays.attempts(),
+                delay: delay,
+            },
+        );
+        thread::sleep(delay);
+    }
+}
+
+/// opens the capture file if traffic should be recorded
+fn open_capture(config: &ControllerConfig) -> Result<Option<switch::SharedCaptureWriter>> {
+    Ok(match config.capture_file {
+        Some(ref path) => {
+            info!("Recording OpenFlow traffic to {:?}.", path);
+            Some(Arc::new(Mutex::new(ds::capture::CaptureWriter::create(
+                path,
+            )?)))
+        }
+        None => None,
+    })
+}
+
+/// starts the handler thread and returns the channel to send messages to it
+/// the time the handler needs per message is recorded in the metrics
+/// messages the middleware does not pass on never reach the handler
+/// panics of the handler are reported as ConnectionPanicked, the thread keeps running
+fn start_handler<F>(
+    handler: F,
+    metrics: Arc<Metrics>,
+    middleware: MiddlewareChain,
+    event_ch: Option<Sender<ControllerEvent>>,
+) -> Result<Sender<switch::IncomingMsg>>
+where
+    F: Fn(switch::IncomingMsg) + Send + 'static,
+{
     let (tcp_s, tcp_r) = channel::<switch::IncomingMsg>();
 
     // start handler thread
     info!("Starting handler thread.");
     thread::Builder::new()
         .name("Handler-Thread".to_string())
-        .spawn(move || loop {
-            match tcp_r.recv() {
-                Ok(of_msg) => {
-                    info!("Handling msg: {:?}.", of_msg.msg);
+        .spawn(move || run_handler(tcp_r, &handler, &metrics, &middleware, &event_ch))?;
+    Ok(tcp_s)
+}
+
+/// handles the messages of the channel until it is closed
+fn run_handler<F>(
+    tcp_r: Receiver<switch::IncomingMsg>,
+    handler: &F,
+    metrics: &Metrics,
+    middleware: &MiddlewareChain,
+    event_ch: &Option<Sender<ControllerEvent>>,
+) where
+    F: Fn(switch::IncomingMsg),
+{
+    loop {
+        match tcp_r.recv() {
+            Ok(of_msg) => {
+                info!("Handling msg: {:?}.", of_msg.msg);
+                let datapath_id = of_msg.switch.as_ref().map(|switch| switch.datapath_id());
+                let peer = of_msg.peer;
+                // only the time of the handler is recorded
+                let mut started = None;
+                // the built-in handlers run in the same containment as the handler
+                let handled = panic::catch_unwind(AssertUnwindSafe(|| {
                     // match msg type and automatically handle special types (hello, ...)
                     match of_msg.msg.header().ttype() {
                         ds::Type::Hello => handle_hello(of_msg),
                         ds::Type::EchoRequest => handle_echo_request(of_msg),
-                        _ => handler(of_msg),
+                        _ if !middleware.filter(&of_msg) => (),
+                        _ => {
+                            started = Some(Instant::now());
+                            handler(of_msg)
+                        }
                     }
+                }));
+                if let Some(start) = started {
+                    metrics.record_handler_latency(start.elapsed());
                 }
-                Err(err) => panic!(err),
-            }
-        })?;
-
-    // endless loop -> accept incoming switches
-    info!("Starting tcp accept.");
-    for stream in tcp_listener.incoming() {
-        // try to open connection
-        // silently fail
-        if let Ok(stream) = stream {
-            info!("Tcp connection from: {:?}.", stream.peer_addr());
-            // start new connection to switch
-            // give copy of tcp_s to inform handler of new messages
-            match switch::start_switch_connection(stream, tcp_s.clone()) {
-                Err(err) => {
-                    error!("{}", err);
+                if let Err(payload) = handled {
+                    report_handler_panic(&*payload, datapath_id, peer, event_ch);
                 }
-                _ => (),
             }
+            Err(err) => panic!("{}", err),
         }
     }
+}
 
-    // should never happen
-    // but makes the compiler happy :)
-    Ok(())
+/// logs a panic of a handler thread and reports it as ConnectionPanicked of
+/// the connection the message came from
+fn report_handler_panic(
+    payload: &(dyn Any + Send),
+    datapath_id: Option<u64>,
+    peer: Option<SocketAddr>,
+    event_ch: &Option<Sender<ControllerEvent>>,
+) {
+    let message = switch::panic_message(payload);
+    let thread = thread::current().name().unwrap_or("unnamed").to_string();
+    error!("Handler panicked: {}", message);
+    events::emit(
+        event_ch,
+        ControllerEvent::ConnectionPanicked {
+            datapath_id: datapath_id,
+            peer: peer,
+            thread: thread,
+            message: message,
+        },
+    );
+}
+
+/// runs the handler on several threads, eg.
+/// `start_controller_with_config(addr, config, with_workers(4, handler)?)`
+/// all messages of a connection are handled in order by the same thread, so
+/// the handler has to be safe to call from several threads at once (Sync)
+/// the handler latency of the metrics then only covers handing messages to the threads
+/// panics of the handler are reported as ConnectionPanicked, the threads keep running
+/// fails if a thread can not be started
+pub fn with_workers<F>(
+    workers: usize,
+    handler: F,
+) -> Result<impl Fn(switch::IncomingMsg) + Send + 'static>
+where
+    F: Fn(switch::IncomingMsg) + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    let workers = ::std::cmp::max(workers, 1);
+    info!("Starting {} handler threads.", workers);
+    let mut worker_chs = Vec::with_capacity(workers);
+    for index in 0..workers {
+        let (worker_s, worker_r) = channel::<switch::IncomingMsg>();
+        let handler = handler.clone();
+        thread::Builder::new()
+            .name(format!("Handler-Worker-{}", index))
+            .spawn(move || {
+                for of_msg in worker_r {
+                    let datapath_id = of_msg.switch.as_ref().map(|switch| switch.datapath_id());
+                    let peer = of_msg.peer;
+                    let event_ch = of_msg.event_ch.clone();
+                    // a panic must not stop the messages of the other connections
+                    let handled = panic::catch_unwind(AssertUnwindSafe(|| handler(of_msg)));
+                    if let Err(payload) = handled {
+                        report_handler_panic(&*payload, datapath_id, peer, &event_ch);
+                    }
+                }
+            })?;
+        worker_chs.push(worker_s);
+    }
+    Ok(move |of_msg: switch::IncomingMsg| {
+        let worker = worker_for(&of_msg, worker_chs.len());
+        if worker_chs[worker].send(of_msg).is_err() {
+            error!("Handler worker {} is gone, dropping the message.", worker);
+        }
+    })
+}
+
+/// index of the worker handling the message
+/// messages are hashed by the connection they came from, which stays the same
+/// for the whole lifetime of the connection (unlike the datapath id, which is
+/// only known after the features reply)
+fn worker_for(msg: &switch::IncomingMsg, workers: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    (Arc::as_ptr(&msg.xids) as usize).hash(&mut hasher);
+    (hasher.finish() % workers as u64) as usize
 }
 
 fn handle_hello(msg: switch::IncomingMsg) {
     //TODO: handle version error
-    let response = ds::OfMsg::generate(*msg.msg.header().xid(), ds::OfPayload::Hello);
-    msg.reply_ch
-        .send(response)
-        .expect("could not send hello response");
+    let response = ds::OfMsg::generate(msg.xids.next(), ds::OfPayload::Hello);
+    // the connection closed while the message waited for the handler
+    if msg.reply_ch.send(response).is_err() {
+        debug!("Connection closed, dropping the hello response.");
+        return;
+    }
+    // ask for the datapath id to register the connection
+    let features_request = ds::OfMsg::generate(msg.xids.next(), ds::OfPayload::FeaturesRequest);
+    if msg.reply_ch.send(features_request).is_err() {
+        debug!("Connection closed, dropping the features request.");
+    }
 }
 
 fn handle_echo_request(msg: switch::IncomingMsg) {
-    let response = ds::OfMsg::generate(*msg.msg.header().xid(), ds::OfPayload::EchoReply);
-    msg.reply_ch
-        .send(response)
-        .expect("could not send hello response");
+    // the reply carries the data of the request back unchanged
+    let data = match *msg.msg.payload() {
+        ds::OfPayload::EchoRequest(ref data) => data.clone(),
+        _ => Vec::new(),
+    };
+    let response = ds::OfMsg::generate(*msg.msg.header().xid(), ds::OfPayload::EchoReply(data));
+    if msg.reply_ch.send(response).is_err() {
+        debug!("Connection closed, dropping the echo response.");
+    }
 }
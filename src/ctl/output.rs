@@ -0,0 +1,202 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use super::transport::Transport;
+
+/// Bytes collected before the output buffer is written even if more messages are queued.
+pub const WRITE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Collects encoded messages so that all messages queued for a connection
+/// are written with one syscall.
+/// The buffer is reused between writes, it only grows if a single message
+/// is bigger than its capacity.
+#[derive(Debug)]
+pub struct OutputBuffer {
+    buf: Vec<u8>,
+    max_len: usize,
+    writes: u64,
+    messages: u64,
+}
+
+impl OutputBuffer {
+    pub fn new(max_len: usize) -> Self {
+        OutputBuffer {
+            buf: Vec::with_capacity(max_len),
+            max_len: max_len,
+            writes: 0,
+            messages: 0,
+        }
+    }
+
+    /// appends an encoded message
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+        self.messages += 1;
+    }
+
+    /// true if the buffer should be written before adding more messages
+    pub fn is_full(&self) -> bool {
+        self.buf.len() >= self.max_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// number of bytes not written yet
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// writes everything collected so far, does nothing if the buffer is empty
+    pub fn flush_to<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        writer.write_all(&self.buf[..])?;
+        self.buf.clear();
+        self.writes += 1;
+        Ok(())
+    }
+
+    /// like flush_to, but fails with TimedOut if writing everything takes longer
+    /// than the deadline, the write timeout of the stream is set to the time
+    /// left before every write (or write_timeout if that is shorter) and set back
+    /// to write_timeout afterwards, the bytes not written are kept
+    pub fn flush_within<T: Transport>(
+        &mut self,
+        stream: &mut T,
+        deadline: Duration,
+        write_timeout: Option<Duration>,
+    ) -> io::Result<()> {
+        let started = Instant::now();
+        let mut written = 0;
+        let result = loop {
+            if written == self.buf.len() {
+                break Ok(());
+            }
+            let left = match deadline.checked_sub(started.elapsed()) {
+                Some(left) if left > Duration::from_secs(0) => left,
+                _ => {
+                    break Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "write deadline passed",
+                    ))
+                }
+            };
+            // a write blocking longer than the configured write timeout still fails
+            let (timeout, until_deadline) = match write_timeout {
+                Some(timeout) if timeout < left => (timeout, false),
+                _ => (left, true),
+            };
+            if let Err(err) = stream.set_write_timeout(Some(timeout)) {
+                break Err(err);
+            }
+            match stream.write(&self.buf[written..]) {
+                Ok(0) => break Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                // the time left elapsed, the deadline is checked again
+                Err(ref err)
+                    if until_deadline
+                        && (err.kind() == io::ErrorKind::WouldBlock
+                            || err.kind() == io::ErrorKind::TimedOut) => {}
+                Err(err) => break Err(err),
+            }
+        };
+        let result = result.and(stream.set_write_timeout(write_timeout));
+        self.buf.drain(..written);
+        if result.is_ok() {
+            self.writes += 1;
+        }
+        result
+    }
+
+    /// number of writes done so far
+    pub fn writes(&self) -> u64 {
+        self.writes
+    }
+
+    /// number of messages pushed so far
+    pub fn messages(&self) -> u64 {
+        self.messages
+    }
+}
+
+impl Default for OutputBuffer {
+    fn default() -> Self {
+        OutputBuffer::new(WRITE_BUFFER_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use socket2::SockRef;
+    use std::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn coalesces_until_full() {
+        let mut output = OutputBuffer::new(16);
+        let mut written = Vec::new();
+        for i in 0..5u8 {
+            output.push(&[i; 8]);
+            if output.is_full() {
+                output.flush_to(&mut written).unwrap();
+            }
+        }
+        output.flush_to(&mut written).unwrap();
+        // an empty buffer is not written
+        output.flush_to(&mut written).unwrap();
+
+        assert_eq!(output.messages(), 5);
+        assert_eq!(output.writes(), 3);
+        assert_eq!(written.len(), 40);
+        assert_eq!(&written[32..], &[4u8; 8]);
+        assert!(output.is_empty());
+    }
+    #[test]
+    fn gives_up_after_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        // the peer never reads
+        let _peer = listener.accept().unwrap();
+        SockRef::from(&stream).set_send_buffer_size(4096).unwrap();
+
+        let mut output = OutputBuffer::new(16);
+        output.push(&vec![0u8; 16 * 1024 * 1024]);
+        let started = Instant::now();
+        let err = output
+            .flush_within(&mut stream, Duration::from_millis(100), None)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        // the write timeout of the stream is set back
+        assert_eq!(stream.write_timeout().unwrap(), None);
+        // the bytes that were not written are kept
+        assert!(!output.is_empty() && output.len() < 16 * 1024 * 1024);
+        assert_eq!(output.writes(), 0);
+    }
+
+    #[test]
+    fn restores_the_write_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let _peer = listener.accept().unwrap();
+
+        let mut output = OutputBuffer::new(16);
+        output.push(&[0u8; 8]);
+        output
+            .flush_within(
+                &mut stream,
+                Duration::from_millis(100),
+                Some(Duration::from_secs(5)),
+            )
+            .unwrap();
+        assert_eq!(output.writes(), 1);
+        assert_eq!(
+            stream.write_timeout().unwrap(),
+            Some(Duration::from_secs(5))
+        );
+    }
+}
@@ -0,0 +1,320 @@
+//! Multi table pipelines declared by the application.
+//!
+//! A `Pipeline` names the tables an application uses, eg. table 0 for
+//! classification, 1 for the acl and 2 for forwarding, and what happens to
+//! packets that match no flow of a table. Flows refer to tables by name with
+//! `goto`, `table_miss_flows` installs the miss behaviour of every table and
+//! `check_features` makes sure the switch has enough tables.
+//!
+//! ```
+//! use oath2::ctl::pipeline::{Pipeline, PipelineTable, TableMiss};
+//!
+//! let pipeline = Pipeline::new(vec![
+//!     PipelineTable::new(0, "classification", TableMiss::Next),
+//!     PipelineTable::new(1, "acl", TableMiss::Drop),
+//!     PipelineTable::new(2, "forwarding", TableMiss::Controller),
+//! ])
+//! .unwrap();
+//! assert_eq!(pipeline.table_id("acl").unwrap(), 1);
+//! // the acl drops packets without a flow, it needs no table miss flow
+//! assert_eq!(pipeline.table_miss_flows().len(), 2);
+//! ```
+
+use super::super::ds::actions::PayloadOutput;
+use super::super::ds::features::SwitchFeatures;
+use super::super::ds::flow_instructions::{
+    InstructionHeader, InstructionPayload, PayloadApplyActions, PayloadGotoTable,
+};
+use super::super::ds::flow_match::Match;
+use super::super::ds::flow_mod::{FlowMod, FlowModCommand, FlowModFlags};
+use super::super::ds::group_mod::GROUP_ANY;
+use super::super::ds::packet_out::{BufferId, CONTROLLER_MAX_LEN};
+use super::super::ds::ports::PortNo;
+use super::super::err::*;
+
+/// Highest table id, 0xff addresses all tables (OFPTT_MAX).
+pub const TABLE_MAX: u8 = 0xfe;
+
+/// What happens to packets that match no flow of a table.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableMiss {
+    /// no table miss flow is installed, the switch drops the packets
+    Drop,
+    /// packets are sent to the controller
+    Controller,
+    /// packets continue in the next table of the pipeline
+    Next,
+    /// packets continue in the named table
+    Goto(String),
+}
+
+/// Table of a pipeline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineTable {
+    pub id: u8,
+    pub name: String,
+    pub miss: TableMiss,
+}
+
+impl PipelineTable {
+    pub fn new(id: u8, name: &str, miss: TableMiss) -> Self {
+        PipelineTable {
+            id: id,
+            name: name.to_string(),
+            miss: miss,
+        }
+    }
+}
+
+/// Tables of a pipeline, see the module documentation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    tables: Vec<PipelineTable>,
+}
+
+impl Pipeline {
+    /// checks the tables, their ids and names have to be unique and table
+    /// misses may only go to later tables (goto_table can not go back)
+    pub fn new(mut tables: Vec<PipelineTable>) -> Result<Self> {
+        tables.sort_by_key(|table| table.id);
+        for (index, table) in tables.iter().enumerate() {
+            if table.id > TABLE_MAX {
+                bail!(ErrorKind::IllegalValue(
+                    table.id as u64,
+                    stringify!(PipelineTable)
+                ));
+            }
+            let duplicate = tables[..index]
+                .iter()
+                .any(|other| other.id == table.id || other.name == table.name);
+            if duplicate {
+                bail!(ErrorKind::IllegalValue(
+                    table.id as u64,
+                    stringify!(Pipeline)
+                ));
+            }
+        }
+        let pipeline = Pipeline { tables: tables };
+        for table in &pipeline.tables {
+            pipeline.miss_target(table)?;
+        }
+        Ok(pipeline)
+    }
+
+    pub fn tables(&self) -> &[PipelineTable] {
+        &self.tables
+    }
+
+    /// id of the named table
+    pub fn table_id(&self, name: &str) -> Result<u8> {
+        match self.tables.iter().find(|table| table.name == name) {
+            Some(table) => Ok(table.id),
+            None => bail!(ErrorKind::UnknownTable(name.to_string())),
+        }
+    }
+
+    /// instruction that continues in the named table
+    pub fn goto(&self, name: &str) -> Result<InstructionHeader> {
+        Ok(goto_table(self.table_id(name)?))
+    }
+
+    /// instruction that continues in the table after the named one,
+    /// None for the last table
+    pub fn goto_next(&self, name: &str) -> Result<Option<InstructionHeader>> {
+        let table_id = self.table_id(name)?;
+        Ok(self.next_table(table_id).map(goto_table))
+    }
+
+    fn next_table(&self, table_id: u8) -> Option<u8> {
+        self.tables
+            .iter()
+            .map(|table| table.id)
+            .find(|&id| id > table_id)
+    }
+
+    /// table the misses of the table continue in, None if they do not continue
+    fn miss_target(&self, table: &PipelineTable) -> Result<Option<u8>> {
+        let target = match table.miss {
+            TableMiss::Next => match self.next_table(table.id) {
+                Some(next) => next,
+                // nothing left to continue in
+                None => bail!(ErrorKind::IllegalValue(
+                    table.id as u64,
+                    stringify!(TableMiss)
+                )),
+            },
+            TableMiss::Goto(ref name) => self.table_id(name)?,
+            _ => return Ok(None),
+        };
+        if target <= table.id {
+            bail!(ErrorKind::IllegalValue(
+                target as u64,
+                stringify!(TableMiss)
+            ));
+        }
+        Ok(Some(target))
+    }
+
+    /// table miss flows (priority 0, empty match) of all tables that do not drop
+    pub fn table_miss_flows(&self) -> Vec<FlowMod> {
+        self.tables
+            .iter()
+            .filter_map(|table| {
+                let instruction = match table.miss {
+                    TableMiss::Drop => return None,
+                    TableMiss::Controller => {
+                        let output = PayloadOutput {
+                            port: PortNo::Controller.into(),
+                            max_len: CONTROLLER_MAX_LEN,
+                        };
+                        InstructionPayload::ApplyActions(PayloadApplyActions {
+                            actions: vec![output.into()],
+                        })
+                        .into()
+                    }
+                    // checked by new
+                    _ => goto_table(self.miss_target(table).unwrap().unwrap()),
+                };
+                Some(table_miss_flow(table.id, instruction))
+            })
+            .collect()
+    }
+
+    /// fails if a table of the pipeline does not exist on the switch
+    pub fn check_features(&self, features: &SwitchFeatures) -> Result<()> {
+        match self.tables.last() {
+            Some(table) => check_table(table.id, features),
+            None => Ok(()),
+        }
+    }
+
+    /// fails if the flow mod is for a table that is not part of the pipeline
+    /// or does not exist on the switch, or goes to such a table
+    pub fn check_flow_mod(&self, flow_mod: &FlowMod, features: &SwitchFeatures) -> Result<()> {
+        for table_id in referenced_tables(flow_mod) {
+            check_table(table_id, features)?;
+            if !self.tables.iter().any(|table| table.id == table_id) {
+                bail!(ErrorKind::UnknownTable(table_id.to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn goto_table(table_id: u8) -> InstructionHeader {
+    InstructionPayload::GotoTable(PayloadGotoTable { table_id: table_id }).into()
+}
+
+fn table_miss_flow(table_id: u8, instruction: InstructionHeader) -> FlowMod {
+    FlowMod {
+        cookie: 0,
+        cookie_mask: 0,
+        table_id: table_id,
+        command: FlowModCommand::Add,
+        idle_timeout: 0,
+        hard_timeout: 0,
+        priority: 0,
+        buffer_id: BufferId::NoBuffer,
+        out_port: PortNo::Any.into(),
+        out_group: GROUP_ANY,
+        flags: FlowModFlags::empty(),
+        mmatch: Match::new(Vec::new()),
+        instructions: vec![instruction],
+    }
+}
+
+/// table of the flow mod and the tables its goto_table instructions go to
+fn referenced_tables(flow_mod: &FlowMod) -> Vec<u8> {
+    let mut tables = vec![flow_mod.table_id];
+    for instruction in &flow_mod.instructions {
+        if let InstructionPayload::GotoTable(ref payload) = *instruction.payload() {
+            tables.push(payload.table_id);
+        }
+    }
+    tables
+}
+
+/// fails if the switch does not have the table
+pub fn check_table(table_id: u8, features: &SwitchFeatures) -> Result<()> {
+    if table_id >= features.n_tables {
+        bail!(ErrorKind::InvalidTable(table_id, features.n_tables));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ds::factory;
+    use super::super::super::ds::flow_parser::{format_flow_mod, parse_flow_mod};
+    use super::*;
+
+    fn features(n_tables: u8) -> SwitchFeatures {
+        let mut features = factory::features_reply(1);
+        features.n_tables = n_tables;
+        features
+    }
+
+    fn pipeline() -> Pipeline {
+        Pipeline::new(vec![
+            PipelineTable::new(4, "forwarding", TableMiss::Controller),
+            PipelineTable::new(0, "classification", TableMiss::Next),
+            PipelineTable::new(1, "acl", TableMiss::Goto("forwarding".to_string())),
+            PipelineTable::new(2, "logging", TableMiss::Drop),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn generates_goto_and_table_miss_flows() {
+        let pipeline = pipeline();
+        assert_eq!(pipeline.goto("acl").unwrap(), goto_table(1));
+        assert_eq!(pipeline.goto_next("logging").unwrap(), Some(goto_table(4)));
+        assert_eq!(pipeline.goto_next("forwarding").unwrap(), None);
+        assert!(pipeline.goto("l2").is_err());
+
+        let flows: Vec<String> = pipeline
+            .table_miss_flows()
+            .iter()
+            .map(format_flow_mod)
+            .collect();
+        assert_eq!(
+            flows,
+            vec![
+                "priority=0,actions=goto_table:1",
+                "table=1,priority=0,actions=goto_table:4",
+                "table=4,priority=0,actions=CONTROLLER",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_pipelines() {
+        // misses can not go back
+        assert!(Pipeline::new(vec![
+            PipelineTable::new(0, "first", TableMiss::Drop),
+            PipelineTable::new(1, "second", TableMiss::Goto("first".to_string())),
+        ])
+        .is_err());
+        // the last table has no next table
+        assert!(Pipeline::new(vec![PipelineTable::new(0, "only", TableMiss::Next)]).is_err());
+        // duplicate names
+        assert!(Pipeline::new(vec![
+            PipelineTable::new(0, "acl", TableMiss::Drop),
+            PipelineTable::new(1, "acl", TableMiss::Drop),
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn checks_tables_of_the_switch() {
+        let pipeline = pipeline();
+        assert!(pipeline.check_features(&features(5)).is_ok());
+        assert!(pipeline.check_features(&features(4)).is_err());
+
+        let flow_mod = parse_flow_mod("table=1,priority=10,actions=goto_table:4").unwrap();
+        assert!(pipeline.check_flow_mod(&flow_mod, &features(5)).is_ok());
+        assert!(pipeline.check_flow_mod(&flow_mod, &features(4)).is_err());
+        let flow_mod = parse_flow_mod("table=1,priority=10,actions=goto_table:3").unwrap();
+        assert!(pipeline.check_flow_mod(&flow_mod, &features(5)).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::super::err::*;
+
+/// Partitions the flow priorities between the applications of a controller.
+///
+/// Each application (eg. "acl" or "routing") gets a named band of
+/// priorities that no other band overlaps, so the precedence between the
+/// flows of different applications is decided by the bands and not by the
+/// priorities each application happens to pick.
+/// Within its band an application uses fixed offsets (`at`) or hands out
+/// consecutive priorities (`next`).
+#[derive(Debug, Default)]
+pub struct PriorityAllocator {
+    bands: Mutex<BTreeMap<String, PriorityBand>>,
+}
+
+impl PriorityAllocator {
+    pub fn new() -> Self {
+        PriorityAllocator::default()
+    }
+
+    /// defines the band with the priorities from low to high (inclusive)
+    /// defining a band again with the same range returns the existing band,
+    /// fails if the range is empty, overlaps another band or the band was
+    /// defined with another range
+    pub fn define(&self, name: &str, low: u16, high: u16) -> Result<PriorityBand> {
+        if low > high {
+            bail!(ErrorKind::IllegalValue(
+                u64::from(low),
+                stringify!(PriorityBand)
+            ));
+        }
+        let mut bands = self.bands.lock().unwrap();
+        if let Some(band) = bands.get(name) {
+            if band.low == low && band.high == high {
+                return Ok(band.clone());
+            }
+            bail!(ErrorKind::PriorityBandRedefined(
+                name.to_string(),
+                band.low,
+                band.high
+            ));
+        }
+        if let Some(other) = bands
+            .values()
+            .find(|band| band.low <= high && low <= band.high)
+        {
+            bail!(ErrorKind::PriorityBandOverlap(
+                name.to_string(),
+                other.name.clone()
+            ));
+        }
+        let band = PriorityBand {
+            name: name.to_string(),
+            low: low,
+            high: high,
+            next: Arc::new(AtomicUsize::new(0)),
+        };
+        bands.insert(name.to_string(), band.clone());
+        Ok(band)
+    }
+
+    /// the band with the given name, fails if it was not defined
+    pub fn band(&self, name: &str) -> Result<PriorityBand> {
+        match self.bands.lock().unwrap().get(name) {
+            Some(band) => Ok(band.clone()),
+            None => bail!(ErrorKind::UnknownPriorityBand(name.to_string())),
+        }
+    }
+
+    /// band a priority belongs to, None if it is in no band
+    pub fn band_of(&self, priority: u16) -> Option<PriorityBand> {
+        self.bands
+            .lock()
+            .unwrap()
+            .values()
+            .find(|band| band.contains(priority))
+            .cloned()
+    }
+
+    /// all bands ordered by name
+    pub fn bands(&self) -> Vec<PriorityBand> {
+        self.bands.lock().unwrap().values().cloned().collect()
+    }
+}
+
+/// Priorities of an application, see `PriorityAllocator`.
+/// Clones hand out priorities from the same counter.
+#[derive(Debug, Clone)]
+pub struct PriorityBand {
+    name: String,
+    low: u16,
+    high: u16,
+    next: Arc<AtomicUsize>,
+}
+
+impl PriorityBand {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// lowest priority of the band
+    pub fn low(&self) -> u16 {
+        self.low
+    }
+
+    /// highest priority of the band
+    pub fn high(&self) -> u16 {
+        self.high
+    }
+
+    /// number of priorities in the band
+    pub fn size(&self) -> usize {
+        usize::from(self.high - self.low) + 1
+    }
+
+    /// priority at the offset from the lowest one of the band,
+    /// fails if the band is smaller
+    pub fn at(&self, offset: u16) -> Result<u16> {
+        match self.low.checked_add(offset) {
+            Some(priority) if priority <= self.high => Ok(priority),
+            _ => bail!(ErrorKind::PrioritiesExhausted(self.name.clone())),
+        }
+    }
+
+    /// next unused priority of the band counting up from the lowest one,
+    /// fails once all were handed out
+    pub fn next(&self) -> Result<u16> {
+        let offset = self.next.fetch_add(1, Ordering::SeqCst);
+        if offset >= self.size() {
+            // keeps the counter from wrapping around into the band again
+            self.next.store(self.size(), Ordering::SeqCst);
+            bail!(ErrorKind::PrioritiesExhausted(self.name.clone()));
+        }
+        Ok(self.low + offset as u16)
+    }
+
+    pub fn contains(&self, priority: u16) -> bool {
+        self.low <= priority && priority <= self.high
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bands_partition_priorities() {
+        let priorities = PriorityAllocator::new();
+        let acl = priorities.define("acl", 40000, 49999).unwrap();
+        let routing = priorities.define("routing", 20000, 29999).unwrap();
+        assert_eq!(acl.size(), 10000);
+
+        assert_eq!(routing.next().unwrap(), 20000);
+        assert_eq!(routing.next().unwrap(), 20001);
+        // the same band shares the counter
+        assert_eq!(priorities.band("routing").unwrap().next().unwrap(), 20002);
+        assert_eq!(acl.at(100).unwrap(), 40100);
+        assert!(acl.at(10000).is_err());
+
+        assert_eq!(priorities.band_of(45000).unwrap().name(), "acl");
+        assert!(priorities.band_of(30000).is_none());
+        assert!(priorities.band("learning").is_err());
+        let names: Vec<String> = priorities
+            .bands()
+            .iter()
+            .map(|band| band.name().to_string())
+            .collect();
+        assert_eq!(names, vec!["acl", "routing"]);
+    }
+
+    #[test]
+    fn bands_do_not_overlap() {
+        let priorities = PriorityAllocator::new();
+        priorities.define("acl", 40000, 49999).unwrap();
+        assert!(priorities.define("acl", 40000, 49999).is_ok());
+        match *priorities.define("acl", 40000, 40001).unwrap_err().kind() {
+            ErrorKind::PriorityBandRedefined(ref name, 40000, 49999) => assert_eq!(name, "acl"),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        // a range that does not overlap the old one does not replace the band
+        assert!(priorities.define("acl", 60000, 60001).is_err());
+        assert_eq!(priorities.band("acl").unwrap().low(), 40000);
+        assert!(priorities.define("qos", 49999, 50000).is_err());
+        assert!(priorities.define("qos", 50001, 50000).is_err());
+
+        let small = priorities.define("small", 0, 1).unwrap();
+        assert_eq!(small.next().unwrap(), 0);
+        assert_eq!(small.next().unwrap(), 1);
+        assert!(small.next().is_err());
+        assert!(small.next().is_err());
+    }
+}
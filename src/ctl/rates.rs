@@ -0,0 +1,266 @@
+//! Rates derived from the packet and byte counters of flow or port statistics.
+//!
+//! Counters only ever grow, so rates need the previous sample of the same
+//! counter. `RateTracker` keeps it per key (eg. `FlowKey` or datapath id and
+//! port number) and computes the rate over the time the switch reports between
+//! both samples, which does not depend on when the replies arrived.
+//! A duration that went backwards or a counter that is smaller than before
+//! means the flow was replaced, the port was added again or the switch
+//! restarted, the sample starts over and no rate is reported.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::Duration;
+
+use super::super::ds::flow_stats::FlowStats;
+use super::super::ds::port_stats::PortStats;
+use super::expiry::FlowKey;
+
+/// Counters of a flow or port at one point in time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub packets: u64,
+    pub bytes: u64,
+    /// time the counters have been running, eg. the duration of the flow
+    pub duration: Duration,
+}
+
+impl Sample {
+    pub fn new(packets: u64, bytes: u64, duration: Duration) -> Self {
+        Sample {
+            packets: packets,
+            bytes: bytes,
+            duration: duration,
+        }
+    }
+
+    pub fn of_flow(stats: &FlowStats) -> Self {
+        Sample::new(stats.packet_count, stats.byte_count, stats.duration())
+    }
+
+    /// the received or transmitted counters of the port
+    pub fn of_port(stats: &PortStats, direction: PortDirection) -> Self {
+        match direction {
+            PortDirection::Rx => Sample::new(stats.rx_packets, stats.rx_bytes, stats.duration()),
+            PortDirection::Tx => Sample::new(stats.tx_packets, stats.tx_bytes, stats.duration()),
+        }
+    }
+}
+
+/// Counters of a port, received or transmitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PortDirection {
+    Rx,
+    Tx,
+}
+
+/// Change of the counters between two samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    /// packets counted since the previous sample
+    pub packets: u64,
+    /// bytes counted since the previous sample
+    pub bytes: u64,
+    /// time between both samples
+    pub interval: Duration,
+}
+
+impl Rate {
+    /// packets per second
+    pub fn pps(&self) -> f64 {
+        self.packets as f64 / secs(self.interval)
+    }
+
+    /// bits per second
+    pub fn bps(&self) -> f64 {
+        self.bytes as f64 * 8.0 / secs(self.interval)
+    }
+}
+
+fn secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9
+}
+
+/// Rate of a flow from a flow stats reply.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowRate {
+    pub key: FlowKey,
+    pub cookie: u64,
+    pub rate: Rate,
+}
+
+/// Rates of a port from a port stats reply.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortRate {
+    pub datapath_id: u64,
+    pub port_no: u32,
+    pub rx: Rate,
+    pub tx: Rate,
+}
+
+/// Previous sample of every counter, see the module documentation.
+#[derive(Debug)]
+pub struct RateTracker<K: Hash + Eq> {
+    previous: HashMap<K, Sample>,
+}
+
+impl<K: Hash + Eq> Default for RateTracker<K> {
+    fn default() -> Self {
+        RateTracker {
+            previous: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq> RateTracker<K> {
+    pub fn new() -> Self {
+        RateTracker::default()
+    }
+
+    /// stores the sample and returns the rate since the previous sample of the key
+    /// None for the first sample, after a restart or if no time passed
+    pub fn update(&mut self, key: K, sample: Sample) -> Option<Rate> {
+        let previous = self.previous.insert(key, sample)?;
+        if sample.duration <= previous.duration
+            || sample.packets < previous.packets
+            || sample.bytes < previous.bytes
+        {
+            return None;
+        }
+        Some(Rate {
+            packets: sample.packets - previous.packets,
+            bytes: sample.bytes - previous.bytes,
+            interval: sample.duration - previous.duration,
+        })
+    }
+
+    /// forgets the previous sample, eg. after the flow was removed
+    pub fn remove(&mut self, key: &K) -> Option<Sample> {
+        self.previous.remove(key)
+    }
+
+    /// keeps only the samples of the keys the predicate accepts,
+    /// eg. the flows of the last reply
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&K) -> bool,
+    {
+        self.previous.retain(|key, _| keep(key))
+    }
+
+    pub fn len(&self) -> usize {
+        self.previous.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.previous.is_empty()
+    }
+}
+
+impl RateTracker<FlowKey> {
+    /// updates the samples of the flows of a flow stats reply
+    /// returns the rates of the flows that were sampled before
+    pub fn update_flows(&mut self, stats: &[FlowStats]) -> Vec<FlowRate> {
+        stats
+            .iter()
+            .filter_map(|flow| {
+                let key = FlowKey::new(flow.table_id, flow.priority, &flow.mmatch);
+                self.update(key.clone(), Sample::of_flow(flow))
+                    .map(|rate| FlowRate {
+                        key: key,
+                        cookie: flow.cookie,
+                        rate: rate,
+                    })
+            })
+            .collect()
+    }
+}
+
+impl RateTracker<(u64, u32, PortDirection)> {
+    /// updates the samples of the ports of a port stats reply of the datapath
+    /// returns the rates of the ports that were sampled before
+    pub fn update_ports(&mut self, datapath_id: u64, stats: &[PortStats]) -> Vec<PortRate> {
+        stats
+            .iter()
+            .filter_map(|port| {
+                let port_no: u32 = port.port_no.clone().into();
+                let mut rate = |direction| {
+                    let sample = Sample::of_port(port, direction);
+                    self.update((datapath_id, port_no, direction), sample)
+                };
+                let (rx, tx) = (rate(PortDirection::Rx), rate(PortDirection::Tx));
+                Some(PortRate {
+                    datapath_id: datapath_id,
+                    port_no: port_no,
+                    rx: rx?,
+                    tx: tx?,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ds::ports::PortNumber;
+    use super::*;
+
+    fn at(secs: u64, packets: u64, bytes: u64) -> Sample {
+        Sample::new(packets, bytes, Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn rates_between_samples() {
+        let mut tracker = RateTracker::new();
+        assert_eq!(tracker.update((1, 1), at(10, 100, 1000)), None);
+        let rate = tracker.update((1, 1), at(12, 300, 3000)).unwrap();
+        assert_eq!((rate.packets, rate.bytes), (200, 2000));
+        assert_eq!(rate.pps(), 100.0);
+        assert_eq!(rate.bps(), 8000.0);
+
+        // counter reset while the duration grew, the next sample starts over
+        tracker.update((1, 2), at(1, u64::MAX - 9, 0));
+        assert_eq!(tracker.update((1, 2), at(2, 10, 0)), None);
+        let rate = tracker.update((1, 2), at(3, 30, 0)).unwrap();
+        assert_eq!(rate.packets, 20);
+
+        // restart, the next sample starts over
+        assert_eq!(tracker.update((1, 1), at(1, 5, 50)), None);
+        let rate = tracker.update((1, 1), at(2, 15, 50)).unwrap();
+        assert_eq!((rate.packets, rate.interval), (10, Duration::from_secs(1)));
+
+        tracker.retain(|&(_, port)| port == 2);
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn rates_of_ports() {
+        let port = |secs: u32, rx_bytes: u64, tx_bytes: u64| PortStats {
+            port_no: PortNumber::NormalPort(3),
+            rx_packets: rx_bytes / 100,
+            tx_packets: tx_bytes / 100,
+            rx_bytes: rx_bytes,
+            tx_bytes: tx_bytes,
+            rx_dropped: 0,
+            tx_dropped: 0,
+            rx_errors: 0,
+            tx_errors: 0,
+            rx_frame_err: 0,
+            rx_over_err: 0,
+            rx_crc_err: 0,
+            collisions: 0,
+            duration_sec: secs,
+            duration_nsec: 0,
+        };
+        let mut tracker = RateTracker::new();
+        assert!(tracker.update_ports(1, &[port(10, 1000, 0)]).is_empty());
+        let rates = tracker.update_ports(1, &[port(12, 3000, 400)]);
+        assert_eq!(rates.len(), 1);
+        assert_eq!((rates[0].datapath_id, rates[0].port_no), (1, 3));
+        assert_eq!(rates[0].rx.bps(), 8000.0);
+        assert_eq!(rates[0].tx.pps(), 2.0);
+        // the port was added again
+        assert!(tracker.update_ports(1, &[port(13, 100, 400)]).is_empty());
+        assert_eq!(tracker.update_ports(1, &[port(14, 200, 400)]).len(), 1);
+    }
+}
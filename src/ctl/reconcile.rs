@@ -0,0 +1,231 @@
+//! Reconciliation of the flows on a switch with the desired flows.
+//!
+//! The `Reconciler` reads the flows of a switch with a flow stats request,
+//! compares them with the flows the application wants (eg. the tracked flows
+//! or a `FlowSnapshot`) and plans the flow mods that delete the extra flows,
+//! change the differing ones and add the missing ones (see `snapshot::diff`).
+//! The plan can be printed for a dry run before it is applied, eg. once a
+//! switch reconnected or on demand from an admin endpoint:
+//!
+//! ```ignore
+//! let reconciler = Reconciler::new().namespace(&cookies);
+//! let plan = reconciler.plan(&handle, snapshot.flows())?;
+//! print!("{}", plan);
+//! if !dry_run {
+//!     let report = plan.apply(&handle)?;
+//! }
+//! ```
+//!
+//! Only the flows in the scope of the reconciler (its table and cookie
+//! namespace) are compared, flows of other applications are left alone.
+//! Flows are compared by their normalized match (see `Match::normalized`), a
+//! switch may report the match fields in another order than they were sent or
+//! with the prerequisites it added.
+
+use std::fmt;
+
+use super::super::ds::flow_mod::{FlowMod, FlowModCommand};
+use super::super::ds::flow_parser::format_flow_mod;
+use super::super::ds::flow_stats::FlowStatsRequest;
+use super::super::ds::table_mod::TABLE_ALL;
+use super::super::err::*;
+use super::cookie::CookieNamespace;
+use super::handle::{FlowInstallReport, SwitchHandle};
+use super::snapshot::diff;
+
+/// The flow mods that bring the flows of a switch to the desired state.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReconcilePlan {
+    pub datapath_id: u64,
+    /// strict deletes of the flows that are not desired
+    pub deletes: Vec<FlowMod>,
+    /// strict modifies of the flows with other instructions
+    pub modifies: Vec<FlowMod>,
+    /// adds of the missing flows and of the flows with other cookies, timeouts or flags
+    pub adds: Vec<FlowMod>,
+}
+
+impl ReconcilePlan {
+    /// plans the flow mods that turn the installed flows into the desired ones
+    pub fn new(datapath_id: u64, installed: &[FlowMod], desired: &[FlowMod]) -> Self {
+        let mut plan = ReconcilePlan {
+            datapath_id: datapath_id,
+            ..ReconcilePlan::default()
+        };
+        for flow_mod in diff(installed, desired) {
+            match flow_mod.command {
+                FlowModCommand::DeleteStrict => plan.deletes.push(flow_mod),
+                FlowModCommand::ModifyStrict => plan.modifies.push(flow_mod),
+                _ => plan.adds.push(flow_mod),
+            }
+        }
+        plan
+    }
+
+    /// whether the switch already has the desired flows
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// number of flow mods of the plan
+    pub fn len(&self) -> usize {
+        self.deletes.len() + self.modifies.len() + self.adds.len()
+    }
+
+    /// the flow mods in the order they are sent, deletes first to make room in the tables
+    pub fn flow_mods(&self) -> Vec<FlowMod> {
+        let mut flow_mods = self.deletes.clone();
+        flow_mods.extend(self.modifies.iter().cloned());
+        flow_mods.extend(self.adds.iter().cloned());
+        flow_mods
+    }
+
+    /// sends the flow mods to the switch, see `SwitchHandle::install_flows`
+    /// fails if the handle is not the one of the datapath of the plan
+    pub fn apply(&self, handle: &SwitchHandle) -> Result<FlowInstallReport> {
+        if handle.datapath_id() != self.datapath_id {
+            bail!(ErrorKind::IllegalValue(
+                handle.datapath_id(),
+                stringify!(datapath_id)
+            ));
+        }
+        handle.install_flows(self.flow_mods())
+    }
+}
+
+/// the plan for a dry run, one flow mod per line in the syntax of ds::flow_parser, eg.
+/// `DeleteStrict priority=10,eth_type=0x0800,actions=drop`
+impl fmt::Display for ReconcilePlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for flow_mod in self.flow_mods() {
+            writeln!(f, "{:?} {}", flow_mod.command, format_flow_mod(&flow_mod))?;
+        }
+        Ok(())
+    }
+}
+
+/// Plans the reconciliation of the flows in its scope, see the module documentation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reconciler {
+    table_id: u8,
+    cookie: u64,
+    cookie_mask: u64,
+}
+
+impl Default for Reconciler {
+    fn default() -> Self {
+        Reconciler::new()
+    }
+}
+
+impl Reconciler {
+    /// reconciles the flows of every table, whatever their cookie
+    pub fn new() -> Self {
+        Reconciler {
+            table_id: TABLE_ALL,
+            cookie: 0,
+            cookie_mask: 0,
+        }
+    }
+
+    /// only reconciles the flows of the table
+    pub fn table(mut self, table_id: u8) -> Self {
+        self.table_id = table_id;
+        self
+    }
+
+    /// only reconciles the flows with a cookie of the namespace
+    pub fn namespace(mut self, namespace: &CookieNamespace) -> Self {
+        self.cookie = namespace.prefix();
+        self.cookie_mask = namespace.mask();
+        self
+    }
+
+    /// whether the flow is in the table and cookie namespace of the reconciler
+    pub fn in_scope(&self, flow_mod: &FlowMod) -> bool {
+        (self.table_id == TABLE_ALL || flow_mod.table_id == self.table_id)
+            && flow_mod.cookie & self.cookie_mask == self.cookie & self.cookie_mask
+    }
+
+    /// requests the flows in scope from the switch and plans their reconciliation
+    /// fails if the flows can not be requested, see `SwitchHandle::flows`
+    pub fn plan(&self, handle: &SwitchHandle, desired: &[FlowMod]) -> Result<ReconcilePlan> {
+        let mut request = FlowStatsRequest::all();
+        request.table_id = self.table_id;
+        request.cookie = self.cookie;
+        request.cookie_mask = self.cookie_mask;
+        let installed = handle.flows(request)?;
+        Ok(self.plan_for(handle.datapath_id(), &installed, desired))
+    }
+
+    /// plans the reconciliation of the flows in scope,
+    /// installed and desired flows out of scope are ignored
+    pub fn plan_for(
+        &self,
+        datapath_id: u64,
+        installed: &[FlowMod],
+        desired: &[FlowMod],
+    ) -> ReconcilePlan {
+        let installed: Vec<FlowMod> = self.scoped(installed);
+        let desired: Vec<FlowMod> = self.scoped(desired);
+        ReconcilePlan::new(datapath_id, &installed, &desired)
+    }
+
+    fn scoped(&self, flows: &[FlowMod]) -> Vec<FlowMod> {
+        flows
+            .iter()
+            .filter(|flow_mod| self.in_scope(flow_mod))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ds::flow_parser::parse_flow_mod;
+    use super::super::cookie::CookieAllocator;
+    use super::*;
+
+    fn flow(s: &str, cookie: u64) -> FlowMod {
+        let mut flow_mod = parse_flow_mod(s).unwrap();
+        flow_mod.cookie = cookie;
+        flow_mod
+    }
+
+    #[test]
+    fn plans_flows_in_scope() {
+        let cookies = CookieAllocator::new(8).unwrap();
+        let ours = cookies.namespace("routing").unwrap();
+        let theirs = cookies.namespace("firewall").unwrap();
+        let installed = vec![
+            flow("priority=10,ip,actions=output:1", ours.next()),
+            flow("priority=20,arp,actions=drop", ours.next()),
+            flow("priority=30,tcp,actions=drop", theirs.next()),
+        ];
+        let desired = vec![
+            flow("priority=10,ip,actions=output:2", installed[0].cookie),
+            flow("priority=40,ipv6,actions=drop", ours.next()),
+        ];
+        let reconciler = Reconciler::new().namespace(&ours);
+        assert!(!reconciler.in_scope(&installed[2]));
+        let plan = reconciler.plan_for(1, &installed, &desired);
+        assert_eq!(plan.len(), 3);
+        assert_eq!(plan.deletes[0].priority, 20);
+        assert_eq!(plan.modifies[0].priority, 10);
+        assert_eq!(plan.adds[0].priority, 40);
+        assert_eq!(
+            plan.to_string(),
+            format!(
+                "DeleteStrict priority=20,cookie={:#x},eth_type=0x0806,actions=drop\n\
+                 ModifyStrict priority=10,cookie={:#x},eth_type=0x0800,actions=output:2\n\
+                 Add priority=40,cookie={:#x},eth_type=0x86dd,actions=drop\n",
+                installed[1].cookie, installed[0].cookie, desired[1].cookie
+            )
+        );
+
+        // no flows of table 1 on either side
+        let desired_only = Reconciler::new().table(1).plan_for(1, &installed, &desired);
+        assert!(desired_only.is_empty());
+        assert!(reconciler.plan_for(1, &desired, &desired).is_empty());
+    }
+}
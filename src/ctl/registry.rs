@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use super::super::ds;
+use super::super::ds::features::SwitchFeatures;
+use super::super::err::*;
+use super::handle::SwitchHandle;
+use super::metrics::ConnectionMetrics;
+use super::replies::ReplyRouter;
+use super::version::NegotiatedVersion;
+use super::xid::XidAllocator;
+
+/// All switches currently connected to the controller, by datapath id.
+#[derive(Debug, Default)]
+pub struct SwitchRegistry {
+    switches: Mutex<HashMap<u64, Arc<SwitchHandle>>>,
+}
+
+impl SwitchRegistry {
+    pub fn new() -> Self {
+        SwitchRegistry::default()
+    }
+
+    pub fn get(&self, datapath_id: u64) -> Option<Arc<SwitchHandle>> {
+        self.switches.lock().unwrap().get(&datapath_id).cloned()
+    }
+
+    /// handles of all connected switches
+    pub fn switches(&self) -> Vec<Arc<SwitchHandle>> {
+        self.switches.lock().unwrap().values().cloned().collect()
+    }
+
+    /// registers a connection after its features reply was received
+    /// main connections create a new handle, auxiliary connections are added
+    /// to the handle of their main connection
+    /// the connection stays registered until the returned value is dropped
+    pub fn register(
+        registry: &Arc<SwitchRegistry>,
+        features: &SwitchFeatures,
+        sender: Sender<ds::OfMsg>,
+        xids: Arc<XidAllocator>,
+        version: Arc<NegotiatedVersion>,
+        replies: Arc<ReplyRouter>,
+        metrics: ConnectionMetrics,
+    ) -> Result<Registration> {
+        let mut switches = registry.switches.lock().unwrap();
+        let handle = if features.auxiliary_id == 0 {
+            let handle = Arc::new(SwitchHandle::new(
+                features.clone(),
+                sender,
+                xids,
+                version,
+                replies,
+                metrics,
+            ));
+            if switches
+                .insert(features.datapath_id, handle.clone())
+                .is_some()
+            {
+                warn!(
+                    "Main connection of datapath {:#x} replaced an older one.",
+                    features.datapath_id
+                );
+            }
+            handle
+        } else {
+            match switches.get(&features.datapath_id) {
+                Some(handle) => {
+                    handle.add_auxiliary(features.auxiliary_id, sender);
+                    handle.clone()
+                }
+                None => bail!(ErrorKind::NoMainConnection(
+                    features.datapath_id,
+                    features.auxiliary_id
+                )),
+            }
+        };
+        Ok(Registration {
+            registry: registry.clone(),
+            handle: handle,
+            auxiliary_id: features.auxiliary_id,
+        })
+    }
+}
+
+/// Keeps a connection registered, unregisters it on drop.
+#[derive(Debug)]
+pub struct Registration {
+    registry: Arc<SwitchRegistry>,
+    handle: Arc<SwitchHandle>,
+    auxiliary_id: u8,
+}
+
+impl Registration {
+    pub fn handle(&self) -> &Arc<SwitchHandle> {
+        &self.handle
+    }
+
+    /// 0 for the main connection
+    pub fn auxiliary_id(&self) -> u8 {
+        self.auxiliary_id
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if self.auxiliary_id != 0 {
+            self.handle.remove_auxiliary(self.auxiliary_id);
+            return;
+        }
+        let mut switches = self.registry.switches.lock().unwrap();
+        // only remove the handle if it was not replaced by a newer connection
+        let is_current = switches
+            .get(&self.handle.datapath_id())
+            .is_some_and(|current| Arc::ptr_eq(current, &self.handle));
+        if is_current {
+            switches.remove(&self.handle.datapath_id());
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+
+use super::super::ds;
+use super::super::ds::multipart::MultipartFlags;
+use super::super::ds::stream;
+
+/// Forwards replies to whoever waits for the xid of the request.
+/// Each connection has its own router, messages nobody waits for go to the handler.
+#[derive(Debug, Default)]
+pub struct ReplyRouter {
+    waiters: Mutex<HashMap<u32, Sender<ds::OfMsg>>>,
+    /// waiters that decode the replies themselves, see subscribe_frames
+    frame_waiters: Mutex<HashMap<u32, Sender<Vec<u8>>>>,
+}
+
+impl ReplyRouter {
+    pub fn new() -> Self {
+        ReplyRouter::default()
+    }
+
+    /// the next message with the xid is sent to the sender instead of the handler,
+    /// the parts of a multipart reply are sent until the last one
+    pub fn subscribe(&self, xid: u32, sender: Sender<ds::OfMsg>) {
+        self.waiters.lock().unwrap().insert(xid, sender);
+    }
+
+    /// like subscribe, but the messages are sent as they were read (including
+    /// their header) before they are decoded, eg. to decode the parts of a
+    /// large multipart reply one entry at a time with `ds::stream`
+    pub fn subscribe_frames(&self, xid: u32, sender: Sender<Vec<u8>>) {
+        self.frame_waiters.lock().unwrap().insert(xid, sender);
+    }
+
+    pub fn unsubscribe(&self, xid: u32) {
+        self.waiters.lock().unwrap().remove(&xid);
+        self.frame_waiters.lock().unwrap().remove(&xid);
+    }
+
+    /// number of xids somebody waits for
+    pub fn pending(&self) -> usize {
+        self.waiters.lock().unwrap().len() + self.frame_waiters.lock().unwrap().len()
+    }
+
+    /// forwards the message to the waiter of its xid, waiters of frames get it encoded
+    /// returns the message if nobody waits for it (or the waiter is gone)
+    pub fn route(&self, msg: ds::OfMsg) -> Option<ds::OfMsg> {
+        let xid = *msg.header().xid();
+        if self.frame_waiters.lock().unwrap().contains_key(&xid) {
+            if let Ok(frame) = msg.encode() {
+                if self.route_frame(&frame) {
+                    return None;
+                }
+            }
+        }
+        let more = match *msg.payload() {
+            ds::OfPayload::MultipartReply(ref reply) => reply.flags.contains(MultipartFlags::MORE),
+            _ => false,
+        };
+        let mut waiters = self.waiters.lock().unwrap();
+        let waiter = match waiters.remove(&xid) {
+            Some(waiter) => waiter,
+            None => return Some(msg),
+        };
+        match waiter.send(msg) {
+            Ok(()) => {
+                if more {
+                    waiters.insert(xid, waiter);
+                }
+                None
+            }
+            Err(err) => Some(err.0),
+        }
+    }
+
+    /// forwards the message to the waiter of frames of its xid before it is decoded
+    /// returns false if nobody waits for the frame (or the waiter is gone),
+    /// the message has to be decoded and routed as usual then
+    pub fn route_frame(&self, frame: &[u8]) -> bool {
+        let (xid, more) = match stream::peek_multipart_reply(frame) {
+            Ok((header, _, flags)) => (*header.xid(), flags.contains(MultipartFlags::MORE)),
+            Err(_) => match stream::peek_header(frame) {
+                Ok(header) => (*header.xid(), false),
+                Err(_) => return false,
+            },
+        };
+        let mut waiters = self.frame_waiters.lock().unwrap();
+        let waiter = match waiters.remove(&xid) {
+            Some(waiter) => waiter,
+            None => return false,
+        };
+        match waiter.send(frame.to_vec()) {
+            Ok(()) => {
+                if more {
+                    waiters.insert(xid, waiter);
+                }
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
@@ -0,0 +1,304 @@
+//! Snapshots of the flows the controller wants on a switch.
+//!
+//! A `FlowSnapshot` captures the flows tracked for a datapath (see
+//! `FlowTimers`) so they can be saved to a file and applied again later, eg.
+//! after the switch rebooted or the controller restarted. `diff` computes the
+//! flow mods that turn the flows on the switch into the desired ones, flows
+//! that are already installed as desired are not sent again:
+//!
+//! ```ignore
+//! // before shutting down
+//! FlowSnapshot::capture(datapath_id, &timers).save("flows-2a.snap")?;
+//!
+//! // after the switch connected again
+//! let snapshot = FlowSnapshot::load("flows-2a.snap")?;
+//! for flow_mod in snapshot.restore(&installed) {
+//!     timers.track(&flow_mod, false);
+//!     handle.send(OfPayload::FlowMod(flow_mod))?;
+//! }
+//! ```
+//!
+//! The file starts with a 12 byte header (8 bytes magic `OATH2FLW`, u16
+//! version, 2 bytes reserved), the u64 datapath id and the u32 number of
+//! flows, followed by the flows as u32 length and the encoded flow mod (at
+//! most `ds::MAX_MESSAGE_LENGTH` bytes). All values are big endian.
+
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use std::collections::BTreeMap;
+use std::convert::{Into, TryFrom};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use super::super::ds::flow_mod::{FlowMod, FlowModCommand};
+use super::super::ds::group_mod::GROUP_ANY;
+use super::super::ds::packet_out::BufferId;
+use super::super::ds::ports::PortNo;
+use super::super::ds::MAX_MESSAGE_LENGTH;
+use super::super::err::*;
+use super::expiry::{FlowKey, FlowTimers};
+
+/// magic bytes at the start of every snapshot file
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"OATH2FLW";
+/// current version of the snapshot format
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+/// The desired flows of a datapath, see the module documentation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowSnapshot {
+    pub datapath_id: u64,
+    /// one add per flow, ordered by table, priority and match
+    flows: Vec<FlowMod>,
+}
+
+impl FlowSnapshot {
+    /// the flow mods are stored as adds of their flow,
+    /// of flows with the same table, priority and match the last one is kept
+    pub fn new(datapath_id: u64, flows: Vec<FlowMod>) -> Self {
+        FlowSnapshot {
+            datapath_id: datapath_id,
+            flows: by_key(flows.iter()).into_values().collect(),
+        }
+    }
+
+    /// the flows currently tracked for the datapath
+    pub fn capture(datapath_id: u64, timers: &FlowTimers) -> Self {
+        FlowSnapshot::new(
+            datapath_id,
+            timers.flow_mods().into_iter().cloned().collect(),
+        )
+    }
+
+    pub fn flows(&self) -> &[FlowMod] {
+        &self.flows[..]
+    }
+
+    pub fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flows.is_empty()
+    }
+
+    /// the flow mods that turn the installed flows into the ones of the snapshot
+    pub fn restore(&self, installed: &[FlowMod]) -> Vec<FlowMod> {
+        diff(installed, &self.flows)
+    }
+
+    /// writes the snapshot in the file format
+    pub fn write<W: Write>(&self, writer: W) -> Result<()> {
+        let mut writer = writer;
+        writer.write_all(&SNAPSHOT_MAGIC[..])?;
+        writer.write_u16::<BigEndian>(SNAPSHOT_VERSION)?;
+        writer.write_u16::<BigEndian>(0)?; // reserved
+        writer.write_u64::<BigEndian>(self.datapath_id)?;
+        writer.write_u32::<BigEndian>(self.flows.len() as u32)?;
+        for flow in &self.flows {
+            let bytes: Vec<u8> = flow.clone().into();
+            writer.write_u32::<BigEndian>(bytes.len() as u32)?;
+            writer.write_all(&bytes[..])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// reads a snapshot in the file format and checks its header
+    pub fn read<R: Read>(reader: R) -> Result<Self> {
+        let mut reader = reader;
+        let mut header = [0; 12];
+        reader.read_exact(&mut header)?;
+        if header[..SNAPSHOT_MAGIC.len()] != SNAPSHOT_MAGIC[..] {
+            let magic = BigEndian::read_u64(&header[..SNAPSHOT_MAGIC.len()]);
+            bail!(ErrorKind::UnknownValue(magic, stringify!(SNAPSHOT_MAGIC)));
+        }
+        let version = BigEndian::read_u16(&header[SNAPSHOT_MAGIC.len()..]);
+        if version != SNAPSHOT_VERSION {
+            bail!(ErrorKind::UnsupportedValue(
+                version as u64,
+                stringify!(SNAPSHOT_VERSION)
+            ));
+        }
+        let datapath_id = reader.read_u64::<BigEndian>()?;
+        let count = reader.read_u32::<BigEndian>()?;
+        let mut flows = Vec::new();
+        for _ in 0..count {
+            let len = reader.read_u32::<BigEndian>()? as usize;
+            if len > MAX_MESSAGE_LENGTH {
+                bail!(ErrorKind::MessageTooLong(len));
+            }
+            let mut bytes = vec![0; len];
+            reader.read_exact(&mut bytes)?;
+            flows.push(FlowMod::try_from(&bytes[..])?);
+        }
+        Ok(FlowSnapshot::new(datapath_id, flows))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        FlowSnapshot::read(BufReader::new(File::open(path)?))
+    }
+}
+
+/// the flow mods that turn the installed flows into the desired ones:
+/// strict deletes of the flows that are not desired, strict modifies of the
+/// flows that only differ in their instructions and adds of the missing flows
+/// and of the flows whose cookie, timeouts or flags differ (an add replaces a
+/// flow with the same table, priority and match)
+pub fn diff(installed: &[FlowMod], desired: &[FlowMod]) -> Vec<FlowMod> {
+    let installed = by_key(installed.iter());
+    let desired = by_key(desired.iter());
+    let mut deletes = Vec::new();
+    let mut modifies = Vec::new();
+    let mut adds = Vec::new();
+    for (key, flow) in &installed {
+        if !desired.contains_key(key) {
+            let mut delete = flow.clone();
+            delete.command = FlowModCommand::DeleteStrict;
+            delete.instructions.clear();
+            deletes.push(delete);
+        }
+    }
+    for (key, flow) in desired {
+        // the key matches the normalized match, the fields may be in another order
+        let current = installed.get(&key).map(|current| FlowMod {
+            mmatch: flow.mmatch.clone(),
+            ..current.clone()
+        });
+        match current {
+            None => adds.push(flow),
+            Some(ref current) if *current == flow => {}
+            Some(current) => {
+                let mut same_instructions = current;
+                same_instructions.instructions = flow.instructions.clone();
+                if same_instructions == flow {
+                    let mut modify = flow;
+                    modify.command = FlowModCommand::ModifyStrict;
+                    modifies.push(modify);
+                } else {
+                    adds.push(flow);
+                }
+            }
+        }
+    }
+    // deletes first to make room in the tables
+    deletes.extend(modifies);
+    deletes.extend(adds);
+    deletes
+}
+
+/// the flows as adds by their key, the last flow mod of a key is kept
+fn by_key<'a, I>(flows: I) -> BTreeMap<FlowKey, FlowMod>
+where
+    I: Iterator<Item = &'a FlowMod>,
+{
+    let mut res = BTreeMap::new();
+    for flow in flows {
+        res.insert(FlowKey::of(flow), as_add(flow));
+    }
+    res
+}
+
+/// the flow mod as add of its flow without the fields that only apply to the message
+fn as_add(flow: &FlowMod) -> FlowMod {
+    let mut add = flow.clone();
+    add.command = FlowModCommand::Add;
+    add.cookie_mask = 0;
+    add.buffer_id = BufferId::NoBuffer;
+    add.out_port = PortNo::Any.into();
+    add.out_group = GROUP_ANY;
+    add
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ds::flow_match::Match;
+    use super::super::super::ds::flow_parser::{parse_flow_mod, parse_match};
+    use super::*;
+    use std::io::Cursor;
+
+    fn flow(s: &str) -> FlowMod {
+        parse_flow_mod(s).unwrap()
+    }
+
+    #[test]
+    fn diffs_installed_and_desired_flows() {
+        let installed = vec![
+            flow("priority=10,ip,actions=output:1"),
+            flow("priority=20,arp,actions=output:2"),
+            flow("priority=30,tcp,actions=drop"),
+            flow("priority=40,udp,actions=drop"),
+        ];
+        let desired = vec![
+            flow("priority=10,ip,actions=output:1"),
+            flow("priority=20,arp,actions=output:3"),
+            flow("priority=30,tcp,idle_timeout=10,actions=drop"),
+            flow("priority=50,ipv6,actions=drop"),
+        ];
+        let mods = diff(&installed, &desired);
+        let commands: Vec<(FlowModCommand, u16)> = mods
+            .iter()
+            .map(|flow_mod| (flow_mod.command.clone(), flow_mod.priority))
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                (FlowModCommand::DeleteStrict, 40),
+                (FlowModCommand::ModifyStrict, 20),
+                (FlowModCommand::Add, 30),
+                (FlowModCommand::Add, 50),
+            ]
+        );
+        assert!(mods[0].instructions.is_empty());
+        assert_eq!(mods[1].instructions, desired[1].instructions);
+        assert_eq!(mods[2].idle_timeout, 10);
+
+        assert!(diff(&desired, &desired).is_empty());
+        assert_eq!(diff(&[], &desired).len(), 4);
+
+        // the switch reports the fields in another order with the prerequisite it added
+        let mut reported = flow("priority=30,eth_type=0x0800,tcp_dst=80,actions=drop");
+        let sent = reported.clone();
+        let mut fields = reported.mmatch.matches().clone();
+        fields.reverse();
+        fields.push(parse_match("ip_proto=6").unwrap().matches()[0].clone());
+        reported.mmatch = Match::new(fields);
+        assert!(diff(&[reported], &[sent]).is_empty());
+    }
+
+    #[test]
+    fn captures_and_reads_back_snapshots() {
+        let mut timers = FlowTimers::new();
+        let mut buffered = flow("priority=10,ip,actions=output:1");
+        buffered.buffer_id = BufferId::Buffered(7);
+        timers.track(&buffered, false);
+        timers.track(&flow("priority=20,arp,actions=output:2"), false);
+        let snapshot = FlowSnapshot::capture(0x2a, &timers);
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot.flows()[0].priority, 10);
+        assert_eq!(snapshot.flows()[0].buffer_id, BufferId::NoBuffer);
+
+        let mut bytes = Vec::new();
+        snapshot.write(&mut bytes).unwrap();
+        assert_eq!(&bytes[..8], &SNAPSHOT_MAGIC[..]);
+        let read = FlowSnapshot::read(Cursor::new(&bytes)).unwrap();
+        assert_eq!(read, snapshot);
+        assert!(read.restore(snapshot.flows()).is_empty());
+
+        // truncated
+        assert!(FlowSnapshot::read(Cursor::new(&bytes[..bytes.len() - 1])).is_err());
+        // a flow longer than a message
+        let mut long = bytes.clone();
+        long[24..28].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        let err = FlowSnapshot::read(Cursor::new(&long)).unwrap_err();
+        match *err.kind() {
+            ErrorKind::MessageTooLong(0xffff_ffff) => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+        bytes[0] = b'X';
+        assert!(FlowSnapshot::read(Cursor::new(&bytes)).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use std::net::TcpStream;
+use std::time::Duration;
+
+use socket2::{SockRef, TcpKeepalive};
+
+use super::super::err::*;
+
+/// Options of the tcp streams to the switches, applied to every connection
+/// when it is accepted or established.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SocketOptions {
+    /// disables Nagle's algorithm so small messages (eg. flow mods, echo
+    /// replies) are sent without delay, on by default
+    pub nodelay: bool,
+    /// enables tcp keepalive, probes are sent after the connection was idle
+    /// for this long, None keeps it disabled
+    pub keepalive: Option<Duration>,
+    /// the connection is closed if nothing is received for this long,
+    /// it should be longer than the echo interval of the switches
+    pub read_timeout: Option<Duration>,
+    /// the connection is closed if a write blocks for this long
+    pub write_timeout: Option<Duration>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        SocketOptions {
+            nodelay: true,
+            keepalive: None,
+            read_timeout: None,
+            write_timeout: None,
+        }
+    }
+}
+
+impl SocketOptions {
+    /// sets the options on the stream, they are shared with its clones
+    pub fn apply(&self, stream: &TcpStream) -> Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        stream.set_read_timeout(self.read_timeout)?;
+        stream.set_write_timeout(self.write_timeout)?;
+        let socket = SockRef::from(stream);
+        match self.keepalive {
+            Some(time) => socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?,
+            None => socket.set_keepalive(false)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn applies_options_to_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let options = SocketOptions {
+            nodelay: true,
+            keepalive: Some(Duration::from_secs(30)),
+            read_timeout: Some(Duration::from_secs(10)),
+            write_timeout: Some(Duration::from_secs(5)),
+        };
+        options.apply(&stream).unwrap();
+        assert!(stream.nodelay().unwrap());
+        assert_eq!(
+            stream.read_timeout().unwrap(),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            stream.write_timeout().unwrap(),
+            Some(Duration::from_secs(5))
+        );
+        let socket = SockRef::from(&stream);
+        assert!(socket.keepalive().unwrap());
+
+        SocketOptions::default().apply(&stream).unwrap();
+        assert_eq!(stream.read_timeout().unwrap(), None);
+        assert!(!SockRef::from(&stream).keepalive().unwrap());
+    }
+}
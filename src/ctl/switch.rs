@@ -1,195 +1,920 @@
+use byteorder::{BigEndian, ByteOrder};
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::any::Any;
+use std::collections::VecDeque;
 use std::convert::TryFrom;
-use std::io::{Read, Write};
-use std::net::{TcpStream, Shutdown};
-use std::sync::mpsc::{channel, Sender};
-use std::thread;
+use std::fs::File;
+use std::io;
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
 
 use super::super::ds;
+use super::super::ds::capture::{CaptureWriter, Direction};
+use super::super::ds::error_msg::{BadRequestCode, ErrorMsg, ErrorType, HelloFailedCode};
+use super::super::ds::flags::FlagsPolicy;
+use super::super::ds::multipart::{MultipartReply, MultipartRequest, RepPayload, ReqPayload};
+use super::super::ds::switch_config::SwitchConfig;
 use super::super::err::*;
+use super::accept::{ActiveConnection, DatapathDecision, DatapathPolicy};
+use super::audit::AuditLog;
+use super::config::ConnectCallback;
+use super::diagnostics::{self, BadFrame, BadFrames};
+use super::events::{self, ControllerEvent};
+use super::framing::FrameReader;
+use super::handle::SwitchHandle;
+use super::metrics::{ConnectionMetrics, Metrics};
+use super::output::OutputBuffer;
+use super::registry::{Registration, SwitchRegistry};
+use super::replies::ReplyRouter;
+use super::socket::SocketOptions;
+use super::tap::Tap;
+use super::transport::Transport;
+use super::version::NegotiatedVersion;
+use super::xid::XidAllocator;
 
 pub struct IncomingMsg {
     pub reply_ch: Sender<ds::OfMsg>,
     pub msg: ds::OfMsg,
+    /// switch the message came from, None until its features reply was received
+    pub switch: Option<Arc<SwitchHandle>>,
+    /// xids of the connection the message came from
+    /// use it for new requests, replies keep the xid of the request
+    pub xids: Arc<XidAllocator>,
+    /// version negotiated on the connection the message came from
+    /// messages sent through reply_ch are encoded with it
+    pub version: ds::Version,
+    /// address of the switch, IPv6 link local addresses include the scope id
+    pub peer: Option<SocketAddr>,
+    /// address of the controller the switch connected to (eg. which of several
+    /// listeners), or of its end of connections made by connect_to_switch
+    pub local: Option<SocketAddr>,
+    /// see ControllerConfig::event_ch, eg. to report panics of handler threads
+    pub event_ch: Option<Sender<ControllerEvent>>,
 }
 
-pub fn start_switch_connection(stream_in: TcpStream, ctl_ch: Sender<IncomingMsg>) -> Result<()> {
+/// capture writer shared between all connections
+pub type SharedCaptureWriter = Arc<Mutex<CaptureWriter<File>>>;
+
+/// Everything a switch connection shares with the rest of the controller.
+#[derive(Clone)]
+pub struct ConnectionContext {
+    /// incoming messages are sent to the handler through this channel
+    pub ctl_ch: Sender<IncomingMsg>,
+    pub capture: Option<SharedCaptureWriter>,
+    pub registry: Arc<SwitchRegistry>,
+    pub event_ch: Option<Sender<ControllerEvent>>,
+    pub on_connect: Option<ConnectCallback>,
+    /// see ControllerConfig::datapath_policy
+    pub datapath_policy: Option<DatapathPolicy>,
+    pub metrics: Arc<Metrics>,
+    /// see ControllerConfig::queue_warning_threshold
+    pub queue_warning_threshold: Option<usize>,
+    /// see ControllerConfig::max_version
+    pub max_version: ds::Version,
+    /// see ControllerConfig::socket_options
+    pub socket_options: SocketOptions,
+    /// see ControllerConfig::async_config
+    pub async_config: Option<ds::async::Async>,
+    /// see ControllerConfig::validate_messages
+    pub validate_messages: bool,
+    /// see ControllerConfig::bad_frames
+    pub bad_frames: Option<Arc<BadFrames>>,
+    /// see ControllerConfig::echo_interval
+    pub echo_interval: Option<Duration>,
+    /// see ControllerConfig::switch_config
+    pub switch_config: Option<SwitchConfig>,
+    /// see ControllerConfig::write_deadline
+    pub write_deadline: Option<Duration>,
+    /// see ControllerConfig::audit_log
+    pub audit_log: Option<Arc<AuditLog>>,
+    /// see ControllerConfig::flags_policy
+    pub flags_policy: FlagsPolicy,
+    /// see ControllerConfig::tap
+    pub tap: Option<Arc<Tap>>,
+}
+
+/// Registration of a connection that reports the disconnect when dropped,
+/// which also happens if the input thread panics.
+struct ConnectionState {
+    registration: Option<Registration>,
+    event_ch: Option<Sender<ControllerEvent>>,
+    peer: Option<SocketAddr>,
+    metrics: ConnectionMetrics,
+    validate_messages: bool,
+    bad_frames: Option<Arc<BadFrames>>,
+}
+
+impl ConnectionState {
+    #[allow(clippy::too_many_arguments)]
+    fn register(
+        &mut self,
+        registry: &Arc<SwitchRegistry>,
+        features: &ds::features::SwitchFeatures,
+        sender: Sender<ds::OfMsg>,
+        xids: &Arc<XidAllocator>,
+        version: &Arc<NegotiatedVersion>,
+        replies: &Arc<ReplyRouter>,
+        on_connect: &Option<ConnectCallback>,
+    ) {
+        if self.registration.is_some() {
+            return;
+        }
+        match SwitchRegistry::register(
+            registry,
+            features,
+            sender,
+            xids.clone(),
+            version.clone(),
+            replies.clone(),
+            self.metrics.clone(),
+        ) {
+            Ok(registration) => {
+                info!(
+                    "Registered datapath {:#x} auxiliary {}.",
+                    features.datapath_id, features.auxiliary_id
+                );
+                events::emit(
+                    &self.event_ch,
+                    ControllerEvent::SwitchConnected {
+                        datapath_id: features.datapath_id,
+                        auxiliary_id: features.auxiliary_id,
+                        peer: self.peer,
+                    },
+                );
+                self.metrics.set_datapath_id(features.datapath_id);
+                if features.auxiliary_id == 0 {
+                    registration.handle().set_validation(self.validate_messages);
+                    self.metrics.metrics().switch_connected(features.datapath_id);
+                    if let Some(ref on_connect) = *on_connect {
+                        on_connect(registration.handle());
+                    }
+                }
+                self.registration = Some(registration);
+            }
+            Err(err) => warn!("{}", err),
+        }
+    }
+
+    fn handle(&self) -> Option<Arc<SwitchHandle>> {
+        self.registration.as_ref().map(|r| r.handle().clone())
+    }
+
+    /// keeps a frame that could not be decoded if bad frames are collected
+    fn record_bad_frame(&self, err: &Error, frame: &[u8]) {
+        if let Some(ref bad_frames) = self.bad_frames {
+            error!(
+                "Frame that could not be decoded:\n{}",
+                diagnostics::hexdump(frame)
+            );
+            bad_frames.record(BadFrame {
+                received: SystemTime::now(),
+                datapath_id: self.metrics.datapath_id(),
+                peer: self.peer,
+                error: err.to_string(),
+                context: err.context().clone(),
+                bytes: frame.to_vec(),
+            });
+        }
+    }
+
+    /// answers a message that was not understood with the error and tells the
+    /// application about it
+    fn reject(&self, send: &Sender<ds::OfMsg>, xid: u32, error: ErrorMsg) {
+        events::emit(
+            &self.event_ch,
+            ControllerEvent::MessageRejected {
+                datapath_id: self.metrics.datapath_id(),
+                peer: self.peer,
+                xid: xid,
+                error: error.clone(),
+            },
+        );
+        let _ = send.send(ds::OfMsg::generate(xid, ds::OfPayload::Error(error)));
+    }
+}
+
+/// a hello of a version newer than the controller knows, its header can not be decoded
+fn is_newer_hello(header: &[u8]) -> bool {
+    header[0] > ds::MAX_VERSION.to_u8().unwrap() && header[1] == ds::Type::Hello.to_u8().unwrap()
+}
+
+/// error reply to a message whose header could not be decoded
+fn header_error(header: &[u8]) -> ErrorMsg {
+    let known_version = ds::Version::from_u8(header[0]).is_some();
+    match (known_version, header[1] == ds::Type::Hello.to_u8().unwrap()) {
+        // newer hellos are negotiated, this one is older than every known version
+        (false, true) => ErrorMsg::hello_failed(HelloFailedCode::Incompatible, header),
+        (false, false) => ErrorMsg::bad_request(BadRequestCode::BadVersion, header),
+        (true, _) => ErrorMsg::bad_request(BadRequestCode::BadType, header),
+    }
+}
+
+/// the type of the message is known but not decoded if a switch sends it
+fn is_unsupported_type(err: &Error) -> bool {
+    match *err.kind() {
+        ErrorKind::UnsupportedValue(_, ttype) => ttype == stringify!(Type),
+        _ => false,
+    }
+}
+
+impl Drop for ConnectionState {
+    fn drop(&mut self) {
+        if let Some(ref registration) = self.registration {
+            let handle = registration.handle();
+            let auxiliary_id = registration.auxiliary_id();
+            warn!(
+                "Datapath {:#x} auxiliary {} disconnected.",
+                handle.datapath_id(),
+                auxiliary_id
+            );
+            events::emit(
+                &self.event_ch,
+                ControllerEvent::SwitchDisconnected {
+                    datapath_id: handle.datapath_id(),
+                    auxiliary_id: auxiliary_id,
+                    peer: self.peer,
+                },
+            );
+            if auxiliary_id == 0 {
+                self.metrics.metrics().switch_disconnected(handle.datapath_id());
+            }
+        }
+    }
+}
+
+/// starts the input and output threads of a connection
+/// returns the input thread, it ends when the connection is closed
+pub fn start_switch_connection<T: Transport>(
+    stream_in: T,
+    context: ConnectionContext,
+    active: Option<ActiveConnection>,
+) -> Result<JoinHandle<()>> {
+    // the clone shares the options of the socket
+    stream_in.apply(&context.socket_options)?;
     let stream_out = stream_in.try_clone()?;
     let (send, recv) = channel::<ds::OfMsg>();
+    let peer_addr = stream_in.peer_addr();
+    let local_addr = stream_in.local_addr();
+    // peer address or socket path for logging
+    let name = stream_in.name();
+    let name_out = name.clone();
+    let ctl_ch = context.ctl_ch;
+    let registry = context.registry;
+    let on_connect = context.on_connect;
+    let datapath_policy = context.datapath_policy;
+    let async_config = context.async_config;
+    let validate_messages = context.validate_messages;
+    let bad_frames = context.bad_frames;
+    let event_ch = context.event_ch;
+    let event_ch_out = event_ch.clone();
+    let queue_warning_threshold = context.queue_warning_threshold;
+    let echo_interval = context.echo_interval;
+    let switch_config = context.switch_config;
+    let write_deadline = context.write_deadline;
+    let write_timeout = context.socket_options.write_timeout;
+    let flags_policy = context.flags_policy;
+    let audit_in = context.audit_log.clone();
+    let audit_out = context.audit_log;
+    let xids = Arc::new(XidAllocator::new());
+    let xids_out = xids.clone();
+    let version_in = Arc::new(NegotiatedVersion::with_max(context.max_version));
+    let version_out = version_in.clone();
+    let replies = Arc::new(ReplyRouter::new());
+    let capture_in = context.capture.clone();
+    let capture_out = context.capture;
+    let tap_in = context.tap.clone();
+    let tap_out = context.tap;
+    let metrics_in = Metrics::connection(&context.metrics);
+    let metrics_out = metrics_in.clone();
 
     // start switch input thread
-    info!("Starting input thread for: {:?}.", stream_in.peer_addr());
-    thread::Builder::new()
-        .name(format!("Switch-In {:?}", stream_in.peer_addr()).to_string())
+    info!("Starting input thread for: {}.", name);
+    let input_thread = thread::Builder::new()
+        .name(format!("Switch-In {}", name))
         .spawn(move || {
-            let mut stream_in = stream_in;
-            loop {
-                // read input header + log
-                let header_bytes = read_bytes(&mut stream_in, ds::HEADER_LENGTH)
-                    .expect("could not read header bytes");
-
-                // check if connection was closed
-                if header_bytes == None {
-                    return;
-                }
-                // else unwrap them
-                let header_bytes = header_bytes.unwrap();
+            let mut reader = FrameReader::new(stream_in);
+            // reported with the datapath id and closed if the thread panics
+            let metrics_panic = metrics_in.clone();
+            let event_ch_panic = event_ch.clone();
+            let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+                // counts as open connection until this thread ends
+                let _active = active;
+                // registered in the registry until this thread ends
+                let mut connection = ConnectionState {
+                    registration: None,
+                    event_ch: event_ch,
+                    peer: peer_addr,
+                    metrics: metrics_in,
+                    validate_messages: validate_messages,
+                    bad_frames: bad_frames,
+                };
+                // xid of the description request sent after registering
+                let mut desc_xid = None;
+                // xid of the port description request sent after registering
+                let mut port_desc_xid = None;
+                // xids of the group and meter features requests sent after registering
+                let mut group_features_xid = None;
+                let mut meter_features_xid = None;
+                // xid of the config request sent after registering if a config is desired
+                let mut config_xid = None;
+                // the version of later messages is checked once it is negotiated
+                let mut hello_received = false;
+                loop {
+                    // read the next message, several messages are read at once if available
+                    let frame = match reader.next_frame() {
+                        Ok(Some(frame)) => frame,
+                        Ok(None) => {
+                            info!("closed {}", name);
+                            let _ = reader.get_ref().shutdown();
+                            return;
+                        }
+                        Err(err) => {
+                            error!("Could not read from {}: {}", name, err);
+                            let _ = reader.get_ref().shutdown();
+                            return;
+                        }
+                    };
+                    connection.metrics.record_bytes_in(frame.len());
+                    let (header_bytes, payload_bytes) = frame.split_at(ds::HEADER_LENGTH);
 
-                let header = ds::Header::try_from(&header_bytes[..])
-                    .expect("could not convert header bytes to actual header");
-                info!("Read OfHeader: {:?}.", header);
+                    // the frame is skipped, its length was valid so the next one can still be read
+                    let header = match ds::Header::try_from(header_bytes) {
+                        Ok(header) => header,
+                        // both sides use the highest version the controller supports
+                        Err(_) if is_newer_hello(header_bytes) => {
+                            let version = version_in.negotiate(ds::MAX_VERSION);
+                            info!("Negotiated version {:?} with {}.", version, name);
+                            hello_received = true;
+                            continue;
+                        }
+                        Err(err) => {
+                            let err = err.excerpt_from(header_bytes);
+                            error!("Could not decode header from {}: {}", name, err);
+                            connection.metrics.record_decode_error();
+                            connection.record_bad_frame(&err, header_bytes);
+                            let error = header_error(header_bytes);
+                            let hello_failed = error.error_type() == Some(ErrorType::HelloFailed);
+                            connection.reject(
+                                &send,
+                                BigEndian::read_u32(&header_bytes[4..]),
+                                error,
+                            );
+                            if hello_failed {
+                                let _ = reader.get_ref().shutdown();
+                                return;
+                            }
+                            continue;
+                        }
+                    };
+                    info!("Read OfHeader: {:?}.", header);
+                    connection.metrics.record_received(header.ttype());
 
-                // read input payload + log
-                let payload_bytes = read_bytes(&mut stream_in, *&header.payload_length() as usize)
-                    .expect("could not read payload bytes");
-                info!("Read Payload Bytes");
+                    // the hello of the switch decides the version of the connection,
+                    // all later messages have to use it
+                    if *header.ttype() == ds::Type::Hello {
+                        let version = version_in.negotiate(*header.version());
+                        info!("Negotiated version {:?} with {}.", version, name);
+                        hello_received = true;
+                    } else if hello_received && *header.version() != version_in.get() {
+                        warn!(
+                            "{:?} from {} uses {:?} instead of {:?}.",
+                            header.ttype(),
+                            name,
+                            header.version(),
+                            version_in.get()
+                        );
+                        let error = ErrorMsg::bad_request(BadRequestCode::BadVersion, frame);
+                        connection.reject(&send, *header.xid(), error);
+                        continue;
+                    }
 
-                // check if connection was closed
-                if payload_bytes == None {
-                    return;
-                }
-                //else unwrap them
-                let payload_bytes = &payload_bytes.unwrap()[..];
-
-                let payload = match &header.ttype() {
-                    ds::Type::Hello => Some(ds::OfPayload::Hello),
-                    ds::Type::Error => Some(ds::OfPayload::Error),
-                    ds::Type::EchoRequest => Some(ds::OfPayload::EchoRequest),
-                    // these should be automatic later, eg.: ds::packet_in::PacketIn::try_from(payload_bytes)?.into(),
-                    ds::Type::Experimenter => {
-                        error!("No experimenter support (yet?)");
-                        None
-                    }
-                    ds::Type::FeaturesReply => Some(ds::OfPayload::FeaturesReply(
-                        ds::features::SwitchFeatures::try_from(&payload_bytes[..])
-                            .expect("error while try_from SwitchFeatures"),
-                    )),
-                    ds::Type::GetConfigReply => Some(ds::OfPayload::GetConfigReply(
-                        ds::switch_config::SwitchConfig::try_from(&payload_bytes[..])
-                            .expect("error while try_from SwitchConfig"),
-                    )),
-                    ds::Type::PacketIn => Some(ds::OfPayload::PacketIn(
-                        ds::packet_in::PacketIn::try_from(&payload_bytes[..])
-                            .expect("error while try_from PacketIn"),
-                    )),
-                    ds::Type::FlowRemoved => Some(ds::OfPayload::FlowRemoved(
-                        ds::flow_removed::FlowRemoved::try_from(&payload_bytes[..])
-                            .expect("error while try_from FlowRemoved"),
-                    )),
-                    ds::Type::PortStatus => Some(ds::OfPayload::PortStatus(
-                        ds::port_status::PortStatus::try_from(&payload_bytes[..])
-                            .expect("error while try_from PortStatus"),
-                    )),
-                    ds::Type::MultipartReply => {
-                        error!("No MultipartReply support (yet?)");
-                        None
-                    }
-                    ds::Type::BarrierReply => Some(ds::OfPayload::BarrierReply),
-                    ds::Type::QueueGetConfigReply => Some(ds::OfPayload::QueueGetConfigReply(
-                        ds::queue_config::QueueGetConfigReply::try_from(&payload_bytes[..])
-                            .expect("error while try_from QueueGetConfigReply"),
-                    )),
-                    ds::Type::RoleReply => Some(ds::OfPayload::RoleReply(
-                        ds::role::Role::try_from(&payload_bytes[..])
-                            .expect("error while try_from Role"),
-                    )),
-                    ds::Type::GetAsyncReply => Some(ds::OfPayload::GetAsyncReply(
-                        ds::async::Async::try_from(&payload_bytes[..])
-                            .expect("error while try_from Async"),
-                    )),
-                    _ => {
-                        error!("received not allowed ofmsg type {:?}", header.ttype());
-                        None
+                    // record raw message if capturing is enabled
+                    if let Some(ref capture) = capture_in {
+                        record(capture, Direction::Incoming, peer_addr, frame);
+                    }
+
+                    // replies whose waiter decodes them itself (eg. the parts of a large
+                    // flow stats reply) are passed on before they are decoded
+                    if replies.route_frame(frame) {
+                        if let Some(ref tap) = tap_in {
+                            let datapath_id = connection.metrics.datapath_id();
+                            tap.publish(Direction::Incoming, datapath_id, peer_addr, frame);
+                        }
+                        continue;
                     }
-                };
-                info!("Read Payload: {:?}.", payload);
 
-                // if the payload is supported
-                match payload {
-                    Some(payload) => {
+                    let (decoded, unknown_flags) = ds::OfPayload::decode_with(
+                        flags_policy,
+                        *header.version(),
+                        header.ttype(),
+                        payload_bytes,
+                    );
+                    for unknown in unknown_flags {
+                        warn!(
+                            "Dropped unknown bits {:#x} of {} in {:?} from {}.",
+                            unknown.bits,
+                            unknown.ttype,
+                            header.ttype(),
+                            name
+                        );
+                        events::emit(
+                            &connection.event_ch,
+                            ControllerEvent::UnknownFlags {
+                                datapath_id: connection.metrics.datapath_id(),
+                                peer: peer_addr,
+                                ttype: header.ttype().clone(),
+                                flags: unknown.ttype,
+                                bits: unknown.bits,
+                            },
+                        );
+                    }
+                    let payload = match decoded {
+                        Ok(payload) => Some(payload),
+                        // a message only the controller may send
+                        Err(ref err) if is_unsupported_type(err) => {
+                            warn!("{:?} from {} is not supported.", header.ttype(), name);
+                            let error = ErrorMsg::bad_request(BadRequestCode::BadType, frame);
+                            connection.reject(&send, *header.xid(), error);
+                            continue;
+                        }
+                        Err(err) => {
+                            let err = err
+                                .in_message(header.ttype().clone(), Some(*header.xid()))
+                                .excerpt_from(payload_bytes);
+                            error!("Could not decode payload: {}", err);
+                            connection.metrics.record_decode_error();
+                            connection.record_bad_frame(&err, frame);
+                            None
+                        }
+                    };
+                    info!("Read Payload: {:?}.", payload);
+                    if let (Some(ref tap), Some(_)) = (&tap_in, &payload) {
+                        let datapath_id = connection.metrics.datapath_id();
+                        tap.publish(Direction::Incoming, datapath_id, peer_addr, frame);
+                    }
+
+                    // the features reply tells which datapath this connection belongs to
+                    if let Some(ds::OfPayload::FeaturesReply(ref features)) = payload {
+                        if connection.registration.is_none() {
+                            let decision = match datapath_policy {
+                                Some(ref policy) => policy(features.datapath_id, features),
+                                None => DatapathDecision::Accept,
+                            };
+                            match decision {
+                                DatapathDecision::Accept => (),
+                                DatapathDecision::AcceptWithRole {
+                                    role,
+                                    generation_id,
+                                } => {
+                                    // sent before on_connect programs any flows
+                                    let role = ds::role::Role {
+                                        role: role,
+                                        generation_id: generation_id,
+                                    };
+                                    let request = ds::OfMsg::generate(
+                                        xids.next(),
+                                        ds::OfPayload::RoleRequest(role),
+                                    );
+                                    let _ = send.send(request);
+                                }
+                                DatapathDecision::Reject => {
+                                    warn!(
+                                        "Rejected datapath {:#x} from {}.",
+                                        features.datapath_id, name
+                                    );
+                                    events::emit(
+                                        &connection.event_ch,
+                                        ControllerEvent::SwitchRejected {
+                                            datapath_id: features.datapath_id,
+                                            peer: peer_addr,
+                                        },
+                                    );
+                                    let _ = reader.get_ref().shutdown();
+                                    return;
+                                }
+                            }
+                            // the config is per connection, auxiliary connections never
+                            // receive asynchronous messages
+                            if let Some(ref config) = async_config {
+                                let set_async = ds::OfPayload::SetAsync(config.clone());
+                                let version = version_in.get();
+                                if features.auxiliary_id == 0 && set_async.available_in(version) {
+                                    let _ = send.send(ds::OfMsg::generate(xids.next(), set_async));
+                                }
+                            }
+                        }
+                        connection.register(
+                            &registry,
+                            features,
+                            send.clone(),
+                            &xids,
+                            &version_in,
+                            &replies,
+                            &on_connect,
+                        );
+                        // multipart messages exist since 1.3
+                        let main = features.auxiliary_id == 0 && connection.registration.is_some();
+                        if main && desc_xid.is_none() && version_in.get() >= ds::Version::V1_3 {
+                            desc_xid = request_multipart(&send, &xids, ReqPayload::Desc);
+                            port_desc_xid = request_multipart(&send, &xids, ReqPayload::PortDesc);
+                            group_features_xid =
+                                request_multipart(&send, &xids, ReqPayload::GroupFeatures);
+                            meter_features_xid =
+                                request_multipart(&send, &xids, ReqPayload::MeterFeatures);
+                        }
+                        if main && config_xid.is_none() && switch_config.is_some() {
+                            let xid = xids.next();
+                            let request = ds::OfMsg::generate(xid, ds::OfPayload::GetConfigRequest);
+                            config_xid = send.send(request).ok().map(|_| xid);
+                        }
+                    }
+
+                    // the config of the switch is replaced if it differs from the desired one
+                    if config_xid == Some(*header.xid()) {
+                        match payload {
+                            Some(ds::OfPayload::GetConfigReply(ref current)) => match switch_config
+                            {
+                                Some(ref desired) if current != desired => {
+                                    info!("Changing config of {} from {:?}.", name, current);
+                                    let set_config = ds::OfPayload::SetConfig(desired.clone());
+                                    let _ = send.send(ds::OfMsg::generate(xids.next(), set_config));
+                                }
+                                _ => (),
+                            },
+                            ref other => warn!("Config request failed: {:?}", other),
+                        }
+                        continue;
+                    }
+
+                    // the reply to the description request is cached on the switch handle
+                    if desc_xid == Some(*header.xid()) {
+                        match payload {
+                            Some(ds::OfPayload::MultipartReply(MultipartReply {
+                                payload: RepPayload::Desc(ref desc),
+                                ..
+                            })) => {
+                                info!("Description of {}: {:?}.", name, desc);
+                                if let Some(handle) = connection.handle() {
+                                    handle.set_description(desc.clone());
+                                }
+                            }
+                            ref other => warn!("Description request failed: {:?}", other),
+                        }
+                        continue;
+                    }
+
+                    // the ports are cached on the switch handle, the reply may be split
+                    if port_desc_xid == Some(*header.xid()) {
+                        match payload {
+                            Some(ds::OfPayload::MultipartReply(MultipartReply {
+                                payload: RepPayload::PortDesc(ref ports),
+                                ..
+                            })) => {
+                                info!("Ports of {}: {:?}.", name, ports);
+                                if let Some(handle) = connection.handle() {
+                                    handle.insert_ports(ports.clone());
+                                }
+                            }
+                            ref other => warn!("Port description request failed: {:?}", other),
+                        }
+                        continue;
+                    }
+
+                    // switches without groups or meters answer with an error, their
+                    // group and meter mods are not checked then
+                    if group_features_xid == Some(*header.xid()) {
+                        match payload {
+                            Some(ds::OfPayload::MultipartReply(MultipartReply {
+                                payload: RepPayload::GroupFeatures(ref features),
+                                ..
+                            })) => {
+                                info!("Group features of {}: {:?}.", name, features);
+                                if let Some(handle) = connection.handle() {
+                                    handle.set_group_features(features.clone());
+                                }
+                            }
+                            ref other => info!("No group features from {}: {:?}", name, other),
+                        }
+                        continue;
+                    }
+                    if meter_features_xid == Some(*header.xid()) {
+                        match payload {
+                            Some(ds::OfPayload::MultipartReply(MultipartReply {
+                                payload: RepPayload::MeterFeatures(ref features),
+                                ..
+                            })) => {
+                                info!("Meter features of {}: {:?}.", name, features);
+                                if let Some(handle) = connection.handle() {
+                                    handle.set_meter_features(features.clone());
+                                }
+                            }
+                            ref other => info!("No meter features from {}: {:?}", name, other),
+                        }
+                        continue;
+                    }
+
+                    // the error is also passed to the handler
+                    if let Some(ds::OfPayload::Error(ref error)) = payload {
+                        if let Some(ref audit) = audit_in {
+                            audit.record_error(
+                                connection.metrics.datapath_id(),
+                                *header.xid(),
+                                error,
+                            );
+                        }
+                    }
+
+                    // replies to the echo requests of the output thread only measure
+                    // the round trip time, they do not reach the handler
+                    if let Some(ds::OfPayload::EchoReply(ref data)) = payload {
+                        if let Some(rtt) = connection.metrics.record_echo_reply(data) {
+                            info!("Echo round trip time of {}: {:?}.", name, rtt);
+                            continue;
+                        }
+                    }
+
+                    // port changes update the port table before the handler sees them
+                    if let Some(ds::OfPayload::PortStatus(ref status)) = payload {
+                        if let Some(handle) = connection.handle() {
+                            handle.update_port(status.reason(), status.desc());
+                            events::emit(
+                                &connection.event_ch,
+                                ControllerEvent::PortChanged {
+                                    datapath_id: handle.datapath_id(),
+                                    reason: status.reason().clone(),
+                                    port: status.desc().clone(),
+                                },
+                            );
+                        }
+                    }
+
+                    // role replies update the role of the handle before the handler sees them
+                    if let Some(ds::OfPayload::RoleReply(ref role)) = payload {
+                        if let Some(handle) = connection.handle() {
+                            info!("Role of {}: {:?}.", name, role);
+                            handle.set_role(role.clone());
+                        }
+                    }
+
+                    // if the payload is supported
+                    if let Some(payload) = payload {
+                        // replies somebody waits for do not reach the handler
+                        let msg = match replies.route(ds::OfMsg::new(header, payload)) {
+                            Some(msg) => msg,
+                            None => continue,
+                        };
                         // send channel message (with sender channel in message)
-                        ctl_ch
-                            .send(IncomingMsg {
-                                reply_ch: send.clone(),
-                                msg: ds::OfMsg::new(header, payload),
-                            })
-                            .expect("error while sending msg via channel to controller");
+                        let incoming = IncomingMsg {
+                            reply_ch: send.clone(),
+                            msg: msg,
+                            switch: connection.handle(),
+                            xids: xids.clone(),
+                            version: version_in.get(),
+                            peer: peer_addr,
+                            local: local_addr,
+                            event_ch: connection.event_ch.clone(),
+                        };
+                        if ctl_ch.send(incoming).is_err() {
+                            error!("The handler is gone, closing {}.", name);
+                            let _ = reader.get_ref().shutdown();
+                            return;
+                        }
                     }
-                    _ => (),
                 }
-            }
+            }));
+            report_panic(
+                panicked,
+                reader.get_ref(),
+                metrics_panic.datapath_id(),
+                peer_addr,
+                &event_ch_panic,
+            );
         })?;
 
     // start switch output thread
-    info!("Starting output thread for: {:?}.", stream_out.peer_addr());
+    info!("Starting output thread for: {}.", name_out);
     thread::Builder::new()
-        .name(format!("Switch-In {:?}", stream_out.peer_addr()).to_string())
+        .name(format!("Switch-Out {}", name_out))
         .spawn(move || {
             let mut stream_out = stream_out;
-            loop {
-                // wait for a message to send from controller
-                match recv.recv() {
-                    Ok(of_msg) => {
-                        // send message to switch
-                        info!("Sending {:?} to: {:?}.", of_msg, stream_out.peer_addr());
-                        let write_slice = &Into::<Vec<u8>>::into(of_msg)[..];
-                        stream_out
-                            .write_all(write_slice)
-                            .expect("could not write bytes to stream");
-                    }
-                    Err(err) => panic!("Connection was closed! {}", err),
+            let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut output = OutputBuffer::default();
+                // messages taken from the channel that were not written yet
+                let mut pending = VecDeque::new();
+                // warned about the queue depth until it goes below the threshold again
+                let mut slow = false;
+                let mut last_echo = Instant::now();
+                // writes the buffered messages, if that fails (eg. the write deadline
+                // passed) the connection is closed, which also ends the input thread
+                let flush = |output: &mut OutputBuffer, stream: &mut T| {
+                    let written = match write_deadline {
+                        Some(deadline) => output.flush_within(stream, deadline, write_timeout),
+                        None => output.flush_to(stream),
+                    };
+                    let err = match written {
+                        Ok(_) => return true,
+                        Err(err) => err,
+                    };
+                    error!("Could not write to {}: {}", name_out, err);
+                    match err.kind() {
+                        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => events::emit(
+                            &event_ch_out,
+                            ControllerEvent::WriteStalled {
+                                datapath_id: metrics_out.datapath_id(),
+                                peer: peer_addr,
+                                pending: output.len(),
+                            },
+                        ),
+                        _ => (),
+                    }
+                    let _ = stream.shutdown();
+                    false
+                };
+                loop {
+                    // wait for a message to send from controller, or until the next
+                    // echo request is due, unless messages are still pending
+                    let received = match echo_interval {
+                        _ if !pending.is_empty() => Err(RecvTimeoutError::Timeout),
+                        Some(interval) => {
+                            let due = interval.checked_sub(last_echo.elapsed());
+                            recv.recv_timeout(due.unwrap_or_default())
+                        }
+                        None => recv.recv().map_err(RecvTimeoutError::from),
+                    };
+                    let of_msg = match received {
+                        Ok(of_msg) => Some(of_msg),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(err) => {
+                            info!("Connection to {} was closed: {}", name_out, err);
+                            return;
+                        }
+                    };
+                    let echo = match echo_interval {
+                        Some(interval) if last_echo.elapsed() >= interval => {
+                            last_echo = Instant::now();
+                            // only sent once the switch is registered
+                            metrics_out.datapath_id().map(|_| {
+                                let echo = ds::OfPayload::EchoRequest(metrics_out.echo_payload());
+                                ds::OfMsg::generate(xids_out.next(), echo)
+                            })
+                        }
+                        _ => None,
+                    };
+                    // messages that are already queued are written together, at most
+                    // until the buffer is full, the rest stays pending and is written
+                    // with the next flush
+                    pending.extend(of_msg.into_iter().chain(echo).chain(recv.try_iter()));
+                    let queued = pending.len();
+                    if queued == 0 {
+                        continue;
+                    }
+                    metrics_out.record_queue_depth(queued);
+                    if let Some(threshold) = queue_warning_threshold {
+                        let exceeded = queued > threshold;
+                        if exceeded && !slow {
+                            warn!("{} messages waiting to be sent to {}.", queued, name_out);
+                            events::emit(
+                                &event_ch_out,
+                                ControllerEvent::SlowConsumer {
+                                    datapath_id: metrics_out.datapath_id(),
+                                    peer: peer_addr,
+                                    queue_depth: queued,
+                                },
+                            );
+                        }
+                        slow = exceeded;
+                    }
+                    while let Some(of_msg) = pending.pop_front() {
+                        // encode with the negotiated version
+                        let of_msg = match of_msg.with_version(version_out.get()) {
+                            Ok(of_msg) => of_msg,
+                            Err(err) => {
+                                error!(
+                                    "Dropping message for {}, not available in {:?}: {}",
+                                    name_out,
+                                    version_out.get(),
+                                    err
+                                );
+                                continue;
+                            }
+                        };
+                        info!("Sending {:?} to: {}.", of_msg, name_out);
+                        let ttype = of_msg.header().ttype().clone();
+                        let bytes = match of_msg.encode() {
+                            Ok(bytes) => bytes,
+                            Err(err) => {
+                                error!("Dropping {:?} for {}: {}", ttype, name_out, err);
+                                continue;
+                            }
+                        };
+                        if let Some(ref audit) = audit_out {
+                            let xid = *of_msg.header().xid();
+                            audit.record(metrics_out.datapath_id(), xid, of_msg.payload());
+                        }
+                        metrics_out.record_sent(&ttype);
+                        metrics_out.record_bytes_out(bytes.len());
+                        if let Some(ref capture) = capture_out {
+                            record(capture, Direction::Outgoing, peer_addr, &bytes[..]);
+                        }
+                        if let Some(ref tap) = tap_out {
+                            let datapath_id = metrics_out.datapath_id();
+                            tap.publish(Direction::Outgoing, datapath_id, peer_addr, &bytes[..]);
+                        }
+                        output.push(&bytes[..]);
+                        if output.is_full() {
+                            break;
+                        }
+                    }
+                    // send the collected messages to the switch
+                    if !flush(&mut output, &mut stream_out) {
+                        return;
+                    }
+                    metrics_out.record_queue_depth(pending.len());
                 }
-            }
+            }));
+            report_panic(
+                panicked,
+                &stream_out,
+                metrics_out.datapath_id(),
+                peer_addr,
+                &event_ch_out,
+            );
         })?;
 
     // function successfull
-    Ok(())
+    Ok(input_thread)
 }
 
-// maybe make this modifiable from outside?
-pub const READ_BUFFER_SIZE: usize = 128;
-
-/// used to read exact number of bytes from stream including any zero bytes
-fn read_bytes(stream: &mut TcpStream, len: usize) -> Result<Option<Vec<u8>>> {
-    let mut res = Vec::new();
-    let mut buffer = [0u8; READ_BUFFER_SIZE];
-    let mut read: usize = 0;
-    while read < len {
-        let bytes_to_read: usize = ::std::cmp::min(len - read, READ_BUFFER_SIZE);
-        let mut buf_slice = &mut buffer[0..bytes_to_read];
-        match read_exact(stream, &mut buf_slice).expect("could not read bytes from stream") {
-            StreamState::Closed => return Ok(None),//indicate that connection is closed -> nothing to read
-            StreamState::Open => (),
-        }
-        read += bytes_to_read;
-        res.extend_from_slice(buf_slice);
+/// the message of a panic, if it has one
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "unknown panic".to_string(),
+        },
     }
-    Ok(Some(res))
 }
 
-/// used inside read_bytes to fill a slice from stream input data including any zero bytes
-fn read_exact(
-    reader: &mut TcpStream,
-    mut buf: &mut [u8],
-) -> ::std::result::Result<StreamState, ::std::io::Error> {
-    while !buf.is_empty() {
-        match reader.read(buf) {
-            Ok(n) => {
-                // check if connection was closed
-                if n == 0 {
-                    info!("closed {:?}", reader.peer_addr());
-                    reader.shutdown(Shutdown::Both).expect("error while closing stream");
-                    return Ok(StreamState::Closed);
-                }
-                let tmp = buf;
-                buf = &mut tmp[n..];
-            }
-            Err(e) => return Err(e),
-        }
+/// reports a panic of a connection thread as ConnectionPanicked and closes the
+/// connection, so the other thread of the connection ends too
+fn report_panic<T: Transport>(
+    result: ::std::result::Result<(), Box<dyn Any + Send>>,
+    stream: &T,
+    datapath_id: Option<u64>,
+    peer: Option<SocketAddr>,
+    event_ch: &Option<Sender<ControllerEvent>>,
+) {
+    let payload = match result {
+        Ok(()) => return,
+        Err(payload) => payload,
+    };
+    let message = panic_message(&*payload);
+    let thread = thread::current().name().unwrap_or("unnamed").to_string();
+    error!("Thread {} panicked: {}", thread, message);
+    let _ = stream.shutdown();
+    events::emit(
+        event_ch,
+        ControllerEvent::ConnectionPanicked {
+            datapath_id: datapath_id,
+            peer: peer,
+            thread: thread,
+            message: message,
+        },
+    );
+}
+
+/// sends a multipart request with a new xid, returns None if the connection is gone
+fn request_multipart(
+    send: &Sender<ds::OfMsg>,
+    xids: &XidAllocator,
+    payload: ReqPayload,
+) -> Option<u32> {
+    let xid = xids.next();
+    let request = ds::OfPayload::MultipartRequest(MultipartRequest::new(payload));
+    match send.send(ds::OfMsg::generate(xid, request)) {
+        Ok(_) => Some(xid),
+        Err(_) => None,
     }
-    Ok(StreamState::Open)
 }
 
-enum StreamState {
-    Closed,
-    Open,
+/// records a raw message, failing to record is logged but does not affect the connection
+fn record(
+    capture: &SharedCaptureWriter,
+    direction: Direction,
+    peer: Option<SocketAddr>,
+    bytes: &[u8],
+) {
+    match capture.lock() {
+        Ok(mut writer) => {
+            if let Err(err) = writer.write_raw(direction, peer, bytes) {
+                error!("Could not record message: {}", err);
+            }
+        }
+        Err(err) => error!("Capture writer is poisoned: {}", err),
+    }
 }
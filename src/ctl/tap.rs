@@ -0,0 +1,424 @@
+//! Live copies of the messages of all switches for external tools.
+//!
+//! With `ControllerConfig::tap` set, every message the controller decoded from
+//! a switch and every message it sent to one is copied to the subscribers of
+//! the tap, so dashboards and debuggers can watch a running controller without
+//! changes to the handler. Subscribers are channels of `TapEvent`s and Unix
+//! domain sockets, which receive the messages in the dump format of
+//! `ds::capture` (read them with `CaptureReader::from_dump`):
+//!
+//! ```no_run
+//! use oath2::ctl::tap::Tap;
+//! use oath2::ctl::ControllerConfig;
+//! use std::sync::Arc;
+//!
+//! let tap = Arc::new(Tap::new());
+//! let events = tap.subscribe();
+//! // eg. `socat UNIX-CONNECT:/run/oath2-tap.sock - | ...`
+//! tap.listen_unix("/run/oath2-tap.sock").unwrap();
+//! let mut config = ControllerConfig::default();
+//! config.tap = Some(tap);
+//! // start the controller, then
+//! for event in events {
+//!     println!("{}", event);
+//! }
+//! ```
+//!
+//! The connections only queue the messages, a thread of the tap copies them to
+//! the subscribers, so a slow subscriber never blocks a switch. Messages are
+//! dropped while `TAP_QUEUE_LENGTH` messages wait for the thread, and for a
+//! channel subscriber that has `TAP_QUEUE_LENGTH` events it did not receive
+//! yet. Subscribers that went away (dropped receivers, closed sockets or
+//! sockets that stopped reading for `SOCKET_WRITE_TIMEOUT`) are removed.
+//! Messages are only copied while the tap has subscribers.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+#[cfg(unix)]
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::super::ds;
+use super::super::ds::capture::{CaptureWriter, Direction};
+use super::super::err::*;
+#[cfg(unix)]
+use super::listener;
+
+/// Time a socket subscriber may block the tap thread writing to it before it is removed.
+pub const SOCKET_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+/// Messages that may wait for the tap thread and events that may wait for a
+/// channel subscriber before further ones are dropped.
+pub const TAP_QUEUE_LENGTH: usize = 1024;
+
+/// A message of a switch or to a switch, as copied by the tap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TapEvent {
+    /// time the message was read or sent (since unix epoch)
+    pub timestamp: Duration,
+    pub direction: Direction,
+    /// None if the switch did not send its features reply yet
+    pub datapath_id: Option<u64>,
+    pub peer: Option<SocketAddr>,
+    pub ttype: ds::Type,
+    pub xid: u32,
+    /// the raw message, header included
+    pub bytes: Vec<u8>,
+}
+
+impl TapEvent {
+    /// the event of a raw message with the current time
+    pub fn new(
+        direction: Direction,
+        datapath_id: Option<u64>,
+        peer: Option<SocketAddr>,
+        bytes: &[u8],
+    ) -> Result<Self> {
+        let header = header(bytes)?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0));
+        Ok(TapEvent {
+            timestamp: timestamp,
+            direction: direction,
+            datapath_id: datapath_id,
+            peer: peer,
+            ttype: header.ttype().clone(),
+            xid: *header.xid(),
+            bytes: bytes.to_vec(),
+        })
+    }
+
+    /// decodes the message, messages of both directions can be decoded
+    pub fn msg(&self) -> Result<ds::OfMsg> {
+        let header = header(&self.bytes[..])?;
+        let payload_bytes = &self.bytes[ds::HEADER_LENGTH..];
+        let payload = ds::decode_any(*header.version(), header.ttype(), payload_bytes)?;
+        Ok(ds::OfMsg::new(header, payload))
+    }
+}
+
+fn header(bytes: &[u8]) -> Result<ds::Header> {
+    if bytes.len() < ds::HEADER_LENGTH {
+        bail!(ErrorKind::InvalidSliceLength(
+            ds::HEADER_LENGTH,
+            bytes.len(),
+            stringify!(TapEvent),
+        ));
+    }
+    ds::Header::try_from(&bytes[..ds::HEADER_LENGTH])
+}
+
+/// one line per event, eg.
+/// `1700000000.000000042 in 0x2a 127.0.0.1:43210 PacketIn xid=7 len=102`,
+/// unknown datapaths and peers are written as `-`
+impl fmt::Display for TapEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:09} ",
+            self.timestamp.as_secs(),
+            self.timestamp.subsec_nanos()
+        )?;
+        match self.direction {
+            Direction::Incoming => write!(f, "in ")?,
+            Direction::Outgoing => write!(f, "out ")?,
+        }
+        match self.datapath_id {
+            Some(datapath_id) => write!(f, "{:#x} ", datapath_id)?,
+            None => write!(f, "- ")?,
+        }
+        match self.peer {
+            Some(peer) => write!(f, "{} ", peer)?,
+            None => write!(f, "- ")?,
+        }
+        write!(
+            f,
+            "{:?} xid={} len={}",
+            self.ttype,
+            self.xid,
+            self.bytes.len()
+        )
+    }
+}
+
+enum Subscriber {
+    Channel(SyncSender<TapEvent>),
+    #[cfg(unix)]
+    Socket(CaptureWriter<UnixStream>),
+}
+
+impl Subscriber {
+    /// returns false if the subscriber went away,
+    /// the event is dropped if a channel subscriber is full
+    fn send(&mut self, event: &TapEvent) -> bool {
+        match *self {
+            Subscriber::Channel(ref sender) => match sender.try_send(event.clone()) {
+                Err(TrySendError::Disconnected(_)) => false,
+                _ => true,
+            },
+            #[cfg(unix)]
+            Subscriber::Socket(ref mut writer) => writer
+                .write_raw_at(
+                    event.timestamp,
+                    event.direction,
+                    event.peer,
+                    &event.bytes[..],
+                )
+                .is_ok(),
+        }
+    }
+}
+
+/// Sent from the tap to its thread.
+enum TapCommand {
+    Subscribe(Subscriber),
+    Publish(TapEvent),
+}
+
+/// Copies messages to its subscribers, see the module documentation.
+pub struct Tap {
+    /// None if the thread could not be started
+    commands: Option<SyncSender<TapCommand>>,
+    subscribers: Arc<AtomicUsize>,
+    dropped: AtomicUsize,
+}
+
+impl Default for Tap {
+    fn default() -> Self {
+        Tap::new()
+    }
+}
+
+impl Tap {
+    /// starts the thread of the tap, it ends when the tap is dropped
+    pub fn new() -> Self {
+        let (commands, receiver) = sync_channel(TAP_QUEUE_LENGTH);
+        let subscribers = Arc::new(AtomicUsize::new(0));
+        let count = subscribers.clone();
+        let thread = thread::Builder::new()
+            .name("Tap".to_string())
+            .spawn(move || copy_events(receiver, &count));
+        let commands = match thread {
+            Ok(_) => Some(commands),
+            Err(err) => {
+                error!("Could not start tap thread: {}", err);
+                None
+            }
+        };
+        Tap {
+            commands: commands,
+            subscribers: subscribers,
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// events of all messages from now on
+    pub fn subscribe(&self) -> Receiver<TapEvent> {
+        let (sender, receiver) = sync_channel(TAP_QUEUE_LENGTH);
+        self.send_to(sender);
+        receiver
+    }
+
+    /// sends the events of all messages from now on to the channel,
+    /// events are dropped while the channel is full
+    pub fn send_to(&self, sender: SyncSender<TapEvent>) {
+        self.add(Subscriber::Channel(sender));
+    }
+
+    /// writes all messages from now on to the connected socket in the dump
+    /// format of ds::capture, starting with its file header
+    #[cfg(unix)]
+    pub fn write_to(&self, stream: UnixStream) -> Result<()> {
+        stream.set_write_timeout(Some(SOCKET_WRITE_TIMEOUT))?;
+        self.add(Subscriber::Socket(CaptureWriter::new(stream)?));
+        Ok(())
+    }
+
+    /// binds a Unix domain socket at the path (see `listener::bind_unix`) and
+    /// subscribes every tool that connects to it with `write_to`
+    /// the returned thread accepts connections until the socket fails
+    #[cfg(unix)]
+    pub fn listen_unix<P: AsRef<Path>>(self: &Arc<Self>, path: P) -> Result<JoinHandle<()>> {
+        let unix_listener = listener::bind_unix(path)?;
+        let tap = self.clone();
+        let thread = thread::Builder::new()
+            .name("Tap-Listener".to_string())
+            .spawn(move || {
+                for stream in unix_listener.incoming() {
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            error!("Tap listener failed: {}", err);
+                            return;
+                        }
+                    };
+                    if let Err(err) = tap.write_to(stream) {
+                        warn!("Could not subscribe to the tap: {}", err);
+                    }
+                }
+            })?;
+        Ok(thread)
+    }
+
+    /// number of subscribers, including the ones that went away since the last message
+    pub fn subscribers(&self) -> usize {
+        self.subscribers.load(Ordering::SeqCst)
+    }
+
+    /// number of messages dropped because the queue of the tap thread was full
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
+    /// queues the raw message for the subscribers, does nothing without subscribers
+    /// never blocks, the message is dropped if the queue is full
+    pub fn publish(
+        &self,
+        direction: Direction,
+        datapath_id: Option<u64>,
+        peer: Option<SocketAddr>,
+        bytes: &[u8],
+    ) {
+        if self.subscribers() == 0 {
+            return;
+        }
+        let event = match TapEvent::new(direction, datapath_id, peer, bytes) {
+            Ok(event) => event,
+            Err(err) => {
+                warn!("Could not tap message: {}", err);
+                return;
+            }
+        };
+        if let Some(ref commands) = self.commands {
+            if let Err(TrySendError::Full(_)) = commands.try_send(TapCommand::Publish(event)) {
+                self.dropped.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    fn add(&self, subscriber: Subscriber) {
+        // counted right away, so messages published from now on are queued
+        self.subscribers.fetch_add(1, Ordering::SeqCst);
+        let sent = match self.commands {
+            Some(ref commands) => commands.send(TapCommand::Subscribe(subscriber)).is_ok(),
+            None => false,
+        };
+        if !sent {
+            self.subscribers.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// runs on the thread of the tap until the tap is dropped
+fn copy_events(commands: Receiver<TapCommand>, count: &AtomicUsize) {
+    let mut subscribers: Vec<Subscriber> = Vec::new();
+    for command in commands {
+        match command {
+            TapCommand::Subscribe(subscriber) => subscribers.push(subscriber),
+            TapCommand::Publish(event) => subscribers.retain_mut(|subscriber| {
+                let alive = subscriber.send(&event);
+                if !alive {
+                    count.fetch_sub(1, Ordering::SeqCst);
+                }
+                alive
+            }),
+        }
+    }
+}
+
+impl fmt::Debug for Tap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Tap")
+            .field("subscribers", &self.subscribers())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ds::capture::CaptureReader;
+    use super::super::super::ds::flow_parser::parse_flow_mod;
+    use super::*;
+
+    #[test]
+    fn copies_messages_to_the_subscribers() {
+        let tap = Tap::new();
+        let peer: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+        let flow_mod = parse_flow_mod("priority=10,actions=drop").unwrap();
+        let bytes = ds::OfMsg::generate(7, ds::OfPayload::FlowMod(flow_mod))
+            .encode()
+            .unwrap();
+        // nobody listens yet
+        tap.publish(Direction::Outgoing, None, Some(peer), &bytes[..]);
+
+        // the dropped receiver is removed before the other one receives the event
+        let dropped = tap.subscribe();
+        drop(dropped);
+        let events = tap.subscribe();
+        tap.publish(Direction::Outgoing, Some(0x2a), Some(peer), &bytes[..]);
+        let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(events.try_recv().is_err());
+        assert_eq!(event.ttype, ds::Type::FlowMod);
+        assert_eq!(event.xid, 7);
+        // messages only the controller sends are decoded as well
+        match *event.msg().unwrap().payload() {
+            ds::OfPayload::FlowMod(ref flow_mod) => assert_eq!(flow_mod.priority, 10),
+            ref payload => panic!("unexpected {:?}", payload),
+        }
+        let line = format!(" out 0x2a 10.0.0.1:40000 FlowMod xid=7 len={}", bytes.len());
+        assert!(event.to_string().ends_with(&line));
+        assert_eq!(tap.subscribers(), 1);
+    }
+
+    #[test]
+    fn drops_events_instead_of_blocking() {
+        let tap = Tap::new();
+        let hello = ds::OfMsg::generate(7, ds::OfPayload::Hello)
+            .encode()
+            .unwrap();
+        // nobody reads the events until the thread ended
+        let events = tap.subscribe();
+        let done = tap.subscribe();
+        tap.publish(Direction::Incoming, None, None, &hello[..]);
+        assert!(done.recv_timeout(Duration::from_secs(5)).is_ok());
+        for _ in 0..3 * TAP_QUEUE_LENGTH {
+            tap.publish(Direction::Incoming, None, None, &hello[..]);
+        }
+        // the thread ends once the tap is dropped and the queue is empty
+        drop(tap);
+        for _ in done.iter() {}
+        assert_eq!(events.iter().count(), TAP_QUEUE_LENGTH);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_the_dump_format_to_sockets() {
+        use std::io::Read;
+
+        let tap = Tap::new();
+        let (stream, mut tool) = UnixStream::pair().unwrap();
+        tap.write_to(stream).unwrap();
+        let hello = ds::OfMsg::generate(7, ds::OfPayload::Hello)
+            .encode()
+            .unwrap();
+        tap.publish(Direction::Incoming, Some(0x2a), None, &hello[..]);
+        drop(tap);
+
+        let mut dump = Vec::new();
+        tool.read_to_end(&mut dump).unwrap();
+        let mut reader = CaptureReader::from_dump(&dump[..]).unwrap();
+        let captured = reader.next().unwrap().unwrap();
+        assert_eq!(captured.direction(), &Direction::Incoming);
+        assert_eq!(*captured.msg().header().xid(), 7);
+        assert!(reader.next().is_none());
+    }
+}
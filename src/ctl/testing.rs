@@ -0,0 +1,1773 @@
+//! Fake switch for end to end tests of controller applications.
+//!
+//! A `MockSwitch` connects to a running controller like a real switch would,
+//! answers hellos, echo, features, description and barrier requests on its own and keeps
+//! every message it receives so tests can check what the controller sent:
+//!
+//! ```no_run
+//! # use oath2::ctl::testing::MockSwitch;
+//! # use std::time::Duration;
+//! # fn packet_in() -> oath2::ds::packet_in::PacketIn { unimplemented!() }
+//! let switch = MockSwitch::connect("127.0.0.1:6653", MockSwitch::default_features(1)).unwrap();
+//! assert!(switch.wait_for_handshake(Duration::from_secs(1)));
+//! switch.send_packet_in(packet_in()).unwrap();
+//! let flow_mod = switch.expect_flow_mod(Duration::from_secs(1)).unwrap();
+//! ```
+//!
+//! Tests that should not open sockets connect the mock switch through an
+//! in-memory `pipe`, a controller serves them with a `PipeListener`:
+//!
+//! ```no_run
+//! # use oath2::ctl::testing::{self, MockSwitch};
+//! # use oath2::ctl::{start_controller_with_listener, ControllerConfig};
+//! # use std::thread;
+//! let (listener, connector) = testing::pipe_listener();
+//! thread::spawn(move || {
+//!     start_controller_with_listener(listener, ControllerConfig::default(), |_| ())
+//! });
+//! let pipe = connector.connect().unwrap();
+//! let switch = MockSwitch::over(pipe, MockSwitch::default_features(1)).unwrap();
+//! ```
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::super::ds;
+use super::super::ds::factory;
+use super::super::ds::features::SwitchFeatures;
+use super::super::ds::flow_mod::FlowMod;
+use super::super::ds::flow_removed::FlowRemoved;
+use super::super::ds::group_mod::{GroupCapabilities, GroupFeatures};
+use super::super::ds::meter_mod::{MeterFeatures, MeterFlags};
+use super::super::ds::multipart::{
+    MultipartFlags, MultipartReply, RepDesc, RepPayload, ReqPayload,
+};
+use super::super::ds::packet_in::PacketIn;
+use super::super::ds::port_status::PortStatus;
+use super::super::ds::ports::Port;
+use super::super::ds::switch_config::{ConfigFlags, SwitchConfig};
+use super::super::err::*;
+use super::framing::FrameReader;
+use super::transport::{Accept, Transport};
+use super::xid::XidAllocator;
+
+/// Writing end of the connection of a mock switch, shared with its input thread.
+type SharedConnection = Arc<Mutex<Box<dyn Connection>>>;
+
+trait Connection: Write + Send + fmt::Debug {
+    fn close(&self);
+}
+
+impl<T: Transport + fmt::Debug> Connection for T {
+    fn close(&self) {
+        let _ = self.shutdown();
+    }
+}
+
+/// Scriptable fake switch, see the module documentation.
+/// The connection is closed when the mock switch is dropped.
+#[derive(Debug)]
+pub struct MockSwitch {
+    features: SwitchFeatures,
+    version: ds::Version,
+    stream: SharedConnection,
+    xids: XidAllocator,
+    received: Receiver<ds::OfMsg>,
+}
+
+impl MockSwitch {
+    /// features of a switch with the given datapath id and no buffers
+    pub fn default_features(datapath_id: u64) -> SwitchFeatures {
+        factory::features_reply(datapath_id)
+    }
+
+    /// connects to the controller with OpenFlow 1.3 and sends the hello
+    pub fn connect<A: ToSocketAddrs>(addr: A, features: SwitchFeatures) -> Result<Self> {
+        MockSwitch::connect_with_version(addr, features, ds::Version::V1_3)
+    }
+
+    /// connects to the controller and sends a hello of the given version
+    pub fn connect_with_version<A: ToSocketAddrs>(
+        addr: A,
+        features: SwitchFeatures,
+        version: ds::Version,
+    ) -> Result<Self> {
+        MockSwitch::start(TcpStream::connect(addr)?, features, version)
+    }
+
+    /// connects to a controller listening on the Unix domain socket with
+    /// OpenFlow 1.3 and sends the hello
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<Path>>(path: P, features: SwitchFeatures) -> Result<Self> {
+        let stream = UnixStream::connect(path)?;
+        MockSwitch::start(stream, features, ds::Version::V1_3)
+    }
+
+    /// speaks OpenFlow 1.3 on an established connection (eg. a `pipe`) and sends the hello
+    pub fn over<T>(stream: T, features: SwitchFeatures) -> Result<Self>
+    where
+        T: Transport + fmt::Debug,
+    {
+        MockSwitch::start(stream, features, ds::Version::V1_3)
+    }
+
+    /// starts the input thread on the connection and sends the hello
+    fn start<T>(stream: T, features: SwitchFeatures, version: ds::Version) -> Result<Self>
+    where
+        T: Transport + fmt::Debug,
+    {
+        let stream_in = stream.try_clone()?;
+        let stream: SharedConnection = Arc::new(Mutex::new(Box::new(stream)));
+        let (send, recv) = channel();
+
+        let stream_out = stream.clone();
+        let reply_features = features.clone();
+        thread::Builder::new()
+            .name(format!("Mock-Switch {:#x}", features.datapath_id))
+            .spawn(move || {
+                receive(
+                    FrameReader::new(stream_in),
+                    stream_out,
+                    reply_features,
+                    version,
+                    send,
+                )
+            })?;
+
+        let switch = MockSwitch {
+            features: features,
+            version: version,
+            stream: stream,
+            xids: XidAllocator::new(),
+            received: recv,
+        };
+        switch.send_payload(ds::OfPayload::Hello)?;
+        Ok(switch)
+    }
+
+    /// description every mock switch replies with
+    pub fn description() -> RepDesc {
+        RepDesc {
+            mfr_desc: "oath2".to_string(),
+            hw_desc: "mock switch".to_string(),
+            sw_desc: env!("CARGO_PKG_VERSION").to_string(),
+            serial_num: String::new(),
+            dp_desc: "ctl::testing::MockSwitch".to_string(),
+        }
+    }
+
+    /// group features every mock switch replies with, all group types with
+    /// every action except experimenter actions
+    pub fn group_features() -> GroupFeatures {
+        GroupFeatures {
+            types: 0xf,
+            capabilities: GroupCapabilities::SELECT_WEIGHT | GroupCapabilities::CHAINING,
+            max_groups: [1024; 4],
+            actions: [0x0fff_f801; 4],
+        }
+    }
+
+    /// meter features every mock switch replies with, drop and dscp remark bands
+    pub fn meter_features() -> MeterFeatures {
+        MeterFeatures {
+            max_meter: 256,
+            band_types: (1 << 1) | (1 << 2),
+            capabilities: MeterFlags::all(),
+            max_bands: 2,
+            max_color: 0,
+        }
+    }
+
+    /// config every mock switch replies with, the default of the specification
+    pub fn config() -> SwitchConfig {
+        SwitchConfig {
+            flags: ConfigFlags::FRAG_NORMAL,
+            miss_send_len: 128,
+        }
+    }
+
+    /// ports every mock switch reports, eth1 and eth2 with port numbers 1 and 2
+    pub fn ports() -> Vec<Port> {
+        (1..3).map(factory::port).collect()
+    }
+
+    pub fn features(&self) -> &SwitchFeatures {
+        &self.features
+    }
+
+    /// sends the payload with a new xid, returns the xid
+    pub fn send_payload(&self, payload: ds::OfPayload) -> Result<u32> {
+        let xid = self.xids.next();
+        let msg = ds::OfMsg::generate_for(self.version, xid, payload)?;
+        write_msg(&self.stream, msg)?;
+        Ok(xid)
+    }
+
+    /// sends the bytes as they are, eg. to test how the controller handles
+    /// messages it does not understand
+    pub fn send_raw(&self, bytes: &[u8]) -> Result<()> {
+        self.stream.lock().unwrap().write_all(bytes)?;
+        Ok(())
+    }
+
+    pub fn send_packet_in(&self, packet_in: PacketIn) -> Result<u32> {
+        self.send_payload(ds::OfPayload::PacketIn(packet_in))
+    }
+
+    pub fn send_port_status(&self, port_status: PortStatus) -> Result<u32> {
+        self.send_payload(ds::OfPayload::PortStatus(port_status))
+    }
+
+    pub fn send_flow_removed(&self, flow_removed: FlowRemoved) -> Result<u32> {
+        self.send_payload(ds::OfPayload::FlowRemoved(flow_removed))
+    }
+
+    /// next message sent by the controller, including the ones answered automatically
+    /// returns None if nothing arrived in time or the connection was closed
+    pub fn recv(&self, timeout: Duration) -> Option<ds::OfMsg> {
+        match self.received.recv_timeout(timeout) {
+            Ok(msg) => Some(msg),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+
+    /// waits for the first message whose payload matches, earlier messages are dropped
+    pub fn expect<F>(&self, timeout: Duration, matches: F) -> Option<ds::OfMsg>
+    where
+        F: Fn(&ds::OfPayload) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            match self.recv(deadline - now) {
+                Some(msg) => {
+                    if matches(msg.payload()) {
+                        return Some(msg);
+                    }
+                }
+                None => return None,
+            }
+        }
+    }
+
+    /// waits for the next flow mod, other messages before it are dropped
+    pub fn expect_flow_mod(&self, timeout: Duration) -> Option<FlowMod> {
+        let msg = self.expect(timeout, |payload| match *payload {
+            ds::OfPayload::FlowMod(_) => true,
+            _ => false,
+        });
+        match msg {
+            Some(msg) => match *msg.payload() {
+                ds::OfPayload::FlowMod(ref flow_mod) => Some(flow_mod.clone()),
+                _ => None,
+            },
+            None => None,
+        }
+    }
+
+    /// true once the controller asked for the features, after that the
+    /// switch is registered with the controller
+    pub fn wait_for_handshake(&self, timeout: Duration) -> bool {
+        self.expect(timeout, |payload| match *payload {
+            ds::OfPayload::FeaturesRequest => true,
+            _ => false,
+        })
+        .is_some()
+    }
+
+    /// closes the connection like a switch going down
+    pub fn disconnect(self) {}
+}
+
+impl Drop for MockSwitch {
+    fn drop(&mut self) {
+        if let Ok(stream) = self.stream.lock() {
+            stream.close();
+        }
+    }
+}
+
+fn write_msg(stream: &SharedConnection, msg: ds::OfMsg) -> Result<()> {
+    let bytes = msg.encode()?;
+    stream.lock().unwrap().write_all(&bytes[..])?;
+    Ok(())
+}
+
+/// decodes a message sent by the controller, OfMsg::try_from only
+/// decodes messages sent by switches
+fn decode(frame: &[u8]) -> Result<ds::OfMsg> {
+    let header = ds::Header::try_from(&frame[..ds::HEADER_LENGTH])?;
+    let payload = ds::decode_any(
+        *header.version(),
+        header.ttype(),
+        &frame[ds::HEADER_LENGTH..],
+    )?;
+    Ok(ds::OfMsg::new(header, payload))
+}
+
+/// input thread of a mock switch, answers requests a switch has to answer
+/// and forwards every message to the mock switch
+fn receive<T: Transport>(
+    mut reader: FrameReader<T>,
+    stream: SharedConnection,
+    features: SwitchFeatures,
+    version: ds::Version,
+    received: Sender<ds::OfMsg>,
+) {
+    loop {
+        let msg = match reader.next_frame() {
+            Ok(Some(frame)) => decode(frame),
+            _ => return,
+        };
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(err) => {
+                warn!("Mock switch could not decode message: {}", err);
+                continue;
+            }
+        };
+        let xid = *msg.header().xid();
+        let replies = match *msg.payload() {
+            ds::OfPayload::EchoRequest(ref data) => vec![ds::OfPayload::EchoReply(data.clone())],
+            ds::OfPayload::FeaturesRequest => vec![ds::OfPayload::FeaturesReply(features.clone())],
+            ds::OfPayload::BarrierRequest => vec![ds::OfPayload::BarrierReply],
+            ds::OfPayload::GetConfigRequest => {
+                vec![ds::OfPayload::GetConfigReply(MockSwitch::config())]
+            }
+            ds::OfPayload::MultipartRequest(ref request) if request.payload == ReqPayload::Desc => {
+                let reply = MultipartReply::new(RepPayload::Desc(MockSwitch::description()));
+                vec![ds::OfPayload::MultipartReply(reply)]
+            }
+            ds::OfPayload::MultipartRequest(ref request)
+                if request.payload == ReqPayload::GroupFeatures =>
+            {
+                let reply =
+                    MultipartReply::new(RepPayload::GroupFeatures(MockSwitch::group_features()));
+                vec![ds::OfPayload::MultipartReply(reply)]
+            }
+            ds::OfPayload::MultipartRequest(ref request)
+                if request.payload == ReqPayload::MeterFeatures =>
+            {
+                let reply =
+                    MultipartReply::new(RepPayload::MeterFeatures(MockSwitch::meter_features()));
+                vec![ds::OfPayload::MultipartReply(reply)]
+            }
+            ds::OfPayload::MultipartRequest(ref request)
+                if request.payload == ReqPayload::PortDesc =>
+            {
+                // one reply per port, all but the last flagged with more
+                let ports = MockSwitch::ports();
+                let last = ports.len() - 1;
+                ports
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, port)| {
+                        let mut reply = MultipartReply::new(RepPayload::PortDesc(vec![port]));
+                        if i < last {
+                            reply.flags = MultipartFlags::MORE;
+                        }
+                        ds::OfPayload::MultipartReply(reply)
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        for reply in replies {
+            let sent = ds::OfMsg::generate_for(version, xid, reply)
+                .and_then(|reply| write_msg(&stream, reply));
+            if let Err(err) = sent {
+                warn!("Mock switch could not reply: {}", err);
+                return;
+            }
+        }
+        if received.send(msg).is_err() {
+            // mock switch is gone
+            return;
+        }
+    }
+}
+
+/// Bytes written to one end of a pipe that the other end did not read yet.
+#[derive(Debug, Default)]
+struct PipeBuffer {
+    state: Mutex<PipeState>,
+    readable: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct PipeState {
+    bytes: VecDeque<u8>,
+    closed: bool,
+}
+
+impl PipeBuffer {
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.readable.notify_all();
+    }
+}
+
+/// One end of an in-memory connection, see `pipe`.
+/// Clones are handles to the same end like clones of a tcp stream.
+#[derive(Debug, Clone)]
+pub struct Pipe {
+    incoming: Arc<PipeBuffer>,
+    outgoing: Arc<PipeBuffer>,
+}
+
+/// two connected ends of an in-memory connection, what is written to one end
+/// is read from the other
+/// reads block until bytes arrive, after either end was shut down reads
+/// return the remaining bytes and then the end of the stream and writes fail
+pub fn pipe() -> (Pipe, Pipe) {
+    let a_to_b = Arc::new(PipeBuffer::default());
+    let b_to_a = Arc::new(PipeBuffer::default());
+    let a = Pipe {
+        incoming: b_to_a.clone(),
+        outgoing: a_to_b.clone(),
+    };
+    let b = Pipe {
+        incoming: a_to_b,
+        outgoing: b_to_a,
+    };
+    (a, b)
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.incoming.state.lock().unwrap();
+        while state.bytes.is_empty() && !state.closed {
+            state = self.incoming.readable.wait(state).unwrap();
+        }
+        let n = ::std::cmp::min(buf.len(), state.bytes.len());
+        for (byte, read) in buf.iter_mut().zip(state.bytes.drain(..n)) {
+            *byte = read;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.outgoing.state.lock().unwrap();
+        if state.closed {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "pipe was shut down",
+            ));
+        }
+        state.bytes.extend(buf);
+        self.outgoing.readable.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Pipe {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(self.clone())
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.incoming.close();
+        self.outgoing.close();
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        "pipe".to_string()
+    }
+}
+
+/// Accepts the pipes connected through its `PipeConnector`, see `pipe_listener`.
+#[derive(Debug)]
+pub struct PipeListener {
+    connections: Receiver<Pipe>,
+}
+
+/// Connects pipes to a `PipeListener`.
+#[derive(Debug, Clone)]
+pub struct PipeConnector {
+    connections: Sender<Pipe>,
+}
+
+/// a listener for start_controller_with_listener and the connector that
+/// opens in-memory connections to it
+pub fn pipe_listener() -> (PipeListener, PipeConnector) {
+    let (send, recv) = channel();
+    (
+        PipeListener { connections: recv },
+        PipeConnector { connections: send },
+    )
+}
+
+impl PipeConnector {
+    /// the switch end of a new connection to the listener,
+    /// fails if the listener is gone
+    pub fn connect(&self) -> io::Result<Pipe> {
+        let (switch, controller) = pipe();
+        match self.connections.send(controller) {
+            Ok(_) => Ok(switch),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "pipe listener is gone",
+            )),
+        }
+    }
+}
+
+impl Accept for PipeListener {
+    type Transport = Pipe;
+
+    /// blocks forever once all connectors are gone, like a tcp listener
+    /// nobody connects to
+    fn accept(&self) -> io::Result<Pipe> {
+        match self.connections.recv() {
+            Ok(pipe) => Ok(pipe),
+            Err(_) => loop {
+                thread::park();
+            },
+        }
+    }
+
+    fn name(&self) -> String {
+        "pipe".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ds::async::Async;
+    use super::super::super::ds::capture::Direction;
+    use super::super::super::ds::error_msg::{BadRequestCode, ErrorMsg, ErrorType};
+    use super::super::super::ds::flags::FlagsPolicy;
+    use super::super::super::ds::flow_match::Match;
+    use super::super::super::ds::flow_parser::parse_flow_mod;
+    use super::super::super::ds::packet_in::InReason;
+    use super::super::super::ds::packet_out::BufferId;
+    use super::super::super::ds::port_status::PortReason;
+    use super::super::super::ds::ports::PortNumber;
+    use super::super::super::ds::role::{ControllerRole, Role};
+    use super::super::accept::DatapathDecision;
+    use super::super::audit::AuditLog;
+    use super::super::diagnostics::BadFrames;
+    use super::super::events::ControllerEvent;
+    use super::super::metrics::{Metrics, ECHO_MARKER};
+    use super::super::middleware::MiddlewareChain;
+    use super::super::switch::IncomingMsg;
+    use super::super::tap::Tap;
+    use super::super::{
+        run_handler, start_controller_with_config, start_controller_with_listener, with_state,
+        with_workers, ControllerConfig, SwitchContext,
+    };
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    /// starts a controller on a free port and connects a mock switch to it
+    fn start<F>(config: ControllerConfig, handler: F) -> MockSwitch
+    where
+        F: Fn(IncomingMsg) + Send + 'static,
+    {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        thread::spawn(move || {
+            start_controller_with_config(addr, config, handler).unwrap();
+        });
+
+        for _ in 0..100 {
+            match MockSwitch::connect(addr, MockSwitch::default_features(0x2a)) {
+                Ok(switch) => return switch,
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+        panic!("controller did not start");
+    }
+
+    #[test]
+    fn controller_answers_packet_in_with_flow_mod() {
+        let config = ControllerConfig::default();
+        let registry = config.registry.clone();
+        let switch = start(config, |msg| {
+            if let ds::OfPayload::PacketIn(_) = *msg.msg.payload() {
+                let flow_mod = parse_flow_mod("in_port=1,actions=drop").unwrap();
+                let reply = ds::OfMsg::generate(msg.xids.next(), ds::OfPayload::FlowMod(flow_mod));
+                msg.reply_ch.send(reply).unwrap();
+            }
+        });
+        assert!(switch.wait_for_handshake(Duration::from_secs(5)));
+
+        switch
+            .send_packet_in(PacketIn {
+                buffer_id: BufferId::NoBuffer,
+                total_len: 4,
+                reason: InReason::NoMatch,
+                table_id: 0,
+                cookie: 0,
+                mmatch: Match::new(Vec::new()),
+                ethernet_frame: vec![1, 2, 3, 4],
+            })
+            .unwrap();
+        let flow_mod = switch.expect_flow_mod(Duration::from_secs(5)).unwrap();
+        assert_eq!(flow_mod, parse_flow_mod("in_port=1,actions=drop").unwrap());
+
+        // requested after the handshake
+        let handle = registry.get(0x2a).unwrap();
+        for _ in 0..100 {
+            if handle.description().is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(handle.description(), Some(MockSwitch::description()));
+    }
+
+    #[test]
+    fn controller_passes_state_to_the_handler() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let handler = with_state(seen.clone(), |seen: &Mutex<Vec<_>>, ctx, msg| {
+            if let ds::OfPayload::PacketIn(_) = *msg.payload() {
+                seen.lock().unwrap().push((ctx.datapath_id(), ctx.version));
+            }
+        });
+        let config = ControllerConfig::default();
+        let registry = config.registry.clone();
+        let switch = start(config, handler);
+        assert!(switch.wait_for_handshake(Duration::from_secs(5)));
+        // the context only knows the switch after its features reply
+        for _ in 0..100 {
+            if registry.get(0x2a).is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        switch
+            .send_packet_in(PacketIn {
+                buffer_id: BufferId::NoBuffer,
+                total_len: 0,
+                reason: InReason::NoMatch,
+                table_id: 0,
+                cookie: 0,
+                mmatch: Match::new(Vec::new()),
+                ethernet_frame: Vec::new(),
+            })
+            .unwrap();
+        for _ in 0..100 {
+            if !seen.lock().unwrap().is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(*seen.lock().unwrap(), vec![(Some(0x2a), ds::Version::V1_3)]);
+    }
+
+    #[test]
+    fn workers_keep_the_order_of_a_connection() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let worker_seen = seen.clone();
+        let handler = with_workers(4, move |msg: IncomingMsg| {
+            if let ds::OfPayload::PacketIn(ref packet_in) = *msg.msg.payload() {
+                // later messages would overtake slow ones on other workers
+                thread::sleep(Duration::from_millis(10 - packet_in.cookie % 10));
+                worker_seen.lock().unwrap().push(packet_in.cookie);
+            }
+        });
+        let switch = start(ControllerConfig::default(), handler.unwrap());
+        assert!(switch.wait_for_handshake(Duration::from_secs(5)));
+
+        // xids the controller does not use for its own requests
+        for cookie in 0..20 {
+            let packet_in = ds::OfPayload::PacketIn(PacketIn {
+                buffer_id: BufferId::NoBuffer,
+                total_len: 0,
+                reason: InReason::NoMatch,
+                table_id: 0,
+                cookie: cookie,
+                mmatch: Match::new(Vec::new()),
+                ethernet_frame: Vec::new(),
+            });
+            let msg = ds::OfMsg::generate_for(ds::Version::V1_3, 1000 + cookie as u32, packet_in);
+            switch
+                .send_raw(&msg.unwrap().encode().unwrap()[..])
+                .unwrap();
+        }
+        for _ in 0..100 {
+            if seen.lock().unwrap().len() == 20 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(*seen.lock().unwrap(), (0..20).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn controller_pushes_async_config() {
+        let mut config = ControllerConfig::default();
+        config.async_config = Some(Async::listen_only());
+        let switch = start(config, |_| ());
+        let msg = switch.expect(Duration::from_secs(5), |payload| match *payload {
+            ds::OfPayload::SetAsync(_) => true,
+            _ => false,
+        });
+        match msg.as_ref().map(|msg| msg.payload()) {
+            Some(ds::OfPayload::SetAsync(config)) => {
+                assert_eq!(*config, Async::listen_only())
+            }
+            other => panic!("expected set async, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn controller_tracks_ports() {
+        let (event_send, events) = channel();
+        let mut config = ControllerConfig::default();
+        config.event_ch = Some(event_send);
+        let registry = config.registry.clone();
+        let switch = start(config, |_| ());
+
+        // the port description is requested once the switch is registered
+        let request = switch.expect(Duration::from_secs(5), |payload| match *payload {
+            ds::OfPayload::MultipartRequest(ref request) => request.payload == ReqPayload::PortDesc,
+            _ => false,
+        });
+        assert!(request.is_some());
+        let handle = registry.get(0x2a).unwrap();
+        for _ in 0..100 {
+            if handle.ports().len() == 2 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(handle.ports(), MockSwitch::ports());
+
+        let removed = MockSwitch::ports().remove(0);
+        switch
+            .send_port_status(PortStatus::new(PortReason::Delete, removed.clone()))
+            .unwrap();
+        // connection events come first
+        loop {
+            if let ControllerEvent::PortChanged {
+                datapath_id,
+                reason,
+                port,
+            } = events.recv_timeout(Duration::from_secs(5)).unwrap()
+            {
+                assert_eq!(datapath_id, 0x2a);
+                assert_eq!(reason, PortReason::Delete);
+                assert_eq!(port, removed);
+                break;
+            }
+        }
+        assert_eq!(handle.port(&PortNumber::NormalPort(1)), None);
+        assert_eq!(handle.ports(), vec![MockSwitch::ports().remove(1)]);
+    }
+
+    #[test]
+    fn handlers_get_the_context_of_the_switch() {
+        let (ctx_send, contexts) = channel();
+        let handler = with_state(
+            Arc::new(Mutex::new(ctx_send)),
+            |ctx_send: &Mutex<Sender<SwitchContext>>, ctx, msg| {
+                if *msg.header().ttype() == ds::Type::RoleReply {
+                    ctx_send.lock().unwrap().send(ctx.clone()).unwrap();
+                }
+            },
+        );
+        let config = ControllerConfig::default();
+        let registry = config.registry.clone();
+        let switch = start(config, handler);
+        assert!(switch.wait_for_handshake(Duration::from_secs(5)));
+        for _ in 0..100 {
+            match registry.get(0x2a) {
+                Some(ref handle) if handle.ports().len() == 2 => break,
+                _ => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+
+        // the role is updated before the handler sees the reply
+        let role = Role {
+            role: ControllerRole::Master,
+            generation_id: 3,
+        };
+        switch
+            .send_payload(ds::OfPayload::RoleReply(role.clone()))
+            .unwrap();
+        let ctx = contexts.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(ctx.datapath_id(), Some(0x2a));
+        assert_eq!(ctx.version, ds::Version::V1_3);
+        assert!(ctx.peer.is_some());
+        assert_eq!(
+            ctx.capabilities(),
+            Some(MockSwitch::default_features(0x2a).capabilities)
+        );
+        assert_eq!(ctx.ports(), MockSwitch::ports());
+        assert_eq!(ctx.role(), Some(role));
+    }
+
+    #[test]
+    fn controller_caches_group_and_meter_features() {
+        let config = ControllerConfig::default();
+        let registry = config.registry.clone();
+        let switch = start(config, |_| ());
+        let request = switch.expect(Duration::from_secs(5), |payload| match *payload {
+            ds::OfPayload::MultipartRequest(ref request) => {
+                request.payload == ReqPayload::MeterFeatures
+            }
+            _ => false,
+        });
+        assert!(request.is_some());
+        let handle = registry.get(0x2a).unwrap();
+        for _ in 0..100 {
+            if handle.meter_features().is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(handle.group_features(), Some(MockSwitch::group_features()));
+        assert_eq!(handle.meter_features(), Some(MockSwitch::meter_features()));
+    }
+
+    #[test]
+    fn controller_reconciles_switch_config() {
+        let desired = SwitchConfig {
+            flags: ConfigFlags::FRAG_DROP,
+            miss_send_len: 0xffff,
+        };
+        let mut config = ControllerConfig::default();
+        config.switch_config = Some(desired.clone());
+        let switch = start(config, |_| ());
+        let set_config = switch
+            .expect(Duration::from_secs(5), |payload| match *payload {
+                ds::OfPayload::SetConfig(_) => true,
+                _ => false,
+            })
+            .unwrap();
+        match *set_config.payload() {
+            ds::OfPayload::SetConfig(ref config) => assert_eq!(*config, desired),
+            _ => unreachable!(),
+        }
+
+        // nothing to change
+        let mut config = ControllerConfig::default();
+        config.switch_config = Some(MockSwitch::config());
+        let switch = start(config, |_| ());
+        assert!(switch
+            .expect(Duration::from_secs(5), |payload| match *payload {
+                ds::OfPayload::GetConfigRequest => true,
+                _ => false,
+            })
+            .is_some());
+        assert!(switch
+            .expect(Duration::from_millis(200), |payload| match *payload {
+                ds::OfPayload::SetConfig(_) => true,
+                _ => false,
+            })
+            .is_none());
+    }
+
+    #[test]
+    fn controller_keeps_audit_trail() {
+        let audit_log = Arc::new(AuditLog::default());
+        let mut config = ControllerConfig::default();
+        config.switch_config = Some(SwitchConfig {
+            flags: ConfigFlags::FRAG_DROP,
+            miss_send_len: 0xffff,
+        });
+        config.audit_log = Some(audit_log.clone());
+        let switch = start(config, |_| ());
+        let set_config = switch
+            .expect(Duration::from_secs(5), |payload| match *payload {
+                ds::OfPayload::SetConfig(_) => true,
+                _ => false,
+            })
+            .unwrap();
+        let xid = *set_config.header().xid();
+        // requests do not change the state of the switch
+        let entries = audit_log.for_datapath(switch.features().datapath_id);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].ttype, ds::Type::SetConfig);
+        assert_eq!(entries[0].xid, xid);
+
+        let error = ErrorMsg::new(ErrorType::SwitchConfigFailed, 0, &[]);
+        let bytes = ds::OfMsg::generate(xid, ds::OfPayload::Error(error.clone()))
+            .encode()
+            .unwrap();
+        switch.send_raw(&bytes).unwrap();
+        for _ in 0..100 {
+            if audit_log.entries()[0].error.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(audit_log.entries()[0].error, Some(error));
+    }
+
+    #[test]
+    fn controller_measures_echo_round_trip() {
+        let mut config = ControllerConfig::default();
+        config.echo_interval = Some(Duration::from_millis(20));
+        let registry = config.registry.clone();
+        let handled = Arc::new(AtomicUsize::new(0));
+        let handled_echos = handled.clone();
+        let switch = start(config, move |msg| {
+            if *msg.msg.header().ttype() == ds::Type::EchoReply {
+                handled_echos.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        let request = switch.expect(Duration::from_secs(5), |payload| match *payload {
+            ds::OfPayload::EchoRequest(ref data) => data.starts_with(ECHO_MARKER),
+            _ => false,
+        });
+        assert!(request.is_some());
+        let handle = registry.get(0x2a).unwrap();
+        for _ in 0..100 {
+            if handle.stats().echo_rtt.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(handle.stats().echo_rtt.is_some());
+        // the replies of the mock switch only measured the round trip time
+        assert_eq!(handled.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn controller_rejects_messages_it_does_not_understand() {
+        let (event_send, events) = channel();
+        let mut config = ControllerConfig::default();
+        config.event_ch = Some(event_send);
+        let switch = start(config, |_| ());
+        assert!(switch.wait_for_handshake(Duration::from_secs(5)));
+
+        let error_reply = |xid: u32| {
+            let msg = switch
+                .expect(Duration::from_secs(5), |payload| match *payload {
+                    ds::OfPayload::Error(_) => true,
+                    _ => false,
+                })
+                .unwrap();
+            assert_eq!(*msg.header().xid(), xid);
+            match *msg.payload() {
+                ds::OfPayload::Error(ref error) => error.clone(),
+                _ => unreachable!(),
+            }
+        };
+
+        // unknown type
+        let unknown_type = [0x04, 0x7f, 0x00, 0x08, 0x00, 0x00, 0x00, 0x01];
+        switch.send_raw(&unknown_type).unwrap();
+        let error = error_reply(1);
+        assert_eq!(error, ErrorMsg::bad_request(BadRequestCode::BadType, &unknown_type));
+
+        // flow mods are only sent by controllers
+        let flow_mod = parse_flow_mod("actions=drop").unwrap();
+        let xid = switch.send_payload(ds::OfPayload::FlowMod(flow_mod)).unwrap();
+        assert_eq!(error_reply(xid).code, BadRequestCode::BadType as u16);
+
+        // not the negotiated version
+        let other_version = [0x05, 0x15, 0x00, 0x08, 0x00, 0x00, 0x00, 0x02];
+        switch.send_raw(&other_version).unwrap();
+        assert_eq!(error_reply(2).code, BadRequestCode::BadVersion as u16);
+
+        let mut rejected = Vec::new();
+        while rejected.len() < 3 {
+            if let ControllerEvent::MessageRejected {
+                datapath_id, xid, ..
+            } = events.recv_timeout(Duration::from_secs(5)).unwrap()
+            {
+                assert_eq!(datapath_id, Some(0x2a));
+                rejected.push(xid);
+            }
+        }
+        assert_eq!(rejected, vec![1, xid, 2]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn controller_listens_on_unix_socket() {
+        use super::super::start_controller_unix;
+
+        let path = ::std::env::temp_dir().join(format!("oath2-ctl-{}.sock", ::std::process::id()));
+        let peers = Arc::new(Mutex::new(Vec::new()));
+        let handler = with_state(peers.clone(), |peers: &Mutex<Vec<_>>, ctx, _| {
+            peers.lock().unwrap().push(ctx.peer);
+        });
+        let config = ControllerConfig::default();
+        let registry = config.registry.clone();
+        let listen_path = path.clone();
+        thread::spawn(move || {
+            start_controller_unix(listen_path, config, handler).unwrap();
+        });
+
+        let mut switch = None;
+        for _ in 0..100 {
+            match MockSwitch::connect_unix(&path, MockSwitch::default_features(0x2a)) {
+                Ok(connected) => {
+                    switch = Some(connected);
+                    break;
+                }
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+        let switch = switch.expect("controller did not start");
+        assert!(switch.wait_for_handshake(Duration::from_secs(5)));
+        for _ in 0..100 {
+            if registry.get(0x2a).is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(registry.get(0x2a).is_some());
+
+        switch.send_payload(ds::OfPayload::BarrierReply).unwrap();
+        for _ in 0..100 {
+            if !peers.lock().unwrap().is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        // Unix domain sockets have no peer address
+        assert_eq!(peers.lock().unwrap().first(), Some(&None));
+        let _ = ::std::fs::remove_file(&path);
+    }
+
+    /// tcp stream counting the bytes the controller reads from it
+    #[derive(Debug)]
+    struct Counted {
+        stream: TcpStream,
+        read: Arc<AtomicUsize>,
+    }
+
+    impl ::std::io::Read for Counted {
+        fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+            let n = self.stream.read(buf)?;
+            self.read.fetch_add(n, Ordering::SeqCst);
+            Ok(n)
+        }
+    }
+
+    impl Write for Counted {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            self.stream.write(buf)
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            self.stream.flush()
+        }
+    }
+
+    impl Transport for Counted {
+        fn try_clone(&self) -> ::std::io::Result<Self> {
+            Ok(Counted {
+                stream: self.stream.try_clone()?,
+                read: self.read.clone(),
+            })
+        }
+
+        fn shutdown(&self) -> ::std::io::Result<()> {
+            self.stream.shutdown(::std::net::Shutdown::Both)
+        }
+    }
+
+    struct CountingListener {
+        listener: TcpListener,
+        read: Arc<AtomicUsize>,
+    }
+
+    impl Accept for CountingListener {
+        type Transport = Counted;
+
+        fn accept(&self) -> ::std::io::Result<Counted> {
+            let (stream, _) = self.listener.accept()?;
+            Ok(Counted {
+                stream: stream,
+                read: self.read.clone(),
+            })
+        }
+
+        fn name(&self) -> String {
+            "counting".to_string()
+        }
+    }
+
+    #[test]
+    fn controller_runs_on_any_transport() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let read = Arc::new(AtomicUsize::new(0));
+        let listener = CountingListener {
+            listener: listener,
+            read: read.clone(),
+        };
+        let config = ControllerConfig::default();
+        let registry = config.registry.clone();
+        thread::spawn(move || {
+            start_controller_with_listener(listener, config, |_| ()).unwrap();
+        });
+
+        let switch = MockSwitch::connect(addr, MockSwitch::default_features(0x2a)).unwrap();
+        assert!(switch.wait_for_handshake(Duration::from_secs(5)));
+        for _ in 0..100 {
+            if registry.get(0x2a).is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(registry.get(0x2a).is_some());
+        // at least the hello and the features reply
+        assert!(read.load(Ordering::SeqCst) >= 2 * ds::HEADER_LENGTH);
+    }
+
+    #[test]
+    fn pipe_connects_both_ends() {
+        let (mut a, b) = pipe();
+        let mut b_in = b.try_clone().unwrap();
+        a.write_all(&[1, 2, 3]).unwrap();
+        let mut buf = [0u8; 2];
+        assert_eq!(b_in.read(&mut buf).unwrap(), 2);
+        assert_eq!(buf, [1, 2]);
+
+        // the rest is still read after the shutdown, then the end of the stream
+        Transport::shutdown(&b).unwrap();
+        assert_eq!(b_in.read(&mut buf).unwrap(), 1);
+        assert_eq!(b_in.read(&mut buf).unwrap(), 0);
+        assert!(a.write_all(&[4]).is_err());
+        assert_eq!(a.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn controller_handshake_over_pipe() {
+        let (listener, connector) = pipe_listener();
+        let config = ControllerConfig::default();
+        let registry = config.registry.clone();
+        thread::spawn(move || {
+            start_controller_with_listener(listener, config, |_| ()).unwrap();
+        });
+
+        let switch = MockSwitch::over(
+            connector.connect().unwrap(),
+            MockSwitch::default_features(0x2a),
+        )
+        .unwrap();
+        assert!(switch.wait_for_handshake(Duration::from_secs(5)));
+        let xid = switch
+            .send_payload(ds::OfPayload::EchoRequest(b"ping".to_vec()))
+            .unwrap();
+        let echo = switch
+            .expect(Duration::from_secs(5), |payload| match *payload {
+                ds::OfPayload::EchoReply(_) => true,
+                _ => false,
+            })
+            .unwrap();
+        assert_eq!(*echo.header().xid(), xid);
+        // the data of the request comes back unchanged
+        match *echo.payload() {
+            ds::OfPayload::EchoReply(ref data) => assert_eq!(&data[..], b"ping"),
+            ref other => panic!("unexpected {:?}", other),
+        }
+        assert!(registry.get(0x2a).is_some());
+
+        // the connection of the controller ends with the pipe
+        switch.disconnect();
+        for _ in 0..100 {
+            if registry.get(0x2a).is_none() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(registry.get(0x2a).is_none());
+    }
+
+    #[test]
+    fn controller_sheds_connections_over_max_switches() {
+        let (listener, connector) = pipe_listener();
+        let (event_s, event_r) = channel();
+        let mut config = ControllerConfig::default();
+        config.event_ch = Some(event_s);
+        config.accept_policy.max_switches = Some(1);
+        thread::spawn(move || {
+            start_controller_with_listener(listener, config, |_| ()).unwrap();
+        });
+
+        let switch = MockSwitch::over(
+            connector.connect().unwrap(),
+            MockSwitch::default_features(0x2a),
+        )
+        .unwrap();
+        assert!(switch.wait_for_handshake(Duration::from_secs(5)));
+
+        // the second switch gets a hello and an error, then the connection is closed
+        let mut shed = connector.connect().unwrap();
+        let mut bytes = Vec::new();
+        shed.read_to_end(&mut bytes).unwrap();
+        let hello = decode(&bytes[..ds::HEADER_LENGTH]).unwrap();
+        assert_eq!(*hello.header().ttype(), ds::Type::Hello);
+        match *decode(&bytes[ds::HEADER_LENGTH..]).unwrap().payload() {
+            ds::OfPayload::Error(ref error) => {
+                assert_eq!(error.error_type(), Some(ErrorType::HelloFailed));
+                assert_eq!(&error.data[..], b"too many connections");
+            }
+            ref payload => panic!("unexpected payload {:?}", payload),
+        }
+        let shed_event = event_r
+            .iter()
+            .filter_map(|event| match event {
+                ControllerEvent::ConnectionShed { peer, active } => Some((peer, active)),
+                _ => None,
+            })
+            .next();
+        assert_eq!(shed_event, Some((None, 1)));
+    }
+
+    #[test]
+    fn controller_contains_panics_of_connection_threads() {
+        let (listener, connector) = pipe_listener();
+        let (event_s, event_r) = channel();
+        let mut config = ControllerConfig::default();
+        config.event_ch = Some(event_s);
+        config.datapath_policy = Some(Arc::new(|datapath_id, _| {
+            if datapath_id == 0x2a {
+                panic!("policy failed");
+            }
+            DatapathDecision::Accept
+        }));
+        let registry = config.registry.clone();
+        thread::spawn(move || {
+            start_controller_with_listener(listener, config, |_| ()).unwrap();
+        });
+
+        let failing = MockSwitch::over(
+            connector.connect().unwrap(),
+            MockSwitch::default_features(0x2a),
+        )
+        .unwrap();
+        let panicked = event_r
+            .iter()
+            .filter_map(|event| match event {
+                ControllerEvent::ConnectionPanicked {
+                    thread, message, ..
+                } => Some((thread, message)),
+                _ => None,
+            })
+            .next()
+            .unwrap();
+        assert!(panicked.0.starts_with("Switch-In "));
+        assert_eq!(panicked.1, "policy failed");
+        failing.disconnect();
+
+        // the controller keeps accepting switches
+        let switch = MockSwitch::over(
+            connector.connect().unwrap(),
+            MockSwitch::default_features(0x2b),
+        )
+        .unwrap();
+        assert!(switch.wait_for_handshake(Duration::from_secs(5)));
+        let connected = event_r
+            .iter()
+            .filter_map(|event| match event {
+                ControllerEvent::SwitchConnected { datapath_id, .. } => Some(datapath_id),
+                _ => None,
+            })
+            .next();
+        assert_eq!(connected, Some(0x2b));
+        assert!(registry.get(0x2a).is_none());
+    }
+
+    #[test]
+    fn controller_contains_panics_of_the_handler() {
+        let (event_s, event_r) = channel();
+        let mut config = ControllerConfig::default();
+        config.event_ch = Some(event_s);
+        let handled = Arc::new(AtomicUsize::new(0));
+        let handler_handled = handled.clone();
+        let switch = start(config, move |msg: IncomingMsg| {
+            if let ds::OfPayload::PacketIn(ref packet_in) = *msg.msg.payload() {
+                if packet_in.cookie == 1 {
+                    panic!("handler failed");
+                }
+                handler_handled.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        assert!(switch.wait_for_handshake(Duration::from_secs(5)));
+
+        // xids the controller does not use for its own requests
+        for cookie in 1..3 {
+            let mut packet_in = factory::packet_in(BufferId::NoBuffer, 1, &[0xff; 14]);
+            packet_in.cookie = cookie;
+            let packet_in = ds::OfPayload::PacketIn(packet_in);
+            let msg = ds::OfMsg::generate_for(ds::Version::V1_3, 1000 + cookie as u32, packet_in);
+            switch
+                .send_raw(&msg.unwrap().encode().unwrap()[..])
+                .unwrap();
+        }
+        let panicked = event_r
+            .iter()
+            .filter_map(|event| match event {
+                ControllerEvent::ConnectionPanicked {
+                    datapath_id,
+                    thread,
+                    message,
+                    ..
+                } => Some((datapath_id, thread, message)),
+                _ => None,
+            })
+            .next()
+            .unwrap();
+        assert_eq!(panicked.0, Some(0x2a));
+        assert_eq!(panicked.1, "Handler-Thread");
+        assert_eq!(panicked.2, "handler failed");
+
+        // the handler thread keeps going with the next message
+        for _ in 0..100 {
+            if handled.load(Ordering::SeqCst) == 1 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(handled.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn controller_reports_panics_of_handler_workers() {
+        let (event_s, event_r) = channel();
+        let mut config = ControllerConfig::default();
+        config.event_ch = Some(event_s);
+        let handler = with_workers(2, |msg: IncomingMsg| {
+            if let ds::OfPayload::PacketIn(_) = *msg.msg.payload() {
+                panic!("worker failed");
+            }
+        });
+        let switch = start(config, handler.unwrap());
+        assert!(switch.wait_for_handshake(Duration::from_secs(5)));
+
+        let packet_in = ds::OfPayload::PacketIn(factory::packet_in(BufferId::NoBuffer, 1, &[]));
+        let msg = ds::OfMsg::generate_for(ds::Version::V1_3, 1000, packet_in);
+        switch
+            .send_raw(&msg.unwrap().encode().unwrap()[..])
+            .unwrap();
+        let panicked = event_r
+            .iter()
+            .filter_map(|event| match event {
+                ControllerEvent::ConnectionPanicked {
+                    datapath_id,
+                    thread,
+                    message,
+                    ..
+                } => Some((datapath_id, thread, message)),
+                _ => None,
+            })
+            .next()
+            .unwrap();
+        assert_eq!(panicked.0, Some(0x2a));
+        assert!(panicked.1.starts_with("Handler-Worker-"));
+        assert_eq!(panicked.2, "worker failed");
+    }
+
+    #[test]
+    fn handler_thread_survives_replies_to_closed_connections() {
+        let (handled_s, handled_r) = channel();
+        let (msg_s, msg_r) = channel();
+        thread::spawn(move || {
+            let handler = move |msg: IncomingMsg| {
+                let _ = handled_s.send(msg.msg.header().ttype().clone());
+            };
+            run_handler(
+                msg_r,
+                &handler,
+                &Metrics::new(),
+                &MiddlewareChain::new(),
+                &None,
+            );
+        });
+
+        // the connection is gone before the hello and the echo request are handled
+        let closed = |payload| {
+            let (reply_s, _) = channel();
+            IncomingMsg {
+                reply_ch: reply_s,
+                msg: ds::OfMsg::generate(1, payload),
+                switch: None,
+                xids: Arc::new(XidAllocator::new()),
+                version: ds::Version::V1_3,
+                peer: None,
+                local: None,
+                event_ch: None,
+            }
+        };
+        msg_s.send(closed(ds::OfPayload::Hello)).unwrap();
+        msg_s
+            .send(closed(ds::OfPayload::EchoRequest(Vec::new())))
+            .unwrap();
+        msg_s.send(closed(ds::OfPayload::BarrierReply)).unwrap();
+        assert_eq!(
+            handled_r.recv_timeout(Duration::from_secs(5)),
+            Ok(ds::Type::BarrierReply)
+        );
+    }
+
+    #[test]
+    fn controller_truncates_unknown_flags_with_the_policy() {
+        let (event_s, event_r) = channel();
+        let mut config = ControllerConfig::default();
+        config.event_ch = Some(event_s);
+        config.flags_policy = FlagsPolicy::Truncate;
+        let (port_s, port_r) = channel();
+        let port_s = Mutex::new(port_s);
+        let switch = start(config, move |msg| {
+            if let ds::OfPayload::PortStatus(ref status) = *msg.msg.payload() {
+                let _ = port_s.lock().unwrap().send(*status.desc().config());
+            }
+        });
+        assert!(switch.wait_for_handshake(Duration::from_secs(5)));
+
+        let status = factory::port_status(PortReason::Modifiy, 1);
+        let msg =
+            ds::OfMsg::generate_for(ds::Version::V1_3, 0x100, ds::OfPayload::PortStatus(status));
+        let mut bytes = msg.unwrap().encode().unwrap();
+        // bit 31 of the config of the port
+        bytes[48] |= 0x80;
+        switch.send_raw(&bytes).unwrap();
+
+        let config = port_r.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(config, MockSwitch::ports()[0].config().clone());
+        let unknown = event_r
+            .iter()
+            .filter_map(|event| match event {
+                ControllerEvent::UnknownFlags {
+                    datapath_id,
+                    ttype,
+                    flags,
+                    bits,
+                    ..
+                } => Some((datapath_id, ttype, flags, bits)),
+                _ => None,
+            })
+            .next();
+        assert_eq!(
+            unknown,
+            Some((Some(0x2a), ds::Type::PortStatus, "PortConfig", 0x8000_0000))
+        );
+    }
+
+    #[test]
+    fn controller_copies_messages_to_the_tap() {
+        let tap = Arc::new(Tap::new());
+        let events = tap.subscribe();
+        let mut config = ControllerConfig::default();
+        config.tap = Some(tap);
+        let switch = start(config, |_| ());
+        assert!(switch.wait_for_handshake(Duration::from_secs(5)));
+
+        let mut seen = Vec::new();
+        while let Ok(event) = events.recv_timeout(Duration::from_secs(5)) {
+            seen.push((event.direction, event.ttype.clone()));
+            if event.ttype == ds::Type::FeaturesReply {
+                assert_eq!(event.datapath_id, None);
+                break;
+            }
+        }
+        assert!(seen.contains(&(Direction::Incoming, ds::Type::Hello)));
+        assert!(seen.contains(&(Direction::Outgoing, ds::Type::FeaturesRequest)));
+        assert!(seen.contains(&(Direction::Incoming, ds::Type::FeaturesReply)));
+    }
+
+    /// pipe whose writes block until their timeout once the switch stops reading
+    #[derive(Debug, Clone)]
+    struct Stalling {
+        pipe: Pipe,
+        stalled: Arc<AtomicBool>,
+        write_timeout: Arc<Mutex<Option<Duration>>>,
+    }
+
+    impl ::std::io::Read for Stalling {
+        fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+            self.pipe.read(buf)
+        }
+    }
+
+    impl Write for Stalling {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            if !self.stalled.load(Ordering::SeqCst) {
+                return self.pipe.write(buf);
+            }
+            let timeout = *self.write_timeout.lock().unwrap();
+            thread::sleep(timeout.unwrap_or(Duration::from_secs(60)));
+            Err(::std::io::ErrorKind::WouldBlock.into())
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for Stalling {
+        fn try_clone(&self) -> ::std::io::Result<Self> {
+            Ok(self.clone())
+        }
+
+        fn shutdown(&self) -> ::std::io::Result<()> {
+            Transport::shutdown(&self.pipe)
+        }
+
+        fn set_write_timeout(&self, timeout: Option<Duration>) -> ::std::io::Result<()> {
+            *self.write_timeout.lock().unwrap() = timeout;
+            Ok(())
+        }
+    }
+
+    struct StallingListener {
+        listener: PipeListener,
+        stalled: Arc<AtomicBool>,
+    }
+
+    impl Accept for StallingListener {
+        type Transport = Stalling;
+
+        fn accept(&self) -> ::std::io::Result<Stalling> {
+            Ok(Stalling {
+                pipe: self.listener.accept()?,
+                stalled: self.stalled.clone(),
+                write_timeout: Arc::new(Mutex::new(None)),
+            })
+        }
+
+        fn name(&self) -> String {
+            "stalling".to_string()
+        }
+    }
+
+    #[test]
+    fn controller_closes_stalled_connections() {
+        let (listener, connector) = pipe_listener();
+        let stalled = Arc::new(AtomicBool::new(false));
+        let listener = StallingListener {
+            listener: listener,
+            stalled: stalled.clone(),
+        };
+        let (event_s, event_r) = channel();
+        let mut config = ControllerConfig::default();
+        config.event_ch = Some(event_s);
+        config.write_deadline = Some(Duration::from_millis(100));
+        let registry = config.registry.clone();
+        thread::spawn(move || {
+            start_controller_with_listener(listener, config, |_| ()).unwrap();
+        });
+
+        let switch = MockSwitch::over(
+            connector.connect().unwrap(),
+            MockSwitch::default_features(0x2a),
+        )
+        .unwrap();
+        assert!(switch.wait_for_handshake(Duration::from_secs(5)));
+        let mut handle = None;
+        for _ in 0..100 {
+            handle = registry.get(0x2a);
+            if handle.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // the switch stops reading, the write is given up after the deadline
+        stalled.store(true, Ordering::SeqCst);
+        handle
+            .unwrap()
+            .send_payload(ds::OfPayload::EchoRequest(vec![0; 64]))
+            .unwrap();
+        let pending = event_r
+            .iter()
+            .filter_map(|event| match event {
+                ControllerEvent::WriteStalled {
+                    datapath_id,
+                    pending,
+                    ..
+                } => Some((datapath_id, pending)),
+                _ => None,
+            })
+            .next();
+        match pending {
+            Some((datapath_id, pending)) => {
+                assert_eq!(datapath_id, Some(0x2a));
+                assert!(pending >= ds::HEADER_LENGTH + 64);
+            }
+            None => panic!("no WriteStalled event"),
+        }
+        // and the connection is closed
+        for _ in 0..100 {
+            if registry.get(0x2a).is_none() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(registry.get(0x2a).is_none());
+    }
+
+    /// pipe whose writes wait while the switch is paused
+    #[derive(Debug, Clone)]
+    struct Paused {
+        pipe: Pipe,
+        paused: Arc<AtomicBool>,
+    }
+
+    impl ::std::io::Read for Paused {
+        fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+            self.pipe.read(buf)
+        }
+    }
+
+    impl Write for Paused {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            while self.paused.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            self.pipe.write(buf)
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for Paused {
+        fn try_clone(&self) -> ::std::io::Result<Self> {
+            Ok(self.clone())
+        }
+
+        fn shutdown(&self) -> ::std::io::Result<()> {
+            Transport::shutdown(&self.pipe)
+        }
+    }
+
+    struct PausedListener {
+        listener: PipeListener,
+        paused: Arc<AtomicBool>,
+    }
+
+    impl Accept for PausedListener {
+        type Transport = Paused;
+
+        fn accept(&self) -> ::std::io::Result<Paused> {
+            Ok(Paused {
+                pipe: self.listener.accept()?,
+                paused: self.paused.clone(),
+            })
+        }
+
+        fn name(&self) -> String {
+            "paused".to_string()
+        }
+    }
+
+    #[test]
+    fn controller_reports_the_messages_waiting_for_slow_consumers() {
+        let (listener, connector) = pipe_listener();
+        let paused = Arc::new(AtomicBool::new(false));
+        let listener = PausedListener {
+            listener: listener,
+            paused: paused.clone(),
+        };
+        let (event_s, event_r) = channel();
+        let mut config = ControllerConfig::default();
+        config.event_ch = Some(event_s);
+        config.queue_warning_threshold = Some(50);
+        let registry = config.registry.clone();
+        thread::spawn(move || {
+            start_controller_with_listener(listener, config, |_| ()).unwrap();
+        });
+
+        let switch = MockSwitch::over(
+            connector.connect().unwrap(),
+            MockSwitch::default_features(0x2a),
+        )
+        .unwrap();
+        assert!(switch.wait_for_handshake(Duration::from_secs(5)));
+        let mut handle = None;
+        for _ in 0..100 {
+            handle = registry.get(0x2a);
+            if handle.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let handle = handle.unwrap();
+
+        // more messages than fit into the output buffer pile up while the
+        // switch does not read, all of them count, not only the next batch
+        paused.store(true, Ordering::SeqCst);
+        for _ in 0..100 {
+            handle
+                .send_payload(ds::OfPayload::EchoRequest(vec![0; 4096]))
+                .unwrap();
+        }
+        paused.store(false, Ordering::SeqCst);
+        let mut queue_depth = None;
+        while let Ok(event) = event_r.recv_timeout(Duration::from_secs(5)) {
+            if let ControllerEvent::SlowConsumer {
+                datapath_id,
+                queue_depth: depth,
+                ..
+            } = event
+            {
+                assert_eq!(datapath_id, Some(0x2a));
+                queue_depth = Some(depth);
+                break;
+            }
+        }
+        match queue_depth {
+            Some(depth) => assert!(depth > 50 && depth <= 100),
+            None => panic!("no SlowConsumer event"),
+        }
+        for _ in 0..100 {
+            if handle.stats().queue_depth == 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(handle.stats().queue_depth, 0);
+    }
+
+    #[test]
+    fn controller_keeps_bad_frames() {
+        let bad_frames = Arc::new(BadFrames::new(4));
+        let mut config = ControllerConfig::default();
+        config.bad_frames = Some(bad_frames.clone());
+        let switch = start(config, |_| ());
+        assert!(switch.wait_for_handshake(Duration::from_secs(5)));
+
+        // a packet in that ends after the buffer id
+        let truncated = [0x04, 0x0a, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x05, 0, 0, 0, 1];
+        switch.send_raw(&truncated).unwrap();
+        for _ in 0..100 {
+            if bad_frames.total() > 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let frames = bad_frames.recent();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].bytes, truncated.to_vec());
+        assert_eq!(frames[0].context.ttype, Some(ds::Type::PacketIn));
+        assert_eq!(frames[0].context.xid, Some(5));
+        assert!(frames[0].hexdump().starts_with("0000  04 0a 00 0c"));
+    }
+}
@@ -0,0 +1,167 @@
+//! Streams the connections to switches run on.
+//!
+//! The connection code only needs a `Transport`: a byte stream that can be
+//! read by one thread and written by another and shut down from both. It is
+//! implemented for tcp streams and, on unix systems, Unix domain sockets, eg.
+//! for the `unix:` and `punix:` control channels of Open vSwitch running on
+//! the same host (see `start_controller_unix` and `connect_to_switch_unix`).
+//! Other streams (eg. tls) implement it to reuse the framing, the handshake
+//! and everything else of the controller, an `Accept` implementation hands
+//! them to `start_controller_with_listener`.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::Duration;
+
+use super::super::err::*;
+use super::socket::SocketOptions;
+
+/// Connection to a switch, see the module documentation.
+pub trait Transport: Read + Write + Send + Sized + 'static {
+    /// another handle to the same connection, the input thread reads from one
+    /// handle while the output thread writes to the other
+    fn try_clone(&self) -> io::Result<Self>;
+
+    /// closes the connection for all handles, blocked reads return
+    fn shutdown(&self) -> io::Result<()>;
+
+    /// address of the switch, None if the transport has none
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// address of the controller end, None if the transport has none
+    fn local_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// sets the options that apply to the transport, nothing by default
+    fn apply(&self, _options: &SocketOptions) -> Result<()> {
+        Ok(())
+    }
+
+    /// limits how long a write blocks, longer writes fail with WouldBlock or
+    /// TimedOut, writes are not limited by default
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// names the connection in logs and thread names
+    fn name(&self) -> String {
+        match self.peer_addr() {
+            Some(peer) => peer.to_string(),
+            None => "unknown peer".to_string(),
+        }
+    }
+}
+
+impl Transport for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        TcpStream::peer_addr(self).ok()
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        TcpStream::local_addr(self).ok()
+    }
+
+    fn apply(&self, options: &SocketOptions) -> Result<()> {
+        options.apply(self)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+}
+
+/// only the timeouts of the socket options apply
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn apply(&self, options: &SocketOptions) -> Result<()> {
+        self.set_read_timeout(options.read_timeout)?;
+        UnixStream::set_write_timeout(self, options.write_timeout)?;
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+
+    /// the socket path, accepted streams only know the path of the listener
+    fn name(&self) -> String {
+        let path = UnixStream::peer_addr(self)
+            .ok()
+            .and_then(|addr| addr.as_pathname().map(|path| path.to_path_buf()))
+            .or_else(|| {
+                let addr = UnixStream::local_addr(self).ok()?;
+                addr.as_pathname().map(|path| path.to_path_buf())
+            });
+        match path {
+            Some(path) => format!("unix:{}", path.display()),
+            None => "unix".to_string(),
+        }
+    }
+}
+
+/// Source of the connections of switches, eg. a listener that wraps the
+/// accepted tcp streams in tls.
+pub trait Accept: Send + 'static {
+    type Transport: Transport;
+
+    /// waits for the next connection
+    fn accept(&self) -> io::Result<Self::Transport>;
+
+    /// names the listener in logs and thread names
+    fn name(&self) -> String;
+}
+
+impl Accept for TcpListener {
+    type Transport = TcpStream;
+
+    fn accept(&self) -> io::Result<TcpStream> {
+        TcpListener::accept(self).map(|(stream, _)| stream)
+    }
+
+    fn name(&self) -> String {
+        match TcpListener::local_addr(self) {
+            Ok(addr) => addr.to_string(),
+            Err(_) => "tcp".to_string(),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Accept for UnixListener {
+    type Transport = UnixStream;
+
+    fn accept(&self) -> io::Result<UnixStream> {
+        UnixListener::accept(self).map(|(stream, _)| stream)
+    }
+
+    fn name(&self) -> String {
+        let path = UnixListener::local_addr(self)
+            .ok()
+            .and_then(|addr| addr.as_pathname().map(|path| path.to_path_buf()));
+        match path {
+            Some(path) => format!("unix:{}", path.display()),
+            None => "unix".to_string(),
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use num_traits::{FromPrimitive, ToPrimitive};
+
+use super::super::ds::{Version, MAX_VERSION};
+
+/// Version negotiated on a connection, shared between its threads.
+/// Until the hello of the switch is received it is the highest version offered.
+#[derive(Debug)]
+pub struct NegotiatedVersion {
+    version: AtomicUsize,
+    max: Version,
+}
+
+impl NegotiatedVersion {
+    pub fn new() -> Self {
+        NegotiatedVersion::with_max(MAX_VERSION)
+    }
+
+    /// never negotiates a version above max
+    pub fn with_max(max: Version) -> Self {
+        let max = ::std::cmp::min(max, MAX_VERSION);
+        NegotiatedVersion {
+            version: AtomicUsize::new(max.to_u8().unwrap() as usize),
+            max: max,
+        }
+    }
+
+    pub fn get(&self) -> Version {
+        Version::from_u8(self.version.load(Ordering::SeqCst) as u8).unwrap()
+    }
+
+    /// negotiates using the version of the hello received from the switch
+    pub fn negotiate(&self, peer: Version) -> Version {
+        let version = ::std::cmp::min(Version::negotiate(peer), self.max);
+        self.version
+            .store(version.to_u8().unwrap() as usize, Ordering::SeqCst);
+        version
+    }
+}
+
+impl Default for NegotiatedVersion {
+    fn default() -> Self {
+        NegotiatedVersion::new()
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Xids from here up to u32::MAX are reserved for messages the controller
+/// sends on its own (eg. keepalive echo requests).
+pub const XID_RESERVED_START: u32 = 0xffff_0000;
+
+/// Hands out transaction ids for messages sent over one connection.
+/// Xids never are 0, wrap around before the reserved range and
+/// can be taken from several threads at once.
+#[derive(Debug, Default)]
+pub struct XidAllocator {
+    next: AtomicUsize,
+    next_reserved: AtomicUsize,
+}
+
+impl XidAllocator {
+    pub fn new() -> Self {
+        XidAllocator::default()
+    }
+
+    /// returns the next xid for application and handshake messages
+    pub fn next(&self) -> u32 {
+        let count = self.next.fetch_add(1, Ordering::SeqCst);
+        // xids 1 ..= XID_RESERVED_START - 1
+        (count % (XID_RESERVED_START as usize - 1)) as u32 + 1
+    }
+
+    /// returns the next xid of the reserved range
+    pub fn next_reserved(&self) -> u32 {
+        let count = self.next_reserved.fetch_add(1, Ordering::SeqCst);
+        let range = (u32::MAX - XID_RESERVED_START) as usize + 1;
+        XID_RESERVED_START + (count % range) as u32
+    }
+
+    /// checks if a xid belongs to the reserved range
+    pub fn is_reserved(xid: u32) -> bool {
+        xid >= XID_RESERVED_START
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xids_start_at_one() {
+        let xids = XidAllocator::new();
+        assert_eq!(xids.next(), 1);
+        assert_eq!(xids.next(), 2);
+    }
+
+    #[test]
+    fn xids_wrap_around_before_the_reserved_range() {
+        let xids = XidAllocator::new();
+        xids.next
+            .store(XID_RESERVED_START as usize - 3, Ordering::SeqCst);
+        assert_eq!(xids.next(), XID_RESERVED_START - 2);
+        assert_eq!(xids.next(), XID_RESERVED_START - 1);
+        // 0 is skipped
+        assert_eq!(xids.next(), 1);
+        assert!(!XidAllocator::is_reserved(XID_RESERVED_START - 1));
+    }
+
+    #[test]
+    fn reserved_xids_stay_in_their_range() {
+        let xids = XidAllocator::new();
+        assert_eq!(xids.next_reserved(), XID_RESERVED_START);
+        assert!(XidAllocator::is_reserved(XID_RESERVED_START));
+        let range = (u32::MAX - XID_RESERVED_START) as usize;
+        xids.next_reserved.store(range, Ordering::SeqCst);
+        assert_eq!(xids.next_reserved(), u32::MAX);
+        assert_eq!(xids.next_reserved(), XID_RESERVED_START);
+        // the ranges do not share xids
+        assert_eq!(xids.next(), 1);
+    }
+}
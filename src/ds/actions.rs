@@ -1,13 +1,18 @@
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use num_traits::{FromPrimitive, ToPrimitive};
 use std::convert::{Into, TryFrom};
-use std::io::{Cursor, Seek, SeekFrom};
+use std::io::Cursor;
 
 use super::super::err::*;
+use super::addr::{Ipv4Addr, Ipv6Addr, MacAddr};
 use super::flow_match::*;
+use super::group_mod::GroupNumber;
 use super::ports::PortNumber;
-
-use std::path;
+use super::checked_slice;
+use super::codec::OfEncode;
+use super::tlv::{decode_list, LengthField};
+#[cfg(feature = "nicira")]
+use super::nx::{NxAction, NX_VENDOR_ID};
 
 #[derive(Primitive, Debug, PartialEq, Clone)]
 pub enum ActionType {
@@ -43,14 +48,28 @@ pub enum ActionType {
     PushPbb = 26,
     /// Pop the outer PBB service tag (I-TAG)
     PopPbb = 27,
-    // not supported
-    //Experimenter = 0xffff,
+    /// Experimenter action, see PayloadExperimenter.
+    Experimenter = 0xffff,
+}
+
+/// actions that send packets out of the port through the queue (set_queue, output)
+/// the queue is not checked, see SwitchHandle::enqueue
+pub fn enqueue(port: PortNumber, queue_id: u32) -> Vec<ActionHeader> {
+    vec![
+        ActionPayload::SetQueue(PayloadSetQueue { queue_id: queue_id }).into(),
+        PayloadOutput {
+            port: port,
+            max_len: 0,
+        }
+        .into(),
+    ]
 }
 
 pub fn calc_actions_len(actions: &Vec<ActionHeader>) -> u16 {
-    let mut actions_len = 0;
+    let mut actions_len = 0u16;
     for action in actions {
-        actions_len += action.len();
+        // saturates, the message is too long to be encoded anyway
+        actions_len = actions_len.saturating_add(*action.len());
     }
     actions_len
 }
@@ -59,45 +78,39 @@ pub const ACTION_HEADER_LEN: u16 = 4;
 
 #[derive(Getters, Debug, PartialEq, Clone)]
 pub struct ActionHeader {
+    #[get = "pub"]
     ttype: ActionType,
     #[get = "pub"]
     len: u16,
+    #[get = "pub"]
     payload: ActionPayload,
 }
 
 impl ActionHeader {
     pub fn read_len(cursor: &mut Cursor<&[u8]>) -> Result<usize> {
-        // go to len position in the raw bytes
-        cursor.seek(SeekFrom::Current(2)).unwrap();
-        // read value and handle errors
-        let len = match cursor.read_u16::<BigEndian>() {
-            Ok(len) => len,
-            Err(err) => {
-                error!(
-                    "Could not read packet queue len.{}{:?}{}{}",
-                    path::MAIN_SEPARATOR,
-                    cursor,
-                    path::MAIN_SEPARATOR,
-                    err
-                );
-                bail!(ErrorKind::CouldNotReadLength(2, stringify!(PacketQueue),))
-            }
-        };
-        // go back to start
-        cursor.seek(SeekFrom::Current(-4)).unwrap();
-        Ok(len as usize)
+        let pos = cursor.position() as usize;
+        ACTION_LEN_FIELD.read(cursor.get_ref(), pos, stringify!(ActionHeader))
     }
 }
 
+/// the length includes the header, anything shorter is corrupt
+pub const ACTION_LEN_FIELD: LengthField =
+    LengthField::u16_at(2, ACTION_HEADER_LEN as usize);
+
+/// decodes a list of actions that fills the whole slice
+pub fn actions_from_slice(bytes: &[u8]) -> Result<Vec<ActionHeader>> {
+    decode_list(bytes, 0, ACTION_LEN_FIELD, stringify!(ActionHeader))
+}
+
 impl<'a> TryFrom<&'a [u8]> for ActionHeader {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let raw_ttype = cursor.read_u16::<BigEndian>().unwrap();
+        let raw_ttype = cursor.read_u16::<BigEndian>()?;
         let ttype = ActionType::from_u16(raw_ttype).ok_or::<Error>(
             ErrorKind::UnknownValue(raw_ttype as u64, stringify!(ActionType)).into(),
         )?;
-        let len = cursor.read_u16::<BigEndian>().unwrap();
+        let len = cursor.read_u16::<BigEndian>()?;
         let payload = try_from_action_payload(&bytes[4..], &ttype)?;
         Ok(ActionHeader {
             ttype: ttype,
@@ -107,14 +120,24 @@ impl<'a> TryFrom<&'a [u8]> for ActionHeader {
     }
 }
 
+impl OfEncode for ActionHeader {
+    fn encoded_len(&self) -> usize {
+        ACTION_HEADER_LEN as usize + self.payload.encoded_len()
+    }
+
+    /// the length is computed from the payload, the stored one is ignored
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.write_u16::<BigEndian>(self.ttype.to_u16().unwrap())
+            .unwrap();
+        buf.write_u16::<BigEndian>(self.encoded_len() as u16)
+            .unwrap();
+        self.payload.encode_into(buf);
+    }
+}
+
 impl Into<Vec<u8>> for ActionHeader {
     fn into(self) -> Vec<u8> {
-        let mut res = Vec::new();
-        res.write_u16::<BigEndian>(self.ttype.to_u16().unwrap())
-            .unwrap();
-        res.write_u16::<BigEndian>(self.len).unwrap();
-        res.extend_from_slice(&Into::<Vec<u8>>::into(self.payload)[..]);
-        res
+        self.to_bytes()
     }
 }
 
@@ -136,7 +159,10 @@ pub enum ActionPayload {
     SetField(PayloadSetField),
     PushPbb(PayloadPushPbb),
     PopPbb(PayloadPopPbb),
-    //Experimenter(PayloadExperimenter),
+    Experimenter(PayloadExperimenter),
+    /// Nicira experimenter action, decoded instead of Experimenter
+    #[cfg(feature = "nicira")]
+    Nicira(NxAction),
 }
 
 impl Into<Vec<u8>> for ActionPayload {
@@ -158,6 +184,53 @@ impl Into<Vec<u8>> for ActionPayload {
             ActionPayload::SetField(payload) => payload.into(),
             ActionPayload::PushPbb(payload) => payload.into(),
             ActionPayload::PopPbb(payload) => payload.into(),
+            ActionPayload::Experimenter(payload) => payload.into(),
+            #[cfg(feature = "nicira")]
+            ActionPayload::Nicira(action) => {
+                let mut res = Vec::new();
+                res.write_u32::<BigEndian>(NX_VENDOR_ID).unwrap();
+                res.extend_from_slice(&Into::<Vec<u8>>::into(action)[..]);
+                res
+            }
+        }
+    }
+}
+
+impl ActionPayload {
+    /// returns the action type of this payload
+    pub fn ttype(&self) -> ActionType {
+        match self {
+            ActionPayload::Output(_) => ActionType::Output,
+            ActionPayload::CopyTtlOut(_) => ActionType::CopyTtlOut,
+            ActionPayload::CopyTtlIn(_) => ActionType::CopyTtlIn,
+            ActionPayload::SetMplsTtl(_) => ActionType::SetMplsTtl,
+            ActionPayload::DecMplsTtl(_) => ActionType::DecMplsTtl,
+            ActionPayload::PushVlan(_) => ActionType::PushVlan,
+            ActionPayload::PopVlan(_) => ActionType::PopVlan,
+            ActionPayload::PushMpls(_) => ActionType::PushMpls,
+            ActionPayload::PopMpls(_) => ActionType::PopMpls,
+            ActionPayload::SetQueue(_) => ActionType::SetQueue,
+            ActionPayload::Group(_) => ActionType::Group,
+            ActionPayload::SetNwTtl(_) => ActionType::SetNwTtl,
+            ActionPayload::DecNwTtl(_) => ActionType::DecNwTtl,
+            ActionPayload::SetField(_) => ActionType::SetField,
+            ActionPayload::PushPbb(_) => ActionType::PushPbb,
+            ActionPayload::PopPbb(_) => ActionType::PopPbb,
+            ActionPayload::Experimenter(_) => ActionType::Experimenter,
+            #[cfg(feature = "nicira")]
+            ActionPayload::Nicira(_) => ActionType::Experimenter,
+        }
+    }
+}
+
+impl Into<ActionHeader> for ActionPayload {
+    fn into(self) -> ActionHeader {
+        let ttype = self.ttype();
+        let payload_len = Into::<Vec<u8>>::into(self.clone()).len() as u16;
+        ActionHeader {
+            ttype: ttype,
+            len: ACTION_HEADER_LEN + payload_len,
+            payload: self,
         }
     }
 }
@@ -180,9 +253,24 @@ fn try_from_action_payload(bytes: &[u8], ttype: &ActionType) -> Result<ActionPay
         ActionType::SetField => ActionPayload::SetField(PayloadSetField::try_from(bytes)?),
         ActionType::PushPbb => ActionPayload::PushPbb(PayloadPushPbb::try_from(bytes)?),
         ActionType::PopPbb => ActionPayload::PopPbb(PayloadPopPbb::try_from(bytes)?),
+        ActionType::Experimenter => experimenter_action(PayloadExperimenter::try_from(bytes)?)?,
     })
 }
 
+/// decodes the actions of known experimenters
+#[cfg(feature = "nicira")]
+fn experimenter_action(payload: PayloadExperimenter) -> Result<ActionPayload> {
+    if payload.experimenter == NX_VENDOR_ID {
+        return Ok(ActionPayload::Nicira(NxAction::try_from(&payload.data[..])?));
+    }
+    Ok(ActionPayload::Experimenter(payload))
+}
+
+#[cfg(not(feature = "nicira"))]
+fn experimenter_action(payload: PayloadExperimenter) -> Result<ActionPayload> {
+    Ok(ActionPayload::Experimenter(payload))
+}
+
 pub const PAYLOAD_OUTPUT_LEN: u16 = 12;
 
 /// Action structure for OFPAT_OUTPUT, which sends packets out 'port'.
@@ -202,11 +290,11 @@ impl<'a> TryFrom<&'a [u8]> for PayloadOutput {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let raw_port = cursor.read_u32::<BigEndian>().unwrap();
+        let raw_port = cursor.read_u32::<BigEndian>()?;
         let port = PortNumber::try_from(raw_port)?;
         Ok(PayloadOutput {
             port: port,
-            max_len: cursor.read_u16::<BigEndian>().unwrap(),
+            max_len: cursor.read_u16::<BigEndian>()?,
         })
         //pad 6 bytes by ignoring them
     }
@@ -236,7 +324,7 @@ impl Into<Vec<u8>> for PayloadOutput {
 /// Action structure for OFPAT_GROUP.
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadGroup {
-    group_id: u32,
+    pub group_id: GroupNumber,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadGroup {
@@ -244,7 +332,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadGroup {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadGroup {
-            group_id: cursor.read_u32::<BigEndian>().unwrap(),
+            group_id: GroupNumber::try_from(cursor.read_u32::<BigEndian>()?)?,
         })
     }
 }
@@ -252,7 +340,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadGroup {
 impl Into<Vec<u8>> for PayloadGroup {
     fn into(self) -> Vec<u8> {
         let mut res = Vec::new();
-        res.write_u32::<BigEndian>(self.group_id).unwrap();
+        res.write_u32::<BigEndian>(self.group_id.into()).unwrap();
         res
     }
 }
@@ -260,7 +348,7 @@ impl Into<Vec<u8>> for PayloadGroup {
 /// Action structure for OFPAT_GROUP.
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadSetQueue {
-    queue_id: u32,
+    pub queue_id: u32,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadSetQueue {
@@ -268,7 +356,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadSetQueue {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadSetQueue {
-            queue_id: cursor.read_u32::<BigEndian>().unwrap(),
+            queue_id: cursor.read_u32::<BigEndian>()?,
         })
     }
 }
@@ -284,7 +372,7 @@ impl Into<Vec<u8>> for PayloadSetQueue {
 /// Action structure for OFPAT_GROUP.
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadSetMplsTtl {
-    mpls_ttl: u8,
+    pub mpls_ttl: u8,
     // pad 3 bytes
 }
 
@@ -293,7 +381,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadSetMplsTtl {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadSetMplsTtl {
-            mpls_ttl: cursor.read_u8().unwrap(),
+            mpls_ttl: cursor.read_u8()?,
         })
         // pad 3 bytes by ignoring them
     }
@@ -334,7 +422,7 @@ impl Into<Vec<u8>> for PayloadDecMplsTtl {
 /// Action structure for OFPAT_GROUP.
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadSetNwTtl {
-    nw_ttl: u8,
+    pub nw_ttl: u8,
     // pad 3 bytes
 }
 
@@ -343,7 +431,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadSetNwTtl {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadSetNwTtl {
-            nw_ttl: cursor.read_u8().unwrap(),
+            nw_ttl: cursor.read_u8()?,
         })
         // pad 3 bytes by ignoring them
     }
@@ -428,7 +516,7 @@ impl Into<Vec<u8>> for PayloadCopyTtlIn {
 /// Action structure for OFPAT_GROUP.
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadPushVlan {
-    ethertype: EtherType,
+    pub ethertype: EtherType,
     // pad 2 bytes
 }
 
@@ -436,7 +524,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadPushVlan {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let raw_ethertpye = cursor.read_u16::<BigEndian>().unwrap();
+        let raw_ethertpye = cursor.read_u16::<BigEndian>()?;
         let ethertype = EtherType::from_u16(raw_ethertpye).ok_or::<Error>(
             ErrorKind::UnknownValue(raw_ethertpye as u64, stringify!(EtherType)).into(),
         )?;
@@ -460,7 +548,7 @@ impl Into<Vec<u8>> for PayloadPushVlan {
 /// Action structure for OFPAT_GROUP.
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadPushMpls {
-    ethertype: EtherType,
+    pub ethertype: EtherType,
     // pad 2 bytes
 }
 
@@ -468,7 +556,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadPushMpls {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let raw_ethertpye = cursor.read_u16::<BigEndian>().unwrap();
+        let raw_ethertpye = cursor.read_u16::<BigEndian>()?;
         let ethertype = EtherType::from_u16(raw_ethertpye).ok_or::<Error>(
             ErrorKind::UnknownValue(raw_ethertpye as u64, stringify!(EtherType)).into(),
         )?;
@@ -492,7 +580,7 @@ impl Into<Vec<u8>> for PayloadPushMpls {
 /// Action structure for OFPAT_GROUP.
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadPushPbb {
-    ethertype: EtherType,
+    pub ethertype: EtherType,
     // pad 2 bytes
 }
 
@@ -500,7 +588,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadPushPbb {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let raw_ethertpye = cursor.read_u16::<BigEndian>().unwrap();
+        let raw_ethertpye = cursor.read_u16::<BigEndian>()?;
         let ethertype = EtherType::from_u16(raw_ethertpye).ok_or::<Error>(
             ErrorKind::UnknownValue(raw_ethertpye as u64, stringify!(EtherType)).into(),
         )?;
@@ -546,7 +634,7 @@ impl Into<Vec<u8>> for PayloadPopVlan {
 /// Action structure for OFPAT_GROUP.
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadPopMpls {
-    ethertype: EtherType,
+    pub ethertype: EtherType,
     // pad 2 bytes
 }
 
@@ -554,7 +642,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadPopMpls {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let raw_ethertpye = cursor.read_u16::<BigEndian>().unwrap();
+        let raw_ethertpye = cursor.read_u16::<BigEndian>()?;
         let ethertype = EtherType::from_u16(raw_ethertpye).ok_or::<Error>(
             ErrorKind::UnknownValue(raw_ethertpye as u64, stringify!(EtherType)).into(),
         )?;
@@ -601,7 +689,7 @@ impl Into<Vec<u8>> for PayloadPopPbb {
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadSetField {
     // pad 4 bytes
-    field: TlvMatch,
+    pub field: TlvMatch,
     /* Followed by:
      * - Exactly (length - 4) (possibly 0) bytes containing OXM TLVs, then
      * - Exactly ((length + 7)/8*8 - length) (between 0 and 7) bytes of
@@ -615,22 +703,231 @@ impl<'a> TryFrom<&'a [u8]> for PayloadSetField {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let tlv_header = OxmTlvHeader(cursor.read_u32::<BigEndian>().unwrap());
-        let field = TlvMatch::try_from(tlv_header, &bytes[4..])?;
+        let tlv_header = OxmTlvHeader(cursor.read_u32::<BigEndian>()?);
+        let field_len = tlv_header.get_length() as usize;
+        let field_slice = checked_slice(bytes, 4, field_len, stringify!(PayloadSetField))?;
+        let field = TlvMatch::try_from(tlv_header, field_slice)?;
+        // decoded as sent, PayloadSetField::new checks the field
         Ok(PayloadSetField { field: field })
         // pad n bytes by ignoring them
     }
 }
 
+/// checks that a value fits into the given number of bits
+fn check_bits(value: u64, bits: u32, field: &'static str) -> Result<()> {
+    if value >> bits != 0 {
+        bail!(ErrorKind::IllegalValue(value, field));
+    }
+    Ok(())
+}
+
+impl PayloadSetField {
+    /// sets the field to the value, set-field actions are never masked
+    /// fails for fields that can not be set
+    /// (in_port, in_phy_port, metadata, ipv6_exthdr and packet_type)
+    pub fn new(payload: MatchPayload) -> Result<Self> {
+        match payload {
+            MatchPayload::InPort(_)
+            | MatchPayload::InPhyPort(_)
+            | MatchPayload::Metadata(_)
+            | MatchPayload::IPv6ExtHdr(_)
+            | MatchPayload::PacketType(_) => {
+                let field = TlvMatch::new(payload).tlv_header.get_oxm_field();
+                bail!(ErrorKind::IllegalValue(
+                    field as u64,
+                    stringify!(PayloadSetField)
+                ))
+            }
+            _ => Ok(PayloadSetField {
+                field: TlvMatch::new(payload),
+            }),
+        }
+    }
+
+    fn unchecked(payload: MatchPayload) -> Self {
+        PayloadSetField {
+            field: TlvMatch::new(payload),
+        }
+    }
+
+    pub fn eth_dst(eth_dst: MacAddr) -> Self {
+        PayloadSetField::unchecked(MatchPayload::EthDst(PayloadEthDst { eth_dst: eth_dst }))
+    }
+
+    pub fn eth_src(eth_src: MacAddr) -> Self {
+        PayloadSetField::unchecked(MatchPayload::EthSrc(PayloadEthSrc { eth_src: eth_src }))
+    }
+
+    pub fn eth_type(ttype: EtherType) -> Self {
+        PayloadSetField::unchecked(MatchPayload::EthType(PayloadEthType { ttype: ttype }))
+    }
+
+    /// sets the vid of the outer vlan tag (the present bit is added)
+    /// fails for vids above VID_MAX
+    pub fn vlan_vid(vid: u16) -> Result<Self> {
+        let vid = VlanVid::vid(vid)?;
+        Ok(PayloadSetField::unchecked(MatchPayload::VlanVId(vid.into())))
+    }
+
+    /// fails for priorities above 7
+    pub fn vlan_pcp(vlan_pcp: u8) -> Result<Self> {
+        check_bits(vlan_pcp as u64, 3, stringify!(PayloadVlanPcp))?;
+        Ok(PayloadSetField::unchecked(MatchPayload::VlanPcp(
+            PayloadVlanPcp { vlan_pcp: vlan_pcp },
+        )))
+    }
+
+    /// fails for values above 63
+    pub fn ip_dscp(ip_dscp: u8) -> Result<Self> {
+        check_bits(ip_dscp as u64, 6, stringify!(PayloadIpDscp))?;
+        Ok(PayloadSetField::unchecked(MatchPayload::IpDscp(
+            PayloadIpDscp { ip_dscp: ip_dscp },
+        )))
+    }
+
+    /// fails for values above 3
+    pub fn ip_ecn(ip_ecn: u8) -> Result<Self> {
+        check_bits(ip_ecn as u64, 2, stringify!(PayloadIpEcn))?;
+        Ok(PayloadSetField::unchecked(MatchPayload::IpEcn(
+            PayloadIpEcn { ip_enc: ip_ecn },
+        )))
+    }
+
+    pub fn ipv4_src(ipv4_src: Ipv4Addr) -> Self {
+        PayloadSetField::unchecked(MatchPayload::IPv4Src(PayloadIPv4Src { ipv4_src: ipv4_src }))
+    }
+
+    pub fn ipv4_dst(ipv4_dst: Ipv4Addr) -> Self {
+        PayloadSetField::unchecked(MatchPayload::IPv4Dst(PayloadIPv4Dst { ipv4_dst: ipv4_dst }))
+    }
+
+    pub fn ipv6_src(ipv6_src: Ipv6Addr) -> Self {
+        PayloadSetField::unchecked(MatchPayload::IPv6Src(PayloadIPv6Src { ipv6_src: ipv6_src }))
+    }
+
+    pub fn ipv6_dst(ipv6_dst: Ipv6Addr) -> Self {
+        PayloadSetField::unchecked(MatchPayload::IPv6Dst(PayloadIPv6Dst { ipv6_dst: ipv6_dst }))
+    }
+
+    pub fn tcp_src(port: u16) -> Self {
+        PayloadSetField::unchecked(MatchPayload::TcpSrc(PayloadTcpSrc { src_port: port }))
+    }
+
+    pub fn tcp_dst(port: u16) -> Self {
+        PayloadSetField::unchecked(MatchPayload::TcpDst(PayloadTcpDst { dst_port: port }))
+    }
+
+    pub fn udp_src(port: u16) -> Self {
+        PayloadSetField::unchecked(MatchPayload::UdpSrc(PayloadUdpSrc { src_port: port }))
+    }
+
+    pub fn udp_dst(port: u16) -> Self {
+        PayloadSetField::unchecked(MatchPayload::UdpDst(PayloadUdpDst { dst_port: port }))
+    }
+
+    pub fn arp_spa(arp_spa: Ipv4Addr) -> Self {
+        PayloadSetField::unchecked(MatchPayload::ArpSpa(PayloadArpSpa { arp_spa: arp_spa }))
+    }
+
+    pub fn arp_tpa(arp_tpa: Ipv4Addr) -> Self {
+        PayloadSetField::unchecked(MatchPayload::ArpTpa(PayloadArpTpa { arp_tpa: arp_tpa }))
+    }
+
+    pub fn arp_sha(arp_sha: MacAddr) -> Self {
+        PayloadSetField::unchecked(MatchPayload::ArpSha(PayloadArpSha { arp_sha: arp_sha }))
+    }
+
+    pub fn arp_tha(arp_tha: MacAddr) -> Self {
+        PayloadSetField::unchecked(MatchPayload::ArpTha(PayloadArpTha { arp_tha: arp_tha }))
+    }
+
+    /// fails for labels above 20 bits
+    pub fn mpls_label(label: u32) -> Result<Self> {
+        check_bits(label as u64, 20, stringify!(PayloadMplsLabel))?;
+        Ok(PayloadSetField::unchecked(MatchPayload::MplsLabel(
+            PayloadMplsLabel { label: label },
+        )))
+    }
+
+    /// fails for values above 7
+    pub fn mpls_tc(tc: u8) -> Result<Self> {
+        check_bits(tc as u64, 3, stringify!(PayloadMplsTc))?;
+        Ok(PayloadSetField::unchecked(MatchPayload::MplsTc(
+            PayloadMplsTc { tc: tc },
+        )))
+    }
+
+    pub fn tunnel_id(tunnel_id: u64) -> Self {
+        PayloadSetField::unchecked(MatchPayload::TunnelId(PayloadTunnelId {
+            metadata: tunnel_id,
+        }))
+    }
+}
+
+impl Into<ActionHeader> for PayloadSetField {
+    fn into(self) -> ActionHeader {
+        ActionPayload::SetField(self).into()
+    }
+}
+
 impl Into<Vec<u8>> for PayloadSetField {
     fn into(self) -> Vec<u8> {
         let mut res = Vec::new();
-        let len = self.field.tlv_header.get_length() + 4; //add 4 bytes from msg header
+        // add 4 bytes action header to the field including its oxm header
+        let len = self.field.encoded_len() + 4;
         res.extend_from_slice(&Into::<Vec<u8>>::into(self.field)[..]);
-        let pad_bytes_count = (len + 7) / 8 * 8 - len;
+        let pad_bytes_count = len.div_ceil(8) * 8 - len;
         for _ in 0..pad_bytes_count {
             res.write_u8(0).unwrap();
         }
         res
     }
 }
+
+/// Action structure for OFPAT_EXPERIMENTER.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PayloadExperimenter {
+    pub experimenter: u32,
+    /// experimenter defined data including the padding of the action
+    pub data: Vec<u8>,
+}
+
+impl<'a> TryFrom<&'a [u8]> for PayloadExperimenter {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(bytes);
+        Ok(PayloadExperimenter {
+            experimenter: cursor.read_u32::<BigEndian>()?,
+            data: Vec::from(&bytes[4..]),
+        })
+    }
+}
+
+impl PayloadExperimenter {
+    /// pads the data with zeros so the action is a multiple of 8 bytes long
+    pub fn new(experimenter: u32, mut data: Vec<u8>) -> Self {
+        // 4 bytes action header and 4 bytes experimenter id
+        while !data.len().is_multiple_of(8) {
+            data.push(0);
+        }
+        PayloadExperimenter {
+            experimenter: experimenter,
+            data: data,
+        }
+    }
+}
+
+impl Into<ActionHeader> for PayloadExperimenter {
+    fn into(self) -> ActionHeader {
+        ActionPayload::Experimenter(self).into()
+    }
+}
+
+impl Into<Vec<u8>> for PayloadExperimenter {
+    fn into(self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.write_u32::<BigEndian>(self.experimenter).unwrap();
+        res.extend_from_slice(&self.data[..]);
+        res
+    }
+}
@@ -0,0 +1,199 @@
+//! Addresses used in match fields and port descriptions.
+//! Every address type can be parsed from and printed in its usual notation
+//! (`aa:bb:cc:dd:ee:ff`, `10.0.0.1`, `fe80::1`).
+
+use std::fmt;
+use std::net;
+use std::str::FromStr;
+
+use super::super::err::*;
+
+/// length of a mac address in bytes (6)
+pub const MAC_ADDR_LEN: usize = 6;
+/// length of an ipv4 address in bytes (4)
+pub const IPV4_ADDR_LEN: usize = 4;
+/// length of an ipv6 address in bytes (16)
+pub const IPV6_ADDR_LEN: usize = 16;
+
+fn check_len(slice: &[u8], len: usize, ttype: &'static str) -> Result<()> {
+    if slice.len() != len {
+        bail!(ErrorKind::InvalidSliceLength(len, slice.len(), ttype));
+    }
+    Ok(())
+}
+
+/// Ethernet (mac) address.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy, Default)]
+pub struct MacAddr(pub [u8; MAC_ADDR_LEN]);
+
+impl MacAddr {
+    pub fn from_slice(slice: &[u8]) -> Result<Self> {
+        check_len(slice, MAC_ADDR_LEN, stringify!(MacAddr))?;
+        let mut addr = [0u8; MAC_ADDR_LEN];
+        addr.copy_from_slice(slice);
+        Ok(MacAddr(addr))
+    }
+
+    pub fn octets(&self) -> [u8; MAC_ADDR_LEN] {
+        self.0
+    }
+
+    /// ff:ff:ff:ff:ff:ff
+    pub fn broadcast() -> Self {
+        MacAddr([0xff; MAC_ADDR_LEN])
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == MacAddr::broadcast()
+    }
+
+    /// the group bit is set for multicast and broadcast addresses
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+}
+
+impl From<[u8; MAC_ADDR_LEN]> for MacAddr {
+    fn from(octets: [u8; MAC_ADDR_LEN]) -> Self {
+        MacAddr(octets)
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(|byte| format!("{:02x}", byte)).collect();
+        write!(f, "{}", parts.join(":"))
+    }
+}
+
+impl FromStr for MacAddr {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let mut addr = [0u8; MAC_ADDR_LEN];
+        let mut parts = s.split(':');
+        for byte in addr.iter_mut() {
+            *byte = match parts.next().map(|part| u8::from_str_radix(part, 16)) {
+                Some(Ok(value)) => value,
+                _ => bail!(ErrorKind::InvalidAddress(s.to_string(), stringify!(MacAddr))),
+            };
+        }
+        if parts.next().is_some() {
+            bail!(ErrorKind::InvalidAddress(s.to_string(), stringify!(MacAddr)));
+        }
+        Ok(MacAddr(addr))
+    }
+}
+
+/// IPv4 address, wraps the std type to add the wire conversions.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct Ipv4Addr(pub net::Ipv4Addr);
+
+impl Ipv4Addr {
+    pub fn from_slice(slice: &[u8]) -> Result<Self> {
+        check_len(slice, IPV4_ADDR_LEN, stringify!(Ipv4Addr))?;
+        Ok(Ipv4Addr(net::Ipv4Addr::new(
+            slice[0], slice[1], slice[2], slice[3],
+        )))
+    }
+
+    pub fn octets(&self) -> [u8; IPV4_ADDR_LEN] {
+        self.0.octets()
+    }
+}
+
+impl Default for Ipv4Addr {
+    /// 0.0.0.0
+    fn default() -> Self {
+        Ipv4Addr(net::Ipv4Addr::new(0, 0, 0, 0))
+    }
+}
+
+impl From<[u8; IPV4_ADDR_LEN]> for Ipv4Addr {
+    fn from(octets: [u8; IPV4_ADDR_LEN]) -> Self {
+        Ipv4Addr(net::Ipv4Addr::from(octets))
+    }
+}
+
+impl From<net::Ipv4Addr> for Ipv4Addr {
+    fn from(addr: net::Ipv4Addr) -> Self {
+        Ipv4Addr(addr)
+    }
+}
+
+impl From<Ipv4Addr> for net::Ipv4Addr {
+    fn from(addr: Ipv4Addr) -> Self {
+        addr.0
+    }
+}
+
+impl fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for Ipv4Addr {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        s.parse()
+            .map(Ipv4Addr)
+            .map_err(|_| ErrorKind::InvalidAddress(s.to_string(), stringify!(Ipv4Addr)).into())
+    }
+}
+
+/// IPv6 address, wraps the std type to add the wire conversions.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct Ipv6Addr(pub net::Ipv6Addr);
+
+impl Ipv6Addr {
+    pub fn from_slice(slice: &[u8]) -> Result<Self> {
+        check_len(slice, IPV6_ADDR_LEN, stringify!(Ipv6Addr))?;
+        let mut addr = [0u8; IPV6_ADDR_LEN];
+        addr.copy_from_slice(slice);
+        Ok(Ipv6Addr(net::Ipv6Addr::from(addr)))
+    }
+
+    pub fn octets(&self) -> [u8; IPV6_ADDR_LEN] {
+        self.0.octets()
+    }
+}
+
+impl Default for Ipv6Addr {
+    /// ::
+    fn default() -> Self {
+        Ipv6Addr(net::Ipv6Addr::from([0u8; IPV6_ADDR_LEN]))
+    }
+}
+
+impl From<[u8; IPV6_ADDR_LEN]> for Ipv6Addr {
+    fn from(octets: [u8; IPV6_ADDR_LEN]) -> Self {
+        Ipv6Addr(net::Ipv6Addr::from(octets))
+    }
+}
+
+impl From<net::Ipv6Addr> for Ipv6Addr {
+    fn from(addr: net::Ipv6Addr) -> Self {
+        Ipv6Addr(addr)
+    }
+}
+
+impl From<Ipv6Addr> for net::Ipv6Addr {
+    fn from(addr: Ipv6Addr) -> Self {
+        addr.0
+    }
+}
+
+impl fmt::Display for Ipv6Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for Ipv6Addr {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        s.parse()
+            .map(Ipv6Addr)
+            .map_err(|_| ErrorKind::InvalidAddress(s.to_string(), stringify!(Ipv6Addr)).into())
+    }
+}
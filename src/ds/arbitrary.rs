@@ -0,0 +1,632 @@
+//! Arbitrary instances of the ds structs for property based tests.
+//! Every generated value is valid on the wire, so encoding and decoding it
+//! again has to result in the same value.
+
+use num_traits::FromPrimitive;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use std::convert::TryFrom;
+use std::fmt::Debug;
+
+use super::super::err::*;
+use super::actions::*;
+use super::addr::{Ipv4Addr, Ipv6Addr, MacAddr};
+use super::async::*;
+use super::codec::OfEncode;
+use super::features::{Capabilities, SwitchFeatures};
+use super::flow_instructions::*;
+use super::flow_match::*;
+use super::flow_mod::{FlowMod, FlowModCommand, FlowModFlags};
+use super::group_mod::{GroupModCommand, GroupNo, GroupNumber};
+use super::meter_mod::{MeterId, MeterNo};
+#[cfg(feature = "nicira")]
+use super::nx::*;
+use super::packet_out::{BufferId, PacketOut};
+use super::ports::{PortNo, PortNumber};
+use super::{decode_any, Type, Version};
+
+/// encodes the value and decodes the result again
+pub fn round_trip<T>(value: T) -> Result<T>
+where
+    T: Into<Vec<u8>>,
+    for<'a> T: TryFrom<&'a [u8], Error = Error>,
+{
+    let bytes: Vec<u8> = value.into();
+    T::try_from(&bytes[..])
+}
+
+/// selects one of the variants of a primitive enum
+/// all raw values in min..=max are tried, so keep the range small
+pub fn primitive<T>(min: u64, max: u64) -> BoxedStrategy<T>
+where
+    T: FromPrimitive + Clone + Debug + 'static,
+{
+    let values: Vec<T> = (min..=max).filter_map(T::from_u64).collect();
+    prop::sample::select(values).boxed()
+}
+
+macro_rules! arbitrary_primitive {
+    ($ttype:ty, $min:expr, $max:expr) => {
+        impl Arbitrary for $ttype {
+            type Parameters = ();
+            type Strategy = BoxedStrategy<Self>;
+            fn arbitrary_with(_: ()) -> Self::Strategy {
+                primitive($min, $max)
+            }
+        }
+    };
+}
+
+arbitrary_primitive!(PortNo, 0xffff_ff00, 0xffff_ffff);
+arbitrary_primitive!(GroupNo, 0xffff_ff00, 0xffff_ffff);
+arbitrary_primitive!(MeterNo, 0xffff_0000, 0xffff_ffff);
+arbitrary_primitive!(EtherType, 0, 0xffff);
+arbitrary_primitive!(IpProto, 0, 0xff);
+arbitrary_primitive!(IcmpType, 0, 0xff);
+arbitrary_primitive!(ArpOp, 0, 0xffff);
+arbitrary_primitive!(IcmpV6Type, 0, 0xff);
+arbitrary_primitive!(FlowModCommand, 0, 0xff);
+arbitrary_primitive!(GroupModCommand, 0, 0xff);
+arbitrary_primitive!(Version, 0, 0xff);
+arbitrary_primitive!(Type, 0, 0xff);
+
+macro_rules! arbitrary_addr {
+    ($ttype:ty, $len:expr) => {
+        impl Arbitrary for $ttype {
+            type Parameters = ();
+            type Strategy = BoxedStrategy<Self>;
+            fn arbitrary_with(_: ()) -> Self::Strategy {
+                any::<[u8; $len]>().prop_map(<$ttype>::from).boxed()
+            }
+        }
+    };
+}
+
+arbitrary_addr!(MacAddr, 6);
+arbitrary_addr!(Ipv4Addr, 4);
+arbitrary_addr!(Ipv6Addr, 16);
+
+impl Arbitrary for PortNumber {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        // port 0 is illegal, everything above max is reserved
+        prop_oneof![
+            (1..PortNo::Max as u32).prop_map(PortNumber::NormalPort),
+            any::<PortNo>().prop_map(PortNumber::Reserved),
+        ]
+        .boxed()
+    }
+}
+
+impl Arbitrary for GroupNumber {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        // max itself decodes as reserved
+        prop_oneof![
+            (0..GroupNo::Max as u32).prop_map(GroupNumber::Group),
+            any::<GroupNo>().prop_map(GroupNumber::Reserved),
+        ]
+        .boxed()
+    }
+}
+
+impl Arbitrary for MeterId {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        // meter 0 is illegal, max itself decodes as reserved
+        prop_oneof![
+            (1..MeterNo::Max as u32).prop_map(MeterId::Meter),
+            any::<MeterNo>().prop_map(MeterId::Reserved),
+        ]
+        .boxed()
+    }
+}
+
+impl Arbitrary for Async {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (
+            (0..=PacketInReasonMask::all().bits()).prop_map(PacketInReasonMask::from_bits_truncate),
+            (0..=PacketInReasonMask::all().bits()).prop_map(PacketInReasonMask::from_bits_truncate),
+            (0..=PortReasonMask::all().bits()).prop_map(PortReasonMask::from_bits_truncate),
+            (0..=PortReasonMask::all().bits()).prop_map(PortReasonMask::from_bits_truncate),
+            (0..=FlowRemovedReasonMask::all().bits())
+                .prop_map(FlowRemovedReasonMask::from_bits_truncate),
+            (0..=FlowRemovedReasonMask::all().bits())
+                .prop_map(FlowRemovedReasonMask::from_bits_truncate),
+        )
+            .prop_map(|(pi1, pi2, ps1, ps2, fr1, fr2)| Async {
+                packet_in_mask_1: pi1,
+                packet_in_mask_2: pi2,
+                port_status_mask_1: ps1,
+                port_status_mask_2: ps2,
+                flow_removed_mask_1: fr1,
+                flow_removed_mask_2: fr2,
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for MatchPayload {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        let payloads = prop_oneof![
+            any::<PortNumber>()
+                .prop_map(|p| MatchPayload::InPort(PayloadInPort { ingress_port: p })),
+            any::<u32>().prop_map(|p| MatchPayload::InPhyPort(PayloadInPhyPort { phy_port: p })),
+            any::<u64>().prop_map(|m| MatchPayload::Metadata(PayloadMetadata { metadata: m })),
+            any::<MacAddr>().prop_map(|a| MatchPayload::EthDst(PayloadEthDst { eth_dst: a })),
+            any::<MacAddr>().prop_map(|a| MatchPayload::EthSrc(PayloadEthSrc { eth_src: a })),
+            any::<EtherType>().prop_map(|t| MatchPayload::EthType(PayloadEthType { ttype: t })),
+            any::<u16>().prop_map(|v| MatchPayload::VlanVId(PayloadVlanVId { vlan_id: v })),
+            any::<u8>().prop_map(|v| MatchPayload::VlanPcp(PayloadVlanPcp { vlan_pcp: v })),
+            any::<u8>().prop_map(|v| MatchPayload::IpDscp(PayloadIpDscp { ip_dscp: v })),
+            any::<u8>().prop_map(|v| MatchPayload::IpEcn(PayloadIpEcn { ip_enc: v })),
+            any::<IpProto>().prop_map(|p| MatchPayload::IpProto(PayloadIpProto { ip_proto: p })),
+            any::<Ipv4Addr>().prop_map(|a| MatchPayload::IPv4Src(PayloadIPv4Src { ipv4_src: a })),
+            any::<Ipv4Addr>().prop_map(|a| MatchPayload::IPv4Dst(PayloadIPv4Dst { ipv4_dst: a })),
+            any::<u16>().prop_map(|p| MatchPayload::TcpSrc(PayloadTcpSrc { src_port: p })),
+            any::<u16>().prop_map(|p| MatchPayload::TcpDst(PayloadTcpDst { dst_port: p })),
+            any::<u16>().prop_map(|p| MatchPayload::UdpSrc(PayloadUdpSrc { src_port: p })),
+            any::<u16>().prop_map(|p| MatchPayload::UdpDst(PayloadUdpDst { dst_port: p })),
+            any::<u16>().prop_map(|p| MatchPayload::SctpSrc(PayloadSctpSrc { src_port: p })),
+            any::<u16>().prop_map(|p| MatchPayload::SctpDst(PayloadSctpDst { dst_port: p })),
+            any::<IcmpType>()
+                .prop_map(|t| MatchPayload::IcmpV4TYype(PayloadIcmpV4Type { ttype: t })),
+            any::<u8>().prop_map(|c| MatchPayload::IcmpV4Code(PayloadIcmpV4Code { code: c })),
+            any::<ArpOp>().prop_map(|o| MatchPayload::ArpOp(PayloadArpOp { arp_op: o })),
+            any::<Ipv4Addr>().prop_map(|a| MatchPayload::ArpSpa(PayloadArpSpa { arp_spa: a })),
+            any::<Ipv4Addr>().prop_map(|a| MatchPayload::ArpTpa(PayloadArpTpa { arp_tpa: a })),
+            any::<MacAddr>().prop_map(|a| MatchPayload::ArpSha(PayloadArpSha { arp_sha: a })),
+            any::<MacAddr>().prop_map(|a| MatchPayload::ArpTha(PayloadArpTha { arp_tha: a })),
+            any::<Ipv6Addr>().prop_map(|a| MatchPayload::IPv6Src(PayloadIPv6Src { ipv6_src: a })),
+            any::<Ipv6Addr>().prop_map(|a| MatchPayload::IPv6Dst(PayloadIPv6Dst { ipv6_dst: a })),
+            any::<u32>().prop_map(|l| MatchPayload::IPv6FLabel(PayloadIPv6FLabel { flabel: l })),
+            any::<IcmpV6Type>()
+                .prop_map(|t| MatchPayload::IcmpV6Type(PayloadIcmpV6Type { ttype: t })),
+            any::<u8>().prop_map(|c| MatchPayload::IcmpV6Code(PayloadIcmpV6Code { code: c })),
+            any::<Ipv6Addr>()
+                .prop_map(|a| MatchPayload::IPv6NdTarget(PayloadIPv6NdTarget { target: a })),
+            any::<MacAddr>().prop_map(|a| MatchPayload::IPv6NdSll(PayloadIPv6NdSll { nd_sll: a })),
+            any::<MacAddr>().prop_map(|a| MatchPayload::IPv6NdTll(PayloadIPv6NdTll { nd_tll: a })),
+            any::<u32>().prop_map(|l| MatchPayload::MplsLabel(PayloadMplsLabel { label: l })),
+            any::<u8>().prop_map(|t| MatchPayload::MplsTc(PayloadMplsTc { tc: t })),
+            any::<u8>().prop_map(|b| MatchPayload::MplsBos(PayloadMplsBos { bos: b })),
+            any::<u32>().prop_map(|i| MatchPayload::PbbISid(PayloadPbbISid { i_sid: i })),
+            any::<u64>().prop_map(|m| MatchPayload::TunnelId(PayloadTunnelId { metadata: m })),
+            (0..=IPv6ExtHdrFlags::all().bits()).prop_map(|f| MatchPayload::IPv6ExtHdr(
+                IPv6ExtHdrFlags::from_bits_truncate(f).into()
+            )),
+            (any::<u16>(), any::<u16>()).prop_map(|(n, t)| {
+                MatchPayload::PacketType(PayloadPacketType {
+                    namespace: n,
+                    ns_type: t,
+                })
+            }),
+            (any::<u32>(), 0u8..0x80, vec(any::<u8>(), 0..8)).prop_map(|(e, f, d)| {
+                MatchPayload::Experimenter(ExperimenterMatch {
+                    experimenter: e,
+                    field: f,
+                    data: d,
+                })
+            }),
+        ]
+        .boxed();
+        #[cfg(feature = "nicira")]
+        let payloads = prop_oneof![payloads, any::<NxmField>().prop_map(MatchPayload::Nxm)];
+        payloads.boxed()
+    }
+}
+
+#[cfg(feature = "nicira")]
+impl Arbitrary for NxmField {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        prop_oneof![
+            (0u8..16, any::<u32>()).prop_map(|(i, v)| NxmField::Reg { index: i, value: v }),
+            any::<u64>().prop_map(NxmField::TunId),
+            any::<u32>().prop_map(|s| NxmField::CtState(CtState::from_bits_truncate(s))),
+            any::<u16>().prop_map(NxmField::CtZone),
+            any::<u32>().prop_map(NxmField::CtMark),
+            any::<u128>().prop_map(NxmField::CtLabel),
+            // fields of the NXM1 class could be decoded as one of the above
+            (0u8..0x80, vec(any::<u8>(), 0..8)).prop_map(|(f, d)| NxmField::Other {
+                class: NXM_CLASS_0,
+                field: f,
+                data: d,
+            }),
+        ]
+        .boxed()
+    }
+}
+
+/// bytes that fill whole multiples of 8 bytes
+fn padded_bytes(max_blocks: usize) -> BoxedStrategy<Vec<u8>> {
+    vec(any::<[u8; 8]>(), 0..max_blocks)
+        .prop_map(|blocks| blocks.iter().flat_map(|block| block.iter().cloned()).collect())
+        .boxed()
+}
+
+impl Arbitrary for TlvMatch {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        // the mask is cut to the length of the payload
+        (any::<MatchPayload>(), any::<bool>(), any::<[u8; 16]>())
+            .prop_map(|(payload, masked, mask)| {
+                if !masked {
+                    return TlvMatch::new(payload);
+                }
+                let value_len = match payload {
+                    MatchPayload::Experimenter(ref payload) => payload.data.len(),
+                    ref payload => Into::<Vec<u8>>::into(payload.clone()).len(),
+                };
+                TlvMatch::with_mask(payload, mask[..value_len].to_vec()).unwrap()
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for Match {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        vec(any::<TlvMatch>(), 0..8).prop_map(Match::new).boxed()
+    }
+}
+
+impl Arbitrary for ActionPayload {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        let payloads = prop_oneof![
+            (any::<PortNumber>(), any::<u16>()).prop_map(|(port, max_len)| {
+                ActionPayload::Output(PayloadOutput {
+                    port: port,
+                    max_len: max_len,
+                })
+            }),
+            Just(ActionPayload::CopyTtlOut(PayloadCopyTtlOut {})),
+            Just(ActionPayload::CopyTtlIn(PayloadCopyTtlIn {})),
+            any::<u8>().prop_map(|t| ActionPayload::SetMplsTtl(PayloadSetMplsTtl { mpls_ttl: t })),
+            Just(ActionPayload::DecMplsTtl(PayloadDecMplsTtl {})),
+            any::<EtherType>()
+                .prop_map(|t| ActionPayload::PushVlan(PayloadPushVlan { ethertype: t })),
+            Just(ActionPayload::PopVlan(PayloadPopVlan {})),
+            any::<EtherType>()
+                .prop_map(|t| ActionPayload::PushMpls(PayloadPushMpls { ethertype: t })),
+            any::<EtherType>()
+                .prop_map(|t| ActionPayload::PopMpls(PayloadPopMpls { ethertype: t })),
+            any::<u32>().prop_map(|q| ActionPayload::SetQueue(PayloadSetQueue { queue_id: q })),
+            any::<GroupNumber>().prop_map(|g| ActionPayload::Group(PayloadGroup { group_id: g })),
+            any::<u8>().prop_map(|t| ActionPayload::SetNwTtl(PayloadSetNwTtl { nw_ttl: t })),
+            Just(ActionPayload::DecNwTtl(PayloadDecNwTtl {})),
+            // set field actions must not be masked
+            any::<MatchPayload>().prop_map(|p| ActionPayload::SetField(PayloadSetField {
+                field: TlvMatch::new(p),
+            })),
+            any::<EtherType>()
+                .prop_map(|t| ActionPayload::PushPbb(PayloadPushPbb { ethertype: t })),
+            Just(ActionPayload::PopPbb(PayloadPopPbb {})),
+            // actions of the Nicira vendor id are decoded as Nicira with the nicira feature
+            (any::<u32>().prop_filter("Nicira", |e| *e != 0x2320), padded_bytes(3))
+                .prop_map(|(e, d)| ActionPayload::Experimenter(PayloadExperimenter::new(e, d))),
+        ]
+        .boxed();
+        #[cfg(feature = "nicira")]
+        let payloads = prop_oneof![payloads, any::<NxAction>().prop_map(ActionPayload::Nicira)];
+        payloads.boxed()
+    }
+}
+
+#[cfg(feature = "nicira")]
+impl Arbitrary for NxAction {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        prop_oneof![
+            (any::<u16>(), any::<Option<u8>>())
+                .prop_map(|(p, t)| NxAction::Resubmit { in_port: p, table: t }),
+            (any::<u32>(), any::<u8>(), any::<u8>()).prop_map(|(i, c, n)| {
+                NxAction::Conjunction {
+                    id: i,
+                    clause: c,
+                    n_clauses: n,
+                }
+            }),
+            (any::<(u16, u16, u16, u64, u16, u8)>(), any::<(u16, u16)>(), padded_bytes(3))
+                .prop_map(|((idle, hard, priority, cookie, flags, table), fin, specs)| {
+                    NxAction::Learn(NxLearn {
+                        idle_timeout: idle,
+                        hard_timeout: hard,
+                        priority: priority,
+                        cookie: cookie,
+                        flags: flags,
+                        table_id: table,
+                        fin_idle_timeout: fin.0,
+                        fin_hard_timeout: fin.1,
+                        specs: specs,
+                    })
+                }),
+            // nested actions are limited to set_field of ct_mark to keep the strategy finite
+            (
+                any::<u16>(),
+                any::<u16>(),
+                any::<Option<u8>>().prop_filter("none", |t| *t != Some(NX_CT_RECIRC_NONE)),
+                any::<u16>(),
+                vec(any::<u32>(), 0..3),
+            )
+                .prop_map(|(flags, zone, table, alg, marks)| {
+                    NxAction::Conntrack(NxConntrack {
+                        flags: CtFlags::from_bits_truncate(flags),
+                        zone_src: 0,
+                        zone: zone,
+                        recirc_table: table,
+                        alg: alg,
+                        actions: marks
+                            .into_iter()
+                            .map(|m| PayloadSetField::new(MatchPayload::Nxm(NxmField::CtMark(m))))
+                            .map(|field| field.unwrap().into())
+                            .collect(),
+                    })
+                }),
+            // subtypes that are not decoded, the data fills the action
+            (100u16..200, vec(any::<u8>(), 6..7), padded_bytes(2)).prop_map(|(s, d, rest)| {
+                NxAction::Other {
+                    subtype: s,
+                    data: d.into_iter().chain(rest).collect(),
+                }
+            }),
+        ]
+        .boxed()
+    }
+}
+
+impl Arbitrary for ActionHeader {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        any::<ActionPayload>().prop_map(Into::into).boxed()
+    }
+}
+
+impl Arbitrary for InstructionPayload {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        prop_oneof![
+            any::<u8>()
+                .prop_map(|t| InstructionPayload::GotoTable(PayloadGotoTable { table_id: t })),
+            (any::<u64>(), any::<u64>()).prop_map(|(metadata, metadata_mask)| {
+                InstructionPayload::WriteMetaData(PayloadWriteMetaData {
+                    metadata: metadata,
+                    metadata_mask: metadata_mask,
+                })
+            }),
+            vec(any::<ActionHeader>(), 0..4).prop_map(|actions| {
+                InstructionPayload::WriteActions(PayloadWriteActions { actions: actions })
+            }),
+            vec(any::<ActionHeader>(), 0..4).prop_map(|actions| {
+                InstructionPayload::ApplyActions(PayloadApplyActions { actions: actions })
+            }),
+            Just(InstructionPayload::ClearActions(PayloadClearActions {})),
+            any::<MeterId>().prop_map(|m| InstructionPayload::Meter(PayloadMeter { meter_id: m })),
+        ]
+        .boxed()
+    }
+}
+
+impl Arbitrary for InstructionHeader {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        any::<InstructionPayload>().prop_map(Into::into).boxed()
+    }
+}
+
+impl Arbitrary for FlowMod {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (
+            (
+                any::<u64>(),
+                any::<u64>(),
+                any::<u8>(),
+                any::<FlowModCommand>(),
+                any::<u16>(),
+                any::<u16>(),
+                any::<u16>(),
+            ),
+            (
+                any::<u32>().prop_map(BufferId::from),
+                any::<PortNumber>(),
+                any::<GroupNumber>(),
+                any::<u16>().prop_map(FlowModFlags::from_bits_truncate),
+                any::<Match>(),
+                vec(any::<InstructionHeader>(), 0..4),
+            ),
+        )
+            .prop_map(
+                |(
+                    (cookie, cookie_mask, table_id, command, idle_timeout, hard_timeout, priority),
+                    (buffer_id, out_port, out_group, flags, mmatch, instructions),
+                )| FlowMod {
+                    cookie: cookie,
+                    cookie_mask: cookie_mask,
+                    table_id: table_id,
+                    command: command,
+                    idle_timeout: idle_timeout,
+                    hard_timeout: hard_timeout,
+                    priority: priority,
+                    buffer_id: buffer_id,
+                    out_port: out_port,
+                    out_group: out_group,
+                    flags: flags,
+                    mmatch: mmatch,
+                    instructions: instructions,
+                },
+            )
+            .boxed()
+    }
+}
+
+impl Arbitrary for PacketOut {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (
+            any::<u32>().prop_map(BufferId::from),
+            any::<PortNumber>(),
+            vec(any::<ActionHeader>(), 0..4),
+            vec(any::<u8>(), 0..128),
+        )
+            .prop_map(|(buffer_id, in_port, actions, data)| {
+                PacketOut::new(buffer_id, in_port, actions, data)
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for SwitchFeatures {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (
+            any::<u64>(),
+            any::<u32>(),
+            any::<u8>(),
+            any::<u8>(),
+            any::<u32>().prop_map(Capabilities::from_bits_truncate),
+            any::<u32>(),
+        )
+            .prop_map(
+                |(datapath_id, n_buffers, n_tables, auxiliary_id, capabilities, reserved)| {
+                    SwitchFeatures {
+                        datapath_id: datapath_id,
+                        n_buffers: n_buffers,
+                        n_tables: n_tables,
+                        auxiliary_id: auxiliary_id,
+                        capabilities: capabilities,
+                        reserved: reserved,
+                    }
+                },
+            )
+            .boxed()
+    }
+}
+
+/// checks that the value survives a round trip, for use inside of proptest!
+macro_rules! prop_assert_round_trip {
+    ($value:expr) => {{
+        let value = $value;
+        let decoded = $crate::ds::arbitrary::round_trip(value.clone());
+        prop_assert!(
+            decoded.is_ok(),
+            "decoding failed: {:?}",
+            decoded.as_ref().err()
+        );
+        prop_assert_eq!(decoded.unwrap(), value);
+    }};
+}
+
+proptest! {
+    #[test]
+    fn match_round_trip(mmatch in any::<Match>()) {
+        prop_assert_round_trip!(mmatch);
+    }
+
+    #[test]
+    fn action_round_trip(action in any::<ActionHeader>()) {
+        prop_assert_round_trip!(action);
+    }
+
+    #[test]
+    fn instruction_round_trip(instruction in any::<InstructionHeader>()) {
+        prop_assert_round_trip!(instruction);
+    }
+
+    #[test]
+    fn flow_mod_round_trip(flow_mod in any::<FlowMod>()) {
+        prop_assert_round_trip!(flow_mod);
+    }
+
+    #[test]
+    fn packet_out_round_trip(packet_out in any::<PacketOut>()) {
+        prop_assert_round_trip!(packet_out);
+    }
+
+    #[test]
+    fn encoded_len_is_exact(flow_mod in any::<FlowMod>(), packet_out in any::<PacketOut>()) {
+        prop_assert_eq!(flow_mod.encoded_len(), flow_mod.to_bytes().len());
+        prop_assert_eq!(packet_out.encoded_len(), packet_out.to_bytes().len());
+    }
+
+    #[test]
+    fn switch_features_round_trip(features in any::<SwitchFeatures>()) {
+        prop_assert_round_trip!(features);
+    }
+
+    #[test]
+    fn async_round_trip(config in any::<Async>()) {
+        prop_assert_round_trip!(config);
+    }
+
+    #[test]
+    fn mac_addr_display_parse(addr in any::<MacAddr>()) {
+        prop_assert_eq!(addr.to_string().parse::<MacAddr>().unwrap(), addr);
+    }
+
+    #[test]
+    fn ipv6_addr_display_parse(addr in any::<Ipv6Addr>()) {
+        prop_assert_eq!(addr.to_string().parse::<Ipv6Addr>().unwrap(), addr);
+    }
+
+    #[test]
+    fn decode_any_never_panics(
+        version in any::<Version>(),
+        ttype in any::<Type>(),
+        bytes in vec(any::<u8>(), 0..256),
+    ) {
+        let _ = decode_any(version, &ttype, &bytes);
+    }
+
+    #[test]
+    fn truncated_flow_mod_never_panics(flow_mod in any::<FlowMod>(), cut in any::<prop::sample::Index>()) {
+        let bytes: Vec<u8> = flow_mod.into();
+        let len = cut.index(bytes.len());
+        let _ = decode_any(Version::V1_3, &Type::FlowMod, &bytes[..len]);
+    }
+
+    #[test]
+    fn corrupted_flow_mod_never_panics(
+        flow_mod in any::<FlowMod>(),
+        at in any::<prop::sample::Index>(),
+        value in any::<u8>(),
+    ) {
+        let mut bytes: Vec<u8> = flow_mod.into();
+        let pos = at.index(bytes.len());
+        bytes[pos] = value;
+        let _ = decode_any(Version::V1_3, &Type::FlowMod, &bytes);
+    }
+
+    #[test]
+    fn corrupted_packet_out_never_panics(
+        packet_out in any::<PacketOut>(),
+        at in any::<prop::sample::Index>(),
+        value in any::<u8>(),
+        cut in any::<prop::sample::Index>(),
+    ) {
+        let mut bytes: Vec<u8> = packet_out.into();
+        let pos = at.index(bytes.len());
+        bytes[pos] = value;
+        let len = cut.index(bytes.len() + 1);
+        let _ = decode_any(Version::V1_3, &Type::PacketOut, &bytes[..len]);
+    }
+}
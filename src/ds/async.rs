@@ -1,45 +1,216 @@
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use std::convert::{Into, TryFrom};
-use std::io::Cursor;
+use super::flow_removed::FlowRemovedReason;
+use super::packet_in::InReason;
+use super::port_status::PortReason;
+use super::role::ControllerRole;
 
-use super::super::err::*;
+bitflags!{
+    /// Packet in reasons the controller wants to receive.
+    pub struct PacketInReasonMask: u32 {
+        const NO_MATCH = 1 << 0;
+        const ACTION = 1 << 1;
+        const INVALID_TTL = 1 << 2;
+    }
+}
+
+bitflags!{
+    /// Port status reasons the controller wants to receive.
+    pub struct PortReasonMask: u32 {
+        const ADD = 1 << 0;
+        const DELETE = 1 << 1;
+        const MODIFY = 1 << 2;
+    }
+}
+
+bitflags!{
+    /// Flow removed reasons the controller wants to receive.
+    pub struct FlowRemovedReasonMask: u32 {
+        const IDLE_TIMEOUT = 1 << 0;
+        const HARD_TIMEOUT = 1 << 1;
+        const DELETE = 1 << 2;
+        const GROUP_DELETE = 1 << 3;
+    }
+}
+
+impl From<InReason> for PacketInReasonMask {
+    fn from(reason: InReason) -> Self {
+        match reason {
+            InReason::NoMatch => PacketInReasonMask::NO_MATCH,
+            InReason::Action => PacketInReasonMask::ACTION,
+            InReason::InvalidTtl => PacketInReasonMask::INVALID_TTL,
+        }
+    }
+}
+
+impl From<PortReason> for PortReasonMask {
+    fn from(reason: PortReason) -> Self {
+        match reason {
+            PortReason::Add => PortReasonMask::ADD,
+            PortReason::Delete => PortReasonMask::DELETE,
+            PortReason::Modifiy => PortReasonMask::MODIFY,
+        }
+    }
+}
+
+impl From<FlowRemovedReason> for FlowRemovedReasonMask {
+    fn from(reason: FlowRemovedReason) -> Self {
+        match reason {
+            FlowRemovedReason::IdleTimeout => FlowRemovedReasonMask::IDLE_TIMEOUT,
+            FlowRemovedReason::HardTimeout => FlowRemovedReasonMask::HARD_TIMEOUT,
+            FlowRemovedReason::Delete => FlowRemovedReasonMask::DELETE,
+            FlowRemovedReason::GroupDelete => FlowRemovedReasonMask::GROUP_DELETE,
+        }
+    }
+}
 
-#[derive(Debug)]
+/// Asynchronous message configuration.
+/// Masks ending in 1 apply to the master and equal role, masks ending in 2 to the slave role.
+#[derive(OfWire, Debug, PartialEq, Clone)]
 pub struct Async {
-    pub packet_in_mask_1: u32,
-    pub packet_in_mask_2: u32,
-    pub port_status_mask_1: u32,
-    pub port_status_mask_2: u32,
-    pub flow_removed_mask_1: u32,
-    pub flow_removed_mask_2: u32,
-}
-
-impl<'a> TryFrom<&'a [u8]> for Async {
-    type Error = Error;
-    fn try_from(bytes: &'a [u8]) -> Result<Self> {
-        let mut cursor = Cursor::new(bytes);
-        Ok(Async {
-            packet_in_mask_1: cursor.read_u32::<BigEndian>().unwrap(),
-            packet_in_mask_2: cursor.read_u32::<BigEndian>().unwrap(),
-            port_status_mask_1: cursor.read_u32::<BigEndian>().unwrap(),
-            port_status_mask_2: cursor.read_u32::<BigEndian>().unwrap(),
-            flow_removed_mask_1: cursor.read_u32::<BigEndian>().unwrap(),
-            flow_removed_mask_2: cursor.read_u32::<BigEndian>().unwrap(),
-        })
-    }
-}
-
-impl Into<Vec<u8>> for Async {
-    fn into(self) -> Vec<u8> {
-        let mut res = Vec::new();
-        res.write_u32::<BigEndian>(self.packet_in_mask_1).unwrap();
-        res.write_u32::<BigEndian>(self.packet_in_mask_2).unwrap();
-        res.write_u32::<BigEndian>(self.port_status_mask_1).unwrap();
-        res.write_u32::<BigEndian>(self.port_status_mask_2).unwrap();
-        res.write_u32::<BigEndian>(self.flow_removed_mask_1)
-            .unwrap();
-        res.write_u32::<BigEndian>(self.flow_removed_mask_2)
-            .unwrap();
-        res
+    pub packet_in_mask_1: PacketInReasonMask,
+    pub packet_in_mask_2: PacketInReasonMask,
+    pub port_status_mask_1: PortReasonMask,
+    pub port_status_mask_2: PortReasonMask,
+    pub flow_removed_mask_1: FlowRemovedReasonMask,
+    pub flow_removed_mask_2: FlowRemovedReasonMask,
+}
+
+impl Default for Async {
+    /// the configuration a switch starts with
+    fn default() -> Self {
+        Async {
+            packet_in_mask_1: PacketInReasonMask::NO_MATCH | PacketInReasonMask::ACTION,
+            packet_in_mask_2: PacketInReasonMask::empty(),
+            port_status_mask_1: PortReasonMask::all(),
+            port_status_mask_2: PortReasonMask::all(),
+            flow_removed_mask_1: FlowRemovedReasonMask::all(),
+            flow_removed_mask_2: FlowRemovedReasonMask::empty(),
+        }
+    }
+}
+
+impl Async {
+    /// a configuration that does not send any asynchronous message
+    pub fn none() -> Self {
+        Async {
+            packet_in_mask_1: PacketInReasonMask::empty(),
+            packet_in_mask_2: PacketInReasonMask::empty(),
+            port_status_mask_1: PortReasonMask::empty(),
+            port_status_mask_2: PortReasonMask::empty(),
+            flow_removed_mask_1: FlowRemovedReasonMask::empty(),
+            flow_removed_mask_2: FlowRemovedReasonMask::empty(),
+        }
+    }
+
+    /// configuration of a controller that programs the switch, the same as the
+    /// one a switch starts with: everything as master, only port status as slave
+    pub fn master_default() -> Self {
+        Async::default()
+    }
+
+    /// configuration of a standby controller that keeps track of the state of
+    /// the switch: port status and flow removed messages as slave, no packet ins
+    pub fn slave_default() -> Self {
+        let mut config = Async::default();
+        config.flow_removed_mask_2 = FlowRemovedReasonMask::all();
+        config
+    }
+
+    /// configuration of a controller that only observes, eg. for monitoring:
+    /// port status and flow removed messages in every role, never packet ins
+    pub fn listen_only() -> Self {
+        Async {
+            packet_in_mask_1: PacketInReasonMask::empty(),
+            packet_in_mask_2: PacketInReasonMask::empty(),
+            port_status_mask_1: PortReasonMask::all(),
+            port_status_mask_2: PortReasonMask::all(),
+            flow_removed_mask_1: FlowRemovedReasonMask::all(),
+            flow_removed_mask_2: FlowRemovedReasonMask::all(),
+        }
+    }
+
+    /// the default of a controller that expects to run in the given role
+    pub fn for_role(role: &ControllerRole) -> Self {
+        match *role {
+            ControllerRole::Slave => Async::slave_default(),
+            _ => Async::master_default(),
+        }
+    }
+
+    /// packet in reasons sent to a controller in the given role
+    pub fn packet_in(&self, role: &ControllerRole) -> PacketInReasonMask {
+        match *role {
+            ControllerRole::Slave => self.packet_in_mask_2,
+            _ => self.packet_in_mask_1,
+        }
+    }
+
+    /// port status reasons sent to a controller in the given role
+    pub fn port_status(&self, role: &ControllerRole) -> PortReasonMask {
+        match *role {
+            ControllerRole::Slave => self.port_status_mask_2,
+            _ => self.port_status_mask_1,
+        }
+    }
+
+    /// flow removed reasons sent to a controller in the given role
+    pub fn flow_removed(&self, role: &ControllerRole) -> FlowRemovedReasonMask {
+        match *role {
+            ControllerRole::Slave => self.flow_removed_mask_2,
+            _ => self.flow_removed_mask_1,
+        }
+    }
+
+    /// sets the packet in reasons for the given role
+    /// eg. `set_packet_in(&ControllerRole::Slave, InReason::NoMatch.into())`
+    pub fn set_packet_in(&mut self, role: &ControllerRole, mask: PacketInReasonMask) -> &mut Self {
+        match *role {
+            ControllerRole::Slave => self.packet_in_mask_2 = mask,
+            _ => self.packet_in_mask_1 = mask,
+        }
+        self
+    }
+
+    /// sets the port status reasons for the given role
+    pub fn set_port_status(&mut self, role: &ControllerRole, mask: PortReasonMask) -> &mut Self {
+        match *role {
+            ControllerRole::Slave => self.port_status_mask_2 = mask,
+            _ => self.port_status_mask_1 = mask,
+        }
+        self
+    }
+
+    /// sets the flow removed reasons for the given role
+    pub fn set_flow_removed(
+        &mut self,
+        role: &ControllerRole,
+        mask: FlowRemovedReasonMask,
+    ) -> &mut Self {
+        match *role {
+            ControllerRole::Slave => self.flow_removed_mask_2 = mask,
+            _ => self.flow_removed_mask_1 = mask,
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_by_role() {
+        let slave = Async::for_role(&ControllerRole::Slave);
+        assert!(slave.packet_in(&ControllerRole::Slave).is_empty());
+        assert_eq!(
+            slave.flow_removed(&ControllerRole::Slave),
+            FlowRemovedReasonMask::all()
+        );
+        assert_eq!(Async::for_role(&ControllerRole::Equal), Async::default());
+
+        let listener = Async::listen_only();
+        for role in &[ControllerRole::Master, ControllerRole::Slave] {
+            assert!(listener.packet_in(role).is_empty());
+            assert_eq!(listener.port_status(role), PortReasonMask::all());
+        }
     }
 }
@@ -0,0 +1,231 @@
+//! Bundles (OpenFlow 1.4): messages added to a bundle are only applied when
+//! the bundle is committed, an atomic bundle is applied completely or not at all.
+//!
+//! Bundle properties are not supported, only experimenter properties exist.
+//! They are skipped when decoding.
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::convert::{Into, TryFrom};
+use std::io::{Cursor, Seek, SeekFrom};
+
+use super::super::err::*;
+use super::codec::OfEncode;
+use super::{checked_slice, decode_any, Header, OfMsg, HEADER_LENGTH};
+
+/// Length of a bundle control message without properties (8 bytes).
+pub const BUNDLE_CTRL_LENGTH: usize = 8;
+/// Length of a bundle add message without the message and properties (8 bytes).
+pub const BUNDLE_ADD_LENGTH: usize = 8;
+
+/// Opens, closes, commits or discards a bundle, and the reply of the switch.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BundleCtrl {
+    pub bundle_id: u32,
+    pub ctrl_type: BundleCtrlType,
+    pub flags: BundleFlags,
+}
+
+impl BundleCtrl {
+    pub fn new(bundle_id: u32, ctrl_type: BundleCtrlType, flags: BundleFlags) -> Self {
+        BundleCtrl {
+            bundle_id: bundle_id,
+            ctrl_type: ctrl_type,
+            flags: flags,
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for BundleCtrl {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(bytes);
+        let bundle_id = cursor.read_u32::<BigEndian>()?;
+        let ctrl_type_raw = cursor.read_u16::<BigEndian>()?;
+        let ctrl_type = BundleCtrlType::from_u16(ctrl_type_raw).ok_or::<Error>(
+            ErrorKind::UnknownValue(ctrl_type_raw as u64, stringify!(BundleCtrlType)).into(),
+        )?;
+        let flags_raw = cursor.read_u16::<BigEndian>()?;
+        let flags = flags_from_bits!(BundleFlags, flags_raw)?;
+        Ok(BundleCtrl {
+            bundle_id: bundle_id,
+            ctrl_type: ctrl_type,
+            flags: flags,
+        })
+    }
+}
+
+impl Into<Vec<u8>> for BundleCtrl {
+    fn into(self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.write_u32::<BigEndian>(self.bundle_id).unwrap();
+        res.write_u16::<BigEndian>(self.ctrl_type.to_u16().unwrap())
+            .unwrap();
+        res.write_u16::<BigEndian>(self.flags.bits()).unwrap();
+        res
+    }
+}
+
+/// Adds a message to an open bundle, the switch only checks it until the
+/// bundle is committed.
+#[derive(Debug)]
+pub struct BundleAdd {
+    pub bundle_id: u32,
+    // pad 2 bytes
+    /// the flags the bundle was opened with
+    pub flags: BundleFlags,
+    /// uses the version of the connection and the xid of the bundle add
+    pub message: Box<OfMsg>,
+}
+
+impl BundleAdd {
+    pub fn new(bundle_id: u32, flags: BundleFlags, message: OfMsg) -> Self {
+        BundleAdd {
+            bundle_id: bundle_id,
+            flags: flags,
+            message: Box::new(message),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for BundleAdd {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(bytes);
+        let bundle_id = cursor.read_u32::<BigEndian>()?;
+        cursor.seek(SeekFrom::Current(2)).unwrap(); // pad 2 bytes
+        let flags_raw = cursor.read_u16::<BigEndian>()?;
+        let flags = flags_from_bits!(BundleFlags, flags_raw)?;
+
+        // the message is followed by the properties
+        let header_bytes = checked_slice(
+            bytes,
+            BUNDLE_ADD_LENGTH,
+            HEADER_LENGTH,
+            stringify!(BundleAdd),
+        )?;
+        let header = Header::try_from(header_bytes).within(BUNDLE_ADD_LENGTH)?;
+        let message = checked_slice(
+            bytes,
+            BUNDLE_ADD_LENGTH,
+            *header.length() as usize,
+            stringify!(BundleAdd),
+        )?;
+        let payload = decode_any(*header.version(), header.ttype(), &message[HEADER_LENGTH..])
+            .within(BUNDLE_ADD_LENGTH + HEADER_LENGTH)?;
+
+        Ok(BundleAdd {
+            bundle_id: bundle_id,
+            flags: flags,
+            message: Box::new(OfMsg::new(header, payload)),
+        })
+    }
+}
+
+impl OfEncode for BundleAdd {
+    fn encoded_len(&self) -> usize {
+        BUNDLE_ADD_LENGTH + self.message.encoded_len()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.message.size_hint().map(|len| BUNDLE_ADD_LENGTH + len)
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.write_u32::<BigEndian>(self.bundle_id).unwrap();
+        buf.write_u16::<BigEndian>(0).unwrap(); // pad 2 bytes
+        buf.write_u16::<BigEndian>(self.flags.bits()).unwrap();
+        // the bundle add fails to encode if the message is too long
+        self.message.encode_into(buf);
+    }
+}
+
+impl Into<Vec<u8>> for BundleAdd {
+    fn into(self) -> Vec<u8> {
+        self.to_bytes()
+    }
+}
+
+/// Bundle control message types.
+#[derive(Primitive, PartialEq, Debug, Clone, Copy)]
+pub enum BundleCtrlType {
+    /// Opens a bundle before messages are added.
+    OpenRequest = 0,
+    OpenReply = 1,
+    /// Closes a bundle, no more messages can be added.
+    CloseRequest = 2,
+    CloseReply = 3,
+    /// Applies the messages of a bundle.
+    CommitRequest = 4,
+    CommitReply = 5,
+    /// Drops a bundle and its messages.
+    DiscardRequest = 6,
+    DiscardReply = 7,
+}
+
+impl BundleCtrlType {
+    /// type of the reply to a request, None for replies
+    pub fn reply(&self) -> Option<BundleCtrlType> {
+        match *self {
+            BundleCtrlType::OpenRequest => Some(BundleCtrlType::OpenReply),
+            BundleCtrlType::CloseRequest => Some(BundleCtrlType::CloseReply),
+            BundleCtrlType::CommitRequest => Some(BundleCtrlType::CommitReply),
+            BundleCtrlType::DiscardRequest => Some(BundleCtrlType::DiscardReply),
+            _ => None,
+        }
+    }
+}
+
+bitflags!{
+    /// How the messages of a bundle are applied.
+    pub struct BundleFlags: u16 {
+        /// All messages are applied or none.
+        const ATOMIC = 1 << 0;
+        /// Messages are applied in the order they were added.
+        const ORDERED = 1 << 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::flow_parser::parse_flow_mod;
+    use super::super::{OfPayload, Version};
+    use super::*;
+    use byteorder::ByteOrder;
+
+    #[test]
+    fn bundle_add_contains_a_whole_message() {
+        let flow_mod = parse_flow_mod("in_port=1,actions=drop").unwrap();
+        let message =
+            OfMsg::generate_for(Version::V1_4, 7, OfPayload::FlowMod(flow_mod.clone())).unwrap();
+        let flags = BundleFlags::ATOMIC | BundleFlags::ORDERED;
+        let bytes: Vec<u8> = BundleAdd::new(1, flags, message).into();
+        assert_eq!(&bytes[..8], &[0, 0, 0, 1, 0, 0, 0, 3]);
+        // the embedded header has the length of the embedded message
+        assert_eq!(bytes[8], 0x05);
+        let len = BigEndian::read_u16(&bytes[10..12]) as usize;
+        assert_eq!(len, bytes.len() - BUNDLE_ADD_LENGTH);
+
+        let add = BundleAdd::try_from(&bytes[..]).unwrap();
+        assert_eq!((add.bundle_id, add.flags), (1, flags));
+        assert_eq!(*add.message.header().xid(), 7);
+        match *add.message.payload() {
+            OfPayload::FlowMod(ref decoded) => assert_eq!(*decoded, flow_mod),
+            ref payload => panic!("unexpected payload {:?}", payload),
+        }
+
+        // the embedded message is cut off
+        let err = BundleAdd::try_from(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(err.context().offset, Some(BUNDLE_ADD_LENGTH));
+    }
+
+    #[test]
+    fn ctrl_replies() {
+        let open = BundleCtrl::new(3, BundleCtrlType::OpenRequest, BundleFlags::ATOMIC);
+        let bytes: Vec<u8> = open.clone().into();
+        assert_eq!(bytes, vec![0, 0, 0, 3, 0, 0, 0, 1]);
+        assert_eq!(BundleCtrl::try_from(&bytes[..]).unwrap(), open);
+        assert_eq!(open.ctrl_type.reply(), Some(BundleCtrlType::OpenReply));
+        assert_eq!(BundleCtrlType::CommitReply.reply(), None);
+    }
+}
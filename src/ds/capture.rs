@@ -0,0 +1,718 @@
+//! Reading and writing of OpenFlow traffic captures.
+//!
+//! Two formats can be read:
+//! - classic pcap files (as written by tcpdump/wireshark) containing the tcp
+//!   session(s) between switches and a controller
+//! - the simple dump format written by `CaptureWriter`
+//!
+//! The dump format starts with a 12 byte file header
+//! (8 bytes magic `OATH2CAP`, u16 version, 2 bytes reserved)
+//! followed by records of the form
+//! - u64 timestamp seconds + u32 timestamp nanoseconds (since unix epoch)
+//! - u8 direction (0 = switch to controller, 1 = controller to switch)
+//! - u8 address family of the switch (0 = unknown, 4 = ipv4, 6 = ipv6)
+//!   followed by the ip address (4 or 16 bytes) and u16 port if known
+//! - u32 message length followed by the raw OpenFlow message (header included)
+//!
+//! All values are big endian.
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{Cursor, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::super::err::*;
+use super::flags::FlagsPolicy;
+use super::{OfMsg, HEADER_LENGTH};
+
+/// magic bytes at the start of every dump file
+pub const DUMP_MAGIC: &[u8; 8] = b"OATH2CAP";
+/// current version of the dump format
+pub const DUMP_VERSION: u16 = 1;
+/// tcp ports that are treated as controller ports when reading pcap files
+pub const OPENFLOW_PORTS: [u16; 2] = [6653, 6633];
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const PCAP_HEADER_LENGTH: usize = 24;
+const PCAP_RECORD_HEADER_LENGTH: usize = 16;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+
+/// Direction of a captured message as seen from the controller.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Direction {
+    /// sent by the switch to the controller
+    Incoming = 0,
+    /// sent by the controller to the switch
+    Outgoing = 1,
+}
+
+/// A single decoded message of a capture.
+#[derive(Getters, Debug)]
+pub struct CapturedMsg {
+    /// time the message was captured (since unix epoch)
+    #[get = "pub"]
+    timestamp: Duration,
+    #[get = "pub"]
+    direction: Direction,
+    /// address of the switch the message was exchanged with (if known)
+    #[get = "pub"]
+    peer: Option<SocketAddr>,
+    #[get = "pub"]
+    msg: OfMsg,
+}
+
+/// raw message bytes of a capture before decoding
+#[derive(Debug)]
+struct RawRecord {
+    timestamp: Duration,
+    direction: Direction,
+    peer: Option<SocketAddr>,
+    bytes: Vec<u8>,
+}
+
+/// Reads all OpenFlow messages of a capture.
+/// Iterating yields the messages in capture order,
+/// messages that can not be decoded are returned as errors
+/// without stopping the iteration.
+#[derive(Debug)]
+pub struct CaptureReader {
+    records: VecDeque<RawRecord>,
+    flags_policy: FlagsPolicy,
+}
+
+impl CaptureReader {
+    /// opens a pcap or dump file, the format is detected automatically
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        CaptureReader::from_bytes(&bytes[..])
+    }
+
+    /// reads a pcap or dump capture from memory, the format is detected automatically
+    /// pcap files are filtered for the default OpenFlow ports (see OPENFLOW_PORTS)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() >= DUMP_MAGIC.len() && bytes[..DUMP_MAGIC.len()] == DUMP_MAGIC[..] {
+            CaptureReader::from_dump(bytes)
+        } else {
+            CaptureReader::from_pcap(bytes, &OPENFLOW_PORTS[..])
+        }
+    }
+
+    /// how unknown flag bits of the messages are handled, Strict by default
+    pub fn with_flags_policy(mut self, flags_policy: FlagsPolicy) -> Self {
+        self.flags_policy = flags_policy;
+        self
+    }
+
+    /// reads a capture in the dump format written by CaptureWriter
+    pub fn from_dump(bytes: &[u8]) -> Result<Self> {
+        let header_len = DUMP_MAGIC.len() + 4;
+        if bytes.len() < header_len || bytes[..DUMP_MAGIC.len()] != DUMP_MAGIC[..] {
+            bail!(ErrorKind::InvalidSliceLength(
+                header_len,
+                bytes.len(),
+                stringify!(CaptureReader),
+            ));
+        }
+        let mut cursor = Cursor::new(bytes);
+        cursor.set_position(DUMP_MAGIC.len() as u64);
+        let version = cursor.read_u16::<BigEndian>()?;
+        if version != DUMP_VERSION {
+            bail!(ErrorKind::UnsupportedValue(
+                version as u64,
+                stringify!(DUMP_VERSION)
+            ));
+        }
+        cursor.read_u16::<BigEndian>()?; // reserved
+
+        let mut records = VecDeque::new();
+        while (cursor.position() as usize) < bytes.len() {
+            let secs = cursor.read_u64::<BigEndian>()?;
+            let nanos = cursor.read_u32::<BigEndian>()?;
+            let direction = match cursor.read_u8()? {
+                0 => Direction::Incoming,
+                1 => Direction::Outgoing,
+                val => bail!(ErrorKind::UnknownValue(val as u64, stringify!(Direction))),
+            };
+            let peer = match cursor.read_u8()? {
+                0 => None,
+                4 => {
+                    let ip = Ipv4Addr::from(cursor.read_u32::<BigEndian>()?);
+                    let port = cursor.read_u16::<BigEndian>()?;
+                    Some(SocketAddr::new(IpAddr::V4(ip), port))
+                }
+                6 => {
+                    let mut octets = [0u8; 16];
+                    cursor.read_exact(&mut octets)?;
+                    let port = cursor.read_u16::<BigEndian>()?;
+                    Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port))
+                }
+                val => bail!(ErrorKind::UnknownValue(val as u64, stringify!(IpAddr))),
+            };
+            let len = cursor.read_u32::<BigEndian>()? as usize;
+            let mut msg_bytes = vec![0u8; len];
+            cursor.read_exact(&mut msg_bytes[..])?;
+            records.push_back(RawRecord {
+                timestamp: timestamp(secs, nanos)?,
+                direction: direction,
+                peer: peer,
+                bytes: msg_bytes,
+            });
+        }
+        Ok(CaptureReader {
+            records: records,
+            flags_policy: FlagsPolicy::Strict,
+        })
+    }
+
+    /// reads a pcap capture and reassembles the OpenFlow messages of all tcp sessions
+    /// which use one of the given ports on the controller side
+    ///
+    /// the capture has to contain the start of each session (or at least start at a
+    /// message boundary), sessions with lost segments are dropped from the point
+    /// where the segment is missing
+    pub fn from_pcap(bytes: &[u8], ports: &[u16]) -> Result<Self> {
+        if bytes.len() < PCAP_HEADER_LENGTH {
+            bail!(ErrorKind::InvalidSliceLength(
+                PCAP_HEADER_LENGTH,
+                bytes.len(),
+                stringify!(CaptureReader),
+            ));
+        }
+        let (big_endian, nanos) = match LittleEndian::read_u32(&bytes[..4]) {
+            PCAP_MAGIC_MICROS => (false, false),
+            PCAP_MAGIC_NANOS => (false, true),
+            magic => match BigEndian::read_u32(&bytes[..4]) {
+                PCAP_MAGIC_MICROS => (true, false),
+                PCAP_MAGIC_NANOS => (true, true),
+                _ => bail!(ErrorKind::UnknownValue(
+                    magic as u64,
+                    stringify!(CaptureReader)
+                )),
+            },
+        };
+        let read_u32 = |slice: &[u8]| {
+            if big_endian {
+                BigEndian::read_u32(slice)
+            } else {
+                LittleEndian::read_u32(slice)
+            }
+        };
+        let link_type = read_u32(&bytes[20..24]);
+
+        let mut streams: HashMap<(SocketAddr, SocketAddr), TcpStreamState> = HashMap::new();
+        let mut records = VecDeque::new();
+        let mut pos = PCAP_HEADER_LENGTH;
+        while pos + PCAP_RECORD_HEADER_LENGTH <= bytes.len() {
+            let ts_sec = read_u32(&bytes[pos..pos + 4]);
+            let ts_frac = read_u32(&bytes[pos + 4..pos + 8]);
+            let incl_len = read_u32(&bytes[pos + 8..pos + 12]) as usize;
+            pos += PCAP_RECORD_HEADER_LENGTH;
+            if pos + incl_len > bytes.len() {
+                bail!(ErrorKind::InvalidSliceLength(
+                    incl_len,
+                    bytes.len() - pos,
+                    stringify!(CaptureReader),
+                ));
+            }
+            let frame = &bytes[pos..pos + incl_len];
+            pos += incl_len;
+
+            let ts_nanos = if nanos {
+                Some(ts_frac)
+            } else {
+                ts_frac.checked_mul(1000)
+            };
+            let timestamp = match ts_nanos {
+                Some(ts_nanos) => timestamp(ts_sec as u64, ts_nanos)?,
+                None => bail!(ErrorKind::IllegalValue(
+                    ts_frac as u64,
+                    stringify!(CaptureReader)
+                )),
+            };
+
+            let segment = match parse_tcp_segment(frame, link_type)? {
+                Some(segment) => segment,
+                None => continue,
+            };
+            let (direction, peer) = if ports.contains(&segment.dst.port()) {
+                (Direction::Incoming, segment.src)
+            } else if ports.contains(&segment.src.port()) {
+                (Direction::Outgoing, segment.dst)
+            } else {
+                continue;
+            };
+
+            let stream = streams
+                .entry((segment.src, segment.dst))
+                .or_insert_with(TcpStreamState::new);
+            for msg_bytes in stream.push(&segment) {
+                records.push_back(RawRecord {
+                    timestamp: timestamp,
+                    direction: direction,
+                    peer: Some(peer),
+                    bytes: msg_bytes,
+                });
+            }
+        }
+        Ok(CaptureReader {
+            records: records,
+            flags_policy: FlagsPolicy::Strict,
+        })
+    }
+}
+
+impl Iterator for CaptureReader {
+    type Item = Result<CapturedMsg>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let flags_policy = self.flags_policy;
+        self.records.pop_front().map(|record| {
+            let (msg, _) = OfMsg::decode_with(flags_policy, &record.bytes[..]);
+            let msg = msg?;
+            Ok(CapturedMsg {
+                timestamp: record.timestamp,
+                direction: record.direction,
+                peer: record.peer,
+                msg: msg,
+            })
+        })
+    }
+}
+
+/// the timestamp of a record, fails if the nanoseconds are not below one second
+fn timestamp(secs: u64, nanos: u32) -> Result<Duration> {
+    if nanos >= 1_000_000_000 {
+        bail!(ErrorKind::IllegalValue(
+            nanos as u64,
+            stringify!(CaptureReader)
+        ));
+    }
+    Ok(Duration::new(secs, nanos))
+}
+
+/// tcp segment extracted from a captured frame
+struct TcpSegment<'a> {
+    src: SocketAddr,
+    dst: SocketAddr,
+    seq: u32,
+    syn: bool,
+    data: &'a [u8],
+}
+
+/// reassembly state of one direction of a tcp session
+struct TcpStreamState {
+    next_seq: Option<u32>,
+    buffer: Vec<u8>,
+    lost: bool,
+}
+
+impl TcpStreamState {
+    fn new() -> Self {
+        TcpStreamState {
+            next_seq: None,
+            buffer: Vec::new(),
+            lost: false,
+        }
+    }
+
+    /// adds a segment to the stream and returns all messages completed by it
+    fn push(&mut self, segment: &TcpSegment) -> Vec<Vec<u8>> {
+        if segment.syn {
+            // new session on the same address pair
+            self.next_seq = Some(segment.seq.wrapping_add(1));
+            self.buffer.clear();
+            self.lost = false;
+            return Vec::new();
+        }
+        if self.lost || segment.data.is_empty() {
+            return Vec::new();
+        }
+        let next_seq = self.next_seq.unwrap_or(segment.seq);
+        let offset = next_seq.wrapping_sub(segment.seq) as i32;
+        if offset < 0 {
+            warn!(
+                "Lost tcp segment between {} and {}, dropping the rest of the stream.",
+                segment.src, segment.dst
+            );
+            self.lost = true;
+            return Vec::new();
+        }
+        let offset = offset as usize;
+        if offset < segment.data.len() {
+            // skip the part that was already seen (retransmission)
+            self.buffer.extend_from_slice(&segment.data[offset..]);
+            self.next_seq = Some(segment.seq.wrapping_add(segment.data.len() as u32));
+        }
+
+        let mut msgs = Vec::new();
+        while self.buffer.len() >= HEADER_LENGTH {
+            let len = BigEndian::read_u16(&self.buffer[2..4]) as usize;
+            if len < HEADER_LENGTH {
+                warn!(
+                    "Invalid OpenFlow message length {} between {} and {}, dropping the rest of the stream.",
+                    len, segment.src, segment.dst
+                );
+                self.lost = true;
+                self.buffer.clear();
+                break;
+            }
+            if self.buffer.len() < len {
+                break;
+            }
+            let rest = self.buffer.split_off(len);
+            msgs.push(::std::mem::replace(&mut self.buffer, rest));
+        }
+        msgs
+    }
+}
+
+/// extracts the tcp segment of a frame, returns None for any non tcp frame
+fn parse_tcp_segment<'a>(frame: &'a [u8], link_type: u32) -> Result<Option<TcpSegment<'a>>> {
+    let ip_packet = match link_type {
+        LINKTYPE_ETHERNET => {
+            if frame.len() < 14 {
+                return Ok(None);
+            }
+            let mut ether_type = BigEndian::read_u16(&frame[12..14]);
+            let mut offset = 14;
+            // skip vlan tags
+            while (ether_type == 0x8100 || ether_type == 0x88a8) && frame.len() >= offset + 4 {
+                ether_type = BigEndian::read_u16(&frame[offset + 2..offset + 4]);
+                offset += 4;
+            }
+            if ether_type != 0x0800 && ether_type != 0x86dd {
+                return Ok(None);
+            }
+            &frame[offset..]
+        }
+        LINKTYPE_LINUX_SLL => {
+            if frame.len() < 16 {
+                return Ok(None);
+            }
+            &frame[16..]
+        }
+        // the 4 byte address family is in host byte order, the ip version tells it anyway
+        LINKTYPE_NULL => {
+            if frame.len() < 4 {
+                return Ok(None);
+            }
+            &frame[4..]
+        }
+        LINKTYPE_RAW => frame,
+        _ => bail!(ErrorKind::UnsupportedValue(link_type as u64, "LinkType")),
+    };
+    if ip_packet.is_empty() {
+        return Ok(None);
+    }
+
+    let (src_ip, dst_ip, tcp_packet) = match ip_packet[0] >> 4 {
+        4 => {
+            if ip_packet.len() < 20 {
+                return Ok(None);
+            }
+            let header_len = ((ip_packet[0] & 0x0f) as usize) * 4;
+            let total_len = BigEndian::read_u16(&ip_packet[2..4]) as usize;
+            let fragment = BigEndian::read_u16(&ip_packet[6..8]) & 0x3fff;
+            if ip_packet[9] != 6 || fragment != 0 || header_len < 20 {
+                return Ok(None);
+            }
+            // ignore ethernet padding
+            let end = ::std::cmp::min(total_len, ip_packet.len());
+            if end < header_len {
+                return Ok(None);
+            }
+            let src = Ipv4Addr::new(ip_packet[12], ip_packet[13], ip_packet[14], ip_packet[15]);
+            let dst = Ipv4Addr::new(ip_packet[16], ip_packet[17], ip_packet[18], ip_packet[19]);
+            (
+                IpAddr::V4(src),
+                IpAddr::V4(dst),
+                &ip_packet[header_len..end],
+            )
+        }
+        6 => {
+            // extension headers are not supported
+            if ip_packet.len() < 40 || ip_packet[6] != 6 {
+                return Ok(None);
+            }
+            let payload_len = BigEndian::read_u16(&ip_packet[4..6]) as usize;
+            let end = ::std::cmp::min(40 + payload_len, ip_packet.len());
+            let mut src = [0u8; 16];
+            src.copy_from_slice(&ip_packet[8..24]);
+            let mut dst = [0u8; 16];
+            dst.copy_from_slice(&ip_packet[24..40]);
+            (
+                IpAddr::V6(Ipv6Addr::from(src)),
+                IpAddr::V6(Ipv6Addr::from(dst)),
+                &ip_packet[40..end],
+            )
+        }
+        _ => return Ok(None),
+    };
+
+    if tcp_packet.len() < 20 {
+        return Ok(None);
+    }
+    let data_offset = ((tcp_packet[12] >> 4) as usize) * 4;
+    if data_offset < 20 || data_offset > tcp_packet.len() {
+        return Ok(None);
+    }
+    Ok(Some(TcpSegment {
+        src: SocketAddr::new(src_ip, BigEndian::read_u16(&tcp_packet[0..2])),
+        dst: SocketAddr::new(dst_ip, BigEndian::read_u16(&tcp_packet[2..4])),
+        seq: BigEndian::read_u32(&tcp_packet[4..8]),
+        syn: tcp_packet[13] & 0x02 != 0,
+        data: &tcp_packet[data_offset..],
+    }))
+}
+
+/// Records raw OpenFlow messages in the dump format.
+pub struct CaptureWriter<W: Write> {
+    inner: W,
+}
+
+impl CaptureWriter<File> {
+    /// creates (or truncates) the file at the given path and writes the file header
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        CaptureWriter::new(File::create(path)?)
+    }
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// writes the file header to the given writer
+    pub fn new(inner: W) -> Result<Self> {
+        let mut inner = inner;
+        inner.write_all(&DUMP_MAGIC[..])?;
+        inner.write_u16::<BigEndian>(DUMP_VERSION)?;
+        inner.write_u16::<BigEndian>(0)?; // reserved
+        inner.flush()?;
+        Ok(CaptureWriter { inner: inner })
+    }
+
+    /// records a raw message (header included) with the current time
+    pub fn write_raw(
+        &mut self,
+        direction: Direction,
+        peer: Option<SocketAddr>,
+        bytes: &[u8],
+    ) -> Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0));
+        self.write_raw_at(timestamp, direction, peer, bytes)
+    }
+
+    /// records a raw message (header included) with the given timestamp (since unix epoch)
+    pub fn write_raw_at(
+        &mut self,
+        timestamp: Duration,
+        direction: Direction,
+        peer: Option<SocketAddr>,
+        bytes: &[u8],
+    ) -> Result<()> {
+        // assemble the record first so it is written with a single write call
+        let mut record = Vec::with_capacity(bytes.len() + 40);
+        record.write_u64::<BigEndian>(timestamp.as_secs())?;
+        record.write_u32::<BigEndian>(timestamp.subsec_nanos())?;
+        record.write_u8(direction as u8)?;
+        match peer {
+            None => record.write_u8(0)?,
+            Some(SocketAddr::V4(addr)) => {
+                record.write_u8(4)?;
+                record.extend_from_slice(&addr.ip().octets()[..]);
+                record.write_u16::<BigEndian>(addr.port())?;
+            }
+            Some(SocketAddr::V6(addr)) => {
+                record.write_u8(6)?;
+                record.extend_from_slice(&addr.ip().octets()[..]);
+                record.write_u16::<BigEndian>(addr.port())?;
+            }
+        }
+        record.write_u32::<BigEndian>(bytes.len() as u32)?;
+        record.extend_from_slice(bytes);
+        self.inner.write_all(&record[..])?;
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    /// returns the underlying writer
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::factory;
+    use super::super::{OfPayload, Type};
+    use super::*;
+
+    fn hello(xid: u32) -> Vec<u8> {
+        OfMsg::generate(xid, OfPayload::Hello).encode().unwrap()
+    }
+
+    #[test]
+    fn dump_write_read() {
+        let peer: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+        let mut writer = CaptureWriter::new(Vec::new()).expect("could not create writer");
+        writer
+            .write_raw_at(
+                Duration::new(5, 7),
+                Direction::Incoming,
+                Some(peer),
+                &hello(1),
+            )
+            .expect("could not write record");
+        writer
+            .write_raw(Direction::Outgoing, None, &hello(2))
+            .expect("could not write record");
+        let bytes = writer.into_inner();
+
+        let msgs: Vec<CapturedMsg> = CaptureReader::from_bytes(&bytes[..])
+            .expect("could not read dump")
+            .map(|msg| msg.expect("could not decode msg"))
+            .collect();
+        assert_eq!(2, msgs.len());
+        assert_eq!(&Duration::new(5, 7), msgs[0].timestamp());
+        assert_eq!(&Direction::Incoming, msgs[0].direction());
+        assert_eq!(&Some(peer), msgs[0].peer());
+        assert_eq!(&1, msgs[0].msg().header().xid());
+        assert_eq!(&Direction::Outgoing, msgs[1].direction());
+        assert_eq!(&None, msgs[1].peer());
+        assert_eq!(&Type::Hello, msgs[1].msg().header().ttype());
+    }
+
+    #[test]
+    fn rejects_invalid_timestamps() {
+        let mut pcap = Vec::new();
+        pcap.write_u32::<LittleEndian>(PCAP_MAGIC_MICROS).unwrap();
+        pcap.write_u16::<LittleEndian>(2).unwrap();
+        pcap.write_u16::<LittleEndian>(4).unwrap();
+        pcap.write_u32::<LittleEndian>(0).unwrap();
+        pcap.write_u32::<LittleEndian>(0).unwrap();
+        pcap.write_u32::<LittleEndian>(65535).unwrap();
+        pcap.write_u32::<LittleEndian>(LINKTYPE_RAW).unwrap();
+        let frame = tcp_frame(40000, 6653, 99, 0x02, &[]);
+        pcap.write_u32::<LittleEndian>(1).unwrap();
+        pcap.write_u32::<LittleEndian>(0).unwrap();
+        pcap.write_u32::<LittleEndian>(frame.len() as u32).unwrap();
+        pcap.write_u32::<LittleEndian>(frame.len() as u32).unwrap();
+        pcap.extend_from_slice(&frame[..]);
+        assert!(CaptureReader::from_bytes(&pcap[..]).is_ok());
+
+        // microseconds that overflow the nanoseconds
+        LittleEndian::write_u32(&mut pcap[PCAP_HEADER_LENGTH + 4..], u32::MAX);
+        assert!(CaptureReader::from_bytes(&pcap[..]).is_err());
+        // a whole second of microseconds
+        LittleEndian::write_u32(&mut pcap[PCAP_HEADER_LENGTH + 4..], 1_000_000);
+        assert!(CaptureReader::from_bytes(&pcap[..]).is_err());
+        // a whole second of nanoseconds
+        LittleEndian::write_u32(&mut pcap[..], PCAP_MAGIC_NANOS);
+        LittleEndian::write_u32(&mut pcap[PCAP_HEADER_LENGTH + 4..], 1_000_000_000);
+        assert!(CaptureReader::from_bytes(&pcap[..]).is_err());
+
+        let mut writer = CaptureWriter::new(Vec::new()).expect("could not create writer");
+        writer
+            .write_raw_at(Duration::new(5, 7), Direction::Incoming, None, &hello(1))
+            .expect("could not write record");
+        let mut dump = writer.into_inner();
+        // the nanoseconds follow the magic, version and u64 seconds
+        BigEndian::write_u32(&mut dump[DUMP_MAGIC.len() + 12..], 1_000_000_000);
+        assert!(CaptureReader::from_bytes(&dump[..]).is_err());
+    }
+
+    #[test]
+    fn decodes_with_the_flags_policy() {
+        let features = OfPayload::FeaturesReply(factory::features_reply(1));
+        let mut reply = OfMsg::generate(1, features).encode().unwrap();
+        // bit 31 of the capabilities
+        reply[24] |= 0x80;
+        let mut writer = CaptureWriter::new(Vec::new()).expect("could not create writer");
+        writer
+            .write_raw(Direction::Incoming, None, &reply)
+            .expect("could not write record");
+        let bytes = writer.into_inner();
+
+        let mut strict = CaptureReader::from_bytes(&bytes[..]).expect("could not read dump");
+        assert!(strict.next().unwrap().is_err());
+        let mut truncate = CaptureReader::from_bytes(&bytes[..])
+            .expect("could not read dump")
+            .with_flags_policy(FlagsPolicy::Truncate);
+        assert!(truncate.next().unwrap().is_ok());
+    }
+
+    /// builds a raw ipv4/tcp frame
+    fn tcp_frame(src_port: u16, dst_port: u16, seq: u32, flags: u8, data: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+        frame.write_u8(0x45).unwrap();
+        frame.write_u8(0).unwrap();
+        frame
+            .write_u16::<BigEndian>(40 + data.len() as u16)
+            .unwrap();
+        frame.write_u32::<BigEndian>(0).unwrap();
+        frame.write_u8(64).unwrap();
+        frame.write_u8(6).unwrap();
+        frame.write_u16::<BigEndian>(0).unwrap();
+        frame.extend_from_slice(&[10, 0, 0, 1]);
+        frame.extend_from_slice(&[10, 0, 0, 2]);
+        frame.write_u16::<BigEndian>(src_port).unwrap();
+        frame.write_u16::<BigEndian>(dst_port).unwrap();
+        frame.write_u32::<BigEndian>(seq).unwrap();
+        frame.write_u32::<BigEndian>(0).unwrap();
+        frame.write_u8(5 << 4).unwrap();
+        frame.write_u8(flags).unwrap();
+        frame.write_u16::<BigEndian>(0).unwrap();
+        frame.write_u32::<BigEndian>(0).unwrap();
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    #[test]
+    fn pcap_reassembly() {
+        let mut pcap = Vec::new();
+        pcap.write_u32::<LittleEndian>(PCAP_MAGIC_MICROS).unwrap();
+        pcap.write_u16::<LittleEndian>(2).unwrap();
+        pcap.write_u16::<LittleEndian>(4).unwrap();
+        pcap.write_u32::<LittleEndian>(0).unwrap();
+        pcap.write_u32::<LittleEndian>(0).unwrap();
+        pcap.write_u32::<LittleEndian>(65535).unwrap();
+        pcap.write_u32::<LittleEndian>(LINKTYPE_RAW).unwrap();
+
+        // two hellos split over three segments, including a retransmission
+        let mut stream = hello(1);
+        stream.extend_from_slice(&hello(2)[..]);
+        let frames = vec![
+            tcp_frame(40000, 6653, 99, 0x02, &[]),
+            tcp_frame(40000, 6653, 100, 0x18, &stream[..5]),
+            tcp_frame(40000, 6653, 100, 0x18, &stream[..5]),
+            tcp_frame(40000, 6653, 105, 0x18, &stream[5..]),
+            tcp_frame(40000, 80, 1, 0x18, &stream[..]),
+        ];
+        for frame in frames {
+            pcap.write_u32::<LittleEndian>(1).unwrap();
+            pcap.write_u32::<LittleEndian>(2).unwrap();
+            pcap.write_u32::<LittleEndian>(frame.len() as u32).unwrap();
+            pcap.write_u32::<LittleEndian>(frame.len() as u32).unwrap();
+            pcap.extend_from_slice(&frame[..]);
+        }
+
+        let msgs: Vec<CapturedMsg> = CaptureReader::from_bytes(&pcap[..])
+            .expect("could not read pcap")
+            .map(|msg| msg.expect("could not decode msg"))
+            .collect();
+        assert_eq!(2, msgs.len());
+        assert_eq!(&1, msgs[0].msg().header().xid());
+        assert_eq!(&2, msgs[1].msg().header().xid());
+        assert_eq!(&Direction::Incoming, msgs[1].direction());
+        assert_eq!(&Some("10.0.0.1:40000".parse().unwrap()), msgs[1].peer());
+        assert_eq!(&Duration::new(1, 2000), msgs[1].timestamp());
+    }
+}
@@ -0,0 +1,210 @@
+//! Encoding and decoding traits of the wire format.
+//!
+//! `OfEncode` writes a value into an existing buffer and knows its encoded
+//! length up front, so buffers can be sized once and length fields (eg. of the
+//! header or of an embedded message) are known without encoding twice.
+//! `OfDecode` is implemented for every type that can be decoded from a slice.
+//!
+//! The `Into<Vec<u8>>` and `TryFrom<&[u8]>` impls of the types in `ds` are
+//! deprecated, new code should use the traits. They are kept until every type
+//! implements `OfEncode` directly; trait impls cannot carry `#[deprecated]`,
+//! so the compiler does not warn about them. Types that are not ported yet
+//! implement `OfEncode` by encoding a clone, see `encode_via_into!`, their
+//! length is only known after encoding, so they are never encoded twice just
+//! to size a buffer (see `size_hint`). Fixed-layout structs derive both traits
+//! and the conversions with `#[derive(OfWire)]`, see `wire`.
+
+use std::convert::TryFrom;
+
+use super::super::err::*;
+
+/// A value that can be written in the OpenFlow wire format.
+pub trait OfEncode {
+    /// number of bytes encode_into appends
+    fn encoded_len(&self) -> usize;
+
+    /// appends the encoded value to buf
+    fn encode_into(&self, buf: &mut Vec<u8>);
+
+    /// encoded_len if it is known without encoding the value, None for types
+    /// that encode themselves to find out (see `encode_via_into!`)
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.encoded_len())
+    }
+
+    /// encodes the value into a new buffer, sized by size_hint if it is known
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.size_hint().unwrap_or(0));
+        self.encode_into(&mut buf);
+        buf
+    }
+}
+
+/// A value that can be read from the OpenFlow wire format.
+pub trait OfDecode: Sized {
+    /// decodes the value from the start of bytes
+    fn decode(bytes: &[u8]) -> Result<Self>;
+}
+
+impl<T> OfDecode for T
+where
+    T: for<'a> TryFrom<&'a [u8], Error = Error>,
+{
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        T::try_from(bytes)
+    }
+}
+
+/// raw bytes, eg. the data of echo messages
+impl OfEncode for u8 {
+    fn encoded_len(&self) -> usize {
+        1
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.push(*self);
+    }
+}
+
+impl<T: OfEncode> OfEncode for [T] {
+    fn encoded_len(&self) -> usize {
+        self.iter().map(|entry| entry.encoded_len()).sum()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.iter().map(|entry| entry.size_hint()).sum()
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        for entry in self {
+            entry.encode_into(buf);
+        }
+    }
+}
+
+impl<T: OfEncode> OfEncode for Vec<T> {
+    fn encoded_len(&self) -> usize {
+        self[..].encoded_len()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self[..].size_hint()
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        self[..].encode_into(buf)
+    }
+}
+
+/// implements OfEncode for types that are only encoded by `Into<Vec<u8>>` so far
+/// the value is cloned and encoded, also to compute its length, so they have no
+/// size_hint and to_bytes encodes them once
+macro_rules! encode_via_into {
+    ($($ttype:ty,)*) => {
+        $(
+            impl OfEncode for $ttype {
+                fn encoded_len(&self) -> usize {
+                    Into::<Vec<u8>>::into(self.clone()).len()
+                }
+
+                fn size_hint(&self) -> Option<usize> {
+                    None
+                }
+
+                fn encode_into(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&Into::<Vec<u8>>::into(self.clone())[..]);
+                }
+
+                fn to_bytes(&self) -> Vec<u8> {
+                    self.clone().into()
+                }
+            }
+        )*
+    };
+}
+
+encode_via_into! {
+    super::actions::ActionPayload,
+    super::bundle::BundleCtrl,
+    super::error_msg::ErrorMsg,
+    super::flow_instructions::PayloadClearActions,
+    super::flow_instructions::PayloadGotoTable,
+    super::flow_instructions::PayloadMeter,
+    super::flow_instructions::PayloadWriteMetaData,
+    super::flow_match::MatchPayload,
+    super::flow_removed::FlowRemoved,
+    super::group_mod::GroupMod,
+    super::meter_mod::MeterBandPayload,
+    super::meter_mod::MeterMod,
+    super::multipart::MultipartReply,
+    super::multipart::MultipartRequest,
+    super::packet_queue::QueuePropPayload,
+    super::port_status::PortStatus,
+    super::table_mod::TableModProp,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::flow_match::Match;
+    use super::super::flow_mod::FlowMod;
+    use super::super::flow_parser::parse_flow_mod;
+    use super::super::multipart::{MultipartReply, RepPayload};
+    use super::super::packet_out::PacketOut;
+    use super::super::{OfMsg, OfPayload, HEADER_LENGTH};
+    use super::*;
+    use byteorder::{BigEndian, ByteOrder};
+
+    #[test]
+    fn encoded_len_matches_the_encoding() {
+        let flow_mod = parse_flow_mod("ip,nw_src=10.0.0.1,actions=output:2").unwrap();
+        assert_eq!(flow_mod.encoded_len(), flow_mod.to_bytes().len());
+        let packet_out = PacketOut::flood(vec![1, 2, 3]);
+        assert_eq!(packet_out.encoded_len(), packet_out.to_bytes().len());
+
+        let msg = OfMsg::generate(3, OfPayload::FlowMod(flow_mod.clone()));
+        let bytes = msg.to_bytes();
+        assert_eq!(msg.encoded_len(), bytes.len());
+        assert_eq!(BigEndian::read_u16(&bytes[2..4]) as usize, bytes.len());
+        assert_eq!(FlowMod::decode(&bytes[HEADER_LENGTH..]).unwrap(), flow_mod);
+
+        // the deprecated conversions agree with the traits
+        assert_eq!(Into::<Vec<u8>>::into(flow_mod.clone()), flow_mod.to_bytes());
+        assert_eq!(msg.encode().unwrap(), bytes);
+    }
+
+    #[test]
+    fn bridged_types_are_not_sized_up_front() {
+        let flow_mod = parse_flow_mod("ip,actions=drop").unwrap();
+        let msg = OfMsg::generate(3, OfPayload::FlowMod(flow_mod));
+        assert_eq!(msg.size_hint(), Some(msg.encoded_len()));
+
+        let reply = MultipartReply::new(RepPayload::Flow(Vec::new()));
+        assert_eq!(reply.size_hint(), None);
+        assert_eq!(reply.to_bytes(), Into::<Vec<u8>>::into(reply.clone()));
+        let msg = OfMsg::generate(3, OfPayload::MultipartReply(reply));
+        assert_eq!(msg.size_hint(), None);
+        let bytes = msg.to_bytes();
+        assert_eq!(msg.encoded_len(), bytes.len());
+        assert_eq!(BigEndian::read_u16(&bytes[2..4]) as usize, bytes.len());
+    }
+
+    #[test]
+    fn stale_length_fields_are_ignored() {
+        let mut packet_out = PacketOut::flood(vec![1, 2, 3]);
+        let actions_len = packet_out.actions_len;
+        packet_out.actions_len = 0;
+        let bytes = packet_out.to_bytes();
+        assert_eq!(BigEndian::read_u16(&bytes[8..10]), actions_len);
+        assert_eq!(
+            PacketOut::decode(&bytes).unwrap().actions,
+            packet_out.actions
+        );
+
+        let mut flow_mod = parse_flow_mod("ip,nw_src=10.0.0.1,actions=output:2").unwrap();
+        let expected = flow_mod.to_bytes();
+        let mut matches = flow_mod.mmatch.matches().clone();
+        matches[0].tlv_header.set_length(99);
+        flow_mod.mmatch = Match::new(matches);
+        assert_eq!(flow_mod.to_bytes(), expected);
+    }
+}
@@ -0,0 +1,211 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::convert::{Into, TryFrom};
+use std::io::Cursor;
+
+use super::super::err::*;
+
+/// length of the fixed part of an error message (type and code)
+pub const ERROR_MSG_LEN: usize = 4;
+/// number of bytes of the failed request sent back in an error
+pub const ERROR_DATA_LEN: usize = 64;
+
+/// Error message sent by the switch if a request failed.
+/// Type and code are kept raw, the meaning of the code depends on the type.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ErrorMsg {
+    pub ttype: u16,
+    pub code: u16,
+    /// at least the first 64 bytes of the failed request
+    pub data: Vec<u8>,
+}
+
+impl ErrorMsg {
+    /// error about the given request, data keeps up to ERROR_DATA_LEN bytes of it
+    pub fn new(ttype: ErrorType, code: u16, request: &[u8]) -> Self {
+        ErrorMsg {
+            ttype: ttype.to_u16().unwrap(),
+            code: code,
+            data: request[..request.len().min(ERROR_DATA_LEN)].to_vec(),
+        }
+    }
+
+    /// hello failed, eg. because no common version exists
+    pub fn hello_failed(code: HelloFailedCode, request: &[u8]) -> Self {
+        ErrorMsg::new(ErrorType::HelloFailed, code.to_u16().unwrap(), request)
+    }
+
+    /// the request was not understood, eg. because of its type or version
+    pub fn bad_request(code: BadRequestCode, request: &[u8]) -> Self {
+        ErrorMsg::new(ErrorType::BadRequest, code.to_u16().unwrap(), request)
+    }
+
+    /// the type of the error, None for types this library does not know
+    pub fn error_type(&self) -> Option<ErrorType> {
+        ErrorType::from_u16(self.ttype)
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for ErrorMsg {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(bytes);
+        let ttype = cursor.read_u16::<BigEndian>()?;
+        let code = cursor.read_u16::<BigEndian>()?;
+        Ok(ErrorMsg {
+            ttype: ttype,
+            code: code,
+            data: Vec::from(&bytes[ERROR_MSG_LEN..]),
+        })
+    }
+}
+
+impl Into<Vec<u8>> for ErrorMsg {
+    fn into(self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.write_u16::<BigEndian>(self.ttype).unwrap();
+        res.write_u16::<BigEndian>(self.code).unwrap();
+        res.extend_from_slice(&self.data[..]);
+        res
+    }
+}
+
+/// Values for the type of error messages.
+#[derive(Primitive, PartialEq, Debug, Clone, Copy)]
+pub enum ErrorType {
+    /// Hello protocol failed.
+    HelloFailed = 0,
+    /// Request was not understood.
+    BadRequest = 1,
+    /// Error in action description.
+    BadAction = 2,
+    /// Error in instruction list.
+    BadInstruction = 3,
+    /// Error in match.
+    BadMatch = 4,
+    /// Problem modifying flow entry.
+    FlowModFailed = 5,
+    /// Problem modifying group entry.
+    GroupModFailed = 6,
+    /// Port mod request failed.
+    PortModFailed = 7,
+    /// Table mod request failed.
+    TableModFailed = 8,
+    /// Queue operation failed.
+    QueueOpFailed = 9,
+    /// Switch config request failed.
+    SwitchConfigFailed = 10,
+    /// Controller Role request failed.
+    RoleRequestFailed = 11,
+    /// Error in meter.
+    MeterModFailed = 12,
+    /// Setting table features failed.
+    TableFeaturesFailed = 13,
+    /// Some property is invalid (since 1.4).
+    BadProperty = 14,
+    /// Asynchronous config request failed (since 1.4).
+    AsyncConfigFailed = 15,
+    /// Setting flow monitor failed (since 1.4).
+    FlowMonitorFailed = 16,
+    /// Bundle operation failed (since 1.4).
+    BundleFailed = 17,
+    /// Experimenter error messages.
+    Experimenter = 0xffff,
+}
+
+/// Codes of HelloFailed errors.
+#[derive(Primitive, PartialEq, Debug, Clone, Copy)]
+pub enum HelloFailedCode {
+    /// No compatible version.
+    Incompatible = 0,
+    /// Permissions error.
+    EPerm = 1,
+}
+
+/// Codes of BadRequest errors.
+#[derive(Primitive, PartialEq, Debug, Clone, Copy)]
+pub enum BadRequestCode {
+    /// Header version not supported.
+    BadVersion = 0,
+    /// Header type not supported.
+    BadType = 1,
+    /// Multipart type not supported.
+    BadMultipart = 2,
+    /// Experimenter id not supported.
+    BadExperimenter = 3,
+    /// Experimenter type not supported.
+    BadExpType = 4,
+    /// Permissions error.
+    EPerm = 5,
+    /// Wrong request length for type.
+    BadLen = 6,
+    /// Specified buffer has already been used.
+    BufferEmpty = 7,
+    /// Specified buffer does not exist.
+    BufferUnknown = 8,
+    /// Specified table id is invalid or does not exist.
+    BadTableId = 9,
+    /// Denied because controller is slave.
+    IsSlave = 10,
+    /// Invalid port.
+    BadPort = 11,
+    /// Invalid packet in packet out.
+    BadPacket = 12,
+    /// Multipart request overflowed the assigned buffer.
+    MultipartBufferOverflow = 13,
+}
+
+/// Codes of BundleFailed errors (since 1.4).
+#[derive(Primitive, PartialEq, Debug, Clone, Copy)]
+pub enum BundleFailedCode {
+    /// Unspecified error.
+    Unknown = 0,
+    /// Permissions error.
+    EPerm = 1,
+    /// Bundle ID doesn't exist.
+    BadId = 2,
+    /// Bundle ID already exists.
+    BundleExist = 3,
+    /// Bundle ID is closed.
+    BundleClosed = 4,
+    /// Too many bundles IDs.
+    OutOfBundles = 5,
+    /// Unsupported or unknown message control type.
+    BadType = 6,
+    /// Unsupported, unknown, or inconsistent flags.
+    BadFlags = 7,
+    /// Length problem in included message.
+    MsgBadLen = 8,
+    /// Inconsistent or duplicate XID.
+    MsgBadXid = 9,
+    /// Unsupported message in this bundle.
+    MsgUnsup = 10,
+    /// Unsupported message combination in this bundle.
+    MsgConflict = 11,
+    /// Can't handle this many messages in bundle.
+    MsgTooMany = 12,
+    /// One message in bundle failed.
+    MsgFailed = 13,
+    /// Bundle is taking too long.
+    Timeout = 14,
+    /// Bundle is locking the resource.
+    BundleInProgress = 15,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_start_of_the_request() {
+        let request = vec![7; 100];
+        let error = ErrorMsg::bad_request(BadRequestCode::BadType, &request);
+        assert_eq!(error.error_type(), Some(ErrorType::BadRequest));
+        assert_eq!(error.code, 1);
+        assert_eq!(error.data, vec![7; ERROR_DATA_LEN]);
+
+        let error = ErrorMsg::hello_failed(HelloFailedCode::Incompatible, &request[..8]);
+        assert_eq!((error.ttype, error.code), (0, 0));
+        assert_eq!(error.data.len(), 8);
+    }
+}
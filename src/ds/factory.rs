@@ -0,0 +1,182 @@
+//! Complete messages in a line, for tests.
+//!
+//! The functions build valid messages with the values a switch or controller
+//! would use for everything that is not given, matches and actions are given
+//! in the syntax of `flow_parser`. They panic if it does not parse, so they are
+//! meant for tests of oath2 and of applications. `ofmsg!` wraps a message into
+//! an `OfMsg`:
+//!
+//! ```
+//! #[macro_use]
+//! extern crate oath2;
+//!
+//! use oath2::ds::factory::{flow_mod_add, packet_in};
+//! use oath2::ds::packet_out::BufferId;
+//!
+//! # fn main() {
+//! let packet_in = ofmsg!(7, PacketIn(packet_in(BufferId::NoBuffer, 1, &[0xff; 14])));
+//! let flow_mod = ofmsg!(FlowMod(flow_mod_add(0, 10, "ip,nw_dst=10.0.0.1", "output:2")));
+//! let barrier = ofmsg!(BarrierRequest);
+//! assert_eq!(*packet_in.header().xid(), 7);
+//! # }
+//! ```
+
+use std::convert::TryFrom;
+
+use super::addr::MacAddr;
+use super::features::{Capabilities, SwitchFeatures};
+use super::flow_match::Match;
+use super::flow_mod::{FlowMod, FlowModBuilder};
+use super::flow_parser::{parse_actions, parse_instructions, parse_match};
+use super::flow_removed::{FlowRemoved, FlowRemovedReason};
+use super::packet_in::{InReason, PacketIn};
+use super::packet_out::{BufferId, PacketOut};
+use super::port_status::{PortReason, PortStatus};
+use super::ports::{Port, PortName, PortNo, PortNumber};
+
+/// wraps a message into an `OfMsg` of the highest version, eg.
+/// `ofmsg!(FlowMod(flow_mod))` with xid 0, `ofmsg!(7, FlowMod(flow_mod))` with
+/// xid 7 and `ofmsg!(BarrierRequest)` for messages without body
+#[macro_export]
+macro_rules! ofmsg {
+    ($variant:ident($message:expr)) => {
+        $crate::ds::OfMsg::generate(0, $crate::ds::OfPayload::$variant($message))
+    };
+    ($variant:ident) => {
+        $crate::ds::OfMsg::generate(0, $crate::ds::OfPayload::$variant)
+    };
+    ($xid:expr, $variant:ident($message:expr)) => {
+        $crate::ds::OfMsg::generate($xid, $crate::ds::OfPayload::$variant($message))
+    };
+    ($xid:expr, $variant:ident) => {
+        $crate::ds::OfMsg::generate($xid, $crate::ds::OfPayload::$variant)
+    };
+}
+
+/// the port number, reserved ports included (eg. 0xfffffffd for the controller)
+fn port_number(port_no: u32) -> PortNumber {
+    PortNumber::try_from(port_no).expect("valid port number")
+}
+
+fn mmatch(fields: &str) -> Match {
+    parse_match(fields).unwrap_or_else(|err| panic!("invalid match {:?}: {}", fields, err))
+}
+
+/// features of a switch with one table and no buffers
+pub fn features_reply(datapath_id: u64) -> SwitchFeatures {
+    SwitchFeatures {
+        datapath_id: datapath_id,
+        n_buffers: 0,
+        n_tables: 1,
+        auxiliary_id: 0,
+        capabilities: Capabilities::empty(),
+        reserved: 0,
+    }
+}
+
+/// port named `eth<port_no>` with the address 02:00:00:00:00:<port_no>
+pub fn port(port_no: u32) -> Port {
+    let name = PortName::new(format!("eth{}", port_no)).expect("short port name");
+    let hw_addr = MacAddr([0x02, 0, 0, 0, 0, port_no as u8]);
+    Port::new(PortNumber::NormalPort(port_no), hw_addr, name)
+}
+
+pub fn port_status(reason: PortReason, port_no: u32) -> PortStatus {
+    PortStatus::new(reason, port(port_no))
+}
+
+/// table miss of the whole frame in table 0
+pub fn packet_in(buffer_id: BufferId, in_port: u32, frame: &[u8]) -> PacketIn {
+    PacketIn {
+        buffer_id: buffer_id,
+        total_len: frame.len() as u16,
+        reason: InReason::NoMatch,
+        table_id: 0,
+        cookie: 0,
+        mmatch: mmatch(&format!("in_port={}", in_port)),
+        ethernet_frame: frame.to_vec(),
+    }
+}
+
+/// applies the actions (eg. `output:2`) to the frame as if it arrived at in_port
+pub fn packet_out(in_port: u32, actions: &str, frame: &[u8]) -> PacketOut {
+    let actions = parse_actions(actions)
+        .unwrap_or_else(|err| panic!("invalid actions {:?}: {}", actions, err));
+    PacketOut::new(
+        BufferId::NoBuffer,
+        port_number(in_port),
+        actions,
+        frame.to_vec(),
+    )
+}
+
+/// adds a permanent flow, eg. `flow_mod_add(0, 10, "ip,nw_dst=10.0.0.1", "output:2")`
+/// the actions may also be instructions like `goto_table:1`
+pub fn flow_mod_add(table_id: u8, priority: u16, fields: &str, actions: &str) -> FlowMod {
+    let instructions = parse_instructions(actions)
+        .unwrap_or_else(|err| panic!("invalid actions {:?}: {}", actions, err));
+    FlowModBuilder::add(mmatch(fields))
+        .table(table_id)
+        .priority(priority)
+        .instructions(instructions)
+        .build()
+        .unwrap_or_else(|err| panic!("invalid flow {:?} {:?}: {}", fields, actions, err))
+}
+
+/// deletes the flows of the table that match the fields
+pub fn flow_mod_delete(table_id: u8, fields: &str) -> FlowMod {
+    let mut flow_mod = FlowMod::delete(0, 0, table_id, PortNo::Any.into());
+    flow_mod.mmatch = mmatch(fields);
+    flow_mod
+}
+
+pub fn flow_removed(
+    table_id: u8,
+    priority: u16,
+    fields: &str,
+    reason: FlowRemovedReason,
+) -> FlowRemoved {
+    FlowRemoved::new(0, priority, reason, table_id, mmatch(fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::flow_mod::FlowModCommand;
+    use super::super::{decode_any, Header, OfMsg, HEADER_LENGTH};
+    use super::*;
+
+    #[test]
+    fn builds_messages_that_round_trip() {
+        let msgs = vec![
+            ofmsg!(
+                1,
+                PacketIn(packet_in(BufferId::Buffered(3), 2, &[0xff; 14]))
+            ),
+            ofmsg!(2, PacketOut(packet_out(2, "output:1", &[0xff; 14]))),
+            ofmsg!(
+                3,
+                FlowMod(flow_mod_add(1, 10, "tcp,tp_dst=80", "goto_table:2"))
+            ),
+            ofmsg!(FlowMod(flow_mod_delete(1, "tcp"))),
+            ofmsg!(
+                5,
+                FlowRemoved(flow_removed(1, 10, "tcp", FlowRemovedReason::Delete))
+            ),
+            ofmsg!(6, PortStatus(port_status(PortReason::Add, 3))),
+            ofmsg!(7, FeaturesReply(features_reply(0x2a))),
+            ofmsg!(BarrierReply),
+        ];
+        for msg in msgs {
+            let bytes = msg.encode().unwrap();
+            let header = Header::try_from(&bytes[..HEADER_LENGTH]).unwrap();
+            let payload = decode_any(*header.version(), header.ttype(), &bytes[HEADER_LENGTH..]);
+            let decoded = OfMsg::new(header, payload.unwrap());
+            assert_eq!(decoded.encode().unwrap(), bytes);
+        }
+
+        assert_eq!(flow_mod_delete(1, "tcp").command, FlowModCommand::Delete);
+        let packet_in = packet_in(BufferId::NoBuffer, 2, &[1, 2, 3]);
+        assert_eq!(packet_in.in_port(), Some(PortNumber::NormalPort(2)));
+        assert_eq!(packet_in.total_len, 3);
+    }
+}
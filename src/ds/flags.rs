@@ -0,0 +1,175 @@
+//! Decoding of bitflags with bits oath2 does not know.
+//!
+//! By default a message fails to decode with `ErrorKind::UnknownValue` if a
+//! switch sets a bit of a flags field the specification does not define (eg.
+//! of `FlowModFlags`, `PortConfig` or `Capabilities`). Switches that use
+//! reserved bits can be accepted by decoding with `FlagsPolicy::Truncate`: the
+//! unknown bits are dropped and returned next to the decoded message, so they
+//! can still be reported:
+//!
+//! ```
+//! use oath2::ds::codec::OfEncode;
+//! use oath2::ds::flags::{self, FlagsPolicy};
+//! use oath2::ds::flow_mod::FlowMod;
+//! use oath2::ds::flow_parser::parse_flow_mod;
+//! use std::convert::TryFrom;
+//!
+//! // a flow mod with the bit 15 of its flags set
+//! let mut bytes = parse_flow_mod("priority=1,actions=drop").unwrap().to_bytes();
+//! bytes[36] |= 0x80;
+//! assert!(FlowMod::try_from(&bytes[..]).is_err());
+//!
+//! let (flow_mod, unknown) =
+//!     flags::decode_with(FlagsPolicy::Truncate, || FlowMod::try_from(&bytes[..]));
+//! assert!(flow_mod.is_ok());
+//! assert_eq!(unknown[0].bits, 0x8000);
+//! ```
+//!
+//! The policy only applies inside `decode_with`, everything else decodes
+//! strictly. Whole messages are decoded with an explicit policy by
+//! `OfMsg::decode_with` and `OfPayload::decode_with`, `CaptureReader` and
+//! `MultipartEntries` take one with `with_flags_policy`. The controller
+//! decodes every message of a switch with `ControllerConfig::flags_policy` and
+//! reports dropped bits with an `UnknownFlags` event.
+//!
+//! The `FlowModFlags` of flow stats replies are the exception: switches set
+//! bits of their own there (eg. Open vSwitch's `NO_PKT_COUNTS`), so unknown
+//! bits are always ignored.
+
+use std::cell::{Cell, RefCell};
+
+use super::super::err::*;
+
+/// What happens to bits of a flags field that oath2 does not know.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlagsPolicy {
+    /// the message fails to decode with ErrorKind::UnknownValue
+    #[default]
+    Strict,
+    /// the bits are dropped and reported, see `decode_with`
+    Truncate,
+}
+
+/// Bits that were dropped from a flags field with FlagsPolicy::Truncate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownFlags {
+    /// name of the flags type, eg. `FlowModFlags`
+    pub ttype: &'static str,
+    /// only the dropped bits
+    pub bits: u64,
+}
+
+thread_local! {
+    static POLICY: Cell<FlagsPolicy> = const { Cell::new(FlagsPolicy::Strict) };
+    static DROPPED: RefCell<Vec<UnknownFlags>> = const { RefCell::new(Vec::new()) };
+}
+
+/// restores the policy of the thread, even if the decode panics
+struct PolicyGuard {
+    previous: FlagsPolicy,
+    dropped: Vec<UnknownFlags>,
+}
+
+impl Drop for PolicyGuard {
+    fn drop(&mut self) {
+        let previous = self.previous;
+        POLICY.with(|policy| policy.set(previous));
+        let dropped = ::std::mem::take(&mut self.dropped);
+        DROPPED.with(|outer| *outer.borrow_mut() = dropped);
+    }
+}
+
+/// runs the decode with the policy on this thread
+/// returns its result and the bits that were dropped while it ran
+pub fn decode_with<T, F: FnOnce() -> T>(policy: FlagsPolicy, decode: F) -> (T, Vec<UnknownFlags>) {
+    let _guard = PolicyGuard {
+        previous: POLICY.with(|current| current.replace(policy)),
+        dropped: DROPPED.with(|dropped| dropped.replace(Vec::new())),
+    };
+    let result = decode();
+    let dropped = DROPPED.with(|dropped| dropped.replace(Vec::new()));
+    (result, dropped)
+}
+
+/// the policy of this thread, Strict outside of decode_with
+pub fn policy() -> FlagsPolicy {
+    POLICY.with(|policy| policy.get())
+}
+
+/// handles the unknown bits of a flags field with the policy of this thread,
+/// used by `flags_from_bits!`
+/// fails with the raw value if the policy is Strict
+pub fn unknown_bits(raw: u64, bits: u64, ttype: &'static str) -> Result<()> {
+    match policy() {
+        FlagsPolicy::Strict => bail!(ErrorKind::UnknownValue(raw, ttype)),
+        FlagsPolicy::Truncate => {
+            debug!("Dropped unknown bits {:#x} of {}.", bits, ttype);
+            DROPPED.with(|dropped| {
+                dropped.borrow_mut().push(UnknownFlags {
+                    ttype: ttype,
+                    bits: bits,
+                })
+            });
+            Ok(())
+        }
+    }
+}
+
+/// decodes the raw bits of a bitflags type with the policy of this thread
+macro_rules! flags_from_bits {
+    ($flags:ident, $raw:expr) => {{
+        let raw = $raw;
+        match $flags::from_bits(raw) {
+            Some(flags) => Ok(flags),
+            None => {
+                let flags = $flags::from_bits_truncate(raw);
+                $crate::ds::flags::unknown_bits(
+                    raw as u64,
+                    (raw & !flags.bits()) as u64,
+                    stringify!($flags),
+                )
+                .map(|()| flags)
+            }
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::flow_mod::FlowModFlags;
+    use super::super::ports::PortConfig;
+    use super::*;
+
+    fn flow_mod_flags(raw: u16) -> Result<FlowModFlags> {
+        flags_from_bits!(FlowModFlags, raw)
+    }
+
+    #[test]
+    fn unknown_bits_follow_the_policy() {
+        assert_eq!(flow_mod_flags(0x0001).unwrap(), FlowModFlags::SEND_FLOW_REM);
+        assert!(flow_mod_flags(0x8001).is_err());
+
+        let (flags, dropped) = decode_with(FlagsPolicy::Truncate, || {
+            let port_config: Result<PortConfig> = flags_from_bits!(PortConfig, 0x8000_0001u32);
+            assert_eq!(port_config.unwrap(), PortConfig::PORT_DOWN);
+            flow_mod_flags(0x8001)
+        });
+        assert_eq!(flags.unwrap(), FlowModFlags::SEND_FLOW_REM);
+        assert_eq!(
+            dropped,
+            vec![
+                UnknownFlags {
+                    ttype: "PortConfig",
+                    bits: 0x8000_0000,
+                },
+                UnknownFlags {
+                    ttype: "FlowModFlags",
+                    bits: 0x8000,
+                },
+            ]
+        );
+        // strict again once the decode is done
+        assert_eq!(policy(), FlagsPolicy::Strict);
+        assert!(flow_mod_flags(0x8001).is_err());
+    }
+}
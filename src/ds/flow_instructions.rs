@@ -3,10 +3,13 @@ use super::super::err::*;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use num_traits::{FromPrimitive, ToPrimitive};
 use std::convert::{Into, TryFrom};
-use std::io::{Cursor, Seek, SeekFrom};
+use std::io::Cursor;
 
 use super::actions;
-use std::path;
+use super::checked_tail;
+use super::codec::OfEncode;
+use super::meter_mod::MeterId;
+use super::tlv::{decode_list, LengthField};
 
 #[derive(Primitive, Debug, PartialEq, Clone)]
 pub enum InstructionType {
@@ -27,30 +30,53 @@ pub enum InstructionType {
     Experimenter = 0xFFFF,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Getters, Debug, PartialEq, Clone)]
 pub struct InstructionHeader {
     /// OFPIT_GOTO_TABLE
+    #[get = "pub"]
     ttype: InstructionType,
     /// Length of this struct in bytes.
+    #[get = "pub"]
     len: u16,
+    #[get = "pub"]
     payload: InstructionPayload,
 }
 
-pub fn get_instruction_slice_len(cur: &mut Cursor<&[u8]>) -> usize {
-    cur.seek(SeekFrom::Current(2)).unwrap(); //skip to length
-    let len = cur.read_u16::<BigEndian>().unwrap();
-    cur.seek(SeekFrom::Current(-4)).unwrap();
-    len as usize
+/// Length of the type and length fields of an instruction (4 bytes).
+pub const INSTRUCTION_HEADER_LEN: u16 = 4;
+
+pub fn get_instruction_slice_len(cur: &mut Cursor<&[u8]>) -> Result<usize> {
+    let pos = cur.position() as usize;
+    INSTRUCTION_LEN_FIELD.read(cur.get_ref(), pos, stringify!(InstructionHeader))
+}
+
+/// the length includes the header, anything shorter is corrupt
+pub const INSTRUCTION_LEN_FIELD: LengthField =
+    LengthField::u16_at(2, INSTRUCTION_HEADER_LEN as usize);
+
+/// decodes a list of instructions that fills the whole slice
+pub fn instructions_from_slice(bytes: &[u8]) -> Result<Vec<InstructionHeader>> {
+    decode_list(bytes, 0, INSTRUCTION_LEN_FIELD, stringify!(InstructionHeader))
+}
+
+impl OfEncode for InstructionHeader {
+    fn encoded_len(&self) -> usize {
+        INSTRUCTION_HEADER_LEN as usize + self.payload.encoded_len()
+    }
+
+    /// the length is computed from the payload, the stored one is ignored
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.write_u16::<BigEndian>(self.ttype.to_u16().unwrap())
+            .unwrap();
+        buf.write_u16::<BigEndian>(self.encoded_len() as u16)
+            .unwrap();
+        self.payload.encode_into(buf);
+    }
 }
 
 impl Into<Vec<u8>> for InstructionHeader {
     fn into(self) -> Vec<u8> {
-        let mut res = Vec::new();
-        res.write_u16::<BigEndian>(self.ttype.to_u16().unwrap())
-            .unwrap();
-        res.write_u16::<BigEndian>(self.len).unwrap();
-        res.extend_from_slice(&Into::<Vec<u8>>::into(self.payload));
-        res
+        self.to_bytes()
     }
 }
 
@@ -59,48 +85,44 @@ impl<'a> TryFrom<&'a [u8]> for InstructionHeader {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
 
-        let raw_ttype = cursor.read_u16::<BigEndian>().chain_err(|| {
-            let err_msg = format!(
-                "Could not read InstructionHeader raw_ttype!{}Cursor: {:?}",
-                path::MAIN_SEPARATOR,
-                cursor
-            );
-            error!("{}", err_msg);
-            err_msg
-        })?;
+        let raw_ttype = cursor.read_u16::<BigEndian>().at(cursor.position())?;
         let ttype = InstructionType::from_u16(raw_ttype).ok_or::<Error>(
             ErrorKind::UnknownValue(raw_ttype as u64, stringify!(InstructionType)).into(),
         )?;
 
-        let length = cursor.read_u16::<BigEndian>().chain_err(|| {
-            let err_msg = format!(
-                "Could not read InstructionHeader length!{}Cursor: {:?}",
-                path::MAIN_SEPARATOR,
-                cursor
-            );
-            error!("{}", err_msg);
-            err_msg
-        })?;
+        let length = cursor.read_u16::<BigEndian>().at(cursor.position())?;
         let payload_slice = &bytes[4..];
 
         let payload = match ttype {
             InstructionType::GotoTable => {
-                InstructionPayload::GotoTable(PayloadGotoTable::try_from(payload_slice)?)
+                InstructionPayload::GotoTable(
+                    PayloadGotoTable::try_from(payload_slice).within(4)?,
+                )
             }
             InstructionType::WriteMetadata => {
-                InstructionPayload::WriteMetaData(PayloadWriteMetaData::try_from(payload_slice)?)
+                InstructionPayload::WriteMetaData(
+                    PayloadWriteMetaData::try_from(payload_slice).within(4)?,
+                )
             }
             InstructionType::WriteActions => {
-                InstructionPayload::WriteActions(PayloadWriteActions::try_from(payload_slice)?)
+                InstructionPayload::WriteActions(
+                    PayloadWriteActions::try_from(payload_slice).within(4)?,
+                )
             }
             InstructionType::ApplyActions => {
-                InstructionPayload::ApplyActions(PayloadApplyActions::try_from(payload_slice)?)
+                InstructionPayload::ApplyActions(
+                    PayloadApplyActions::try_from(payload_slice).within(4)?,
+                )
             }
             InstructionType::Clearactions => {
-                InstructionPayload::ClearActions(PayloadClearActions::try_from(payload_slice)?)
+                InstructionPayload::ClearActions(
+                    PayloadClearActions::try_from(payload_slice).within(4)?,
+                )
             }
             InstructionType::Meter => {
-                InstructionPayload::Meter(PayloadMeter::try_from(payload_slice)?)
+                InstructionPayload::Meter(
+                    PayloadMeter::try_from(payload_slice).within(4)?,
+                )
             }
             InstructionType::Experimenter => bail!(ErrorKind::UnsupportedValue(
                 ttype as u64,
@@ -127,15 +149,57 @@ pub enum InstructionPayload {
     //Experimenter(PayloadExperimenter), // not supported
 }
 
+impl InstructionPayload {
+    fn body(&self) -> &dyn OfEncode {
+        match *self {
+            InstructionPayload::GotoTable(ref payload) => payload,
+            InstructionPayload::WriteMetaData(ref payload) => payload,
+            InstructionPayload::WriteActions(ref payload) => payload,
+            InstructionPayload::ApplyActions(ref payload) => payload,
+            InstructionPayload::ClearActions(ref payload) => payload,
+            InstructionPayload::Meter(ref payload) => payload,
+        }
+    }
+}
+
+impl OfEncode for InstructionPayload {
+    fn encoded_len(&self) -> usize {
+        self.body().encoded_len()
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        self.body().encode_into(buf)
+    }
+}
+
 impl Into<Vec<u8>> for InstructionPayload {
     fn into(self) -> Vec<u8> {
+        self.to_bytes()
+    }
+}
+
+impl InstructionPayload {
+    /// returns the instruction type of this payload
+    pub fn ttype(&self) -> InstructionType {
         match self {
-            InstructionPayload::GotoTable(payload) => payload.into(),
-            InstructionPayload::WriteMetaData(payload) => payload.into(),
-            InstructionPayload::WriteActions(payload) => payload.into(),
-            InstructionPayload::ApplyActions(payload) => payload.into(),
-            InstructionPayload::ClearActions(payload) => payload.into(),
-            InstructionPayload::Meter(payload) => payload.into(),
+            InstructionPayload::GotoTable(_) => InstructionType::GotoTable,
+            InstructionPayload::WriteMetaData(_) => InstructionType::WriteMetadata,
+            InstructionPayload::WriteActions(_) => InstructionType::WriteActions,
+            InstructionPayload::ApplyActions(_) => InstructionType::ApplyActions,
+            InstructionPayload::ClearActions(_) => InstructionType::Clearactions,
+            InstructionPayload::Meter(_) => InstructionType::Meter,
+        }
+    }
+}
+
+impl Into<InstructionHeader> for InstructionPayload {
+    fn into(self) -> InstructionHeader {
+        let ttype = self.ttype();
+        let payload_len = self.encoded_len() as u16;
+        InstructionHeader {
+            ttype: ttype,
+            len: INSTRUCTION_HEADER_LEN + payload_len,
+            payload: self,
         }
     }
 }
@@ -143,7 +207,7 @@ impl Into<Vec<u8>> for InstructionPayload {
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadGotoTable {
     /// Set next table in the lookup pipeline
-    table_id: u8,
+    pub table_id: u8,
     // Pad 3 bytes
 }
 
@@ -152,15 +216,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadGotoTable {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadGotoTable {
-            table_id: cursor.read_u8().chain_err(|| {
-                let err_msg = format!(
-                    "Could not read PayloadGotoTable table_id!{}Cursor: {:?}",
-                    path::MAIN_SEPARATOR,
-                    cursor
-                );
-                error!("{}", err_msg);
-                err_msg
-            })?,
+            table_id: cursor.read_u8().at(cursor.position())?,
         })
         // pad 3 bytes by ignoring them
     }
@@ -179,34 +235,18 @@ impl Into<Vec<u8>> for PayloadGotoTable {
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadWriteMetaData {
     // pad 4 bytes
-    metadata: u64,
-    metadata_mask: u64,
+    pub metadata: u64,
+    pub metadata_mask: u64,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadWriteMetaData {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        cursor.read_u32::<BigEndian>().unwrap(); //4 bytes padding
+        cursor.read_u32::<BigEndian>()?; //4 bytes padding
         Ok(PayloadWriteMetaData {
-            metadata: cursor.read_u64::<BigEndian>().chain_err(|| {
-                let err_msg = format!(
-                    "Could not read PayloadWriteMetaData metadata!{}Cursor: {:?}",
-                    path::MAIN_SEPARATOR,
-                    cursor
-                );
-                error!("{}", err_msg);
-                err_msg
-            })?,
-            metadata_mask: cursor.read_u64::<BigEndian>().chain_err(|| {
-                let err_msg = format!(
-                    "Could not read PayloadWriteMetaData metadata_mask!{}Cursor: {:?}",
-                    path::MAIN_SEPARATOR,
-                    cursor
-                );
-                error!("{}", err_msg);
-                err_msg
-            })?,
+            metadata: cursor.read_u64::<BigEndian>().at(cursor.position())?,
+            metadata_mask: cursor.read_u64::<BigEndian>().at(cursor.position())?,
         })
     }
 }
@@ -224,71 +264,67 @@ impl Into<Vec<u8>> for PayloadWriteMetaData {
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadWriteActions {
     // pad 4 bytes
-    actions: Vec<actions::ActionHeader>,
+    pub actions: Vec<actions::ActionHeader>,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadWriteActions {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
-        let mut cursor = Cursor::new(bytes);
-        let mut actions = Vec::new();
-        let mut bytes_remaining = bytes.len();
-        while bytes_remaining > 0 {
-            let action_len = actions::ActionHeader::read_len(&mut cursor)?;
-            let action_slice =
-                &bytes[cursor.position() as usize..cursor.position() as usize + action_len];
-            let action = actions::ActionHeader::try_from(action_slice)?;
-            cursor.seek(SeekFrom::Current(action_len as i64)).unwrap();
-            bytes_remaining -= action_len;
-            actions.push(action);
-        }
-        Ok(PayloadWriteActions { actions: actions })
+        // pad 4 bytes
+        let actions_slice = checked_tail(bytes, 4, stringify!(PayloadWriteActions))?;
+        Ok(PayloadWriteActions {
+            actions: actions::actions_from_slice(actions_slice).within(4)?,
+        })
+    }
+}
+
+impl OfEncode for PayloadWriteActions {
+    fn encoded_len(&self) -> usize {
+        4 + self.actions.encoded_len()
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.write_u32::<BigEndian>(0).unwrap(); // pad 4 bytes
+        self.actions.encode_into(buf);
     }
 }
 
 impl Into<Vec<u8>> for PayloadWriteActions {
     fn into(self) -> Vec<u8> {
-        let mut res = Vec::new();
-        res.write_u32::<BigEndian>(0).unwrap(); // pad 4 bytes
-        for action in self.actions {
-            res.extend_from_slice(&Into::<Vec<u8>>::into(action)[..]);
-        }
-        res
+        self.to_bytes()
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadApplyActions {
     // pad 4 bytes
-    actions: Vec<actions::ActionHeader>,
+    pub actions: Vec<actions::ActionHeader>,
 }
 impl<'a> TryFrom<&'a [u8]> for PayloadApplyActions {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
-        let mut cursor = Cursor::new(bytes);
-        let mut actions = Vec::new();
-        let mut bytes_remaining = bytes.len();
-        while bytes_remaining > 0 {
-            let action_len = actions::ActionHeader::read_len(&mut cursor)?;
-            let action_slice =
-                &bytes[cursor.position() as usize..cursor.position() as usize + action_len];
-            let action = actions::ActionHeader::try_from(action_slice)?;
-            cursor.seek(SeekFrom::Current(action_len as i64)).unwrap();
-            bytes_remaining -= action_len;
-            actions.push(action);
-        }
-        Ok(PayloadApplyActions { actions: actions })
+        // pad 4 bytes
+        let actions_slice = checked_tail(bytes, 4, stringify!(PayloadApplyActions))?;
+        Ok(PayloadApplyActions {
+            actions: actions::actions_from_slice(actions_slice).within(4)?,
+        })
+    }
+}
+
+impl OfEncode for PayloadApplyActions {
+    fn encoded_len(&self) -> usize {
+        4 + self.actions.encoded_len()
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.write_u32::<BigEndian>(0).unwrap(); // pad 4 bytes
+        self.actions.encode_into(buf);
     }
 }
 
 impl Into<Vec<u8>> for PayloadApplyActions {
     fn into(self) -> Vec<u8> {
-        let mut res = Vec::new();
-        res.write_u32::<BigEndian>(0).unwrap(); // pad 4 bytes
-        for action in self.actions {
-            res.extend_from_slice(&Into::<Vec<u8>>::into(action)[..]);
-        }
-        res
+        self.to_bytes()
     }
 }
 
@@ -314,23 +350,16 @@ impl Into<Vec<u8>> for PayloadClearActions {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadMeter {
-    meter_id: u32,
+    pub meter_id: MeterId,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadMeter {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
+        let meter_id = cursor.read_u32::<BigEndian>().at(cursor.position())?;
         Ok(PayloadMeter {
-            meter_id: cursor.read_u32::<BigEndian>().chain_err(|| {
-                let err_msg = format!(
-                    "Could not read PayloadMeter meter_id!{}Cursor: {:?}",
-                    path::MAIN_SEPARATOR,
-                    cursor
-                );
-                error!("{}", err_msg);
-                err_msg
-            })?,
+            meter_id: MeterId::try_from(meter_id).at(0)?,
         })
     }
 }
@@ -338,7 +367,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadMeter {
 impl Into<Vec<u8>> for PayloadMeter {
     fn into(self) -> Vec<u8> {
         let mut res = Vec::new();
-        res.write_u32::<BigEndian>(self.meter_id).unwrap(); // pad 4 bytes
+        res.write_u32::<BigEndian>(self.meter_id.into()).unwrap();
         res
     }
 }
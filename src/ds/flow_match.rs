@@ -1,21 +1,37 @@
 use super::super::err::*;
-use super::hw_addr;
+use super::checked_slice;
+use super::codec::OfEncode;
+use super::addr::{Ipv4Addr, Ipv6Addr, MacAddr};
+#[cfg(feature = "nicira")]
+use super::nx;
 use super::ports::PortNumber;
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use super::tlv::{LengthField, TlvCursor};
+use super::Version;
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
 use num_traits::{FromPrimitive, ToPrimitive};
 use std::convert::{Into, TryFrom};
-use std::io::{Cursor, Seek, SeekFrom};
-use std::path;
+use std::io::Cursor;
+use std::sync::{Arc, RwLock};
 
 /// Length of Math is 8 bytes.
 pub const MATCH_LENGTH: usize = 8;
+/// Length of the type and length fields at the start of a match (4 bytes).
+pub const MATCH_HEADER_LENGTH: usize = 4;
+/// Length of an oxm tlv header (4 bytes).
+pub const OXM_TLV_HEADER_LENGTH: usize = 4;
+/// Matches are padded to a multiple of 8 bytes, the length excludes the padding.
+pub const MATCH_LEN_FIELD: LengthField =
+    LengthField::u16_at(2, MATCH_HEADER_LENGTH).padded_to(8);
+/// The last byte of an oxm tlv header is the length of the payload.
+pub const OXM_LEN_FIELD: LengthField = LengthField::u8_at(3, OXM_TLV_HEADER_LENGTH);
 
 /// Fields to match against flows
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Getters, Debug, PartialEq, Clone)]
 pub struct Match {
     ttype: MatchType,
     length: u16, //excluding last padding bytes
     //pad 4 bytes
+    #[get = "pub"]
     matches: Vec<TlvMatch>,
     /* Followed by:
      * - Exactly (length - 4) (possibly 0) bytes containing OXM TLVs, then
@@ -27,26 +43,140 @@ pub struct Match {
 }
 
 impl Match {
-    pub fn read_len(cursor: &mut Cursor<&[u8]>) -> Result<usize> {
-        // go to len position in the raw bytes
-        cursor.seek(SeekFrom::Current(2)).unwrap();
-        // read value and handle errors
-        let len = match cursor.read_u16::<BigEndian>() {
-            Ok(len) => len,
-            Err(err) => {
-                error!(
-                    "Could not read packet queue len.{}{:?}{}{}",
-                    path::MAIN_SEPARATOR,
-                    cursor,
-                    path::MAIN_SEPARATOR,
-                    err
-                );
-                bail!(ErrorKind::CouldNotReadLength(2, stringify!(Match),))
-            }
+    /// creates an OXM match of the given fields
+    /// an empty vec matches everything
+    pub fn new(matches: Vec<TlvMatch>) -> Self {
+        Match {
+            ttype: MatchType::Oxm,
+            length: (MATCH_HEADER_LENGTH + matches.encoded_len()) as u16,
+            matches: matches,
+        }
+    }
+
+    /// the first field of the given payload type, eg. `mmatch.get::<PayloadIpProto>()`
+    /// the field may be masked, see `TlvMatch::mask`
+    pub fn get<F: MatchField>(&self) -> Option<&F> {
+        self.matches
+            .iter()
+            .filter_map(|mmatch| F::from_payload(&mmatch.payload))
+            .next()
+    }
+
+    /// ingress port, always present in packet ins
+    pub fn in_port(&self) -> Option<PortNumber> {
+        self.get::<PayloadInPort>()
+            .map(|field| field.ingress_port.clone())
+    }
+
+    /// metadata the packet had when it was sent to the controller
+    pub fn metadata(&self) -> Option<u64> {
+        self.get::<PayloadMetadata>().map(|field| field.metadata)
+    }
+
+    pub fn eth_src(&self) -> Option<MacAddr> {
+        self.get::<PayloadEthSrc>().map(|field| field.eth_src)
+    }
+
+    pub fn eth_dst(&self) -> Option<MacAddr> {
+        self.get::<PayloadEthDst>().map(|field| field.eth_dst)
+    }
+
+    /// tunnel id of packets received on a logical (tunnel) port
+    pub fn tunnel_id(&self) -> Option<u64> {
+        self.get::<PayloadTunnelId>().map(|field| field.metadata)
+    }
+
+    /// oldest version the fields can be sent with, 1.3 unless a field was added later
+    pub fn min_version(&self) -> Version {
+        let packet_type = self.matches.iter().any(|mmatch| match mmatch.payload {
+            MatchPayload::PacketType(_) => true,
+            _ => false,
+        });
+        if packet_type {
+            Version::V1_5
+        } else {
+            Version::V1_3
+        }
+    }
+
+    /// the same match with its fields sorted by class, field and value and
+    /// the prerequisites other fields imply (eg. eth_type=0x0800 of ipv4_src)
+    /// added, switches may report the match of a flow in this form
+    /// normalized matches are equal if they match the same packets
+    pub fn normalized(&self) -> Match {
+        let field_of = |tlv_match: &TlvMatch| {
+            (
+                tlv_match.tlv_header.get_oxm_class(),
+                tlv_match.tlv_header.get_oxm_field(),
+            )
         };
-        // go back to start
-        cursor.seek(SeekFrom::Current(-4)).unwrap();
-        Ok((len + ((len + 7) / 8 * 8 - len)) as usize) // see above for this formula
+        let mut fields = self.matches.clone();
+        for field in &self.matches {
+            for implied in implied_prerequisites(&field.payload) {
+                let implied = TlvMatch::new(implied);
+                if !fields
+                    .iter()
+                    .any(|field| field_of(field) == field_of(&implied))
+                {
+                    fields.push(implied);
+                }
+            }
+        }
+        let mut encoded: Vec<(Vec<u8>, TlvMatch)> = fields
+            .into_iter()
+            .map(|field| (field.to_bytes(), field))
+            .collect();
+        encoded.sort_by(|a, b| a.0.cmp(&b.0));
+        encoded.dedup_by(|a, b| a.0 == b.0);
+        Match::new(encoded.into_iter().map(|(_, field)| field).collect())
+    }
+
+    /// length of the match including the padding to a multiple of 8 bytes
+    pub fn read_len(cursor: &mut Cursor<&[u8]>) -> Result<usize> {
+        let pos = cursor.position() as usize;
+        MATCH_LEN_FIELD.read(cursor.get_ref(), pos, stringify!(Match))
+    }
+}
+
+/// the prerequisites a field can only have one value of
+fn implied_prerequisites(payload: &MatchPayload) -> Vec<MatchPayload> {
+    let eth_type = |ttype: EtherType| MatchPayload::EthType(PayloadEthType { ttype: ttype });
+    let ip_proto = |ip_proto: IpProto| MatchPayload::IpProto(PayloadIpProto { ip_proto: ip_proto });
+    let icmpv6_type =
+        |ttype: IcmpV6Type| MatchPayload::IcmpV6Type(PayloadIcmpV6Type { ttype: ttype });
+    match *payload {
+        MatchPayload::IPv4Src(_) | MatchPayload::IPv4Dst(_) => vec![eth_type(EtherType::IPv4)],
+        MatchPayload::TcpSrc(_) | MatchPayload::TcpDst(_) => vec![ip_proto(IpProto::Tcp)],
+        MatchPayload::UdpSrc(_) | MatchPayload::UdpDst(_) => vec![ip_proto(IpProto::Udp)],
+        MatchPayload::SctpSrc(_) | MatchPayload::SctpDst(_) => vec![ip_proto(IpProto::Sctp)],
+        MatchPayload::IcmpV4TYype(_) | MatchPayload::IcmpV4Code(_) => {
+            vec![eth_type(EtherType::IPv4), ip_proto(IpProto::Icmp)]
+        }
+        MatchPayload::ArpOp(_)
+        | MatchPayload::ArpSpa(_)
+        | MatchPayload::ArpTpa(_)
+        | MatchPayload::ArpSha(_)
+        | MatchPayload::ArpTha(_) => vec![eth_type(EtherType::Arp)],
+        MatchPayload::IPv6Src(_)
+        | MatchPayload::IPv6Dst(_)
+        | MatchPayload::IPv6FLabel(_)
+        | MatchPayload::IPv6ExtHdr(_) => vec![eth_type(EtherType::IPv6)],
+        MatchPayload::IcmpV6Type(_)
+        | MatchPayload::IcmpV6Code(_)
+        | MatchPayload::IPv6NdTarget(_) => {
+            vec![eth_type(EtherType::IPv6), ip_proto(IpProto::IPv6Icmp)]
+        }
+        MatchPayload::IPv6NdSll(_) => vec![
+            eth_type(EtherType::IPv6),
+            ip_proto(IpProto::IPv6Icmp),
+            icmpv6_type(IcmpV6Type::NeighborSolicitation),
+        ],
+        MatchPayload::IPv6NdTll(_) => vec![
+            eth_type(EtherType::IPv6),
+            ip_proto(IpProto::IPv6Icmp),
+            icmpv6_type(IcmpV6Type::NeighborAdvertisement),
+        ],
+        _ => Vec::new(),
     }
 }
 
@@ -57,36 +187,36 @@ impl<'a> TryFrom<&'a [u8]> for Match {
         let mut matches = Vec::new();
 
         // read raw version val
-        let ttype_raw = cursor.read_u16::<BigEndian>().unwrap();
+        let ttype_raw = cursor.read_u16::<BigEndian>()?;
         // try to decode it
         let ttype = MatchType::from_u16(ttype_raw).ok_or::<Error>(
             ErrorKind::UnknownValue(ttype_raw as u64, stringify!(MatchType)).into(),
         )?;
-        if ttype != MatchType::OXM {
+        if ttype != MatchType::Oxm {
             bail!(ErrorKind::UnsupportedValue(
                 ttype_raw as u64,
                 stringify!(MatchType)
             ));
         }
 
-        let length = cursor.read_u16::<BigEndian>().unwrap();
-
-        let mut bytes_remaining = length as usize - MATCH_LENGTH;
-        while bytes_remaining > 0 {
-            let tlv_header_raw = cursor.read_u32::<BigEndian>().unwrap();
-            let tlv_header = OxmTlvHeader(tlv_header_raw);
-            let tlv_slice = &bytes[cursor.position() as usize
-                                       ..cursor.position() as usize
-                                           + tlv_header.get_length() as usize];
-
-            let tlv_match = TlvMatch::try_from(tlv_header, &tlv_slice[..])?;
-            // ad to vector
+        let length = cursor.read_u16::<BigEndian>()?;
+        if (length as usize) < MATCH_HEADER_LENGTH {
+            bail!(ErrorKind::IllegalValue(length as u64, stringify!(Match)));
+        }
 
-            // count down by bytes read
-            cursor
-                .seek(SeekFrom::Current(tlv_match.tlv_header.get_length() as i64))
-                .unwrap();
-            bytes_remaining -= tlv_match.tlv_header.get_length() as usize;
+        // the tlvs end at length, the rest is padding
+        let tlv_bytes = checked_slice(bytes, 0, length as usize, stringify!(Match))?;
+        let entries = TlvCursor::new(
+            tlv_bytes,
+            MATCH_HEADER_LENGTH,
+            OXM_LEN_FIELD,
+            stringify!(TlvMatch),
+        );
+        for entry in entries {
+            let (pos, entry) = entry?;
+            let tlv_header = OxmTlvHeader(BigEndian::read_u32(entry));
+            let tlv_match = TlvMatch::try_from(tlv_header, &entry[OXM_TLV_HEADER_LENGTH..])
+                .within(pos + OXM_TLV_HEADER_LENGTH)?;
             matches.push(tlv_match);
         }
 
@@ -98,20 +228,31 @@ impl<'a> TryFrom<&'a [u8]> for Match {
     }
 }
 
+/// length of a match padded to a multiple of 8 bytes
+fn pad_len(length: usize) -> usize {
+    length.div_ceil(8) * 8
+}
+
+impl OfEncode for Match {
+    fn encoded_len(&self) -> usize {
+        pad_len(MATCH_HEADER_LENGTH + self.matches.encoded_len())
+    }
+
+    /// the length is computed from the fields, the stored one is ignored
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        let length = MATCH_HEADER_LENGTH + self.matches.encoded_len();
+        buf.write_u16::<BigEndian>(self.ttype.to_u16().unwrap())
+            .unwrap();
+        buf.write_u16::<BigEndian>(length as u16).unwrap();
+        self.matches.encode_into(buf);
+        let len = buf.len();
+        buf.resize(len + pad_len(length) - length, 0);
+    }
+}
+
 impl Into<Vec<u8>> for Match {
     fn into(self) -> Vec<u8> {
-        let mut res = Vec::new();
-        res.write_u16::<BigEndian>(self.ttype.to_u16().unwrap())
-            .unwrap();
-        res.write_u16::<BigEndian>(self.length).unwrap();
-        for mmatch in self.matches {
-            res.extend_from_slice(&Into::<Vec<u8>>::into(mmatch)[..]);
-        }
-        let pad_bytes_count = (self.length + 7) / 8 * 8 - self.length;
-        for _ in 0..pad_bytes_count {
-            res.write_u8(0).unwrap();
-        }
-        res
+        self.to_bytes()
     }
 }
 /// The match type indicates the match structure (set of fields that compose the
@@ -125,26 +266,111 @@ enum MatchType {
     /// Deprecated.
     Standard = 0,
     /// OpenFlow Extensible Match
-    OXM = 1,
+    Oxm = 1,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Getters, Debug, PartialEq, Clone)]
 pub struct TlvMatch {
     pub tlv_header: OxmTlvHeader,
+    #[get = "pub"]
     payload: MatchPayload,
+    /// raw mask bytes (same length as the payload) if the field is masked
+    #[get = "pub"]
+    mask: Option<Vec<u8>>,
 }
 
 impl TlvMatch {
+    /// creates an unmasked match of the OpenFlow basic class,
+    /// the experimenter class for experimenter payloads or an NXM class for NXM payloads
+    pub fn new(payload: MatchPayload) -> Self {
+        let payload_len = payload.encoded_len();
+        let (oxm_class, oxm_field) = payload.oxm_field();
+        let mut tlv_header = OxmTlvHeader(0);
+        tlv_header.set_oxm_class(oxm_class);
+        tlv_header.set_oxm_field(oxm_field);
+        tlv_header.set_hasmask(0);
+        tlv_header.set_length(payload_len as u32);
+        TlvMatch {
+            tlv_header: tlv_header,
+            payload: payload,
+            mask: None,
+        }
+    }
+
+    /// creates a masked match of the OpenFlow basic class
+    /// the mask has to have the same length as the encoded payload
+    /// (for experimenter payloads the length of the value without the experimenter id)
+    pub fn with_mask(payload: MatchPayload, mask: Vec<u8>) -> Result<Self> {
+        let mut tlv_match = TlvMatch::new(payload);
+        let payload_len = tlv_match.tlv_header.get_length() as usize;
+        let value_len = match tlv_match.payload {
+            MatchPayload::Experimenter(ref payload) => payload.data.len(),
+            _ => payload_len,
+        };
+        if mask.len() != value_len {
+            bail!(ErrorKind::InvalidSliceLength(
+                value_len,
+                mask.len(),
+                stringify!(TlvMatch),
+            ));
+        }
+        tlv_match.tlv_header.set_hasmask(1);
+        tlv_match
+            .tlv_header
+            .set_length((payload_len + mask.len()) as u32);
+        tlv_match.mask = Some(mask);
+        Ok(tlv_match)
+    }
+
     pub fn try_from(tlv_header: OxmTlvHeader, match_slice: &[u8]) -> Result<TlvMatch> {
-        // only support open flow basic oxm class
+        // only support open flow basic and experimenter oxm classes,
+        // NXM classes with the nicira feature
 
         //check if class is supported
-        match OxmClass::from_u32(tlv_header.get_oxm_class()) {
-            Some(_) => (),
+        let oxm_class = match OxmClass::from_u32(tlv_header.get_oxm_class()) {
+            Some(oxm_class) => oxm_class,
             None => bail!(ErrorKind::UnknownValue(
                 tlv_header.get_oxm_class() as u64,
                 stringify!(OxmClass)
             )),
+        };
+
+        // experimenter fields start with the experimenter id, the mask only covers the value
+        let (match_slice, experimenter) = if oxm_class == OxmClass::XmcExperimenter {
+            let experimenter = Cursor::new(match_slice).read_u32::<BigEndian>()?;
+            (&match_slice[4..], Some(experimenter))
+        } else {
+            (match_slice, None)
+        };
+
+        // masked fields contain the value followed by a mask of the same length
+        let (match_slice, mask) = if tlv_header.get_hasmask() == 1 {
+            let half = match_slice.len() / 2;
+            (&match_slice[..half], Some(Vec::from(&match_slice[half..])))
+        } else {
+            (match_slice, None)
+        };
+
+        if oxm_class == OxmClass::XmcNxm0 || oxm_class == OxmClass::XmcNxm1 {
+            return Ok(TlvMatch {
+                payload: nxm_payload(&tlv_header, match_slice)?,
+                tlv_header: tlv_header,
+                mask: mask,
+            });
+        }
+
+        if let Some(experimenter) = experimenter {
+            let payload = ExperimenterMatch {
+                experimenter: experimenter,
+                field: tlv_header.get_oxm_field() as u8,
+                data: Vec::from(match_slice),
+            };
+            payload.validate(mask.as_ref().map(|mask| &mask[..]))?;
+            return Ok(TlvMatch {
+                tlv_header: tlv_header,
+                payload: MatchPayload::Experimenter(payload),
+                mask: mask,
+            });
         }
 
         // read match
@@ -245,23 +471,44 @@ impl TlvMatch {
             OfbMatchFields::IPv6ExtHdr => {
                 MatchPayload::IPv6ExtHdr(PayloadIPv6ExtHdr::try_from(match_slice)?)
             }
+            OfbMatchFields::PacketType => {
+                MatchPayload::PacketType(PayloadPacketType::try_from(match_slice)?)
+            }
         };
 
         // create match
         let tlv_match = TlvMatch {
             tlv_header: tlv_header,
             payload: payload,
+            mask: mask,
         };
         Ok(tlv_match)
     }
 }
 
+impl OfEncode for TlvMatch {
+    fn encoded_len(&self) -> usize {
+        let mask_len = self.mask.as_ref().map_or(0, |mask| mask.len());
+        OXM_TLV_HEADER_LENGTH + self.payload.encoded_len() + mask_len
+    }
+
+    /// the length in the header is computed from the payload and mask
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        let mut tlv_header = self.tlv_header.clone();
+        let mask_len = self.mask.as_ref().map_or(0, |mask| mask.len());
+        tlv_header.set_length((self.payload.encoded_len() + mask_len) as u32);
+        tlv_header.set_hasmask(self.mask.is_some() as u32);
+        buf.write_u32::<BigEndian>(tlv_header.0).unwrap();
+        self.payload.encode_into(buf);
+        if let Some(ref mask) = self.mask {
+            buf.extend_from_slice(&mask[..]);
+        }
+    }
+}
+
 impl Into<Vec<u8>> for TlvMatch {
     fn into(self) -> Vec<u8> {
-        let mut res = Vec::new();
-        res.write_u32::<BigEndian>(self.tlv_header.0).unwrap();
-        res.extend_from_slice(&Into::<Vec<u8>>::into(self.payload));
-        res
+        self.to_bytes()
     }
 }
 
@@ -278,7 +525,7 @@ bitfield!{
 
 impl Clone for OxmTlvHeader {
     fn clone(&self) -> Self {
-        OxmTlvHeader(self.0.clone())
+        OxmTlvHeader(self.0)
     }
 }
 
@@ -386,6 +633,8 @@ enum OfbMatchFields {
     TunnelId = 38,
     /// IPv6 Extension Header pseudo-field
     IPv6ExtHdr = 39,
+    /// Packet type (since 1.5).
+    PacketType = 44,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -470,6 +719,164 @@ pub enum MatchPayload {
     TunnelId(PayloadTunnelId),
     /// IPv6 Extension Header pseudo-field
     IPv6ExtHdr(PayloadIPv6ExtHdr),
+    /// Packet type (since 1.5).
+    PacketType(PayloadPacketType),
+    /// Field of an experimenter oxm class.
+    Experimenter(ExperimenterMatch),
+    /// Field of the NXM classes used by Open vSwitch.
+    #[cfg(feature = "nicira")]
+    Nxm(nx::NxmField),
+}
+
+impl MatchPayload {
+    /// oxm class and field of the payload
+    fn oxm_field(&self) -> (u32, u32) {
+        let field = match self {
+            MatchPayload::Experimenter(payload) => {
+                return (
+                    OxmClass::XmcExperimenter.to_u32().unwrap(),
+                    u32::from(payload.field),
+                )
+            }
+            #[cfg(feature = "nicira")]
+            MatchPayload::Nxm(field) => return (u32::from(field.class()), u32::from(field.field())),
+            MatchPayload::InPort(_) => OfbMatchFields::InPort,
+            MatchPayload::InPhyPort(_) => OfbMatchFields::InPhyPort,
+            MatchPayload::Metadata(_) => OfbMatchFields::Metadata,
+            MatchPayload::EthDst(_) => OfbMatchFields::EthDst,
+            MatchPayload::EthSrc(_) => OfbMatchFields::EthSrc,
+            MatchPayload::EthType(_) => OfbMatchFields::EthType,
+            MatchPayload::VlanVId(_) => OfbMatchFields::VlanVid,
+            MatchPayload::VlanPcp(_) => OfbMatchFields::VlanPcp,
+            MatchPayload::IpDscp(_) => OfbMatchFields::IpDscp,
+            MatchPayload::IpEcn(_) => OfbMatchFields::IpEcn,
+            MatchPayload::IpProto(_) => OfbMatchFields::IpProto,
+            MatchPayload::IPv4Src(_) => OfbMatchFields::IPv4Src,
+            MatchPayload::IPv4Dst(_) => OfbMatchFields::IPv4Dst,
+            MatchPayload::TcpSrc(_) => OfbMatchFields::TcpSrc,
+            MatchPayload::TcpDst(_) => OfbMatchFields::TcpDst,
+            MatchPayload::UdpSrc(_) => OfbMatchFields::UdpSrc,
+            MatchPayload::UdpDst(_) => OfbMatchFields::UdpDst,
+            MatchPayload::SctpSrc(_) => OfbMatchFields::SctpSrc,
+            MatchPayload::SctpDst(_) => OfbMatchFields::SctpDst,
+            MatchPayload::IcmpV4TYype(_) => OfbMatchFields::IcmpV4TYype,
+            MatchPayload::IcmpV4Code(_) => OfbMatchFields::IcmpV4Code,
+            MatchPayload::ArpOp(_) => OfbMatchFields::ArpOp,
+            MatchPayload::ArpSpa(_) => OfbMatchFields::ArpSpa,
+            MatchPayload::ArpTpa(_) => OfbMatchFields::ArpTpa,
+            MatchPayload::ArpSha(_) => OfbMatchFields::ArpSha,
+            MatchPayload::ArpTha(_) => OfbMatchFields::ArpTha,
+            MatchPayload::IPv6Src(_) => OfbMatchFields::IPv6Src,
+            MatchPayload::IPv6Dst(_) => OfbMatchFields::IPv6Dst,
+            MatchPayload::IPv6FLabel(_) => OfbMatchFields::IPv6FLabel,
+            MatchPayload::IcmpV6Type(_) => OfbMatchFields::IcmpV6Type,
+            MatchPayload::IcmpV6Code(_) => OfbMatchFields::IcmpV6Code,
+            MatchPayload::IPv6NdTarget(_) => OfbMatchFields::IPv6NdTarget,
+            MatchPayload::IPv6NdSll(_) => OfbMatchFields::IPv6NdSll,
+            MatchPayload::IPv6NdTll(_) => OfbMatchFields::IPv6NdTll,
+            MatchPayload::MplsLabel(_) => OfbMatchFields::MplsLabel,
+            MatchPayload::MplsTc(_) => OfbMatchFields::MplsTc,
+            MatchPayload::MplsBos(_) => OfbMatchFields::MplsBos,
+            MatchPayload::PbbISid(_) => OfbMatchFields::PbbISid,
+            MatchPayload::TunnelId(_) => OfbMatchFields::TunnelId,
+            MatchPayload::IPv6ExtHdr(_) => OfbMatchFields::IPv6ExtHdr,
+            MatchPayload::PacketType(_) => OfbMatchFields::PacketType,
+        };
+        (
+            OxmClass::XmcOpenFlowBasic.to_u32().unwrap(),
+            field.to_u32().unwrap(),
+        )
+    }
+}
+
+/// Payload type of a single match field, lets `Match::get` look fields up by their type.
+pub trait MatchField {
+    /// the field if the payload is of this type
+    fn from_payload(payload: &MatchPayload) -> Option<&Self>;
+}
+
+macro_rules! match_field {
+    ($($variant:ident => $payload:ty,)*) => {
+        $(
+            impl MatchField for $payload {
+                fn from_payload(payload: &MatchPayload) -> Option<&Self> {
+                    match *payload {
+                        MatchPayload::$variant(ref field) => Some(field),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+match_field! {
+    InPort => PayloadInPort,
+    InPhyPort => PayloadInPhyPort,
+    Metadata => PayloadMetadata,
+    EthDst => PayloadEthDst,
+    EthSrc => PayloadEthSrc,
+    EthType => PayloadEthType,
+    VlanVId => PayloadVlanVId,
+    VlanPcp => PayloadVlanPcp,
+    IpDscp => PayloadIpDscp,
+    IpEcn => PayloadIpEcn,
+    IpProto => PayloadIpProto,
+    IPv4Src => PayloadIPv4Src,
+    IPv4Dst => PayloadIPv4Dst,
+    TcpSrc => PayloadTcpSrc,
+    TcpDst => PayloadTcpDst,
+    UdpSrc => PayloadUdpSrc,
+    UdpDst => PayloadUdpDst,
+    SctpSrc => PayloadSctpSrc,
+    SctpDst => PayloadSctpDst,
+    IcmpV4TYype => PayloadIcmpV4Type,
+    IcmpV4Code => PayloadIcmpV4Code,
+    ArpOp => PayloadArpOp,
+    ArpSpa => PayloadArpSpa,
+    ArpTpa => PayloadArpTpa,
+    ArpSha => PayloadArpSha,
+    ArpTha => PayloadArpTha,
+    IPv6Src => PayloadIPv6Src,
+    IPv6Dst => PayloadIPv6Dst,
+    IPv6FLabel => PayloadIPv6FLabel,
+    IcmpV6Type => PayloadIcmpV6Type,
+    IcmpV6Code => PayloadIcmpV6Code,
+    IPv6NdTarget => PayloadIPv6NdTarget,
+    IPv6NdSll => PayloadIPv6NdSll,
+    IPv6NdTll => PayloadIPv6NdTll,
+    MplsLabel => PayloadMplsLabel,
+    MplsTc => PayloadMplsTc,
+    MplsBos => PayloadMplsBos,
+    PbbISid => PayloadPbbISid,
+    TunnelId => PayloadTunnelId,
+    IPv6ExtHdr => PayloadIPv6ExtHdr,
+    PacketType => PayloadPacketType,
+    Experimenter => ExperimenterMatch,
+}
+
+#[cfg(feature = "nicira")]
+match_field! {
+    Nxm => nx::NxmField,
+}
+
+/// decodes a field of the NXM classes
+#[cfg(feature = "nicira")]
+fn nxm_payload(tlv_header: &OxmTlvHeader, match_slice: &[u8]) -> Result<MatchPayload> {
+    Ok(MatchPayload::Nxm(nx::NxmField::decode(
+        tlv_header.get_oxm_class() as u16,
+        tlv_header.get_oxm_field() as u8,
+        match_slice,
+    )?))
+}
+
+/// NXM fields are only decoded with the nicira feature
+#[cfg(not(feature = "nicira"))]
+fn nxm_payload(tlv_header: &OxmTlvHeader, _match_slice: &[u8]) -> Result<MatchPayload> {
+    bail!(ErrorKind::UnsupportedValue(
+        tlv_header.get_oxm_class() as u64,
+        stringify!(OxmClass)
+    ))
 }
 
 impl Into<Vec<u8>> for MatchPayload {
@@ -515,13 +922,17 @@ impl Into<Vec<u8>> for MatchPayload {
             MatchPayload::PbbISid(payload) => payload.into(),
             MatchPayload::TunnelId(payload) => payload.into(),
             MatchPayload::IPv6ExtHdr(payload) => payload.into(),
+            MatchPayload::PacketType(payload) => payload.into(),
+            MatchPayload::Experimenter(payload) => payload.into(),
+            #[cfg(feature = "nicira")]
+            MatchPayload::Nxm(field) => field.into(),
         }
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadInPort {
-    ingress_port: PortNumber,
+    pub ingress_port: PortNumber,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadInPort {
@@ -529,7 +940,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadInPort {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadInPort {
-            ingress_port: PortNumber::try_from(cursor.read_u32::<BigEndian>().unwrap())?,
+            ingress_port: PortNumber::try_from(cursor.read_u32::<BigEndian>()?)?,
         })
     }
 }
@@ -545,7 +956,7 @@ impl Into<Vec<u8>> for PayloadInPort {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadInPhyPort {
-    phy_port: u32,
+    pub phy_port: u32,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadInPhyPort {
@@ -553,7 +964,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadInPhyPort {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadInPhyPort {
-            phy_port: cursor.read_u32::<BigEndian>().unwrap(),
+            phy_port: cursor.read_u32::<BigEndian>()?,
         })
     }
 }
@@ -568,7 +979,7 @@ impl Into<Vec<u8>> for PayloadInPhyPort {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadMetadata {
-    metadata: u64,
+    pub metadata: u64,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadMetadata {
@@ -576,7 +987,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadMetadata {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadMetadata {
-            metadata: cursor.read_u64::<BigEndian>().unwrap(),
+            metadata: cursor.read_u64::<BigEndian>()?,
         })
     }
 }
@@ -591,14 +1002,14 @@ impl Into<Vec<u8>> for PayloadMetadata {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadEthDst {
-    eth_dst: hw_addr::EthernetAddress,
+    pub eth_dst: MacAddr,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadEthDst {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         Ok(PayloadEthDst {
-            eth_dst: hw_addr::from_slice_eth(bytes)?,
+            eth_dst: MacAddr::from_slice(bytes)?,
         })
     }
 }
@@ -606,21 +1017,21 @@ impl<'a> TryFrom<&'a [u8]> for PayloadEthDst {
 impl Into<Vec<u8>> for PayloadEthDst {
     fn into(self) -> Vec<u8> {
         let mut res = Vec::new();
-        res.extend_from_slice(&self.eth_dst[..]);
+        res.extend_from_slice(&self.eth_dst.octets()[..]);
         res
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadEthSrc {
-    eth_src: hw_addr::EthernetAddress,
+    pub eth_src: MacAddr,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadEthSrc {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         Ok(PayloadEthSrc {
-            eth_src: hw_addr::from_slice_eth(bytes)?,
+            eth_src: MacAddr::from_slice(bytes)?,
         })
     }
 }
@@ -628,21 +1039,21 @@ impl<'a> TryFrom<&'a [u8]> for PayloadEthSrc {
 impl Into<Vec<u8>> for PayloadEthSrc {
     fn into(self) -> Vec<u8> {
         let mut res = Vec::new();
-        res.extend_from_slice(&self.eth_src[..]);
+        res.extend_from_slice(&self.eth_src.octets()[..]);
         res
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadEthType {
-    ttype: EtherType,
+    pub ttype: EtherType,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadEthType {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let raw_ttype = cursor.read_u16::<BigEndian>().unwrap();
+        let raw_ttype = cursor.read_u16::<BigEndian>()?;
         Ok(PayloadEthType {
             ttype: EtherType::from_u16(raw_ttype).ok_or::<Error>(
                 ErrorKind::UnknownValue(raw_ttype as u64, stringify!(EtherType)).into(),
@@ -716,9 +1127,82 @@ pub enum EtherType {
     VlanTaggedWithDoubleTagging = 0x9100,
 }
 
+/// Bit set in vlan vids of tagged packets (OFPVID_PRESENT).
+pub const VID_PRESENT: u16 = 0x1000;
+/// Vlan vid of untagged packets (OFPVID_NONE).
+pub const VID_NONE: u16 = 0x0000;
+/// Largest vid that fits into the 12 bits of a vlan tag.
+pub const VID_MAX: u16 = 0x0fff;
+
+/// Vlan vid match with the present bit handled.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VlanVid {
+    /// Packets without vlan tag.
+    None,
+    /// Packets with a vlan tag, regardless of its vid.
+    Any,
+    /// Packets with a vlan tag with the given vid (12 bits).
+    Vid(u16),
+}
+
+impl VlanVid {
+    /// fails if the vid does not fit into 12 bits
+    pub fn vid(vid: u16) -> Result<Self> {
+        if vid > VID_MAX {
+            bail!(ErrorKind::IllegalValue(vid as u64, stringify!(VlanVid)));
+        }
+        Ok(VlanVid::Vid(vid))
+    }
+
+    /// reads a vlan vid match
+    /// returns None for other fields and masks that do not correspond to a VlanVid
+    pub fn from_match(tlv_match: &TlvMatch) -> Option<Self> {
+        let vlan_id = match tlv_match.payload() {
+            MatchPayload::VlanVId(payload) => payload.vlan_id,
+            _ => return None,
+        };
+        match tlv_match.mask() {
+            None if vlan_id == VID_NONE => Some(VlanVid::None),
+            None if vlan_id & VID_PRESENT != 0 && vlan_id & !(VID_PRESENT | VID_MAX) == 0 => {
+                Some(VlanVid::Vid(vlan_id & VID_MAX))
+            }
+            Some(mask) if vlan_id == VID_PRESENT && mask[..] == [0x10, 0x00] => Some(VlanVid::Any),
+            _ => None,
+        }
+    }
+}
+
+impl From<VlanVid> for TlvMatch {
+    fn from(vid: VlanVid) -> Self {
+        match vid {
+            VlanVid::Any => {
+                let mask = Into::<Vec<u8>>::into(PayloadVlanVId {
+                    vlan_id: VID_PRESENT,
+                });
+                TlvMatch::with_mask(MatchPayload::VlanVId(vid.into()), mask)
+                    .expect("mask has the payload length")
+            }
+            _ => TlvMatch::new(MatchPayload::VlanVId(vid.into())),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadVlanVId {
-    vlan_id: u16, // 12+1 bits
+    pub vlan_id: u16, // 12+1 bits
+}
+
+impl From<VlanVid> for PayloadVlanVId {
+    /// the value of the match, `VlanVid::Any` additionally needs the present bit as mask
+    /// vids are cut to 12 bits
+    fn from(vid: VlanVid) -> Self {
+        let vlan_id = match vid {
+            VlanVid::None => VID_NONE,
+            VlanVid::Any => VID_PRESENT,
+            VlanVid::Vid(vid) => VID_PRESENT | (vid & VID_MAX),
+        };
+        PayloadVlanVId { vlan_id: vlan_id }
+    }
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadVlanVId {
@@ -726,7 +1210,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadVlanVId {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadVlanVId {
-            vlan_id: cursor.read_u16::<BigEndian>().unwrap(),
+            vlan_id: cursor.read_u16::<BigEndian>()?,
         })
     }
 }
@@ -741,7 +1225,7 @@ impl Into<Vec<u8>> for PayloadVlanVId {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadVlanPcp {
-    vlan_pcp: u8, // 3 bits
+    pub vlan_pcp: u8, // 3 bits
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadVlanPcp {
@@ -749,7 +1233,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadVlanPcp {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadVlanPcp {
-            vlan_pcp: cursor.read_u8().unwrap(),
+            vlan_pcp: cursor.read_u8()?,
         })
     }
 }
@@ -764,7 +1248,7 @@ impl Into<Vec<u8>> for PayloadVlanPcp {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadIpDscp {
-    ip_dscp: u8, // 6 bits
+    pub ip_dscp: u8, // 6 bits
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadIpDscp {
@@ -772,7 +1256,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadIpDscp {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadIpDscp {
-            ip_dscp: cursor.read_u8().unwrap(),
+            ip_dscp: cursor.read_u8()?,
         })
     }
 }
@@ -787,7 +1271,7 @@ impl Into<Vec<u8>> for PayloadIpDscp {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadIpEcn {
-    ip_enc: u8, // 2 bits
+    pub ip_enc: u8, // 2 bits
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadIpEcn {
@@ -795,7 +1279,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadIpEcn {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadIpEcn {
-            ip_enc: cursor.read_u8().unwrap(),
+            ip_enc: cursor.read_u8()?,
         })
     }
 }
@@ -810,16 +1294,16 @@ impl Into<Vec<u8>> for PayloadIpEcn {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadIpProto {
-    ip_proto: IpProto,
+    pub ip_proto: IpProto,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadIpProto {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let ip_proto_raw = cursor.read_u16::<BigEndian>().unwrap();
+        let ip_proto_raw = cursor.read_u8()?;
         Ok(PayloadIpProto {
-            ip_proto: IpProto::from_u16(ip_proto_raw).ok_or::<Error>(
+            ip_proto: IpProto::from_u8(ip_proto_raw).ok_or::<Error>(
                 ErrorKind::UnknownValue(ip_proto_raw as u64, stringify!(IpProto)).into(),
             )?,
         })
@@ -991,14 +1475,14 @@ pub enum IpProto {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadIPv4Src {
-    ipv4_src: hw_addr::IPv4Address,
+    pub ipv4_src: Ipv4Addr,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadIPv4Src {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         Ok(PayloadIPv4Src {
-            ipv4_src: hw_addr::from_slice_v4(bytes)?,
+            ipv4_src: Ipv4Addr::from_slice(bytes)?,
         })
     }
 }
@@ -1006,21 +1490,21 @@ impl<'a> TryFrom<&'a [u8]> for PayloadIPv4Src {
 impl Into<Vec<u8>> for PayloadIPv4Src {
     fn into(self) -> Vec<u8> {
         let mut res = Vec::new();
-        res.extend_from_slice(&self.ipv4_src[..]);
+        res.extend_from_slice(&self.ipv4_src.octets()[..]);
         res
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadIPv4Dst {
-    ipv4_dst: hw_addr::IPv4Address,
+    pub ipv4_dst: Ipv4Addr,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadIPv4Dst {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         Ok(PayloadIPv4Dst {
-            ipv4_dst: hw_addr::from_slice_v4(bytes)?,
+            ipv4_dst: Ipv4Addr::from_slice(bytes)?,
         })
     }
 }
@@ -1028,14 +1512,14 @@ impl<'a> TryFrom<&'a [u8]> for PayloadIPv4Dst {
 impl Into<Vec<u8>> for PayloadIPv4Dst {
     fn into(self) -> Vec<u8> {
         let mut res = Vec::new();
-        res.extend_from_slice(&self.ipv4_dst[..]);
+        res.extend_from_slice(&self.ipv4_dst.octets()[..]);
         res
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadTcpSrc {
-    src_port: u16,
+    pub src_port: u16,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadTcpSrc {
@@ -1043,7 +1527,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadTcpSrc {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadTcpSrc {
-            src_port: cursor.read_u16::<BigEndian>().unwrap(),
+            src_port: cursor.read_u16::<BigEndian>()?,
         })
     }
 }
@@ -1058,7 +1542,7 @@ impl Into<Vec<u8>> for PayloadTcpSrc {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadTcpDst {
-    dst_port: u16,
+    pub dst_port: u16,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadTcpDst {
@@ -1066,7 +1550,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadTcpDst {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadTcpDst {
-            dst_port: cursor.read_u16::<BigEndian>().unwrap(),
+            dst_port: cursor.read_u16::<BigEndian>()?,
         })
     }
 }
@@ -1081,7 +1565,7 @@ impl Into<Vec<u8>> for PayloadTcpDst {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadUdpSrc {
-    src_port: u16,
+    pub src_port: u16,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadUdpSrc {
@@ -1089,7 +1573,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadUdpSrc {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadUdpSrc {
-            src_port: cursor.read_u16::<BigEndian>().unwrap(),
+            src_port: cursor.read_u16::<BigEndian>()?,
         })
     }
 }
@@ -1104,7 +1588,7 @@ impl Into<Vec<u8>> for PayloadUdpSrc {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadUdpDst {
-    dst_port: u16,
+    pub dst_port: u16,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadUdpDst {
@@ -1112,7 +1596,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadUdpDst {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadUdpDst {
-            dst_port: cursor.read_u16::<BigEndian>().unwrap(),
+            dst_port: cursor.read_u16::<BigEndian>()?,
         })
     }
 }
@@ -1127,7 +1611,7 @@ impl Into<Vec<u8>> for PayloadUdpDst {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadSctpSrc {
-    src_port: u16,
+    pub src_port: u16,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadSctpSrc {
@@ -1135,7 +1619,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadSctpSrc {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadSctpSrc {
-            src_port: cursor.read_u16::<BigEndian>().unwrap(),
+            src_port: cursor.read_u16::<BigEndian>()?,
         })
     }
 }
@@ -1150,7 +1634,7 @@ impl Into<Vec<u8>> for PayloadSctpSrc {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadSctpDst {
-    dst_port: u16,
+    pub dst_port: u16,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadSctpDst {
@@ -1158,7 +1642,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadSctpDst {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadSctpDst {
-            dst_port: cursor.read_u16::<BigEndian>().unwrap(),
+            dst_port: cursor.read_u16::<BigEndian>()?,
         })
     }
 }
@@ -1173,14 +1657,14 @@ impl Into<Vec<u8>> for PayloadSctpDst {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadIcmpV4Type {
-    ttype: IcmpType,
+    pub ttype: IcmpType,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadIcmpV4Type {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let raw_ttype = cursor.read_u8().unwrap();
+        let raw_ttype = cursor.read_u8()?;
         Ok(PayloadIcmpV4Type {
             ttype: IcmpType::from_u8(raw_ttype).ok_or::<Error>(
                 ErrorKind::UnknownValue(raw_ttype as u64, stringify!(IcmpType)).into(),
@@ -1242,7 +1726,7 @@ pub enum IcmpType {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadIcmpV4Code {
-    code: u8,
+    pub code: u8,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadIcmpV4Code {
@@ -1250,7 +1734,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadIcmpV4Code {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadIcmpV4Code {
-            code: cursor.read_u8().unwrap(),
+            code: cursor.read_u8()?,
         })
     }
 }
@@ -1265,14 +1749,14 @@ impl Into<Vec<u8>> for PayloadIcmpV4Code {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadArpOp {
-    arp_op: ArpOp,
+    pub arp_op: ArpOp,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadArpOp {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let raw_arp_op = cursor.read_u16::<BigEndian>().unwrap();
+        let raw_arp_op = cursor.read_u16::<BigEndian>()?;
         Ok(PayloadArpOp {
             arp_op: ArpOp::from_u16(raw_arp_op).ok_or::<Error>(
                 ErrorKind::UnknownValue(raw_arp_op as u64, stringify!(ArpOp)).into(),
@@ -1324,14 +1808,14 @@ pub enum ArpOp {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadArpSpa {
-    arp_spa: hw_addr::IPv4Address,
+    pub arp_spa: Ipv4Addr,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadArpSpa {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         Ok(PayloadArpSpa {
-            arp_spa: hw_addr::from_slice_v4(&bytes[..])?,
+            arp_spa: Ipv4Addr::from_slice(bytes)?,
         })
     }
 }
@@ -1339,21 +1823,21 @@ impl<'a> TryFrom<&'a [u8]> for PayloadArpSpa {
 impl Into<Vec<u8>> for PayloadArpSpa {
     fn into(self) -> Vec<u8> {
         let mut res = Vec::new();
-        res.extend_from_slice(&self.arp_spa[..]);
+        res.extend_from_slice(&self.arp_spa.octets()[..]);
         res
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadArpTpa {
-    arp_tpa: hw_addr::IPv4Address,
+    pub arp_tpa: Ipv4Addr,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadArpTpa {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         Ok(PayloadArpTpa {
-            arp_tpa: hw_addr::from_slice_v4(&bytes[..])?,
+            arp_tpa: Ipv4Addr::from_slice(bytes)?,
         })
     }
 }
@@ -1361,21 +1845,21 @@ impl<'a> TryFrom<&'a [u8]> for PayloadArpTpa {
 impl Into<Vec<u8>> for PayloadArpTpa {
     fn into(self) -> Vec<u8> {
         let mut res = Vec::new();
-        res.extend_from_slice(&self.arp_tpa[..]);
+        res.extend_from_slice(&self.arp_tpa.octets()[..]);
         res
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadArpSha {
-    arp_sha: hw_addr::EthernetAddress,
+    pub arp_sha: MacAddr,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadArpSha {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         Ok(PayloadArpSha {
-            arp_sha: hw_addr::from_slice_eth(&bytes[..])?,
+            arp_sha: MacAddr::from_slice(bytes)?,
         })
     }
 }
@@ -1383,21 +1867,21 @@ impl<'a> TryFrom<&'a [u8]> for PayloadArpSha {
 impl Into<Vec<u8>> for PayloadArpSha {
     fn into(self) -> Vec<u8> {
         let mut res = Vec::new();
-        res.extend_from_slice(&self.arp_sha[..]);
+        res.extend_from_slice(&self.arp_sha.octets()[..]);
         res
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadArpTha {
-    arp_tha: hw_addr::EthernetAddress,
+    pub arp_tha: MacAddr,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadArpTha {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         Ok(PayloadArpTha {
-            arp_tha: hw_addr::from_slice_eth(&bytes[..])?,
+            arp_tha: MacAddr::from_slice(bytes)?,
         })
     }
 }
@@ -1405,21 +1889,21 @@ impl<'a> TryFrom<&'a [u8]> for PayloadArpTha {
 impl Into<Vec<u8>> for PayloadArpTha {
     fn into(self) -> Vec<u8> {
         let mut res = Vec::new();
-        res.extend_from_slice(&self.arp_tha[..]);
+        res.extend_from_slice(&self.arp_tha.octets()[..]);
         res
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadIPv6Src {
-    ipv6_src: hw_addr::IPv6Address,
+    pub ipv6_src: Ipv6Addr,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadIPv6Src {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         Ok(PayloadIPv6Src {
-            ipv6_src: hw_addr::from_slice_v6(&bytes[..])?,
+            ipv6_src: Ipv6Addr::from_slice(bytes)?,
         })
     }
 }
@@ -1427,21 +1911,21 @@ impl<'a> TryFrom<&'a [u8]> for PayloadIPv6Src {
 impl Into<Vec<u8>> for PayloadIPv6Src {
     fn into(self) -> Vec<u8> {
         let mut res = Vec::new();
-        res.extend_from_slice(&self.ipv6_src[..]);
+        res.extend_from_slice(&self.ipv6_src.octets()[..]);
         res
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadIPv6Dst {
-    ipv6_dst: hw_addr::IPv6Address,
+    pub ipv6_dst: Ipv6Addr,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadIPv6Dst {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         Ok(PayloadIPv6Dst {
-            ipv6_dst: hw_addr::from_slice_v6(&bytes[..])?,
+            ipv6_dst: Ipv6Addr::from_slice(bytes)?,
         })
     }
 }
@@ -1449,14 +1933,14 @@ impl<'a> TryFrom<&'a [u8]> for PayloadIPv6Dst {
 impl Into<Vec<u8>> for PayloadIPv6Dst {
     fn into(self) -> Vec<u8> {
         let mut res = Vec::new();
-        res.extend_from_slice(&self.ipv6_dst[..]);
+        res.extend_from_slice(&self.ipv6_dst.octets()[..]);
         res
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadIPv6FLabel {
-    flabel: u32, // 20 bits
+    pub flabel: u32, // 20 bits
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadIPv6FLabel {
@@ -1464,7 +1948,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadIPv6FLabel {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadIPv6FLabel {
-            flabel: cursor.read_u32::<BigEndian>().unwrap(),
+            flabel: cursor.read_u32::<BigEndian>()?,
         })
     }
 }
@@ -1479,14 +1963,14 @@ impl Into<Vec<u8>> for PayloadIPv6FLabel {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadIcmpV6Type {
-    ttype: IcmpV6Type,
+    pub ttype: IcmpV6Type,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadIcmpV6Type {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let raw_ttype = cursor.read_u8().unwrap();
+        let raw_ttype = cursor.read_u8()?;
         Ok(PayloadIcmpV6Type {
             ttype: IcmpV6Type::from_u8(raw_ttype).ok_or::<Error>(
                 ErrorKind::UnknownValue(raw_ttype as u64, stringify!(IcmpV6Type)).into(),
@@ -1554,7 +2038,7 @@ pub enum IcmpV6Type {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadIcmpV6Code {
-    code: u8,
+    pub code: u8,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadIcmpV6Code {
@@ -1562,7 +2046,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadIcmpV6Code {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadIcmpV6Code {
-            code: cursor.read_u8().unwrap(),
+            code: cursor.read_u8()?,
         })
     }
 }
@@ -1577,14 +2061,14 @@ impl Into<Vec<u8>> for PayloadIcmpV6Code {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadIPv6NdTarget {
-    target: hw_addr::IPv6Address,
+    pub target: Ipv6Addr,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadIPv6NdTarget {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         Ok(PayloadIPv6NdTarget {
-            target: hw_addr::from_slice_v6(&bytes[..])?,
+            target: Ipv6Addr::from_slice(bytes)?,
         })
     }
 }
@@ -1592,21 +2076,21 @@ impl<'a> TryFrom<&'a [u8]> for PayloadIPv6NdTarget {
 impl Into<Vec<u8>> for PayloadIPv6NdTarget {
     fn into(self) -> Vec<u8> {
         let mut res = Vec::new();
-        res.extend_from_slice(&self.target[..]);
+        res.extend_from_slice(&self.target.octets()[..]);
         res
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadIPv6NdSll {
-    nd_sll: hw_addr::EthernetAddress,
+    pub nd_sll: MacAddr,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadIPv6NdSll {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         Ok(PayloadIPv6NdSll {
-            nd_sll: hw_addr::from_slice_eth(&bytes[..])?,
+            nd_sll: MacAddr::from_slice(bytes)?,
         })
     }
 }
@@ -1614,21 +2098,21 @@ impl<'a> TryFrom<&'a [u8]> for PayloadIPv6NdSll {
 impl Into<Vec<u8>> for PayloadIPv6NdSll {
     fn into(self) -> Vec<u8> {
         let mut res = Vec::new();
-        res.extend_from_slice(&self.nd_sll[..]);
+        res.extend_from_slice(&self.nd_sll.octets()[..]);
         res
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadIPv6NdTll {
-    nd_tll: hw_addr::EthernetAddress,
+    pub nd_tll: MacAddr,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadIPv6NdTll {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         Ok(PayloadIPv6NdTll {
-            nd_tll: hw_addr::from_slice_eth(&bytes[..])?,
+            nd_tll: MacAddr::from_slice(bytes)?,
         })
     }
 }
@@ -1636,14 +2120,14 @@ impl<'a> TryFrom<&'a [u8]> for PayloadIPv6NdTll {
 impl Into<Vec<u8>> for PayloadIPv6NdTll {
     fn into(self) -> Vec<u8> {
         let mut res = Vec::new();
-        res.extend_from_slice(&self.nd_tll[..]);
+        res.extend_from_slice(&self.nd_tll.octets()[..]);
         res
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadMplsLabel {
-    label: u32, // 20 bits
+    pub label: u32, // 20 bits
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadMplsLabel {
@@ -1651,7 +2135,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadMplsLabel {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadMplsLabel {
-            label: cursor.read_u32::<BigEndian>().unwrap(),
+            label: cursor.read_u32::<BigEndian>()?,
         })
     }
 }
@@ -1666,7 +2150,7 @@ impl Into<Vec<u8>> for PayloadMplsLabel {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadMplsTc {
-    tc: u8, // 3 bits
+    pub tc: u8, // 3 bits
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadMplsTc {
@@ -1674,7 +2158,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadMplsTc {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadMplsTc {
-            tc: cursor.read_u8().unwrap(),
+            tc: cursor.read_u8()?,
         })
     }
 }
@@ -1689,7 +2173,7 @@ impl Into<Vec<u8>> for PayloadMplsTc {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadMplsBos {
-    bos: u8, // 1 bits
+    pub bos: u8, // 1 bits
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadMplsBos {
@@ -1697,7 +2181,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadMplsBos {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadMplsBos {
-            bos: cursor.read_u8().unwrap(),
+            bos: cursor.read_u8()?,
         })
     }
 }
@@ -1712,7 +2196,7 @@ impl Into<Vec<u8>> for PayloadMplsBos {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadPbbISid {
-    i_sid: u32, // 24 bits
+    pub i_sid: u32, // 24 bits
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadPbbISid {
@@ -1720,7 +2204,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadPbbISid {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadPbbISid {
-            i_sid: cursor.read_u32::<BigEndian>().unwrap(),
+            i_sid: cursor.read_u32::<BigEndian>()?,
         })
     }
 }
@@ -1735,7 +2219,7 @@ impl Into<Vec<u8>> for PayloadPbbISid {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadTunnelId {
-    metadata: u64,
+    pub metadata: u64,
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadTunnelId {
@@ -1743,7 +2227,7 @@ impl<'a> TryFrom<&'a [u8]> for PayloadTunnelId {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(PayloadTunnelId {
-            metadata: cursor.read_u64::<BigEndian>().unwrap(),
+            metadata: cursor.read_u64::<BigEndian>()?,
         })
     }
 }
@@ -1758,16 +2242,23 @@ impl Into<Vec<u8>> for PayloadTunnelId {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct PayloadIPv6ExtHdr {
-    ext_hdr_flags: IPv6ExtHdrFlags, // 9 bits
+    pub ext_hdr_flags: IPv6ExtHdrFlags, // 9 bits
+}
+
+impl From<IPv6ExtHdrFlags> for PayloadIPv6ExtHdr {
+    fn from(flags: IPv6ExtHdrFlags) -> Self {
+        PayloadIPv6ExtHdr {
+            ext_hdr_flags: flags,
+        }
+    }
 }
 
 impl<'a> TryFrom<&'a [u8]> for PayloadIPv6ExtHdr {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let raw_flags = cursor.read_u16::<BigEndian>().unwrap();
         Ok(PayloadIPv6ExtHdr {
-            ext_hdr_flags: IPv6ExtHdrFlags(raw_flags),
+            ext_hdr_flags: flags_from_bits!(IPv6ExtHdrFlags, cursor.read_u16::<BigEndian>()?)?,
         })
     }
 }
@@ -1775,44 +2266,403 @@ impl<'a> TryFrom<&'a [u8]> for PayloadIPv6ExtHdr {
 impl Into<Vec<u8>> for PayloadIPv6ExtHdr {
     fn into(self) -> Vec<u8> {
         let mut res = Vec::new();
-        res.write_u16::<BigEndian>(self.ext_hdr_flags.0).unwrap();
+        res.write_u16::<BigEndian>(self.ext_hdr_flags.bits())
+            .unwrap();
         res
     }
 }
 
-bitfield!{
-    pub struct IPv6ExtHdrFlags(u16);
-    impl Debug;
+bitflags!{
+    /// IPv6 extension headers of a packet (OFPIEH_*).
+    pub struct IPv6ExtHdrFlags: u16 {
+        /// "No next header" encountered.
+        const NONEXT = 1 << 0;
+        /// Encrypted Sec Payload header present.
+        const ESP = 1 << 1;
+        /// Authentication header present.
+        const AUTH = 1 << 2;
+        /// 1 or 2 dest headers present.
+        const DEST = 1 << 3;
+        /// Fragment header present.
+        const FRAG = 1 << 4;
+        /// Router header present.
+        const ROUTER = 1 << 5;
+        /// Hop-by-hop header present.
+        const HOP = 1 << 6;
+        /// Unexpected repeats encountered.
+        const UNREP = 1 << 7;
+        /// Unexpected sequencing encountered.
+        const UNSEQ = 1 << 8;
+    }
+}
+
+/// Builds an ipv6_exthdr match from the flags that have to be set and the
+/// ones that must not be set, all other flags are masked out:
+///
+/// ```
+/// use oath2::ds::flow_match::{IPv6ExtHdrFlags, IPv6ExtHdrMatch};
+///
+/// // fragmented packets without authentication header
+/// let tlv_match = IPv6ExtHdrMatch::new()
+///     .present(IPv6ExtHdrFlags::FRAG)
+///     .absent(IPv6ExtHdrFlags::AUTH)
+///     .build()
+///     .unwrap();
+/// assert_eq!(tlv_match.mask(), &Some(vec![0x00, 0x14]));
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct IPv6ExtHdrMatch {
+    present: IPv6ExtHdrFlags,
+    absent: IPv6ExtHdrFlags,
+}
+
+impl Default for IPv6ExtHdrMatch {
+    fn default() -> Self {
+        IPv6ExtHdrMatch::new()
+    }
+}
+
+impl IPv6ExtHdrMatch {
+    /// matches every packet
+    pub fn new() -> Self {
+        IPv6ExtHdrMatch {
+            present: IPv6ExtHdrFlags::empty(),
+            absent: IPv6ExtHdrFlags::empty(),
+        }
+    }
 
-    u8;
-    /// "No next header" encountered.
-    pub get_no_next, set_no_next: 1, 0;
-    /// Encrypted Sec Payload header present.
-    pub get_esp, set_esp: 2, 1;
-    /// Authentication header present.
-    pub get_auth, set_auth: 3, 2;
-    /// 1 or 2 dest headers present.
-    pub get_dest, set_dest: 4, 3;
-    /// Fragment header present.
-    pub get_frag, set_frag: 5, 4;
-    /// Router header present.
-    pub get_router, set_router: 6, 5;
-    /// Hop-by-hop header present.
-    pub get_hop, set_hop: 7, 6;
-    /// Unexpected repeats encountered.
-    pub get_unrep, set_unrep: 8, 7;
-    /// Unexpected sequencing encountered.
-    pub get_unseq, set_unseq: 9, 8;
-}
-
-impl Clone for IPv6ExtHdrFlags {
-    fn clone(&self) -> Self {
-        IPv6ExtHdrFlags(self.0.clone())
+    /// the flags have to be set
+    pub fn present(mut self, flags: IPv6ExtHdrFlags) -> Self {
+        self.present |= flags;
+        self
+    }
+
+    /// the flags must not be set
+    pub fn absent(mut self, flags: IPv6ExtHdrFlags) -> Self {
+        self.absent |= flags;
+        self
+    }
+
+    /// the flags the match looks at
+    pub fn mask(&self) -> IPv6ExtHdrFlags {
+        self.present | self.absent
+    }
+
+    /// the match, without mask if every flag is either present or absent
+    /// fails if a flag is both present and absent
+    pub fn build(&self) -> Result<TlvMatch> {
+        let both = self.present & self.absent;
+        if !both.is_empty() {
+            bail!(ErrorKind::IllegalValue(
+                both.bits() as u64,
+                stringify!(IPv6ExtHdrMatch)
+            ));
+        }
+        let payload = MatchPayload::IPv6ExtHdr(self.present.into());
+        if self.mask() == IPv6ExtHdrFlags::all() {
+            return Ok(TlvMatch::new(payload));
+        }
+        let mask = Into::<Vec<u8>>::into(PayloadIPv6ExtHdr::from(self.mask()));
+        TlvMatch::with_mask(payload, mask)
+    }
+
+    /// reads an ipv6_exthdr match, bits of the value outside of the mask are ignored
+    /// returns None for other fields and masks with unknown flags
+    pub fn from_match(tlv_match: &TlvMatch) -> Option<Self> {
+        let flags = match tlv_match.payload() {
+            MatchPayload::IPv6ExtHdr(payload) => payload.ext_hdr_flags,
+            _ => return None,
+        };
+        let mask = match tlv_match.mask() {
+            None => IPv6ExtHdrFlags::all(),
+            Some(mask) if mask.len() == 2 => {
+                IPv6ExtHdrFlags::from_bits(BigEndian::read_u16(&mask[..]))?
+            }
+            Some(_) => return None,
+        };
+        Some(IPv6ExtHdrMatch {
+            present: flags & mask,
+            absent: mask - flags,
+        })
     }
 }
 
-impl ::std::cmp::PartialEq for IPv6ExtHdrFlags {
-    fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
+/// Namespace of packet types that are identified by an ethertype.
+pub const PT_NS_ETHERTYPE: u16 = 1;
+
+/// Type of the packet (since 1.5), the namespace tells how the type is
+/// interpreted, namespace 0 with type 0 are ethernet frames.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PayloadPacketType {
+    pub namespace: u16,
+    pub ns_type: u16,
+}
+
+impl PayloadPacketType {
+    /// ethernet frames
+    pub fn ethernet() -> Self {
+        PayloadPacketType {
+            namespace: 0,
+            ns_type: 0,
+        }
+    }
+
+    /// packets without an ethernet header that start with the given protocol
+    pub fn ethertype(ttype: u16) -> Self {
+        PayloadPacketType {
+            namespace: PT_NS_ETHERTYPE,
+            ns_type: ttype,
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for PayloadPacketType {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(bytes);
+        Ok(PayloadPacketType {
+            namespace: cursor.read_u16::<BigEndian>()?,
+            ns_type: cursor.read_u16::<BigEndian>()?,
+        })
+    }
+}
+
+impl Into<Vec<u8>> for PayloadPacketType {
+    fn into(self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.write_u16::<BigEndian>(self.namespace).unwrap();
+        res.write_u16::<BigEndian>(self.ns_type).unwrap();
+        res
+    }
+}
+
+/// Field of an experimenter oxm class (eg. the NXM extensions of Open vSwitch).
+/// The value is kept as raw bytes, so fields of unknown experimenters are
+/// decoded and encoded again unchanged.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExperimenterMatch {
+    pub experimenter: u32,
+    /// experimenter defined field (7 bits)
+    pub field: u8,
+    /// value without the mask
+    pub data: Vec<u8>,
+}
+
+impl ExperimenterMatch {
+    /// name of the field if a decoder is registered for the experimenter
+    pub fn field_name(&self) -> Option<&'static str> {
+        experimenter_oxm_decoder(self.experimenter)
+            .and_then(|decoder| decoder.field_name(self.field))
+    }
+
+    /// checks the field with the decoder registered for the experimenter
+    /// fields of unknown experimenters are always valid
+    fn validate(&self, mask: Option<&[u8]>) -> Result<()> {
+        match experimenter_oxm_decoder(self.experimenter) {
+            Some(decoder) => decoder.validate(self.field, &self.data[..], mask),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Into<Vec<u8>> for ExperimenterMatch {
+    fn into(self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.write_u32::<BigEndian>(self.experimenter).unwrap();
+        res.extend_from_slice(&self.data[..]);
+        res
+    }
+}
+
+/// Knows the oxm fields of one experimenter, see `register_experimenter_oxm`.
+pub trait ExperimenterOxmDecoder: Send + Sync {
+    /// checks the value and mask of a decoded field
+    /// an error fails decoding the whole match
+    fn validate(&self, field: u8, value: &[u8], mask: Option<&[u8]>) -> Result<()>;
+
+    /// name of the field, None for unknown fields
+    fn field_name(&self, field: u8) -> Option<&'static str>;
+}
+
+/// decoders by experimenter id
+static EXPERIMENTER_OXM_DECODERS: RwLock<Vec<(u32, Arc<dyn ExperimenterOxmDecoder>)>> =
+    RwLock::new(Vec::new());
+
+/// registers the decoder used for the oxm fields of the experimenter
+/// replaces the decoder registered before for the same experimenter
+pub fn register_experimenter_oxm(experimenter: u32, decoder: Arc<dyn ExperimenterOxmDecoder>) {
+    let mut decoders = EXPERIMENTER_OXM_DECODERS.write().unwrap();
+    decoders.retain(|&(id, _)| id != experimenter);
+    decoders.push((experimenter, decoder));
+}
+
+fn experimenter_oxm_decoder(experimenter: u32) -> Option<Arc<dyn ExperimenterOxmDecoder>> {
+    EXPERIMENTER_OXM_DECODERS
+        .read()
+        .unwrap()
+        .iter()
+        .find(|&&(id, _)| id == experimenter)
+        .map(|(_, decoder)| decoder.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_order_and_prerequisites() {
+        let eth_type = TlvMatch::new(MatchPayload::EthType(PayloadEthType {
+            ttype: EtherType::IPv4,
+        }));
+        let ip_proto = TlvMatch::new(MatchPayload::IpProto(PayloadIpProto {
+            ip_proto: IpProto::Tcp,
+        }));
+        let tcp_dst = TlvMatch::new(MatchPayload::TcpDst(PayloadTcpDst { dst_port: 80 }));
+        let sent = Match::new(vec![eth_type.clone(), tcp_dst.clone()]);
+        let reported = Match::new(vec![tcp_dst.clone(), ip_proto.clone(), eth_type.clone()]);
+        assert_ne!(sent, reported);
+        assert_eq!(sent.normalized(), reported.normalized());
+        assert_eq!(
+            reported.normalized().matches(),
+            &vec![eth_type.clone(), ip_proto, tcp_dst]
+        );
+        // eth_type is not implied by tcp_dst
+        let ipv6 = TlvMatch::new(MatchPayload::EthType(PayloadEthType {
+            ttype: EtherType::IPv6,
+        }));
+        assert_ne!(
+            sent.normalized(),
+            Match::new(vec![ipv6, reported.matches()[0].clone()]).normalized()
+        );
+    }
+
+    #[test]
+    fn builds_masked_ipv6_exthdr_matches() {
+        let exthdr = IPv6ExtHdrMatch::new()
+            .present(IPv6ExtHdrFlags::FRAG | IPv6ExtHdrFlags::HOP)
+            .absent(IPv6ExtHdrFlags::UNREP);
+        let bytes: Vec<u8> = Match::new(vec![exthdr.build().unwrap()]).into();
+        // match header, oxm header (basic class, field 39, masked), value and mask, padding
+        assert_eq!(
+            bytes,
+            vec![0, 1, 0, 12, 0x80, 0x00, 0x4f, 0x04, 0x00, 0x50, 0x00, 0xd0, 0, 0, 0, 0]
+        );
+        let decoded = Match::try_from(&bytes[..]).unwrap();
+        let tlv_match = &decoded.matches()[0];
+        assert_eq!(IPv6ExtHdrMatch::from_match(tlv_match), Some(exthdr));
+
+        // every flag is known, no mask is needed
+        let exact = IPv6ExtHdrMatch::new()
+            .present(IPv6ExtHdrFlags::ESP)
+            .absent(IPv6ExtHdrFlags::all() - IPv6ExtHdrFlags::ESP)
+            .build()
+            .unwrap();
+        assert_eq!(exact.mask(), &None);
+        assert!(IPv6ExtHdrMatch::new()
+            .present(IPv6ExtHdrFlags::ESP)
+            .absent(IPv6ExtHdrFlags::ESP)
+            .build()
+            .is_err());
+
+        // bit 9 is not defined
+        let unknown = [0, 1, 0, 10, 0x80, 0x00, 0x4e, 0x02, 0x02, 0x00, 0, 0, 0, 0, 0, 0];
+        assert!(Match::try_from(&unknown[..]).is_err());
+    }
+
+    /// experimenter class match on field 3 with the value 0x0102 and mask 0x00ff
+    const EXPERIMENTER_MATCH: [u8; 16] = [
+        0x00, 0x01, 0x00, 0x10, // match header
+        0xff, 0xff, 0x07, 0x08, // oxm header (experimenter class, field 3, masked)
+        0x00, 0x00, 0xbe, 0xef, // experimenter id
+        0x01, 0x02, 0x00, 0xff, // value and mask
+    ];
+
+    #[test]
+    fn decodes_experimenter_fields() {
+        let mmatch = Match::try_from(&EXPERIMENTER_MATCH[..]).unwrap();
+        let tlv_match = &mmatch.matches()[0];
+        assert_eq!(
+            tlv_match.payload(),
+            &MatchPayload::Experimenter(ExperimenterMatch {
+                experimenter: 0xbeef,
+                field: 3,
+                data: vec![0x01, 0x02],
+            })
+        );
+        assert_eq!(tlv_match.mask(), &Some(vec![0x00, 0xff]));
+
+        let rebuilt = Match::new(vec![
+            TlvMatch::with_mask(tlv_match.payload().clone(), vec![0x00, 0xff]).unwrap(),
+        ]);
+        assert_eq!(rebuilt, mmatch);
+        assert_eq!(Into::<Vec<u8>>::into(rebuilt), EXPERIMENTER_MATCH.to_vec());
+    }
+
+    #[test]
+    fn looks_up_fields_by_type() {
+        let mac = MacAddr([0, 0, 0, 0, 0, 1]);
+        let mmatch = Match::new(vec![
+            TlvMatch::new(MatchPayload::InPort(PayloadInPort {
+                ingress_port: PortNumber::NormalPort(3),
+            })),
+            TlvMatch::new(MatchPayload::EthSrc(PayloadEthSrc { eth_src: mac })),
+            TlvMatch::new(MatchPayload::TunnelId(PayloadTunnelId { metadata: 42 })),
+        ]);
+        assert_eq!(mmatch.in_port(), Some(PortNumber::NormalPort(3)));
+        assert_eq!(mmatch.eth_src(), Some(mac));
+        assert_eq!(mmatch.tunnel_id(), Some(42));
+        assert_eq!(mmatch.eth_dst(), None);
+        assert_eq!(mmatch.metadata(), None);
+        assert_eq!(
+            mmatch.get::<PayloadEthSrc>(),
+            Some(&PayloadEthSrc { eth_src: mac })
+        );
+        assert!(mmatch.get::<PayloadIpProto>().is_none());
+    }
+
+    struct TwoByteFields;
+
+    impl ExperimenterOxmDecoder for TwoByteFields {
+        fn validate(&self, field: u8, value: &[u8], _: Option<&[u8]>) -> Result<()> {
+            if value.len() != 2 {
+                bail!(ErrorKind::InvalidSliceLength(
+                    2,
+                    value.len(),
+                    stringify!(TwoByteFields)
+                ));
+            }
+            if field != 3 {
+                bail!(ErrorKind::UnknownValue(field as u64, stringify!(TwoByteFields)));
+            }
+            Ok(())
+        }
+
+        fn field_name(&self, field: u8) -> Option<&'static str> {
+            match field {
+                3 => Some("two_bytes"),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn registered_decoders_check_fields() {
+        let mut bytes = EXPERIMENTER_MATCH;
+        // the experimenter id is only used by this test
+        bytes[10] = 0xca;
+        bytes[11] = 0xfe;
+        register_experimenter_oxm(0xcafe, Arc::new(TwoByteFields));
+
+        let mmatch = Match::try_from(&bytes[..]).unwrap();
+        match mmatch.matches()[0].payload() {
+            MatchPayload::Experimenter(payload) => {
+                assert_eq!(payload.field_name(), Some("two_bytes"))
+            }
+            payload => panic!("unexpected payload {:?}", payload),
+        }
+
+        // field 4
+        bytes[6] = 0x09;
+        assert!(Match::try_from(&bytes[..]).is_err());
     }
 }
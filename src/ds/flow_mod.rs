@@ -3,12 +3,22 @@ use num_traits::{FromPrimitive, ToPrimitive};
 use std::convert::{Into, TryFrom};
 use std::io::{Cursor, Seek, SeekFrom};
 
+use super::codec::OfEncode;
 use super::flow_instructions;
 use super::flow_match::Match;
-use super::ports::PortNumber;
+use super::group_mod::{GroupNumber, GROUP_ANY};
+use super::packet_out::BufferId;
+use super::ports::{PortNo, PortNumber};
+use super::validate;
+use super::{checked_slice, checked_tail};
 
 use super::super::err::*;
 
+/// Length of a flow mod without the match and the instructions (40 bytes).
+pub const FLOW_MOD_LENGTH: usize = 40;
+/// Priority of flows that do not specify one (OFP_DEFAULT_PRIORITY).
+pub const DEFAULT_PRIORITY: u16 = 0x8000;
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct FlowMod {
     pub cookie: u64,
@@ -18,57 +28,203 @@ pub struct FlowMod {
     pub idle_timeout: u16,
     pub hard_timeout: u16,
     pub priority: u16,
-    pub buffer_id: u32,
+    pub buffer_id: BufferId,
     pub out_port: PortNumber,
-    pub out_group: u32,
+    pub out_group: GroupNumber,
     pub flags: FlowModFlags,
     //pad 2 bytes
     pub mmatch: Match,
     pub instructions: Vec<flow_instructions::InstructionHeader>,
 }
 
+impl FlowMod {
+    /// adds the flow to the table, without cookie, timeouts and flags
+    pub fn add(
+        table_id: u8,
+        priority: u16,
+        mmatch: Match,
+        instructions: Vec<flow_instructions::InstructionHeader>,
+    ) -> Self {
+        FlowMod {
+            cookie: 0,
+            cookie_mask: 0,
+            table_id: table_id,
+            command: FlowModCommand::Add,
+            idle_timeout: 0,
+            hard_timeout: 0,
+            priority: priority,
+            buffer_id: BufferId::NoBuffer,
+            out_port: PortNo::Any.into(),
+            out_group: GROUP_ANY,
+            flags: FlowModFlags::empty(),
+            mmatch: mmatch,
+            instructions: instructions,
+        }
+    }
+
+    /// deletes all flows of the table (or every table with TABLE_ALL) whose cookie
+    /// matches under cookie_mask and that output to out_port (or any port with PortNo::Any)
+    pub fn delete(cookie: u64, cookie_mask: u64, table_id: u8, out_port: PortNumber) -> Self {
+        FlowMod {
+            cookie: cookie,
+            cookie_mask: cookie_mask,
+            table_id: table_id,
+            command: FlowModCommand::Delete,
+            idle_timeout: 0,
+            hard_timeout: 0,
+            priority: 0,
+            buffer_id: BufferId::NoBuffer,
+            out_port: out_port,
+            out_group: GROUP_ANY,
+            flags: FlowModFlags::empty(),
+            mmatch: Match::new(Vec::new()),
+            instructions: Vec::new(),
+        }
+    }
+}
+
+/// Flow mod that adds a flow, with presets for the common patterns, eg.
+/// `FlowModBuilder::add(mmatch).idle(30).tracked(cookies.next()).build()?`.
+/// Starts with DEFAULT_PRIORITY in table 0, without timeouts and instructions.
+/// The instructions can be added in any order, see `build`.
+#[derive(Debug, Clone)]
+pub struct FlowModBuilder {
+    flow_mod: FlowMod,
+}
+
+impl FlowModBuilder {
+    pub fn add(mmatch: Match) -> Self {
+        FlowModBuilder {
+            flow_mod: FlowMod {
+                cookie: 0,
+                cookie_mask: 0,
+                table_id: 0,
+                command: FlowModCommand::Add,
+                idle_timeout: 0,
+                hard_timeout: 0,
+                priority: DEFAULT_PRIORITY,
+                buffer_id: BufferId::NoBuffer,
+                out_port: PortNo::Any.into(),
+                out_group: GROUP_ANY,
+                flags: FlowModFlags::empty(),
+                mmatch: mmatch,
+                instructions: Vec::new(),
+            },
+        }
+    }
+
+    pub fn table(mut self, table_id: u8) -> Self {
+        self.flow_mod.table_id = table_id;
+        self
+    }
+
+    pub fn priority(mut self, priority: u16) -> Self {
+        self.flow_mod.priority = priority;
+        self
+    }
+
+    pub fn cookie(mut self, cookie: u64) -> Self {
+        self.flow_mod.cookie = cookie;
+        self
+    }
+
+    /// adds the flags to the ones already set
+    pub fn flags(mut self, flags: FlowModFlags) -> Self {
+        self.flow_mod.flags.insert(flags);
+        self
+    }
+
+    pub fn instruction(mut self, instruction: flow_instructions::InstructionHeader) -> Self {
+        self.flow_mod.instructions.push(instruction);
+        self
+    }
+
+    pub fn instructions(mut self, instructions: Vec<flow_instructions::InstructionHeader>) -> Self {
+        self.flow_mod.instructions.extend(instructions);
+        self
+    }
+
+    /// removes the flow after it matched no packets for secs
+    pub fn idle(mut self, secs: u16) -> Self {
+        self.flow_mod.idle_timeout = secs;
+        self
+    }
+
+    /// removes the flow secs after it was added
+    pub fn hard(mut self, secs: u16) -> Self {
+        self.flow_mod.hard_timeout = secs;
+        self
+    }
+
+    /// keeps the flow until it is deleted, clears both timeouts
+    pub fn permanent(mut self) -> Self {
+        self.flow_mod.idle_timeout = 0;
+        self.flow_mod.hard_timeout = 0;
+        self
+    }
+
+    /// the switch reports the removal of the flow, which is told apart from
+    /// other flows by the cookie (eg. from `CookieNamespace::next`)
+    /// see also `FlowTimers::add_tracked`
+    pub fn tracked(self, cookie: u64) -> Self {
+        self.cookie(cookie).flags(FlowModFlags::SEND_FLOW_REM)
+    }
+
+    /// sorts the instructions in the order the switch executes them, fails
+    /// with ErrorKind::InvalidMessage if an instruction type is used twice or
+    /// goto table does not go to a later table
+    pub fn build(self) -> Result<FlowMod> {
+        let mut flow_mod = self.flow_mod;
+        flow_mod.instructions.sort_by_key(|instruction| {
+            validate::instruction_rank(instruction.ttype()).unwrap_or(usize::MAX)
+        });
+        let mut violations = Vec::new();
+        validate::validate_instructions(flow_mod.table_id, &flow_mod.instructions, &mut violations);
+        if !violations.is_empty() {
+            bail!(ErrorKind::InvalidMessage(violations));
+        }
+        Ok(flow_mod)
+    }
+}
+
 impl<'a> TryFrom<&'a [u8]> for FlowMod {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let cookie = cursor.read_u64::<BigEndian>().unwrap();
-        let cookie_mask = cursor.read_u64::<BigEndian>().unwrap();
-        let table_id = cursor.read_u8().unwrap();
-        let command_raw = cursor.read_u8().unwrap();
+        let cookie = cursor.read_u64::<BigEndian>()?;
+        let cookie_mask = cursor.read_u64::<BigEndian>()?;
+        let table_id = cursor.read_u8()?;
+        let command_raw = cursor.read_u8()?;
         let command = FlowModCommand::from_u8(command_raw).ok_or::<Error>(
             ErrorKind::UnknownValue(command_raw as u64, stringify!(FlowModCommand)).into(),
         )?;
-        let idle_timeout = cursor.read_u16::<BigEndian>().unwrap();
-        let hard_timeout = cursor.read_u16::<BigEndian>().unwrap();
-        let priority = cursor.read_u16::<BigEndian>().unwrap();
-        let buffer_id = cursor.read_u32::<BigEndian>().unwrap();
-        let out_port = PortNumber::try_from(cursor.read_u32::<BigEndian>().unwrap())?;
-        let out_group = cursor.read_u32::<BigEndian>().unwrap();
-        let flags_raw = cursor.read_u16::<BigEndian>().unwrap();
-        let flags = FlowModFlags::from_bits(flags_raw).unwrap();
+        let idle_timeout = cursor.read_u16::<BigEndian>()?;
+        let hard_timeout = cursor.read_u16::<BigEndian>()?;
+        let priority = cursor.read_u16::<BigEndian>()?;
+        let buffer_id = BufferId::from(cursor.read_u32::<BigEndian>()?);
+        let out_port = PortNumber::try_from(cursor.read_u32::<BigEndian>()?)?;
+        let out_group = GroupNumber::try_from(cursor.read_u32::<BigEndian>()?)?;
+        let flags_raw = cursor.read_u16::<BigEndian>()?;
+        let flags = flags_from_bits!(FlowModFlags, flags_raw)?;
+        cursor.seek(SeekFrom::Current(2)).unwrap(); // pad 2 bytes
 
         let mmatch_slice_len = Match::read_len(&mut cursor)?;
-        let mmatch_slice =
-            &bytes[cursor.position() as usize..cursor.position() as usize + mmatch_slice_len];
+        let mmatch_slice = checked_slice(
+            bytes,
+            cursor.position() as usize,
+            mmatch_slice_len,
+            stringify!(Match),
+        )?;
 
-        let mmatch = Match::try_from(mmatch_slice)?;
+        let mmatch = Match::try_from(mmatch_slice).within(cursor.position() as usize)?;
         cursor
             .seek(SeekFrom::Current(mmatch_slice_len as i64))
             .unwrap();
 
-        let mut instructions = Vec::new();
-        let mut bytes_left = bytes.len() as u64;
-        while bytes_left > cursor.position() {
-            let instruction_len = flow_instructions::get_instruction_slice_len(&mut cursor);
-            let instruction_slice =
-                &bytes[cursor.position() as usize..cursor.position() as usize + instruction_len];
-            let instruction = flow_instructions::InstructionHeader::try_from(instruction_slice)?;
-            cursor
-                .seek(SeekFrom::Current(instruction_len as i64))
-                .unwrap();
-            bytes_left -= instruction_len as u64;
-            instructions.push(instruction);
-        }
+        let instructions_slice =
+            checked_tail(bytes, cursor.position() as usize, stringify!(FlowMod))?;
+        let instructions = flow_instructions::instructions_from_slice(instructions_slice)
+            .within(cursor.position() as usize)?;
 
         Ok(FlowMod {
             cookie: cookie,
@@ -88,26 +244,34 @@ impl<'a> TryFrom<&'a [u8]> for FlowMod {
     }
 }
 
+impl OfEncode for FlowMod {
+    fn encoded_len(&self) -> usize {
+        FLOW_MOD_LENGTH + self.mmatch.encoded_len() + self.instructions.encoded_len()
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.write_u64::<BigEndian>(self.cookie).unwrap();
+        buf.write_u64::<BigEndian>(self.cookie_mask).unwrap();
+        buf.write_u8(self.table_id).unwrap();
+        buf.write_u8(self.command.to_u8().unwrap()).unwrap();
+        buf.write_u16::<BigEndian>(self.idle_timeout).unwrap();
+        buf.write_u16::<BigEndian>(self.hard_timeout).unwrap();
+        buf.write_u16::<BigEndian>(self.priority).unwrap();
+        buf.write_u32::<BigEndian>(self.buffer_id.into()).unwrap();
+        buf.write_u32::<BigEndian>(self.out_port.clone().into())
+            .unwrap();
+        buf.write_u32::<BigEndian>(self.out_group.clone().into())
+            .unwrap();
+        buf.write_u16::<BigEndian>(self.flags.bits()).unwrap();
+        buf.write_u16::<BigEndian>(0).unwrap(); // pad 2 bytes
+        self.mmatch.encode_into(buf);
+        self.instructions.encode_into(buf);
+    }
+}
+
 impl Into<Vec<u8>> for FlowMod {
     fn into(self) -> Vec<u8> {
-        let mut res = Vec::new();
-        res.write_u64::<BigEndian>(self.cookie).unwrap();
-        res.write_u64::<BigEndian>(self.cookie_mask).unwrap();
-        res.write_u8(self.table_id).unwrap();
-        res.write_u8(self.command.to_u8().unwrap()).unwrap();
-        res.write_u16::<BigEndian>(self.idle_timeout).unwrap();
-        res.write_u16::<BigEndian>(self.hard_timeout).unwrap();
-        res.write_u16::<BigEndian>(self.priority).unwrap();
-        res.write_u32::<BigEndian>(self.buffer_id).unwrap();
-        res.write_u32::<BigEndian>(self.out_port.into()).unwrap();
-        res.write_u32::<BigEndian>(self.out_group).unwrap();
-        res.write_u16::<BigEndian>(self.flags.bits()).unwrap();
-        res.write_u16::<BigEndian>(0).unwrap(); // pad 2 bytes
-        res.extend_from_slice(&Into::<Vec<u8>>::into(self.mmatch)[..]);
-        for instruction in self.instructions {
-            res.extend_from_slice(&Into::<Vec<u8>>::into(instruction)[..]);
-        }
-        res
+        self.to_bytes()
     }
 }
 
@@ -142,3 +306,64 @@ bitflags!{
         const NO_BYT_COUNTS = 1 << 4;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::flow_parser::{parse_flow_mod, parse_instructions, parse_match};
+    use super::*;
+
+    #[test]
+    fn builds_flows_like_the_parser() {
+        let flow_mod = FlowModBuilder::add(parse_match("in_port=1").unwrap())
+            .table(1)
+            .priority(10)
+            .hard(60)
+            .idle(30)
+            .tracked(7)
+            .instructions(parse_instructions("output:2").unwrap())
+            .build()
+            .unwrap();
+        let parsed = parse_flow_mod(
+            "table=1,priority=10,idle_timeout=30,hard_timeout=60,cookie=7,send_flow_rem,\
+             in_port=1,actions=output:2",
+        )
+        .unwrap();
+        assert_eq!(flow_mod, parsed);
+
+        let permanent = FlowModBuilder::add(Match::new(Vec::new()))
+            .idle(30)
+            .hard(60)
+            .permanent()
+            .build()
+            .unwrap();
+        assert_eq!((permanent.idle_timeout, permanent.hard_timeout), (0, 0));
+        assert_eq!(permanent.priority, DEFAULT_PRIORITY);
+    }
+
+    #[test]
+    fn orders_instructions_and_rejects_duplicates() {
+        let instructions = parse_instructions("goto_table:2,output:1").unwrap();
+        let mut reversed = instructions.clone();
+        reversed.reverse();
+        let flow_mod = FlowModBuilder::add(Match::new(Vec::new()))
+            .table(1)
+            .instructions(reversed)
+            .build()
+            .unwrap();
+        assert_eq!(flow_mod.instructions, instructions);
+
+        let duplicate = FlowModBuilder::add(Match::new(Vec::new()))
+            .instructions(instructions.clone())
+            .instruction(instructions[0].clone())
+            .build();
+        assert!(duplicate.is_err());
+        let backwards = FlowModBuilder::add(Match::new(Vec::new()))
+            .table(2)
+            .instructions(instructions)
+            .build();
+        match *backwards.unwrap_err().kind() {
+            ErrorKind::InvalidMessage(ref violations) => assert_eq!(violations.len(), 1),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+    }
+}
@@ -0,0 +1,1409 @@
+//! Parser for the flow syntax used by `ovs-ofctl`, e.g.
+//! `table=0,priority=100,in_port=1,ip,nw_dst=10.0.0.1,actions=output:2`.
+//!
+//! Parsed flows always use the `Add` command, other commands can be set on the
+//! returned `FlowMod` afterwards. `format_flow_mod` turns a `FlowMod` back into
+//! the same syntax.
+
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::fmt::Write;
+use std::str::FromStr;
+
+use super::super::err::*;
+use super::actions::{self, ActionHeader, ActionPayload};
+use super::flow_instructions::{self, InstructionHeader, InstructionPayload};
+use super::flow_match::*;
+pub use super::flow_match::VID_PRESENT;
+pub use super::packet_out::{BufferId, CONTROLLER_MAX_LEN, NO_BUFFER};
+pub use super::flow_mod::DEFAULT_PRIORITY;
+use super::flow_mod::{FlowMod, FlowModCommand, FlowModFlags};
+#[cfg(feature = "nicira")]
+use super::nx;
+use super::addr::{Ipv4Addr, Ipv6Addr, MacAddr};
+pub use super::group_mod::GROUP_ANY;
+use super::group_mod::GroupNumber;
+use super::meter_mod::MeterId;
+use super::ports::{PortNo, PortNumber};
+
+/// parses a complete flow including its actions
+pub fn parse_flow_mod(flow: &str) -> Result<FlowMod> {
+    let (fields, actions) = split_actions(flow);
+    let mut flow_mod = FlowMod {
+        cookie: 0,
+        cookie_mask: 0,
+        table_id: 0,
+        command: FlowModCommand::Add,
+        idle_timeout: 0,
+        hard_timeout: 0,
+        priority: DEFAULT_PRIORITY,
+        buffer_id: BufferId::NoBuffer,
+        out_port: PortNo::Any.into(),
+        out_group: GROUP_ANY,
+        flags: FlowModFlags::empty(),
+        mmatch: Match::new(Vec::new()),
+        instructions: Vec::new(),
+    };
+
+    let mut match_parser = MatchParser::new();
+    for token in split_list(fields, true) {
+        match split_key_value(token) {
+            ("table", Some(value)) => flow_mod.table_id = parse_uint(value, 0xff, token)? as u8,
+            ("priority", Some(value)) => {
+                flow_mod.priority = parse_uint(value, 0xffff, token)? as u16
+            }
+            ("cookie", Some(value)) => {
+                let (cookie, mask) = split_mask(value);
+                flow_mod.cookie = parse_uint(cookie, u64::MAX, token)?;
+                if let Some(mask) = mask {
+                    flow_mod.cookie_mask = parse_uint(mask, u64::MAX, token)?;
+                }
+            }
+            ("idle_timeout", Some(value)) => {
+                flow_mod.idle_timeout = parse_uint(value, 0xffff, token)? as u16
+            }
+            ("hard_timeout", Some(value)) => {
+                flow_mod.hard_timeout = parse_uint(value, 0xffff, token)? as u16
+            }
+            ("out_port", Some(value)) => flow_mod.out_port = parse_port(value, token)?,
+            ("out_group", Some(value)) => flow_mod.out_group = parse_group(value, token)?,
+            ("send_flow_rem", None) => flow_mod.flags.insert(FlowModFlags::SEND_FLOW_REM),
+            ("check_overlap", None) => flow_mod.flags.insert(FlowModFlags::CHECK_OVERLAP),
+            ("reset_counts", None) => flow_mod.flags.insert(FlowModFlags::RESET_COUNTS),
+            ("no_packet_counts", None) => flow_mod.flags.insert(FlowModFlags::NO_PKT_COUNTS),
+            ("no_byte_counts", None) => flow_mod.flags.insert(FlowModFlags::NO_BYT_COUNTS),
+            (key, value) => match_parser.add(key, value, token)?,
+        }
+    }
+    flow_mod.mmatch = match_parser.into_match();
+
+    if let Some(actions) = actions {
+        flow_mod.instructions = parse_instructions(actions)?;
+    }
+    Ok(flow_mod)
+}
+
+/// parses match fields only, e.g. `in_port=1,tcp,tcp_dst=80`
+pub fn parse_match(fields: &str) -> Result<Match> {
+    let mut match_parser = MatchParser::new();
+    for token in split_list(fields, true) {
+        let (key, value) = split_key_value(token);
+        match_parser.add(key, value, token)?;
+    }
+    Ok(match_parser.into_match())
+}
+
+/// parses the value of `actions=`, e.g. `pop_vlan,output:2,goto_table:1`
+/// plain actions are collected into a single apply actions instruction
+pub fn parse_instructions(actions: &str) -> Result<Vec<InstructionHeader>> {
+    let mut meter = None;
+    let mut apply_actions = Vec::new();
+    let mut clear_actions = false;
+    let mut write_actions = None;
+    let mut write_metadata = None;
+    let mut goto_table = None;
+
+    for token in split_list(actions, false) {
+        if token.starts_with("write_actions(") && token.ends_with(')') {
+            let inner = &token["write_actions(".len()..token.len() - 1];
+            write_actions = Some(parse_actions(inner)?);
+            continue;
+        }
+        match split_action(token) {
+            ("meter", Some(value)) => meter = Some(parse_meter(value, token)?),
+            ("clear_actions", None) => clear_actions = true,
+            ("write_metadata", Some(value)) => {
+                let (metadata, mask) = split_mask(value);
+                let metadata = parse_uint(metadata, u64::MAX, token)?;
+                let mask = match mask {
+                    Some(mask) => parse_uint(mask, u64::MAX, token)?,
+                    None => u64::MAX,
+                };
+                write_metadata = Some((metadata, mask));
+            }
+            ("goto_table", Some(value)) => goto_table = Some(parse_uint(value, 0xff, token)? as u8),
+            ("drop", None) => (),
+            _ => apply_actions.push(parse_action(token)?),
+        }
+    }
+
+    // instructions are executed in this order regardless of how they are listed
+    let mut instructions = Vec::new();
+    if let Some(meter_id) = meter {
+        instructions.push(
+            InstructionPayload::Meter(flow_instructions::PayloadMeter { meter_id: meter_id })
+                .into(),
+        );
+    }
+    if !apply_actions.is_empty() {
+        instructions.push(
+            InstructionPayload::ApplyActions(flow_instructions::PayloadApplyActions {
+                actions: apply_actions,
+            })
+            .into(),
+        );
+    }
+    if clear_actions {
+        instructions.push(
+            InstructionPayload::ClearActions(flow_instructions::PayloadClearActions {}).into(),
+        );
+    }
+    if let Some(actions) = write_actions {
+        instructions.push(
+            InstructionPayload::WriteActions(flow_instructions::PayloadWriteActions {
+                actions: actions,
+            })
+            .into(),
+        );
+    }
+    if let Some((metadata, mask)) = write_metadata {
+        instructions.push(
+            InstructionPayload::WriteMetaData(flow_instructions::PayloadWriteMetaData {
+                metadata: metadata,
+                metadata_mask: mask,
+            })
+            .into(),
+        );
+    }
+    if let Some(table_id) = goto_table {
+        instructions.push(
+            InstructionPayload::GotoTable(flow_instructions::PayloadGotoTable {
+                table_id: table_id,
+            })
+            .into(),
+        );
+    }
+    Ok(instructions)
+}
+
+/// parses a list of plain actions, e.g. `set_field:1->vlan_vid,output:2`
+pub fn parse_actions(actions: &str) -> Result<Vec<ActionHeader>> {
+    let mut res = Vec::new();
+    for token in split_list(actions, false) {
+        if token != "drop" {
+            res.push(parse_action(token)?);
+        }
+    }
+    Ok(res)
+}
+
+impl FromStr for FlowMod {
+    type Err = Error;
+    fn from_str(flow: &str) -> Result<Self> {
+        parse_flow_mod(flow)
+    }
+}
+
+/// formats a flow mod, fields with default values are left out
+pub fn format_flow_mod(flow_mod: &FlowMod) -> String {
+    let mut parts = Vec::new();
+    if flow_mod.table_id != 0 {
+        parts.push(format!("table={}", flow_mod.table_id));
+    }
+    if flow_mod.priority != DEFAULT_PRIORITY {
+        parts.push(format!("priority={}", flow_mod.priority));
+    }
+    if flow_mod.cookie != 0 || flow_mod.cookie_mask != 0 {
+        if flow_mod.cookie_mask != 0 {
+            parts.push(format!(
+                "cookie={:#x}/{:#x}",
+                flow_mod.cookie, flow_mod.cookie_mask
+            ));
+        } else {
+            parts.push(format!("cookie={:#x}", flow_mod.cookie));
+        }
+    }
+    if flow_mod.idle_timeout != 0 {
+        parts.push(format!("idle_timeout={}", flow_mod.idle_timeout));
+    }
+    if flow_mod.hard_timeout != 0 {
+        parts.push(format!("hard_timeout={}", flow_mod.hard_timeout));
+    }
+    if flow_mod.out_port != PortNumber::Reserved(PortNo::Any) {
+        parts.push(format!("out_port={}", format_port(&flow_mod.out_port)));
+    }
+    if flow_mod.out_group != GROUP_ANY {
+        parts.push(format!("out_group={}", format_group(&flow_mod.out_group)));
+    }
+    let flags = [
+        (FlowModFlags::SEND_FLOW_REM, "send_flow_rem"),
+        (FlowModFlags::CHECK_OVERLAP, "check_overlap"),
+        (FlowModFlags::RESET_COUNTS, "reset_counts"),
+        (FlowModFlags::NO_PKT_COUNTS, "no_packet_counts"),
+        (FlowModFlags::NO_BYT_COUNTS, "no_byte_counts"),
+    ];
+    for &(flag, name) in flags.iter() {
+        if flow_mod.flags.contains(flag) {
+            parts.push(name.to_string());
+        }
+    }
+    let mmatch = format_match(&flow_mod.mmatch);
+    if !mmatch.is_empty() {
+        parts.push(mmatch);
+    }
+    parts.push(format!(
+        "actions={}",
+        format_instructions(&flow_mod.instructions)
+    ));
+    parts.join(",")
+}
+
+/// formats match fields, an empty string matches everything
+pub fn format_match(mmatch: &Match) -> String {
+    let fields: Vec<String> = mmatch.matches().iter().map(format_tlv_match).collect();
+    fields.join(",")
+}
+
+/// formats instructions as the value of `actions=`
+pub fn format_instructions(instructions: &[InstructionHeader]) -> String {
+    let mut parts = Vec::new();
+    for instruction in instructions {
+        match instruction.payload() {
+            InstructionPayload::GotoTable(payload) => {
+                parts.push(format!("goto_table:{}", payload.table_id))
+            }
+            InstructionPayload::WriteMetaData(payload) => {
+                if payload.metadata_mask == u64::MAX {
+                    parts.push(format!("write_metadata:{:#x}", payload.metadata));
+                } else {
+                    parts.push(format!(
+                        "write_metadata:{:#x}/{:#x}",
+                        payload.metadata, payload.metadata_mask
+                    ));
+                }
+            }
+            InstructionPayload::WriteActions(payload) => parts.push(format!(
+                "write_actions({})",
+                format_actions(&payload.actions)
+            )),
+            InstructionPayload::ApplyActions(payload) => {
+                if !payload.actions.is_empty() {
+                    parts.push(format_actions(&payload.actions));
+                }
+            }
+            InstructionPayload::ClearActions(_) => parts.push("clear_actions".to_string()),
+            InstructionPayload::Meter(payload) => {
+                parts.push(format!("meter:{}", format_meter(&payload.meter_id)))
+            }
+        }
+    }
+    if parts.is_empty() {
+        return "drop".to_string();
+    }
+    parts.join(",")
+}
+
+/// formats plain actions, an empty list is formatted as `drop`
+pub fn format_actions(actions: &[ActionHeader]) -> String {
+    if actions.is_empty() {
+        return "drop".to_string();
+    }
+    let actions: Vec<String> = actions.iter().map(format_action).collect();
+    actions.join(",")
+}
+
+/// collects match fields and checks them for conflicts
+struct MatchParser {
+    matches: Vec<TlvMatch>,
+    ip_proto: Option<IpProto>,
+}
+
+impl MatchParser {
+    fn new() -> Self {
+        MatchParser {
+            matches: Vec::new(),
+            ip_proto: None,
+        }
+    }
+
+    fn add(&mut self, key: &str, value: Option<&str>, token: &str) -> Result<()> {
+        let value = match value {
+            Some(value) => value,
+            None => {
+                let (eth_type, ip_proto) = match shorthand(key) {
+                    Some(shorthand) => shorthand,
+                    None => bail!(ErrorKind::InvalidFlowSyntax(
+                        token.to_string(),
+                        "unknown keyword"
+                    )),
+                };
+                self.push(
+                    TlvMatch::new(MatchPayload::EthType(PayloadEthType { ttype: eth_type })),
+                    token,
+                )?;
+                if let Some(ip_proto) = ip_proto {
+                    self.push(
+                        TlvMatch::new(MatchPayload::IpProto(PayloadIpProto { ip_proto: ip_proto })),
+                        token,
+                    )?;
+                }
+                return Ok(());
+            }
+        };
+
+        // vlans without numeric vid
+        if key == "dl_vlan" || key == "vlan_vid" {
+            match value {
+                "none" => return self.push(VlanVid::None.into(), token),
+                "any" => return self.push(VlanVid::Any.into(), token),
+                _ => (),
+            }
+        }
+
+        // ct_state flags like +trk-est, the flags not listed are not matched
+        #[cfg(feature = "nicira")]
+        {
+            if key == "ct_state" && (value.starts_with('+') || value.starts_with('-')) {
+                return self.push(parse_ct_state_flags(value, token)?, token);
+            }
+        }
+
+        let (value, mask) = split_mask(value);
+        let payload = parse_payload(key, value, &self.ip_proto, token)?;
+        let tlv_match = match mask {
+            Some(mask) => {
+                if !is_maskable(&payload) {
+                    bail!(ErrorKind::InvalidFlowSyntax(
+                        token.to_string(),
+                        "field can not be masked"
+                    ));
+                }
+                let mask = parse_mask(&payload, key, mask, token)?;
+                TlvMatch::with_mask(payload, mask)?
+            }
+            None => TlvMatch::new(payload),
+        };
+        self.push(tlv_match, token)
+    }
+
+    fn push(&mut self, tlv_match: TlvMatch, token: &str) -> Result<()> {
+        if let MatchPayload::IpProto(ref payload) = tlv_match.payload() {
+            self.ip_proto = Some(payload.ip_proto.clone());
+        }
+        let header = &tlv_match.tlv_header;
+        let field = (header.get_oxm_class(), header.get_oxm_field());
+        for existing in &self.matches {
+            let existing_header = &existing.tlv_header;
+            if (existing_header.get_oxm_class(), existing_header.get_oxm_field()) == field {
+                if *existing == tlv_match {
+                    return Ok(());
+                }
+                bail!(ErrorKind::InvalidFlowSyntax(
+                    token.to_string(),
+                    "conflicting values for field"
+                ));
+            }
+        }
+        self.matches.push(tlv_match);
+        Ok(())
+    }
+
+    fn into_match(self) -> Match {
+        Match::new(self.matches)
+    }
+}
+
+/// ethernet type and ip protocol of protocol keywords like `tcp`
+fn shorthand(keyword: &str) -> Option<(EtherType, Option<IpProto>)> {
+    Some(match keyword {
+        "ip" => (EtherType::IPv4, None),
+        "ipv6" => (EtherType::IPv6, None),
+        "arp" => (EtherType::Arp, None),
+        "rarp" => (EtherType::ReverseAddressResolutionProtocol, None),
+        "mpls" => (EtherType::MplsUnicast, None),
+        "mplsm" => (EtherType::MplsMulticast, None),
+        "icmp" => (EtherType::IPv4, Some(IpProto::Icmp)),
+        "tcp" => (EtherType::IPv4, Some(IpProto::Tcp)),
+        "udp" => (EtherType::IPv4, Some(IpProto::Udp)),
+        "sctp" => (EtherType::IPv4, Some(IpProto::Sctp)),
+        "icmp6" => (EtherType::IPv6, Some(IpProto::IPv6Icmp)),
+        "tcp6" => (EtherType::IPv6, Some(IpProto::Tcp)),
+        "udp6" => (EtherType::IPv6, Some(IpProto::Udp)),
+        "sctp6" => (EtherType::IPv6, Some(IpProto::Sctp)),
+        _ => return None,
+    })
+}
+
+fn parse_payload(
+    name: &str,
+    value: &str,
+    ip_proto: &Option<IpProto>,
+    token: &str,
+) -> Result<MatchPayload> {
+    Ok(match name {
+        "in_port" => MatchPayload::InPort(PayloadInPort {
+            ingress_port: parse_port(value, token)?,
+        }),
+        "in_phy_port" => MatchPayload::InPhyPort(PayloadInPhyPort {
+            phy_port: parse_uint(value, 0xffff_ffff, token)? as u32,
+        }),
+        "metadata" => MatchPayload::Metadata(PayloadMetadata {
+            metadata: parse_uint(value, u64::MAX, token)?,
+        }),
+        "dl_dst" | "eth_dst" => MatchPayload::EthDst(PayloadEthDst {
+            eth_dst: parse_addr(value, token)?,
+        }),
+        "dl_src" | "eth_src" => MatchPayload::EthSrc(PayloadEthSrc {
+            eth_src: parse_addr(value, token)?,
+        }),
+        "dl_type" | "eth_type" => MatchPayload::EthType(PayloadEthType {
+            ttype: parse_ether_type(value, token)?,
+        }),
+        // dl_vlan takes the plain vid, vlan_vid the oxm value including the present bit
+        "dl_vlan" => MatchPayload::VlanVId(PayloadVlanVId {
+            vlan_id: parse_uint(value, 0xfff, token)? as u16 | VID_PRESENT,
+        }),
+        "vlan_vid" => MatchPayload::VlanVId(PayloadVlanVId {
+            vlan_id: parse_uint(value, 0x1fff, token)? as u16,
+        }),
+        "dl_vlan_pcp" | "vlan_pcp" => MatchPayload::VlanPcp(PayloadVlanPcp {
+            vlan_pcp: parse_uint(value, 0x7, token)? as u8,
+        }),
+        "ip_dscp" => MatchPayload::IpDscp(PayloadIpDscp {
+            ip_dscp: parse_uint(value, 0x3f, token)? as u8,
+        }),
+        // nw_tos contains the dscp in its upper 6 bits
+        "nw_tos" => MatchPayload::IpDscp(PayloadIpDscp {
+            ip_dscp: parse_uint(value, 0xff, token)? as u8 >> 2,
+        }),
+        "nw_ecn" | "ip_ecn" => MatchPayload::IpEcn(PayloadIpEcn {
+            ip_enc: parse_uint(value, 0x3, token)? as u8,
+        }),
+        "nw_proto" | "ip_proto" => {
+            let raw = parse_uint(value, 0xff, token)?;
+            MatchPayload::IpProto(PayloadIpProto {
+                ip_proto: IpProto::from_u64(raw)
+                    .ok_or::<Error>(ErrorKind::UnknownValue(raw, stringify!(IpProto)).into())?,
+            })
+        }
+        "nw_src" | "ip_src" | "ipv4_src" => MatchPayload::IPv4Src(PayloadIPv4Src {
+            ipv4_src: parse_addr(value, token)?,
+        }),
+        "nw_dst" | "ip_dst" | "ipv4_dst" => MatchPayload::IPv4Dst(PayloadIPv4Dst {
+            ipv4_dst: parse_addr(value, token)?,
+        }),
+        "tcp_src" => MatchPayload::TcpSrc(PayloadTcpSrc {
+            src_port: parse_uint(value, 0xffff, token)? as u16,
+        }),
+        "tcp_dst" => MatchPayload::TcpDst(PayloadTcpDst {
+            dst_port: parse_uint(value, 0xffff, token)? as u16,
+        }),
+        "udp_src" => MatchPayload::UdpSrc(PayloadUdpSrc {
+            src_port: parse_uint(value, 0xffff, token)? as u16,
+        }),
+        "udp_dst" => MatchPayload::UdpDst(PayloadUdpDst {
+            dst_port: parse_uint(value, 0xffff, token)? as u16,
+        }),
+        "sctp_src" => MatchPayload::SctpSrc(PayloadSctpSrc {
+            src_port: parse_uint(value, 0xffff, token)? as u16,
+        }),
+        "sctp_dst" => MatchPayload::SctpDst(PayloadSctpDst {
+            dst_port: parse_uint(value, 0xffff, token)? as u16,
+        }),
+        // tp_src and tp_dst depend on the ip protocol matched before
+        "tp_src" | "tp_dst" => {
+            let port_name = match ip_proto {
+                Some(IpProto::Tcp) => "tcp",
+                Some(IpProto::Udp) => "udp",
+                Some(IpProto::Sctp) => "sctp",
+                _ => bail!(ErrorKind::InvalidFlowSyntax(
+                    token.to_string(),
+                    "tp_src and tp_dst require tcp, udp or sctp"
+                )),
+            };
+            let name = format!("{}{}", port_name, &name[2..]);
+            return parse_payload(&name, value, ip_proto, token);
+        }
+        "icmp_type" | "icmpv4_type" => {
+            let raw = parse_uint(value, 0xff, token)?;
+            MatchPayload::IcmpV4TYype(PayloadIcmpV4Type {
+                ttype: IcmpType::from_u64(raw)
+                    .ok_or::<Error>(ErrorKind::UnknownValue(raw, stringify!(IcmpType)).into())?,
+            })
+        }
+        "icmp_code" | "icmpv4_code" => MatchPayload::IcmpV4Code(PayloadIcmpV4Code {
+            code: parse_uint(value, 0xff, token)? as u8,
+        }),
+        "arp_op" => {
+            let raw = parse_uint(value, 0xffff, token)?;
+            MatchPayload::ArpOp(PayloadArpOp {
+                arp_op: ArpOp::from_u64(raw)
+                    .ok_or::<Error>(ErrorKind::UnknownValue(raw, stringify!(ArpOp)).into())?,
+            })
+        }
+        "arp_spa" => MatchPayload::ArpSpa(PayloadArpSpa {
+            arp_spa: parse_addr(value, token)?,
+        }),
+        "arp_tpa" => MatchPayload::ArpTpa(PayloadArpTpa {
+            arp_tpa: parse_addr(value, token)?,
+        }),
+        "arp_sha" => MatchPayload::ArpSha(PayloadArpSha {
+            arp_sha: parse_addr(value, token)?,
+        }),
+        "arp_tha" => MatchPayload::ArpTha(PayloadArpTha {
+            arp_tha: parse_addr(value, token)?,
+        }),
+        "ipv6_src" => MatchPayload::IPv6Src(PayloadIPv6Src {
+            ipv6_src: parse_addr(value, token)?,
+        }),
+        "ipv6_dst" => MatchPayload::IPv6Dst(PayloadIPv6Dst {
+            ipv6_dst: parse_addr(value, token)?,
+        }),
+        "ipv6_label" => MatchPayload::IPv6FLabel(PayloadIPv6FLabel {
+            flabel: parse_uint(value, 0xf_ffff, token)? as u32,
+        }),
+        "icmpv6_type" => {
+            let raw = parse_uint(value, 0xff, token)?;
+            MatchPayload::IcmpV6Type(PayloadIcmpV6Type {
+                ttype: IcmpV6Type::from_u64(raw)
+                    .ok_or::<Error>(ErrorKind::UnknownValue(raw, stringify!(IcmpV6Type)).into())?,
+            })
+        }
+        "icmpv6_code" => MatchPayload::IcmpV6Code(PayloadIcmpV6Code {
+            code: parse_uint(value, 0xff, token)? as u8,
+        }),
+        "nd_target" => MatchPayload::IPv6NdTarget(PayloadIPv6NdTarget {
+            target: parse_addr(value, token)?,
+        }),
+        "nd_sll" => MatchPayload::IPv6NdSll(PayloadIPv6NdSll {
+            nd_sll: parse_addr(value, token)?,
+        }),
+        "nd_tll" => MatchPayload::IPv6NdTll(PayloadIPv6NdTll {
+            nd_tll: parse_addr(value, token)?,
+        }),
+        "mpls_label" => MatchPayload::MplsLabel(PayloadMplsLabel {
+            label: parse_uint(value, 0xf_ffff, token)? as u32,
+        }),
+        "mpls_tc" => MatchPayload::MplsTc(PayloadMplsTc {
+            tc: parse_uint(value, 0x7, token)? as u8,
+        }),
+        "mpls_bos" => MatchPayload::MplsBos(PayloadMplsBos {
+            bos: parse_uint(value, 0x1, token)? as u8,
+        }),
+        "pbb_isid" => MatchPayload::PbbISid(PayloadPbbISid {
+            i_sid: parse_uint(value, 0xff_ffff, token)? as u32,
+        }),
+        "tun_id" | "tunnel_id" => MatchPayload::TunnelId(PayloadTunnelId {
+            metadata: parse_uint(value, u64::MAX, token)?,
+        }),
+        "ipv6_exthdr" => MatchPayload::IPv6ExtHdr(
+            IPv6ExtHdrFlags::from_bits_truncate(parse_uint(value, 0x1ff, token)? as u16).into(),
+        ),
+        "packet_type" => MatchPayload::PacketType(parse_packet_type(value, token)?),
+        _ => parse_nxm_payload(name, value, token)?,
+    })
+}
+
+/// parses the fields of the NXM classes, reg0 to reg15 and the ct fields
+#[cfg(feature = "nicira")]
+fn parse_nxm_payload(name: &str, value: &str, token: &str) -> Result<MatchPayload> {
+    let field = match name {
+        "ct_state" => {
+            let raw = parse_uint(value, 0xffff_ffff, token)? as u32;
+            match nx::CtState::from_bits(raw) {
+                Some(state) => nx::NxmField::CtState(state),
+                None => bail!(ErrorKind::InvalidFlowSyntax(
+                    token.to_string(),
+                    "unknown ct_state flags"
+                )),
+            }
+        }
+        "ct_zone" => nx::NxmField::CtZone(parse_uint(value, 0xffff, token)? as u16),
+        "ct_mark" => nx::NxmField::CtMark(parse_uint(value, 0xffff_ffff, token)? as u32),
+        "ct_label" => nx::NxmField::CtLabel(parse_u128(value, token)?),
+        _ => match register(name) {
+            Some(index) => nx::NxmField::Reg {
+                index: index,
+                value: parse_uint(value, 0xffff_ffff, token)? as u32,
+            },
+            None => bail!(ErrorKind::InvalidFlowSyntax(
+                token.to_string(),
+                "unknown match field"
+            )),
+        },
+    };
+    Ok(MatchPayload::Nxm(field))
+}
+
+#[cfg(not(feature = "nicira"))]
+fn parse_nxm_payload(_name: &str, _value: &str, token: &str) -> Result<MatchPayload> {
+    bail!(ErrorKind::InvalidFlowSyntax(
+        token.to_string(),
+        "unknown match field"
+    ))
+}
+
+/// index of reg0 to reg15
+#[cfg(feature = "nicira")]
+fn register(name: &str) -> Option<u8> {
+    if !name.starts_with("reg") {
+        return None;
+    }
+    match name[3..].parse::<u8>() {
+        Ok(index) if index < 16 => Some(index),
+        _ => None,
+    }
+}
+
+/// parses ct_state flags, eg. `+trk+est-new`, into a masked match
+#[cfg(feature = "nicira")]
+fn parse_ct_state_flags(value: &str, token: &str) -> Result<TlvMatch> {
+    let mut state = nx::CtState::empty();
+    let mut mask = nx::CtState::empty();
+    let mut rest = value;
+    while !rest.is_empty() {
+        let set = rest.starts_with('+');
+        if !set && !rest.starts_with('-') {
+            bail!(ErrorKind::InvalidFlowSyntax(
+                token.to_string(),
+                "ct_state flags start with + or -"
+            ));
+        }
+        let end = rest[1..].find(['+', '-']).map_or(rest.len(), |pos| pos + 1);
+        let flag = match nx::CT_STATE_NAMES
+            .iter()
+            .find(|&&(_, name)| name == &rest[1..end])
+        {
+            Some(&(flag, _)) => flag,
+            None => bail!(ErrorKind::InvalidFlowSyntax(
+                token.to_string(),
+                "unknown ct_state flag"
+            )),
+        };
+        mask.insert(flag);
+        if set {
+            state.insert(flag);
+        }
+        rest = &rest[end..];
+    }
+    let mask = Into::<Vec<u8>>::into(nx::NxmField::CtState(mask));
+    TlvMatch::with_mask(MatchPayload::Nxm(nx::NxmField::CtState(state)), mask)
+}
+
+/// ct_state as flags, None if it is not masked or the mask has unnamed bits
+#[cfg(feature = "nicira")]
+fn format_ct_state(tlv_match: &TlvMatch) -> Option<String> {
+    let state = match tlv_match.payload() {
+        MatchPayload::Nxm(nx::NxmField::CtState(state)) => state,
+        _ => return None,
+    };
+    let mask = match tlv_match.mask() {
+        Some(mask) if mask.len() == 4 => {
+            (mask[0] as u32) << 24 | (mask[1] as u32) << 16 | (mask[2] as u32) << 8 | mask[3] as u32
+        }
+        _ => return None,
+    };
+    let mask = nx::CtState::from_bits(mask)?;
+    let mut res = String::new();
+    for &(flag, name) in nx::CT_STATE_NAMES.iter() {
+        if mask.contains(flag) {
+            res.push(if state.contains(flag) { '+' } else { '-' });
+            res.push_str(name);
+        }
+    }
+    Some(res)
+}
+
+/// parses decimal or `0x` prefixed hexadecimal 128 bit numbers
+#[cfg(feature = "nicira")]
+fn parse_u128(value: &str, token: &str) -> Result<u128> {
+    let hex = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"));
+    let parsed = match hex {
+        Some(hex) => u128::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| ErrorKind::InvalidFlowSyntax(token.to_string(), "invalid number").into())
+}
+
+/// fields that may carry a mask according to the OpenFlow spec
+fn is_maskable(payload: &MatchPayload) -> bool {
+    match payload {
+        MatchPayload::Metadata(_)
+        | MatchPayload::EthDst(_)
+        | MatchPayload::EthSrc(_)
+        | MatchPayload::VlanVId(_)
+        | MatchPayload::IPv4Src(_)
+        | MatchPayload::IPv4Dst(_)
+        | MatchPayload::ArpSpa(_)
+        | MatchPayload::ArpTpa(_)
+        | MatchPayload::ArpSha(_)
+        | MatchPayload::ArpTha(_)
+        | MatchPayload::IPv6Src(_)
+        | MatchPayload::IPv6Dst(_)
+        | MatchPayload::IPv6FLabel(_)
+        | MatchPayload::PbbISid(_)
+        | MatchPayload::TunnelId(_)
+        | MatchPayload::IPv6ExtHdr(_) => true,
+        #[cfg(feature = "nicira")]
+        MatchPayload::Nxm(nx::NxmField::CtZone(_)) => false,
+        #[cfg(feature = "nicira")]
+        MatchPayload::Nxm(_) => true,
+        _ => false,
+    }
+}
+
+/// parses a mask in the notation of its field
+/// ipv4 masks may also be given as prefix length
+fn parse_mask(payload: &MatchPayload, name: &str, mask: &str, token: &str) -> Result<Vec<u8>> {
+    let is_ipv4 = match payload {
+        MatchPayload::IPv4Src(_)
+        | MatchPayload::IPv4Dst(_)
+        | MatchPayload::ArpSpa(_)
+        | MatchPayload::ArpTpa(_) => true,
+        _ => false,
+    };
+    if is_ipv4 && !mask.contains('.') {
+        let prefix_len = parse_uint(mask, 32, token)? as u32;
+        let mask = if prefix_len == 0 {
+            0
+        } else {
+            u32::MAX << (32 - prefix_len)
+        };
+        return Ok(vec![
+            (mask >> 24) as u8,
+            (mask >> 16) as u8,
+            (mask >> 8) as u8,
+            mask as u8,
+        ]);
+    }
+    // dl_vlan adds the present bit to the value, masks are taken as is
+    let name = if name == "dl_vlan" { "vlan_vid" } else { name };
+    let mask = parse_payload(name, mask, &None, token)?;
+    Ok(mask.into())
+}
+
+fn format_tlv_match(tlv_match: &TlvMatch) -> String {
+    match VlanVid::from_match(tlv_match) {
+        Some(VlanVid::None) => return "vlan_vid=none".to_string(),
+        Some(VlanVid::Any) => return "vlan_vid=any".to_string(),
+        _ => (),
+    }
+    #[cfg(feature = "nicira")]
+    {
+        if let Some(flags) = format_ct_state(tlv_match) {
+            return format!("ct_state={}", flags);
+        }
+    }
+    let payload = tlv_match.payload();
+    let (name, value) = format_payload(payload);
+    match tlv_match.mask() {
+        Some(mask) => format!("{}={}/{}", name, value, format_mask(payload, mask)),
+        None => format!("{}={}", name, value),
+    }
+}
+
+/// name of the match field in the flow syntax, eg. "tcp_dst"
+pub fn field_name(payload: &MatchPayload) -> &'static str {
+    format_payload(payload).0
+}
+
+fn format_payload(payload: &MatchPayload) -> (&'static str, String) {
+    match payload {
+        MatchPayload::InPort(p) => ("in_port", format_port(&p.ingress_port)),
+        MatchPayload::InPhyPort(p) => ("in_phy_port", p.phy_port.to_string()),
+        MatchPayload::Metadata(p) => ("metadata", format!("{:#x}", p.metadata)),
+        MatchPayload::EthDst(p) => ("eth_dst", p.eth_dst.to_string()),
+        MatchPayload::EthSrc(p) => ("eth_src", p.eth_src.to_string()),
+        MatchPayload::EthType(p) => ("eth_type", format!("{:#06x}", p.ttype.to_u16().unwrap())),
+        MatchPayload::VlanVId(p) => ("vlan_vid", format!("{:#x}", p.vlan_id)),
+        MatchPayload::VlanPcp(p) => ("vlan_pcp", p.vlan_pcp.to_string()),
+        MatchPayload::IpDscp(p) => ("ip_dscp", p.ip_dscp.to_string()),
+        MatchPayload::IpEcn(p) => ("ip_ecn", p.ip_enc.to_string()),
+        MatchPayload::IpProto(p) => ("ip_proto", p.ip_proto.to_u8().unwrap().to_string()),
+        MatchPayload::IPv4Src(p) => ("ipv4_src", p.ipv4_src.to_string()),
+        MatchPayload::IPv4Dst(p) => ("ipv4_dst", p.ipv4_dst.to_string()),
+        MatchPayload::TcpSrc(p) => ("tcp_src", p.src_port.to_string()),
+        MatchPayload::TcpDst(p) => ("tcp_dst", p.dst_port.to_string()),
+        MatchPayload::UdpSrc(p) => ("udp_src", p.src_port.to_string()),
+        MatchPayload::UdpDst(p) => ("udp_dst", p.dst_port.to_string()),
+        MatchPayload::SctpSrc(p) => ("sctp_src", p.src_port.to_string()),
+        MatchPayload::SctpDst(p) => ("sctp_dst", p.dst_port.to_string()),
+        MatchPayload::IcmpV4TYype(p) => ("icmp_type", p.ttype.to_u8().unwrap().to_string()),
+        MatchPayload::IcmpV4Code(p) => ("icmp_code", p.code.to_string()),
+        MatchPayload::ArpOp(p) => ("arp_op", p.arp_op.to_u16().unwrap().to_string()),
+        MatchPayload::ArpSpa(p) => ("arp_spa", p.arp_spa.to_string()),
+        MatchPayload::ArpTpa(p) => ("arp_tpa", p.arp_tpa.to_string()),
+        MatchPayload::ArpSha(p) => ("arp_sha", p.arp_sha.to_string()),
+        MatchPayload::ArpTha(p) => ("arp_tha", p.arp_tha.to_string()),
+        MatchPayload::IPv6Src(p) => ("ipv6_src", p.ipv6_src.to_string()),
+        MatchPayload::IPv6Dst(p) => ("ipv6_dst", p.ipv6_dst.to_string()),
+        MatchPayload::IPv6FLabel(p) => ("ipv6_label", format!("{:#x}", p.flabel)),
+        MatchPayload::IcmpV6Type(p) => ("icmpv6_type", p.ttype.to_u8().unwrap().to_string()),
+        MatchPayload::IcmpV6Code(p) => ("icmpv6_code", p.code.to_string()),
+        MatchPayload::IPv6NdTarget(p) => ("nd_target", p.target.to_string()),
+        MatchPayload::IPv6NdSll(p) => ("nd_sll", p.nd_sll.to_string()),
+        MatchPayload::IPv6NdTll(p) => ("nd_tll", p.nd_tll.to_string()),
+        MatchPayload::MplsLabel(p) => ("mpls_label", p.label.to_string()),
+        MatchPayload::MplsTc(p) => ("mpls_tc", p.tc.to_string()),
+        MatchPayload::MplsBos(p) => ("mpls_bos", p.bos.to_string()),
+        MatchPayload::PbbISid(p) => ("pbb_isid", format!("{:#x}", p.i_sid)),
+        MatchPayload::TunnelId(p) => ("tunnel_id", format!("{:#x}", p.metadata)),
+        MatchPayload::IPv6ExtHdr(p) => ("ipv6_exthdr", format!("{:#x}", p.ext_hdr_flags.bits())),
+        MatchPayload::PacketType(p) => {
+            ("packet_type", format!("({},{:#x})", p.namespace, p.ns_type))
+        }
+        MatchPayload::Experimenter(p) => match p.field_name() {
+            Some(name) => (name, format_hex(&p.data[..])),
+            None => (
+                "experimenter",
+                format!("{:#x}:{}:{}", p.experimenter, p.field, format_hex(&p.data[..])),
+            ),
+        },
+        #[cfg(feature = "nicira")]
+        MatchPayload::Nxm(field) => match (field.name(), field) {
+            (Some(name), nx::NxmField::Reg { value, .. }) => (name, format!("{:#x}", value)),
+            (Some(name), nx::NxmField::TunId(tun_id)) => (name, format!("{:#x}", tun_id)),
+            (Some(name), nx::NxmField::CtState(state)) => (name, format!("{:#x}", state.bits())),
+            (Some(name), nx::NxmField::CtZone(zone)) => (name, zone.to_string()),
+            (Some(name), nx::NxmField::CtMark(mark)) => (name, format!("{:#x}", mark)),
+            (Some(name), nx::NxmField::CtLabel(label)) => (name, format!("{:#x}", label)),
+            (_, field) => (
+                "nxm",
+                format!(
+                    "{:#x}:{}:{}",
+                    field.class(),
+                    field.field(),
+                    format_hex(&Into::<Vec<u8>>::into(field.clone())[..])
+                ),
+            ),
+        },
+    }
+}
+
+fn format_mask(payload: &MatchPayload, mask: &[u8]) -> String {
+    let formatted = match payload {
+        MatchPayload::IPv4Src(_)
+        | MatchPayload::IPv4Dst(_)
+        | MatchPayload::ArpSpa(_)
+        | MatchPayload::ArpTpa(_) => Ipv4Addr::from_slice(mask).map(|addr| addr.to_string()),
+        MatchPayload::EthDst(_)
+        | MatchPayload::EthSrc(_)
+        | MatchPayload::ArpSha(_)
+        | MatchPayload::ArpTha(_) => MacAddr::from_slice(mask).map(|addr| addr.to_string()),
+        MatchPayload::IPv6Src(_) | MatchPayload::IPv6Dst(_) => {
+            Ipv6Addr::from_slice(mask).map(|addr| addr.to_string())
+        }
+        _ => Ok(format_hex(mask)),
+    };
+    formatted.unwrap_or_else(|_| format_hex(mask))
+}
+
+fn parse_action(token: &str) -> Result<ActionHeader> {
+    let (name, value) = split_action(token);
+    let payload = match (name.to_lowercase().as_ref(), value) {
+        ("output", Some(value)) => ActionPayload::Output(output_to(parse_port(value, token)?)),
+        ("controller", None) => ActionPayload::Output(actions::PayloadOutput {
+            port: PortNo::Controller.into(),
+            max_len: CONTROLLER_MAX_LEN,
+        }),
+        ("controller", Some(value)) => ActionPayload::Output(actions::PayloadOutput {
+            port: PortNo::Controller.into(),
+            max_len: parse_uint(value, 0xffff, token)? as u16,
+        }),
+        ("group", Some(value)) => ActionPayload::Group(actions::PayloadGroup {
+            group_id: parse_group(value, token)?,
+        }),
+        ("set_queue", Some(value)) => ActionPayload::SetQueue(actions::PayloadSetQueue {
+            queue_id: parse_uint(value, 0xffff_ffff, token)? as u32,
+        }),
+        ("set_mpls_ttl", Some(value)) => ActionPayload::SetMplsTtl(actions::PayloadSetMplsTtl {
+            mpls_ttl: parse_uint(value, 0xff, token)? as u8,
+        }),
+        ("dec_mpls_ttl", None) => ActionPayload::DecMplsTtl(actions::PayloadDecMplsTtl {}),
+        ("set_nw_ttl", Some(value)) => ActionPayload::SetNwTtl(actions::PayloadSetNwTtl {
+            nw_ttl: parse_uint(value, 0xff, token)? as u8,
+        }),
+        ("dec_ttl", None) => ActionPayload::DecNwTtl(actions::PayloadDecNwTtl {}),
+        ("copy_ttl_out", None) => ActionPayload::CopyTtlOut(actions::PayloadCopyTtlOut {}),
+        ("copy_ttl_in", None) => ActionPayload::CopyTtlIn(actions::PayloadCopyTtlIn {}),
+        ("push_vlan", Some(value)) => ActionPayload::PushVlan(actions::PayloadPushVlan {
+            ethertype: parse_ether_type(value, token)?,
+        }),
+        ("pop_vlan", None) => ActionPayload::PopVlan(actions::PayloadPopVlan {}),
+        ("push_mpls", Some(value)) => ActionPayload::PushMpls(actions::PayloadPushMpls {
+            ethertype: parse_ether_type(value, token)?,
+        }),
+        ("pop_mpls", Some(value)) => ActionPayload::PopMpls(actions::PayloadPopMpls {
+            ethertype: parse_ether_type(value, token)?,
+        }),
+        ("push_pbb", Some(value)) => ActionPayload::PushPbb(actions::PayloadPushPbb {
+            ethertype: parse_ether_type(value, token)?,
+        }),
+        ("pop_pbb", None) => ActionPayload::PopPbb(actions::PayloadPopPbb {}),
+        #[cfg(feature = "nicira")]
+        ("ct", value) => ActionPayload::Nicira(nx::NxAction::Conntrack(parse_ct(value, token)?)),
+        ("set_field", Some(value)) => {
+            let arrow = match value.find("->") {
+                Some(arrow) => arrow,
+                None => bail!(ErrorKind::InvalidFlowSyntax(
+                    token.to_string(),
+                    "set_field requires 'value->field'"
+                )),
+            };
+            let field = &value[arrow + 2..];
+            let payload = parse_payload(field, &value[..arrow], &None, token)?;
+            ActionPayload::SetField(actions::PayloadSetField::new(payload)?)
+        }
+        // plain port numbers and reserved port names are outputs
+        (_, None) => ActionPayload::Output(output_to(parse_port(name, token)?)),
+        _ => bail!(ErrorKind::InvalidFlowSyntax(
+            token.to_string(),
+            "unknown action"
+        )),
+    };
+    Ok(payload.into())
+}
+
+/// parses the arguments of `ct(commit,zone=1,table=2,alg=ftp,exec(...))`
+#[cfg(feature = "nicira")]
+fn parse_ct(args: Option<&str>, token: &str) -> Result<nx::NxConntrack> {
+    let mut ct = nx::NxConntrack::new(0);
+    for arg in split_list(args.unwrap_or(""), false) {
+        if arg.starts_with("exec(") && arg.ends_with(')') {
+            ct.actions = parse_actions(&arg["exec(".len()..arg.len() - 1])?;
+            continue;
+        }
+        match split_key_value(arg) {
+            ("commit", None) => ct.flags.insert(nx::CtFlags::COMMIT),
+            ("force", None) => ct.flags.insert(nx::CtFlags::FORCE),
+            ("zone", Some(value)) => ct.zone = parse_uint(value, 0xffff, token)? as u16,
+            ("table", Some(value)) => ct.recirc_table = Some(parse_uint(value, 0xfe, token)? as u8),
+            ("alg", Some("ftp")) => ct.alg = 21,
+            ("alg", Some("tftp")) => ct.alg = 69,
+            ("alg", Some(value)) => ct.alg = parse_uint(value, 0xffff, token)? as u16,
+            _ => bail!(ErrorKind::InvalidFlowSyntax(
+                token.to_string(),
+                "unknown ct argument"
+            )),
+        }
+    }
+    Ok(ct)
+}
+
+fn output_to(port: PortNumber) -> actions::PayloadOutput {
+    let max_len = match port {
+        PortNumber::Reserved(PortNo::Controller) => CONTROLLER_MAX_LEN,
+        _ => 0,
+    };
+    actions::PayloadOutput {
+        port: port,
+        max_len: max_len,
+    }
+}
+
+fn format_action(action: &ActionHeader) -> String {
+    match action.payload() {
+        ActionPayload::Output(payload) => match payload.port {
+            PortNumber::Reserved(PortNo::Controller) => {
+                if payload.max_len == CONTROLLER_MAX_LEN {
+                    "CONTROLLER".to_string()
+                } else {
+                    format!("CONTROLLER:{}", payload.max_len)
+                }
+            }
+            PortNumber::Reserved(_) => format_port(&payload.port),
+            PortNumber::NormalPort(port_no) => format!("output:{}", port_no),
+        },
+        ActionPayload::Group(payload) => format!("group:{}", format_group(&payload.group_id)),
+        ActionPayload::SetQueue(payload) => format!("set_queue:{}", payload.queue_id),
+        ActionPayload::SetMplsTtl(payload) => format!("set_mpls_ttl:{}", payload.mpls_ttl),
+        ActionPayload::DecMplsTtl(_) => "dec_mpls_ttl".to_string(),
+        ActionPayload::SetNwTtl(payload) => format!("set_nw_ttl:{}", payload.nw_ttl),
+        ActionPayload::DecNwTtl(_) => "dec_ttl".to_string(),
+        ActionPayload::CopyTtlOut(_) => "copy_ttl_out".to_string(),
+        ActionPayload::CopyTtlIn(_) => "copy_ttl_in".to_string(),
+        ActionPayload::PushVlan(payload) => {
+            format!("push_vlan:{:#06x}", payload.ethertype.to_u16().unwrap())
+        }
+        ActionPayload::PopVlan(_) => "pop_vlan".to_string(),
+        ActionPayload::PushMpls(payload) => {
+            format!("push_mpls:{:#06x}", payload.ethertype.to_u16().unwrap())
+        }
+        ActionPayload::PopMpls(payload) => {
+            format!("pop_mpls:{:#06x}", payload.ethertype.to_u16().unwrap())
+        }
+        ActionPayload::PushPbb(payload) => {
+            format!("push_pbb:{:#06x}", payload.ethertype.to_u16().unwrap())
+        }
+        ActionPayload::PopPbb(_) => "pop_pbb".to_string(),
+        ActionPayload::SetField(payload) => {
+            let (name, value) = format_payload(payload.field.payload());
+            format!("set_field:{}->{}", value, name)
+        }
+        ActionPayload::Experimenter(payload) => format!(
+            "experimenter:{:#x}:{}",
+            payload.experimenter,
+            format_hex(&payload.data[..])
+        ),
+        #[cfg(feature = "nicira")]
+        ActionPayload::Nicira(action) => format_nx_action(action),
+    }
+}
+
+#[cfg(feature = "nicira")]
+fn format_nx_action(action: &nx::NxAction) -> String {
+    match action {
+        nx::NxAction::Resubmit { in_port, table } => {
+            let in_port = if *in_port == 0xfff8 {
+                String::new()
+            } else {
+                in_port.to_string()
+            };
+            match table {
+                Some(table) => format!("resubmit({},{})", in_port, table),
+                None => format!("resubmit:{}", in_port),
+            }
+        }
+        nx::NxAction::Conjunction {
+            id,
+            clause,
+            n_clauses,
+        } => format!("conjunction({},{}/{})", id, clause + 1, n_clauses),
+        nx::NxAction::Conntrack(ct) => {
+            let mut args = Vec::new();
+            if ct.flags.contains(nx::CtFlags::COMMIT) {
+                args.push("commit".to_string());
+            }
+            if ct.flags.contains(nx::CtFlags::FORCE) {
+                args.push("force".to_string());
+            }
+            // zones read from a field are shown raw
+            if ct.zone_src != 0 {
+                args.push(format!("zone_src={:#x}:{:#x}", ct.zone_src, ct.zone));
+            } else if ct.zone != 0 {
+                args.push(format!("zone={}", ct.zone));
+            }
+            if let Some(table) = ct.recirc_table {
+                args.push(format!("table={}", table));
+            }
+            if ct.alg != 0 {
+                args.push(format!("alg={}", ct.alg));
+            }
+            if !ct.actions.is_empty() {
+                args.push(format!("exec({})", format_actions(&ct.actions)));
+            }
+            if args.is_empty() {
+                "ct".to_string()
+            } else {
+                format!("ct({})", args.join(","))
+            }
+        }
+        nx::NxAction::Learn(learn) => format!(
+            "learn(table={},priority={},idle_timeout={},hard_timeout={},specs={})",
+            learn.table_id,
+            learn.priority,
+            learn.idle_timeout,
+            learn.hard_timeout,
+            format_hex(&learn.specs[..])
+        ),
+        nx::NxAction::Other { subtype, data } => {
+            format!("nx:{}:{}", subtype, format_hex(&data[..]))
+        }
+    }
+}
+
+/// splits `fields,actions=...` into both parts, actions always come last
+fn split_actions(flow: &str) -> (&str, Option<&str>) {
+    let mut search_from = 0;
+    while let Some(pos) = flow[search_from..].find("actions=") {
+        let pos = search_from + pos;
+        let at_boundary = flow[..pos]
+            .chars()
+            .last()
+            .is_none_or(|c| c == ',' || c.is_whitespace());
+        if at_boundary {
+            return (&flow[..pos], Some(&flow[pos + "actions=".len()..]));
+        }
+        search_from = pos + "actions=".len();
+    }
+    (flow, None)
+}
+
+/// splits at commas (and whitespace if requested) outside of parentheses
+fn split_list(list: &str, split_whitespace: bool) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in list.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                res.push(&list[start..i]);
+                start = i + 1;
+            }
+            c if split_whitespace && depth == 0 && c.is_whitespace() => {
+                res.push(&list[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    res.push(&list[start..]);
+    res.into_iter()
+        .map(|token| token.trim())
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+fn split_key_value(token: &str) -> (&str, Option<&str>) {
+    match token.find('=') {
+        Some(pos) => (&token[..pos], Some(&token[pos + 1..])),
+        None => (token, None),
+    }
+}
+
+/// actions use `name:value`, ovs-ofctl also accepts `name(value)`
+fn split_action(token: &str) -> (&str, Option<&str>) {
+    if token.ends_with(')') {
+        if let Some(pos) = token.find('(') {
+            return (&token[..pos], Some(&token[pos + 1..token.len() - 1]));
+        }
+    }
+    match token.find(':') {
+        Some(pos) => (&token[..pos], Some(&token[pos + 1..])),
+        None => (token, None),
+    }
+}
+
+fn split_mask(value: &str) -> (&str, Option<&str>) {
+    match value.find('/') {
+        Some(pos) => (&value[..pos], Some(&value[pos + 1..])),
+        None => (value, None),
+    }
+}
+
+/// parses decimal or `0x` prefixed hexadecimal numbers up to `max`
+fn parse_uint(value: &str, max: u64, token: &str) -> Result<u64> {
+    let hex = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"));
+    let parsed = match hex {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    match parsed {
+        Ok(num) if num <= max => Ok(num),
+        Ok(_) => bail!(ErrorKind::InvalidFlowSyntax(
+            token.to_string(),
+            "number out of range"
+        )),
+        Err(_) => bail!(ErrorKind::InvalidFlowSyntax(
+            token.to_string(),
+            "invalid number"
+        )),
+    }
+}
+
+/// parses `(namespace,type)` packet types as written by ovs-ofctl
+fn parse_packet_type(value: &str, token: &str) -> Result<PayloadPacketType> {
+    let inner = if value.starts_with('(') && value.ends_with(')') {
+        &value[1..value.len() - 1]
+    } else {
+        bail!(ErrorKind::InvalidFlowSyntax(
+            token.to_string(),
+            "expected (namespace,type)"
+        ))
+    };
+    match inner.find(',') {
+        Some(pos) => Ok(PayloadPacketType {
+            namespace: parse_uint(inner[..pos].trim(), 0xffff, token)? as u16,
+            ns_type: parse_uint(inner[pos + 1..].trim(), 0xffff, token)? as u16,
+        }),
+        None => bail!(ErrorKind::InvalidFlowSyntax(
+            token.to_string(),
+            "expected (namespace,type)"
+        )),
+    }
+}
+
+fn parse_port(value: &str, token: &str) -> Result<PortNumber> {
+    value
+        .parse()
+        .map_err(|_| ErrorKind::InvalidFlowSyntax(token.to_string(), "invalid port").into())
+}
+
+/// reserved ports are written in upper case
+fn format_port(port: &PortNumber) -> String {
+    port.to_string().to_uppercase()
+}
+
+fn parse_group(value: &str, token: &str) -> Result<GroupNumber> {
+    value
+        .parse()
+        .map_err(|_| ErrorKind::InvalidFlowSyntax(token.to_string(), "invalid group").into())
+}
+
+/// reserved groups are written in upper case like ports
+fn format_group(group: &GroupNumber) -> String {
+    group.to_string().to_uppercase()
+}
+
+fn parse_meter(value: &str, token: &str) -> Result<MeterId> {
+    value
+        .parse()
+        .map_err(|_| ErrorKind::InvalidFlowSyntax(token.to_string(), "invalid meter").into())
+}
+
+/// reserved meters are written in upper case like ports
+fn format_meter(meter: &MeterId) -> String {
+    meter.to_string().to_uppercase()
+}
+
+fn parse_ether_type(value: &str, token: &str) -> Result<EtherType> {
+    let raw = parse_uint(value, 0xffff, token)?;
+    EtherType::from_u64(raw)
+        .ok_or::<Error>(ErrorKind::UnknownValue(raw, stringify!(EtherType)).into())
+}
+
+/// parses addresses in their usual notation
+fn parse_addr<T: FromStr<Err = Error>>(value: &str, token: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| ErrorKind::InvalidFlowSyntax(token.to_string(), "invalid address").into())
+}
+
+fn format_hex(bytes: &[u8]) -> String {
+    let mut res = "0x".to_string();
+    for byte in bytes {
+        write!(res, "{:02x}", byte).unwrap();
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_format() {
+        let flow = "table=0,priority=100,in_port=1,ip,nw_dst=10.0.0.1,actions=output:2";
+        let flow_mod = parse_flow_mod(flow).unwrap();
+        assert_eq!(flow_mod.table_id, 0);
+        assert_eq!(flow_mod.priority, 100);
+        assert_eq!(flow_mod.mmatch.matches().len(), 3);
+        assert_eq!(flow_mod.instructions.len(), 1);
+        assert_eq!(
+            format_flow_mod(&flow_mod),
+            "priority=100,in_port=1,eth_type=0x0800,ipv4_dst=10.0.0.1,actions=output:2"
+        );
+
+        let reparsed: FlowMod = format_flow_mod(&flow_mod).parse().unwrap();
+        assert_eq!(reparsed, flow_mod);
+    }
+
+    #[test]
+    fn parse_instructions_and_masks() {
+        let flow = "cookie=0x10/0xff tcp,tp_dst=80,nw_src=192.168.0.0/16,\
+                    actions=goto_table:2,set_field:00:00:00:00:00:01->eth_dst,\
+                    write_actions(CONTROLLER:128),pop_vlan,FLOOD";
+        let flow_mod = parse_flow_mod(flow).unwrap();
+        assert_eq!(flow_mod.cookie_mask, 0xff);
+        assert_eq!(
+            format_match(&flow_mod.mmatch),
+            "eth_type=0x0800,ip_proto=6,tcp_dst=80,ipv4_src=192.168.0.0/255.255.0.0"
+        );
+        assert_eq!(
+            format_instructions(&flow_mod.instructions),
+            "set_field:00:00:00:00:00:01->eth_dst,pop_vlan,FLOOD,\
+             write_actions(CONTROLLER:128),goto_table:2"
+        );
+
+        let bytes: Vec<u8> = flow_mod.instructions[0].clone().into();
+        assert_eq!(bytes.len() as u16, *flow_mod.instructions[0].len());
+        assert_eq!(bytes.len() % 8, 0);
+    }
+
+    #[test]
+    fn parse_packet_types() {
+        let flow_mod = parse_flow_mod("packet_type=(1,0x800),actions=drop").unwrap();
+        assert_eq!(
+            flow_mod.mmatch.get::<PayloadPacketType>(),
+            Some(&PayloadPacketType::ethertype(0x800))
+        );
+        assert_eq!(format_match(&flow_mod.mmatch), "packet_type=(1,0x800)");
+        assert!(parse_flow_mod("packet_type=1,actions=drop").is_err());
+    }
+
+    #[test]
+    fn parse_vlan_vids() {
+        let flow_mod = parse_flow_mod("dl_vlan=10,actions=drop").unwrap();
+        assert_eq!(
+            VlanVid::from_match(&flow_mod.mmatch.matches()[0]),
+            Some(VlanVid::Vid(10))
+        );
+        assert_eq!(format_match(&flow_mod.mmatch), "vlan_vid=0x100a");
+
+        for vid in &["none", "any"] {
+            let flow = format!("vlan_vid={},actions=drop", vid);
+            let flow_mod = parse_flow_mod(&flow).unwrap();
+            assert_eq!(format_flow_mod(&flow_mod), flow);
+        }
+        let flow_mod = parse_flow_mod("vlan_vid=any,actions=drop").unwrap();
+        assert_eq!(flow_mod.mmatch.matches()[0], VlanVid::Any.into());
+        assert!(VlanVid::vid(0x1000).is_err());
+    }
+
+    #[test]
+    fn parse_addresses() {
+        let flow = "eth_dst=aa:bb:cc:dd:ee:ff,eth_type=0x86dd,ipv6_src=fe80::1/ffff:ffff::,\
+                    actions=set_field:2001:db8::2->ipv6_dst";
+        let flow_mod = parse_flow_mod(flow).unwrap();
+        assert_eq!(format_flow_mod(&flow_mod), flow);
+        match flow_mod.mmatch.matches()[2].payload() {
+            MatchPayload::IPv6Src(payload) => {
+                assert_eq!(payload.ipv6_src.octets()[0], 0xfe);
+                assert_eq!(payload.ipv6_src.octets()[15], 0x01);
+            }
+            payload => panic!("unexpected payload {:?}", payload),
+        }
+        assert!(parse_flow_mod("eth_dst=aa:bb:cc:dd:ee,actions=drop").is_err());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse_flow_mod("in_port=1,foo=2,actions=drop").is_err());
+        assert!(parse_flow_mod("tp_dst=80,actions=drop").is_err());
+        assert!(parse_flow_mod("tcp,udp,actions=drop").is_err());
+        assert!(parse_flow_mod("in_port=1/1,actions=drop").is_err());
+        assert!(parse_flow_mod("priority=70000,actions=drop").is_err());
+        assert!(parse_flow_mod("actions=bogus:1").is_err());
+    }
+
+    #[test]
+    fn typed_set_fields() {
+        // vlan_vid is the raw field, the present bit (0x1000) has to be included
+        let parsed =
+            parse_actions("set_field:00:00:00:00:00:01->eth_dst,set_field:4101->vlan_vid").unwrap();
+        let typed: Vec<ActionHeader> = vec![
+            actions::PayloadSetField::eth_dst("00:00:00:00:00:01".parse().unwrap()).into(),
+            actions::PayloadSetField::vlan_vid(5).unwrap().into(),
+        ];
+        assert_eq!(parsed, typed);
+        assert!(actions::PayloadSetField::vlan_vid(0x1000).is_err());
+        assert!(actions::PayloadSetField::ip_dscp(64).is_err());
+        assert!(parse_actions("set_field:1->in_port").is_err());
+    }
+
+    #[cfg(feature = "nicira")]
+    #[test]
+    fn parse_conntrack_flows() {
+        let flow = "table=1,priority=10,ip,ct_state=+trk+new-est,ct_zone=5,ct_mark=0x1/0xff,\
+                    actions=ct(commit,zone=5,table=2,exec(set_field:0x2->ct_mark))";
+        let flow_mod = parse_flow_mod(flow).unwrap();
+        assert_eq!(
+            format_flow_mod(&flow_mod),
+            "table=1,priority=10,eth_type=0x0800,ct_state=+new-est+trk,ct_zone=5,\
+             ct_mark=0x1/0x000000ff,actions=ct(commit,zone=5,table=2,exec(set_field:0x2->ct_mark))"
+        );
+        let reparsed: FlowMod = format_flow_mod(&flow_mod).parse().unwrap();
+        assert_eq!(reparsed, flow_mod);
+
+        // reg0 and in_port are both field 0
+        let mmatch = parse_match("in_port=1,reg0=0x10,ct_label=0x1/0x1").unwrap();
+        assert_eq!(mmatch.matches().len(), 3);
+        assert!(parse_match("ct_state=+trk+foo").is_err());
+        assert!(parse_match("ct_zone=1/0xff").is_err());
+    }
+}
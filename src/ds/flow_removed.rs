@@ -2,53 +2,120 @@ use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use num_traits::{FromPrimitive, ToPrimitive};
 use std::convert::{Into, TryFrom};
 use std::io::Cursor;
+use std::time::Duration;
 
 use super::flow_match::Match;
+use super::checked_slice;
 
 use super::super::err::*;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Getters, Debug, PartialEq, Clone)]
 pub struct FlowRemoved {
+    #[get = "pub"]
     cookie: u64,
 
+    #[get = "pub"]
     priority: u16,
+    #[get = "pub"]
     reason: FlowRemovedReason,
+    #[get = "pub"]
     table_id: u8,
 
+    #[get = "pub"]
     duration_sec: u32,
+    #[get = "pub"]
     duration_nsec: u32,
 
+    #[get = "pub"]
     idle_timeout: u16,
+    #[get = "pub"]
     hard_timeout: u16,
 
+    #[get = "pub"]
     packet_count: u64,
+    #[get = "pub"]
     byte_count: u64,
 
+    #[get = "pub"]
     mmatch: Match,
 }
 
+impl FlowRemoved {
+    /// creates the notification of a removed flow that never matched a packet
+    pub fn new(
+        cookie: u64,
+        priority: u16,
+        reason: FlowRemovedReason,
+        table_id: u8,
+        mmatch: Match,
+    ) -> Self {
+        FlowRemoved {
+            cookie: cookie,
+            priority: priority,
+            reason: reason,
+            table_id: table_id,
+            duration_sec: 0,
+            duration_nsec: 0,
+            idle_timeout: 0,
+            hard_timeout: 0,
+            packet_count: 0,
+            byte_count: 0,
+            mmatch: mmatch,
+        }
+    }
+
+    /// time the flow was alive
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration_sec = duration.as_secs() as u32;
+        self.duration_nsec = duration.subsec_nanos();
+        self
+    }
+
+    /// timeouts of the original flow mod in seconds
+    pub fn with_timeouts(mut self, idle_timeout: u16, hard_timeout: u16) -> Self {
+        self.idle_timeout = idle_timeout;
+        self.hard_timeout = hard_timeout;
+        self
+    }
+
+    pub fn with_counts(mut self, packet_count: u64, byte_count: u64) -> Self {
+        self.packet_count = packet_count;
+        self.byte_count = byte_count;
+        self
+    }
+
+    /// time the flow was alive
+    pub fn duration(&self) -> Duration {
+        Duration::new(self.duration_sec as u64, self.duration_nsec)
+    }
+}
+
 impl<'a> TryFrom<&'a [u8]> for FlowRemoved {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let cookie = cursor.read_u64::<BigEndian>().unwrap();
-        let priority = cursor.read_u16::<BigEndian>().unwrap();
-        let reason_raw = cursor.read_u8().unwrap();
+        let cookie = cursor.read_u64::<BigEndian>()?;
+        let priority = cursor.read_u16::<BigEndian>()?;
+        let reason_raw = cursor.read_u8()?;
         let reason = FlowRemovedReason::from_u8(reason_raw).ok_or::<Error>(
             ErrorKind::UnknownValue(reason_raw as u64, stringify!(FlowRemovedReason)).into(),
         )?;
-        let table_id = cursor.read_u8().unwrap();
-        let duration_sec = cursor.read_u32::<BigEndian>().unwrap();
-        let duration_nsec = cursor.read_u32::<BigEndian>().unwrap();
-        let idle_timeout = cursor.read_u16::<BigEndian>().unwrap();
-        let hard_timeout = cursor.read_u16::<BigEndian>().unwrap();
-        let packet_count = cursor.read_u64::<BigEndian>().unwrap();
-        let byte_count = cursor.read_u64::<BigEndian>().unwrap();
+        let table_id = cursor.read_u8()?;
+        let duration_sec = cursor.read_u32::<BigEndian>()?;
+        let duration_nsec = cursor.read_u32::<BigEndian>()?;
+        let idle_timeout = cursor.read_u16::<BigEndian>()?;
+        let hard_timeout = cursor.read_u16::<BigEndian>()?;
+        let packet_count = cursor.read_u64::<BigEndian>()?;
+        let byte_count = cursor.read_u64::<BigEndian>()?;
 
         let mmatch_slice_len = Match::read_len(&mut cursor)?;
-        let mmatch_slice =
-            &bytes[cursor.position() as usize..cursor.position() as usize + mmatch_slice_len];
-        let mmatch = Match::try_from(mmatch_slice)?;
+        let mmatch_slice = checked_slice(
+            bytes,
+            cursor.position() as usize,
+            mmatch_slice_len,
+            stringify!(Match),
+        )?;
+        let mmatch = Match::try_from(mmatch_slice).within(cursor.position() as usize)?;
 
         Ok(FlowRemoved {
             cookie: cookie,
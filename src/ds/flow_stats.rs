@@ -0,0 +1,552 @@
+//! Individual flow statistics (multipart type Flow).
+//!
+//! The request is the same in every version. Replies contain `FlowStats`
+//! entries up to OpenFlow 1.4, OpenFlow 1.5 replaced them with `FlowDesc`
+//! entries that carry their counters as a list of OXS fields.
+
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use num_traits::FromPrimitive;
+use std::convert::{Into, TryFrom};
+use std::io::{Cursor, Seek, SeekFrom};
+use std::time::Duration;
+
+use super::flow_instructions::{self, InstructionHeader};
+use super::flow_match::Match;
+use super::flow_mod::{FlowMod, FlowModFlags};
+use super::group_mod::{GroupNumber, GROUP_ANY};
+use super::ports::{PortNo, PortNumber};
+use super::table_mod::TABLE_ALL;
+use super::tlv::{encode_list, pad_to, LengthField, TlvCursor};
+use super::{checked_slice, checked_tail};
+
+use super::super::err::*;
+
+/// Length of a flow stats request without the match (32 bytes).
+pub const FLOW_STATS_REQUEST_LENGTH: usize = 32;
+/// Length of a flow stats entry without match and instructions (48 bytes).
+pub const FLOW_STATS_LENGTH: usize = 48;
+/// Length of a flow description without match, stats and instructions (24 bytes).
+pub const FLOW_DESC_LENGTH: usize = 24;
+/// The entries of a reply start with their u16 length.
+pub const FLOW_STATS_LEN_FIELD: LengthField = LengthField::u16_at(0, FLOW_STATS_LENGTH);
+/// The flow descriptions of a reply start with their u16 length.
+pub const FLOW_DESC_LEN_FIELD: LengthField = LengthField::u16_at(0, FLOW_DESC_LENGTH);
+/// Length of the reserved and length fields at the start of the stats (4 bytes).
+pub const STATS_HEADER_LENGTH: usize = 4;
+/// Stats are padded to a multiple of 8 bytes, the length excludes the padding.
+pub const STATS_LEN_FIELD: LengthField = LengthField::u16_at(2, STATS_HEADER_LENGTH).padded_to(8);
+/// The last byte of an oxs header is the length of the payload.
+pub const OXS_LEN_FIELD: LengthField = LengthField::u8_at(3, 4);
+/// Class of the OpenFlow basic statistics.
+pub const OXS_CLASS_OPENFLOW_BASIC: u16 = 0x8002;
+
+/// Requests the statistics of the flows matching the fields of the request.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FlowStatsRequest {
+    /// table to read, or TABLE_ALL
+    pub table_id: u8,
+    // pad 3 bytes
+    /// only flows that output to this port, PortNo::Any for all
+    pub out_port: PortNumber,
+    /// only flows that output to this group, GROUP_ANY for all
+    pub out_group: GroupNumber,
+    // pad 4 bytes
+    pub cookie: u64,
+    /// only the bits of the cookie set in the mask have to match
+    pub cookie_mask: u64,
+    pub mmatch: Match,
+}
+
+impl FlowStatsRequest {
+    /// requests all flows of all tables
+    pub fn all() -> Self {
+        FlowStatsRequest {
+            table_id: TABLE_ALL,
+            out_port: PortNumber::Reserved(PortNo::Any),
+            out_group: GROUP_ANY,
+            cookie: 0,
+            cookie_mask: 0,
+            mmatch: Match::new(Vec::new()),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for FlowStatsRequest {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(bytes);
+        let table_id = cursor.read_u8()?;
+        cursor.seek(SeekFrom::Current(3)).unwrap(); // pad 3 bytes
+        let out_port = PortNumber::try_from(cursor.read_u32::<BigEndian>()?)?;
+        let out_group = GroupNumber::try_from(cursor.read_u32::<BigEndian>()?)?;
+        cursor.seek(SeekFrom::Current(4)).unwrap(); // pad 4 bytes
+        let cookie = cursor.read_u64::<BigEndian>()?;
+        let cookie_mask = cursor.read_u64::<BigEndian>()?;
+        let mmatch = read_match(bytes, &mut cursor)?;
+        Ok(FlowStatsRequest {
+            table_id: table_id,
+            out_port: out_port,
+            out_group: out_group,
+            cookie: cookie,
+            cookie_mask: cookie_mask,
+            mmatch: mmatch,
+        })
+    }
+}
+
+impl Into<Vec<u8>> for FlowStatsRequest {
+    fn into(self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.write_u8(self.table_id).unwrap();
+        res.extend_from_slice(&[0; 3]); // pad 3 bytes
+        res.write_u32::<BigEndian>(self.out_port.into()).unwrap();
+        res.write_u32::<BigEndian>(self.out_group.into()).unwrap();
+        res.write_u32::<BigEndian>(0).unwrap(); // pad 4 bytes
+        res.write_u64::<BigEndian>(self.cookie).unwrap();
+        res.write_u64::<BigEndian>(self.cookie_mask).unwrap();
+        res.extend_from_slice(&Into::<Vec<u8>>::into(self.mmatch)[..]);
+        res
+    }
+}
+
+/// Statistics of a single flow (OpenFlow 1.3 and 1.4).
+#[derive(Debug, PartialEq, Clone)]
+pub struct FlowStats {
+    // length u16
+    pub table_id: u8,
+    // pad 1 byte
+    pub duration_sec: u32,
+    pub duration_nsec: u32,
+    pub priority: u16,
+    pub idle_timeout: u16,
+    pub hard_timeout: u16,
+    pub flags: FlowModFlags,
+    // pad 4 bytes
+    pub cookie: u64,
+    pub packet_count: u64,
+    pub byte_count: u64,
+    pub mmatch: Match,
+    pub instructions: Vec<InstructionHeader>,
+}
+
+impl FlowStats {
+    /// time the flow has been alive
+    pub fn duration(&self) -> Duration {
+        Duration::new(self.duration_sec as u64, self.duration_nsec)
+    }
+
+    /// flow mod that adds the flow as it is installed, without its counters
+    pub fn flow_mod(&self) -> FlowMod {
+        FlowMod {
+            cookie: self.cookie,
+            idle_timeout: self.idle_timeout,
+            hard_timeout: self.hard_timeout,
+            flags: self.flags,
+            ..FlowMod::add(
+                self.table_id,
+                self.priority,
+                self.mmatch.clone(),
+                self.instructions.clone(),
+            )
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for FlowStats {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(bytes);
+        cursor.seek(SeekFrom::Current(2)).unwrap(); // length, checked by the list
+        let table_id = cursor.read_u8()?;
+        cursor.seek(SeekFrom::Current(1)).unwrap(); // pad 1 byte
+        let duration_sec = cursor.read_u32::<BigEndian>()?;
+        let duration_nsec = cursor.read_u32::<BigEndian>()?;
+        let priority = cursor.read_u16::<BigEndian>()?;
+        let idle_timeout = cursor.read_u16::<BigEndian>()?;
+        let hard_timeout = cursor.read_u16::<BigEndian>()?;
+        // unknown flags are ignored
+        let flags = FlowModFlags::from_bits_truncate(cursor.read_u16::<BigEndian>()?);
+        cursor.seek(SeekFrom::Current(4)).unwrap(); // pad 4 bytes
+        let cookie = cursor.read_u64::<BigEndian>()?;
+        let packet_count = cursor.read_u64::<BigEndian>()?;
+        let byte_count = cursor.read_u64::<BigEndian>()?;
+        let mmatch = read_match(bytes, &mut cursor)?;
+        let instructions = read_instructions(bytes, &cursor)?;
+
+        Ok(FlowStats {
+            table_id: table_id,
+            duration_sec: duration_sec,
+            duration_nsec: duration_nsec,
+            priority: priority,
+            idle_timeout: idle_timeout,
+            hard_timeout: hard_timeout,
+            flags: flags,
+            cookie: cookie,
+            packet_count: packet_count,
+            byte_count: byte_count,
+            mmatch: mmatch,
+            instructions: instructions,
+        })
+    }
+}
+
+impl Into<Vec<u8>> for FlowStats {
+    fn into(self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.write_u16::<BigEndian>(0).unwrap(); // length, set below
+        res.write_u8(self.table_id).unwrap();
+        res.write_u8(0).unwrap(); // pad 1 byte
+        res.write_u32::<BigEndian>(self.duration_sec).unwrap();
+        res.write_u32::<BigEndian>(self.duration_nsec).unwrap();
+        res.write_u16::<BigEndian>(self.priority).unwrap();
+        res.write_u16::<BigEndian>(self.idle_timeout).unwrap();
+        res.write_u16::<BigEndian>(self.hard_timeout).unwrap();
+        res.write_u16::<BigEndian>(self.flags.bits()).unwrap();
+        res.write_u32::<BigEndian>(0).unwrap(); // pad 4 bytes
+        res.write_u64::<BigEndian>(self.cookie).unwrap();
+        res.write_u64::<BigEndian>(self.packet_count).unwrap();
+        res.write_u64::<BigEndian>(self.byte_count).unwrap();
+        res.extend_from_slice(&Into::<Vec<u8>>::into(self.mmatch)[..]);
+        res.extend_from_slice(&encode_list(self.instructions)[..]);
+        set_entry_length(&mut res);
+        res
+    }
+}
+
+/// Description and statistics of a single flow (OpenFlow 1.5).
+#[derive(Debug, PartialEq, Clone)]
+pub struct FlowDesc {
+    // length u16
+    // pad 2 bytes
+    pub table_id: u8,
+    // pad 1 byte
+    pub priority: u16,
+    pub idle_timeout: u16,
+    pub hard_timeout: u16,
+    pub flags: FlowModFlags,
+    /// importance of the flow when the switch evicts flows
+    pub importance: u16,
+    pub cookie: u64,
+    pub mmatch: Match,
+    pub stats: OxsStats,
+    pub instructions: Vec<InstructionHeader>,
+}
+
+impl FlowDesc {
+    /// flow mod that adds the flow as it is installed, without its counters
+    /// (the importance is not part of the flow mods of this crate)
+    pub fn flow_mod(&self) -> FlowMod {
+        FlowMod {
+            cookie: self.cookie,
+            idle_timeout: self.idle_timeout,
+            hard_timeout: self.hard_timeout,
+            flags: self.flags,
+            ..FlowMod::add(
+                self.table_id,
+                self.priority,
+                self.mmatch.clone(),
+                self.instructions.clone(),
+            )
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for FlowDesc {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(bytes);
+        cursor.seek(SeekFrom::Current(4)).unwrap(); // length and pad 2 bytes
+        let table_id = cursor.read_u8()?;
+        cursor.seek(SeekFrom::Current(1)).unwrap(); // pad 1 byte
+        let priority = cursor.read_u16::<BigEndian>()?;
+        let idle_timeout = cursor.read_u16::<BigEndian>()?;
+        let hard_timeout = cursor.read_u16::<BigEndian>()?;
+        // unknown flags are ignored
+        let flags = FlowModFlags::from_bits_truncate(cursor.read_u16::<BigEndian>()?);
+        let importance = cursor.read_u16::<BigEndian>()?;
+        let cookie = cursor.read_u64::<BigEndian>()?;
+        let mmatch = read_match(bytes, &mut cursor)?;
+
+        let pos = cursor.position() as usize;
+        let stats_len = STATS_LEN_FIELD.read(bytes, pos, stringify!(OxsStats))?;
+        let stats_slice = checked_slice(bytes, pos, stats_len, stringify!(OxsStats))?;
+        let stats = OxsStats::try_from(stats_slice).within(pos)?;
+        cursor.set_position((pos + stats_len) as u64);
+
+        let instructions = read_instructions(bytes, &cursor)?;
+
+        Ok(FlowDesc {
+            table_id: table_id,
+            priority: priority,
+            idle_timeout: idle_timeout,
+            hard_timeout: hard_timeout,
+            flags: flags,
+            importance: importance,
+            cookie: cookie,
+            mmatch: mmatch,
+            stats: stats,
+            instructions: instructions,
+        })
+    }
+}
+
+impl Into<Vec<u8>> for FlowDesc {
+    fn into(self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.write_u16::<BigEndian>(0).unwrap(); // length, set below
+        res.write_u16::<BigEndian>(0).unwrap(); // pad 2 bytes
+        res.write_u8(self.table_id).unwrap();
+        res.write_u8(0).unwrap(); // pad 1 byte
+        res.write_u16::<BigEndian>(self.priority).unwrap();
+        res.write_u16::<BigEndian>(self.idle_timeout).unwrap();
+        res.write_u16::<BigEndian>(self.hard_timeout).unwrap();
+        res.write_u16::<BigEndian>(self.flags.bits()).unwrap();
+        res.write_u16::<BigEndian>(self.importance).unwrap();
+        res.write_u64::<BigEndian>(self.cookie).unwrap();
+        res.extend_from_slice(&Into::<Vec<u8>>::into(self.mmatch)[..]);
+        res.extend_from_slice(&Into::<Vec<u8>>::into(self.stats)[..]);
+        res.extend_from_slice(&encode_list(self.instructions)[..]);
+        set_entry_length(&mut res);
+        res
+    }
+}
+
+/// Counters of a flow (ofp_stats), the switch only sends the ones it supports.
+/// Unknown and experimenter fields are skipped when decoding.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct OxsStats {
+    /// time the flow has been alive
+    pub duration: Option<Duration>,
+    /// time since the flow last matched a packet
+    pub idle_time: Option<Duration>,
+    /// number of aggregated flows
+    pub flow_count: Option<u32>,
+    pub packet_count: Option<u64>,
+    pub byte_count: Option<u64>,
+}
+
+/// Fields of the OpenFlow basic statistics class.
+#[derive(Primitive, PartialEq, Debug, Clone)]
+pub enum OxsField {
+    /// Time the flow has been alive.
+    Duration = 0,
+    /// Time since the flow last matched a packet.
+    IdleTime = 1,
+    /// Number of aggregated flows.
+    FlowCount = 3,
+    /// Number of packets matched by the flow.
+    PacketCount = 4,
+    /// Number of bytes matched by the flow.
+    ByteCount = 5,
+}
+
+impl<'a> TryFrom<&'a [u8]> for OxsStats {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let length = BigEndian::read_u16(checked_slice(bytes, 2, 2, stringify!(OxsStats))?);
+        let fields = checked_slice(bytes, 0, length as usize, stringify!(OxsStats))?;
+        let mut stats = OxsStats::default();
+        let entries = TlvCursor::new(fields, STATS_HEADER_LENGTH, OXS_LEN_FIELD, "OxsField");
+        for entry in entries {
+            let (pos, entry) = entry?;
+            let class = BigEndian::read_u16(&entry[..2]);
+            let field = entry[2] >> 1;
+            let mut cursor = Cursor::new(&entry[4..]);
+            let read = if class != OXS_CLASS_OPENFLOW_BASIC {
+                Ok(())
+            } else {
+                match OxsField::from_u8(field) {
+                    Some(OxsField::Duration) => cursor
+                        .read_u64::<BigEndian>()
+                        .map(|raw| stats.duration = Some(split_duration(raw))),
+                    Some(OxsField::IdleTime) => cursor
+                        .read_u64::<BigEndian>()
+                        .map(|raw| stats.idle_time = Some(split_duration(raw))),
+                    Some(OxsField::FlowCount) => cursor
+                        .read_u32::<BigEndian>()
+                        .map(|count| stats.flow_count = Some(count)),
+                    Some(OxsField::PacketCount) => cursor
+                        .read_u64::<BigEndian>()
+                        .map(|count| stats.packet_count = Some(count)),
+                    Some(OxsField::ByteCount) => cursor
+                        .read_u64::<BigEndian>()
+                        .map(|count| stats.byte_count = Some(count)),
+                    None => Ok(()),
+                }
+            };
+            read.at(cursor.position()).within(pos + 4)?;
+        }
+        Ok(stats)
+    }
+}
+
+impl Into<Vec<u8>> for OxsStats {
+    fn into(self) -> Vec<u8> {
+        let mut fields = Vec::new();
+        if let Some(duration) = self.duration {
+            write_oxs(&mut fields, OxsField::Duration, 8);
+            fields
+                .write_u64::<BigEndian>(join_duration(duration))
+                .unwrap();
+        }
+        if let Some(idle_time) = self.idle_time {
+            write_oxs(&mut fields, OxsField::IdleTime, 8);
+            fields
+                .write_u64::<BigEndian>(join_duration(idle_time))
+                .unwrap();
+        }
+        if let Some(count) = self.flow_count {
+            write_oxs(&mut fields, OxsField::FlowCount, 4);
+            fields.write_u32::<BigEndian>(count).unwrap();
+        }
+        if let Some(count) = self.packet_count {
+            write_oxs(&mut fields, OxsField::PacketCount, 8);
+            fields.write_u64::<BigEndian>(count).unwrap();
+        }
+        if let Some(count) = self.byte_count {
+            write_oxs(&mut fields, OxsField::ByteCount, 8);
+            fields.write_u64::<BigEndian>(count).unwrap();
+        }
+        let mut res = Vec::new();
+        res.write_u16::<BigEndian>(0).unwrap(); // reserved
+        res.write_u16::<BigEndian>((STATS_HEADER_LENGTH + fields.len()) as u16)
+            .unwrap();
+        res.extend_from_slice(&fields[..]);
+        // the length excludes the padding to a multiple of 8 bytes
+        pad_to(&mut res, 8);
+        res
+    }
+}
+
+/// writes the header of an unmasked oxs field of the basic class
+fn write_oxs(res: &mut Vec<u8>, field: OxsField, len: u8) {
+    res.write_u16::<BigEndian>(OXS_CLASS_OPENFLOW_BASIC)
+        .unwrap();
+    res.write_u8((field as u8) << 1).unwrap();
+    res.write_u8(len).unwrap();
+}
+
+/// durations are sent as seconds in the upper and nanoseconds in the lower 32 bits
+fn split_duration(raw: u64) -> Duration {
+    Duration::new(raw >> 32, (raw & 0xffff_ffff) as u32)
+}
+
+fn join_duration(duration: Duration) -> u64 {
+    (duration.as_secs() << 32) | u64::from(duration.subsec_nanos())
+}
+
+/// reads the match at the cursor and moves the cursor behind it
+fn read_match(bytes: &[u8], cursor: &mut Cursor<&[u8]>) -> Result<Match> {
+    let mmatch_slice_len = Match::read_len(cursor)?;
+    let pos = cursor.position() as usize;
+    let mmatch_slice = checked_slice(bytes, pos, mmatch_slice_len, stringify!(Match))?;
+    let mmatch = Match::try_from(mmatch_slice).within(pos)?;
+    cursor.set_position((pos + mmatch_slice_len) as u64);
+    Ok(mmatch)
+}
+
+/// reads the instructions that fill the rest of the entry
+fn read_instructions(bytes: &[u8], cursor: &Cursor<&[u8]>) -> Result<Vec<InstructionHeader>> {
+    let pos = cursor.position() as usize;
+    let instructions_slice = checked_tail(bytes, pos, stringify!(FlowStats))?;
+    flow_instructions::instructions_from_slice(instructions_slice).within(pos)
+}
+
+/// sets the u16 length at the start of an encoded entry
+fn set_entry_length(res: &mut [u8]) {
+    let len = res.len() as u16;
+    BigEndian::write_u16(&mut res[..2], len);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::flow_parser::parse_flow_mod;
+    use super::super::tlv::decode_list;
+    use super::*;
+
+    #[test]
+    fn request_all_flows() {
+        let bytes: Vec<u8> = FlowStatsRequest::all().into();
+        assert_eq!(bytes.len(), FLOW_STATS_REQUEST_LENGTH + 8);
+        assert_eq!(&bytes[..8], &[0xff, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(
+            FlowStatsRequest::try_from(&bytes[..]).unwrap(),
+            FlowStatsRequest::all()
+        );
+    }
+
+    #[test]
+    fn flow_desc_round_trip() {
+        let flow_mod = parse_flow_mod("priority=10,in_port=1,actions=output:2").unwrap();
+        let desc = FlowDesc {
+            table_id: 1,
+            priority: 10,
+            idle_timeout: 0,
+            hard_timeout: 30,
+            flags: FlowModFlags::SEND_FLOW_REM,
+            importance: 5,
+            cookie: 0x42,
+            mmatch: flow_mod.mmatch,
+            stats: OxsStats {
+                duration: Some(Duration::new(3, 500)),
+                packet_count: Some(7),
+                byte_count: Some(700),
+                ..OxsStats::default()
+            },
+            instructions: flow_mod.instructions,
+        };
+        let add = desc.flow_mod();
+        assert_eq!((add.table_id, add.priority, add.cookie), (1, 10, 0x42));
+        assert_eq!(add.instructions, desc.instructions);
+        let mut bytes: Vec<u8> = desc.clone().into();
+        assert_eq!(BigEndian::read_u16(&bytes[..2]) as usize, bytes.len());
+        // stats header and three fields of 12 bytes after the match of 16 bytes
+        assert_eq!(&bytes[40..44], &[0, 0, 0, 40]);
+        assert_eq!(&bytes[44..48], &[0x80, 0x02, 0, 8]);
+        let copy = bytes.clone();
+        bytes.extend_from_slice(&copy[..]);
+        let descs: Vec<FlowDesc> = decode_list(&bytes, 0, FLOW_DESC_LEN_FIELD, "FlowDesc").unwrap();
+        assert_eq!(descs, vec![desc.clone(), desc]);
+    }
+
+    #[test]
+    fn ignores_unknown_flags() {
+        let flow_mod = parse_flow_mod("priority=10,in_port=1,actions=output:2").unwrap();
+        let stats = FlowStats {
+            table_id: 0,
+            duration_sec: 1,
+            duration_nsec: 0,
+            priority: 10,
+            idle_timeout: 0,
+            hard_timeout: 0,
+            flags: FlowModFlags::SEND_FLOW_REM,
+            cookie: 0,
+            packet_count: 0,
+            byte_count: 0,
+            mmatch: flow_mod.mmatch,
+            instructions: flow_mod.instructions,
+        };
+        let mut bytes: Vec<u8> = stats.clone().into();
+        // bit 15 of the flags, decoded with the default strict policy
+        bytes[18] |= 0x80;
+        assert_eq!(FlowStats::try_from(&bytes[..]).unwrap(), stats);
+    }
+
+    #[test]
+    fn skips_unknown_stats() {
+        let mut bytes = vec![0, 0, 0, 28];
+        // experimenter class field and unknown basic field before the packet count
+        bytes.extend_from_slice(&[0xff, 0xff, 0, 4, 0, 0, 0x23, 0x20]);
+        bytes.extend_from_slice(&[0x80, 0x02, 0x7f << 1, 0]);
+        bytes.extend_from_slice(&[0x80, 0x02, 4 << 1, 8, 0, 0, 0, 0, 0, 0, 0, 9]);
+        bytes.extend_from_slice(&[0; 4]); // pad to 32 bytes
+        let stats = OxsStats::try_from(&bytes[..]).unwrap();
+        assert_eq!(stats.packet_count, Some(9));
+        assert_eq!(stats.byte_count, None);
+
+        // the packet count is cut off
+        bytes[3] = 24;
+        bytes[19] = 4;
+        assert!(OxsStats::try_from(&bytes[..]).is_err());
+    }
+}
@@ -0,0 +1,612 @@
+//! Golden byte vectors of every message type.
+//!
+//! The vectors are laid out field by field after the structs of the OpenFlow
+//! 1.3.5 specification (openflow.h), including every padding byte. Each
+//! vector has to decode and encode to exactly the same bytes, messages that
+//! can be built with their constructors also have to encode to them.
+//!
+//! OFPT_EXPERIMENTER is not covered, its body is not decoded.
+
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use super::actions::PayloadOutput;
+use super::addr::MacAddr;
+use super::async::*;
+use super::error_msg::ErrorMsg;
+use super::factory;
+use super::features::Capabilities;
+use super::flow_instructions::{InstructionPayload, PayloadApplyActions};
+use super::flow_match::{Match, MatchPayload, PayloadInPort, TlvMatch};
+use super::flow_mod::{FlowMod, FlowModCommand, FlowModFlags};
+use super::flow_removed::{FlowRemoved, FlowRemovedReason};
+use super::group_mod::{Bucket, GroupMod, GroupModCommand, GroupNo, GroupNumber, GroupType};
+use super::meter_mod::*;
+use super::multipart::*;
+use super::packet_in::{InReason, PacketIn};
+use super::packet_out::{BufferId, PacketOut};
+use super::port_mod::PortMod;
+use super::port_status::{PortReason, PortStatus};
+use super::ports::{Port, PortConfig, PortFeatures, PortName, PortNo, PortNumber, PortState};
+use super::queue_config::QueueGetConfigRequest;
+use super::role::{ControllerRole, Role};
+use super::switch_config::{ConfigFlags, SwitchConfig};
+use super::table_mod::{TableConfig, TableMod};
+use super::*;
+
+/// xid of all golden messages
+const XID: u32 = 0x2a;
+
+/// decodes the golden message and checks that it encodes to the same bytes
+fn check_decode(golden: &[u8]) {
+    let header = Header::try_from(&golden[..HEADER_LENGTH]).unwrap();
+    assert_eq!(*header.version(), Version::V1_3);
+    assert_eq!(*header.length() as usize, golden.len(), "length of {:?}", header.ttype());
+    assert_eq!(*header.xid(), XID);
+    let payload = decode_any(Version::V1_3, header.ttype(), &golden[HEADER_LENGTH..])
+        .unwrap_or_else(|err| panic!("decoding {:?}: {}", header.ttype(), err));
+    let bytes = OfMsg::new(header.clone(), payload).encode().unwrap();
+    assert_eq!(bytes, golden, "re-encoding {:?}", header.ttype());
+}
+
+/// checks that the payload encodes to the golden message and that it decodes again
+fn check(payload: OfPayload, golden: &[u8]) {
+    let ttype = payload.ttype();
+    let bytes = OfMsg::generate_for(Version::V1_3, XID, payload)
+        .unwrap()
+        .encode()
+        .unwrap();
+    assert_eq!(bytes, golden, "encoding {:?}", ttype);
+    check_decode(golden);
+}
+
+/// header of a message with the given type and body length
+fn header(ttype: u8, body_len: usize) -> Vec<u8> {
+    let len = HEADER_LENGTH + body_len;
+    vec![0x04, ttype, (len >> 8) as u8, len as u8, 0x00, 0x00, 0x00, 0x2a]
+}
+
+/// match with in_port=1 (ofp_match with a single oxm and 4 bytes of padding)
+const IN_PORT_MATCH: [u8; 16] = [
+    0x00, 0x01, 0x00, 0x0c, // OFPMT_OXM, length 12 without padding
+    0x80, 0x00, 0x00, 0x04, // OFPXMC_OPENFLOW_BASIC, OFPXMT_OFB_IN_PORT, length 4
+    0x00, 0x00, 0x00, 0x01, // port 1
+    0x00, 0x00, 0x00, 0x00, // pad to 8 bytes
+];
+
+/// output action to port 1 (ofp_action_output)
+const OUTPUT_PORT_1: [u8; 16] = [
+    0x00, 0x00, 0x00, 0x10, // OFPAT_OUTPUT, len 16
+    0x00, 0x00, 0x00, 0x01, // port 1
+    0xff, 0xff, // max_len OFPCML_NO_BUFFER
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // pad 6
+];
+
+fn in_port_match() -> Match {
+    Match::new(vec![TlvMatch::new(MatchPayload::InPort(PayloadInPort {
+        ingress_port: PortNumber::NormalPort(1),
+    }))])
+}
+
+fn output_port_1() -> PayloadOutput {
+    PayloadOutput {
+        port: PortNumber::NormalPort(1),
+        max_len: 0xffff,
+    }
+}
+
+#[test]
+fn messages_without_body() {
+    let payloads = vec![
+        OfPayload::Hello,
+        OfPayload::EchoRequest(Vec::new()),
+        OfPayload::EchoReply(Vec::new()),
+        OfPayload::FeaturesRequest,
+        OfPayload::GetConfigRequest,
+        OfPayload::BarrierRequest,
+        OfPayload::BarrierReply,
+        OfPayload::GetAsyncRequest,
+    ];
+    for payload in payloads {
+        let ttype = payload.ttype().to_u8().unwrap();
+        check(payload, &header(ttype, 0));
+    }
+    check(OfPayload::Hello, &[0x04, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x2a]);
+}
+
+#[test]
+fn echo_data() {
+    let golden = [
+        0x04, 0x02, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x2a, // header
+        0x70, 0x69, 0x6e, 0x67, // arbitrary data
+    ];
+    check(OfPayload::EchoRequest(b"ping".to_vec()), &golden);
+    let mut reply = golden.to_vec();
+    reply[1] = 0x03;
+    check(OfPayload::EchoReply(b"ping".to_vec()), &reply);
+}
+
+#[test]
+fn error() {
+    let golden = [
+        0x04, 0x01, 0x00, 0x14, 0x00, 0x00, 0x00, 0x2a, // header
+        0x00, 0x01, // OFPET_BAD_REQUEST
+        0x00, 0x01, // OFPBRC_BAD_TYPE
+        0x04, 0xff, 0x00, 0x08, 0x00, 0x00, 0x00, 0x07, // failed request
+    ];
+    let error = ErrorMsg {
+        ttype: 1,
+        code: 1,
+        data: golden[12..].to_vec(),
+    };
+    check(OfPayload::Error(error), &golden);
+}
+
+#[test]
+fn features_reply() {
+    let golden = [
+        0x04, 0x06, 0x00, 0x20, 0x00, 0x00, 0x00, 0x2a, // header
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // datapath_id
+        0x00, 0x00, 0x01, 0x00, // n_buffers
+        0xfe, // n_tables
+        0x00, // auxiliary_id
+        0x00, 0x00, // pad 2
+        0x00, 0x00, 0x00, 0x4f, // capabilities
+        0x00, 0x00, 0x00, 0x00, // reserved
+    ];
+    let mut features = factory::features_reply(1);
+    features.n_buffers = 256;
+    features.n_tables = 0xfe;
+    features.capabilities = Capabilities::FLOW_STATS
+        | Capabilities::TABLE_STATS
+        | Capabilities::PORT_STATS
+        | Capabilities::GROUP_STATS
+        | Capabilities::QUEUE_STATS;
+    check(OfPayload::FeaturesReply(features), &golden);
+}
+
+#[test]
+fn switch_config() {
+    let config = SwitchConfig {
+        flags: ConfigFlags::FRAG_DROP,
+        miss_send_len: 128,
+    };
+    let body = [
+        0x00, 0x01, // OFPC_FRAG_DROP
+        0x00, 0x80, // miss_send_len
+    ];
+    let mut golden = header(Type::GetConfigReply.to_u8().unwrap(), body.len());
+    golden.extend_from_slice(&body);
+    check(OfPayload::GetConfigReply(config.clone()), &golden);
+
+    let mut golden = header(Type::SetConfig.to_u8().unwrap(), body.len());
+    golden.extend_from_slice(&body);
+    check(OfPayload::SetConfig(config), &golden);
+}
+
+#[test]
+fn packet_in() {
+    let mut golden = vec![
+        0x04, 0x0a, 0x00, 0x2e, 0x00, 0x00, 0x00, 0x2a, // header
+        0xff, 0xff, 0xff, 0xff, // buffer_id OFP_NO_BUFFER
+        0x00, 0x04, // total_len
+        0x00, // OFPR_NO_MATCH
+        0x00, // table_id
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // cookie
+    ];
+    golden.extend_from_slice(&IN_PORT_MATCH);
+    golden.extend_from_slice(&[0x00, 0x00]); // pad 2
+    golden.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]); // frame
+    let packet_in = PacketIn {
+        buffer_id: BufferId::NoBuffer,
+        total_len: 4,
+        reason: InReason::NoMatch,
+        table_id: 0,
+        cookie: 0,
+        mmatch: in_port_match(),
+        ethernet_frame: vec![0xde, 0xad, 0xbe, 0xef],
+    };
+    check(OfPayload::PacketIn(packet_in), &golden);
+}
+
+#[test]
+fn flow_removed() {
+    let golden = [
+        0x04, 0x0b, 0x00, 0x38, 0x00, 0x00, 0x00, 0x2a, // header
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, // cookie
+        0x00, 0x64, // priority
+        0x00, // OFPRR_IDLE_TIMEOUT
+        0x01, // table_id
+        0x00, 0x00, 0x00, 0x0a, // duration_sec
+        0x00, 0x00, 0x00, 0x00, // duration_nsec
+        0x00, 0x0a, // idle_timeout
+        0x00, 0x00, // hard_timeout
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // packet_count
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // byte_count
+        0x00, 0x01, 0x00, 0x04, // empty match
+        0x00, 0x00, 0x00, 0x00, // pad to 8 bytes
+    ];
+    let flow_removed = FlowRemoved::new(
+        5,
+        100,
+        FlowRemovedReason::IdleTimeout,
+        1,
+        Match::new(vec![]),
+    )
+    .with_duration(Duration::from_secs(10))
+    .with_timeouts(10, 0)
+    .with_counts(2, 128);
+    check(OfPayload::FlowRemoved(flow_removed), &golden);
+}
+
+#[test]
+fn port_status() {
+    let golden = [
+        0x04, 0x0c, 0x00, 0x50, 0x00, 0x00, 0x00, 0x2a, // header
+        0x02, // OFPPR_MODIFY
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // pad 7
+        0x00, 0x00, 0x00, 0x01, // port_no
+        0x00, 0x00, 0x00, 0x00, // pad 4
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // hw_addr
+        0x00, 0x00, // pad 2
+        b'e', b't', b'h', b'1', 0x00, 0x00, 0x00, 0x00, // name
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, // config
+        0x00, 0x00, 0x00, 0x04, // OFPPS_LIVE
+        0x00, 0x00, 0x08, 0x40, // curr OFPPF_10GB_FD | OFPPF_COPPER
+        0x00, 0x00, 0x00, 0x00, // advertised
+        0x00, 0x00, 0x00, 0x00, // supported
+        0x00, 0x00, 0x00, 0x00, // peer
+        0x00, 0x98, 0x96, 0x80, // curr_speed in kbps
+        0x00, 0x98, 0x96, 0x80, // max_speed in kbps
+    ];
+    let port = Port::new(
+        PortNumber::NormalPort(1),
+        MacAddr([0, 0, 0, 0, 0, 1]),
+        PortName::new("eth1").unwrap(),
+    )
+    .with_state(PortState::LIVE)
+    .with_features(
+        PortFeatures::GB10_FD | PortFeatures::COPPER,
+        PortFeatures::empty(),
+        PortFeatures::empty(),
+        PortFeatures::empty(),
+    )
+    .with_speed(10_000_000, 10_000_000);
+    let port_status = PortStatus::new(PortReason::Modifiy, port);
+    check(OfPayload::PortStatus(port_status), &golden);
+}
+
+#[test]
+fn packet_out() {
+    let mut golden = vec![
+        0x04, 0x0d, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x2a, // header
+        0xff, 0xff, 0xff, 0xff, // buffer_id OFP_NO_BUFFER
+        0xff, 0xff, 0xff, 0xfd, // in_port OFPP_CONTROLLER
+        0x00, 0x10, // actions_len
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // pad 6
+    ];
+    golden.extend_from_slice(&OUTPUT_PORT_1);
+    golden.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]); // data
+    let packet_out = PacketOut::new(
+        BufferId::NoBuffer,
+        PortNo::Controller.into(),
+        vec![output_port_1().into()],
+        vec![0xde, 0xad, 0xbe, 0xef],
+    );
+    check(OfPayload::PacketOut(packet_out), &golden);
+}
+
+#[test]
+fn flow_mod() {
+    let mut golden = vec![
+        0x04, 0x0e, 0x00, 0x58, 0x00, 0x00, 0x00, 0x2a, // header
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, // cookie
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // cookie_mask
+        0x00, // table_id
+        0x00, // OFPFC_ADD
+        0x00, 0x0a, // idle_timeout
+        0x00, 0x00, // hard_timeout
+        0x00, 0x64, // priority
+        0xff, 0xff, 0xff, 0xff, // buffer_id OFP_NO_BUFFER
+        0xff, 0xff, 0xff, 0xff, // out_port OFPP_ANY
+        0xff, 0xff, 0xff, 0xff, // out_group OFPG_ANY
+        0x00, 0x01, // OFPFF_SEND_FLOW_REM
+        0x00, 0x00, // pad 2
+    ];
+    golden.extend_from_slice(&IN_PORT_MATCH);
+    golden.extend_from_slice(&[
+        0x00, 0x04, 0x00, 0x18, // OFPIT_APPLY_ACTIONS, len 24
+        0x00, 0x00, 0x00, 0x00, // pad 4
+    ]);
+    golden.extend_from_slice(&OUTPUT_PORT_1);
+    let actions = PayloadApplyActions {
+        actions: vec![output_port_1().into()],
+    };
+    let flow_mod = FlowMod {
+        cookie: 5,
+        cookie_mask: 0,
+        table_id: 0,
+        command: FlowModCommand::Add,
+        idle_timeout: 10,
+        hard_timeout: 0,
+        priority: 100,
+        buffer_id: BufferId::NoBuffer,
+        out_port: PortNo::Any.into(),
+        out_group: GroupNo::Any.into(),
+        flags: FlowModFlags::SEND_FLOW_REM,
+        mmatch: in_port_match(),
+        instructions: vec![InstructionPayload::ApplyActions(actions).into()],
+    };
+    check(OfPayload::FlowMod(flow_mod), &golden);
+}
+
+#[test]
+fn group_mod() {
+    let mut golden = vec![
+        0x04, 0x0f, 0x00, 0x30, 0x00, 0x00, 0x00, 0x2a, // header
+        0x00, 0x00, // OFPGC_ADD
+        0x00, // OFPGT_ALL
+        0x00, // pad 1
+        0x00, 0x00, 0x00, 0x01, // group_id
+        0x00, 0x20, // bucket len
+        0x00, 0x00, // weight
+        0xff, 0xff, 0xff, 0xff, // watch_port OFPP_ANY
+        0xff, 0xff, 0xff, 0xff, // watch_group OFPG_ANY
+        0x00, 0x00, 0x00, 0x00, // pad 4
+    ];
+    golden.extend_from_slice(&OUTPUT_PORT_1);
+    let bucket = Bucket::new(
+        0,
+        PortNo::Any.into(),
+        GroupNo::Any.into(),
+        vec![output_port_1().into()],
+    );
+    let group_mod = GroupMod::new(
+        GroupModCommand::Add,
+        GroupType::All,
+        GroupNumber::Group(1),
+        vec![bucket],
+    );
+    check(OfPayload::GroupMod(group_mod), &golden);
+}
+
+#[test]
+fn port_mod() {
+    let golden = [
+        0x04, 0x10, 0x00, 0x28, 0x00, 0x00, 0x00, 0x2a, // header
+        0x00, 0x00, 0x00, 0x01, // port_no
+        0x00, 0x00, 0x00, 0x00, // pad 4
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // hw_addr
+        0x00, 0x00, // pad 2
+        0x00, 0x00, 0x00, 0x01, // config OFPPC_PORT_DOWN
+        0x00, 0x00, 0x00, 0x01, // mask
+        0x00, 0x00, 0x00, 0x00, // advertise
+        0x00, 0x00, 0x00, 0x00, // pad 4
+    ];
+    let port_mod = PortMod::new(
+        PortNumber::NormalPort(1),
+        MacAddr([0, 0, 0, 0, 0, 1]),
+        PortConfig::PORT_DOWN,
+        PortConfig::PORT_DOWN,
+        PortFeatures::empty(),
+    );
+    check(OfPayload::PortMod(port_mod), &golden);
+}
+
+#[test]
+fn table_mod() {
+    let golden = [
+        0x04, 0x11, 0x00, 0x10, 0x00, 0x00, 0x00, 0x2a, // header
+        0x01, // table_id
+        0x00, 0x00, 0x00, // pad 3
+        0x00, 0x00, 0x00, 0x00, // config
+    ];
+    check(OfPayload::TableMod(TableMod::new(1, TableConfig::empty())), &golden);
+}
+
+#[test]
+fn multipart_desc() {
+    let golden = [
+        0x04, 0x12, 0x00, 0x10, 0x00, 0x00, 0x00, 0x2a, // header
+        0x00, 0x00, // OFPMP_DESC
+        0x00, 0x00, // flags
+        0x00, 0x00, 0x00, 0x00, // pad 4
+    ];
+    let request = MultipartRequest::new(ReqPayload::Desc);
+    check(OfPayload::MultipartRequest(request), &golden);
+
+    let mut golden = header(Type::MultipartReply.to_u8().unwrap(), 8 + DESC_LENGTH);
+    golden.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    for &(value, len) in &[
+        ("Nicira, Inc.", DESC_STR_LEN),
+        ("Open vSwitch", DESC_STR_LEN),
+        ("2.17.0", DESC_STR_LEN),
+        ("None", SERIAL_NUM_LEN),
+        ("br0", DESC_STR_LEN),
+    ] {
+        golden.extend_from_slice(value.as_bytes());
+        golden.resize(golden.len() + len - value.len(), 0);
+    }
+    let desc = RepDesc {
+        mfr_desc: "Nicira, Inc.".to_string(),
+        hw_desc: "Open vSwitch".to_string(),
+        sw_desc: "2.17.0".to_string(),
+        serial_num: "None".to_string(),
+        dp_desc: "br0".to_string(),
+    };
+    let reply = MultipartReply::new(RepPayload::Desc(desc));
+    check(OfPayload::MultipartReply(reply), &golden);
+}
+
+#[test]
+fn multipart_port_desc() {
+    let golden = [
+        0x04, 0x12, 0x00, 0x10, 0x00, 0x00, 0x00, 0x2a, // header
+        0x00, 0x0d, // OFPMP_PORT_DESC
+        0x00, 0x00, // flags
+        0x00, 0x00, 0x00, 0x00, // pad 4
+    ];
+    let request = MultipartRequest::new(ReqPayload::PortDesc);
+    check(OfPayload::MultipartRequest(request), &golden);
+
+    let golden = [
+        0x04, 0x13, 0x00, 0x50, 0x00, 0x00, 0x00, 0x2a, // header
+        0x00, 0x0d, // OFPMP_PORT_DESC
+        0x00, 0x01, // OFPMPF_REPLY_MORE
+        0x00, 0x00, 0x00, 0x00, // pad 4
+        0x00, 0x00, 0x00, 0x02, // port_no
+        0x00, 0x00, 0x00, 0x00, // pad 4
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // hw_addr
+        0x00, 0x00, // pad 2
+        b'e', b't', b'h', b'2', 0x00, 0x00, 0x00, 0x00, // name
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x01, // OFPPC_PORT_DOWN
+        0x00, 0x00, 0x00, 0x01, // OFPPS_LINK_DOWN
+        0x00, 0x00, 0x00, 0x00, // curr
+        0x00, 0x00, 0x00, 0x00, // advertised
+        0x00, 0x00, 0x00, 0x00, // supported
+        0x00, 0x00, 0x00, 0x00, // peer
+        0x00, 0x00, 0x00, 0x00, // curr_speed in kbps
+        0x00, 0x00, 0x00, 0x00, // max_speed in kbps
+    ];
+    let port = Port::new(
+        PortNumber::NormalPort(2),
+        MacAddr([0, 0, 0, 0, 0, 2]),
+        PortName::new("eth2").unwrap(),
+    )
+    .with_config(PortConfig::PORT_DOWN)
+    .with_state(PortState::LINK_DOWN);
+    let mut reply = MultipartReply::new(RepPayload::PortDesc(vec![port]));
+    reply.flags = MultipartFlags::MORE;
+    check(OfPayload::MultipartReply(reply), &golden);
+}
+
+#[test]
+fn queue_config() {
+    let golden = [
+        0x04, 0x16, 0x00, 0x10, 0x00, 0x00, 0x00, 0x2a, // header
+        0x00, 0x00, 0x00, 0x01, // port
+        0x00, 0x00, 0x00, 0x00, // pad 4
+    ];
+    let request = QueueGetConfigRequest {
+        port: PortNumber::NormalPort(1),
+    };
+    check(OfPayload::QueueGetConfigRequest(request), &golden);
+
+    let golden = [
+        0x04, 0x17, 0x00, 0x30, 0x00, 0x00, 0x00, 0x2a, // header
+        0x00, 0x00, 0x00, 0x01, // port
+        0x00, 0x00, 0x00, 0x00, // pad 4
+        0x00, 0x00, 0x00, 0x01, // queue_id
+        0x00, 0x00, 0x00, 0x01, // port
+        0x00, 0x20, // len
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // pad 6
+        0x00, 0x01, 0x00, 0x10, // OFPQT_MIN_RATE, len 16
+        0x00, 0x00, 0x00, 0x00, // pad 4
+        0x00, 0x64, // rate 10%
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // pad 6
+    ];
+    check_decode(&golden);
+}
+
+#[test]
+fn role() {
+    let body = [
+        0x00, 0x00, 0x00, 0x02, // OFPCR_ROLE_MASTER
+        0x00, 0x00, 0x00, 0x00, // pad 4
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07, // generation_id
+    ];
+    for ttype in [Type::RoleRequest, Type::RoleReply] {
+        let role = Role {
+            role: ControllerRole::Master,
+            generation_id: 7,
+        };
+        let payload = match ttype {
+            Type::RoleRequest => OfPayload::RoleRequest(role),
+            _ => OfPayload::RoleReply(role),
+        };
+        let mut golden = header(ttype.to_u8().unwrap(), body.len());
+        golden.extend_from_slice(&body);
+        check(payload, &golden);
+    }
+}
+
+#[test]
+fn async_config() {
+    let body = [
+        0x00, 0x00, 0x00, 0x03, // packet_in_mask master
+        0x00, 0x00, 0x00, 0x00, // packet_in_mask slave
+        0x00, 0x00, 0x00, 0x07, // port_status_mask master
+        0x00, 0x00, 0x00, 0x07, // port_status_mask slave
+        0x00, 0x00, 0x00, 0x0f, // flow_removed_mask master
+        0x00, 0x00, 0x00, 0x00, // flow_removed_mask slave
+    ];
+    let config = Async {
+        packet_in_mask_1: PacketInReasonMask::NO_MATCH | PacketInReasonMask::ACTION,
+        packet_in_mask_2: PacketInReasonMask::empty(),
+        port_status_mask_1: PortReasonMask::all(),
+        port_status_mask_2: PortReasonMask::all(),
+        flow_removed_mask_1: FlowRemovedReasonMask::all(),
+        flow_removed_mask_2: FlowRemovedReasonMask::empty(),
+    };
+    let mut golden = header(Type::GetAsyncReply.to_u8().unwrap(), body.len());
+    golden.extend_from_slice(&body);
+    check(OfPayload::GetAsyncReply(config.clone()), &golden);
+
+    let mut golden = header(Type::SetAsync.to_u8().unwrap(), body.len());
+    golden.extend_from_slice(&body);
+    check(OfPayload::SetAsync(config), &golden);
+}
+
+#[test]
+fn meter_mod() {
+    let golden = [
+        0x04, 0x1d, 0x00, 0x20, 0x00, 0x00, 0x00, 0x2a, // header
+        0x00, 0x01, // OFPMC_ADD
+        0x00, 0x05, // OFPMF_KBPS | OFPMF_BURST
+        0x00, 0x00, 0x00, 0x01, // meter_id
+        0x00, 0x01, 0x00, 0x10, // OFPMBT_DROP, len 16
+        0x00, 0x00, 0x03, 0xe8, // rate
+        0x00, 0x00, 0x00, 0x64, // burst_size
+        0x00, 0x00, 0x00, 0x00, // pad 4
+    ];
+    let meter_mod = MeterMod {
+        command: MeterModCommand::Add,
+        flags: MeterFlags::KBPS | MeterFlags::BURST,
+        meter_id: MeterId::Meter(1),
+        bands: vec![MeterBandHeader::new(
+            1000,
+            100,
+            MeterBandPayload::Drop(MeterBandDrop {}),
+        )],
+    };
+    check(OfPayload::MeterMod(meter_mod), &golden);
+}
+
+#[test]
+fn rejects_trailing_bytes() {
+    let body = [0x00, 0x01, 0xff, 0xe5, 0x00]; // OFPC_FRAG_DROP, miss_send_len, 1 extra byte
+    let err = decode_any(Version::V1_3, &Type::GetConfigReply, &body).unwrap_err();
+    match *err.kind() {
+        ErrorKind::InvalidSliceLength(4, 5, _) => (),
+        ref kind => panic!("unexpected error kind {:?}", kind),
+    }
+    assert_eq!(err.context().offset, Some(4));
+    assert!(decode_any(Version::V1_3, &Type::BarrierReply, &[0]).is_err());
+    assert!(decode_any(Version::V1_3, &Type::GetConfigReply, &body[..4]).is_ok());
+}
+
+#[test]
+fn rejects_messages_longer_than_the_header_length() {
+    let frame = vec![0; MAX_MESSAGE_LENGTH];
+    let msg = OfMsg::generate(XID, OfPayload::PacketOut(PacketOut::flood(frame)));
+    match *msg.encode().unwrap_err().kind() {
+        ErrorKind::MessageTooLong(len) => assert!(len > MAX_MESSAGE_LENGTH),
+        ref kind => panic!("unexpected error kind {:?}", kind),
+    }
+
+    let frame = vec![0; MAX_MESSAGE_LENGTH - HEADER_LENGTH - 32];
+    let msg = OfMsg::generate(XID, OfPayload::PacketOut(PacketOut::flood(frame)));
+    assert_eq!(msg.encode().unwrap().len(), MAX_MESSAGE_LENGTH);
+}
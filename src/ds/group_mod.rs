@@ -1,48 +1,71 @@
+// used to allow GroupNo constants
+#![allow(overflowing_literals)]
+
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use num_traits::{FromPrimitive, ToPrimitive};
 use std::convert::{Into, TryFrom};
+use std::fmt;
 use std::io::{Cursor, Seek, SeekFrom};
+use std::str::FromStr;
 
-use super::actions::ActionHeader;
+use super::actions::{actions_from_slice, calc_actions_len, ActionHeader, ActionType};
+use super::checked_slice;
+use super::codec::OfEncode;
 use super::ports::PortNumber;
+use super::tlv::{decode_list, encode_list, LengthField};
 
 use super::super::err::*;
-use std::path;
 
-#[derive(Debug)]
+#[derive(Getters, Debug, PartialEq, Clone)]
 pub struct GroupMod {
+    #[get = "pub"]
     command: GroupModCommand,
+    #[get = "pub"]
     ttype: GroupType,
     //pad 1 bytes
-    group_id: u32,
+    #[get = "pub"]
+    group_id: GroupNumber,
+    #[get = "pub"]
     buckets: Vec<Bucket>,
 }
 
+impl GroupMod {
+    pub fn new(
+        command: GroupModCommand,
+        ttype: GroupType,
+        group_id: GroupNumber,
+        buckets: Vec<Bucket>,
+    ) -> Self {
+        GroupMod {
+            command: command,
+            ttype: ttype,
+            group_id: group_id,
+            buckets: buckets,
+        }
+    }
+}
+
 impl<'a> TryFrom<&'a [u8]> for GroupMod {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let command_raw = cursor.read_u16::<BigEndian>().unwrap();
+        let command_raw = cursor.read_u16::<BigEndian>()?;
         let command = GroupModCommand::from_u16(command_raw).ok_or::<Error>(
             ErrorKind::UnknownValue(command_raw as u64, stringify!(GroupModCommand)).into(),
         )?;
-        let ttype_raw = cursor.read_u8().unwrap();
+        let ttype_raw = cursor.read_u8()?;
         let ttype = GroupType::from_u8(ttype_raw).ok_or::<Error>(
             ErrorKind::UnknownValue(ttype_raw as u64, stringify!(GroupType)).into(),
         )?;
-        let group_id = cursor.read_u32::<BigEndian>().unwrap();
+        cursor.seek(SeekFrom::Current(1)).unwrap(); // pad 1 byte
+        let group_id = GroupNumber::try_from(cursor.read_u32::<BigEndian>()?)?;
 
-        let mut buckets = Vec::new();
-        let mut bytes_remaining = bytes.len() - 8;
-        while bytes_remaining > 0 {
-            let bucket_len = Bucket::read_len(&mut cursor)?;
-            let bucket_slice =
-                &bytes[cursor.position() as usize..cursor.position() as usize + bucket_len];
-            let bucket = Bucket::try_from(bucket_slice)?;
-            buckets.push(bucket);
-            cursor.seek(SeekFrom::Current(bucket_len as i64)).unwrap();
-            bytes_remaining -= bucket_len;
-        }
+        let buckets = decode_list(
+            bytes,
+            cursor.position() as usize,
+            BUCKET_LEN_FIELD,
+            stringify!(Bucket),
+        )?;
 
         Ok(GroupMod {
             command: command,
@@ -60,10 +83,8 @@ impl Into<Vec<u8>> for GroupMod {
             .unwrap();
         res.write_u8(self.ttype.to_u8().unwrap()).unwrap();
         res.write_u8(0).unwrap(); // pad 1 byte
-        res.write_u32::<BigEndian>(self.group_id).unwrap();
-        for bucket in self.buckets {
-            res.extend_from_slice(&Into::<Vec<u8>>::into(bucket)[..]);
-        }
+        res.write_u32::<BigEndian>(self.group_id.into()).unwrap();
+        res.extend_from_slice(&encode_list(self.buckets)[..]);
         res
     }
 }
@@ -82,7 +103,7 @@ pub enum GroupModCommand {
 /// Group types. Values in the range [128, 255] are reserved for experimental
 /// use.
 #[derive(Primitive, PartialEq, Debug, Clone)]
-enum GroupType {
+pub enum GroupType {
     /// All (multicast/broadcast) group.
     All = 0,
     /// Select group.
@@ -93,61 +114,193 @@ enum GroupType {
     Ff = 3,
 }
 
-#[derive(Debug)]
+/// Wildcard group of flow mods (OFPG_ANY).
+pub const GROUP_ANY: GroupNumber = GroupNumber::Reserved(GroupNo::Any);
+
+/// Group number of a group mod, a group action or the group a bucket or a
+/// flow stats request refers to.
+#[derive(Debug, PartialEq, Clone)]
+pub enum GroupNumber {
+    Reserved(GroupNo),
+    Group(u32),
+}
+
+impl GroupNumber {
+    /// true for all and any, max is the last usable group
+    pub fn is_reserved(&self) -> bool {
+        match *self {
+            GroupNumber::Reserved(GroupNo::Max) | GroupNumber::Group(_) => false,
+            GroupNumber::Reserved(_) => true,
+        }
+    }
+}
+
+impl TryFrom<u32> for GroupNumber {
+    type Error = Error;
+    /// fails for values above max that are no reserved group
+    fn try_from(group_id: u32) -> Result<Self> {
+        Ok(match GroupNo::from_u32(group_id) {
+            Some(group) => GroupNumber::Reserved(group),
+            None if group_id > GroupNo::Max as u32 => {
+                bail!(ErrorKind::UnknownValue(
+                    group_id as u64,
+                    stringify!(GroupNo)
+                ))
+            }
+            None => GroupNumber::Group(group_id),
+        })
+    }
+}
+
+impl fmt::Display for GroupNumber {
+    /// reserved groups are printed by name, eg. "all", others as number
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GroupNumber::Reserved(ref group) => write!(f, "{}", group.name()),
+            GroupNumber::Group(group_id) => write!(f, "{}", group_id),
+        }
+    }
+}
+
+impl FromStr for GroupNumber {
+    type Err = Error;
+    /// parses group names (case insensitive) and decimal or `0x` prefixed numbers
+    fn from_str(s: &str) -> Result<Self> {
+        let lower = s.to_lowercase();
+        if let Some(group) = GroupNo::from_name(&lower) {
+            return Ok(group.into());
+        }
+        let parsed = match lower.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => lower.parse(),
+        };
+        match parsed {
+            Ok(group_id) => GroupNumber::try_from(group_id),
+            Err(_) => bail!(ErrorKind::InvalidGroup(s.to_string())),
+        }
+    }
+}
+
+impl Into<u32> for GroupNumber {
+    fn into(self) -> u32 {
+        match self {
+            GroupNumber::Reserved(group) => group.to_u32().unwrap(),
+            GroupNumber::Group(group_id) => group_id,
+        }
+    }
+}
+
+/// Group numbering. Groups can use any number up to max.
+#[derive(Primitive, PartialEq, Debug, Clone)]
+pub enum GroupNo {
+    /// Last usable group number.
+    Max = 0xffffff00,
+    /// Represents all groups for group delete commands.
+    All = 0xfffffffc,
+    /// Wildcard group used only for flow stats requests and the watch group
+    /// of buckets. Selects all flows regardless of group (including flows with
+    /// no group).
+    Any = 0xffffffff,
+}
+
+impl GroupNo {
+    const NAMES: [(GroupNo, &'static str); 3] = [
+        (GroupNo::Max, "max"),
+        (GroupNo::All, "all"),
+        (GroupNo::Any, "any"),
+    ];
+
+    /// lowercase name of the group as used by ovs-ofctl, eg. "all"
+    pub fn name(&self) -> &'static str {
+        GroupNo::NAMES
+            .iter()
+            .find(|&(group, _)| group == self)
+            .map(|&(_, name)| name)
+            .unwrap()
+    }
+
+    /// looks up a group by its lowercase name
+    pub fn from_name(name: &str) -> Option<GroupNo> {
+        GroupNo::NAMES
+            .iter()
+            .find(|&&(_, group_name)| group_name == name)
+            .map(|(group, _)| group.clone())
+    }
+}
+
+impl Into<GroupNumber> for GroupNo {
+    fn into(self) -> GroupNumber {
+        GroupNumber::Reserved(self)
+    }
+}
+
+/// Length of a bucket without actions (16 bytes).
+pub const BUCKET_LEN: usize = 16;
+
+#[derive(Getters, Debug, PartialEq, Clone)]
 pub struct Bucket {
+    /// length including the actions
+    #[get = "pub"]
     len: u16,
+    /// relative weight, only used by select groups
+    #[get = "pub"]
     weight: u16,
+    /// port whose liveness decides if the bucket is used, only used by fast failover groups
+    #[get = "pub"]
     watch_port: PortNumber,
-    watch_group: u32,
+    /// group whose liveness decides if the bucket is used, only used by fast failover groups
+    #[get = "pub"]
+    watch_group: GroupNumber,
     //pad 4 bytes
+    #[get = "pub"]
     actions: Vec<ActionHeader>,
 }
 
 impl Bucket {
+    pub fn new(
+        weight: u16,
+        watch_port: PortNumber,
+        watch_group: GroupNumber,
+        actions: Vec<ActionHeader>,
+    ) -> Self {
+        Bucket {
+            len: BUCKET_LEN as u16 + calc_actions_len(&actions),
+            weight: weight,
+            watch_port: watch_port,
+            watch_group: watch_group,
+            actions: actions,
+        }
+    }
+
     pub fn read_len(cursor: &mut Cursor<&[u8]>) -> Result<usize> {
-        // read value and handle errors
-        let len = match cursor.read_u16::<BigEndian>() {
-            Ok(len) => len,
-            Err(err) => {
-                error!(
-                    "Could not read packet queue len.{}{:?}{}{}",
-                    path::MAIN_SEPARATOR,
-                    cursor,
-                    path::MAIN_SEPARATOR,
-                    err
-                );
-                bail!(ErrorKind::CouldNotReadLength(0, stringify!(PacketQueue),))
-            }
-        };
-        // go back to start
-        cursor.seek(SeekFrom::Current(-2)).unwrap();
-        Ok(len as usize)
+        let pos = cursor.position() as usize;
+        BUCKET_LEN_FIELD.read(cursor.get_ref(), pos, stringify!(Bucket))
     }
 }
 
+/// the length is the first field and includes the actions
+pub const BUCKET_LEN_FIELD: LengthField = LengthField::u16_at(0, BUCKET_LEN);
+
 impl<'a> TryFrom<&'a [u8]> for Bucket {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
 
-        let len = cursor.read_u16::<BigEndian>().unwrap();
-        let weight = cursor.read_u16::<BigEndian>().unwrap();
-        let watch_port = PortNumber::try_from(cursor.read_u32::<BigEndian>().unwrap())?;
-        let watch_group = cursor.read_u32::<BigEndian>().unwrap();
+        let len = cursor.read_u16::<BigEndian>()?;
+        let weight = cursor.read_u16::<BigEndian>()?;
+        let watch_port = PortNumber::try_from(cursor.read_u32::<BigEndian>()?)?;
+        let watch_group = GroupNumber::try_from(cursor.read_u32::<BigEndian>()?)?;
         //4 bytes padding
-        cursor.seek(SeekFrom::Current(4)).unwrap();
-
-        let mut actions = Vec::new();
-        let mut bytes_remaining = (len - 16) as usize;
-        while bytes_remaining > 0 {
-            let action_len = ActionHeader::read_len(&mut cursor)?;
-            let action_slice =
-                &bytes[cursor.position() as usize..cursor.position() as usize + action_len];
-            let action = ActionHeader::try_from(action_slice)?;
-            actions.push(action);
-            cursor.seek(SeekFrom::Current(action_len as i64)).unwrap();
-            bytes_remaining -= action_len;
+        if (len as usize) < BUCKET_LEN {
+            bail!(ErrorKind::IllegalValue(len as u64, stringify!(Bucket)));
         }
+        let actions_slice = checked_slice(
+            bytes,
+            BUCKET_LEN,
+            len as usize - BUCKET_LEN,
+            stringify!(Bucket),
+        )?;
+        let actions = actions_from_slice(actions_slice).within(BUCKET_LEN)?;
 
         Ok(Bucket {
             len: len,
@@ -159,16 +312,229 @@ impl<'a> TryFrom<&'a [u8]> for Bucket {
     }
 }
 
+impl OfEncode for Bucket {
+    fn encoded_len(&self) -> usize {
+        BUCKET_LEN + self.actions.encoded_len()
+    }
+
+    /// the length is computed from the actions, the stored one is ignored
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.write_u16::<BigEndian>(self.encoded_len() as u16)
+            .unwrap();
+        buf.write_u16::<BigEndian>(self.weight).unwrap();
+        buf.write_u32::<BigEndian>(self.watch_port.clone().into())
+            .unwrap();
+        buf.write_u32::<BigEndian>(self.watch_group.clone().into())
+            .unwrap();
+        buf.write_u32::<BigEndian>(0).unwrap(); // pad 4 bytes
+        self.actions.encode_into(buf);
+    }
+}
+
 impl Into<Vec<u8>> for Bucket {
     fn into(self) -> Vec<u8> {
-        let mut res = Vec::new();
-        res.write_u16::<BigEndian>(self.len).unwrap();
-        res.write_u16::<BigEndian>(self.len).unwrap();
-        res.write_u32::<BigEndian>(self.watch_port.into()).unwrap();
-        res.write_u32::<BigEndian>(self.watch_group).unwrap();
-        for action in self.actions {
-            res.extend_from_slice(&Into::<Vec<u8>>::into(action)[..]);
+        self.to_bytes()
+    }
+}
+
+/// Length of the group features reply body (40 bytes).
+pub const GROUP_FEATURES_LENGTH: usize = 40;
+/// Number of group types the group features describe.
+pub const GROUP_TYPES: usize = 4;
+
+bitflags! {
+    /// Capabilities of the groups of a switch.
+    pub struct GroupCapabilities: u32 {
+        /// Support weight for select groups.
+        const SELECT_WEIGHT = 1 << 0;
+        /// Support liveness for select groups.
+        const SELECT_LIVENESS = 1 << 1;
+        /// Support chaining groups.
+        const CHAINING = 1 << 2;
+        /// Check chaining for loops and delete.
+        const CHAINING_CHECKS = 1 << 3;
+    }
+}
+
+/// Group features of a switch (ofp_group_features), the switch handle
+/// requests them after the handshake.
+#[derive(Debug, PartialEq, Clone)]
+pub struct GroupFeatures {
+    /// bitmap of the supported group types, see `supports`
+    pub types: u32,
+    pub capabilities: GroupCapabilities,
+    /// maximum number of groups of each type, indexed by the group type
+    pub max_groups: [u32; GROUP_TYPES],
+    /// bitmaps of the action types each group type supports, indexed by the group type
+    pub actions: [u32; GROUP_TYPES],
+}
+
+impl GroupFeatures {
+    pub fn supports(&self, ttype: &GroupType) -> bool {
+        self.types & (1 << ttype.to_u8().unwrap()) != 0
+    }
+
+    /// maximum number of groups of the type
+    pub fn max_groups(&self, ttype: &GroupType) -> u32 {
+        self.max_groups[ttype.to_u8().unwrap() as usize]
+    }
+
+    /// whether buckets of groups of the type can contain the action,
+    /// experimenter actions do not fit the bitmap and are assumed to be supported
+    pub fn supports_action(&self, ttype: &GroupType, action: &ActionType) -> bool {
+        match action.to_u16().unwrap() {
+            bit if bit < 32 => self.actions[ttype.to_u8().unwrap() as usize] & (1 << bit) != 0,
+            _ => true,
+        }
+    }
+
+    /// the first part of the group mod the switch does not support, None if
+    /// it supports all of them
+    pub fn unsupported(&self, group_mod: &GroupMod) -> Option<String> {
+        // deleted groups only need an id
+        if group_mod.command == GroupModCommand::Delete {
+            return None;
+        }
+        let ttype = &group_mod.ttype;
+        if !self.supports(ttype) {
+            return Some(format!("group type '{:?}'", ttype));
+        }
+        let actions = group_mod
+            .buckets
+            .iter()
+            .flat_map(|bucket| bucket.actions.iter());
+        for action in actions {
+            if !self.supports_action(ttype, action.ttype()) {
+                return Some(format!(
+                    "action '{:?}' in '{:?}' groups",
+                    action.ttype(),
+                    ttype
+                ));
+            }
+        }
+        let weighted = group_mod.buckets.iter().any(|bucket| bucket.weight != 0);
+        if *ttype == GroupType::Select
+            && weighted
+            && !self.capabilities.contains(GroupCapabilities::SELECT_WEIGHT)
+        {
+            return Some("weighted buckets".to_string());
+        }
+        None
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for GroupFeatures {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let bytes = checked_slice(bytes, 0, GROUP_FEATURES_LENGTH, stringify!(GroupFeatures))?;
+        let mut cursor = Cursor::new(bytes);
+        let types = cursor.read_u32::<BigEndian>()?;
+        // unknown capabilities are ignored
+        let capabilities = GroupCapabilities::from_bits_truncate(cursor.read_u32::<BigEndian>()?);
+        let mut max_groups = [0; GROUP_TYPES];
+        for value in max_groups.iter_mut() {
+            *value = cursor.read_u32::<BigEndian>()?;
+        }
+        let mut actions = [0; GROUP_TYPES];
+        for value in actions.iter_mut() {
+            *value = cursor.read_u32::<BigEndian>()?;
+        }
+        Ok(GroupFeatures {
+            types: types,
+            capabilities: capabilities,
+            max_groups: max_groups,
+            actions: actions,
+        })
+    }
+}
+
+impl Into<Vec<u8>> for GroupFeatures {
+    fn into(self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(GROUP_FEATURES_LENGTH);
+        res.write_u32::<BigEndian>(self.types).unwrap();
+        res.write_u32::<BigEndian>(self.capabilities.bits())
+            .unwrap();
+        for value in self.max_groups.iter().chain(self.actions.iter()) {
+            res.write_u32::<BigEndian>(*value).unwrap();
         }
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    use super::super::arbitrary::primitive;
+    use super::*;
+
+    fn bucket() -> BoxedStrategy<Bucket> {
+        (
+            any::<u16>(),
+            any::<PortNumber>(),
+            any::<GroupNumber>(),
+            vec(any::<ActionHeader>(), 0..4),
+        ).prop_map(|(weight, watch_port, watch_group, actions)| Bucket {
+                len: BUCKET_LEN as u16 + calc_actions_len(&actions),
+                weight: weight,
+                watch_port: watch_port,
+                watch_group: watch_group,
+                actions: actions,
+            })
+            .boxed()
+    }
+
+    fn group_mod() -> BoxedStrategy<GroupMod> {
+        (
+            any::<GroupModCommand>(),
+            primitive::<GroupType>(0, 0xff),
+            any::<GroupNumber>(),
+            vec(bucket(), 0..4),
+        ).prop_map(|(command, ttype, group_id, buckets)| GroupMod {
+                command: command,
+                ttype: ttype,
+                group_id: group_id,
+                buckets: buckets,
+            })
+            .boxed()
+    }
+
+    proptest! {
+        #[test]
+        fn bucket_round_trip(bucket in bucket()) {
+            prop_assert_round_trip!(bucket);
+        }
+
+        #[test]
+        fn group_mod_round_trip(group_mod in group_mod()) {
+            prop_assert_round_trip!(group_mod);
+        }
+    }
+
+    #[test]
+    fn parses_and_prints_group_numbers() {
+        assert_eq!("ALL".parse::<GroupNumber>().unwrap(), GroupNo::All.into());
+        assert_eq!(
+            "0x2a".parse::<GroupNumber>().unwrap(),
+            GroupNumber::Group(42)
+        );
+        assert_eq!("0".parse::<GroupNumber>().unwrap(), GroupNumber::Group(0));
+        assert!("bogus".parse::<GroupNumber>().is_err());
+        assert_eq!(GroupNumber::Reserved(GroupNo::Any).to_string(), "any");
+        assert!(GroupNumber::Reserved(GroupNo::All).is_reserved());
+        // the last usable group
+        assert!(!GroupNumber::Reserved(GroupNo::Max).is_reserved());
+        let raw: u32 = GroupNumber::Reserved(GroupNo::All).into();
+        assert_eq!(raw, 0xffff_fffc);
+    }
+
+    #[test]
+    fn rejects_unknown_reserved_groups() {
+        assert!(GroupNumber::try_from(0xffff_ff01).is_err());
+        assert_eq!(
+            GroupNumber::try_from(0xffff_ffff).unwrap(),
+            GroupNumber::Reserved(GroupNo::Any)
+        );
+    }
+}
@@ -1,16 +1,60 @@
+// used to allow MeterNo constants
+#![allow(overflowing_literals)]
+
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use num_traits::{FromPrimitive, ToPrimitive};
 use std::convert::{Into, TryFrom};
+use std::fmt;
 use std::io::Cursor;
+use std::str::FromStr;
+
+use super::codec::OfEncode;
+use super::tlv::{decode_list, encode_list, LengthField};
+use super::{checked_slice, checked_tail};
 
 use super::super::err::*;
 
-#[derive(Debug)]
+/// Length of the meter band header (type, len, rate and burst size).
+pub const METER_BAND_HEADER_LENGTH: usize = 12;
+/// The length is the second field of every band and includes the header.
+pub const METER_BAND_LEN_FIELD: LengthField = LengthField::u16_at(2, METER_BAND_HEADER_LENGTH);
+/// Length of the meter features reply body (16 bytes).
+pub const METER_FEATURES_LENGTH: usize = 16;
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct MeterMod {
     pub command: MeterModCommand,
     pub flags: MeterFlags,
-    pub meter_id: u32,
-    pub bands: Vec<MeterBandPayload>,
+    pub meter_id: MeterId,
+    pub bands: Vec<MeterBandHeader>,
+}
+
+impl<'a> TryFrom<&'a [u8]> for MeterMod {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(bytes);
+        let command_raw = cursor.read_u16::<BigEndian>()?;
+        let command = MeterModCommand::from_u16(command_raw).ok_or::<Error>(
+            ErrorKind::UnknownValue(command_raw as u64, stringify!(MeterModCommand)).into(),
+        )?;
+        let flags_raw = cursor.read_u16::<BigEndian>()?;
+        let flags = flags_from_bits!(MeterFlags, flags_raw)?;
+        let meter_id = MeterId::try_from(cursor.read_u32::<BigEndian>()?)?;
+
+        let bands = decode_list(
+            bytes,
+            cursor.position() as usize,
+            METER_BAND_LEN_FIELD,
+            stringify!(MeterBandHeader),
+        )?;
+
+        Ok(MeterMod {
+            command: command,
+            flags: flags,
+            meter_id: meter_id,
+            bands: bands,
+        })
+    }
 }
 
 impl Into<Vec<u8>> for MeterMod {
@@ -20,10 +64,8 @@ impl Into<Vec<u8>> for MeterMod {
         res.write_u16::<BigEndian>(self.command.to_u16().unwrap())
             .unwrap();
         res.write_u16::<BigEndian>(self.flags.bits()).unwrap();
-        res.write_u32::<BigEndian>(self.meter_id).unwrap();
-        for band in self.bands {
-            res.extend_from_slice(&Into::<Vec<u8>>::into(band)[..]);
-        }
+        res.write_u32::<BigEndian>(self.meter_id.into()).unwrap();
+        res.extend_from_slice(&encode_list(self.bands)[..]);
         res
     }
 }
@@ -39,6 +81,127 @@ pub enum MeterModCommand {
     Delete = 3,
 }
 
+/// Meter of a meter mod or a meter instruction.
+#[derive(Debug, PartialEq, Clone)]
+pub enum MeterId {
+    Reserved(MeterNo),
+    Meter(u32),
+}
+
+impl MeterId {
+    /// true for the virtual meters and all, max is the last usable meter
+    pub fn is_reserved(&self) -> bool {
+        match *self {
+            MeterId::Reserved(MeterNo::Max) | MeterId::Meter(_) => false,
+            MeterId::Reserved(_) => true,
+        }
+    }
+}
+
+impl TryFrom<u32> for MeterId {
+    type Error = Error;
+    /// fails for meter 0 and values above max that are no reserved meter
+    fn try_from(meter_id: u32) -> Result<Self> {
+        if meter_id == 0 {
+            bail!(ErrorKind::IllegalValue(0, stringify!(MeterId)));
+        }
+        Ok(match MeterNo::from_u32(meter_id) {
+            Some(meter) => MeterId::Reserved(meter),
+            None if meter_id > MeterNo::Max as u32 => {
+                bail!(ErrorKind::UnknownValue(
+                    meter_id as u64,
+                    stringify!(MeterNo)
+                ))
+            }
+            None => MeterId::Meter(meter_id),
+        })
+    }
+}
+
+impl fmt::Display for MeterId {
+    /// reserved meters are printed by name, eg. "controller", others as number
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MeterId::Reserved(ref meter) => write!(f, "{}", meter.name()),
+            MeterId::Meter(meter_id) => write!(f, "{}", meter_id),
+        }
+    }
+}
+
+impl FromStr for MeterId {
+    type Err = Error;
+    /// parses meter names (case insensitive) and decimal or `0x` prefixed numbers
+    fn from_str(s: &str) -> Result<Self> {
+        let lower = s.to_lowercase();
+        if let Some(meter) = MeterNo::from_name(&lower) {
+            return Ok(meter.into());
+        }
+        let parsed = match lower.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => lower.parse(),
+        };
+        match parsed {
+            Ok(meter_id) => MeterId::try_from(meter_id),
+            Err(_) => bail!(ErrorKind::InvalidMeter(s.to_string())),
+        }
+    }
+}
+
+impl Into<u32> for MeterId {
+    fn into(self) -> u32 {
+        match self {
+            MeterId::Reserved(meter) => meter.to_u32().unwrap(),
+            MeterId::Meter(meter_id) => meter_id,
+        }
+    }
+}
+
+/// Meter numbering. Flow meters can use any number up to max.
+#[derive(Primitive, PartialEq, Debug, Clone)]
+pub enum MeterNo {
+    /// Last usable meter.
+    Max = 0xffff0000,
+    /* Virtual meters. */
+    /// Meter for slow datapath.
+    Slowpath = 0xfffffffd,
+    /// Meter for controller connection.
+    Controller = 0xfffffffe,
+    /// Represents all meters for stat requests commands.
+    All = 0xffffffff,
+}
+
+impl MeterNo {
+    const NAMES: [(MeterNo, &'static str); 4] = [
+        (MeterNo::Max, "max"),
+        (MeterNo::Slowpath, "slowpath"),
+        (MeterNo::Controller, "controller"),
+        (MeterNo::All, "all"),
+    ];
+
+    /// lowercase name of the meter as used by ovs-ofctl, eg. "controller"
+    pub fn name(&self) -> &'static str {
+        MeterNo::NAMES
+            .iter()
+            .find(|&(meter, _)| meter == self)
+            .map(|&(_, name)| name)
+            .unwrap()
+    }
+
+    /// looks up a meter by its lowercase name
+    pub fn from_name(name: &str) -> Option<MeterNo> {
+        MeterNo::NAMES
+            .iter()
+            .find(|&&(_, meter_name)| meter_name == name)
+            .map(|(meter, _)| meter.clone())
+    }
+}
+
+impl Into<MeterId> for MeterNo {
+    fn into(self) -> MeterId {
+        MeterId::Reserved(self)
+    }
+}
+
 /* Meter configuration flags */
 bitflags!{
     pub struct MeterFlags: u16 {
@@ -54,29 +217,61 @@ bitflags!{
 }
 
 /// Common header for all meter bands
-#[derive(Debug)]
+#[derive(Getters, Debug, PartialEq, Clone)]
 pub struct MeterBandHeader {
     /// One of OFPMBT_*.
+    #[get = "pub"]
     ttype: MeterBandType,
     /// Length in bytes of this band.
     len: u16,
     /// Rate for this band.
+    #[get = "pub"]
     rate: u32,
     /// Size of bursts.
+    #[get = "pub"]
     burst_size: u32,
+    #[get = "pub"]
     payload: MeterBandPayload,
 }
 
+impl MeterBandHeader {
+    pub fn new(rate: u32, burst_size: u32, payload: MeterBandPayload) -> Self {
+        let ttype = match payload {
+            MeterBandPayload::Drop(_) => MeterBandType::Drop,
+            MeterBandPayload::Remark(_) => MeterBandType::DscpRemark,
+            MeterBandPayload::Experimenter(_) => MeterBandType::Experimenter,
+        };
+        // every band payload is 4 bytes
+        MeterBandHeader {
+            ttype: ttype,
+            len: (METER_BAND_HEADER_LENGTH + 4) as u16,
+            rate: rate,
+            burst_size: burst_size,
+            payload: payload,
+        }
+    }
+}
+
+impl OfEncode for MeterBandHeader {
+    fn encoded_len(&self) -> usize {
+        METER_BAND_HEADER_LENGTH + self.payload.encoded_len()
+    }
+
+    /// the length is computed from the payload, the stored one is ignored
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.write_u16::<BigEndian>(self.ttype.to_u16().unwrap())
+            .unwrap();
+        buf.write_u16::<BigEndian>(self.encoded_len() as u16)
+            .unwrap();
+        buf.write_u32::<BigEndian>(self.rate).unwrap();
+        buf.write_u32::<BigEndian>(self.burst_size).unwrap();
+        self.payload.encode_into(buf);
+    }
+}
+
 impl Into<Vec<u8>> for MeterBandHeader {
     fn into(self) -> Vec<u8> {
-        let mut res = Vec::new();
-        res.write_u16::<BigEndian>(self.ttype.to_u16().unwrap())
-            .unwrap();
-        res.write_u16::<BigEndian>(self.len).unwrap();
-        res.write_u32::<BigEndian>(self.rate).unwrap();
-        res.write_u32::<BigEndian>(self.burst_size).unwrap();
-        res.extend_from_slice(&Into::<Vec<u8>>::into(self.payload)[..]);
-        res
+        self.to_bytes()
     }
 }
 
@@ -84,15 +279,19 @@ impl<'a> TryFrom<&'a [u8]> for MeterBandHeader {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let ttype_raw = cursor.read_u16::<BigEndian>().unwrap();
+        let ttype_raw = cursor.read_u16::<BigEndian>()?;
         let ttype = MeterBandType::from_u16(ttype_raw).ok_or::<Error>(
             ErrorKind::UnknownValue(ttype_raw as u64, stringify!(MeterBandType)).into(),
         )?;
-        let len = cursor.read_u16::<BigEndian>().unwrap();
-        let rate = cursor.read_u32::<BigEndian>().unwrap();
-        let burst_size = cursor.read_u32::<BigEndian>().unwrap();
+        let len = cursor.read_u16::<BigEndian>()?;
+        let rate = cursor.read_u32::<BigEndian>()?;
+        let burst_size = cursor.read_u32::<BigEndian>()?;
 
-        let payload_slice = &bytes[12..];
+        let payload_slice = checked_tail(
+            bytes,
+            METER_BAND_HEADER_LENGTH,
+            stringify!(MeterBandHeader),
+        )?;
         let payload = match ttype {
             MeterBandType::Drop => MeterBandPayload::Drop(MeterBandDrop::try_from(payload_slice)?),
             MeterBandType::DscpRemark => {
@@ -164,7 +363,8 @@ impl<'a> TryFrom<&'a [u8]> for MeterBandDrop {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct MeterBandRemark {
-    prec_level: u8,
+    /// number of precedence levels to subtract
+    pub prec_level: u8,
     //pad 3 bytes
 }
 
@@ -184,7 +384,7 @@ impl<'a> TryFrom<&'a [u8]> for MeterBandRemark {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let prec_level = cursor.read_u8().unwrap();
+        let prec_level = cursor.read_u8()?;
         // pad by ignoring
         Ok(MeterBandRemark {
             prec_level: prec_level,
@@ -194,7 +394,7 @@ impl<'a> TryFrom<&'a [u8]> for MeterBandRemark {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct MeterBandExperimenter {
-    experimenter: u32,
+    pub experimenter: u32,
 }
 
 impl Into<Vec<u8>> for MeterBandExperimenter {
@@ -209,9 +409,133 @@ impl<'a> TryFrom<&'a [u8]> for MeterBandExperimenter {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let experimenter = cursor.read_u32::<BigEndian>().unwrap();
+        let experimenter = cursor.read_u32::<BigEndian>()?;
         Ok(MeterBandExperimenter {
             experimenter: experimenter,
         })
     }
 }
+
+/// Meter features of a switch (ofp_meter_features), the switch handle
+/// requests them after the handshake.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MeterFeatures {
+    /// maximum number of meters
+    pub max_meter: u32,
+    /// bitmap of the supported band types, see `supports_band`
+    pub band_types: u32,
+    /// the meter flags the switch supports
+    pub capabilities: MeterFlags,
+    /// maximum number of bands per meter
+    pub max_bands: u8,
+    /// maximum color value
+    pub max_color: u8,
+    // pad 2 bytes
+}
+
+impl MeterFeatures {
+    /// experimenter bands do not fit the bitmap and are assumed to be supported
+    pub fn supports_band(&self, ttype: &MeterBandType) -> bool {
+        match ttype.to_u16().unwrap() {
+            bit if bit < 32 => self.band_types & (1 << bit) != 0,
+            _ => true,
+        }
+    }
+
+    /// the first part of the meter mod the switch does not support, None if
+    /// it supports all of them
+    pub fn unsupported(&self, meter_mod: &MeterMod) -> Option<String> {
+        // deleted meters only need an id
+        if meter_mod.command == MeterModCommand::Delete {
+            return None;
+        }
+        let meter_id: u32 = meter_mod.meter_id.clone().into();
+        if !meter_mod.meter_id.is_reserved() && meter_id > self.max_meter {
+            return Some(format!(
+                "meter id '{}' above the maximum of '{}'",
+                meter_id, self.max_meter
+            ));
+        }
+        let missing = meter_mod.flags - self.capabilities;
+        if !missing.is_empty() {
+            return Some(format!("meter flags '{:?}'", missing));
+        }
+        if meter_mod.bands.len() > self.max_bands as usize {
+            return Some(format!(
+                "'{}' bands, at most '{}' are supported",
+                meter_mod.bands.len(),
+                self.max_bands
+            ));
+        }
+        meter_mod
+            .bands
+            .iter()
+            .find(|band| !self.supports_band(band.ttype()))
+            .map(|band| format!("meter band '{:?}'", band.ttype()))
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for MeterFeatures {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let bytes = checked_slice(bytes, 0, METER_FEATURES_LENGTH, stringify!(MeterFeatures))?;
+        let mut cursor = Cursor::new(bytes);
+        let max_meter = cursor.read_u32::<BigEndian>()?;
+        let band_types = cursor.read_u32::<BigEndian>()?;
+        // unknown capabilities are ignored
+        let capabilities = MeterFlags::from_bits_truncate(cursor.read_u32::<BigEndian>()? as u16);
+        let max_bands = cursor.read_u8()?;
+        let max_color = cursor.read_u8()?;
+        Ok(MeterFeatures {
+            max_meter: max_meter,
+            band_types: band_types,
+            capabilities: capabilities,
+            max_bands: max_bands,
+            max_color: max_color,
+        })
+    }
+}
+
+impl Into<Vec<u8>> for MeterFeatures {
+    fn into(self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(METER_FEATURES_LENGTH);
+        res.write_u32::<BigEndian>(self.max_meter).unwrap();
+        res.write_u32::<BigEndian>(self.band_types).unwrap();
+        res.write_u32::<BigEndian>(u32::from(self.capabilities.bits()))
+            .unwrap();
+        res.write_u8(self.max_bands).unwrap();
+        res.write_u8(self.max_color).unwrap();
+        res.write_u16::<BigEndian>(0).unwrap(); // pad 2 bytes
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_prints_meter_ids() {
+        assert_eq!(
+            "CONTROLLER".parse::<MeterId>().unwrap(),
+            MeterId::Reserved(MeterNo::Controller)
+        );
+        assert_eq!("0x2a".parse::<MeterId>().unwrap(), MeterId::Meter(42));
+        assert!("0".parse::<MeterId>().is_err());
+        assert!("bogus".parse::<MeterId>().is_err());
+        assert_eq!(MeterId::Reserved(MeterNo::Slowpath).to_string(), "slowpath");
+        assert!(MeterId::Reserved(MeterNo::All).is_reserved());
+        assert!(!MeterId::Reserved(MeterNo::Max).is_reserved());
+        let raw: u32 = MeterId::Reserved(MeterNo::Slowpath).into();
+        assert_eq!(raw, 0xffff_fffd);
+    }
+
+    #[test]
+    fn rejects_unknown_reserved_meters() {
+        assert!(MeterId::try_from(0xffff_0001).is_err());
+        assert_eq!(
+            MeterId::try_from(0xffff_ffff).unwrap(),
+            MeterId::Reserved(MeterNo::All)
+        );
+    }
+}
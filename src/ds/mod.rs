@@ -1,32 +1,57 @@
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
 use num_traits::{FromPrimitive, ToPrimitive};
 use std::convert::{Into, TryFrom};
 use std::io::Cursor;
 
 use super::err::*;
-use std::path;
+use self::codec::OfEncode;
 
+// first, the other modules decode their flags with its macro
+#[macro_use]
+pub mod flags;
 pub mod actions;
+pub mod addr;
+#[cfg(test)]
+#[macro_use]
+mod arbitrary;
 pub mod async;
+pub mod bundle;
+pub mod capture;
+pub mod codec;
+pub mod error_msg;
+#[macro_use]
+pub mod factory;
 pub mod features;
 pub mod flow_instructions;
 pub mod flow_match;
 pub mod flow_mod;
+pub mod flow_parser;
 pub mod flow_removed;
+pub mod flow_stats;
+#[cfg(test)]
+mod golden;
 pub mod group_mod;
-pub mod hw_addr;
 pub mod meter_mod;
 pub mod multipart;
+#[cfg(feature = "nicira")]
+pub mod nx;
+pub mod packet;
 pub mod packet_in;
 pub mod packet_out;
 pub mod packet_queue;
 pub mod port_mod;
+pub mod port_stats;
 pub mod port_status;
 pub mod ports;
 pub mod queue_config;
 pub mod role;
+pub mod stream;
 pub mod switch_config;
+pub mod table_features;
 pub mod table_mod;
+pub mod tlv;
+pub mod validate;
+pub mod wire;
 
 /// defines an OpenFlow message
 /// header + payload
@@ -52,18 +77,122 @@ impl OfMsg {
             payload: payload,
         }
     }
+
+    /// same as generate but for the given (negotiated) version
+    /// fails if the payload can not be sent with this version
+    pub fn generate_for(version: Version, xid: u32, payload: OfPayload) -> Result<Self> {
+        Ok(OfMsg {
+            header: payload.generate_header_for(version, xid)?,
+            payload: payload,
+        })
+    }
+
+    /// changes the header version, eg. to the version negotiated with a switch
+    /// fails if the payload can not be sent with this version
+    pub fn with_version(mut self, version: Version) -> Result<Self> {
+        if !self.payload.available_in(version) {
+            bail!(ErrorKind::UnsupportedValue(
+                version.to_u8().unwrap() as u64,
+                stringify!(Version)
+            ));
+        }
+        self.header.version = version;
+        Ok(self)
+    }
+
+    /// encodes the message, the header length is set to the encoded length
+    /// fails if the message does not fit the u16 length of the header
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let vec = self.to_bytes();
+        if vec.len() > MAX_MESSAGE_LENGTH {
+            bail!(ErrorKind::MessageTooLong(vec.len()));
+        }
+        Ok(vec)
+    }
+
+    /// rules of the specification the payload breaks, see `validate`
+    pub fn validate(&self) -> Vec<validate::Violation> {
+        self.payload.validate()
+    }
+
+    /// same as try_from but handles unknown flag bits with the given policy
+    /// returns the bits that were dropped next to the message
+    pub fn decode_with(
+        policy: flags::FlagsPolicy,
+        bytes: &[u8],
+    ) -> (Result<OfMsg>, Vec<flags::UnknownFlags>) {
+        flags::decode_with(policy, || OfMsg::try_from(bytes))
+    }
 }
 
+impl<'a> TryFrom<&'a [u8]> for OfMsg {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        if bytes.len() < HEADER_LENGTH {
+            bail!(ErrorKind::InvalidSliceLength(
+                HEADER_LENGTH,
+                bytes.len(),
+                stringify!(OfMsg),
+            ));
+        }
+        let header = Header::try_from(&bytes[..HEADER_LENGTH])?;
+        if bytes.len() != *header.length() as usize {
+            bail!(ErrorKind::InvalidSliceLength(
+                *header.length() as usize,
+                bytes.len(),
+                stringify!(OfMsg),
+            ));
+        }
+        let payload_bytes = &bytes[HEADER_LENGTH..];
+        let payload = OfPayload::decode_for(*header.version(), header.ttype(), payload_bytes)
+            .map_err(|err| {
+                err.within(HEADER_LENGTH)
+                    .in_message(header.ttype().clone(), Some(*header.xid()))
+                    .excerpt_from(bytes)
+            })?;
+        Ok(OfMsg::new(header, payload))
+    }
+}
+
+/// the header length is set to the encoded length, the length of messages that
+/// are too long is capped, messages containing it (eg. bundle adds) fail to encode instead
+impl OfEncode for OfMsg {
+    fn encoded_len(&self) -> usize {
+        HEADER_LENGTH + self.payload.encoded_len()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.payload.size_hint().map(|len| HEADER_LENGTH + len)
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        self.header.encode_into(buf);
+        self.payload.encode_into(buf);
+        let length = (buf.len() - start).min(MAX_MESSAGE_LENGTH) as u16;
+        BigEndian::write_u16(&mut buf[start + 2..start + 4], length);
+    }
+}
+
+
+/// deprecated, use `OfMsg::encode`, which fails for messages longer than
+/// MAX_MESSAGE_LENGTH; their header length is cut to MAX_MESSAGE_LENGTH here
 impl Into<Vec<u8>> for OfMsg {
     fn into(self) -> Vec<u8> {
-        let mut vec = Into::<Vec<u8>>::into(self.header);
-        vec.extend_from_slice(&Into::<Vec<u8>>::into(self.payload)[..]);
-        vec
+        let bytes = self.to_bytes();
+        debug_assert!(
+            bytes.len() <= MAX_MESSAGE_LENGTH,
+            "message of {} bytes does not fit the header length",
+            bytes.len()
+        );
+        bytes
     }
 }
 
 /// OpenFlow message header length is 8 bytes.
 pub const HEADER_LENGTH: usize = 8;
+/// Longest message the u16 length of the header can describe, including the header.
+pub const MAX_MESSAGE_LENGTH: usize = 0xffff;
 
 /// OpenFlow header struct.
 #[derive(Getters, Debug, PartialEq, Clone)]
@@ -109,70 +238,52 @@ impl<'a> TryFrom<&'a [u8]> for Header {
         }
         let mut cursor = Cursor::new(bytes);
 
-        let version_raw = cursor.read_u8().chain_err(|| {
-            let err_msg = format!(
-                "Could not read header version!{}Cursor: {:?}",
-                path::MAIN_SEPARATOR,
-                cursor
-            );
-            error!("{}", err_msg);
-            err_msg
-        })?;
+        let version_raw = cursor.read_u8().at(cursor.position())?;
         let version = Version::from_u8(version_raw).ok_or::<Error>(
             ErrorKind::UnknownValue(version_raw as u64, stringify!(Version)).into(),
         )?;
 
-        let ttype_raw = cursor.read_u8().chain_err(|| {
-            let err_msg = format!(
-                "Could not read header type!{}Cursor: {:?}",
-                path::MAIN_SEPARATOR,
-                cursor
-            );
-            error!("{}", err_msg);
-            err_msg
-        })?;
+        let ttype_raw = cursor.read_u8().at(cursor.position())?;
         let ttype = Type::from_u8(ttype_raw)
             .ok_or::<Error>(ErrorKind::UnknownValue(ttype_raw as u64, stringify!(Type)).into())?;
 
+        let length = cursor.read_u16::<BigEndian>().at(cursor.position())?;
+        // the length includes the header
+        if (length as usize) < HEADER_LENGTH {
+            bail!(ErrorKind::IllegalValue(length as u64, stringify!(Header)));
+        }
+
         // build result
         Ok(Header {
             version: version,
             ttype: ttype,
-            length: cursor.read_u16::<BigEndian>().chain_err(|| {
-                let err_msg = format!(
-                    "Could not read header length!{}Cursor: {:?}",
-                    path::MAIN_SEPARATOR,
-                    cursor
-                );
-                error!("{}", err_msg);
-                err_msg
-            })?,
-            xid: cursor.read_u32::<BigEndian>().chain_err(|| {
-                let err_msg = format!(
-                    "Could not read header xid!{}Cursor: {:?}",
-                    path::MAIN_SEPARATOR,
-                    cursor
-                );
-                error!("{}", err_msg);
-                err_msg
-            })?,
+            length: length,
+            xid: cursor.read_u32::<BigEndian>().at(cursor.position())?,
         })
     }
 }
 
+impl OfEncode for Header {
+    fn encoded_len(&self) -> usize {
+        HEADER_LENGTH
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.write_u8(self.version.to_u8().unwrap()).unwrap();
+        buf.write_u8(self.ttype.to_u8().unwrap()).unwrap();
+        buf.write_u16::<BigEndian>(self.length).unwrap();
+        buf.write_u32::<BigEndian>(self.xid).unwrap();
+    }
+}
+
 impl Into<Vec<u8>> for Header {
     fn into(self) -> Vec<u8> {
-        let mut res = Vec::new();
-        res.write_u8(self.version.to_u8().unwrap()).unwrap();
-        res.write_u8(self.ttype.to_u8().unwrap()).unwrap();
-        res.write_u16::<BigEndian>(self.length).unwrap();
-        res.write_u32::<BigEndian>(self.xid).unwrap();
-        res
+        self.to_bytes()
     }
 }
 
 /// OpenFlow Version enum.
-#[derive(Primitive, PartialEq, Debug, Clone)]
+#[derive(Primitive, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
 pub enum Version {
     /// indicates OpenFlow version 1.0
     V1_0 = 0x01,
@@ -184,10 +295,26 @@ pub enum Version {
     V1_3 = 0x04,
     /// indicates OpenFlow version 1.4
     V1_4 = 0x05,
+    /// indicates OpenFlow version 1.5
+    V1_5 = 0x06,
+}
+
+/// Highest version the controller offers during version negotiation.
+/// Newer versions are spoken within the message set they share with 1.3,
+/// see OfPayload::available_in.
+pub const MAX_VERSION: Version = Version::V1_5;
+
+impl Version {
+    /// version both sides use after exchanging hellos (the lower one)
+    pub fn negotiate(peer: Version) -> Version {
+        ::std::cmp::min(peer, MAX_VERSION)
+    }
 }
 
 /// Enum of OpenFlow message types.
-#[derive(Primitive, PartialEq, Debug, Clone)]
+/// The conversions are written by hand, the TryFrom impl generated by
+/// `Primitive` clashes with the `Error` variant (ambiguous `Self::Error`).
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub enum Type {
     /* Immutable messages. */
     /// Hello message sent by switch and controller
@@ -269,14 +396,78 @@ pub enum Type {
     /* Meters and rate limiters configuration messages. */
     /// Controller/switch message
     MeterMod = 29,
+
+    /* Bundle operations (since 1.4). */
+    /// Controller/switch message
+    BundleControl = 33,
+    /// Controller/switch message
+    BundleAddMessage = 34,
+}
+
+impl FromPrimitive for Type {
+    fn from_i64(val: i64) -> Option<Self> {
+        if val < 0 {
+            return None;
+        }
+        Type::from_u64(val as u64)
+    }
+
+    fn from_u64(val: u64) -> Option<Self> {
+        Some(match val {
+            0 => Type::Hello,
+            1 => Type::Error,
+            2 => Type::EchoRequest,
+            3 => Type::EchoReply,
+            4 => Type::Experimenter,
+            5 => Type::FeaturesRequest,
+            6 => Type::FeaturesReply,
+            7 => Type::GetConfigRequest,
+            8 => Type::GetConfigReply,
+            9 => Type::SetConfig,
+            10 => Type::PacketIn,
+            11 => Type::FlowRemoved,
+            12 => Type::PortStatus,
+            13 => Type::PacketOut,
+            14 => Type::FlowMod,
+            15 => Type::GroupMod,
+            16 => Type::PortMod,
+            17 => Type::TableMod,
+            18 => Type::MultipartRequest,
+            19 => Type::MultipartReply,
+            20 => Type::BarrierRequest,
+            21 => Type::BarrierReply,
+            22 => Type::QueueGetConfigRequest,
+            23 => Type::QueueGetConfigReply,
+            24 => Type::RoleRequest,
+            25 => Type::RoleReply,
+            26 => Type::GetAsyncRequest,
+            27 => Type::GetAsyncReply,
+            28 => Type::SetAsync,
+            29 => Type::MeterMod,
+            33 => Type::BundleControl,
+            34 => Type::BundleAddMessage,
+            _ => return None,
+        })
+    }
+}
+
+impl ToPrimitive for Type {
+    fn to_i64(&self) -> Option<i64> {
+        Some(self.clone() as i64)
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        Some(self.clone() as u64)
+    }
 }
 
 #[derive(Debug)]
 pub enum OfPayload {
     Hello,
-    Error,
-    EchoRequest,
-    EchoReply,
+    Error(error_msg::ErrorMsg),
+    /// arbitrary data the reply has to carry back unchanged
+    EchoRequest(Vec<u8>),
+    EchoReply(Vec<u8>),
     Experimenter,
 
     FeaturesRequest,
@@ -312,46 +503,530 @@ pub enum OfPayload {
     SetAsync(async::Async),
 
     MeterMod(meter_mod::MeterMod),
+
+    BundleControl(bundle::BundleCtrl),
+    BundleAddMessage(bundle::BundleAdd),
 }
 
 impl OfPayload {
+    /// generates an OpenFlow 1.3 header for this payload
+    /// payloads that are newer than 1.3 (eg. bundles) get a MAX_VERSION header
     pub fn generate_header(&self, xid: u32) -> Header {
+        let version = if self.available_in(Version::V1_3) {
+            Version::V1_3
+        } else {
+            MAX_VERSION
+        };
+        self.generate_header_for(version, xid)
+            .expect("all payloads are available in 1.3 or MAX_VERSION")
+    }
+
+    /// generates a header of the given version for this payload
+    pub fn generate_header_for(&self, version: Version, xid: u32) -> Result<Header> {
+        if !self.available_in(version) {
+            bail!(ErrorKind::UnsupportedValue(
+                version.to_u8().unwrap() as u64,
+                stringify!(Version)
+            ));
+        }
         //create basic default header
         let mut header = Header {
-            version: Version::V1_3,
+            version: version,
             ttype: Type::Hello,
             length: HEADER_LENGTH as u16,
             xid: xid,
         };
         //change header depending on payload
+        header.ttype = self.ttype();
+        // the length of other payloads is set when the message is encoded
+        if let OfPayload::PacketOut(payload) = self {
+            // capped, encoding rejects messages that are too long
+            let len = HEADER_LENGTH + packet_out::PACKET_OUT_LEN + payload.actions_len as usize
+                + payload.data.len();
+            header.length = len.min(MAX_MESSAGE_LENGTH) as u16;
+        }
+        Ok(header)
+    }
+
+    /// the message type of this payload
+    pub fn ttype(&self) -> Type {
+        match self {
+            OfPayload::Hello => Type::Hello,
+            OfPayload::Error(_) => Type::Error,
+            OfPayload::EchoRequest(_) => Type::EchoRequest,
+            OfPayload::EchoReply(_) => Type::EchoReply,
+            OfPayload::Experimenter => Type::Experimenter,
+            OfPayload::FeaturesRequest => Type::FeaturesRequest,
+            OfPayload::FeaturesReply(_) => Type::FeaturesReply,
+            OfPayload::GetConfigRequest => Type::GetConfigRequest,
+            OfPayload::GetConfigReply(_) => Type::GetConfigReply,
+            OfPayload::SetConfig(_) => Type::SetConfig,
+            OfPayload::PacketIn(_) => Type::PacketIn,
+            OfPayload::FlowRemoved(_) => Type::FlowRemoved,
+            OfPayload::PortStatus(_) => Type::PortStatus,
+            OfPayload::PacketOut(_) => Type::PacketOut,
+            OfPayload::FlowMod(_) => Type::FlowMod,
+            OfPayload::GroupMod(_) => Type::GroupMod,
+            OfPayload::PortMod(_) => Type::PortMod,
+            OfPayload::TableMod(_) => Type::TableMod,
+            OfPayload::MultipartRequest(_) => Type::MultipartRequest,
+            OfPayload::MultipartReply(_) => Type::MultipartReply,
+            OfPayload::BarrierRequest => Type::BarrierRequest,
+            OfPayload::BarrierReply => Type::BarrierReply,
+            OfPayload::QueueGetConfigRequest(_) => Type::QueueGetConfigRequest,
+            OfPayload::QueueGetConfigReply(_) => Type::QueueGetConfigReply,
+            OfPayload::RoleRequest(_) => Type::RoleRequest,
+            OfPayload::RoleReply(_) => Type::RoleReply,
+            OfPayload::GetAsyncRequest => Type::GetAsyncRequest,
+            OfPayload::GetAsyncReply(_) => Type::GetAsyncReply,
+            OfPayload::SetAsync(_) => Type::SetAsync,
+            OfPayload::MeterMod(_) => Type::MeterMod,
+            OfPayload::BundleControl(_) => Type::BundleControl,
+            OfPayload::BundleAddMessage(_) => Type::BundleAddMessage,
+        }
+    }
+
+    /// checks if this payload can be sent with the given version
+    /// payloads are encoded in their 1.3 layout, so versions before 1.3 only
+    /// support messages without body and newer versions only support the
+    /// messages whose type and layout did not change
+    pub fn available_in(&self, version: Version) -> bool {
         match self {
-            OfPayload::Hello => (),
-            //OfPayload::Error,
-            OfPayload::EchoRequest => {
-                header.ttype = Type::EchoRequest;
+            // same type and body in every version
+            OfPayload::Hello
+            | OfPayload::EchoRequest(_)
+            | OfPayload::EchoReply(_)
+            | OfPayload::FeaturesRequest
+            | OfPayload::GetConfigRequest
+            | OfPayload::GetConfigReply(_)
+            | OfPayload::SetConfig(_) => true,
+            _ if version < Version::V1_3 => false,
+            // replaced by multipart queue descriptions in 1.4
+            OfPayload::QueueGetConfigRequest(_) | OfPayload::QueueGetConfigReply(_) => {
+                version < Version::V1_4
             }
-            OfPayload::EchoReply => {
-                header.ttype = Type::EchoReply;
+            // properties were appended in 1.4
+            OfPayload::TableMod(table_mod) => {
+                table_mod.properties().is_empty() || version >= Version::V1_4
             }
-            OfPayload::PacketOut(payload) => {
-                header.ttype = Type::PacketOut;
-                header.length += packet_out::PACKET_OUT_LEN as u16 + payload.actions_len as u16
-                    + payload.data.len() as u16;
+            // layouts changed to property lists in 1.4
+            OfPayload::PortMod(_)
+            | OfPayload::PortStatus(_)
+            | OfPayload::GetAsyncReply(_)
+            | OfPayload::SetAsync(_) => version < Version::V1_4,
+            // in_port replaced by a match (packet out) and bucket layout (groups) changed in 1.5
+            OfPayload::PacketOut(_) | OfPayload::GroupMod(_) => version < Version::V1_5,
+            // the counters moved to a list of oxs fields in 1.5
+            OfPayload::FlowRemoved(_) => version < Version::V1_5,
+            // fields added in 1.5 (packet type)
+            OfPayload::FlowMod(flow_mod) => flow_mod.mmatch.min_version() <= version,
+            OfPayload::MultipartRequest(request) => match request.payload {
+                multipart::ReqPayload::Flow(ref flow) => flow.mmatch.min_version() <= version,
+                // only the reply layout of 1.3 is supported
+                multipart::ReqPayload::PortStats(_) => version < Version::V1_4,
+                _ => true,
+            },
+            // flow stats were replaced by flow descriptions in 1.5
+            OfPayload::MultipartReply(reply) => match reply.payload {
+                multipart::RepPayload::Flow(_) => version < Version::V1_5,
+                multipart::RepPayload::FlowDesc(_) => version >= Version::V1_5,
+                multipart::RepPayload::PortStats(_) => version < Version::V1_4,
+                _ => true,
+            },
+            OfPayload::BundleControl(_) | OfPayload::BundleAddMessage(_) => {
+                version >= Version::V1_4
             }
-            _ => panic!("illegal or not implemented header gen for {:?}", self),
+            _ => true,
+        }
+    }
+
+    /// capabilities the switch has to advertise in its features to handle this payload
+    pub fn required_capabilities(&self) -> features::Capabilities {
+        match self {
+            OfPayload::MultipartRequest(request) => request.ttype().capability(),
+            _ => features::Capabilities::empty(),
+        }
+    }
+
+    /// rules of the specification the payload breaks, see `validate`
+    pub fn validate(&self) -> Vec<validate::Violation> {
+        validate::validate(self)
+    }
+}
+
+impl OfPayload {
+    /// decodes the payload bytes (everything after the header) of a message of the given type
+    /// only messages a switch may send to the controller are supported
+    /// uses the OpenFlow 1.3 layout, see `OfPayload::decode_for`
+    pub fn try_from(ttype: &Type, bytes: &[u8]) -> Result<OfPayload> {
+        OfPayload::decode_for(Version::V1_3, ttype, bytes)
+    }
+
+    /// same as decode_for but handles unknown flag bits with the given policy
+    /// returns the bits that were dropped next to the payload
+    pub fn decode_with(
+        policy: flags::FlagsPolicy,
+        version: Version,
+        ttype: &Type,
+        bytes: &[u8],
+    ) -> (Result<OfPayload>, Vec<flags::UnknownFlags>) {
+        flags::decode_with(policy, || OfPayload::decode_for(version, ttype, bytes))
+    }
+
+    /// same as try_from but uses the layout of the given (negotiated) version
+    /// where it differs, eg. for flow stats replies of 1.5
+    pub fn decode_for(version: Version, ttype: &Type, bytes: &[u8]) -> Result<OfPayload> {
+        match ttype {
+            Type::Hello
+            | Type::Error
+            | Type::EchoRequest
+            | Type::EchoReply
+            | Type::FeaturesReply
+            | Type::GetConfigReply
+            | Type::PacketIn
+            | Type::FlowRemoved
+            | Type::PortStatus
+            | Type::MultipartReply
+            | Type::BarrierReply
+            | Type::QueueGetConfigReply
+            | Type::RoleReply
+            | Type::GetAsyncReply
+            | Type::BundleControl => decode_payload(version, ttype, bytes),
+            _ => bail!(ErrorKind::UnsupportedValue(
+                ttype.to_u8().unwrap() as u64,
+                stringify!(Type),
+            )),
+        }
+    }
+}
+
+/// implements `as_x` and `into_x` on OfPayload for the variants with a message
+macro_rules! payload_accessors {
+    ($($variant:ident($message:ty) => $as_message:ident, $into_message:ident;)*) => {
+        impl OfPayload {
+            $(
+                /// the message, None if the payload is another message
+                pub fn $as_message(&self) -> Option<&$message> {
+                    match *self {
+                        OfPayload::$variant(ref message) => Some(message),
+                        _ => None,
+                    }
+                }
+
+                /// the message, None if the payload is another message
+                pub fn $into_message(self) -> Option<$message> {
+                    match self {
+                        OfPayload::$variant(message) => Some(message),
+                        _ => None,
+                    }
+                }
+            )*
+        }
+    };
+}
+
+payload_accessors! {
+    Error(error_msg::ErrorMsg) => as_error, into_error;
+    EchoRequest(Vec<u8>) => as_echo_request, into_echo_request;
+    EchoReply(Vec<u8>) => as_echo_reply, into_echo_reply;
+    FeaturesReply(features::SwitchFeatures) => as_features_reply, into_features_reply;
+    GetConfigReply(switch_config::SwitchConfig) => as_get_config_reply, into_get_config_reply;
+    SetConfig(switch_config::SwitchConfig) => as_set_config, into_set_config;
+    PacketIn(packet_in::PacketIn) => as_packet_in, into_packet_in;
+    FlowRemoved(flow_removed::FlowRemoved) => as_flow_removed, into_flow_removed;
+    PortStatus(port_status::PortStatus) => as_port_status, into_port_status;
+    PacketOut(packet_out::PacketOut) => as_packet_out, into_packet_out;
+    FlowMod(flow_mod::FlowMod) => as_flow_mod, into_flow_mod;
+    GroupMod(group_mod::GroupMod) => as_group_mod, into_group_mod;
+    PortMod(port_mod::PortMod) => as_port_mod, into_port_mod;
+    TableMod(table_mod::TableMod) => as_table_mod, into_table_mod;
+    MultipartRequest(multipart::MultipartRequest) => as_multipart_request, into_multipart_request;
+    MultipartReply(multipart::MultipartReply) => as_multipart_reply, into_multipart_reply;
+    QueueGetConfigRequest(queue_config::QueueGetConfigRequest)
+        => as_queue_get_config_request, into_queue_get_config_request;
+    QueueGetConfigReply(queue_config::QueueGetConfigReply)
+        => as_queue_get_config_reply, into_queue_get_config_reply;
+    RoleRequest(role::Role) => as_role_request, into_role_request;
+    RoleReply(role::Role) => as_role_reply, into_role_reply;
+    GetAsyncReply(async::Async) => as_get_async_reply, into_get_async_reply;
+    SetAsync(async::Async) => as_set_async, into_set_async;
+    MeterMod(meter_mod::MeterMod) => as_meter_mod, into_meter_mod;
+    BundleControl(bundle::BundleCtrl) => as_bundle_control, into_bundle_control;
+    BundleAddMessage(bundle::BundleAdd) => as_bundle_add_message, into_bundle_add_message;
+}
+
+/// implements `TryFrom<OfPayload>` for the messages only one variant carries,
+/// the payload is handed back if it is another message
+macro_rules! payload_try_from {
+    ($($variant:ident($message:ty),)*) => {
+        $(
+            impl TryFrom<OfPayload> for $message {
+                type Error = OfPayload;
+                fn try_from(payload: OfPayload) -> ::std::result::Result<Self, OfPayload> {
+                    match payload {
+                        OfPayload::$variant(message) => Ok(message),
+                        other => Err(other),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+payload_try_from! {
+    Error(error_msg::ErrorMsg),
+    FeaturesReply(features::SwitchFeatures),
+    PacketIn(packet_in::PacketIn),
+    FlowRemoved(flow_removed::FlowRemoved),
+    PortStatus(port_status::PortStatus),
+    PacketOut(packet_out::PacketOut),
+    FlowMod(flow_mod::FlowMod),
+    GroupMod(group_mod::GroupMod),
+    PortMod(port_mod::PortMod),
+    TableMod(table_mod::TableMod),
+    MultipartRequest(multipart::MultipartRequest),
+    MultipartReply(multipart::MultipartReply),
+    QueueGetConfigRequest(queue_config::QueueGetConfigRequest),
+    QueueGetConfigReply(queue_config::QueueGetConfigReply),
+    MeterMod(meter_mod::MeterMod),
+    BundleControl(bundle::BundleCtrl),
+    BundleAddMessage(bundle::BundleAdd),
+}
+
+/// decodes the payload bytes of a message of the given type and version
+/// in contrast to OfPayload::try_from messages of both directions are decoded
+/// never panics, invalid or truncated input results in an error
+pub fn decode_any(version: Version, ttype: &Type, bytes: &[u8]) -> Result<OfPayload> {
+    let payload = decode_payload(version, ttype, bytes)
+        .map_err(|err| err.in_message(ttype.clone(), None).excerpt_from(bytes))?;
+    if !payload.available_in(version) {
+        bail!(ErrorKind::UnsupportedValue(
+            version.to_u8().unwrap() as u64,
+            stringify!(Version)
+        ));
+    }
+    Ok(payload)
+}
+
+/// length of the payload of messages that always have the same size in the
+/// versions they are decoded for
+fn fixed_payload_length(ttype: &Type) -> Option<usize> {
+    match ttype {
+        Type::FeaturesRequest
+        | Type::GetConfigRequest
+        | Type::BarrierRequest
+        | Type::BarrierReply
+        | Type::GetAsyncRequest => Some(0),
+        Type::GetConfigReply | Type::SetConfig => Some(4),
+        Type::QueueGetConfigRequest => Some(8),
+        Type::RoleRequest | Type::RoleReply => Some(16),
+        Type::GetAsyncReply | Type::SetAsync => Some(24),
+        Type::PortMod => Some(32),
+        Type::PortStatus => Some(8 + ports::PORT_LENGTH),
+        _ => None,
+    }
+}
+
+fn decode_payload(version: Version, ttype: &Type, bytes: &[u8]) -> Result<OfPayload> {
+    // trailing bytes mean the length of the header does not match the payload,
+    // shorter payloads are rejected by the decoders
+    match fixed_payload_length(ttype) {
+        Some(len) if bytes.len() > len => {
+            let err = ErrorKind::InvalidSliceLength(len, bytes.len(), stringify!(OfPayload));
+            return Err(Error::from(err).at(len));
+        }
+        _ => (),
+    }
+    Ok(match ttype {
+        Type::Hello => OfPayload::Hello,
+        Type::Error => OfPayload::Error(error_msg::ErrorMsg::try_from(bytes)?),
+        Type::EchoRequest => OfPayload::EchoRequest(bytes.to_vec()),
+        Type::EchoReply => OfPayload::EchoReply(bytes.to_vec()),
+        Type::Experimenter => OfPayload::Experimenter,
+        Type::FeaturesRequest => OfPayload::FeaturesRequest,
+        Type::FeaturesReply => OfPayload::FeaturesReply(features::SwitchFeatures::try_from(bytes)?),
+        Type::GetConfigRequest => OfPayload::GetConfigRequest,
+        Type::GetConfigReply => {
+            OfPayload::GetConfigReply(switch_config::SwitchConfig::try_from(bytes)?)
+        }
+        Type::SetConfig => OfPayload::SetConfig(switch_config::SwitchConfig::try_from(bytes)?),
+        Type::PacketIn => OfPayload::PacketIn(packet_in::PacketIn::try_from(bytes)?),
+        Type::FlowRemoved => OfPayload::FlowRemoved(flow_removed::FlowRemoved::try_from(bytes)?),
+        Type::PortStatus => OfPayload::PortStatus(port_status::PortStatus::try_from(bytes)?),
+        Type::PacketOut => OfPayload::PacketOut(packet_out::PacketOut::try_from(bytes)?),
+        Type::FlowMod => OfPayload::FlowMod(flow_mod::FlowMod::try_from(bytes)?),
+        Type::GroupMod => OfPayload::GroupMod(group_mod::GroupMod::try_from(bytes)?),
+        Type::PortMod => OfPayload::PortMod(port_mod::PortMod::try_from(bytes)?),
+        Type::TableMod => OfPayload::TableMod(table_mod::TableMod::try_from(bytes)?),
+        Type::BarrierRequest => OfPayload::BarrierRequest,
+        Type::BarrierReply => OfPayload::BarrierReply,
+        Type::QueueGetConfigRequest => OfPayload::QueueGetConfigRequest(
+            queue_config::QueueGetConfigRequest::try_from(bytes)?,
+        ),
+        Type::QueueGetConfigReply => OfPayload::QueueGetConfigReply(
+            queue_config::QueueGetConfigReply::try_from(bytes)?,
+        ),
+        Type::RoleRequest => OfPayload::RoleRequest(role::Role::try_from(bytes)?),
+        Type::RoleReply => OfPayload::RoleReply(role::Role::try_from(bytes)?),
+        Type::GetAsyncRequest => OfPayload::GetAsyncRequest,
+        Type::GetAsyncReply => OfPayload::GetAsyncReply(async::Async::try_from(bytes)?),
+        Type::SetAsync => OfPayload::SetAsync(async::Async::try_from(bytes)?),
+        Type::MultipartRequest => {
+            OfPayload::MultipartRequest(multipart::MultipartRequest::try_from(bytes)?)
+        }
+        Type::MultipartReply => {
+            OfPayload::MultipartReply(multipart::MultipartReply::decode_for(version, bytes)?)
+        }
+        Type::MeterMod => OfPayload::MeterMod(meter_mod::MeterMod::try_from(bytes)?),
+        Type::BundleControl => OfPayload::BundleControl(bundle::BundleCtrl::try_from(bytes)?),
+        Type::BundleAddMessage => {
+            OfPayload::BundleAddMessage(bundle::BundleAdd::try_from(bytes)?)
+        }
+    })
+}
+
+/// returns `len` bytes of the slice starting at `start`
+/// fails instead of panicking if the slice is too short, eg. because of a corrupt length field
+pub fn checked_slice<'a>(
+    bytes: &'a [u8],
+    start: usize,
+    len: usize,
+    ttype: &'static str,
+) -> Result<&'a [u8]> {
+    match start.checked_add(len) {
+        Some(end) if end <= bytes.len() => Ok(&bytes[start..end]),
+        _ => Err(Error::from(ErrorKind::InvalidSliceLength(
+            start.saturating_add(len),
+            bytes.len(),
+            ttype,
+        ))
+        .at(start)),
+    }
+}
+
+/// returns all bytes of the slice starting at `start`
+/// fails instead of panicking if the slice is shorter than `start`
+pub fn checked_tail<'a>(bytes: &'a [u8], start: usize, ttype: &'static str) -> Result<&'a [u8]> {
+    checked_slice(bytes, start, bytes.len().saturating_sub(start), ttype)
+}
+
+impl OfPayload {
+    /// the encoded body, None for payloads without one
+    fn body(&self) -> Option<&dyn OfEncode> {
+        match *self {
+            OfPayload::Hello
+            | OfPayload::FeaturesRequest
+            | OfPayload::GetConfigRequest
+            | OfPayload::BarrierRequest
+            | OfPayload::BarrierReply
+            | OfPayload::GetAsyncRequest => None,
+            OfPayload::Error(ref payload) => Some(payload),
+            OfPayload::EchoRequest(ref data) => Some(data),
+            OfPayload::EchoReply(ref data) => Some(data),
+            OfPayload::FeaturesReply(ref payload) => Some(payload),
+            OfPayload::GetConfigReply(ref payload) => Some(payload),
+            OfPayload::SetConfig(ref payload) => Some(payload),
+            OfPayload::PacketIn(ref payload) => Some(payload),
+            OfPayload::FlowRemoved(ref payload) => Some(payload),
+            OfPayload::PortStatus(ref payload) => Some(payload),
+            OfPayload::PacketOut(ref payload) => Some(payload),
+            OfPayload::FlowMod(ref payload) => Some(payload),
+            OfPayload::GroupMod(ref payload) => Some(payload),
+            OfPayload::PortMod(ref payload) => Some(payload),
+            OfPayload::TableMod(ref payload) => Some(payload),
+            OfPayload::QueueGetConfigRequest(ref payload) => Some(payload),
+            OfPayload::QueueGetConfigReply(ref payload) => Some(payload),
+            OfPayload::RoleRequest(ref payload) => Some(payload),
+            OfPayload::RoleReply(ref payload) => Some(payload),
+            OfPayload::GetAsyncReply(ref payload) => Some(payload),
+            OfPayload::SetAsync(ref payload) => Some(payload),
+            OfPayload::MeterMod(ref payload) => Some(payload),
+            OfPayload::MultipartRequest(ref payload) => Some(payload),
+            OfPayload::MultipartReply(ref payload) => Some(payload),
+            OfPayload::BundleControl(ref payload) => Some(payload),
+            OfPayload::BundleAddMessage(ref payload) => Some(payload),
+            OfPayload::Experimenter => panic!("not yet implemented {:?}", self),
+        }
+    }
+}
+
+impl OfEncode for OfPayload {
+    fn encoded_len(&self) -> usize {
+        self.body().map_or(0, |body| body.encoded_len())
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.body().map_or(Some(0), |body| body.size_hint())
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        if let Some(body) = self.body() {
+            body.encode_into(buf);
         }
-        header
     }
 }
 
 impl Into<Vec<u8>> for OfPayload {
     fn into(self) -> Vec<u8> {
-        match self {
-            OfPayload::Hello => vec![],       // no body
-            OfPayload::EchoRequest => vec![], // no body
-            OfPayload::EchoReply => vec![],   // no body
-            OfPayload::PacketOut(payload) => payload.into(),
-            _ => panic!("not yet implemented {:?}", self),
+        self.to_bytes()
+    }
+}
+
+/// messages are passed between the connection and handler threads
+/// fails to compile if a message type stops being Send + Sync
+#[allow(dead_code)]
+fn assert_messages_are_send_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<OfMsg>();
+    assert_send_sync::<Header>();
+    assert_send_sync::<OfPayload>();
+    assert_send_sync::<flow_mod::FlowMod>();
+    assert_send_sync::<packet_in::PacketIn>();
+    assert_send_sync::<packet_out::PacketOut>();
+    assert_send_sync::<group_mod::GroupMod>();
+    assert_send_sync::<meter_mod::MeterMod>();
+    assert_send_sync::<role::Role>();
+    assert_send_sync::<multipart::MultipartRequest>();
+    assert_send_sync::<multipart::MultipartReply>();
+    assert_send_sync::<bundle::BundleAdd>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::error_msg::{ErrorMsg, ErrorType};
+    use super::flow_parser::parse_flow_mod;
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn extracts_typed_messages() {
+        let flow_mod = parse_flow_mod("priority=10,actions=drop").unwrap();
+        let payload = OfPayload::FlowMod(flow_mod.clone());
+        assert_eq!(payload.as_flow_mod(), Some(&flow_mod));
+        assert!(payload.as_packet_in().is_none());
+        assert_eq!(payload.into_flow_mod(), Some(flow_mod.clone()));
+
+        let error = ErrorMsg::new(ErrorType::BadRequest, 1, &[]);
+        let converted: ::std::result::Result<error_msg::ErrorMsg, OfPayload> =
+            OfPayload::Error(error.clone()).try_into();
+        assert_eq!(converted.unwrap(), error);
+        // other messages are handed back
+        match flow_mod::FlowMod::try_from(OfPayload::Error(error)) {
+            Err(OfPayload::Error(_)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(OfPayload::BarrierReply.into_error().is_none());
+    }
+
+    #[test]
+    fn converts_types_from_and_into_their_numbers() {
+        for raw in 0..=255u8 {
+            match Type::from_u8(raw) {
+                Some(ttype) => assert_eq!(ttype.to_u8(), Some(raw)),
+                None => assert!(raw > 34 || (raw > 29 && raw < 33)),
+            }
         }
+        assert_eq!(Type::from_u8(1), Some(Type::Error));
+        assert_eq!(Type::from_i64(-1), None);
     }
 }
@@ -1,34 +1,377 @@
-#[derive(Debug)]
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::convert::{Into, TryFrom};
+use std::io::Cursor;
+
+use super::super::err::*;
+use super::features::Capabilities;
+use super::flow_stats::{
+    FlowDesc, FlowStats, FlowStatsRequest, FLOW_DESC_LEN_FIELD, FLOW_STATS_LEN_FIELD,
+};
+use super::group_mod::GroupFeatures;
+use super::meter_mod::MeterFeatures;
+use super::port_stats::{PortStats, PortStatsRequest, PORT_STATS_LENGTH};
+use super::ports::{Port, PORT_LENGTH};
+use super::table_features::{TableFeatures, TABLE_FEATURES_LEN_FIELD};
+use super::tlv::{decode_list, encode_list};
+use super::{checked_slice, Version};
+
+/// Length of the multipart header (type, flags and padding) in front of the body.
+pub const MULTIPART_HEADER_LENGTH: usize = 8;
+/// Length of the description strings of RepDesc, including the terminating null.
+pub const DESC_STR_LEN: usize = 256;
+/// Length of the serial number of RepDesc, including the terminating null.
+pub const SERIAL_NUM_LEN: usize = 32;
+/// Length of the ofp_desc body.
+pub const DESC_LENGTH: usize = 4 * DESC_STR_LEN + SERIAL_NUM_LEN;
+
+bitflags! {
+    /// Flags of multipart requests and replies.
+    pub struct MultipartFlags: u16 {
+        /// more requests or replies follow (OFPMPF_REQ_MORE / OFPMPF_REPLY_MORE)
+        const MORE = 1 << 0;
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct MultipartRequest {
-    ttype: MultipartTypes,
-    flags: bool,
+    pub flags: MultipartFlags,
     // pad 4 bytes
-    payload: ReqPayload,
+    pub payload: ReqPayload,
+}
+
+impl MultipartRequest {
+    pub fn new(payload: ReqPayload) -> Self {
+        MultipartRequest {
+            flags: MultipartFlags::empty(),
+            payload: payload,
+        }
+    }
+
+    pub fn ttype(&self) -> MultipartTypes {
+        match self.payload {
+            ReqPayload::Desc => MultipartTypes::Desc,
+            ReqPayload::Flow(_) => MultipartTypes::Flow,
+            ReqPayload::GroupFeatures => MultipartTypes::GroupFeatures,
+            ReqPayload::MeterFeatures => MultipartTypes::MeterFeatures,
+            ReqPayload::TableFeatures => MultipartTypes::TableFeatures,
+            ReqPayload::PortDesc => MultipartTypes::PortDesc,
+            ReqPayload::PortStats(_) => MultipartTypes::PortStats,
+        }
+    }
+}
+
+impl Into<Vec<u8>> for MultipartRequest {
+    fn into(self) -> Vec<u8> {
+        let mut vec = Vec::new();
+        vec.write_u16::<BigEndian>(self.ttype().to_u16().unwrap())
+            .unwrap();
+        vec.write_u16::<BigEndian>(self.flags.bits()).unwrap();
+        vec.write_u32::<BigEndian>(0).unwrap(); // pad 4 bytes
+        match self.payload {
+            ReqPayload::Flow(request) => vec.extend_from_slice(&Into::<Vec<u8>>::into(request)[..]),
+            ReqPayload::PortStats(request) => {
+                vec.extend_from_slice(&Into::<Vec<u8>>::into(request)[..])
+            }
+            // no body
+            ReqPayload::Desc
+            | ReqPayload::GroupFeatures
+            | ReqPayload::MeterFeatures
+            | ReqPayload::TableFeatures
+            | ReqPayload::PortDesc => (),
+        }
+        vec
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for MultipartRequest {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let (ttype, flags) = read_header(bytes)?;
+        let body = &bytes[MULTIPART_HEADER_LENGTH..];
+        let payload = match ttype {
+            MultipartTypes::Desc => ReqPayload::Desc,
+            MultipartTypes::Flow => ReqPayload::Flow(
+                FlowStatsRequest::try_from(body).within(MULTIPART_HEADER_LENGTH)?,
+            ),
+            MultipartTypes::GroupFeatures => ReqPayload::GroupFeatures,
+            MultipartTypes::MeterFeatures => ReqPayload::MeterFeatures,
+            // requests that change the table features are not supported
+            MultipartTypes::TableFeatures if body.is_empty() => ReqPayload::TableFeatures,
+            MultipartTypes::PortDesc => ReqPayload::PortDesc,
+            MultipartTypes::PortStats => ReqPayload::PortStats(
+                PortStatsRequest::try_from(body).within(MULTIPART_HEADER_LENGTH)?,
+            ),
+            _ => bail!(ErrorKind::UnsupportedValue(
+                ttype.to_u16().unwrap() as u64,
+                stringify!(MultipartTypes),
+            )),
+        };
+        Ok(MultipartRequest {
+            flags: flags,
+            payload: payload,
+        })
+    }
 }
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum ReqPayload {
     Desc,
+    Flow(FlowStatsRequest),
+    GroupFeatures,
+    MeterFeatures,
+    /// reads the features of all tables
+    TableFeatures,
+    PortDesc,
+    /// the counters of a port or of all ports
+    PortStats(PortStatsRequest),
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct MultipartReply {
-    ttype: MultipartTypes,
-    flags: u16,
+    pub flags: MultipartFlags,
     // pad 4 bytes
-    payload: RepPayload,
+    pub payload: RepPayload,
+}
+
+impl MultipartReply {
+    pub fn new(payload: RepPayload) -> Self {
+        MultipartReply {
+            flags: MultipartFlags::empty(),
+            payload: payload,
+        }
+    }
+
+    pub fn ttype(&self) -> MultipartTypes {
+        match self.payload {
+            RepPayload::Desc(_) => MultipartTypes::Desc,
+            RepPayload::Flow(_) | RepPayload::FlowDesc(_) => MultipartTypes::Flow,
+            RepPayload::GroupFeatures(_) => MultipartTypes::GroupFeatures,
+            RepPayload::MeterFeatures(_) => MultipartTypes::MeterFeatures,
+            RepPayload::TableFeatures(_) => MultipartTypes::TableFeatures,
+            RepPayload::PortDesc(_) => MultipartTypes::PortDesc,
+            RepPayload::PortStats(_) => MultipartTypes::PortStats,
+        }
+    }
+
+    /// decodes a reply of the given version, flow stats replies of 1.5 contain
+    /// flow descriptions
+    pub fn decode_for(version: Version, bytes: &[u8]) -> Result<Self> {
+        let (ttype, flags) = read_header(bytes)?;
+        let body = &bytes[MULTIPART_HEADER_LENGTH..];
+        let payload = match ttype {
+            MultipartTypes::Desc => {
+                RepPayload::Desc(RepDesc::try_from(body).within(MULTIPART_HEADER_LENGTH)?)
+            }
+            MultipartTypes::Flow if version >= Version::V1_5 => RepPayload::FlowDesc(decode_list(
+                bytes,
+                MULTIPART_HEADER_LENGTH,
+                FLOW_DESC_LEN_FIELD,
+                stringify!(FlowDesc),
+            )?),
+            MultipartTypes::Flow => RepPayload::Flow(decode_list(
+                bytes,
+                MULTIPART_HEADER_LENGTH,
+                FLOW_STATS_LEN_FIELD,
+                stringify!(FlowStats),
+            )?),
+            MultipartTypes::GroupFeatures => RepPayload::GroupFeatures(
+                GroupFeatures::try_from(body).within(MULTIPART_HEADER_LENGTH)?,
+            ),
+            MultipartTypes::MeterFeatures => RepPayload::MeterFeatures(
+                MeterFeatures::try_from(body).within(MULTIPART_HEADER_LENGTH)?,
+            ),
+            MultipartTypes::TableFeatures => RepPayload::TableFeatures(decode_list(
+                bytes,
+                MULTIPART_HEADER_LENGTH,
+                TABLE_FEATURES_LEN_FIELD,
+                stringify!(TableFeatures),
+            )?),
+            MultipartTypes::PortDesc => {
+                if !body.len().is_multiple_of(PORT_LENGTH) {
+                    bail!(ErrorKind::InvalidSliceLength(
+                        (body.len() / PORT_LENGTH + 1) * PORT_LENGTH,
+                        body.len(),
+                        stringify!(RepPayload::PortDesc),
+                    ));
+                }
+                let mut ports = Vec::with_capacity(body.len() / PORT_LENGTH);
+                for (i, port) in body.chunks(PORT_LENGTH).enumerate() {
+                    let pos = MULTIPART_HEADER_LENGTH + i * PORT_LENGTH;
+                    ports.push(Port::try_from(port).within(pos)?);
+                }
+                RepPayload::PortDesc(ports)
+            }
+            // the counters moved to properties in 1.4
+            MultipartTypes::PortStats if version < Version::V1_4 => {
+                if !body.len().is_multiple_of(PORT_STATS_LENGTH) {
+                    bail!(ErrorKind::InvalidSliceLength(
+                        (body.len() / PORT_STATS_LENGTH + 1) * PORT_STATS_LENGTH,
+                        body.len(),
+                        stringify!(RepPayload::PortStats),
+                    ));
+                }
+                let mut stats = Vec::with_capacity(body.len() / PORT_STATS_LENGTH);
+                for (i, port) in body.chunks(PORT_STATS_LENGTH).enumerate() {
+                    let pos = MULTIPART_HEADER_LENGTH + i * PORT_STATS_LENGTH;
+                    stats.push(PortStats::try_from(port).within(pos)?);
+                }
+                RepPayload::PortStats(stats)
+            }
+            _ => bail!(ErrorKind::UnsupportedValue(
+                ttype.to_u16().unwrap() as u64,
+                stringify!(MultipartTypes),
+            )),
+        };
+        Ok(MultipartReply {
+            flags: flags,
+            payload: payload,
+        })
+    }
+}
+
+impl Into<Vec<u8>> for MultipartReply {
+    fn into(self) -> Vec<u8> {
+        let mut vec = Vec::new();
+        vec.write_u16::<BigEndian>(self.ttype().to_u16().unwrap())
+            .unwrap();
+        vec.write_u16::<BigEndian>(self.flags.bits()).unwrap();
+        vec.write_u32::<BigEndian>(0).unwrap(); // pad 4 bytes
+        match self.payload {
+            RepPayload::Desc(desc) => vec.extend_from_slice(&Into::<Vec<u8>>::into(desc)[..]),
+            RepPayload::Flow(stats) => vec.extend_from_slice(&encode_list(stats)[..]),
+            RepPayload::FlowDesc(descs) => vec.extend_from_slice(&encode_list(descs)[..]),
+            RepPayload::GroupFeatures(features) => {
+                vec.extend_from_slice(&Into::<Vec<u8>>::into(features)[..])
+            }
+            RepPayload::MeterFeatures(features) => {
+                vec.extend_from_slice(&Into::<Vec<u8>>::into(features)[..])
+            }
+            RepPayload::TableFeatures(tables) => vec.extend_from_slice(&encode_list(tables)[..]),
+            RepPayload::PortDesc(ports) => {
+                for port in ports {
+                    vec.extend_from_slice(&Into::<Vec<u8>>::into(port)[..]);
+                }
+            }
+            RepPayload::PortStats(stats) => {
+                for port in stats {
+                    vec.extend_from_slice(&Into::<Vec<u8>>::into(port)[..]);
+                }
+            }
+        }
+        vec
+    }
+}
+
+/// decodes the OpenFlow 1.3 layout, see `MultipartReply::decode_for`
+impl<'a> TryFrom<&'a [u8]> for MultipartReply {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        MultipartReply::decode_for(Version::V1_3, bytes)
+    }
+}
+
+/// reads type and flags, fails if the bytes are shorter than the header
+pub(crate) fn read_header(bytes: &[u8]) -> Result<(MultipartTypes, MultipartFlags)> {
+    let header = checked_slice(bytes, 0, MULTIPART_HEADER_LENGTH, stringify!(Multipart))?;
+    let mut cursor = Cursor::new(header);
+    let ttype_raw = cursor.read_u16::<BigEndian>()?;
+    let ttype = MultipartTypes::from_u16(ttype_raw).ok_or::<Error>(
+        ErrorKind::UnknownValue(ttype_raw as u64, stringify!(MultipartTypes)).into(),
+    )?;
+    // unknown flags are ignored
+    let flags = MultipartFlags::from_bits_truncate(cursor.read_u16::<BigEndian>()?);
+    Ok((ttype, flags))
 }
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum RepPayload {
     Desc(RepDesc),
+    /// statistics of the flows up to OpenFlow 1.4, may be split over several replies
+    Flow(Vec<FlowStats>),
+    /// descriptions of the flows since OpenFlow 1.5, may be split over several replies
+    FlowDesc(Vec<FlowDesc>),
+    GroupFeatures(GroupFeatures),
+    MeterFeatures(MeterFeatures),
+    TableFeatures(Vec<TableFeatures>),
+    /// all ports of the switch, may be split over several replies
+    PortDesc(Vec<Port>),
+    /// counters of the requested ports (OpenFlow 1.3), may be split over several replies
+    PortStats(Vec<PortStats>),
 }
 
-#[derive(PartialEq, Debug, Clone)]
-pub struct RepDesc {}
+/// Description of a switch (ofp_desc).
+/// The strings are sent null terminated in fixed size fields, longer
+/// strings are cut when encoding.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct RepDesc {
+    /// manufacturer description
+    pub mfr_desc: String,
+    /// hardware description
+    pub hw_desc: String,
+    /// software description
+    pub sw_desc: String,
+    /// serial number
+    pub serial_num: String,
+    /// human readable description of the datapath
+    pub dp_desc: String,
+}
 
+impl<'a> TryFrom<&'a [u8]> for RepDesc {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let bytes = checked_slice(bytes, 0, DESC_LENGTH, stringify!(RepDesc))?;
+        let mut pos = 0;
+        let mut field = |len: usize| {
+            let value = read_fixed_str(&bytes[pos..pos + len]);
+            pos += len;
+            value
+        };
+        Ok(RepDesc {
+            mfr_desc: field(DESC_STR_LEN),
+            hw_desc: field(DESC_STR_LEN),
+            sw_desc: field(DESC_STR_LEN),
+            serial_num: field(SERIAL_NUM_LEN),
+            dp_desc: field(DESC_STR_LEN),
+        })
+    }
+}
+
+impl Into<Vec<u8>> for RepDesc {
+    fn into(self) -> Vec<u8> {
+        let mut vec = Vec::with_capacity(DESC_LENGTH);
+        write_fixed_str(&mut vec, &self.mfr_desc, DESC_STR_LEN);
+        write_fixed_str(&mut vec, &self.hw_desc, DESC_STR_LEN);
+        write_fixed_str(&mut vec, &self.sw_desc, DESC_STR_LEN);
+        write_fixed_str(&mut vec, &self.serial_num, SERIAL_NUM_LEN);
+        write_fixed_str(&mut vec, &self.dp_desc, DESC_STR_LEN);
+        vec
+    }
+}
+
+/// reads a null padded string, everything after the first null is ignored
+/// invalid utf-8 is replaced
+pub(crate) fn read_fixed_str(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// writes the string null padded to len bytes
+/// the string is cut at a character boundary so that it ends with at least one null,
+/// it also ends at its first null
+pub(crate) fn write_fixed_str(vec: &mut Vec<u8>, value: &str, len: usize) {
+    let value = value.split('\0').next().unwrap_or("");
+    let mut end = ::std::cmp::min(value.len(), len - 1);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    vec.extend_from_slice(&value.as_bytes()[..end]);
+    vec.resize(vec.len() + len - end, 0);
+}
+
+/// Types of multipart messages.
 #[derive(Primitive, PartialEq, Debug, Clone)]
-enum MultipartTypes {
+pub enum MultipartTypes {
     /// Description of this OpenFlow switch.
     /// The request body is empty.
     /// The reply body is struct ofp_desc.
@@ -94,3 +437,173 @@ enum MultipartTypes {
     /// The request and reply bodies are otherwise experimenter-defined.
     Experimenter = 0xffff,
 }
+
+impl MultipartTypes {
+    /// capability the switch has to advertise to answer requests of this type
+    pub fn capability(&self) -> Capabilities {
+        match *self {
+            MultipartTypes::Flow | MultipartTypes::Aggregate => Capabilities::FLOW_STATS,
+            MultipartTypes::Table => Capabilities::TABLE_STATS,
+            MultipartTypes::PortStats => Capabilities::PORT_STATS,
+            MultipartTypes::Queue => Capabilities::QUEUE_STATS,
+            MultipartTypes::Group => Capabilities::GROUP_STATS,
+            _ => Capabilities::empty(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn desc_reply_round_trip() {
+        let desc = RepDesc {
+            mfr_desc: "Nicira, Inc.".to_string(),
+            hw_desc: "Open vSwitch".to_string(),
+            sw_desc: "2.17.0".to_string(),
+            serial_num: "None".to_string(),
+            dp_desc: "br0".to_string(),
+        };
+        let reply = MultipartReply::new(RepPayload::Desc(desc.clone()));
+        let bytes: Vec<u8> = reply.clone().into();
+        assert_eq!(bytes.len(), MULTIPART_HEADER_LENGTH + DESC_LENGTH);
+        assert_eq!(&bytes[..4], &[0, 0, 0, 0]);
+        assert_eq!(&bytes[8..20], b"Nicira, Inc.");
+        assert_eq!(bytes[20], 0);
+        assert_eq!(MultipartReply::try_from(&bytes[..]).unwrap(), reply);
+    }
+
+    #[test]
+    fn desc_strings_are_cut_and_null_terminated() {
+        let desc = RepDesc {
+            // 2 byte characters, 16 of them fill the serial number exactly
+            serial_num: "ä".repeat(16),
+            ..RepDesc::default()
+        };
+        let bytes: Vec<u8> = desc.into();
+        assert_eq!(bytes.len(), DESC_LENGTH);
+        let serial = &bytes[3 * DESC_STR_LEN..3 * DESC_STR_LEN + SERIAL_NUM_LEN];
+        assert_eq!(serial[SERIAL_NUM_LEN - 1], 0);
+        let decoded = RepDesc::try_from(&bytes[..]).unwrap();
+        assert_eq!(decoded.serial_num, "ä".repeat(15));
+    }
+
+    #[test]
+    fn desc_request_has_no_body() {
+        let bytes: Vec<u8> = MultipartRequest::new(ReqPayload::Desc).into();
+        assert_eq!(bytes, vec![0; MULTIPART_HEADER_LENGTH]);
+        assert!(MultipartRequest::try_from(&[0, 1, 0, 0, 0, 0, 0, 0][..]).is_err());
+    }
+
+    #[test]
+    fn features_replies_round_trip() {
+        use super::super::group_mod::{GroupCapabilities, GroupType, GROUP_FEATURES_LENGTH};
+        use super::super::meter_mod::{MeterBandType, MeterFlags, METER_FEATURES_LENGTH};
+
+        let groups = GroupFeatures {
+            types: 0b1011,
+            capabilities: GroupCapabilities::SELECT_WEIGHT,
+            max_groups: [64, 0, 32, 16],
+            actions: [1, 0, 1, 1],
+        };
+        assert!(groups.supports(&GroupType::Ff) && !groups.supports(&GroupType::Indirect));
+        assert_eq!(groups.max_groups(&GroupType::Indirect), 32);
+        let reply = MultipartReply::new(RepPayload::GroupFeatures(groups));
+        let bytes: Vec<u8> = reply.clone().into();
+        assert_eq!(bytes.len(), MULTIPART_HEADER_LENGTH + GROUP_FEATURES_LENGTH);
+        assert_eq!(&bytes[..2], &[0, 8]);
+        assert_eq!(MultipartReply::try_from(&bytes[..]).unwrap(), reply);
+        assert!(MultipartReply::try_from(&bytes[..bytes.len() - 1]).is_err());
+
+        let meters = MeterFeatures {
+            max_meter: 100,
+            band_types: 1 << 1,
+            capabilities: MeterFlags::KBPS | MeterFlags::BURST,
+            max_bands: 1,
+            max_color: 0,
+        };
+        assert!(meters.supports_band(&MeterBandType::Drop));
+        assert!(!meters.supports_band(&MeterBandType::DscpRemark));
+        let reply = MultipartReply::new(RepPayload::MeterFeatures(meters));
+        let bytes: Vec<u8> = reply.clone().into();
+        assert_eq!(bytes.len(), MULTIPART_HEADER_LENGTH + METER_FEATURES_LENGTH);
+        assert_eq!(&bytes[..2], &[0, 11]);
+        assert_eq!(MultipartReply::try_from(&bytes[..]).unwrap(), reply);
+
+        let bytes: Vec<u8> = MultipartRequest::new(ReqPayload::MeterFeatures).into();
+        assert_eq!(bytes, vec![0, 11, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn port_desc_reply_round_trip() {
+        use super::super::addr::MacAddr;
+        use super::super::ports::{PortName, PortNumber};
+
+        let ports: Vec<Port> = (1..3)
+            .map(|no| {
+                let name = PortName::new(format!("eth{}", no)).unwrap();
+                let hw_addr = MacAddr([0, 0, 0, 0, 0, no as u8]);
+                Port::new(PortNumber::NormalPort(no), hw_addr, name)
+            })
+            .collect();
+        let mut reply = MultipartReply::new(RepPayload::PortDesc(ports));
+        reply.flags = MultipartFlags::MORE;
+        let bytes: Vec<u8> = reply.clone().into();
+        assert_eq!(bytes.len(), MULTIPART_HEADER_LENGTH + 2 * PORT_LENGTH);
+        assert_eq!(&bytes[..4], &[0, 13, 0, 1]);
+        assert_eq!(MultipartReply::try_from(&bytes[..]).unwrap(), reply);
+        assert!(MultipartReply::try_from(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn flow_replies_depend_on_the_version() {
+        use super::super::flow_match::{Match, MatchPayload, PayloadPacketType, TlvMatch};
+        use super::super::flow_mod::FlowModFlags;
+        use super::super::flow_stats::OxsStats;
+        use super::super::OfPayload;
+
+        let mmatch = Match::new(vec![TlvMatch::new(MatchPayload::PacketType(
+            PayloadPacketType::ethertype(0x0800),
+        ))]);
+        let desc = FlowDesc {
+            table_id: 0,
+            priority: 1,
+            idle_timeout: 0,
+            hard_timeout: 0,
+            flags: FlowModFlags::empty(),
+            importance: 0,
+            cookie: 0,
+            mmatch: mmatch.clone(),
+            stats: OxsStats {
+                packet_count: Some(1),
+                ..OxsStats::default()
+            },
+            instructions: Vec::new(),
+        };
+        let reply = MultipartReply::new(RepPayload::FlowDesc(vec![desc]));
+        let bytes: Vec<u8> = reply.clone().into();
+        assert_eq!(&bytes[..2], &[0, 1]);
+        assert_eq!(MultipartReply::decode_for(Version::V1_5, &bytes).unwrap(), reply);
+        // too short for the 1.3 flow stats
+        assert!(MultipartReply::decode_for(Version::V1_3, &bytes).is_err());
+
+        let payload = OfPayload::MultipartReply(reply);
+        assert!(payload.available_in(Version::V1_5));
+        assert!(!payload.available_in(Version::V1_4));
+
+        // packet type matches are only sent to 1.5 switches
+        let mut request = FlowStatsRequest::all();
+        request.mmatch = mmatch;
+        let payload = OfPayload::MultipartRequest(MultipartRequest::new(ReqPayload::Flow(request)));
+        assert!(payload.available_in(Version::V1_5));
+        assert!(!payload.available_in(Version::V1_3));
+        let bytes: Vec<u8> = payload.into();
+        match MultipartRequest::try_from(&bytes[..]).unwrap().payload {
+            ReqPayload::Flow(ref request) => {
+                assert_eq!(request.mmatch.min_version(), Version::V1_5)
+            }
+            ref payload => panic!("unexpected payload {:?}", payload),
+        }
+    }
+}
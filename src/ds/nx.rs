@@ -0,0 +1,561 @@
+//! Nicira extensions used by Open vSwitch, compiled with the `nicira` feature.
+//!
+//! Match fields of the NXM classes (0x0000 and 0x0001) are decoded into
+//! `MatchPayload::Nxm` and experimenter actions of the Nicira vendor id into
+//! `ActionPayload::Nicira`. Only the most common fields and actions are
+//! decoded, all others are kept as raw bytes so they are encoded unchanged.
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::convert::{Into, TryFrom};
+use std::io::{Cursor, Seek, SeekFrom};
+
+use super::super::err::*;
+use super::actions::{actions_from_slice, ActionHeader};
+use super::tlv::encode_list;
+
+/// Experimenter id of Nicira (NX_VENDOR_ID).
+pub const NX_VENDOR_ID: u32 = 0x0000_2320;
+/// OXM class of the fields of the original NXM format (NXM_OF_*).
+pub const NXM_CLASS_0: u16 = 0x0000;
+/// OXM class of the Nicira extension fields (NXM_NX_*).
+pub const NXM_CLASS_1: u16 = 0x0001;
+
+/// NXM_NX_TUN_ID
+const NXM_NX_TUN_ID: u8 = 16;
+/// NXM_NX_CT_STATE
+const NXM_NX_CT_STATE: u8 = 105;
+/// NXM_NX_CT_ZONE
+const NXM_NX_CT_ZONE: u8 = 106;
+/// NXM_NX_CT_MARK
+const NXM_NX_CT_MARK: u8 = 107;
+/// NXM_NX_CT_LABEL
+const NXM_NX_CT_LABEL: u8 = 108;
+/// NXM_NX_REG0 to NXM_NX_REG15 are fields 0 to 15
+const NXM_NX_REGS: u8 = 16;
+
+static REG_NAMES: [&str; 16] = [
+    "reg0", "reg1", "reg2", "reg3", "reg4", "reg5", "reg6", "reg7", "reg8", "reg9", "reg10",
+    "reg11", "reg12", "reg13", "reg14", "reg15",
+];
+
+/// Match field of one of the NXM classes, the mask is kept by the TlvMatch.
+#[derive(Debug, PartialEq, Clone)]
+pub enum NxmField {
+    /// register 0 to 15
+    Reg { index: u8, value: u32 },
+    /// tunnel id
+    TunId(u64),
+    /// connection tracking state
+    CtState(CtState),
+    /// connection tracking zone
+    CtZone(u16),
+    /// mark of the tracked connection
+    CtMark(u32),
+    /// label of the tracked connection
+    CtLabel(u128),
+    /// any other field of the NXM classes
+    Other {
+        class: u16,
+        field: u8,
+        data: Vec<u8>,
+    },
+}
+
+impl NxmField {
+    /// decodes the value of a field
+    /// fields that are not known or have unknown bits set are kept as raw bytes
+    pub fn decode(class: u16, field: u8, value: &[u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(value);
+        Ok(match (class, field, value.len()) {
+            (NXM_CLASS_1, index, 4) if index < NXM_NX_REGS => NxmField::Reg {
+                index: index,
+                value: cursor.read_u32::<BigEndian>()?,
+            },
+            (NXM_CLASS_1, NXM_NX_TUN_ID, 8) => NxmField::TunId(cursor.read_u64::<BigEndian>()?),
+            (NXM_CLASS_1, NXM_NX_CT_STATE, 4) => {
+                let raw = cursor.read_u32::<BigEndian>()?;
+                match CtState::from_bits(raw) {
+                    Some(state) => NxmField::CtState(state),
+                    None => NxmField::other(class, field, value),
+                }
+            }
+            (NXM_CLASS_1, NXM_NX_CT_ZONE, 2) => NxmField::CtZone(cursor.read_u16::<BigEndian>()?),
+            (NXM_CLASS_1, NXM_NX_CT_MARK, 4) => NxmField::CtMark(cursor.read_u32::<BigEndian>()?),
+            (NXM_CLASS_1, NXM_NX_CT_LABEL, 16) => {
+                NxmField::CtLabel(cursor.read_u128::<BigEndian>()?)
+            }
+            _ => NxmField::other(class, field, value),
+        })
+    }
+
+    fn other(class: u16, field: u8, value: &[u8]) -> Self {
+        NxmField::Other {
+            class: class,
+            field: field,
+            data: Vec::from(value),
+        }
+    }
+
+    /// oxm class of the field
+    pub fn class(&self) -> u16 {
+        match *self {
+            NxmField::Other { class, .. } => class,
+            _ => NXM_CLASS_1,
+        }
+    }
+
+    /// oxm field of the field (7 bits)
+    pub fn field(&self) -> u8 {
+        match *self {
+            NxmField::Reg { index, .. } => index,
+            NxmField::TunId(_) => NXM_NX_TUN_ID,
+            NxmField::CtState(_) => NXM_NX_CT_STATE,
+            NxmField::CtZone(_) => NXM_NX_CT_ZONE,
+            NxmField::CtMark(_) => NXM_NX_CT_MARK,
+            NxmField::CtLabel(_) => NXM_NX_CT_LABEL,
+            NxmField::Other { field, .. } => field,
+        }
+    }
+
+    /// name of the field as used by ovs-ofctl, None for other fields
+    pub fn name(&self) -> Option<&'static str> {
+        match *self {
+            NxmField::Reg { index, .. } => REG_NAMES.get(index as usize).cloned(),
+            NxmField::TunId(_) => Some("tun_id"),
+            NxmField::CtState(_) => Some("ct_state"),
+            NxmField::CtZone(_) => Some("ct_zone"),
+            NxmField::CtMark(_) => Some("ct_mark"),
+            NxmField::CtLabel(_) => Some("ct_label"),
+            NxmField::Other { .. } => None,
+        }
+    }
+}
+
+impl Into<Vec<u8>> for NxmField {
+    fn into(self) -> Vec<u8> {
+        let mut res = Vec::new();
+        match self {
+            NxmField::Reg { value, .. } => res.write_u32::<BigEndian>(value).unwrap(),
+            NxmField::TunId(tun_id) => res.write_u64::<BigEndian>(tun_id).unwrap(),
+            NxmField::CtState(state) => res.write_u32::<BigEndian>(state.bits()).unwrap(),
+            NxmField::CtZone(zone) => res.write_u16::<BigEndian>(zone).unwrap(),
+            NxmField::CtMark(mark) => res.write_u32::<BigEndian>(mark).unwrap(),
+            NxmField::CtLabel(label) => res.write_u128::<BigEndian>(label).unwrap(),
+            NxmField::Other { data, .. } => res.extend_from_slice(&data[..]),
+        }
+        res
+    }
+}
+
+bitflags! {
+    /// State of a packet in connection tracking.
+    pub struct CtState: u32 {
+        /// Beginning of a new connection.
+        const NEW = 1 << 0;
+        /// Part of an existing connection.
+        const ESTABLISHED = 1 << 1;
+        /// Related to an existing connection.
+        const RELATED = 1 << 2;
+        /// Flow is in the reply direction.
+        const REPLY = 1 << 3;
+        /// Could not be tracked.
+        const INVALID = 1 << 4;
+        /// Went through connection tracking.
+        const TRACKED = 1 << 5;
+        /// Source address was translated.
+        const SRC_NAT = 1 << 6;
+        /// Destination address was translated.
+        const DST_NAT = 1 << 7;
+    }
+}
+
+/// Names of the ct_state flags as used by ovs-ofctl (`ct_state=+trk+est`).
+pub static CT_STATE_NAMES: [(CtState, &str); 8] = [
+    (CtState::NEW, "new"),
+    (CtState::ESTABLISHED, "est"),
+    (CtState::RELATED, "rel"),
+    (CtState::REPLY, "rpl"),
+    (CtState::INVALID, "inv"),
+    (CtState::TRACKED, "trk"),
+    (CtState::SRC_NAT, "snat"),
+    (CtState::DST_NAT, "dnat"),
+];
+
+/// NXAST_RESUBMIT
+const NXAST_RESUBMIT: u16 = 1;
+/// NXAST_RESUBMIT_TABLE
+const NXAST_RESUBMIT_TABLE: u16 = 14;
+/// NXAST_LEARN
+const NXAST_LEARN: u16 = 16;
+/// NXAST_CONJUNCTION
+const NXAST_CONJUNCTION: u16 = 34;
+/// NXAST_CT
+const NXAST_CT: u16 = 35;
+
+/// recirc_table of ct actions that do not recirculate (NX_CT_RECIRC_NONE).
+pub const NX_CT_RECIRC_NONE: u8 = 0xff;
+
+/// Length of the action header, the vendor id and the subtype (10 bytes).
+const NX_ACTION_HEADER_LEN: usize = 10;
+
+/// Nicira experimenter action, without the action header and the vendor id.
+#[derive(Debug, PartialEq, Clone)]
+pub enum NxAction {
+    /// searches the flow table again with in_port replaced
+    /// (OFPP_IN_PORT 0xfff8 keeps it), table None searches the current table
+    Resubmit { in_port: u16, table: Option<u8> },
+    /// the flow is one clause of the conjunctive match with the given id
+    Conjunction { id: u32, clause: u8, n_clauses: u8 },
+    /// adds or modifies a flow when a packet matches
+    Learn(NxLearn),
+    /// sends the packet through the connection tracker
+    Conntrack(NxConntrack),
+    /// any other action of the subtype, data includes the padding
+    Other { subtype: u16, data: Vec<u8> },
+}
+
+/// Fixed part of the learn action, the flow mod specs are kept as raw bytes.
+#[derive(Debug, PartialEq, Clone)]
+pub struct NxLearn {
+    pub idle_timeout: u16,
+    pub hard_timeout: u16,
+    pub priority: u16,
+    pub cookie: u64,
+    pub flags: u16,
+    pub table_id: u8,
+    //pad 1 byte
+    pub fin_idle_timeout: u16,
+    pub fin_hard_timeout: u16,
+    /// flow mod specs including the padding of the action
+    pub specs: Vec<u8>,
+}
+
+/// Action of the connection tracker (NXAST_CT).
+#[derive(Debug, PartialEq, Clone)]
+pub struct NxConntrack {
+    pub flags: CtFlags,
+    /// field the zone is read from, 0 if zone is the zone itself
+    pub zone_src: u32,
+    /// zone or the bits of zone_src (ofs_nbits) if zone_src is set
+    pub zone: u16,
+    /// table the packet continues in after tracking, None to continue with the next action
+    pub recirc_table: Option<u8>,
+    //pad 3 bytes
+    /// application layer gateway, the well known port of the protocol (eg. 21 for ftp)
+    pub alg: u16,
+    /// actions applied to the connection (set_field of ct_mark and ct_label, nat),
+    /// only executed with commit
+    pub actions: Vec<ActionHeader>,
+}
+
+impl NxConntrack {
+    /// tracks the packet in the zone without committing or recirculating it
+    pub fn new(zone: u16) -> Self {
+        NxConntrack {
+            flags: CtFlags::empty(),
+            zone_src: 0,
+            zone: zone,
+            recirc_table: None,
+            alg: 0,
+            actions: Vec::new(),
+        }
+    }
+
+    /// commits the connection and applies the actions to it
+    pub fn commit(zone: u16, actions: Vec<ActionHeader>) -> Self {
+        NxConntrack {
+            flags: CtFlags::COMMIT,
+            actions: actions,
+            ..NxConntrack::new(zone)
+        }
+    }
+}
+
+bitflags! {
+    /// Flags of the ct action.
+    pub struct CtFlags: u16 {
+        /// Commits the connection to the connection tracker.
+        const COMMIT = 1 << 0;
+        /// Commits as a new connection if the existing one is in the other direction.
+        const FORCE = 1 << 1;
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for NxAction {
+    type Error = Error;
+    /// decodes the action from the bytes after the vendor id
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(bytes);
+        let subtype = cursor.read_u16::<BigEndian>()?;
+        Ok(match subtype {
+            NXAST_RESUBMIT | NXAST_RESUBMIT_TABLE => {
+                let in_port = cursor.read_u16::<BigEndian>()?;
+                let table = cursor.read_u8()?;
+                NxAction::Resubmit {
+                    in_port: in_port,
+                    table: if subtype == NXAST_RESUBMIT_TABLE {
+                        Some(table)
+                    } else {
+                        None
+                    },
+                }
+            }
+            NXAST_CONJUNCTION => {
+                let clause = cursor.read_u8()?;
+                let n_clauses = cursor.read_u8()?;
+                NxAction::Conjunction {
+                    id: cursor.read_u32::<BigEndian>()?,
+                    clause: clause,
+                    n_clauses: n_clauses,
+                }
+            }
+            NXAST_CT => {
+                let raw_flags = cursor.read_u16::<BigEndian>()?;
+                let flags = flags_from_bits!(CtFlags, raw_flags)?;
+                let zone_src = cursor.read_u32::<BigEndian>()?;
+                let zone = cursor.read_u16::<BigEndian>()?;
+                let recirc_table = cursor.read_u8()?;
+                cursor.seek(SeekFrom::Current(3)).unwrap(); // pad 3 bytes
+                let alg = cursor.read_u16::<BigEndian>()?;
+                // nested actions fill the rest of the action
+                let actions = &bytes[cursor.position() as usize..];
+                NxAction::Conntrack(NxConntrack {
+                    flags: flags,
+                    zone_src: zone_src,
+                    zone: zone,
+                    recirc_table: if recirc_table == NX_CT_RECIRC_NONE {
+                        None
+                    } else {
+                        Some(recirc_table)
+                    },
+                    alg: alg,
+                    actions: actions_from_slice(actions)?,
+                })
+            }
+            NXAST_LEARN => {
+                let idle_timeout = cursor.read_u16::<BigEndian>()?;
+                let hard_timeout = cursor.read_u16::<BigEndian>()?;
+                let priority = cursor.read_u16::<BigEndian>()?;
+                let cookie = cursor.read_u64::<BigEndian>()?;
+                let flags = cursor.read_u16::<BigEndian>()?;
+                let table_id = cursor.read_u8()?;
+                cursor.seek(SeekFrom::Current(1)).unwrap(); // pad 1 byte
+                let fin_idle_timeout = cursor.read_u16::<BigEndian>()?;
+                let fin_hard_timeout = cursor.read_u16::<BigEndian>()?;
+                NxAction::Learn(NxLearn {
+                    idle_timeout: idle_timeout,
+                    hard_timeout: hard_timeout,
+                    priority: priority,
+                    cookie: cookie,
+                    flags: flags,
+                    table_id: table_id,
+                    fin_idle_timeout: fin_idle_timeout,
+                    fin_hard_timeout: fin_hard_timeout,
+                    specs: Vec::from(&bytes[cursor.position() as usize..]),
+                })
+            }
+            _ => NxAction::Other {
+                subtype: subtype,
+                data: Vec::from(&bytes[2..]),
+            },
+        })
+    }
+}
+
+impl Into<Vec<u8>> for NxAction {
+    /// encodes the action without action header and vendor id,
+    /// padded so the whole action is a multiple of 8 bytes long
+    fn into(self) -> Vec<u8> {
+        let mut res = Vec::new();
+        match self {
+            NxAction::Resubmit { in_port, table } => match table {
+                Some(table) => {
+                    res.write_u16::<BigEndian>(NXAST_RESUBMIT_TABLE).unwrap();
+                    res.write_u16::<BigEndian>(in_port).unwrap();
+                    res.write_u8(table).unwrap();
+                }
+                None => {
+                    res.write_u16::<BigEndian>(NXAST_RESUBMIT).unwrap();
+                    res.write_u16::<BigEndian>(in_port).unwrap();
+                }
+            },
+            NxAction::Conjunction {
+                id,
+                clause,
+                n_clauses,
+            } => {
+                res.write_u16::<BigEndian>(NXAST_CONJUNCTION).unwrap();
+                res.write_u8(clause).unwrap();
+                res.write_u8(n_clauses).unwrap();
+                res.write_u32::<BigEndian>(id).unwrap();
+            }
+            NxAction::Conntrack(ct) => {
+                res.write_u16::<BigEndian>(NXAST_CT).unwrap();
+                res.write_u16::<BigEndian>(ct.flags.bits()).unwrap();
+                res.write_u32::<BigEndian>(ct.zone_src).unwrap();
+                res.write_u16::<BigEndian>(ct.zone).unwrap();
+                res.write_u8(ct.recirc_table.unwrap_or(NX_CT_RECIRC_NONE))
+                    .unwrap();
+                res.write_u8(0).unwrap(); // pad 3 bytes
+                res.write_u16::<BigEndian>(0).unwrap();
+                res.write_u16::<BigEndian>(ct.alg).unwrap();
+                res.extend_from_slice(&encode_list(ct.actions)[..]);
+            }
+            NxAction::Learn(learn) => {
+                res.write_u16::<BigEndian>(NXAST_LEARN).unwrap();
+                res.write_u16::<BigEndian>(learn.idle_timeout).unwrap();
+                res.write_u16::<BigEndian>(learn.hard_timeout).unwrap();
+                res.write_u16::<BigEndian>(learn.priority).unwrap();
+                res.write_u64::<BigEndian>(learn.cookie).unwrap();
+                res.write_u16::<BigEndian>(learn.flags).unwrap();
+                res.write_u8(learn.table_id).unwrap();
+                res.write_u8(0).unwrap(); // pad 1 byte
+                res.write_u16::<BigEndian>(learn.fin_idle_timeout).unwrap();
+                res.write_u16::<BigEndian>(learn.fin_hard_timeout).unwrap();
+                res.extend_from_slice(&learn.specs[..]);
+            }
+            NxAction::Other { subtype, data } => {
+                res.write_u16::<BigEndian>(subtype).unwrap();
+                res.extend_from_slice(&data[..]);
+            }
+        }
+        // the action header and vendor id are 8 bytes, pad the rest
+        while !(res.len() + NX_ACTION_HEADER_LEN - 2).is_multiple_of(8) {
+            res.write_u8(0).unwrap();
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::actions::{ActionPayload, PayloadSetField};
+    use super::super::flow_match::{Match, MatchPayload, TlvMatch};
+    use super::*;
+
+    #[test]
+    fn decodes_nxm_fields() {
+        let bytes = [
+            0x00, 0x01, 0x00, 0x1e, // match header
+            0x00, 0x01, 0x04, 0x04, // NXM_NX_REG2
+            0x00, 0x00, 0x00, 0x2a, // value
+            0x00, 0x01, 0xd3, 0x08, // NXM_NX_CT_STATE masked
+            0x00, 0x00, 0x00, 0x21, // +trk+new
+            0x00, 0x00, 0x00, 0x23, // mask
+            0x00, 0x00, 0x02, 0x02, // NXM_OF_ETH_SRC is kept raw
+            0x00, 0x01, 0x00, 0x00, // value, 2 bytes of padding of the match
+        ];
+        let mmatch = Match::try_from(&bytes[..]).unwrap();
+        let payloads: Vec<&MatchPayload> =
+            mmatch.matches().iter().map(|tlv| tlv.payload()).collect();
+        assert_eq!(
+            payloads,
+            vec![
+                &MatchPayload::Nxm(NxmField::Reg {
+                    index: 2,
+                    value: 42,
+                }),
+                &MatchPayload::Nxm(NxmField::CtState(CtState::TRACKED | CtState::NEW)),
+                &MatchPayload::Nxm(NxmField::Other {
+                    class: NXM_CLASS_0,
+                    field: 1,
+                    data: vec![0x00, 0x01],
+                }),
+            ]
+        );
+        assert_eq!(Into::<Vec<u8>>::into(mmatch.clone()), bytes.to_vec());
+
+        let rebuilt = Match::new(vec![
+            TlvMatch::new(MatchPayload::Nxm(NxmField::Reg {
+                index: 2,
+                value: 42,
+            })),
+            TlvMatch::with_mask(
+                MatchPayload::Nxm(NxmField::CtState(CtState::TRACKED | CtState::NEW)),
+                vec![0x00, 0x00, 0x00, 0x23],
+            )
+            .unwrap(),
+            mmatch.matches()[2].clone(),
+        ]);
+        assert_eq!(rebuilt, mmatch);
+    }
+
+    #[test]
+    fn decodes_nicira_actions() {
+        let actions: Vec<ActionHeader> = vec![
+            ActionPayload::Nicira(NxAction::Resubmit {
+                in_port: 0xfff8,
+                table: Some(3),
+            })
+            .into(),
+            ActionPayload::Nicira(NxAction::Conjunction {
+                id: 7,
+                clause: 1,
+                n_clauses: 2,
+            })
+            .into(),
+            ActionPayload::Nicira(NxAction::Learn(NxLearn {
+                idle_timeout: 10,
+                hard_timeout: 0,
+                priority: 100,
+                cookie: 0,
+                flags: 0,
+                table_id: 1,
+                fin_idle_timeout: 0,
+                fin_hard_timeout: 0,
+                specs: vec![0; 8],
+            }))
+            .into(),
+        ];
+        let lens: Vec<u16> = actions.iter().map(|action| *action.len()).collect();
+        assert_eq!(lens, vec![16, 16, 40]);
+
+        let mut bytes = Vec::new();
+        for action in actions.clone() {
+            bytes.extend(Into::<Vec<u8>>::into(action));
+        }
+        assert_eq!(
+            &bytes[..10],
+            &[0xff, 0xff, 0x00, 0x10, 0x00, 0x00, 0x23, 0x20, 0x00, 0x0e]
+        );
+        assert_eq!(actions_from_slice(&bytes[..]).unwrap(), actions);
+    }
+
+    #[test]
+    fn encodes_conntrack_actions() {
+        let set_mark = PayloadSetField::new(MatchPayload::Nxm(NxmField::CtMark(1))).unwrap();
+        let mut ct = NxConntrack::commit(5, vec![set_mark.into()]);
+        ct.recirc_table = Some(2);
+        let action: ActionHeader = ActionPayload::Nicira(NxAction::Conntrack(ct)).into();
+        assert_eq!(*action.len(), 24 + 16);
+
+        let bytes: Vec<u8> = action.clone().into();
+        assert_eq!(
+            &bytes[..24],
+            &[
+                0xff, 0xff, 0x00, 0x28, // experimenter action
+                0x00, 0x00, 0x23, 0x20, // nicira
+                0x00, 0x23, 0x00, 0x01, // NXAST_CT, commit
+                0x00, 0x00, 0x00, 0x00, // zone_src
+                0x00, 0x05, 0x02, 0x00, // zone, recirc_table, pad
+                0x00, 0x00, 0x00, 0x00, // pad, alg
+            ]
+        );
+        // set_field of NXM_NX_CT_MARK
+        assert_eq!(&bytes[28..32], &[0x00, 0x01, 0xd6, 0x04]);
+        assert_eq!(actions_from_slice(&bytes[..]).unwrap(), vec![action]);
+
+        let labels = [
+            0x00, 0x01, 0xd8, 0x10, // NXM_NX_CT_LABEL
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07, //
+        ];
+        let header = super::super::flow_match::OxmTlvHeader(0x0001_d810);
+        let tlv_match = TlvMatch::try_from(header, &labels[4..]).unwrap();
+        assert_eq!(
+            tlv_match.payload(),
+            &MatchPayload::Nxm(NxmField::CtLabel(7))
+        );
+        assert_eq!(Into::<Vec<u8>>::into(tlv_match), labels.to_vec());
+    }
+}
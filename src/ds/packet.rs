@@ -0,0 +1,468 @@
+//! Ethernet frames carried by packet ins and packet outs.
+//!
+//! Only what the controller needs to triage and answer packets itself is
+//! supported: reading the ether type behind any vlan tags and the source
+//! addresses, classifying control plane protocols (`classify`) and parsing and
+//! crafting arp packets (IPv4 over ethernet).
+
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::net;
+
+use super::addr::{MacAddr, MAC_ADDR_LEN};
+use super::checked_slice;
+use super::flow_match::{ArpOp, EtherType};
+
+use super::super::err::*;
+
+/// Length of the ethernet header without vlan tags (14 bytes).
+pub const ETH_HEADER_LENGTH: usize = 14;
+/// Shortest ethernet frame without the frame check sequence (60 bytes).
+pub const ETH_MIN_FRAME_LENGTH: usize = 60;
+/// Length of an arp packet for IPv4 over ethernet (28 bytes).
+pub const ARP_LENGTH: usize = 28;
+/// Length of a vlan tag (tag protocol id and tag control information).
+pub const VLAN_TAG_LENGTH: usize = 4;
+/// Length of an IPv4 header without options (20 bytes).
+pub const IPV4_HEADER_LENGTH: usize = 20;
+
+const IPV4_DONT_FRAGMENT: u16 = 0x4000;
+const IPV4_MORE_FRAGMENTS: u16 = 0x2000;
+const IPV4_FRAGMENT_OFFSET: u16 = 0x1fff;
+
+/// ether type of the frame after any vlan tags and the offset its payload starts at
+/// None if the frame is cut off
+pub fn eth_type(frame: &[u8]) -> Option<(u16, usize)> {
+    let mut offset = 2 * MAC_ADDR_LEN;
+    loop {
+        let bytes = frame.get(offset..offset + 2)?;
+        match BigEndian::read_u16(bytes) {
+            // 802.1Q and 802.1ad tags are followed by the next ether type
+            0x8100 | 0x88a8 => offset += VLAN_TAG_LENGTH,
+            eth_type => return Some((eth_type, offset + 2)),
+        }
+    }
+}
+
+/// Control plane protocol of a frame, see `classify`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum PacketClass {
+    /// link layer discovery (ether type 0x88cc)
+    Lldp,
+    Arp,
+    /// dhcp or dhcpv6 (udp ports 67 and 68, 546 and 547)
+    Dhcp,
+    Igmp,
+    /// icmpv6 router and neighbor solicitations and advertisements and redirects
+    NeighborDiscovery,
+    /// any other frame, with its ether type
+    Other(u16),
+    /// the frame ends before its class is known
+    Truncated,
+}
+
+/// identifies common control plane protocols by looking at a few header fields,
+/// the frame is not validated (eg. checksums or ip options)
+pub fn classify(frame: &[u8]) -> PacketClass {
+    let (eth_type, offset) = match eth_type(frame) {
+        Some(eth_type) => eth_type,
+        None => return PacketClass::Truncated,
+    };
+    let payload = &frame[offset..];
+    let class = match eth_type {
+        0x88cc => Some(PacketClass::Lldp),
+        0x0806 => Some(PacketClass::Arp),
+        0x0800 => classify_ipv4(payload),
+        0x86dd => classify_ipv6(payload),
+        _ => Some(PacketClass::Other(eth_type)),
+    };
+    class.unwrap_or(PacketClass::Truncated)
+}
+
+fn classify_ipv4(packet: &[u8]) -> Option<PacketClass> {
+    let header_len = (*packet.first()? & 0x0f) as usize * 4;
+    let protocol = *packet.get(9)?;
+    // only the first fragment has the udp header
+    let fragment_offset = BigEndian::read_u16(packet.get(6..8)?) & 0x1fff;
+    Some(match protocol {
+        2 => PacketClass::Igmp,
+        17 if fragment_offset == 0 => match udp_ports(packet, header_len)? {
+            (67, 68) | (68, 67) | (67, 67) => PacketClass::Dhcp,
+            _ => PacketClass::Other(0x0800),
+        },
+        _ => PacketClass::Other(0x0800),
+    })
+}
+
+fn classify_ipv6(packet: &[u8]) -> Option<PacketClass> {
+    let mut next_header = *packet.get(6)?;
+    let mut offset = 40;
+    // hop by hop, routing and destination options headers
+    while next_header == 0 || next_header == 43 || next_header == 60 {
+        next_header = *packet.get(offset)?;
+        offset += (*packet.get(offset + 1)? as usize + 1) * 8;
+    }
+    Some(match next_header {
+        58 => match *packet.get(offset)? {
+            133..=137 => PacketClass::NeighborDiscovery,
+            _ => PacketClass::Other(0x86dd),
+        },
+        17 => match udp_ports(packet, offset)? {
+            (546, 547) | (547, 546) | (547, 547) => PacketClass::Dhcp,
+            _ => PacketClass::Other(0x86dd),
+        },
+        _ => PacketClass::Other(0x86dd),
+    })
+}
+
+/// source and destination port of the udp header at offset
+fn udp_ports(packet: &[u8], offset: usize) -> Option<(u16, u16)> {
+    let udp = packet.get(offset..offset + 4)?;
+    Some((
+        BigEndian::read_u16(&udp[..2]),
+        BigEndian::read_u16(&udp[2..]),
+    ))
+}
+
+/// source mac address of the frame, None if the frame is cut off
+pub fn src_mac(frame: &[u8]) -> Option<MacAddr> {
+    MacAddr::from_slice(frame.get(MAC_ADDR_LEN..2 * MAC_ADDR_LEN)?).ok()
+}
+
+/// source ip address of arp (sender), IPv4 and IPv6 frames
+/// None for other frames, if the frame is cut off or the address is unspecified
+pub fn src_ip(frame: &[u8]) -> Option<net::IpAddr> {
+    let (eth_type, offset) = eth_type(frame)?;
+    let payload = &frame[offset..];
+    let ip = match eth_type {
+        0x0806 => {
+            let ip = payload.get(14..18)?;
+            net::IpAddr::V4(net::Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]))
+        }
+        0x0800 => {
+            let ip = payload.get(12..16)?;
+            net::IpAddr::V4(net::Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]))
+        }
+        0x86dd => {
+            let mut ip = [0u8; 16];
+            ip.copy_from_slice(payload.get(8..24)?);
+            net::IpAddr::V6(net::Ipv6Addr::from(ip))
+        }
+        _ => return None,
+    };
+    if ip.is_unspecified() {
+        None
+    } else {
+        Some(ip)
+    }
+}
+
+/// bytes after the ethernet header and any vlan tags, the length the MTU of a
+/// port limits, the length of the whole frame if it is cut off
+pub fn payload_len(frame: &[u8]) -> usize {
+    match eth_type(frame) {
+        Some((_, offset)) => frame.len() - offset,
+        None => frame.len(),
+    }
+}
+
+/// splits the IPv4 packet of the frame into fragments of at most mtu bytes
+/// (see `payload_len`), each in a copy of the ethernet header and vlan tags
+/// None if the frame is no IPv4 packet, has ip options, is cut off or has the
+/// don't fragment bit set, or if the mtu leaves no room for data
+pub fn fragment_ipv4(frame: &[u8], mtu: usize) -> Option<Vec<Vec<u8>>> {
+    let (eth_type, offset) = eth_type(frame)?;
+    let packet = &frame[offset..];
+    // version 4 without options
+    if eth_type != 0x0800 || packet.len() < IPV4_HEADER_LENGTH || packet[0] != 0x45 {
+        return None;
+    }
+    let total_len = BigEndian::read_u16(&packet[2..4]) as usize;
+    let flags = BigEndian::read_u16(&packet[6..8]);
+    if total_len < IPV4_HEADER_LENGTH || total_len > packet.len() {
+        return None;
+    }
+    if flags & IPV4_DONT_FRAGMENT != 0 {
+        return None;
+    }
+    if total_len <= mtu {
+        return Some(vec![frame.to_vec()]);
+    }
+    // the data of all fragments but the last is a multiple of 8 bytes
+    let chunk = mtu.checked_sub(IPV4_HEADER_LENGTH)? & !7;
+    if chunk == 0 {
+        return None;
+    }
+    // the packet may be a fragment itself
+    let first_offset = (flags & IPV4_FRAGMENT_OFFSET) as usize;
+    let data = &packet[IPV4_HEADER_LENGTH..total_len];
+    let fragments = data
+        .chunks(chunk)
+        .enumerate()
+        .map(|(i, fragment_data)| {
+            let last = (i + 1) * chunk >= data.len();
+            let mut fragment_flags = (first_offset + i * chunk / 8) as u16;
+            if !last || flags & IPV4_MORE_FRAGMENTS != 0 {
+                fragment_flags |= IPV4_MORE_FRAGMENTS;
+            }
+            let mut fragment = frame[..offset + IPV4_HEADER_LENGTH].to_vec();
+            {
+                let header = &mut fragment[offset..];
+                let len = (IPV4_HEADER_LENGTH + fragment_data.len()) as u16;
+                BigEndian::write_u16(&mut header[2..4], len);
+                BigEndian::write_u16(&mut header[6..8], fragment_flags);
+                BigEndian::write_u16(&mut header[10..12], 0);
+                let checksum = ipv4_checksum(header);
+                BigEndian::write_u16(&mut header[10..12], checksum);
+            }
+            fragment.extend_from_slice(fragment_data);
+            if fragment.len() < ETH_MIN_FRAME_LENGTH {
+                fragment.resize(ETH_MIN_FRAME_LENGTH, 0);
+            }
+            fragment
+        })
+        .collect();
+    Some(fragments)
+}
+
+/// internet checksum of the header, its checksum field has to be zero
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header
+        .chunks(2)
+        .map(|word| BigEndian::read_u16(word) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Arp request or reply of IPv4 over ethernet.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Arp {
+    pub op: ArpOp,
+    pub sender_mac: MacAddr,
+    pub sender_ip: net::Ipv4Addr,
+    pub target_mac: MacAddr,
+    pub target_ip: net::Ipv4Addr,
+    /// vlan tags of the frame in wire format, replies carry the same tags
+    pub vlan_tags: Vec<u8>,
+}
+
+impl Arp {
+    /// asks who has target_ip, sent untagged
+    pub fn request(
+        sender_mac: MacAddr,
+        sender_ip: net::Ipv4Addr,
+        target_ip: net::Ipv4Addr,
+    ) -> Self {
+        Arp {
+            op: ArpOp::Request,
+            sender_mac: sender_mac,
+            sender_ip: sender_ip,
+            target_mac: MacAddr::default(),
+            target_ip: target_ip,
+            vlan_tags: Vec::new(),
+        }
+    }
+
+    /// answers this request, mac is the address of the target ip
+    pub fn reply(&self, mac: MacAddr) -> Self {
+        Arp {
+            op: ArpOp::Reply,
+            sender_mac: mac,
+            sender_ip: self.target_ip,
+            target_mac: self.sender_mac,
+            target_ip: self.sender_ip,
+            vlan_tags: self.vlan_tags.clone(),
+        }
+    }
+
+    /// parses the arp packet of an ethernet frame
+    pub fn from_frame(frame: &[u8]) -> Result<Self> {
+        let (eth_type, offset) = match eth_type(frame) {
+            Some(eth_type) => eth_type,
+            None => bail!(ErrorKind::InvalidSliceLength(
+                ETH_HEADER_LENGTH,
+                frame.len(),
+                stringify!(Arp)
+            )),
+        };
+        if eth_type != EtherType::Arp.to_u16().unwrap() {
+            bail!(ErrorKind::UnsupportedValue(
+                eth_type as u64,
+                stringify!(EtherType)
+            ));
+        }
+        let bytes = checked_slice(frame, offset, ARP_LENGTH, stringify!(Arp))?;
+        // hardware type ethernet, protocol type IPv4 and their address lengths
+        if bytes[..6] != [0, 1, 0x08, 0x00, 6, 4] {
+            bail!(ErrorKind::UnsupportedValue(
+                BigEndian::read_u16(&bytes[..2]) as u64,
+                stringify!(Arp)
+            ));
+        }
+        let op_raw = BigEndian::read_u16(&bytes[6..8]);
+        let op = ArpOp::from_u16(op_raw)
+            .ok_or::<Error>(ErrorKind::UnknownValue(op_raw as u64, stringify!(ArpOp)).into())?;
+        Ok(Arp {
+            op: op,
+            sender_mac: MacAddr::from_slice(&bytes[8..14])?,
+            sender_ip: ipv4(&bytes[14..18]),
+            target_mac: MacAddr::from_slice(&bytes[18..24])?,
+            target_ip: ipv4(&bytes[24..28]),
+            vlan_tags: frame[2 * MAC_ADDR_LEN..offset - 2].to_vec(),
+        })
+    }
+
+    /// ethernet frame of the packet, requests are broadcast and replies sent
+    /// to the target mac, padded to the minimum frame length
+    pub fn to_frame(&self) -> Vec<u8> {
+        let eth_dst = match self.op {
+            ArpOp::Request => MacAddr::broadcast(),
+            _ => self.target_mac,
+        };
+        let mut frame = Vec::with_capacity(ETH_MIN_FRAME_LENGTH);
+        frame.extend_from_slice(&eth_dst.octets());
+        frame.extend_from_slice(&self.sender_mac.octets());
+        frame.extend_from_slice(&self.vlan_tags[..]);
+        frame
+            .write_u16::<BigEndian>(EtherType::Arp.to_u16().unwrap())
+            .unwrap();
+        frame.extend_from_slice(&[0, 1, 0x08, 0x00, 6, 4]);
+        frame
+            .write_u16::<BigEndian>(self.op.to_u16().unwrap())
+            .unwrap();
+        frame.extend_from_slice(&self.sender_mac.octets());
+        frame.extend_from_slice(&self.sender_ip.octets());
+        frame.extend_from_slice(&self.target_mac.octets());
+        frame.extend_from_slice(&self.target_ip.octets());
+        if frame.len() < ETH_MIN_FRAME_LENGTH {
+            frame.resize(ETH_MIN_FRAME_LENGTH, 0);
+        }
+        frame
+    }
+}
+
+fn ipv4(bytes: &[u8]) -> net::Ipv4Addr {
+    net::Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arp_round_trip() {
+        let mac = MacAddr([2, 0, 0, 0, 0, 1]);
+        let mut request = Arp::request(
+            mac,
+            net::Ipv4Addr::new(10, 0, 0, 1),
+            net::Ipv4Addr::new(10, 0, 0, 2),
+        );
+        request.vlan_tags = vec![0x81, 0x00, 0x00, 0x0a];
+        let frame = request.to_frame();
+        assert_eq!(frame.len(), ETH_MIN_FRAME_LENGTH);
+        assert_eq!(&frame[..6], &[0xff; 6]);
+        assert_eq!(eth_type(&frame), Some((0x0806, 18)));
+        assert_eq!(Arp::from_frame(&frame).unwrap(), request);
+
+        let reply = request.reply(MacAddr([2, 0, 0, 0, 0, 2]));
+        let frame = reply.to_frame();
+        assert_eq!(&frame[..6], &mac.octets());
+        let decoded = Arp::from_frame(&frame).unwrap();
+        assert_eq!(decoded.op, ArpOp::Reply);
+        assert_eq!(decoded.sender_ip, net::Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(decoded.vlan_tags, request.vlan_tags);
+        assert_eq!(src_mac(&frame), Some(MacAddr([2, 0, 0, 0, 0, 2])));
+        assert_eq!(src_ip(&frame), Some(net::Ipv4Addr::new(10, 0, 0, 2).into()));
+
+        assert!(Arp::from_frame(&frame[..30]).is_err());
+        let mut ip = frame.clone();
+        ip[16] = 0x08;
+        ip[17] = 0x00;
+        assert!(Arp::from_frame(&ip).is_err());
+    }
+
+    /// ethernet header with the ether type followed by the payload
+    fn frame(eth_type: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0xff; 12];
+        frame.write_u16::<BigEndian>(eth_type).unwrap();
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn classifies_control_plane_packets() {
+        let mut ipv4 = vec![0x45, 0, 0, 48, 0, 0, 0, 0, 64, 17, 0, 0];
+        ipv4.extend_from_slice(&[0; 8]); // addresses
+        ipv4.extend_from_slice(&[0, 68, 0, 67, 0, 28, 0, 0]); // udp
+        assert_eq!(classify(&frame(0x0800, &ipv4)), PacketClass::Dhcp);
+        ipv4[23] = 53;
+        assert_eq!(classify(&frame(0x0800, &ipv4)), PacketClass::Other(0x0800));
+        ipv4[9] = 2;
+        assert_eq!(classify(&frame(0x0800, &ipv4)), PacketClass::Igmp);
+
+        // neighbor solicitation behind a hop by hop header
+        let mut ipv6 = vec![0x60, 0, 0, 0, 0, 32, 0, 255];
+        ipv6.extend_from_slice(&[0; 32]); // addresses
+        ipv6.extend_from_slice(&[58, 0, 0, 0, 0, 0, 0, 0]);
+        ipv6.extend_from_slice(&[135, 0, 0, 0]);
+        assert_eq!(
+            classify(&frame(0x86dd, &ipv6)),
+            PacketClass::NeighborDiscovery
+        );
+        assert_eq!(
+            classify(&frame(0x86dd, &ipv6[..48])),
+            PacketClass::Truncated
+        );
+
+        assert_eq!(classify(&frame(0x88cc, &[])), PacketClass::Lldp);
+        let arp = Arp::request(
+            MacAddr::default(),
+            net::Ipv4Addr::new(10, 0, 0, 1),
+            net::Ipv4Addr::new(10, 0, 0, 2),
+        );
+        assert_eq!(classify(&arp.to_frame()), PacketClass::Arp);
+        assert_eq!(classify(&frame(0x8847, &[])), PacketClass::Other(0x8847));
+        assert_eq!(classify(&[0; 13]), PacketClass::Truncated);
+    }
+
+    #[test]
+    fn fragments_ipv4_packets() {
+        // udp packet of 20 + 8 + 1472 bytes with id 7
+        let mut ipv4 = vec![0x45, 0, 0x05, 0xdc, 0, 7, 0, 0, 64, 17, 0, 0];
+        ipv4.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        ipv4.extend((0..1480).map(|i| i as u8));
+        let mut packet = frame(0x0800, &ipv4);
+        // tagged
+        packet.splice(12..12, vec![0x81, 0x00, 0x00, 0x0a]);
+        assert_eq!(payload_len(&packet), 1500);
+        assert_eq!(fragment_ipv4(&packet, 1500), Some(vec![packet.clone()]));
+
+        let fragments = fragment_ipv4(&packet, 1000).unwrap();
+        assert_eq!(fragments.len(), 2);
+        let (first, second) = (&fragments[0], &fragments[1]);
+        assert_eq!(&first[..18], &packet[..18]);
+        // 976 bytes of data, a multiple of 8, with more fragments
+        assert_eq!(payload_len(first), 996);
+        assert_eq!(&first[20..22], &[0x03, 0xe4]);
+        assert_eq!(&first[24..26], &[0x20, 0x00]);
+        assert_eq!(ipv4_checksum(&first[18..38]), 0);
+        assert_eq!(&first[38..], &ipv4[20..996]);
+        // the rest at offset 976 / 8
+        assert_eq!(payload_len(second), 524);
+        assert_eq!(&second[24..26], &[0x00, 122]);
+        assert_eq!(ipv4_checksum(&second[18..38]), 0);
+        assert_eq!(&second[38..], &ipv4[996..]);
+        assert_eq!(&second[22..24], &[0, 7]);
+
+        // don't fragment, no room for data and other packets
+        let mut dont_fragment = packet.clone();
+        dont_fragment[24] = 0x40;
+        assert_eq!(fragment_ipv4(&dont_fragment, 1000), None);
+        assert_eq!(fragment_ipv4(&packet, 27), None);
+        assert_eq!(fragment_ipv4(&frame(0x86dd, &ipv4), 1000), None);
+        assert_eq!(fragment_ipv4(&packet[..100], 60), None);
+    }
+}
@@ -3,13 +3,21 @@ use num_traits::{FromPrimitive, ToPrimitive};
 use std::convert::{Into, TryFrom};
 use std::io::{Cursor, Seek, SeekFrom};
 
+use super::codec::OfEncode;
 use super::flow_match::Match;
+use super::packet;
+use super::packet_out::BufferId;
+use super::ports::PortNumber;
+use super::{checked_slice, checked_tail};
 
 use super::super::err::*;
 
+/// Length of a packet in before the match (16 bytes).
+pub const PACKET_IN_LENGTH: usize = 16;
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct PacketIn {
-    pub buffer_id: u32,
+    pub buffer_id: BufferId,
     pub total_len: u16,
     pub reason: InReason,
     pub table_id: u8,
@@ -19,29 +27,50 @@ pub struct PacketIn {
     pub ethernet_frame: Vec<u8>,
 }
 
+impl PacketIn {
+    /// port the packet was received on
+    pub fn in_port(&self) -> Option<PortNumber> {
+        self.mmatch.in_port()
+    }
+
+    /// ether type of the frame after any vlan tags, None if the frame is cut off
+    pub fn eth_type(&self) -> Option<u16> {
+        packet::eth_type(&self.ethernet_frame).map(|(eth_type, _)| eth_type)
+    }
+
+    /// control plane protocol of the frame, eg. to pass lldp to the topology discovery
+    pub fn class(&self) -> packet::PacketClass {
+        packet::classify(&self.ethernet_frame)
+    }
+}
+
 impl<'a> TryFrom<&'a [u8]> for PacketIn {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let buffer_id = cursor.read_u32::<BigEndian>().unwrap();
-        let total_len = cursor.read_u16::<BigEndian>().unwrap();
-        let reason_raw = cursor.read_u8().unwrap();
+        let buffer_id = BufferId::from(cursor.read_u32::<BigEndian>()?);
+        let total_len = cursor.read_u16::<BigEndian>()?;
+        let reason_raw = cursor.read_u8()?;
         let reason = InReason::from_u8(reason_raw).ok_or::<Error>(
             ErrorKind::UnknownValue(reason_raw as u64, stringify!(InReason)).into(),
         )?;
-        let table_id = cursor.read_u8().unwrap();
-        let cookie = cursor.read_u64::<BigEndian>().unwrap();
+        let table_id = cursor.read_u8()?;
+        let cookie = cursor.read_u64::<BigEndian>()?;
 
         let mmatch_slice_len = Match::read_len(&mut cursor)?;
-        let mmatch_slice =
-            &bytes[cursor.position() as usize..cursor.position() as usize + mmatch_slice_len];
-        let mmatch = Match::try_from(mmatch_slice)?;
+        let mmatch_slice = checked_slice(
+            bytes,
+            cursor.position() as usize,
+            mmatch_slice_len,
+            stringify!(Match),
+        )?;
+        let mmatch = Match::try_from(mmatch_slice).within(cursor.position() as usize)?;
         cursor
             .seek(SeekFrom::Current(mmatch_slice_len as i64))
             .unwrap();
 
         cursor.seek(SeekFrom::Current(2)).unwrap(); //2 bytes padding
-        let eth_slice = &bytes[cursor.position() as usize..];
+        let eth_slice = checked_tail(bytes, cursor.position() as usize, stringify!(PacketIn))?;
         let ethernet_frame = Vec::from(eth_slice);
 
         Ok(PacketIn {
@@ -56,17 +85,27 @@ impl<'a> TryFrom<&'a [u8]> for PacketIn {
     }
 }
 
+impl OfEncode for PacketIn {
+    fn encoded_len(&self) -> usize {
+        // the match is followed by 2 bytes padding
+        PACKET_IN_LENGTH + self.mmatch.encoded_len() + 2 + self.ethernet_frame.len()
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.write_u32::<BigEndian>(self.buffer_id.into()).unwrap();
+        buf.write_u16::<BigEndian>(self.total_len).unwrap();
+        buf.write_u8(self.reason.to_u8().unwrap()).unwrap();
+        buf.write_u8(self.table_id).unwrap();
+        buf.write_u64::<BigEndian>(self.cookie).unwrap();
+        self.mmatch.encode_into(buf);
+        buf.write_u16::<BigEndian>(0).unwrap(); //2 bytes padding
+        buf.extend_from_slice(&self.ethernet_frame[..]);
+    }
+}
+
 impl Into<Vec<u8>> for PacketIn {
     fn into(self) -> Vec<u8> {
-        let mut res = Vec::new();
-        res.write_u32::<BigEndian>(self.buffer_id).unwrap();
-        res.write_u16::<BigEndian>(self.total_len).unwrap();
-        res.write_u8(self.reason.to_u8().unwrap()).unwrap();
-        res.write_u8(self.table_id).unwrap();
-        res.write_u64::<BigEndian>(self.cookie).unwrap();
-        res.extend_from_slice(&Into::<Vec<u8>>::into(self.mmatch)[..]);
-        res.extend_from_slice(&self.ethernet_frame[..]);
-        res
+        self.to_bytes()
     }
 }
 
@@ -1,17 +1,80 @@
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::convert::{Into, TryFrom};
-use std::io::{Cursor, Seek, SeekFrom};
+use std::fmt;
+use std::io::Cursor;
 
-use super::actions::{calc_actions_len, ActionHeader};
-use super::ports::PortNumber;
+use super::actions::{actions_from_slice, calc_actions_len, ActionHeader, PayloadOutput};
+use super::checked_slice;
+use super::codec::OfEncode;
+use super::ports::{PortNo, PortNumber};
+use super::{HEADER_LENGTH, MAX_MESSAGE_LENGTH};
 
 use super::super::err::*;
 
 pub const PACKET_OUT_LEN: usize = 16;
 
+/// Buffer id of messages that do not refer to a buffered packet (OFP_NO_BUFFER).
+pub const NO_BUFFER: u32 = 0xffff_ffff;
+/// Max len of controller outputs that send the whole packet (OFPCML_NO_BUFFER).
+pub const CONTROLLER_MAX_LEN: u16 = 0xffff;
+
+/// Buffer id of packet ins, packet outs and flow mods.
+/// Converted from and into the raw u32, NO_BUFFER becomes `NoBuffer`.
+/// Create it from a raw id with `BufferId::from` instead of `Buffered`,
+/// `Buffered(NO_BUFFER)` is not a buffered packet.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum BufferId {
+    /// the message carries the packet itself (OFP_NO_BUFFER)
+    #[default]
+    NoBuffer,
+    /// the packet is buffered on the switch
+    Buffered(u32),
+}
+
+impl BufferId {
+    /// the id of the buffer, None for NoBuffer
+    pub fn buffered(&self) -> Option<u32> {
+        match *self {
+            BufferId::NoBuffer => None,
+            BufferId::Buffered(buffer_id) => Some(buffer_id),
+        }
+    }
+
+    pub fn is_buffered(&self) -> bool {
+        self.buffered().is_some()
+    }
+}
+
+impl From<u32> for BufferId {
+    fn from(buffer_id: u32) -> Self {
+        match buffer_id {
+            NO_BUFFER => BufferId::NoBuffer,
+            buffer_id => BufferId::Buffered(buffer_id),
+        }
+    }
+}
+
+impl Into<u32> for BufferId {
+    fn into(self) -> u32 {
+        match self {
+            BufferId::NoBuffer => NO_BUFFER,
+            BufferId::Buffered(buffer_id) => buffer_id,
+        }
+    }
+}
+
+impl fmt::Display for BufferId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.buffered() {
+            Some(buffer_id) => write!(f, "{}", buffer_id),
+            None => write!(f, "none"),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct PacketOut {
-    pub buffer_id: u32,
+    pub buffer_id: BufferId,
     pub in_port: PortNumber,
     pub actions_len: u16,
     //pad 6 bytes
@@ -21,7 +84,7 @@ pub struct PacketOut {
 
 impl PacketOut {
     pub fn new(
-        buffer_id: u32,
+        buffer_id: BufferId,
         in_port: PortNumber,
         actions: Vec<ActionHeader>,
         data: Vec<u8>,
@@ -34,30 +97,90 @@ impl PacketOut {
             data: data,
         }
     }
+
+    /// sends the frame out of the given port
+    /// the frame is not buffered on the switch, the in port is the controller
+    pub fn output(port: PortNumber, frame: Vec<u8>) -> Self {
+        PacketOut::with_actions(frame, vec![output_action(port)])
+    }
+
+    /// sends the frame out of all ports except the ones it would come from (OFPP_FLOOD)
+    pub fn flood(frame: Vec<u8>) -> Self {
+        PacketOut::output(PortNo::Flood.into(), frame)
+    }
+
+    /// processes the frame in the flow tables as if it arrived at in_port (OFPP_TABLE)
+    pub fn to_table(frame: Vec<u8>, in_port: PortNumber) -> Self {
+        let mut packet_out = PacketOut::output(PortNo::Table.into(), frame);
+        packet_out.in_port = in_port;
+        packet_out
+    }
+
+    /// applies the actions to the frame, the in port is the controller
+    pub fn with_actions(frame: Vec<u8>, actions: Vec<ActionHeader>) -> Self {
+        PacketOut::new(BufferId::NoBuffer, PortNo::Controller.into(), actions, frame)
+    }
+
+    /// applies the actions to a packet buffered on the switch (eg. the buffer id of a packet in)
+    /// no data is sent, the switch uses the buffered packet
+    pub fn from_buffered(buffer_id: u32, in_port: PortNumber, actions: Vec<ActionHeader>) -> Self {
+        PacketOut::new(BufferId::from(buffer_id), in_port, actions, Vec::new())
+    }
+
+    /// true if the packet out refers to a packet buffered on the switch
+    pub fn is_buffered(&self) -> bool {
+        self.buffer_id.is_buffered()
+    }
+
+    /// most bytes of data a message with these actions can carry
+    pub fn max_data_len(&self) -> usize {
+        (MAX_MESSAGE_LENGTH - HEADER_LENGTH - PACKET_OUT_LEN)
+            .saturating_sub(self.actions.encoded_len())
+    }
+
+    /// fails with ErrorKind::PacketOutTooLong if the data does not fit the
+    /// u16 length of the message header next to the actions
+    pub fn check_len(&self) -> Result<()> {
+        if self.data.len() > self.max_data_len() {
+            bail!(ErrorKind::PacketOutTooLong(
+                self.data.len(),
+                self.max_data_len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn output_action(port: PortNumber) -> ActionHeader {
+    // only outputs to the controller use max_len, send the whole packet there
+    let max_len = match port {
+        PortNumber::Reserved(PortNo::Controller) => CONTROLLER_MAX_LEN,
+        _ => 0,
+    };
+    PayloadOutput {
+        port: port,
+        max_len: max_len,
+    }.into()
 }
 
 impl<'a> TryFrom<&'a [u8]> for PacketOut {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let buffer_id = cursor.read_u32::<BigEndian>().unwrap();
-        let in_port = PortNumber::try_from(cursor.read_u32::<BigEndian>().unwrap())?;
-        let actions_len = cursor.read_u16::<BigEndian>().unwrap();
-        cursor.seek(SeekFrom::Start(8)).unwrap();
-
-        let mut actions = Vec::new();
-        let mut bytes_remaining = actions_len as usize;
-        while bytes_remaining > 0 {
-            let action_len = ActionHeader::read_len(&mut cursor)?;
-            let action_slice =
-                &bytes[cursor.position() as usize..cursor.position() as usize + action_len];
-            let action = ActionHeader::try_from(action_slice)?;
-            actions.push(action);
-            bytes_remaining -= action_len;
-            cursor.seek(SeekFrom::Current(action_len as i64)).unwrap();
-        }
+        let buffer_id = BufferId::from(cursor.read_u32::<BigEndian>()?);
+        let in_port = PortNumber::try_from(cursor.read_u32::<BigEndian>()?)?;
+        let actions_len = cursor.read_u16::<BigEndian>()?;
+        // pad 6 bytes
+
+        let actions_slice = checked_slice(
+            bytes,
+            PACKET_OUT_LEN,
+            actions_len as usize,
+            stringify!(PacketOut),
+        )?;
+        let actions = actions_from_slice(actions_slice).within(PACKET_OUT_LEN)?;
 
-        let data = Vec::from(&bytes[cursor.position() as usize..]);
+        let data = Vec::from(&bytes[PACKET_OUT_LEN + actions_len as usize..]);
 
         Ok(PacketOut {
             buffer_id: buffer_id,
@@ -69,19 +192,79 @@ impl<'a> TryFrom<&'a [u8]> for PacketOut {
     }
 }
 
+impl OfEncode for PacketOut {
+    fn encoded_len(&self) -> usize {
+        PACKET_OUT_LEN + self.actions.encoded_len() + self.data.len()
+    }
+
+    /// actions_len is computed from the actions, the stored one is ignored
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.write_u32::<BigEndian>(self.buffer_id.into()).unwrap();
+        buf.write_u32::<BigEndian>(self.in_port.clone().into())
+            .unwrap();
+        buf.write_u16::<BigEndian>(self.actions.encoded_len() as u16)
+            .unwrap();
+        //pad 6 bytes
+        buf.write_u32::<BigEndian>(0).unwrap();
+        buf.write_u16::<BigEndian>(0).unwrap();
+        self.actions.encode_into(buf);
+        buf.extend_from_slice(&self.data[..]);
+    }
+}
+
 impl Into<Vec<u8>> for PacketOut {
     fn into(self) -> Vec<u8> {
-        let mut vec = Vec::new();
-        vec.write_u32::<BigEndian>(self.buffer_id).unwrap();
-        vec.write_u32::<BigEndian>(self.in_port.into()).unwrap();
-        vec.write_u16::<BigEndian>(self.actions_len).unwrap();
-        //pad 6 bytes
-        vec.write_u32::<BigEndian>(0).unwrap();
-        vec.write_u16::<BigEndian>(0).unwrap();
-        for action in self.actions {
-            vec.extend_from_slice(&Into::<Vec<u8>>::into(action)[..]);
+        self.to_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constructors_set_buffer_and_actions_len() {
+        let flood = PacketOut::flood(vec![1, 2, 3]);
+        assert!(!flood.is_buffered());
+        assert_eq!(flood.in_port, PortNo::Controller.into());
+        assert_eq!(flood.actions_len, 16);
+        let bytes: Vec<u8> = flood.clone().into();
+        assert_eq!(PacketOut::try_from(&bytes[..]).unwrap(), flood);
+
+        let to_table = PacketOut::to_table(vec![1], PortNumber::NormalPort(3));
+        assert_eq!(to_table.in_port, PortNumber::NormalPort(3));
+
+        let buffered =
+            PacketOut::from_buffered(7, PortNumber::NormalPort(1), flood.actions.clone());
+        assert!(buffered.is_buffered());
+        assert!(buffered.data.is_empty());
+    }
+
+    #[test]
+    fn buffer_id_maps_no_buffer() {
+        assert_eq!(BufferId::from(NO_BUFFER), BufferId::NoBuffer);
+        assert_eq!(BufferId::from(7), BufferId::Buffered(7));
+        assert_eq!(Into::<u32>::into(BufferId::NoBuffer), NO_BUFFER);
+        assert_eq!(BufferId::Buffered(7).buffered(), Some(7));
+        assert!(!BufferId::from(NO_BUFFER).is_buffered());
+        let packet_out = PacketOut::from_buffered(NO_BUFFER, PortNo::Controller.into(), vec![]);
+        assert!(!packet_out.is_buffered());
+        assert_eq!(BufferId::NoBuffer.to_string(), "none");
+    }
+
+    #[test]
+    fn data_has_to_fit_the_message_length() {
+        // 8 bytes header, 16 bytes packet out and 16 bytes output action
+        let max = MAX_MESSAGE_LENGTH - 40;
+        let packet_out = PacketOut::flood(vec![0; max]);
+        assert_eq!(packet_out.max_data_len(), max);
+        assert!(packet_out.check_len().is_ok());
+        assert_eq!(packet_out.encoded_len() + HEADER_LENGTH, MAX_MESSAGE_LENGTH);
+
+        let packet_out = PacketOut::flood(vec![0; max + 1]);
+        match *packet_out.check_len().unwrap_err().kind() {
+            ErrorKind::PacketOutTooLong(len, left) => assert_eq!((len, left), (max + 1, max)),
+            ref kind => panic!("unexpected {:?}", kind),
         }
-        vec.extend_from_slice(&self.data[..]);
-        vec
     }
 }
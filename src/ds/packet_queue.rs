@@ -1,10 +1,12 @@
 use super::super::err::*;
+use super::codec::OfEncode;
 use super::ports::PortNumber;
+use super::tlv::{decode_list, LengthField};
+use super::{checked_slice, checked_tail};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use num_traits::{FromPrimitive, ToPrimitive};
 use std::convert::{Into, TryFrom};
 use std::io::{Cursor, Seek, SeekFrom};
-use std::path;
 
 /// Length in bytes of a PacketQueue struct is 16 bytes.
 pub const PACKET_QUEUE_LENGTH: usize = 16;
@@ -22,68 +24,80 @@ pub struct PacketQueue {
 }
 
 impl PacketQueue {
+    pub fn new(queue_id: u32, port: PortNumber, properties: Vec<QueuePropPayload>) -> Self {
+        let properties: Vec<QueuePropMessage> =
+            properties.into_iter().map(QueuePropMessage::new).collect();
+        let len = PACKET_QUEUE_LENGTH
+            + properties
+                .iter()
+                .map(|prop| prop.header.len as usize)
+                .sum::<usize>();
+        PacketQueue {
+            queue_id: queue_id,
+            port: port,
+            len: len as u16,
+            properties: properties,
+        }
+    }
+
+    pub fn queue_id(&self) -> u32 {
+        self.queue_id
+    }
+
+    pub fn port(&self) -> &PortNumber {
+        &self.port
+    }
+
+    pub fn properties(&self) -> Vec<&QueuePropPayload> {
+        self.properties.iter().map(|prop| &prop.payload).collect()
+    }
+
+    /// guaranteed rate, None if not configured or disabled
+    pub fn min_rate(&self) -> Option<QueueRate> {
+        self.properties
+            .iter()
+            .filter_map(|prop| match prop.payload {
+                QueuePropPayload::Min(ref min) => min.rate(),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// maximum rate, None if not configured or disabled
+    pub fn max_rate(&self) -> Option<QueueRate> {
+        self.properties
+            .iter()
+            .filter_map(|prop| match prop.payload {
+                QueuePropPayload::Max(ref max) => max.rate(),
+                _ => None,
+            })
+            .next()
+    }
+
     pub fn read_len(cursor: &mut Cursor<&[u8]>) -> Result<usize> {
-        // go to len position in the raw bytes
-        cursor.seek(SeekFrom::Current(8)).unwrap();
-        // read value and handle errors
-        let len = match cursor.read_u16::<BigEndian>() {
-            Ok(len) => len,
-            Err(err) => {
-                error!(
-                    "Could not read packet queue len.{}{:?}{}{}",
-                    path::MAIN_SEPARATOR,
-                    cursor,
-                    path::MAIN_SEPARATOR,
-                    err
-                );
-                bail!(ErrorKind::CouldNotReadLength(8, stringify!(PacketQueue),))
-            }
-        };
-        // go back to start
-        cursor.seek(SeekFrom::Current(-10)).unwrap();
-        Ok(len as usize)
+        let pos = cursor.position() as usize;
+        PACKET_QUEUE_LEN_FIELD.read(cursor.get_ref(), pos, stringify!(PacketQueue))
     }
 }
 
+/// the length follows queue id and port and includes the properties
+pub const PACKET_QUEUE_LEN_FIELD: LengthField =
+    LengthField::u16_at(8, PACKET_QUEUE_LENGTH);
+
 impl<'a> TryFrom<&'a [u8]> for PacketQueue {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
-        let mut properties = Vec::new();
         let mut cursor = Cursor::new(bytes);
         // first get "header" data and verify bytes.len()
-        let queue_id = cursor.read_u32::<BigEndian>().chain_err(|| {
-            let err_msg = format!(
-                "Could not read PacketQueue queue_id!{}Cursor: {:?}",
-                path::MAIN_SEPARATOR,
-                cursor
-            );
-            error!("{}", err_msg);
-            err_msg
-        })?;
+        let queue_id = cursor.read_u32::<BigEndian>().at(cursor.position())?;
 
         // read raw val
-        let port = cursor.read_u32::<BigEndian>().chain_err(|| {
-            let err_msg = format!(
-                "Could not read PacketQueue port!{}Cursor: {:?}",
-                path::MAIN_SEPARATOR,
-                cursor
-            );
-            error!("{}", err_msg);
-            err_msg
-        })?;
+        let port = cursor.read_u32::<BigEndian>().at(cursor.position())?;
         // try to decode it
         // can a packetqueue port be a reserved keyword?
         let port = PortNumber::try_from(port)?;
 
-        let len = cursor.read_u16::<BigEndian>().chain_err(|| {
-            let err_msg = format!(
-                "Could not read PacketQueue len!{}Cursor: {:?}",
-                path::MAIN_SEPARATOR,
-                cursor
-            );
-            error!("{}", err_msg);
-            err_msg
-        })?;
+        let len = cursor.read_u16::<BigEndian>().at(cursor.position())?;
 
         //put cursor to correct position
         cursor
@@ -99,49 +113,12 @@ impl<'a> TryFrom<&'a [u8]> for PacketQueue {
             ));
         }
 
-        //read properties
-        while cursor.position() < bytes.len() as u64 {
-            //read header first
-            let queue_prop_header = QueuePropHeader::try_from(
-                &bytes[cursor.position() as usize
-                           ..cursor.position() as usize + QUEUE_PROP_HEADER_LENGTH],
-            )?;
-
-            //put cursor to correct position
-            cursor
-                .seek(SeekFrom::Current(QUEUE_PROP_HEADER_LENGTH as i64))
-                .unwrap();
-
-            //then read payload
-            let prop_slice = &bytes[cursor.position() as usize
-                                        ..cursor.position() as usize
-                                            + queue_prop_header.len as usize];
-            let queue_prop_payload = match queue_prop_header.property {
-                QueueProperties::MinRate => {
-                    QueuePropPayload::Min(QueuePropMinRate::try_from(prop_slice)?)
-                }
-                QueueProperties::MaxRate => {
-                    QueuePropPayload::Max(QueuePropMaxRate::try_from(prop_slice)?)
-                }
-                QueueProperties::Experimenter => {
-                    QueuePropPayload::Experimenter(QueuePropExperimenter::try_from(prop_slice)?)
-                }
-            };
-
-            //put cursor to correct position
-            cursor
-                .seek(SeekFrom::Current(queue_prop_header.len as i64))
-                .unwrap();
-
-            //construct message
-            let property = QueuePropMessage {
-                header: queue_prop_header,
-                payload: queue_prop_payload,
-            };
-
-            //add to vec
-            properties.push(property);
-        }
+        let properties = decode_list(
+            bytes,
+            PACKET_QUEUE_LENGTH,
+            QUEUE_PROP_LEN_FIELD,
+            stringify!(QueuePropMessage),
+        )?;
 
         Ok(PacketQueue {
             queue_id: queue_id,
@@ -152,18 +129,27 @@ impl<'a> TryFrom<&'a [u8]> for PacketQueue {
     }
 }
 
+impl OfEncode for PacketQueue {
+    fn encoded_len(&self) -> usize {
+        PACKET_QUEUE_LENGTH + self.properties.encoded_len()
+    }
+
+    /// the length is computed from the properties, the stored one is ignored
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.write_u32::<BigEndian>(self.queue_id).unwrap();
+        buf.write_u32::<BigEndian>(self.port.clone().into())
+            .unwrap();
+        buf.write_u16::<BigEndian>(self.encoded_len() as u16)
+            .unwrap();
+        buf.write_u16::<BigEndian>(0).unwrap(); //pad 2 bytes
+        buf.write_u32::<BigEndian>(0).unwrap(); //pad 4 bytes
+        self.properties.encode_into(buf);
+    }
+}
+
 impl Into<Vec<u8>> for PacketQueue {
     fn into(self) -> Vec<u8> {
-        let mut res = Vec::new();
-        res.write_u32::<BigEndian>(self.queue_id).unwrap();
-        res.write_u32::<BigEndian>(self.port.into()).unwrap();
-        res.write_u16::<BigEndian>(self.len).unwrap();
-        res.write_u16::<BigEndian>(0).unwrap(); //pad 2 bytes
-        res.write_u32::<BigEndian>(0).unwrap(); //pad 4 bytes
-        for prop in self.properties {
-            res.extend_from_slice(&Into::<Vec<u8>>::into(prop)[..]);
-        }
-        res
+        self.to_bytes()
     }
 }
 
@@ -192,30 +178,14 @@ impl<'a> TryFrom<&'a [u8]> for QueuePropHeader {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let property_raw = cursor.read_u16::<BigEndian>().chain_err(|| {
-            let err_msg = format!(
-                "Could not read QueuePropHeader property_raw!{}Cursor: {:?}",
-                path::MAIN_SEPARATOR,
-                cursor
-            );
-            error!("{}", err_msg);
-            err_msg
-        })?;
+        let property_raw = cursor.read_u16::<BigEndian>().at(cursor.position())?;
         let property = QueueProperties::from_u16(property_raw).ok_or::<Error>(
             ErrorKind::UnknownValue(property_raw as u64, stringify!(QueueProperties)).into(),
         )?;
 
         Ok(QueuePropHeader {
             property: property,
-            len: cursor.read_u16::<BigEndian>().chain_err(|| {
-                let err_msg = format!(
-                    "Could not read QueuePropHeader len!{}Cursor: {:?}",
-                    path::MAIN_SEPARATOR,
-                    cursor
-                );
-                error!("{}", err_msg);
-                err_msg
-            })?,
+            len: cursor.read_u16::<BigEndian>().at(cursor.position())?,
         })
         //padding 4 bytes by ignoring them
     }
@@ -238,12 +208,78 @@ pub struct QueuePropMessage {
     payload: QueuePropPayload,
 }
 
+impl QueuePropMessage {
+    pub fn new(payload: QueuePropPayload) -> Self {
+        let (property, len) = match payload {
+            QueuePropPayload::Min(_) => (QueueProperties::MinRate, 8),
+            QueuePropPayload::Max(_) => (QueueProperties::MaxRate, 8),
+            QueuePropPayload::Experimenter(ref experimenter) => {
+                (QueueProperties::Experimenter, 8 + experimenter.data.len())
+            }
+        };
+        QueuePropMessage {
+            header: QueuePropHeader {
+                property: property,
+                len: (QUEUE_PROP_HEADER_LENGTH + len) as u16,
+            },
+            payload: payload,
+        }
+    }
+
+    pub fn payload(&self) -> &QueuePropPayload {
+        &self.payload
+    }
+}
+
+/// the length includes the header, anything shorter is corrupt
+pub const QUEUE_PROP_LEN_FIELD: LengthField =
+    LengthField::u16_at(2, QUEUE_PROP_HEADER_LENGTH);
+
+impl<'a> TryFrom<&'a [u8]> for QueuePropMessage {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let header_slice =
+            checked_slice(bytes, 0, QUEUE_PROP_HEADER_LENGTH, stringify!(QueuePropHeader))?;
+        let header = QueuePropHeader::try_from(header_slice)?;
+        let prop_slice =
+            checked_tail(bytes, QUEUE_PROP_HEADER_LENGTH, stringify!(QueuePropMessage))?;
+        let payload = match header.property {
+            QueueProperties::MinRate => {
+                QueuePropPayload::Min(QueuePropMinRate::try_from(prop_slice)?)
+            }
+            QueueProperties::MaxRate => {
+                QueuePropPayload::Max(QueuePropMaxRate::try_from(prop_slice)?)
+            }
+            QueueProperties::Experimenter => {
+                QueuePropPayload::Experimenter(QueuePropExperimenter::try_from(prop_slice)?)
+            }
+        };
+        Ok(QueuePropMessage {
+            header: header,
+            payload: payload,
+        })
+    }
+}
+
+impl OfEncode for QueuePropMessage {
+    fn encoded_len(&self) -> usize {
+        QUEUE_PROP_HEADER_LENGTH + self.payload.encoded_len()
+    }
+
+    /// the length in the header is computed from the payload
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        let header = QueuePropHeader {
+            property: self.header.property.clone(),
+            len: self.encoded_len() as u16,
+        };
+        buf.extend_from_slice(&Into::<Vec<u8>>::into(header));
+        self.payload.encode_into(buf);
+    }
+}
+
 impl Into<Vec<u8>> for QueuePropMessage {
     fn into(self) -> Vec<u8> {
-        let mut res = Vec::new();
-        res.extend_from_slice(&Into::<Vec<u8>>::into(self.header));
-        res.extend_from_slice(&Into::<Vec<u8>>::into(self.payload));
-        res
+        self.to_bytes()
     }
 }
 
@@ -265,6 +301,47 @@ impl Into<Vec<u8>> for QueuePropPayload {
     }
 }
 
+/// Rate of a queue in 1/10 of a percent of the port speed (0 - 1000).
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct QueueRate(u16);
+
+/// Largest rate, 100 percent.
+pub const QUEUE_RATE_MAX: u16 = 1000;
+/// Rate sent for a disabled rate property (OFPQ_MIN_RATE_UNCFG / OFPQ_MAX_RATE_UNCFG).
+pub const QUEUE_RATE_UNCFG: u16 = 0xffff;
+
+impl QueueRate {
+    /// None for rates above 100 percent, they mean the property is disabled
+    pub fn from_tenths(tenths: u16) -> Option<Self> {
+        if tenths > QUEUE_RATE_MAX {
+            None
+        } else {
+            Some(QueueRate(tenths))
+        }
+    }
+
+    /// None for rates above 100 percent
+    pub fn from_percent(percent: f64) -> Option<Self> {
+        if percent < 0.0 {
+            return None;
+        }
+        let tenths = (percent * 10.0).round();
+        if tenths > QUEUE_RATE_MAX as f64 {
+            None
+        } else {
+            Some(QueueRate(tenths as u16))
+        }
+    }
+
+    pub fn tenths(&self) -> u16 {
+        self.0
+    }
+
+    pub fn percent(&self) -> f64 {
+        self.0 as f64 / 10.0
+    }
+}
+
 /// Min-Rate queue property description.
 #[derive(Debug, PartialEq, Clone)]
 pub struct QueuePropMinRate {
@@ -273,20 +350,26 @@ pub struct QueuePropMinRate {
     //pad 6 bytes
 }
 
+impl QueuePropMinRate {
+    /// None disables the property
+    pub fn new(rate: Option<QueueRate>) -> Self {
+        QueuePropMinRate {
+            rate: rate.map(|rate| rate.tenths()).unwrap_or(QUEUE_RATE_UNCFG),
+        }
+    }
+
+    /// guaranteed rate, None if disabled
+    pub fn rate(&self) -> Option<QueueRate> {
+        QueueRate::from_tenths(self.rate)
+    }
+}
+
 impl<'a> TryFrom<&'a [u8]> for QueuePropMinRate {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(QueuePropMinRate {
-            rate: cursor.read_u16::<BigEndian>().chain_err(|| {
-                let err_msg = format!(
-                    "Could not read QueuePropMinRate rate!{}Cursor: {:?}",
-                    path::MAIN_SEPARATOR,
-                    cursor
-                );
-                error!("{}", err_msg);
-                err_msg
-            })?,
+            rate: cursor.read_u16::<BigEndian>().at(cursor.position())?,
         })
         //pad 6 bytes by ignoring them
     }
@@ -310,20 +393,26 @@ pub struct QueuePropMaxRate {
     //pad 6 bytes
 }
 
+impl QueuePropMaxRate {
+    /// None disables the property
+    pub fn new(rate: Option<QueueRate>) -> Self {
+        QueuePropMaxRate {
+            rate: rate.map(|rate| rate.tenths()).unwrap_or(QUEUE_RATE_UNCFG),
+        }
+    }
+
+    /// maximum rate, None if disabled
+    pub fn rate(&self) -> Option<QueueRate> {
+        QueueRate::from_tenths(self.rate)
+    }
+}
+
 impl<'a> TryFrom<&'a [u8]> for QueuePropMaxRate {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(QueuePropMaxRate {
-            rate: cursor.read_u16::<BigEndian>().chain_err(|| {
-                let err_msg = format!(
-                    "Could not read QueuePropMaxRate rate!{}Cursor: {:?}",
-                    path::MAIN_SEPARATOR,
-                    cursor
-                );
-                error!("{}", err_msg);
-                err_msg
-            })?,
+            rate: cursor.read_u16::<BigEndian>().at(cursor.position())?,
         })
         //pad 6 bytes by ignoring them
     }
@@ -347,21 +436,23 @@ pub struct QueuePropExperimenter {
     data: Vec<u8>,
 }
 
+impl QueuePropExperimenter {
+    pub fn experimenter(&self) -> u32 {
+        self.experimenter
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
 impl<'a> TryFrom<&'a [u8]> for QueuePropExperimenter {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let experimenter = cursor.read_u32::<BigEndian>().chain_err(|| {
-            let err_msg = format!(
-                "Could not read QueuePropExperimenter experimenter!{}Cursor: {:?}",
-                path::MAIN_SEPARATOR,
-                cursor
-            );
-            error!("{}", err_msg);
-            err_msg
-        })?;
+        let experimenter = cursor.read_u32::<BigEndian>().at(cursor.position())?;
         //pad 4 bytes by ignoring them
-        let data = Vec::from(&bytes[8..]);
+        let data = Vec::from(checked_tail(bytes, 8, stringify!(QueuePropExperimenter))?);
         Ok(QueuePropExperimenter {
             experimenter: experimenter,
             data: data,
@@ -410,6 +501,28 @@ mod tests {
         assert_eq!(testee, from);
     }
 
+    #[test]
+    fn typed_rates() {
+        assert_eq!(QueueRate::from_tenths(1001), None);
+        assert_eq!(QueueRate::from_percent(12.5).unwrap().tenths(), 125);
+        assert_eq!(QueuePropMinRate::new(None).rate(), None);
+
+        let testee = PacketQueue::new(
+            1,
+            PortNumber::NormalPort(2),
+            vec![
+                QueuePropPayload::Min(QueuePropMinRate::new(QueueRate::from_tenths(100))),
+                QueuePropPayload::Max(QueuePropMaxRate::new(None)),
+            ],
+        );
+        let bytes = Into::<Vec<u8>>::into(testee.clone());
+        assert_eq!(bytes.len(), PACKET_QUEUE_LENGTH + 2 * 16);
+        let from = PacketQueue::try_from(&bytes[..]).unwrap();
+        assert_eq!(from, testee);
+        assert_eq!(from.min_rate().unwrap().percent(), 10.0);
+        assert_eq!(from.max_rate(), None);
+    }
+
     #[test]
     fn into_length_qpe() {
         let testee = QueuePropExperimenter {
@@ -1,71 +1,118 @@
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use std::convert::{Into, TryFrom};
-use std::io::{Cursor, Seek, SeekFrom};
+use super::addr::MacAddr;
+use super::ports::{Port, PortConfig, PortFeatures, PortNumber};
 
-use super::hw_addr::{from_slice_eth, EthernetAddress};
-use super::ports::{PortConfig, PortFeatures, PortNumber};
-
-use super::super::err::*;
-
-#[derive(Debug, PartialEq, Clone)]
+#[derive(OfWire, Getters, Debug, PartialEq, Clone)]
 pub struct PortMod {
+    #[get = "pub"]
+    #[pad(4)]
     port_no: PortNumber,
-    // pad 4 bytes
-    hw_addr: EthernetAddress,
-    //pad 2 bytes,
+    /// has to be the hardware address of the port (see Port::hw_addr)
+    #[get = "pub"]
+    #[pad(2)]
+    hw_addr: MacAddr,
+    #[get = "pub"]
     config: PortConfig,
+    /// flags of config that are changed
+    #[get = "pub"]
     mask: PortConfig,
+    /// features to advertise, empty to leave them unchanged
+    #[get = "pub"]
+    #[pad(4)]
     advertise: PortFeatures,
-    //pad 4 bytes
 }
 
-impl<'a> TryFrom<&'a [u8]> for PortMod {
-    type Error = Error;
-    fn try_from(bytes: &'a [u8]) -> Result<Self> {
-        let mut cursor = Cursor::new(bytes);
-        let port_no = PortNumber::try_from(cursor.read_u32::<BigEndian>().unwrap())?;
-        let hw_addr = &bytes[8..14];
-        cursor.seek(SeekFrom::Start(16)).unwrap();
-
-        // read raw version val
-        let config = cursor.read_u32::<BigEndian>().unwrap();
-        // try to decode it
-        let config = PortConfig::from_bits(config)
-            .ok_or::<Error>(ErrorKind::UnknownValue(config as u64, stringify!(PortConfig)).into())?;
-        // read raw version val
-        let mask = cursor.read_u32::<BigEndian>().unwrap();
-        // try to decode it
-        let mask = PortConfig::from_bits(mask)
-            .ok_or::<Error>(ErrorKind::UnknownValue(mask as u64, stringify!(PortConfig)).into())?;
-
-        // read raw version val
-        let advertise = cursor.read_u32::<BigEndian>().unwrap();
-        // try to decode it
-        let advertise = PortFeatures::from_bits(advertise).ok_or::<Error>(
-            ErrorKind::UnknownValue(advertise as u64, stringify!(PortFeatures)).into(),
-        )?;
-
-        Ok(PortMod {
+impl PortMod {
+    pub fn new(
+        port_no: PortNumber,
+        hw_addr: MacAddr,
+        config: PortConfig,
+        mask: PortConfig,
+        advertise: PortFeatures,
+    ) -> Self {
+        PortMod {
             port_no: port_no,
-            hw_addr: from_slice_eth(hw_addr)?,
+            hw_addr: hw_addr,
             config: config,
             mask: mask,
             advertise: advertise,
-        })
+        }
+    }
+
+    /// sets the flags of mask to their value in config, other flags and the
+    /// advertised features of the port stay unchanged
+    pub fn for_port(port: &Port, config: PortConfig, mask: PortConfig) -> Self {
+        PortMod::new(
+            port.port_no().clone(),
+            *port.hw_addr(),
+            config & mask,
+            mask,
+            PortFeatures::empty(),
+        )
+    }
+
+    /// brings the port administratively up or down
+    pub fn admin_state(port: &Port, state: PortAdminState) -> Self {
+        PortMod::for_port(port, state.config(), PortConfig::PORT_DOWN)
+    }
+
+    /// sets or clears one of the NO_* flags, enabled clears the flag
+    /// eg. `PortMod::enable(port, PortConfig::NO_PACKET_IN, false)` stops packet ins
+    pub fn enable(port: &Port, no_flag: PortConfig, enabled: bool) -> Self {
+        let config = if enabled {
+            PortConfig::empty()
+        } else {
+            no_flag
+        };
+        PortMod::for_port(port, config, no_flag)
+    }
+}
+
+/// Administrative state of a port (OFPPC_PORT_DOWN).
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PortAdminState {
+    Up,
+    Down,
+}
+
+impl PortAdminState {
+    /// config of a port mod with PORT_DOWN as mask
+    pub fn config(&self) -> PortConfig {
+        match *self {
+            PortAdminState::Up => PortConfig::empty(),
+            PortAdminState::Down => PortConfig::PORT_DOWN,
+        }
     }
 }
 
-impl Into<Vec<u8>> for PortMod {
-    fn into(self) -> Vec<u8> {
-        let mut res = Vec::new();
-        res.write_u32::<BigEndian>(self.port_no.into()).unwrap();
-        res.write_u32::<BigEndian>(0).unwrap(); // pad 4 bytes
-        res.extend_from_slice(&self.hw_addr[..]);
-        res.write_u16::<BigEndian>(0).unwrap(); // pad 2 bytes
-        res.write_u32::<BigEndian>(self.config.bits()).unwrap();
-        res.write_u32::<BigEndian>(self.mask.bits()).unwrap();
-        res.write_u32::<BigEndian>(self.advertise.bits()).unwrap();
-        res.write_u32::<BigEndian>(0).unwrap(); // pad 4 bytes
-        res
+#[cfg(test)]
+mod tests {
+    use super::super::ports::PortName;
+    use super::*;
+
+    #[test]
+    fn helpers_only_touch_masked_flags() {
+        let port = Port::new(
+            PortNumber::NormalPort(3),
+            MacAddr([2, 0, 0, 0, 0, 3]),
+            PortName::new("eth3").unwrap(),
+        )
+        .with_config(PortConfig::NO_RECV);
+
+        let down = PortMod::admin_state(&port, PortAdminState::Down);
+        assert_eq!(*down.hw_addr(), MacAddr([2, 0, 0, 0, 0, 3]));
+        assert_eq!(*down.config(), PortConfig::PORT_DOWN);
+        assert_eq!(*down.mask(), PortConfig::PORT_DOWN);
+        assert_eq!(*down.advertise(), PortFeatures::empty());
+
+        let up = PortMod::admin_state(&port, PortAdminState::Up);
+        assert_eq!(*up.config(), PortConfig::empty());
+        assert_eq!(*up.mask(), PortConfig::PORT_DOWN);
+
+        let no_packet_in = PortMod::enable(&port, PortConfig::NO_PACKET_IN, false);
+        assert_eq!(*no_packet_in.config(), PortConfig::NO_PACKET_IN);
+        assert_eq!(*no_packet_in.mask(), PortConfig::NO_PACKET_IN);
+        let recv = PortMod::enable(&port, PortConfig::NO_RECV, true);
+        assert_eq!(*recv.config(), PortConfig::empty());
+        assert_eq!(*recv.mask(), PortConfig::NO_RECV);
     }
 }
@@ -0,0 +1,188 @@
+//! Port statistics (multipart type PortStats).
+//!
+//! Only the fixed layout of OpenFlow 1.3 is supported, OpenFlow 1.4 moved the
+//! ethernet specific counters into properties.
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::convert::{Into, TryFrom};
+use std::io::{Cursor, Seek, SeekFrom};
+use std::time::Duration;
+
+use super::ports::{PortNo, PortNumber};
+
+use super::super::err::*;
+
+/// Length of a port stats request (8 bytes).
+pub const PORT_STATS_REQUEST_LENGTH: usize = 8;
+/// Length of a port stats entry (112 bytes).
+pub const PORT_STATS_LENGTH: usize = 112;
+
+/// Requests the statistics of a port or of all ports.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PortStatsRequest {
+    /// the port to read, PortNo::Any for all
+    pub port_no: PortNumber,
+    // pad 4 bytes
+}
+
+impl PortStatsRequest {
+    /// requests the statistics of all ports
+    pub fn all() -> Self {
+        PortStatsRequest {
+            port_no: PortNumber::Reserved(PortNo::Any),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for PortStatsRequest {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(bytes);
+        let port_no = PortNumber::try_from(cursor.read_u32::<BigEndian>()?)?;
+        Ok(PortStatsRequest { port_no: port_no })
+    }
+}
+
+impl Into<Vec<u8>> for PortStatsRequest {
+    fn into(self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.write_u32::<BigEndian>(self.port_no.into()).unwrap();
+        res.write_u32::<BigEndian>(0).unwrap(); // pad 4 bytes
+        res
+    }
+}
+
+/// Counters of a single port (OpenFlow 1.3).
+/// Counters the switch does not support are all ones.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PortStats {
+    pub port_no: PortNumber,
+    // pad 4 bytes
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_dropped: u64,
+    pub tx_dropped: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    pub rx_frame_err: u64,
+    pub rx_over_err: u64,
+    pub rx_crc_err: u64,
+    pub collisions: u64,
+    pub duration_sec: u32,
+    pub duration_nsec: u32,
+}
+
+impl PortStats {
+    /// time the port has been alive
+    pub fn duration(&self) -> Duration {
+        Duration::new(self.duration_sec as u64, self.duration_nsec)
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for PortStats {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        if bytes.len() != PORT_STATS_LENGTH {
+            bail!(ErrorKind::InvalidSliceLength(
+                PORT_STATS_LENGTH,
+                bytes.len(),
+                stringify!(PortStats),
+            ));
+        }
+        let mut cursor = Cursor::new(bytes);
+        let port_no = PortNumber::try_from(cursor.read_u32::<BigEndian>()?)?;
+        cursor.seek(SeekFrom::Current(4)).unwrap(); // pad 4 bytes
+        Ok(PortStats {
+            port_no: port_no,
+            rx_packets: cursor.read_u64::<BigEndian>()?,
+            tx_packets: cursor.read_u64::<BigEndian>()?,
+            rx_bytes: cursor.read_u64::<BigEndian>()?,
+            tx_bytes: cursor.read_u64::<BigEndian>()?,
+            rx_dropped: cursor.read_u64::<BigEndian>()?,
+            tx_dropped: cursor.read_u64::<BigEndian>()?,
+            rx_errors: cursor.read_u64::<BigEndian>()?,
+            tx_errors: cursor.read_u64::<BigEndian>()?,
+            rx_frame_err: cursor.read_u64::<BigEndian>()?,
+            rx_over_err: cursor.read_u64::<BigEndian>()?,
+            rx_crc_err: cursor.read_u64::<BigEndian>()?,
+            collisions: cursor.read_u64::<BigEndian>()?,
+            duration_sec: cursor.read_u32::<BigEndian>()?,
+            duration_nsec: cursor.read_u32::<BigEndian>()?,
+        })
+    }
+}
+
+impl Into<Vec<u8>> for PortStats {
+    fn into(self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(PORT_STATS_LENGTH);
+        res.write_u32::<BigEndian>(self.port_no.into()).unwrap();
+        res.write_u32::<BigEndian>(0).unwrap(); // pad 4 bytes
+        for counter in &[
+            self.rx_packets,
+            self.tx_packets,
+            self.rx_bytes,
+            self.tx_bytes,
+            self.rx_dropped,
+            self.tx_dropped,
+            self.rx_errors,
+            self.tx_errors,
+            self.rx_frame_err,
+            self.rx_over_err,
+            self.rx_crc_err,
+            self.collisions,
+        ] {
+            res.write_u64::<BigEndian>(*counter).unwrap();
+        }
+        res.write_u32::<BigEndian>(self.duration_sec).unwrap();
+        res.write_u32::<BigEndian>(self.duration_nsec).unwrap();
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::multipart::{MultipartReply, RepPayload, MULTIPART_HEADER_LENGTH};
+    use super::super::Version;
+    use super::*;
+
+    #[test]
+    fn request_all_ports() {
+        let bytes: Vec<u8> = PortStatsRequest::all().into();
+        assert_eq!(bytes, vec![0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+        assert_eq!(
+            PortStatsRequest::try_from(&bytes[..]).unwrap(),
+            PortStatsRequest::all()
+        );
+    }
+
+    #[test]
+    fn port_stats_reply_round_trip() {
+        let stats = PortStats {
+            port_no: PortNumber::NormalPort(1),
+            rx_packets: 10,
+            tx_packets: 20,
+            rx_bytes: 1000,
+            tx_bytes: 2000,
+            rx_dropped: 0,
+            tx_dropped: 0,
+            rx_errors: 0,
+            tx_errors: 0,
+            rx_frame_err: 0,
+            rx_over_err: 0,
+            rx_crc_err: 0,
+            collisions: u64::MAX,
+            duration_sec: 5,
+            duration_nsec: 7,
+        };
+        assert_eq!(stats.duration(), Duration::new(5, 7));
+        let reply = MultipartReply::new(RepPayload::PortStats(vec![stats.clone(), stats]));
+        let bytes: Vec<u8> = reply.clone().into();
+        assert_eq!(bytes.len(), MULTIPART_HEADER_LENGTH + 2 * PORT_STATS_LENGTH);
+        assert_eq!(MultipartReply::try_from(&bytes[..]).unwrap(), reply);
+        // the layout changed in 1.4
+        assert!(MultipartReply::decode_for(Version::V1_4, &bytes[..]).is_err());
+        assert!(MultipartReply::try_from(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
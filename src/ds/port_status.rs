@@ -4,24 +4,36 @@ use std::convert::{Into, TryFrom};
 use std::io::Cursor;
 
 use super::super::err::*;
+use super::checked_tail;
 use super::ports::Port;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Getters, Debug, PartialEq, Clone)]
 pub struct PortStatus {
+    #[get = "pub"]
     reason: PortReason,
     //pad 7 bytes
+    #[get = "pub"]
     desc: Port,
 }
 
+impl PortStatus {
+    pub fn new(reason: PortReason, desc: Port) -> Self {
+        PortStatus {
+            reason: reason,
+            desc: desc,
+        }
+    }
+}
+
 impl<'a> TryFrom<&'a [u8]> for PortStatus {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let reason_raw = cursor.read_u8().unwrap();
+        let reason_raw = cursor.read_u8()?;
         let reason = PortReason::from_u8(reason_raw).ok_or::<Error>(
             ErrorKind::UnknownValue(reason_raw as u64, stringify!(PortReason)).into(),
         )?;
-        let desc = Port::try_from(&bytes[8..])?;
+        let desc = Port::try_from(checked_tail(bytes, 8, stringify!(PortStatus))?).within(8)?;
 
         Ok(PortStatus {
             reason: reason,
@@ -2,16 +2,19 @@
 #![allow(overflowing_literals)]
 
 use super::super::err::*;
-use super::hw_addr;
+use super::addr::MacAddr;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use num_traits::{FromPrimitive, ToPrimitive};
 use std::convert::{Into, TryFrom};
-use std::ffi::CString;
+use std::borrow::Cow;
+use std::fmt;
 use std::io::{Cursor, Seek, SeekFrom, Write};
-use std::path;
+use std::str::FromStr;
 
 /// OpenFlow port struct length is 64 bytes.
 pub const PORT_LENGTH: usize = 64;
+/// Length of the port name including the terminating null byte.
+pub const PORT_NAME_LEN: usize = 16;
 
 /// OpenFlow port struct.
 #[derive(Getters, Debug, PartialEq, Clone)]
@@ -20,11 +23,11 @@ pub struct Port {
     port_no: PortNumber,
     //pad 4 bytes
     #[get = "pub"]
-    hw_addr: hw_addr::EthernetAddress,
+    hw_addr: MacAddr,
     //pad 2 bytes,
     /// Null terminated 16 byte (including null) port name
     #[get = "pub"]
-    name: CString,
+    name: PortName,
 
     /// Bitmap of PortConfig flags.
     #[get = "pub"]
@@ -54,6 +57,57 @@ pub struct Port {
     max_speed: u32,
 }
 
+impl Port {
+    /// creates a port without config, state, features and speeds
+    pub fn new(port_no: PortNumber, hw_addr: MacAddr, name: PortName) -> Self {
+        Port {
+            port_no: port_no,
+            hw_addr: hw_addr,
+            name: name,
+            config: PortConfig::empty(),
+            state: PortState::empty(),
+            curr: PortFeatures::empty(),
+            advertised: PortFeatures::empty(),
+            supported: PortFeatures::empty(),
+            peer: PortFeatures::empty(),
+            curr_speed: 0,
+            max_speed: 0,
+        }
+    }
+
+    pub fn with_config(mut self, config: PortConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn with_state(mut self, state: PortState) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// current, advertised, supported and peer features
+    pub fn with_features(
+        mut self,
+        curr: PortFeatures,
+        advertised: PortFeatures,
+        supported: PortFeatures,
+        peer: PortFeatures,
+    ) -> Self {
+        self.curr = curr;
+        self.advertised = advertised;
+        self.supported = supported;
+        self.peer = peer;
+        self
+    }
+
+    /// current and maximum bitrate in kbps
+    pub fn with_speed(mut self, curr_speed: u32, max_speed: u32) -> Self {
+        self.curr_speed = curr_speed;
+        self.max_speed = max_speed;
+        self
+    }
+}
+
 impl<'a> TryFrom<&'a [u8]> for Port {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
@@ -68,97 +122,39 @@ impl<'a> TryFrom<&'a [u8]> for Port {
         let mut cursor = Cursor::new(bytes);
 
         // read raw version val
-        let port_no = cursor.read_u32::<BigEndian>().unwrap();
+        let port_no = cursor.read_u32::<BigEndian>()?;
         // try to decode it
         let port_no = PortNumber::try_from(port_no)?;
 
         //works because big endian format
         let hw_addr_slice = &bytes[8..14];
 
-        //works because big endian format
-        let name_slice = &bytes[16..32];
-        let name = unsafe { CString::from_vec_unchecked(Vec::from(name_slice)) };
+        let name = PortName::from_field(&bytes[16..32]);
 
         //put cursor to correct position after string (32 bytes)
         cursor.seek(SeekFrom::Start(32)).unwrap();
 
-        let config = cursor.read_u32::<BigEndian>().chain_err(|| {
-            let err_msg = format!(
-                "Could not read port config!{}Cursor: {:?}",
-                path::MAIN_SEPARATOR,
-                cursor
-            );
-            error!("{}", err_msg);
-            err_msg
-        })?;
-        let config = PortConfig::from_bits(config)
-            .ok_or::<Error>(ErrorKind::UnknownValue(config as u64, stringify!(PortConfig)).into())?;
-
-        let state = cursor.read_u32::<BigEndian>().chain_err(|| {
-            let err_msg = format!(
-                "Could not read port state!{}Cursor: {:?}",
-                path::MAIN_SEPARATOR,
-                cursor
-            );
-            error!("{}", err_msg);
-            err_msg
-        })?;
-        let state = PortState::from_bits(state)
-            .ok_or::<Error>(ErrorKind::UnknownValue(state as u64, stringify!(PortState)).into())?;
-
-        let curr = cursor.read_u32::<BigEndian>().chain_err(|| {
-            let err_msg = format!(
-                "Could not read port curr!{}Cursor: {:?}",
-                path::MAIN_SEPARATOR,
-                cursor
-            );
-            error!("{}", err_msg);
-            err_msg
-        })?;
-        let curr = PortFeatures::from_bits(curr)
-            .ok_or::<Error>(ErrorKind::UnknownValue(curr as u64, stringify!(PortFeatures)).into())?;
-
-        let advertised = cursor.read_u32::<BigEndian>().chain_err(|| {
-            let err_msg = format!(
-                "Could not read port advertised!{}Cursor: {:?}",
-                path::MAIN_SEPARATOR,
-                cursor
-            );
-            error!("{}", err_msg);
-            err_msg
-        })?;
-        let advertised = PortFeatures::from_bits(advertised).ok_or::<Error>(
-            ErrorKind::UnknownValue(advertised as u64, stringify!(PortFeatures)).into(),
-        )?;
-
-        let supported = cursor.read_u32::<BigEndian>().chain_err(|| {
-            let err_msg = format!(
-                "Could not read port supported!{}Cursor: {:?}",
-                path::MAIN_SEPARATOR,
-                cursor
-            );
-            error!("{}", err_msg);
-            err_msg
-        })?;
-        let supported = PortFeatures::from_bits(supported).ok_or::<Error>(
-            ErrorKind::UnknownValue(supported as u64, stringify!(PortFeatures)).into(),
-        )?;
-
-        let peer = cursor.read_u32::<BigEndian>().chain_err(|| {
-            let err_msg = format!(
-                "Could not read port peer!{}Cursor: {:?}",
-                path::MAIN_SEPARATOR,
-                cursor
-            );
-            error!("{}", err_msg);
-            err_msg
-        })?;
-        let peer = PortFeatures::from_bits(peer)
-            .ok_or::<Error>(ErrorKind::UnknownValue(peer as u64, stringify!(PortFeatures)).into())?;
+        let config = cursor.read_u32::<BigEndian>().at(cursor.position())?;
+        let config = flags_from_bits!(PortConfig, config)?;
+
+        let state = cursor.read_u32::<BigEndian>().at(cursor.position())?;
+        let state = flags_from_bits!(PortState, state)?;
+
+        let curr = cursor.read_u32::<BigEndian>().at(cursor.position())?;
+        let curr = flags_from_bits!(PortFeatures, curr)?;
+
+        let advertised = cursor.read_u32::<BigEndian>().at(cursor.position())?;
+        let advertised = flags_from_bits!(PortFeatures, advertised)?;
+
+        let supported = cursor.read_u32::<BigEndian>().at(cursor.position())?;
+        let supported = flags_from_bits!(PortFeatures, supported)?;
+
+        let peer = cursor.read_u32::<BigEndian>().at(cursor.position())?;
+        let peer = flags_from_bits!(PortFeatures, peer)?;
 
         Ok(Port {
             port_no: port_no,
-            hw_addr: hw_addr::from_slice_eth(hw_addr_slice)?,
+            hw_addr: MacAddr::from_slice(hw_addr_slice)?,
             name: name,
             config: config,
             state: state,
@@ -166,24 +162,8 @@ impl<'a> TryFrom<&'a [u8]> for Port {
             advertised: advertised,
             supported: supported,
             peer: peer,
-            curr_speed: cursor.read_u32::<BigEndian>().chain_err(|| {
-                let err_msg = format!(
-                    "Could not read port curr_speed!{}Cursor: {:?}",
-                    path::MAIN_SEPARATOR,
-                    cursor
-                );
-                error!("{}", err_msg);
-                err_msg
-            })?,
-            max_speed: cursor.read_u32::<BigEndian>().chain_err(|| {
-                let err_msg = format!(
-                    "Could not read port max_speed!{}Cursor: {:?}",
-                    path::MAIN_SEPARATOR,
-                    cursor
-                );
-                error!("{}", err_msg);
-                err_msg
-            })?,
+            curr_speed: cursor.read_u32::<BigEndian>().at(cursor.position())?,
+            max_speed: cursor.read_u32::<BigEndian>().at(cursor.position())?,
         })
     }
 }
@@ -194,17 +174,10 @@ impl Into<Vec<u8>> for Port {
         res.write_u32::<BigEndian>(self.port_no.into()).unwrap();
         // pad 4 bytes
         res.write_u32::<BigEndian>(0).unwrap();
-        res.extend_from_slice(&self.hw_addr[..]);
+        res.extend_from_slice(&self.hw_addr.octets()[..]);
         // pad 2 bytes
         res.write_u16::<BigEndian>(0).unwrap();
-        // dont check validity of CString (length) here
-        // instead do so in the creation method (if one exists)
-        let mut bytes_written = res.write(&self.name.into_bytes()[..]).unwrap();
-        //pad with 0 bytes until 16 bytes are written
-        while bytes_written < 16 {
-            res.write_u8(0u8).unwrap();
-            bytes_written += 1;
-        }
+        res.write_all(&self.name.to_field()).unwrap();
         res.write_u32::<BigEndian>(self.config.bits()).unwrap();
         res.write_u32::<BigEndian>(self.state.bits()).unwrap();
         res.write_u32::<BigEndian>(self.curr.bits()).unwrap();
@@ -217,25 +190,145 @@ impl Into<Vec<u8>> for Port {
     }
 }
 
+/// Name of a port, at most PORT_NAME_LEN - 1 bytes without null bytes.
+/// Names are bytes on the wire and are displayed as lossy utf-8.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default)]
+pub struct PortName(Vec<u8>);
+
+impl PortName {
+    /// fails if the name is too long or contains a null byte
+    pub fn new<T: Into<Vec<u8>>>(name: T) -> Result<Self> {
+        let name = name.into();
+        if name.len() >= PORT_NAME_LEN {
+            bail!(ErrorKind::InvalidSliceLength(
+                PORT_NAME_LEN - 1,
+                name.len(),
+                stringify!(PortName)
+            ));
+        }
+        if name.contains(&0) {
+            bail!(ErrorKind::IllegalValue(0, stringify!(PortName)));
+        }
+        Ok(PortName(name))
+    }
+
+    /// the name up to the first null byte, cut to PORT_NAME_LEN - 1 bytes
+    pub fn truncated<T: Into<Vec<u8>>>(name: T) -> Self {
+        let mut name = name.into();
+        if let Some(end) = name.iter().position(|&b| b == 0) {
+            name.truncate(end);
+        }
+        name.truncate(PORT_NAME_LEN - 1);
+        PortName(name)
+    }
+
+    /// decodes the name field of a port, the name ends at the first null byte
+    /// and the rest is padding
+    /// a field without null byte (which the spec does not allow) loses its last byte
+    pub fn from_field(field: &[u8]) -> Self {
+        PortName::truncated(&field[..field.len().min(PORT_NAME_LEN)])
+    }
+
+    /// the name padded with null bytes to PORT_NAME_LEN bytes
+    pub fn to_field(&self) -> [u8; PORT_NAME_LEN] {
+        let mut field = [0u8; PORT_NAME_LEN];
+        field[..self.0.len()].copy_from_slice(&self.0);
+        field
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.0)
+    }
+}
+
+impl fmt::Display for PortName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_string_lossy())
+    }
+}
+
+impl FromStr for PortName {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        PortName::new(s)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum PortNumber {
     Reserved(PortNo),
     NormalPort(u32),
 }
 
+impl PortNumber {
+    /// true for the reserved ports like controller or flood
+    pub fn is_reserved(&self) -> bool {
+        match *self {
+            PortNumber::Reserved(_) => true,
+            PortNumber::NormalPort(_) => false,
+        }
+    }
+
+    /// true for ports of the switch (physical or logical), false for reserved ports
+    pub fn is_physical(&self) -> bool {
+        !self.is_reserved()
+    }
+}
+
 impl TryFrom<u32> for PortNumber {
     type Error = Error;
+    /// fails for port 0 and values above max that are no reserved port
     fn try_from(port_no: u32) -> Result<Self> {
         if port_no == 0 {
             bail!(ErrorKind::IllegalValue(0, stringify!(PortNumber)));
         }
         Ok(match PortNo::from_u32(port_no) {
             Some(port) => PortNumber::Reserved(port),
+            None if port_no > PortNo::Max as u32 => {
+                bail!(ErrorKind::UnknownValue(port_no as u64, stringify!(PortNo)))
+            }
             None => PortNumber::NormalPort(port_no),
         })
     }
 }
 
+impl fmt::Display for PortNumber {
+    /// reserved ports are printed by name, eg. "controller", others as number
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PortNumber::Reserved(ref port) => write!(f, "{}", port.name()),
+            PortNumber::NormalPort(port_no) => write!(f, "{}", port_no),
+        }
+    }
+}
+
+impl FromStr for PortNumber {
+    type Err = Error;
+    /// parses port names (case insensitive) and decimal or `0x` prefixed numbers
+    fn from_str(s: &str) -> Result<Self> {
+        let lower = s.to_lowercase();
+        let port = match lower.as_ref() {
+            "none" => Some(PortNo::Any),
+            name => PortNo::from_name(name),
+        };
+        if let Some(port) = port {
+            return Ok(port.into());
+        }
+        let parsed = match lower.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => lower.parse(),
+        };
+        match parsed {
+            Ok(port_no) => PortNumber::try_from(port_no),
+            Err(_) => bail!(ErrorKind::InvalidPort(s.to_string())),
+        }
+    }
+}
+
 impl Into<u32> for PortNumber {
     fn into(self) -> u32 {
         match self {
@@ -278,6 +371,37 @@ pub enum PortNo {
     Any = 0xffffffff,
 }
 
+impl PortNo {
+    const NAMES: [(PortNo, &'static str); 9] = [
+        (PortNo::Max, "max"),
+        (PortNo::InPort, "in_port"),
+        (PortNo::Table, "table"),
+        (PortNo::Normal, "normal"),
+        (PortNo::Flood, "flood"),
+        (PortNo::All, "all"),
+        (PortNo::Controller, "controller"),
+        (PortNo::Local, "local"),
+        (PortNo::Any, "any"),
+    ];
+
+    /// lowercase name of the port as used by ovs-ofctl, eg. "controller"
+    pub fn name(&self) -> &'static str {
+        PortNo::NAMES
+            .iter()
+            .find(|&(port, _)| port == self)
+            .map(|&(_, name)| name)
+            .unwrap()
+    }
+
+    /// looks up a port by its lowercase name
+    pub fn from_name(name: &str) -> Option<PortNo> {
+        PortNo::NAMES
+            .iter()
+            .find(|&&(_, port_name)| port_name == name)
+            .map(|(port, _)| port.clone())
+    }
+}
+
 impl Into<PortNumber> for PortNo {
     fn into(self) -> PortNumber {
         PortNumber::Reserved(self)
@@ -353,7 +477,7 @@ bitflags! {
 
 #[cfg(test)]
 mod tests {
-    use super::super::hw_addr;
+    use super::super::addr::MacAddr;
     use super::*;
 
     #[test]
@@ -366,13 +490,41 @@ mod tests {
         assert!(Port::try_from(&[0u8; PORT_LENGTH + 1][..]).is_err());
     }
 
+    #[test]
+    fn new_checks_name_length() {
+        let hw_addr = MacAddr::from_slice(&[0u8; 6]).unwrap();
+        let name = PortName::new(b"exactly15bytesa".to_vec()).unwrap();
+        let port = Port::new(PortNumber::NormalPort(1), hw_addr, name);
+        assert_eq!(port.name().as_bytes().len(), PORT_NAME_LEN - 1);
+
+        assert!(PortName::new(b"exactly16bytesab".to_vec()).is_err());
+        assert!(PortName::new(b"eth\x001".to_vec()).is_err());
+        assert_eq!(
+            PortName::truncated(b"exactly16bytesab".to_vec()).as_bytes(),
+            b"exactly16bytesa"
+        );
+    }
+
+    #[test]
+    fn port_name_field() {
+        let name: PortName = "eth1".parse().unwrap();
+        assert_eq!(&name.to_field()[..5], b"eth1\0");
+        assert_eq!(PortName::from_field(&name.to_field()), name);
+        // garbage after the null byte and a missing null byte
+        assert_eq!(PortName::from_field(b"eth1\0garbage\xff\xff\xff"), name);
+        let full = PortName::from_field(&[b'a'; PORT_NAME_LEN]);
+        assert_eq!(full.as_bytes().len(), PORT_NAME_LEN - 1);
+        assert_eq!(full.to_field()[PORT_NAME_LEN - 1], 0);
+        assert_eq!(PortName::from_field(b"eth\xff").to_string(), "eth\u{fffd}");
+    }
+
     #[test]
     fn into_length() {
         let p = Port {
             port_no: PortNumber::NormalPort(1),
-            hw_addr: hw_addr::from_slice_eth(&[0u8; 6]).expect("could not parse test hw_addr"),
-            name: ::std::ffi::CString::new(b"exactly15bytesa".to_vec())
-                .expect("error while creating CString for test"),
+            hw_addr: MacAddr::from_slice(&[0u8; 6]).expect("could not parse test hw_addr"),
+            name: PortName::new(b"exactly15bytesa".to_vec())
+                .expect("error while creating PortName for test"),
             config: PortConfig::empty(),
             state: PortState::empty(),
             curr: PortFeatures::MB10_HD,
@@ -390,9 +542,9 @@ mod tests {
     fn into_tryfrom() {
         let testee = Port {
             port_no: PortNumber::NormalPort(1),
-            hw_addr: hw_addr::from_slice_eth(&[0u8; 6]).expect("could not parse test hw_addr"),
-            name: ::std::ffi::CString::new(b"exactly15bytesa".to_vec())
-                .expect("error while creating CString for test"),
+            hw_addr: MacAddr::from_slice(&[0u8; 6]).expect("could not parse test hw_addr"),
+            name: PortName::new(b"exactly15bytesa".to_vec())
+                .expect("error while creating PortName for test"),
             config: PortConfig::empty(),
             state: PortState::empty(),
             curr: PortFeatures::MB10_HD,
@@ -403,7 +555,6 @@ mod tests {
             max_speed: 8,
         };
         // create 2 byte arrays and 2 from ports
-        // because else the CStrings dont have a null at the end
         let bytes = Into::<Vec<u8>>::into(testee.clone());
         let from = Port::try_from(&bytes[..]).expect("Error while decoding Port from bytes.");
         let bytes2 = Into::<Vec<u8>>::into(from.clone());
@@ -412,4 +563,28 @@ mod tests {
         assert_eq!(PORT_LENGTH, bytes.len());
         assert_eq!(PORT_LENGTH, bytes2.len());
     }
+
+    #[test]
+    fn port_number_display_parse() {
+        assert_eq!(PortNumber::Reserved(PortNo::Controller).to_string(), "controller");
+        assert_eq!(PortNumber::NormalPort(42).to_string(), "42");
+        assert_eq!(
+            "FLOOD".parse::<PortNumber>().unwrap(),
+            PortNumber::Reserved(PortNo::Flood)
+        );
+        assert_eq!("0x2a".parse::<PortNumber>().unwrap(), PortNumber::NormalPort(42));
+        assert!("0".parse::<PortNumber>().is_err());
+        assert!("bogus".parse::<PortNumber>().is_err());
+        assert!(PortNumber::NormalPort(1).is_physical());
+        assert!(PortNumber::Reserved(PortNo::Local).is_reserved());
+    }
+
+    #[test]
+    fn reject_unknown_reserved_ports() {
+        assert!(PortNumber::try_from(0xffff_ff01).is_err());
+        assert_eq!(
+            PortNumber::try_from(0xffff_fffd).unwrap(),
+            PortNumber::Reserved(PortNo::Controller)
+        );
+    }
 }
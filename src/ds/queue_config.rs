@@ -2,10 +2,15 @@ use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::convert::{Into, TryFrom};
 use std::io::{Cursor, Seek, SeekFrom};
 
-use super::packet_queue;
+use super::packet_queue::{self, PacketQueue, QueueRate};
 use super::ports::PortNumber;
+use super::tlv::decode_list;
 
 use super::super::err::*;
+use super::codec::OfEncode;
+
+/// Length of a queue config request and of a reply without the queues (8 bytes).
+pub const QUEUE_GET_CONFIG_LENGTH: usize = 8;
 
 #[derive(Debug)]
 pub struct QueueGetConfigRequest {
@@ -13,13 +18,21 @@ pub struct QueueGetConfigRequest {
     // pad 4 bytes
 }
 
+impl OfEncode for QueueGetConfigRequest {
+    fn encoded_len(&self) -> usize {
+        QUEUE_GET_CONFIG_LENGTH
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.write_u32::<BigEndian>(self.port.clone().into()).unwrap();
+        // pad 4 bytes
+        buf.write_u32::<BigEndian>(0).unwrap();
+    }
+}
+
 impl Into<Vec<u8>> for QueueGetConfigRequest {
     fn into(self) -> Vec<u8> {
-        let mut vec = Vec::new();
-        vec.write_u32::<BigEndian>(self.port.into()).unwrap();
-        // pad 4 bytes
-        vec.write_u32::<BigEndian>(0).unwrap();
-        vec
+        self.to_bytes()
     }
 }
 
@@ -28,7 +41,7 @@ impl<'a> TryFrom<&'a [u8]> for QueueGetConfigRequest {
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
         Ok(QueueGetConfigRequest {
-            port: PortNumber::try_from(cursor.read_u32::<BigEndian>().unwrap())?,
+            port: PortNumber::try_from(cursor.read_u32::<BigEndian>()?)?,
         })
     }
 }
@@ -40,16 +53,60 @@ pub struct QueueGetConfigReply {
     queues: Vec<packet_queue::PacketQueue>,
 }
 
+impl QueueGetConfigReply {
+    pub fn new(port: PortNumber, queues: Vec<PacketQueue>) -> Self {
+        QueueGetConfigReply {
+            port: port,
+            queues: queues,
+        }
+    }
+
+    pub fn queues(&self) -> &[PacketQueue] {
+        &self.queues
+    }
+
+    /// rates of all queues of the port
+    pub fn queue_configs(&self) -> Vec<QueueConfig> {
+        self.queues.iter().map(QueueConfig::from).collect()
+    }
+}
+
+/// Rates of a queue, None if the rate is not configured or disabled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueConfig {
+    pub queue_id: u32,
+    pub port: PortNumber,
+    pub min_rate: Option<QueueRate>,
+    pub max_rate: Option<QueueRate>,
+}
+
+impl<'a> From<&'a PacketQueue> for QueueConfig {
+    fn from(queue: &'a PacketQueue) -> Self {
+        QueueConfig {
+            queue_id: queue.queue_id(),
+            port: queue.port().clone(),
+            min_rate: queue.min_rate(),
+            max_rate: queue.max_rate(),
+        }
+    }
+}
+
+impl OfEncode for QueueGetConfigReply {
+    fn encoded_len(&self) -> usize {
+        QUEUE_GET_CONFIG_LENGTH + self.queues.encoded_len()
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.write_u32::<BigEndian>(self.port.clone().into()).unwrap();
+        // pad 4 bytes
+        buf.write_u32::<BigEndian>(0).unwrap();
+        self.queues.encode_into(buf);
+    }
+}
+
 impl Into<Vec<u8>> for QueueGetConfigReply {
     fn into(self) -> Vec<u8> {
-        let mut vec = Vec::new();
-        vec.write_u32::<BigEndian>(self.port.into()).unwrap();
-        // pad 4 bytes
-        vec.write_u32::<BigEndian>(0).unwrap();
-        for queue in self.queues {
-            vec.extend_from_slice(&Into::<Vec<u8>>::into(queue)[..]);
-        }
-        vec
+        self.to_bytes()
     }
 }
 
@@ -57,20 +114,15 @@ impl<'a> TryFrom<&'a [u8]> for QueueGetConfigReply {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let port = PortNumber::try_from(cursor.read_u32::<BigEndian>().unwrap())?;
+        let port = PortNumber::try_from(cursor.read_u32::<BigEndian>()?)?;
         cursor.seek(SeekFrom::Current(4)).unwrap();
 
-        let mut queues = Vec::new();
-        let mut bytes_left = bytes.len() - 8;
-        while bytes_left > 0 {
-            let queue_len = packet_queue::PacketQueue::read_len(&mut cursor)?;
-            let queue_slice =
-                &bytes[cursor.position() as usize..cursor.position() as usize + queue_len];
-            let queue = packet_queue::PacketQueue::try_from(queue_slice)?;
-            queues.push(queue);
-            bytes_left -= queue_len;
-            cursor.seek(SeekFrom::Current(queue_len as i64)).unwrap();
-        }
+        let queues = decode_list(
+            bytes,
+            cursor.position() as usize,
+            packet_queue::PACKET_QUEUE_LEN_FIELD,
+            stringify!(PacketQueue),
+        )?;
 
         Ok(QueueGetConfigReply {
             port: port,
@@ -0,0 +1,499 @@
+//! Incremental decoding of large messages.
+//!
+//! A flow stats reply of a switch with many flows fills the whole 64 KiB of a
+//! message and is usually split over many of them. Decoding it as an `OfMsg`
+//! builds every entry before the first one can be looked at. The iterators of
+//! this module decode one entry at a time from the bytes of the message
+//! instead, entries that are not needed can be dropped right away. A
+//! `FlowFilter` skips entries before their match and instructions are decoded.
+//! The controller passes the replies to `SwitchHandle::for_each_flow` (and
+//! `flows`) this way, see `ReplyRouter::subscribe_frames`:
+//!
+//! ```
+//! # use oath2::ds::multipart::{MultipartReply, RepPayload};
+//! # use oath2::ds::{OfMsg, OfPayload};
+//! use oath2::ds::stream::{self, FlowFilter, FlowStatsIter};
+//!
+//! # let reply = MultipartReply::new(RepPayload::Flow(Vec::new()));
+//! # let bytes = OfMsg::generate(1, OfPayload::MultipartReply(reply)).encode().unwrap();
+//! let header = stream::peek_header(&bytes).unwrap();
+//! assert_eq!(*header.length() as usize, bytes.len());
+//! let mut packets = 0;
+//! let filter = FlowFilter::all().table(0);
+//! for stats in FlowStatsIter::new(&bytes).unwrap().with_filter(filter) {
+//!     packets += stats.unwrap().packet_count;
+//! }
+//! assert_eq!(packets, 0);
+//! ```
+
+use byteorder::{BigEndian, ByteOrder};
+use num_traits::ToPrimitive;
+use std::convert::TryFrom;
+use std::marker::PhantomData;
+
+use super::super::err::*;
+use super::codec::OfEncode;
+use super::flags::{self, FlagsPolicy};
+use super::flow_match::{Match, TlvMatch, MATCH_HEADER_LENGTH, OXM_LEN_FIELD};
+use super::flow_stats::{
+    FlowDesc, FlowStats, FLOW_DESC_LENGTH, FLOW_DESC_LEN_FIELD, FLOW_STATS_LENGTH,
+    FLOW_STATS_LEN_FIELD,
+};
+use super::multipart::{self, MultipartFlags, MultipartTypes, MULTIPART_HEADER_LENGTH};
+use super::tlv::{LengthField, TlvCursor};
+use super::{checked_slice, checked_tail, Header, Type, Version, HEADER_LENGTH};
+
+/// decodes the header at the start of the bytes, the rest of the message may
+/// still be missing (eg. to find out how many bytes to wait for)
+pub fn peek_header(bytes: &[u8]) -> Result<Header> {
+    if bytes.len() < HEADER_LENGTH {
+        bail!(ErrorKind::InvalidSliceLength(
+            HEADER_LENGTH,
+            bytes.len(),
+            stringify!(Header),
+        ));
+    }
+    Header::try_from(&bytes[..HEADER_LENGTH])
+}
+
+/// decodes the header of a multipart reply and the type and flags in front of
+/// its body, fails for other messages
+pub fn peek_multipart_reply(bytes: &[u8]) -> Result<(Header, MultipartTypes, MultipartFlags)> {
+    let header = peek_header(bytes)?;
+    if *header.ttype() != Type::MultipartReply {
+        bail!(ErrorKind::UnexpectedReply(
+            *header.xid(),
+            header.ttype().clone()
+        ));
+    }
+    let (ttype, flags) = multipart::read_header(&bytes[HEADER_LENGTH..]).within(HEADER_LENGTH)?;
+    Ok((header, ttype, flags))
+}
+
+/// Flow stats entries of a reply up to OpenFlow 1.4, see the module documentation.
+pub type FlowStatsIter<'a> = MultipartEntries<'a, FlowStats>;
+/// Flow descriptions of a reply since OpenFlow 1.5, see the module documentation.
+pub type FlowDescIter<'a> = MultipartEntries<'a, FlowDesc>;
+
+/// Decides which flow entries are decoded, see `MultipartEntries::with_filter`.
+/// The conditions are checked on the bytes of an entry, entries that do not
+/// pass are skipped without decoding their match and instructions.
+/// All conditions have to match.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FlowFilter {
+    table_id: Option<u8>,
+    cookie: u64,
+    cookie_mask: u64,
+    /// encoded fields the match has to contain
+    fields: Vec<Vec<u8>>,
+}
+
+impl FlowFilter {
+    /// passes every entry
+    pub fn all() -> Self {
+        FlowFilter::default()
+    }
+
+    /// only flows of the table
+    pub fn table(mut self, table_id: u8) -> Self {
+        self.table_id = Some(table_id);
+        self
+    }
+
+    /// only flows whose cookie has the bits set in the mask set like the
+    /// given cookie, as in a FlowStatsRequest
+    pub fn cookie(mut self, cookie: u64, cookie_mask: u64) -> Self {
+        self.cookie = cookie;
+        self.cookie_mask = cookie_mask;
+        self
+    }
+
+    /// only flows whose match contains the field with the same value and mask
+    pub fn field(mut self, field: TlvMatch) -> Self {
+        self.fields.push(field.to_bytes());
+        self
+    }
+
+    /// only flows whose match contains every field of the given one,
+    /// it may contain more
+    pub fn contains(mut self, mmatch: &Match) -> Self {
+        self.fields
+            .extend(mmatch.matches().iter().map(|field| field.to_bytes()));
+        self
+    }
+
+    /// checks the entry without decoding it, fails if the match the fields
+    /// are compared with is invalid
+    fn passes(&self, entry: &[u8], layout: &FlowLayout) -> Result<bool> {
+        // the length field of the list guarantees the fixed part of the entry
+        if let Some(table_id) = self.table_id {
+            if entry[layout.table_id] != table_id {
+                return Ok(false);
+            }
+        }
+        let cookie = BigEndian::read_u64(&entry[layout.cookie..layout.cookie + 8]);
+        if cookie & self.cookie_mask != self.cookie & self.cookie_mask {
+            return Ok(false);
+        }
+        if self.fields.is_empty() {
+            return Ok(true);
+        }
+        let match_bytes = checked_tail(entry, layout.mmatch, stringify!(Match))?;
+        let match_header = checked_slice(match_bytes, 0, MATCH_HEADER_LENGTH, stringify!(Match))
+            .within(layout.mmatch)?;
+        // the fields end at the length, the rest is padding
+        let length = BigEndian::read_u16(&match_header[2..]) as usize;
+        let tlv_bytes =
+            checked_slice(match_bytes, 0, length, stringify!(Match)).within(layout.mmatch)?;
+        let tlvs = TlvCursor::new(
+            tlv_bytes,
+            MATCH_HEADER_LENGTH,
+            OXM_LEN_FIELD,
+            stringify!(TlvMatch),
+        )
+        .map(|tlv| tlv.map(|(_, tlv)| tlv))
+        .collect::<Result<Vec<&[u8]>>>()
+        .within(layout.mmatch)?;
+        Ok(self
+            .fields
+            .iter()
+            .all(|field| tlvs.iter().any(|tlv| tlv == field)))
+    }
+}
+
+/// offsets of the fields a filter looks at in an entry
+#[derive(Debug, Clone)]
+struct FlowLayout {
+    table_id: usize,
+    cookie: usize,
+    mmatch: usize,
+}
+
+const FLOW_STATS_LAYOUT: FlowLayout = FlowLayout {
+    table_id: 2,
+    cookie: 24,
+    mmatch: FLOW_STATS_LENGTH,
+};
+
+const FLOW_DESC_LAYOUT: FlowLayout = FlowLayout {
+    table_id: 4,
+    cookie: 16,
+    mmatch: FLOW_DESC_LENGTH,
+};
+
+/// Decodes the entries of a multipart reply one at a time.
+/// Error offsets are relative to the start of the message, an invalid entry
+/// ends the iteration.
+#[derive(Debug, Clone)]
+pub struct MultipartEntries<'a, T> {
+    bytes: &'a [u8],
+    header: Header,
+    flags: MultipartFlags,
+    entries: TlvCursor<'a>,
+    layout: FlowLayout,
+    filter: FlowFilter,
+    flags_policy: FlagsPolicy,
+    skipped: usize,
+    failed: bool,
+    entry: PhantomData<T>,
+}
+
+impl<'a> MultipartEntries<'a, FlowStats> {
+    /// iterates over the flow stats of the reply, fails if the bytes are not
+    /// a complete flow stats reply of OpenFlow 1.4 or older
+    pub fn new(bytes: &'a [u8]) -> Result<Self> {
+        MultipartEntries::of(
+            bytes,
+            FLOW_STATS_LEN_FIELD,
+            FLOW_STATS_LAYOUT,
+            stringify!(FlowStats),
+            |version| version < Version::V1_5,
+        )
+    }
+}
+
+impl<'a> MultipartEntries<'a, FlowDesc> {
+    /// iterates over the flow descriptions of the reply, fails if the bytes
+    /// are not a complete flow stats reply of OpenFlow 1.5 or newer
+    pub fn new(bytes: &'a [u8]) -> Result<Self> {
+        MultipartEntries::of(
+            bytes,
+            FLOW_DESC_LEN_FIELD,
+            FLOW_DESC_LAYOUT,
+            stringify!(FlowDesc),
+            |version| version >= Version::V1_5,
+        )
+    }
+}
+
+impl<'a, T> MultipartEntries<'a, T> {
+    fn of<F>(
+        bytes: &'a [u8],
+        field: LengthField,
+        layout: FlowLayout,
+        ttype: &'static str,
+        supports: F,
+    ) -> Result<Self>
+    where
+        F: Fn(Version) -> bool,
+    {
+        let (header, multipart_type, flags) = peek_multipart_reply(bytes)?;
+        if bytes.len() != *header.length() as usize {
+            bail!(ErrorKind::InvalidSliceLength(
+                *header.length() as usize,
+                bytes.len(),
+                stringify!(OfMsg),
+            ));
+        }
+        if multipart_type != MultipartTypes::Flow {
+            bail!(ErrorKind::UnsupportedValue(
+                multipart_type.to_u16().unwrap() as u64,
+                ttype
+            ));
+        }
+        if !supports(*header.version()) {
+            bail!(ErrorKind::UnsupportedValue(
+                header.version().to_u8().unwrap() as u64,
+                ttype
+            ));
+        }
+        let start = HEADER_LENGTH + MULTIPART_HEADER_LENGTH;
+        Ok(MultipartEntries {
+            bytes: bytes,
+            header: header,
+            flags: flags,
+            entries: TlvCursor::new(bytes, start, field, ttype),
+            layout: layout,
+            filter: FlowFilter::all(),
+            flags_policy: FlagsPolicy::Strict,
+            skipped: 0,
+            failed: false,
+            entry: PhantomData,
+        })
+    }
+
+    /// only decodes the entries that pass the filter, the others are skipped
+    pub fn with_filter(mut self, filter: FlowFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// how unknown flag bits of the entries are handled, Strict by default
+    pub fn with_flags_policy(mut self, flags_policy: FlagsPolicy) -> Self {
+        self.flags_policy = flags_policy;
+        self
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// MultipartFlags::MORE if more replies follow
+    pub fn flags(&self) -> MultipartFlags {
+        self.flags
+    }
+
+    /// number of entries the filter skipped so far
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// the entry at pos if it passes the filter
+    fn decode(&self, pos: usize, entry: &'a [u8]) -> Result<Option<T>>
+    where
+        T: TryFrom<&'a [u8], Error = Error>,
+    {
+        if !self.filter.passes(entry, &self.layout).within(pos)? {
+            return Ok(None);
+        }
+        let (decoded, _) = flags::decode_with(self.flags_policy, || T::try_from(entry));
+        decoded.within(pos).map(Some)
+    }
+}
+
+impl<'a, T> Iterator for MultipartEntries<'a, T>
+where
+    T: TryFrom<&'a [u8], Error = Error>,
+{
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.failed {
+            let decoded = match self.entries.next()? {
+                Ok((pos, entry)) => self.decode(pos, entry),
+                Err(err) => Err(err),
+            };
+            match decoded {
+                Ok(Some(entry)) => return Some(Ok(entry)),
+                Ok(None) => self.skipped += 1,
+                Err(err) => {
+                    self.failed = true;
+                    let err = err
+                        .in_message(self.header.ttype().clone(), Some(*self.header.xid()))
+                        .excerpt_from(self.bytes);
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::flow_mod::FlowModFlags;
+    use super::super::flow_parser::parse_flow_mod;
+    use super::super::flow_stats::OxsStats;
+    use super::super::multipart::{MultipartReply, RepPayload};
+    use super::super::{OfMsg, OfPayload};
+    use super::*;
+
+    fn flow_stats(priority: u16) -> FlowStats {
+        let flow_mod = parse_flow_mod("in_port=1,actions=output:2").unwrap();
+        FlowStats {
+            table_id: 0,
+            duration_sec: 1,
+            duration_nsec: 0,
+            priority: priority,
+            idle_timeout: 0,
+            hard_timeout: 0,
+            flags: FlowModFlags::empty(),
+            cookie: 0,
+            packet_count: u64::from(priority),
+            byte_count: 0,
+            mmatch: flow_mod.mmatch,
+            instructions: flow_mod.instructions,
+        }
+    }
+
+    fn reply(version: Version, payload: RepPayload) -> Vec<u8> {
+        let mut reply = MultipartReply::new(payload);
+        reply.flags = MultipartFlags::MORE;
+        OfMsg::generate_for(version, 7, OfPayload::MultipartReply(reply))
+            .unwrap()
+            .encode()
+            .unwrap()
+    }
+
+    #[test]
+    fn peeks_header_of_partial_message() {
+        let bytes = reply(Version::V1_3, RepPayload::Flow(vec![flow_stats(1)]));
+        let header = peek_header(&bytes[..HEADER_LENGTH]).unwrap();
+        assert_eq!(*header.length() as usize, bytes.len());
+        assert!(peek_header(&bytes[..HEADER_LENGTH - 1]).is_err());
+
+        let (_, ttype, flags) = peek_multipart_reply(&bytes[..20]).unwrap();
+        assert_eq!(ttype, MultipartTypes::Flow);
+        assert_eq!(flags, MultipartFlags::MORE);
+        // the entries are not complete yet
+        assert!(FlowStatsIter::new(&bytes[..20]).is_err());
+
+        let hello = OfMsg::generate(1, OfPayload::Hello).encode().unwrap();
+        assert!(peek_header(&hello).is_ok());
+        assert!(peek_multipart_reply(&hello).is_err());
+    }
+
+    #[test]
+    fn iterates_over_flow_stats() {
+        let stats: Vec<FlowStats> = (1..4).map(flow_stats).collect();
+        let bytes = reply(Version::V1_3, RepPayload::Flow(stats.clone()));
+        let entries = FlowStatsIter::new(&bytes).unwrap();
+        assert_eq!(*entries.header().xid(), 7);
+        assert_eq!(entries.flags(), MultipartFlags::MORE);
+        let decoded: Result<Vec<FlowStats>> = entries.collect();
+        assert_eq!(decoded.unwrap(), stats);
+
+        // 1.5 replies contain descriptions
+        assert!(FlowDescIter::new(&bytes).is_err());
+        let desc = FlowDesc {
+            table_id: 0,
+            priority: 1,
+            idle_timeout: 0,
+            hard_timeout: 0,
+            flags: FlowModFlags::empty(),
+            importance: 0,
+            cookie: 0,
+            mmatch: stats[0].mmatch.clone(),
+            stats: OxsStats::default(),
+            instructions: Vec::new(),
+        };
+        let bytes = reply(Version::V1_5, RepPayload::FlowDesc(vec![desc.clone()]));
+        assert!(FlowStatsIter::new(&bytes).is_err());
+        let descs: Vec<FlowDesc> = FlowDescIter::new(&bytes)
+            .unwrap()
+            .map(|desc| desc.unwrap())
+            .collect();
+        assert_eq!(descs, vec![desc]);
+    }
+
+    #[test]
+    fn stops_at_invalid_entry() {
+        let stats: Vec<FlowStats> = (1..3).map(flow_stats).collect();
+        let mut bytes = reply(Version::V1_3, RepPayload::Flow(stats.clone()));
+        // the second entry claims to be longer than the message
+        let second = HEADER_LENGTH + MULTIPART_HEADER_LENGTH + (bytes.len() - 16) / 2;
+        bytes[second] = 0xff;
+        let mut entries = FlowStatsIter::new(&bytes).unwrap();
+        assert_eq!(entries.next().unwrap().unwrap(), stats[0]);
+        let err = entries.next().unwrap().unwrap_err();
+        assert_eq!(err.context().xid, Some(7));
+        assert!(entries.next().is_none());
+    }
+
+    #[test]
+    fn filters_before_decoding() {
+        let mut stats: Vec<FlowStats> = (1..5).map(flow_stats).collect();
+        stats[1].table_id = 1;
+        stats[2].cookie = 0x1234_0001;
+        stats[3].mmatch = parse_flow_mod("in_port=1,dl_type=0x0800,actions=drop")
+            .unwrap()
+            .mmatch;
+        let bytes = reply(Version::V1_3, RepPayload::Flow(stats.clone()));
+        let decode = |filter: FlowFilter| -> Vec<u16> {
+            FlowStatsIter::new(&bytes)
+                .unwrap()
+                .with_filter(filter)
+                .map(|stats| stats.unwrap().priority)
+                .collect()
+        };
+        assert_eq!(decode(FlowFilter::all()), vec![1, 2, 3, 4]);
+        assert_eq!(decode(FlowFilter::all().table(1)), vec![2]);
+        assert_eq!(
+            decode(FlowFilter::all().cookie(0x1234_0000, 0xffff_0000)),
+            vec![3]
+        );
+        let ipv4 = parse_flow_mod("dl_type=0x0800,actions=drop")
+            .unwrap()
+            .mmatch;
+        assert_eq!(decode(FlowFilter::all().contains(&ipv4)), vec![4]);
+        let field = stats[0].mmatch.matches()[0].clone();
+        assert_eq!(
+            decode(FlowFilter::all().field(field).table(0)),
+            vec![1, 3, 4]
+        );
+
+        let mut entries = FlowStatsIter::new(&bytes)
+            .unwrap()
+            .with_filter(FlowFilter::all().table(1));
+        assert!(entries.next().is_some());
+        assert!(entries.next().is_none());
+        assert_eq!(entries.skipped(), 3);
+    }
+
+    #[test]
+    fn skipped_entries_are_not_decoded() {
+        let stats: Vec<FlowStats> = (1..3).map(flow_stats).collect();
+        let mut bytes = reply(Version::V1_3, RepPayload::Flow(stats.clone()));
+        // the type of the first instruction of the first entry is unknown
+        let instruction = HEADER_LENGTH + MULTIPART_HEADER_LENGTH + FLOW_STATS_LENGTH + 16;
+        bytes[instruction] = 0x7f;
+        assert!(FlowStatsIter::new(&bytes).unwrap().next().unwrap().is_err());
+
+        bytes[HEADER_LENGTH + MULTIPART_HEADER_LENGTH + FLOW_STATS_LAYOUT.table_id] = 1;
+        let decoded: Result<Vec<FlowStats>> = FlowStatsIter::new(&bytes)
+            .unwrap()
+            .with_filter(FlowFilter::all().table(0))
+            .collect();
+        assert_eq!(decoded.unwrap(), vec![stats[1].clone()]);
+    }
+}
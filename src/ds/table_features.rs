@@ -0,0 +1,220 @@
+//! Table features (multipart type TableFeatures).
+//!
+//! Since OpenFlow 1.5 the features tell which tables are ingress and which
+//! are egress tables, the egress pipeline starts at the first egress table.
+//! Properties are kept raw, the controller only reads them.
+
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use std::convert::{Into, TryFrom};
+use std::io::{Cursor, Seek, SeekFrom};
+
+use super::multipart::{read_fixed_str, write_fixed_str};
+use super::tlv::{decode_list, encode_list, pad_to, LengthField};
+use super::{checked_slice, checked_tail};
+
+use super::super::err::*;
+
+/// Length of the table features without properties (64 bytes).
+pub const TABLE_FEATURES_LENGTH: usize = 64;
+/// Length of the table name including the terminating null.
+pub const MAX_TABLE_NAME_LEN: usize = 32;
+/// The entries of a reply start with their u16 length.
+pub const TABLE_FEATURES_LEN_FIELD: LengthField = LengthField::u16_at(0, TABLE_FEATURES_LENGTH);
+/// Length of the type and length fields of a property (4 bytes).
+pub const TABLE_FEATURE_PROP_HEADER_LENGTH: usize = 4;
+/// Properties are padded to a multiple of 8 bytes, the length excludes the padding.
+pub const TABLE_FEATURE_PROP_LEN_FIELD: LengthField =
+    LengthField::u16_at(2, TABLE_FEATURE_PROP_HEADER_LENGTH).padded_to(8);
+
+/// Features of a flow table.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TableFeatures {
+    // length u16
+    pub table_id: u8,
+    /// how the switch applies the features of a request (since 1.5, pad before)
+    pub command: u8,
+    /// ingress and egress flags (since 1.5, pad before)
+    pub features: TableFeatureFlags,
+    pub name: String,
+    /// bits of the metadata the table can match
+    pub metadata_match: u64,
+    /// bits of the metadata the table can write
+    pub metadata_write: u64,
+    /// table config in 1.3, table capabilities since 1.4
+    pub capabilities: u32,
+    /// maximum number of entries supported
+    pub max_entries: u32,
+    pub properties: Vec<TableFeatureProp>,
+}
+
+impl TableFeatures {
+    /// the table belongs to the egress pipeline (since 1.5)
+    pub fn is_egress(&self) -> bool {
+        self.features.contains(TableFeatureFlags::EGRESS_TABLE)
+    }
+
+    /// id of the table the egress pipeline starts at, None if the switch has no egress tables
+    pub fn first_egress(tables: &[TableFeatures]) -> Option<u8> {
+        tables
+            .iter()
+            .find(|table| table.features.contains(TableFeatureFlags::FIRST_EGRESS))
+            .or_else(|| tables.iter().find(|table| table.is_egress()))
+            .map(|table| table.table_id)
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for TableFeatures {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let fixed = checked_slice(bytes, 0, TABLE_FEATURES_LENGTH, stringify!(TableFeatures))?;
+        let mut cursor = Cursor::new(fixed);
+        cursor.seek(SeekFrom::Current(2)).unwrap(); // length, checked by the list
+        let table_id = cursor.read_u8()?;
+        let command = cursor.read_u8()?;
+        // unknown flags are ignored
+        let features = TableFeatureFlags::from_bits_truncate(cursor.read_u32::<BigEndian>()?);
+        let name = read_fixed_str(&fixed[8..8 + MAX_TABLE_NAME_LEN]);
+        cursor
+            .seek(SeekFrom::Current(MAX_TABLE_NAME_LEN as i64))
+            .unwrap();
+        let metadata_match = cursor.read_u64::<BigEndian>()?;
+        let metadata_write = cursor.read_u64::<BigEndian>()?;
+        let capabilities = cursor.read_u32::<BigEndian>()?;
+        let max_entries = cursor.read_u32::<BigEndian>()?;
+
+        let properties = decode_list(
+            bytes,
+            TABLE_FEATURES_LENGTH,
+            TABLE_FEATURE_PROP_LEN_FIELD,
+            stringify!(TableFeatureProp),
+        )?;
+
+        Ok(TableFeatures {
+            table_id: table_id,
+            command: command,
+            features: features,
+            name: name,
+            metadata_match: metadata_match,
+            metadata_write: metadata_write,
+            capabilities: capabilities,
+            max_entries: max_entries,
+            properties: properties,
+        })
+    }
+}
+
+impl Into<Vec<u8>> for TableFeatures {
+    fn into(self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.write_u16::<BigEndian>(0).unwrap(); // length, set below
+        res.write_u8(self.table_id).unwrap();
+        res.write_u8(self.command).unwrap();
+        res.write_u32::<BigEndian>(self.features.bits()).unwrap();
+        write_fixed_str(&mut res, &self.name, MAX_TABLE_NAME_LEN);
+        res.write_u64::<BigEndian>(self.metadata_match).unwrap();
+        res.write_u64::<BigEndian>(self.metadata_write).unwrap();
+        res.write_u32::<BigEndian>(self.capabilities).unwrap();
+        res.write_u32::<BigEndian>(self.max_entries).unwrap();
+        res.extend_from_slice(&encode_list(self.properties)[..]);
+        let len = res.len() as u16;
+        BigEndian::write_u16(&mut res[..2], len);
+        res
+    }
+}
+
+bitflags!{
+    /// Role of a table in the pipeline (OpenFlow 1.5).
+    pub struct TableFeatureFlags: u32 {
+        /// Part of the ingress pipeline.
+        const INGRESS_TABLE = 1 << 0;
+        /// Part of the egress pipeline.
+        const EGRESS_TABLE = 1 << 1;
+        /// First table of the egress pipeline.
+        const FIRST_EGRESS = 1 << 4;
+    }
+}
+
+/// Property of the table features, eg. the supported instructions or match fields.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TableFeatureProp {
+    pub ttype: u16,
+    /// property data, without the padding
+    pub data: Vec<u8>,
+}
+
+impl<'a> TryFrom<&'a [u8]> for TableFeatureProp {
+    type Error = Error;
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(bytes);
+        let ttype = cursor.read_u16::<BigEndian>()?;
+        let len = cursor.read_u16::<BigEndian>()? as usize;
+        let data = checked_tail(
+            checked_slice(bytes, 0, len, stringify!(TableFeatureProp))?,
+            TABLE_FEATURE_PROP_HEADER_LENGTH,
+            stringify!(TableFeatureProp),
+        )?;
+        Ok(TableFeatureProp {
+            ttype: ttype,
+            data: data.to_vec(),
+        })
+    }
+}
+
+impl Into<Vec<u8>> for TableFeatureProp {
+    fn into(self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.write_u16::<BigEndian>(self.ttype).unwrap();
+        res.write_u16::<BigEndian>((TABLE_FEATURE_PROP_HEADER_LENGTH + self.data.len()) as u16)
+            .unwrap();
+        res.extend_from_slice(&self.data[..]);
+        // the length excludes the padding to a multiple of 8 bytes
+        pad_to(&mut res, 8);
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(table_id: u8, features: TableFeatureFlags) -> TableFeatures {
+        TableFeatures {
+            table_id: table_id,
+            command: 0,
+            features: features,
+            name: format!("table{}", table_id),
+            metadata_match: !0,
+            metadata_write: !0,
+            capabilities: 0,
+            max_entries: 1000,
+            properties: vec![TableFeatureProp {
+                ttype: 0,
+                data: vec![0, 1, 0, 4],
+            }],
+        }
+    }
+
+    #[test]
+    fn finds_the_egress_tables() {
+        let first_egress = TableFeatureFlags::EGRESS_TABLE | TableFeatureFlags::FIRST_EGRESS;
+        let tables = vec![
+            table(0, TableFeatureFlags::INGRESS_TABLE),
+            table(1, TableFeatureFlags::INGRESS_TABLE),
+            table(2, first_egress),
+            table(3, TableFeatureFlags::EGRESS_TABLE),
+        ];
+        let egress: Vec<u8> = tables
+            .iter()
+            .filter(|table| table.is_egress())
+            .map(|table| table.table_id)
+            .collect();
+        assert_eq!(egress, vec![2, 3]);
+        assert_eq!(TableFeatures::first_egress(&tables), Some(2));
+        assert_eq!(TableFeatures::first_egress(&tables[..2]), None);
+
+        let bytes: Vec<u8> = tables[2].clone().into();
+        assert_eq!(bytes.len(), TABLE_FEATURES_LENGTH + 8);
+        assert_eq!(&bytes[..8], &[0, 72, 2, 0, 0, 0, 0, 0x12]);
+        assert_eq!(TableFeatures::try_from(&bytes[..]).unwrap(), tables[2]);
+    }
+}
@@ -1,38 +1,222 @@
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use num_traits::{FromPrimitive, ToPrimitive};
 use std::convert::{Into, TryFrom};
-use std::io::{Cursor, Seek, SeekFrom};
+use std::io::Cursor;
+
+use super::tlv::{pad_to, LengthField};
+use super::{checked_slice, checked_tail};
 
 use super::super::err::*;
 
-#[derive(Debug, PartialEq, Clone)]
+/// Length of a table mod without properties (8 bytes).
+pub const TABLE_MOD_LENGTH: usize = 8;
+/// Length of the type and length fields of a table mod property (4 bytes).
+pub const TABLE_MOD_PROP_HEADER_LENGTH: usize = 4;
+/// Table id of a table mod that configures all tables (OFPTT_ALL).
+pub const TABLE_ALL: u8 = 0xff;
+/// Properties are padded to a multiple of 8 bytes, the length excludes the padding.
+pub const TABLE_MOD_PROP_LEN_FIELD: LengthField =
+    LengthField::u16_at(2, TABLE_MOD_PROP_HEADER_LENGTH).padded_to(8);
+
+#[derive(OfWire, Getters, Debug, PartialEq, Clone)]
 pub struct TableMod {
+    #[get = "pub"]
+    #[pad(3)]
     table_id: u8,
-    // pad 3 bytes
-    /// reserved for future use
-    config: u32,
+    #[get = "pub"]
+    config: TableConfig,
+    /// since OpenFlow 1.4
+    #[get = "pub"]
+    #[tlv(TABLE_MOD_PROP_LEN_FIELD)]
+    properties: Vec<TableModProp>,
+}
+
+impl TableMod {
+    /// table_id may be TABLE_ALL
+    pub fn new(table_id: u8, config: TableConfig) -> Self {
+        TableMod {
+            table_id: table_id,
+            config: config,
+            properties: Vec::new(),
+        }
+    }
+
+    /// adds a property, only OpenFlow 1.4 and later support them
+    pub fn with_property(mut self, property: TableModProp) -> Self {
+        self.properties.push(property);
+        self
+    }
+}
+
+bitflags!{
+    /// Configuration of a flow table.
+    pub struct TableConfig: u32 {
+        /// Table miss configuration of OpenFlow 1.1 and 1.2, ignored since 1.3.
+        const DEPRECATED_MASK = 3;
+        /// The switch may evict flows to make room for new ones (since 1.4).
+        const EVICTION = 1 << 2;
+        /// The switch sends vacancy events (since 1.4).
+        const VACANCY_EVENTS = 1 << 3;
+    }
+}
+
+bitflags!{
+    /// What the switch considers when evicting flows.
+    pub struct EvictionFlags: u32 {
+        /// Using other factors.
+        const OTHER = 1 << 0;
+        /// Using the importance of the flows.
+        const IMPORTANCE = 1 << 1;
+        /// Using the remaining lifetime of the flows.
+        const LIFETIME = 1 << 2;
+    }
+}
+
+#[derive(Primitive, PartialEq, Debug, Clone)]
+pub enum TableModPropType {
+    /// Eviction property.
+    Eviction = 0x2,
+    /// Vacancy property.
+    Vacancy = 0x3,
+    /// Experimenter property.
+    Experimenter = 0xFFFF,
+}
+
+/// Property of a table mod (OpenFlow 1.4 and later).
+#[derive(Debug, PartialEq, Clone)]
+pub enum TableModProp {
+    Eviction(EvictionFlags),
+    Vacancy(TableModPropVacancy),
+    Experimenter(TableModPropExperimenter),
+}
+
+/// Thresholds of the vacancy events in percent of the table size.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TableModPropVacancy {
+    /// a vacancy down event is sent when the vacancy falls below this
+    pub vacancy_down: u8,
+    /// a vacancy up event is sent when the vacancy rises above this
+    pub vacancy_up: u8,
+    /// current vacancy, only set by the switch
+    pub vacancy: u8,
+    // pad 1 byte
 }
 
-impl<'a> TryFrom<&'a [u8]> for TableMod {
+#[derive(Debug, PartialEq, Clone)]
+pub struct TableModPropExperimenter {
+    pub experimenter: u32,
+    pub exp_type: u32,
+    /// experimenter defined data, without the padding
+    pub data: Vec<u8>,
+}
+
+impl TableModProp {
+    pub fn ttype(&self) -> TableModPropType {
+        match *self {
+            TableModProp::Eviction(_) => TableModPropType::Eviction,
+            TableModProp::Vacancy(_) => TableModPropType::Vacancy,
+            TableModProp::Experimenter(_) => TableModPropType::Experimenter,
+        }
+    }
+
+    /// length of the property including the padding to a multiple of 8 bytes
+    pub fn read_len(cursor: &mut Cursor<&[u8]>) -> Result<usize> {
+        let pos = cursor.position() as usize;
+        TABLE_MOD_PROP_LEN_FIELD.read(cursor.get_ref(), pos, stringify!(TableModProp))
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for TableModProp {
     type Error = Error;
     fn try_from(bytes: &'a [u8]) -> Result<Self> {
         let mut cursor = Cursor::new(bytes);
-        let table_id = cursor.read_u8().unwrap();
-        cursor.seek(SeekFrom::Current(3)).unwrap(); // pad 3 bytes
-        let config = cursor.read_u32::<BigEndian>().unwrap();
-        Ok(TableMod {
-            table_id: table_id,
-            config: config,
-        })
+        let ttype_raw = cursor.read_u16::<BigEndian>()?;
+        let ttype = TableModPropType::from_u16(ttype_raw).ok_or::<Error>(
+            ErrorKind::UnknownValue(ttype_raw as u64, stringify!(TableModPropType)).into(),
+        )?;
+        let len = cursor.read_u16::<BigEndian>()? as usize;
+        let property = match ttype {
+            TableModPropType::Eviction => {
+                let flags_raw = cursor.read_u32::<BigEndian>()?;
+                let flags = flags_from_bits!(EvictionFlags, flags_raw)?;
+                TableModProp::Eviction(flags)
+            }
+            TableModPropType::Vacancy => TableModProp::Vacancy(TableModPropVacancy {
+                vacancy_down: cursor.read_u8()?,
+                vacancy_up: cursor.read_u8()?,
+                vacancy: cursor.read_u8()?,
+            }),
+            TableModPropType::Experimenter => {
+                let experimenter = cursor.read_u32::<BigEndian>()?;
+                let exp_type = cursor.read_u32::<BigEndian>()?;
+                let data = checked_tail(
+                    checked_slice(bytes, 0, len, stringify!(TableModPropExperimenter))?,
+                    cursor.position() as usize,
+                    stringify!(TableModPropExperimenter),
+                )?;
+                TableModProp::Experimenter(TableModPropExperimenter {
+                    experimenter: experimenter,
+                    exp_type: exp_type,
+                    data: data.to_vec(),
+                })
+            }
+        };
+        Ok(property)
     }
 }
 
-impl Into<Vec<u8>> for TableMod {
+impl Into<Vec<u8>> for TableModProp {
     fn into(self) -> Vec<u8> {
+        let mut body = Vec::new();
+        let ttype = self.ttype();
+        match self {
+            TableModProp::Eviction(flags) => body.write_u32::<BigEndian>(flags.bits()).unwrap(),
+            TableModProp::Vacancy(vacancy) => {
+                body.write_u8(vacancy.vacancy_down).unwrap();
+                body.write_u8(vacancy.vacancy_up).unwrap();
+                body.write_u8(vacancy.vacancy).unwrap();
+                body.write_u8(0).unwrap(); // pad 1 byte
+            }
+            TableModProp::Experimenter(experimenter) => {
+                body.write_u32::<BigEndian>(experimenter.experimenter)
+                    .unwrap();
+                body.write_u32::<BigEndian>(experimenter.exp_type).unwrap();
+                body.extend_from_slice(&experimenter.data[..]);
+            }
+        }
+        let len = TABLE_MOD_PROP_HEADER_LENGTH + body.len();
         let mut res = Vec::new();
-        res.write_u8(self.table_id).unwrap();
-        res.write_u8(0).unwrap(); //pad 1 bytes
-        res.write_u16::<BigEndian>(0).unwrap(); //pad 2 bytes
-        res.write_u32::<BigEndian>(self.config).unwrap();
+        res.write_u16::<BigEndian>(ttype.to_u16().unwrap()).unwrap();
+        res.write_u16::<BigEndian>(len as u16).unwrap();
+        res.extend_from_slice(&body[..]);
+        // the length excludes the padding to a multiple of 8 bytes
+        pad_to(&mut res, 8);
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn properties_round_trip() {
+        let table_mod = TableMod::new(TABLE_ALL, TableConfig::EVICTION)
+            .with_property(TableModProp::Eviction(EvictionFlags::IMPORTANCE))
+            .with_property(TableModProp::Vacancy(TableModPropVacancy {
+                vacancy_down: 10,
+                vacancy_up: 20,
+                vacancy: 0,
+            }))
+            .with_property(TableModProp::Experimenter(TableModPropExperimenter {
+                experimenter: 0x2320,
+                exp_type: 1,
+                data: vec![1, 2, 3],
+            }));
+        let bytes: Vec<u8> = table_mod.clone().into();
+        // fixed part, eviction, vacancy and experimenter padded from 15 to 16 bytes
+        assert_eq!(bytes.len(), TABLE_MOD_LENGTH + 8 + 8 + 16);
+        assert_eq!(&bytes[24..28], &[0xff, 0xff, 0x00, 0x0f]);
+        assert_eq!(TableMod::try_from(&bytes[..]).unwrap(), table_mod);
+    }
+}
@@ -0,0 +1,279 @@
+//! Lists of entries that carry their own length (TLVs).
+//!
+//! Most variable length parts of OpenFlow messages are such lists, eg.
+//! actions, instructions, buckets, meter bands, queue properties and OXM
+//! fields. They only differ in where the length is stored, what it covers and
+//! whether entries are padded. `LengthField` describes that, `TlvCursor` walks
+//! a list and checks every length against the bytes that are left.
+
+use byteorder::{BigEndian, ByteOrder};
+use std::convert::TryFrom;
+
+use super::super::err::*;
+use super::checked_slice;
+
+/// Where the length of an entry is stored and how it is interpreted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LengthField {
+    /// offset of the length from the start of the entry
+    pub offset: usize,
+    /// size of the big endian length in bytes, 1 or 2
+    pub width: usize,
+    /// added to the length, for lengths that do not include the header
+    pub header: usize,
+    /// smallest valid length of an entry including the header, at least 1
+    pub min: usize,
+    /// entries are padded to a multiple of align bytes, the length excludes the padding
+    pub align: usize,
+}
+
+impl LengthField {
+    /// u16 at offset that includes the header, entries shorter than min are invalid
+    pub const fn u16_at(offset: usize, min: usize) -> Self {
+        LengthField {
+            offset: offset,
+            width: 2,
+            header: 0,
+            min: min,
+            align: 1,
+        }
+    }
+
+    /// u8 at offset that excludes the header of header bytes
+    pub const fn u8_at(offset: usize, header: usize) -> Self {
+        LengthField {
+            offset: offset,
+            width: 1,
+            header: header,
+            min: header,
+            align: 1,
+        }
+    }
+
+    /// entries are padded to a multiple of align bytes
+    pub const fn padded_to(self, align: usize) -> Self {
+        LengthField {
+            offset: self.offset,
+            width: self.width,
+            header: self.header,
+            min: self.min,
+            align: align,
+        }
+    }
+
+    /// length of the entry starting at pos including its padding
+    /// fails if the length is cut off or shorter than min, the entry itself
+    /// may still exceed the bytes
+    pub fn read(&self, bytes: &[u8], pos: usize, ttype: &'static str) -> Result<usize> {
+        let raw = checked_slice(bytes, pos.saturating_add(self.offset), self.width, ttype)
+            .map_err(|_| Error::from(ErrorKind::CouldNotReadLength(self.offset, ttype)).at(pos))?;
+        let value = match self.width {
+            1 => raw[0] as usize,
+            _ => BigEndian::read_u16(raw) as usize,
+        };
+        let len = value + self.header;
+        // zero length entries would never end the list
+        if len < self.min.max(1) {
+            return Err(Error::from(ErrorKind::IllegalValue(value as u64, ttype)).at(pos));
+        }
+        Ok(len.div_ceil(self.align) * self.align)
+    }
+}
+
+/// Iterates over the entries of a list, see the module documentation.
+/// Yields every entry with its offset in the bytes, an invalid entry ends the
+/// iteration with an error.
+#[derive(Debug, Clone)]
+pub struct TlvCursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    field: LengthField,
+    ttype: &'static str,
+}
+
+impl<'a> TlvCursor<'a> {
+    /// walks the entries of bytes that start at start and fill the rest of the bytes
+    pub fn new(bytes: &'a [u8], start: usize, field: LengthField, ttype: &'static str) -> Self {
+        TlvCursor {
+            bytes: bytes,
+            pos: start,
+            field: field,
+            ttype: ttype,
+        }
+    }
+
+    /// offset of the next entry
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    fn next_entry(&mut self) -> Result<(usize, &'a [u8])> {
+        let len = self.field.read(self.bytes, self.pos, self.ttype)?;
+        let entry = checked_slice(self.bytes, self.pos, len, self.ttype)?;
+        let pos = self.pos;
+        self.pos += len;
+        Ok((pos, entry))
+    }
+}
+
+impl<'a> Iterator for TlvCursor<'a> {
+    type Item = Result<(usize, &'a [u8])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.bytes.len() {
+            return None;
+        }
+        let entry = self.next_entry();
+        if entry.is_err() {
+            self.pos = self.bytes.len();
+        }
+        Some(entry)
+    }
+}
+
+/// decodes the entries of bytes that start at start, error offsets are relative to bytes
+pub fn decode_list<'a, T>(
+    bytes: &'a [u8],
+    start: usize,
+    field: LengthField,
+    ttype: &'static str,
+) -> Result<Vec<T>>
+where
+    T: TryFrom<&'a [u8], Error = Error>,
+{
+    TlvCursor::new(bytes, start, field, ttype)
+        .map(|entry| entry.and_then(|(pos, entry)| T::try_from(entry).within(pos)))
+        .collect()
+}
+
+/// concatenates the encoded entries
+pub fn encode_list<T: Into<Vec<u8>>>(entries: Vec<T>) -> Vec<u8> {
+    let mut res = Vec::new();
+    for entry in entries {
+        res.extend_from_slice(&entry.into()[..]);
+    }
+    res
+}
+
+/// pads the bytes with zeros to a multiple of align
+pub fn pad_to(bytes: &mut Vec<u8>, align: usize) {
+    let len = bytes.len().div_ceil(align) * align;
+    bytes.resize(len, 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// type and length of 2 bytes each, the length includes them
+    const FIELD: LengthField = LengthField::u16_at(2, 4);
+
+    /// entry with its type as the only content
+    #[derive(Debug, PartialEq)]
+    struct Entry(u16);
+
+    impl<'a> TryFrom<&'a [u8]> for Entry {
+        type Error = Error;
+        fn try_from(bytes: &'a [u8]) -> Result<Self> {
+            match BigEndian::read_u16(bytes) {
+                0xffff => Err(Error::from(ErrorKind::UnknownValue(0xffff, "Entry")).at(0)),
+                ttype => Ok(Entry(ttype)),
+            }
+        }
+    }
+
+    fn offset(err: Error) -> Option<usize> {
+        err.context().offset
+    }
+
+    #[test]
+    fn decodes_entries_after_start() {
+        let bytes = [9, 9, 0, 1, 0, 4, 0, 2, 0, 6, 0, 0];
+        let entries: Vec<Entry> = decode_list(&bytes, 2, FIELD, "Entry").unwrap();
+        assert_eq!(entries, vec![Entry(1), Entry(2)]);
+
+        let offsets: Vec<usize> = TlvCursor::new(&bytes, 2, FIELD, "Entry")
+            .map(|entry| entry.unwrap().0)
+            .collect();
+        assert_eq!(offsets, vec![2, 6]);
+        assert!(TlvCursor::new(&bytes, 12, FIELD, "Entry").next().is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_lists() {
+        // length exceeds the bytes
+        let err = decode_list::<Entry>(&[0, 1, 0, 4, 0, 2, 0, 8, 0], 0, FIELD, "Entry");
+        match *err.as_ref().unwrap_err().kind() {
+            ErrorKind::InvalidSliceLength(12, 9, "Entry") => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+        assert_eq!(offset(err.unwrap_err()), Some(4));
+
+        // length itself is cut off
+        let err = decode_list::<Entry>(&[0, 1, 0, 4, 0, 2, 0], 0, FIELD, "Entry").unwrap_err();
+        match *err.kind() {
+            ErrorKind::CouldNotReadLength(2, "Entry") => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+        assert_eq!(offset(err), Some(4));
+    }
+
+    #[test]
+    fn rejects_entries_shorter_than_their_header() {
+        for len in 0..4 {
+            let bytes = [0, 1, 0, len, 0, 0, 0, 0];
+            let err = decode_list::<Entry>(&bytes, 0, FIELD, "Entry").unwrap_err();
+            match *err.kind() {
+                ErrorKind::IllegalValue(value, "Entry") => assert_eq!(value, len as u64),
+                ref kind => panic!("unexpected error kind {:?}", kind),
+            }
+        }
+        // even if the field does not require a minimum
+        let field = LengthField::u16_at(2, 0);
+        assert!(decode_list::<Entry>(&[0, 1, 0, 0], 0, field, "Entry").is_err());
+    }
+
+    #[test]
+    fn stops_after_an_error() {
+        let mut cursor = TlvCursor::new(&[0, 1, 0, 2, 0, 1, 0, 4], 0, FIELD, "Entry");
+        assert!(cursor.next().unwrap().is_err());
+        assert!(cursor.next().is_none());
+    }
+
+    #[test]
+    fn moves_entry_errors_to_the_list() {
+        let bytes = [0, 1, 0, 4, 0xff, 0xff, 0, 4];
+        let err = decode_list::<Entry>(&bytes, 0, FIELD, "Entry").unwrap_err();
+        assert_eq!(offset(err), Some(4));
+    }
+
+    #[test]
+    fn handles_padding_and_excluded_headers() {
+        // the length of 5 is padded to 8 bytes
+        let padded = LengthField::u16_at(2, 4).padded_to(8);
+        let bytes = [0, 1, 0, 5, 7, 0, 0, 0, 0, 2, 0, 4, 0, 0, 0, 0];
+        let entries: Vec<Entry> = decode_list(&bytes, 0, padded, "Entry").unwrap();
+        assert_eq!(entries, vec![Entry(1), Entry(2)]);
+        // the padding of the last entry is required
+        assert!(decode_list::<Entry>(&bytes[..12], 0, padded, "Entry").is_err());
+
+        // oxm like header of 4 bytes with the payload length in its last byte
+        let oxm = LengthField::u8_at(3, 4);
+        let bytes = [0, 1, 0, 2, 9, 9, 0, 2, 0, 0];
+        let entries: Vec<(usize, &[u8])> = TlvCursor::new(&bytes, 0, oxm, "Entry")
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(entries, vec![(0, &bytes[..6]), (6, &bytes[6..])]);
+    }
+
+    #[test]
+    fn encodes_and_pads() {
+        let bytes = encode_list(vec![vec![1, 2], vec![3]]);
+        assert_eq!(bytes, vec![1, 2, 3]);
+        let mut bytes = bytes;
+        pad_to(&mut bytes, 8);
+        assert_eq!(bytes, vec![1, 2, 3, 0, 0, 0, 0, 0]);
+        pad_to(&mut bytes, 8);
+        assert_eq!(bytes.len(), 8);
+    }
+}
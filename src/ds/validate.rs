@@ -0,0 +1,688 @@
+//! Semantic checks of messages before they are sent.
+//!
+//! Encoding only ensures the wire format, a message can still break rules of
+//! the specification that the switch answers with an error message, often long
+//! after the message was sent. `OfPayload::validate` finds the common ones on
+//! the controller and returns every violation it finds:
+//!
+//! - match fields without their prerequisites (eg. tcp_dst needs ip_proto=6)
+//! - actions that are not allowed where they are used (eg. output to TABLE in a flow)
+//! - non strict deletes and modifies with a priority, which the switch ignores,
+//!   and strict ones without the exact priority of a flow (priority 0 with a match)
+//! - instructions that are repeated, not in the order the switch executes them
+//!   (meter, apply, clear, write, metadata, goto) or go to an earlier table
+//! - buckets that do not fit the group type (eg. indirect groups have exactly one)
+//! - meter bands that can never work as intended
+//! - reserved groups and meters where they can not be used (eg. group actions to ALL)
+//!
+//! Only flow mods, packet outs, group mods and meter mods are checked, see
+//! `ControllerConfig::validate_messages` to check every message sent.
+
+use byteorder::{BigEndian, ByteOrder};
+use std::fmt;
+
+use super::actions::{ActionHeader, ActionPayload, ActionType};
+use super::flow_instructions::{InstructionHeader, InstructionPayload, InstructionType};
+use super::flow_match::{EtherType, IpProto, Match, MatchPayload, VID_PRESENT};
+use super::flow_mod::{FlowMod, FlowModCommand};
+use super::flow_parser::field_name;
+use super::group_mod::{GroupMod, GroupModCommand, GroupNo, GroupNumber, GroupType, GROUP_ANY};
+use super::meter_mod::{MeterBandPayload, MeterFlags, MeterId, MeterMod, MeterModCommand, MeterNo};
+use super::packet_out::PacketOut;
+use super::ports::{PortNo, PortNumber};
+use super::OfPayload;
+use num_traits::ToPrimitive;
+
+/// A rule of the specification a message breaks.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// the match field needs the prerequisite to be matched exactly
+    MissingPrerequisite {
+        field: &'static str,
+        prerequisite: &'static str,
+    },
+    /// the action can not be used in the place it is used in
+    ActionNotAllowed {
+        action: ActionType,
+        reason: &'static str,
+    },
+    /// non strict deletes and modifies select flows of every priority,
+    /// their priority has to be 0
+    PriorityIgnored(u16),
+    /// strict deletes and modifies only select the flow with exactly their
+    /// priority, priority 0 with a match is missing the priority of the flow
+    /// (0 is the priority of the table-miss flow, which matches every packet)
+    MissingPriority,
+    /// a flow has at most one instruction of each type
+    DuplicateInstruction(InstructionType),
+    /// the instruction is listed after one that the switch executes later
+    InstructionOrder {
+        instruction: InstructionType,
+        after: InstructionType,
+    },
+    /// goto table instructions can only go to a table with a higher id
+    GotoEarlierTable { table_id: u8, goto: u8 },
+    /// the group or one of its buckets (by index) does not fit the group type
+    InvalidGroup {
+        bucket: Option<usize>,
+        reason: &'static str,
+    },
+    /// the meter or one of its bands (by index) can not work as intended
+    InvalidMeter {
+        band: Option<usize>,
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Violation::MissingPrerequisite {
+                field,
+                prerequisite,
+            } => write!(f, "match field '{}' requires {}", field, prerequisite),
+            Violation::ActionNotAllowed { ref action, reason } => {
+                write!(f, "action '{:?}' {}", action, reason)
+            }
+            Violation::PriorityIgnored(priority) => write!(
+                f,
+                "priority '{}' is ignored by non strict commands",
+                priority
+            ),
+            Violation::MissingPriority => write!(
+                f,
+                "strict commands need the exact priority of the flow they select"
+            ),
+            Violation::DuplicateInstruction(ref instruction) => {
+                write!(f, "instruction '{:?}' is used more than once", instruction)
+            }
+            Violation::InstructionOrder {
+                ref instruction,
+                ref after,
+            } => write!(
+                f,
+                "instruction '{:?}' has to come before '{:?}'",
+                instruction, after
+            ),
+            Violation::GotoEarlierTable { table_id, goto } => write!(
+                f,
+                "goto table '{}' in table '{}' does not go to a later table",
+                goto, table_id
+            ),
+            Violation::InvalidGroup {
+                bucket: Some(bucket),
+                reason,
+            } => write!(f, "bucket '{}': {}", bucket, reason),
+            Violation::InvalidGroup {
+                bucket: None,
+                reason,
+            } => write!(f, "group: {}", reason),
+            Violation::InvalidMeter {
+                band: Some(band),
+                reason,
+            } => write!(f, "band '{}': {}", band, reason),
+            Violation::InvalidMeter { band: None, reason } => write!(f, "meter: {}", reason),
+        }
+    }
+}
+
+/// checks the payload, empty if no rule is broken
+pub fn validate(payload: &OfPayload) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    match *payload {
+        OfPayload::FlowMod(ref flow_mod) => validate_flow_mod(flow_mod, &mut violations),
+        OfPayload::PacketOut(ref packet_out) => validate_packet_out(packet_out, &mut violations),
+        OfPayload::GroupMod(ref group_mod) => validate_group_mod(group_mod, &mut violations),
+        OfPayload::MeterMod(ref meter_mod) => validate_meter_mod(meter_mod, &mut violations),
+        _ => (),
+    }
+    violations
+}
+
+fn validate_flow_mod(flow_mod: &FlowMod, violations: &mut Vec<Violation>) {
+    validate_match(&flow_mod.mmatch, violations);
+    match flow_mod.command {
+        FlowModCommand::Modify | FlowModCommand::Delete if flow_mod.priority != 0 => {
+            violations.push(Violation::PriorityIgnored(flow_mod.priority))
+        }
+        FlowModCommand::ModifyStrict | FlowModCommand::DeleteStrict
+            if flow_mod.priority == 0 && !flow_mod.mmatch.matches().is_empty() =>
+        {
+            violations.push(Violation::MissingPriority)
+        }
+        _ => (),
+    }
+    validate_instructions(flow_mod.table_id, &flow_mod.instructions, violations);
+    for instruction in &flow_mod.instructions {
+        match *instruction.payload() {
+            InstructionPayload::ApplyActions(ref payload) => {
+                validate_actions(&payload.actions, Place::Flow, violations)
+            }
+            InstructionPayload::WriteActions(ref payload) => {
+                validate_actions(&payload.actions, Place::Flow, violations)
+            }
+            InstructionPayload::Meter(ref payload)
+                if payload.meter_id == MeterId::Reserved(MeterNo::All) =>
+            {
+                violations.push(Violation::InvalidMeter {
+                    band: None,
+                    reason: "flows can not use meter ALL",
+                })
+            }
+            _ => (),
+        }
+    }
+}
+
+/// position of the instruction type in the order the switch executes them,
+/// None for experimenter instructions
+pub(crate) fn instruction_rank(ttype: &InstructionType) -> Option<usize> {
+    match *ttype {
+        InstructionType::Meter => Some(0),
+        InstructionType::ApplyActions => Some(1),
+        InstructionType::Clearactions => Some(2),
+        InstructionType::WriteActions => Some(3),
+        InstructionType::WriteMetadata => Some(4),
+        InstructionType::GotoTable => Some(5),
+        InstructionType::Experimenter => None,
+    }
+}
+
+/// instructions of a flow in the given table
+pub(crate) fn validate_instructions(
+    table_id: u8,
+    instructions: &[InstructionHeader],
+    violations: &mut Vec<Violation>,
+) {
+    // the instruction executed last so far
+    let mut latest: Option<(usize, &InstructionType)> = None;
+    let mut seen = [false; 6];
+    for instruction in instructions {
+        let ttype = instruction.ttype();
+        let rank = match instruction_rank(ttype) {
+            Some(rank) => rank,
+            None => continue,
+        };
+        match latest {
+            _ if seen[rank] => {
+                violations.push(Violation::DuplicateInstruction(ttype.clone()));
+            }
+            Some((latest_rank, after)) if latest_rank > rank => {
+                violations.push(Violation::InstructionOrder {
+                    instruction: ttype.clone(),
+                    after: after.clone(),
+                });
+            }
+            _ => latest = Some((rank, ttype)),
+        }
+        seen[rank] = true;
+        if let InstructionPayload::GotoTable(ref goto) = *instruction.payload() {
+            if goto.table_id <= table_id {
+                violations.push(Violation::GotoEarlierTable {
+                    table_id: table_id,
+                    goto: goto.table_id,
+                });
+            }
+        }
+    }
+}
+
+fn validate_packet_out(packet_out: &PacketOut, violations: &mut Vec<Violation>) {
+    validate_actions(&packet_out.actions, Place::PacketOut, violations);
+}
+
+fn validate_group_mod(group_mod: &GroupMod, violations: &mut Vec<Violation>) {
+    let reason = match *group_mod.group_id() {
+        GroupNumber::Reserved(GroupNo::Any) => Some("group ANY can not be modified"),
+        GroupNumber::Reserved(GroupNo::All) if *group_mod.command() != GroupModCommand::Delete => {
+            Some("group ALL can only be deleted")
+        }
+        _ => None,
+    };
+    if let Some(reason) = reason {
+        violations.push(Violation::InvalidGroup {
+            bucket: None,
+            reason: reason,
+        });
+    }
+    if *group_mod.command() == GroupModCommand::Delete {
+        return;
+    }
+    let ttype = group_mod.ttype();
+    let buckets = group_mod.buckets();
+    if *ttype == GroupType::Indirect && buckets.len() != 1 {
+        violations.push(Violation::InvalidGroup {
+            bucket: None,
+            reason: "indirect groups have exactly one bucket",
+        });
+    }
+    for (index, bucket) in buckets.iter().enumerate() {
+        let reason = match *ttype {
+            GroupType::Select if *bucket.weight() == 0 => {
+                Some("buckets of select groups with weight 0 are never used")
+            }
+            GroupType::Select => None,
+            _ if *bucket.weight() != 0 => Some("only select groups use the weight"),
+            _ if *bucket.watch_group() == GroupNumber::Reserved(GroupNo::All) => {
+                Some("buckets can not watch group ALL")
+            }
+            GroupType::Ff
+                if *bucket.watch_port() == PortNumber::Reserved(PortNo::Any)
+                    && *bucket.watch_group() == GROUP_ANY =>
+            {
+                Some("buckets of fast failover groups watch a port or a group")
+            }
+            _ => None,
+        };
+        if let Some(reason) = reason {
+            violations.push(Violation::InvalidGroup {
+                bucket: Some(index),
+                reason: reason,
+            });
+        }
+        validate_actions(bucket.actions(), Place::Bucket, violations);
+    }
+}
+
+fn validate_meter_mod(meter_mod: &MeterMod, violations: &mut Vec<Violation>) {
+    let reason = match meter_mod.meter_id {
+        MeterId::Reserved(MeterNo::All) if meter_mod.command != MeterModCommand::Delete => {
+            Some("meter ALL can only be deleted")
+        }
+        // the switch has them from the start
+        MeterId::Reserved(MeterNo::Slowpath) | MeterId::Reserved(MeterNo::Controller)
+            if meter_mod.command != MeterModCommand::Modify =>
+        {
+            Some("virtual meters can only be modified")
+        }
+        _ => None,
+    };
+    if let Some(reason) = reason {
+        violations.push(Violation::InvalidMeter {
+            band: None,
+            reason: reason,
+        });
+    }
+    if meter_mod.command == MeterModCommand::Delete {
+        return;
+    }
+    if meter_mod
+        .flags
+        .contains(MeterFlags::KBPS | MeterFlags::PKTPS)
+    {
+        violations.push(Violation::InvalidMeter {
+            band: None,
+            reason: "rates are either in kb/s or in packets/s",
+        });
+    }
+    for (index, band) in meter_mod.bands.iter().enumerate() {
+        let reason = if *band.rate() == 0 {
+            Some("rate 0 applies the band to every packet")
+        } else if *band.burst_size() != 0 && !meter_mod.flags.contains(MeterFlags::BURST) {
+            Some("burst size is ignored without the BURST flag")
+        } else {
+            match *band.payload() {
+                MeterBandPayload::Remark(ref remark) if remark.prec_level == 0 => {
+                    Some("remark band with precedence level 0 changes nothing")
+                }
+                _ => None,
+            }
+        };
+        if let Some(reason) = reason {
+            violations.push(Violation::InvalidMeter {
+                band: Some(index),
+                reason: reason,
+            });
+        }
+    }
+}
+
+/// where a list of actions is used
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Place {
+    Flow,
+    PacketOut,
+    Bucket,
+}
+
+fn validate_actions(actions: &[ActionHeader], place: Place, violations: &mut Vec<Violation>) {
+    for action in actions {
+        let reason = match *action.payload() {
+            ActionPayload::Output(ref output) => match output.port {
+                PortNumber::Reserved(PortNo::Any) => Some("can not output to port ANY"),
+                PortNumber::Reserved(PortNo::Table) if place != Place::PacketOut => {
+                    Some("can only output to TABLE in packet outs")
+                }
+                _ => None,
+            },
+            ActionPayload::Group(ref group) if group.group_id.is_reserved() => {
+                Some("can not output to group ALL or ANY")
+            }
+            ActionPayload::SetField(ref set_field) => match *set_field.field.payload() {
+                MatchPayload::InPort(_) | MatchPayload::InPhyPort(_) => {
+                    Some("can not set the input port")
+                }
+                MatchPayload::Metadata(_) => {
+                    Some("can not set the metadata, use the write metadata instruction")
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(reason) = reason {
+            violations.push(Violation::ActionNotAllowed {
+                action: action.ttype().clone(),
+                reason: reason,
+            });
+        }
+    }
+}
+
+/// fields of a match the prerequisites of other fields depend on,
+/// only exact (unmasked) values count
+#[derive(Debug, Default)]
+struct Known {
+    eth_type: Option<u16>,
+    ip_proto: Option<u8>,
+    icmpv6_type: Option<u8>,
+    in_port: bool,
+    vlan_present: bool,
+}
+
+fn validate_match(mmatch: &Match, violations: &mut Vec<Violation>) {
+    let mut known = Known::default();
+    for field in mmatch.matches() {
+        let exact = field.mask().is_none();
+        match *field.payload() {
+            MatchPayload::InPort(_) => known.in_port = true,
+            MatchPayload::EthType(ref p) if exact => known.eth_type = p.ttype.to_u16(),
+            MatchPayload::IpProto(ref p) if exact => known.ip_proto = p.ip_proto.to_u8(),
+            MatchPayload::IcmpV6Type(ref p) if exact => known.icmpv6_type = p.ttype.to_u8(),
+            // a masked vid still requires a tag if the mask covers the present bit
+            MatchPayload::VlanVId(ref p) => {
+                let mask = match *field.mask() {
+                    Some(ref mask) if mask.len() == 2 => BigEndian::read_u16(mask),
+                    Some(_) => 0,
+                    None => 0xffff,
+                };
+                known.vlan_present = p.vlan_id & mask & VID_PRESENT != 0
+            }
+            _ => (),
+        }
+    }
+
+    let eth_type = |eth_type: EtherType| known.eth_type == eth_type.to_u16();
+    let ip = eth_type(EtherType::IPv4) || eth_type(EtherType::IPv6);
+    let ip_proto = |ip_proto: IpProto| known.ip_proto == ip_proto.to_u8();
+    for field in mmatch.matches() {
+        let (satisfied, prerequisite) = match *field.payload() {
+            MatchPayload::InPhyPort(_) => (known.in_port, "in_port"),
+            MatchPayload::VlanPcp(_) => (known.vlan_present, "a vlan_vid with a tag present"),
+            MatchPayload::IpDscp(_) | MatchPayload::IpEcn(_) | MatchPayload::IpProto(_) => {
+                (ip, "eth_type=0x0800 or eth_type=0x86dd")
+            }
+            MatchPayload::IPv4Src(_) | MatchPayload::IPv4Dst(_) => {
+                (eth_type(EtherType::IPv4), "eth_type=0x0800")
+            }
+            MatchPayload::TcpSrc(_) | MatchPayload::TcpDst(_) => {
+                (ip && ip_proto(IpProto::Tcp), "ip with ip_proto=6")
+            }
+            MatchPayload::UdpSrc(_) | MatchPayload::UdpDst(_) => {
+                (ip && ip_proto(IpProto::Udp), "ip with ip_proto=17")
+            }
+            MatchPayload::SctpSrc(_) | MatchPayload::SctpDst(_) => {
+                (ip && ip_proto(IpProto::Sctp), "ip with ip_proto=132")
+            }
+            MatchPayload::IcmpV4TYype(_) | MatchPayload::IcmpV4Code(_) => (
+                eth_type(EtherType::IPv4) && ip_proto(IpProto::Icmp),
+                "eth_type=0x0800 with ip_proto=1",
+            ),
+            MatchPayload::ArpOp(_)
+            | MatchPayload::ArpSpa(_)
+            | MatchPayload::ArpTpa(_)
+            | MatchPayload::ArpSha(_)
+            | MatchPayload::ArpTha(_) => (eth_type(EtherType::Arp), "eth_type=0x0806"),
+            MatchPayload::IPv6Src(_)
+            | MatchPayload::IPv6Dst(_)
+            | MatchPayload::IPv6FLabel(_)
+            | MatchPayload::IPv6ExtHdr(_) => (eth_type(EtherType::IPv6), "eth_type=0x86dd"),
+            MatchPayload::IcmpV6Type(_) | MatchPayload::IcmpV6Code(_) => (
+                eth_type(EtherType::IPv6) && ip_proto(IpProto::IPv6Icmp),
+                "eth_type=0x86dd with ip_proto=58",
+            ),
+            MatchPayload::IPv6NdTarget(_) => (
+                known.icmpv6_type == Some(135) || known.icmpv6_type == Some(136),
+                "icmpv6_type=135 or icmpv6_type=136",
+            ),
+            MatchPayload::IPv6NdSll(_) => (known.icmpv6_type == Some(135), "icmpv6_type=135"),
+            MatchPayload::IPv6NdTll(_) => (known.icmpv6_type == Some(136), "icmpv6_type=136"),
+            MatchPayload::MplsLabel(_) | MatchPayload::MplsTc(_) | MatchPayload::MplsBos(_) => (
+                eth_type(EtherType::MplsUnicast) || eth_type(EtherType::MplsMulticast),
+                "eth_type=0x8847 or eth_type=0x8848",
+            ),
+            MatchPayload::PbbISid(_) => (known.eth_type == Some(0x88e7), "eth_type=0x88e7"),
+            _ => (true, ""),
+        };
+        if !satisfied {
+            violations.push(Violation::MissingPrerequisite {
+                field: field_name(field.payload()),
+                prerequisite: prerequisite,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::flow_mod::DEFAULT_PRIORITY;
+    use super::super::flow_parser::{parse_actions, parse_flow_mod};
+    use super::super::group_mod::Bucket;
+    use super::super::meter_mod::{MeterBandDrop, MeterBandHeader, MeterBandRemark};
+    use super::*;
+
+    fn flow_mod(flow: &str) -> Vec<Violation> {
+        validate(&OfPayload::FlowMod(parse_flow_mod(flow).unwrap()))
+    }
+
+    #[test]
+    fn flow_mods_need_prerequisites() {
+        assert!(flow_mod("ip,nw_dst=10.0.0.1,tcp_dst=80,actions=output:1").len() == 1);
+        assert!(flow_mod("tcp,tcp_dst=80,actions=output:1").is_empty());
+        assert!(flow_mod("icmp6,icmpv6_type=135,nd_target=::1,actions=drop").is_empty());
+        assert_eq!(
+            flow_mod("arp_tpa=10.0.0.1,actions=output:table"),
+            vec![
+                Violation::MissingPrerequisite {
+                    field: "arp_tpa",
+                    prerequisite: "eth_type=0x0806",
+                },
+                Violation::ActionNotAllowed {
+                    action: ActionType::Output,
+                    reason: "can only output to TABLE in packet outs",
+                },
+            ]
+        );
+        // the mask of the vid has to cover the present bit
+        assert!(flow_mod("vlan_vid=any,vlan_pcp=3,actions=drop").is_empty());
+        assert_eq!(
+            flow_mod("vlan_vid=0x1005/0x0fff,vlan_pcp=3,actions=drop"),
+            vec![Violation::MissingPrerequisite {
+                field: "vlan_pcp",
+                prerequisite: "a vlan_vid with a tag present",
+            }]
+        );
+        let mut delete = parse_flow_mod("priority=100,tcp,actions=drop").unwrap();
+        delete.command = FlowModCommand::Delete;
+        assert_eq!(
+            validate(&OfPayload::FlowMod(delete.clone())),
+            vec![Violation::PriorityIgnored(100)]
+        );
+        // the default priority is a priority as well
+        delete.priority = DEFAULT_PRIORITY;
+        assert_eq!(
+            validate(&OfPayload::FlowMod(delete.clone())),
+            vec![Violation::PriorityIgnored(DEFAULT_PRIORITY)]
+        );
+        delete.priority = 0;
+        assert!(validate(&OfPayload::FlowMod(delete.clone())).is_empty());
+        delete.command = FlowModCommand::DeleteStrict;
+        assert_eq!(
+            validate(&OfPayload::FlowMod(delete.clone())),
+            vec![Violation::MissingPriority]
+        );
+        delete.priority = 100;
+        assert!(validate(&OfPayload::FlowMod(delete.clone())).is_empty());
+        // the table-miss flow
+        delete.priority = 0;
+        delete.mmatch = Match::new(Vec::new());
+        assert!(validate(&OfPayload::FlowMod(delete)).is_empty());
+
+        let packet_out = PacketOut::new(
+            super::super::packet_out::BufferId::NoBuffer,
+            PortNo::Controller.into(),
+            parse_actions("output:table").unwrap(),
+            vec![0; 60],
+        );
+        assert!(validate(&OfPayload::PacketOut(packet_out)).is_empty());
+    }
+
+    #[test]
+    fn instructions_are_unique_and_ordered() {
+        assert!(flow_mod("table=1,actions=meter:1,output:1,clear_actions,goto_table:2").is_empty());
+        let mut flow = parse_flow_mod("table=1,actions=output:1,goto_table:1").unwrap();
+        assert_eq!(
+            validate(&OfPayload::FlowMod(flow.clone())),
+            vec![Violation::GotoEarlierTable {
+                table_id: 1,
+                goto: 1,
+            }]
+        );
+
+        let goto = flow.instructions.pop().unwrap();
+        let apply = flow.instructions[0].clone();
+        flow.instructions = vec![goto, apply.clone(), apply];
+        assert_eq!(
+            validate(&OfPayload::FlowMod(flow)),
+            vec![
+                Violation::GotoEarlierTable {
+                    table_id: 1,
+                    goto: 1,
+                },
+                Violation::InstructionOrder {
+                    instruction: InstructionType::ApplyActions,
+                    after: InstructionType::GotoTable,
+                },
+                Violation::DuplicateInstruction(InstructionType::ApplyActions),
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_and_meters_fit_their_type() {
+        let output = parse_actions("output:1").unwrap();
+        let bucket = |weight| Bucket::new(weight, PortNo::Any.into(), GROUP_ANY, output.clone());
+        let indirect = GroupMod::new(
+            GroupModCommand::Add,
+            GroupType::Indirect,
+            GroupNumber::Group(1),
+            vec![bucket(0), bucket(1)],
+        );
+        let violations = validate(&OfPayload::GroupMod(indirect));
+        assert_eq!(violations.len(), 2);
+        assert_eq!(
+            violations[0].to_string(),
+            "group: indirect groups have exactly one bucket"
+        );
+        let ff = GroupMod::new(
+            GroupModCommand::Add,
+            GroupType::Ff,
+            GroupNumber::Group(1),
+            vec![bucket(0)],
+        );
+        assert_eq!(validate(&OfPayload::GroupMod(ff)).len(), 1);
+        let select = GroupMod::new(
+            GroupModCommand::Add,
+            GroupType::Select,
+            GroupNumber::Group(1),
+            vec![bucket(1)],
+        );
+        assert!(validate(&OfPayload::GroupMod(select)).is_empty());
+
+        let all = |command| GroupMod::new(command, GroupType::Select, GroupNo::All.into(), vec![]);
+        assert!(validate(&OfPayload::GroupMod(all(GroupModCommand::Delete))).is_empty());
+        assert_eq!(
+            validate(&OfPayload::GroupMod(all(GroupModCommand::Add)))[0].to_string(),
+            "group: group ALL can only be deleted"
+        );
+        let watch_all = Bucket::new(0, PortNo::Any.into(), GroupNo::All.into(), output.clone());
+        let ff = GroupMod::new(
+            GroupModCommand::Add,
+            GroupType::Ff,
+            GroupNumber::Group(2),
+            vec![watch_all],
+        );
+        assert_eq!(
+            validate(&OfPayload::GroupMod(ff)),
+            vec![Violation::InvalidGroup {
+                bucket: Some(0),
+                reason: "buckets can not watch group ALL",
+            }]
+        );
+        assert_eq!(
+            flow_mod("actions=group:all"),
+            vec![Violation::ActionNotAllowed {
+                action: ActionType::Group,
+                reason: "can not output to group ALL or ANY",
+            }]
+        );
+        assert!(flow_mod("out_group=any,actions=group:1").is_empty());
+
+        let meter = MeterMod {
+            command: MeterModCommand::Add,
+            flags: MeterFlags::KBPS,
+            meter_id: MeterId::Meter(1),
+            bands: vec![
+                MeterBandHeader::new(1000, 100, MeterBandPayload::Drop(MeterBandDrop {})),
+                MeterBandHeader::new(
+                    500,
+                    0,
+                    MeterBandPayload::Remark(MeterBandRemark { prec_level: 0 }),
+                ),
+            ],
+        };
+        let violations = validate(&OfPayload::MeterMod(meter));
+        assert_eq!(
+            violations,
+            vec![
+                Violation::InvalidMeter {
+                    band: Some(0),
+                    reason: "burst size is ignored without the BURST flag",
+                },
+                Violation::InvalidMeter {
+                    band: Some(1),
+                    reason: "remark band with precedence level 0 changes nothing",
+                },
+            ]
+        );
+
+        let reserved = |command, meter: MeterNo| MeterMod {
+            command: command,
+            flags: MeterFlags::KBPS,
+            meter_id: meter.into(),
+            bands: vec![],
+        };
+        let violations = |meter_mod| validate(&OfPayload::MeterMod(meter_mod));
+        assert!(violations(reserved(MeterModCommand::Delete, MeterNo::All)).is_empty());
+        assert!(violations(reserved(MeterModCommand::Modify, MeterNo::Controller)).is_empty());
+        assert_eq!(
+            violations(reserved(MeterModCommand::Add, MeterNo::Slowpath))[0].to_string(),
+            "meter: virtual meters can only be modified"
+        );
+        assert_eq!(
+            violations(reserved(MeterModCommand::Modify, MeterNo::All))[0].to_string(),
+            "meter: meter ALL can only be deleted"
+        );
+        assert_eq!(flow_mod("actions=meter:all,output:1").len(), 1);
+        assert!(flow_mod("actions=meter:controller,output:1").is_empty());
+    }
+}
@@ -0,0 +1,267 @@
+//! Fields of the fixed-layout structs that derive `OfWire`.
+//!
+//! Most messages are structs whose fields are read and written in order with
+//! padding in between. `#[derive(OfWire)]` (see the oath2-derive crate)
+//! generates `OfEncode`, `TryFrom<&[u8]>` and `Into<Vec<u8>>` of such a struct
+//! from its declaration, so encoding and decoding can not disagree about the
+//! layout:
+//!
+//! ```ignore
+//! #[derive(OfWire, Debug, PartialEq, Clone)]
+//! pub struct Role {
+//!     #[pad(4)]
+//!     pub role: ControllerRole,
+//!     pub generation_id: u64,
+//! }
+//! ```
+//!
+//! Every field has a type that implements `OfField`, the attributes are:
+//!
+//! * `#[pad(n)]`: n zero bytes after the field, skipped when decoding
+//! * `#[len_of(items)]`: the field holds the encoded length of the list
+//!   `items`, a `Vec` of `OfField` entries after it; the length is taken from
+//!   the list when encoding
+//! * `#[tlv(LENGTH_FIELD)]`: the last field is a list of entries with their
+//!   own length, read with the given `tlv::LengthField`
+//! * `#[big_endian]`, `#[little_endian]`: byte order of the struct or of a
+//!   single field, network byte order (big endian) by default
+
+use byteorder::{ByteOrder, ReadBytesExt, WriteBytesExt};
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::convert::TryFrom;
+use std::io::{Cursor, Seek, SeekFrom};
+
+use super::super::err::*;
+use super::addr::{MacAddr, MAC_ADDR_LEN};
+use super::async::{FlowRemovedReasonMask, PacketInReasonMask, PortReasonMask};
+use super::checked_slice;
+use super::features::Capabilities;
+use super::ports::{PortConfig, PortFeatures, PortNumber};
+use super::role::ControllerRole;
+use super::switch_config::ConfigFlags;
+use super::table_mod::TableConfig;
+
+/// A field of a struct that derives `OfWire`, see the module documentation.
+pub trait OfField: Sized {
+    /// number of bytes write_field appends
+    fn wire_len(&self) -> usize;
+
+    fn write_field<B: ByteOrder>(&self, buf: &mut Vec<u8>);
+
+    /// reads the field at the position of the cursor
+    fn read_field<B: ByteOrder>(cursor: &mut Cursor<&[u8]>) -> Result<Self>;
+}
+
+impl OfField for u8 {
+    fn wire_len(&self) -> usize {
+        1
+    }
+
+    fn write_field<B: ByteOrder>(&self, buf: &mut Vec<u8>) {
+        buf.push(*self);
+    }
+
+    fn read_field<B: ByteOrder>(cursor: &mut Cursor<&[u8]>) -> Result<Self> {
+        Ok(cursor.read_u8()?)
+    }
+}
+
+macro_rules! wire_int {
+    ($($int:ty, $len:expr, $write:ident, $read:ident;)*) => {
+        $(
+            impl OfField for $int {
+                fn wire_len(&self) -> usize {
+                    $len
+                }
+
+                fn write_field<B: ByteOrder>(&self, buf: &mut Vec<u8>) {
+                    buf.$write::<B>(*self).unwrap();
+                }
+
+                fn read_field<B: ByteOrder>(cursor: &mut Cursor<&[u8]>) -> Result<Self> {
+                    Ok(cursor.$read::<B>()?)
+                }
+            }
+        )*
+    };
+}
+
+wire_int! {
+    u16, 2, write_u16, read_u16;
+    u32, 4, write_u32, read_u32;
+    u64, 8, write_u64, read_u64;
+}
+
+/// bitflags are written as their bits, unknown bits are decoded with the
+/// policy of the thread (see `flags`)
+macro_rules! wire_flags {
+    ($($flags:ident: $bits:ty,)*) => {
+        $(
+            impl OfField for $flags {
+                fn wire_len(&self) -> usize {
+                    self.bits().wire_len()
+                }
+
+                fn write_field<B: ByteOrder>(&self, buf: &mut Vec<u8>) {
+                    self.bits().write_field::<B>(buf);
+                }
+
+                fn read_field<B: ByteOrder>(cursor: &mut Cursor<&[u8]>) -> Result<Self> {
+                    flags_from_bits!($flags, <$bits>::read_field::<B>(cursor)?)
+                }
+            }
+        )*
+    };
+}
+
+wire_flags! {
+    Capabilities: u32,
+    ConfigFlags: u16,
+    FlowRemovedReasonMask: u32,
+    PacketInReasonMask: u32,
+    PortConfig: u32,
+    PortFeatures: u32,
+    PortReasonMask: u32,
+    TableConfig: u32,
+}
+
+/// enums are written as their value, unknown values fail to decode
+macro_rules! wire_enum {
+    ($($enum:ident: $raw:ident, $to_raw:ident, $from_raw:ident,)*) => {
+        $(
+            impl OfField for $enum {
+                fn wire_len(&self) -> usize {
+                    ::std::mem::size_of::<$raw>()
+                }
+
+                fn write_field<B: ByteOrder>(&self, buf: &mut Vec<u8>) {
+                    self.$to_raw().unwrap().write_field::<B>(buf);
+                }
+
+                fn read_field<B: ByteOrder>(cursor: &mut Cursor<&[u8]>) -> Result<Self> {
+                    let raw = $raw::read_field::<B>(cursor)?;
+                    $enum::$from_raw(raw).ok_or_else(|| {
+                        ErrorKind::UnknownValue(raw as u64, stringify!($enum)).into()
+                    })
+                }
+            }
+        )*
+    };
+}
+
+wire_enum! {
+    ControllerRole: u32, to_u32, from_u32,
+}
+
+impl OfField for MacAddr {
+    fn wire_len(&self) -> usize {
+        MAC_ADDR_LEN
+    }
+
+    fn write_field<B: ByteOrder>(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.octets()[..]);
+    }
+
+    fn read_field<B: ByteOrder>(cursor: &mut Cursor<&[u8]>) -> Result<Self> {
+        let pos = cursor.position() as usize;
+        let octets = checked_slice(cursor.get_ref(), pos, MAC_ADDR_LEN, stringify!(MacAddr))?;
+        skip(cursor, MAC_ADDR_LEN);
+        MacAddr::from_slice(octets)
+    }
+}
+
+impl OfField for PortNumber {
+    fn wire_len(&self) -> usize {
+        4
+    }
+
+    fn write_field<B: ByteOrder>(&self, buf: &mut Vec<u8>) {
+        Into::<u32>::into(self.clone()).write_field::<B>(buf);
+    }
+
+    fn read_field<B: ByteOrder>(cursor: &mut Cursor<&[u8]>) -> Result<Self> {
+        PortNumber::try_from(u32::read_field::<B>(cursor)?)
+    }
+}
+
+/// encoded length of the entries of a list
+pub fn list_len<T: OfField>(entries: &[T]) -> usize {
+    entries.iter().map(|entry| entry.wire_len()).sum()
+}
+
+pub fn write_list<B: ByteOrder, T: OfField>(entries: &[T], buf: &mut Vec<u8>) {
+    for entry in entries {
+        entry.write_field::<B>(buf);
+    }
+}
+
+/// reads the entries of the next len bytes
+/// fails if there are less bytes or the last entry does not fit into them
+pub fn read_list<B: ByteOrder, T: OfField>(
+    cursor: &mut Cursor<&[u8]>,
+    len: usize,
+    ttype: &'static str,
+) -> Result<Vec<T>> {
+    let pos = cursor.position() as usize;
+    let bytes = checked_slice(cursor.get_ref(), pos, len, ttype)?;
+    let mut entries_cursor = Cursor::new(bytes);
+    let mut entries = Vec::new();
+    while (entries_cursor.position() as usize) < len {
+        entries.push(T::read_field::<B>(&mut entries_cursor).within(pos)?);
+    }
+    skip(cursor, len);
+    Ok(entries)
+}
+
+/// skips padding, a missing padding at the end of the bytes is not an error
+pub fn skip(cursor: &mut Cursor<&[u8]>, len: usize) {
+    cursor.seek(SeekFrom::Current(len as i64)).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::codec::OfEncode;
+    use super::*;
+    use byteorder::BigEndian;
+
+    #[derive(OfWire, Debug, PartialEq, Clone)]
+    struct Sample {
+        #[pad(1)]
+        kind: u8,
+        #[len_of(ports)]
+        ports_len: u16,
+        #[little_endian]
+        cookie: u32,
+        ports: Vec<PortNumber>,
+        #[pad(2)]
+        hw_addr: MacAddr,
+    }
+
+    #[test]
+    fn derived_codec_round_trips() {
+        let sample = Sample {
+            kind: 7,
+            // stale, the length of the ports is written
+            ports_len: 0,
+            cookie: 0x0102_0304,
+            ports: vec![PortNumber::NormalPort(1), PortNumber::NormalPort(2)],
+            hw_addr: MacAddr([2, 0, 0, 0, 0, 1]),
+        };
+        let bytes = sample.to_bytes();
+        assert_eq!(sample.encoded_len(), bytes.len());
+        assert_eq!(
+            bytes,
+            vec![7, 0, 0, 8, 4, 3, 2, 1, 0, 0, 0, 1, 0, 0, 0, 2, 2, 0, 0, 0, 0, 1, 0, 0]
+        );
+        let decoded = Sample::try_from(&bytes[..]).unwrap();
+        assert_eq!(decoded.ports_len, 8);
+        assert_eq!(decoded.ports, sample.ports);
+        assert_eq!(Into::<Vec<u8>>::into(decoded), bytes);
+
+        // the list is longer than the bytes
+        assert!(Sample::try_from(&bytes[..10]).is_err());
+        let mut bad_len = bytes.clone();
+        BigEndian::write_u16(&mut bad_len[2..4], 6);
+        assert!(Sample::try_from(&bad_len[..]).is_err());
+    }
+}
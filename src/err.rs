@@ -1,40 +1,344 @@
-error_chain!{
-    types{
-        Error, ErrorKind, ResultExt, Result;
+//! Error type of oath2.
+//! Errors consist of an `ErrorKind` telling what went wrong and a `MessageContext`
+//! telling where in which message it went wrong, if it happened while decoding.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+use super::ds::validate::Violation;
+use super::ds::Type;
+
+/// number of bytes of the offending message kept in the error
+pub const EXCERPT_LEN: usize = 16;
+
+pub type Result<T> = result::Result<T, Error>;
+
+macro_rules! bail {
+    ($e:expr) => {
+        return Err($e.into())
+    };
+}
+
+/// what went wrong
+#[derive(Debug)]
+pub enum ErrorKind {
+    Io(io::Error),
+    InvalidSliceLength(usize, usize, &'static str),
+    CouldNotReadLength(usize, &'static str),
+    UnknownValue(u64, &'static str),
+    UnsupportedValue(u64, &'static str),
+    IllegalValue(u64, &'static str),
+    ConnectionClosed(u64),
+    NoMainConnection(u64, u8),
+    RetriesExhausted(u32),
+    InvalidFlowSyntax(String, &'static str),
+    InvalidAddress(String, &'static str),
+    InvalidPort(String),
+    InvalidGroup(String),
+    InvalidMeter(String),
+    ReplyTimeout(u32),
+    UnexpectedReply(u32, Type),
+    UnknownQueue(u32, u32),
+    UnknownPort(u32),
+    UnknownTable(String),
+    InvalidTable(u8, u8),
+    CookiesExhausted(String),
+    MessageTooLong(usize),
+    PacketOutTooLong(usize, usize),
+    ExceedsMtu(usize, u32, u16),
+    UnsupportedBySwitch(u64, String),
+    PriorityBandOverlap(String, String),
+    PriorityBandRedefined(String, u16, u16),
+    UnknownPriorityBand(String),
+    PrioritiesExhausted(String),
+    InvalidMessage(Vec<Violation>),
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::Io(ref err) => write!(f, "{}", err),
+            ErrorKind::InvalidSliceLength(expected, actual, ttype) => write!(
+                f,
+                "Expected '{}' but got '{}' bytes for '{}'.",
+                expected, actual, ttype
+            ),
+            ErrorKind::CouldNotReadLength(access, ttype) => {
+                write!(f, "Could not read length at '{}' of '{}'.", access, ttype)
+            }
+            ErrorKind::UnknownValue(val, ttype) => write!(
+                f,
+                "Encountered unknown value '{}' for type '{}.",
+                val, ttype
+            ),
+            ErrorKind::UnsupportedValue(val, ttype) => write!(
+                f,
+                "Encountered unsupported value '{}' for type '{}.",
+                val, ttype
+            ),
+            ErrorKind::IllegalValue(val, ttype) => write!(
+                f,
+                "Encountered illegal value '{}' for type '{}.",
+                val, ttype
+            ),
+            ErrorKind::ConnectionClosed(datapath_id) => {
+                write!(f, "Connection to switch '{:#x}' is closed.", datapath_id)
+            }
+            ErrorKind::NoMainConnection(datapath_id, auxiliary_id) => write!(
+                f,
+                "Auxiliary connection '{}' of switch '{:#x}' has no main connection.",
+                auxiliary_id, datapath_id
+            ),
+            ErrorKind::RetriesExhausted(attempts) => {
+                write!(f, "Gave up reconnecting after '{}' attempts.", attempts)
+            }
+            ErrorKind::InvalidFlowSyntax(ref token, reason) => {
+                write!(f, "Invalid flow syntax at '{}': {}.", token, reason)
+            }
+            ErrorKind::InvalidAddress(ref addr, ttype) => {
+                write!(f, "Invalid address '{}' for type '{}'.", addr, ttype)
+            }
+            ErrorKind::InvalidPort(ref port) => write!(f, "Invalid port '{}'.", port),
+            ErrorKind::InvalidGroup(ref group) => write!(f, "Invalid group '{}'.", group),
+            ErrorKind::InvalidMeter(ref meter) => write!(f, "Invalid meter '{}'.", meter),
+            ErrorKind::ReplyTimeout(xid) => write!(f, "No reply for xid '{}' in time.", xid),
+            ErrorKind::UnexpectedReply(xid, ref ttype) => {
+                write!(f, "Unexpected reply '{:?}' for xid '{}'.", ttype, xid)
+            }
+            ErrorKind::UnknownQueue(port, queue_id) => {
+                write!(f, "Port '{}' has no queue '{}'.", port, queue_id)
+            }
+            ErrorKind::UnknownPort(port) => write!(f, "Switch has no port '{}'.", port),
+            ErrorKind::UnknownTable(ref name) => write!(f, "No table named '{}'.", name),
+            ErrorKind::InvalidTable(table_id, n_tables) => write!(
+                f,
+                "Table '{}' does not exist on a switch with '{}' tables.",
+                table_id, n_tables
+            ),
+            ErrorKind::CookiesExhausted(ref namespace) => {
+                write!(f, "No cookie namespace left for '{}'.", namespace)
+            }
+            ErrorKind::MessageTooLong(len) => write!(
+                f,
+                "Message of '{}' bytes exceeds the maximum of '{}' bytes.",
+                len,
+                super::ds::MAX_MESSAGE_LENGTH
+            ),
+            ErrorKind::PacketOutTooLong(len, max) => write!(
+                f,
+                "Packet out data of '{}' bytes exceeds the '{}' bytes left by its actions.",
+                len, max
+            ),
+            ErrorKind::ExceedsMtu(len, port, mtu) => write!(
+                f,
+                "Packet of '{}' bytes exceeds the MTU '{}' of port '{}'.",
+                len, mtu, port
+            ),
+            ErrorKind::UnsupportedBySwitch(datapath_id, ref what) => {
+                write!(f, "Switch '{:#x}' does not support {}.", datapath_id, what)
+            }
+            ErrorKind::PriorityBandOverlap(ref name, ref other) => write!(
+                f,
+                "Priority band '{}' overlaps priority band '{}'.",
+                name, other
+            ),
+            ErrorKind::PriorityBandRedefined(ref name, low, high) => write!(
+                f,
+                "Priority band '{}' is already defined with the priorities '{}' to '{}'.",
+                name, low, high
+            ),
+            ErrorKind::UnknownPriorityBand(ref name) => {
+                write!(f, "No priority band named '{}'.", name)
+            }
+            ErrorKind::PrioritiesExhausted(ref name) => {
+                write!(f, "No priority left in band '{}'.", name)
+            }
+            ErrorKind::InvalidMessage(ref violations) => {
+                let violations: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+                write!(
+                    f,
+                    "Message violates the specification: {}.",
+                    violations.join("; ")
+                )
+            }
+        }
+    }
+}
+
+/// where in a message an error occurred
+/// everything is optional, the context is filled in while the error
+/// travels up through the decoders
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MessageContext {
+    pub ttype: Option<Type>,
+    pub xid: Option<u32>,
+    /// offset of the failing read, relative to the bytes of the excerpt
+    pub offset: Option<usize>,
+    /// up to EXCERPT_LEN bytes of the message starting at offset
+    pub excerpt: Vec<u8>,
+}
+
+impl fmt::Display for MessageContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(ref ttype) = self.ttype {
+            parts.push(format!("type: {:?}", ttype));
+        }
+        if let Some(xid) = self.xid {
+            parts.push(format!("xid: {}", xid));
+        }
+        if let Some(offset) = self.offset {
+            parts.push(format!("offset: {}", offset));
+        }
+        if !self.excerpt.is_empty() {
+            parts.push(format!("bytes: {:02x?}", self.excerpt));
+        }
+        write!(f, "{}", parts.join(", "))
     }
+}
 
-    links {
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    context: MessageContext,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Self {
+        Error {
+            kind: kind,
+            context: MessageContext::default(),
+        }
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
 
+    pub fn context(&self) -> &MessageContext {
+        &self.context
     }
 
-    foreign_links{
-        Io(::std::io::Error);
+    /// records the offset of the failure if no decoder further down did
+    pub fn at(mut self, offset: usize) -> Self {
+        if self.context.offset.is_none() {
+            self.context.offset = Some(offset);
+        }
+        self
     }
 
-    errors{
-        InvalidSliceLength(expected: usize, actual: usize, ttype: &'static str) {
-            description("Invalid slice length received."),
-            display("Expected '{}' but got '{}' bytes for '{}'.", expected, actual, ttype),
+    /// moves the offset from a sub slice to the slice it was taken from
+    pub fn within(mut self, start: usize) -> Self {
+        if let Some(offset) = self.context.offset {
+            self.context.offset = Some(start + offset);
         }
+        self
+    }
 
-        CouldNotReadLength(access: usize, ttype: &'static str) {
-            description("Could not read length of a message part."),
-            display("Could not read length at '{}' of '{}'.", access, ttype),
+    /// records the message the failure occurred in
+    pub fn in_message(mut self, ttype: Type, xid: Option<u32>) -> Self {
+        self.context.ttype = Some(ttype);
+        if xid.is_some() {
+            self.context.xid = xid;
         }
+        self
+    }
 
-        UnknownValue(val: u64, ttype: &'static str) {
-            description("Encountered unknown value."),
-            display("Encountered unknown value '{}' for type '{}.", val, ttype),
+    /// keeps the bytes at the offset of the failure
+    /// bytes has to be the slice the offset is relative to
+    pub fn excerpt_from(mut self, bytes: &[u8]) -> Self {
+        let start = match self.context.offset {
+            Some(offset) if offset < bytes.len() => offset,
+            Some(_) => bytes.len(),
+            None => 0,
+        };
+        let end = bytes.len().min(start + EXCERPT_LEN);
+        self.context.excerpt = bytes[start..end].to_vec();
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if self.context != MessageContext::default() {
+            write!(f, " ({})", self.context)?;
         }
+        Ok(())
+    }
+}
 
-        UnsupportedValue(val: u64, ttype: &'static str) {
-            description("Encountered unsupported value."),
-            display("Encountered unsupported value '{}' for type '{}.", val, ttype),
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self.kind {
+            ErrorKind::Io(ref err) => Some(err),
+            _ => None,
         }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error::new(kind)
+    }
+}
 
-        IllegalValue(val: u64, ttype: &'static str) {
-            description("Encountered illegal value."),
-            display("Encountered illegal value '{}' for type '{}.", val, ttype),
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::new(ErrorKind::Io(err))
+    }
+}
+
+/// adds message context to results
+pub trait ResultExt<T> {
+    fn at(self, offset: u64) -> Result<T>;
+    fn within(self, start: usize) -> Result<T>;
+}
+
+impl<T, E> ResultExt<T> for result::Result<T, E>
+where
+    E: Into<Error>,
+{
+    /// records the offset of the failure, usually the position of the cursor
+    fn at(self, offset: u64) -> Result<T> {
+        self.map_err(|err| err.into().at(offset as usize))
+    }
+
+    /// moves the offset from a sub slice to the slice it was taken from
+    fn within(self, start: usize) -> Result<T> {
+        self.map_err(|err| err.into().within(start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ds::{decode_any, Type, Version};
+    use super::*;
+
+    #[test]
+    fn decode_errors_carry_location() {
+        // packet out announcing 8 bytes of actions but ending after its header
+        let mut bytes = vec![0xff, 0xff, 0xff, 0xff, 0, 0, 0, 1, 0, 8];
+        bytes.extend_from_slice(&[0; 6]);
+        let err = decode_any(Version::V1_3, &Type::PacketOut, &bytes).unwrap_err();
+        match *err.kind() {
+            ErrorKind::InvalidSliceLength(24, 16, _) => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
         }
+        assert_eq!(err.context().ttype, Some(Type::PacketOut));
+        assert_eq!(err.context().offset, Some(16));
+    }
+
+    #[test]
+    fn excerpt_starts_at_offset() {
+        let bytes: Vec<u8> = (0..32).collect();
+        let err = Error::from(ErrorKind::IllegalValue(0, "Test"))
+            .at(2)
+            .within(8)
+            .excerpt_from(&bytes);
+        assert_eq!(err.context().offset, Some(10));
+        assert_eq!(err.context().excerpt, (10..26).collect::<Vec<u8>>());
     }
 }
@@ -1,10 +1,30 @@
-#![feature(try_from)]
+//! OpenFlow library.
+//!
+//! `ds` contains the OpenFlow messages and their wire codecs, `err` the errors
+//! of decoding them. Both do not start threads or open connections, so they
+//! can be used in any runtime, but they need std: the codecs read with
+//! `std::io::Cursor`, `ds::capture` reads and writes files and `ds::flags`
+//! keeps the policy of a decode in a thread local. `ctl` is the controller
+//! runtime built on top of them and `apps` contains building blocks for
+//! applications, both are only compiled with the default `controller` feature:
+//!
+//! ```toml
+//! oath2 = { version = "0.1", default-features = false }
+//! ```
+
+// `field: field`, Into impls for the wire format, u32 discriminants of the
+// OpenFlow constants and matches to bool are the style of the crate
+#![allow(
+    clippy::redundant_field_names,
+    clippy::from_over_into,
+    clippy::enum_clike_unportable_variant,
+    clippy::field_reassign_with_default,
+    clippy::match_like_matches_macro
+)]
 
 #[macro_use]
 extern crate getset;
 
-#[macro_use]
-extern crate error_chain;
 #[macro_use]
 extern crate log;
 
@@ -12,11 +32,23 @@ extern crate log;
 extern crate enum_primitive_derive;
 extern crate byteorder;
 extern crate num_traits;
+#[cfg(feature = "controller")]
+extern crate socket2;
 #[macro_use]
 extern crate bitflags;
 #[macro_use]
 extern crate bitfield;
+#[macro_use]
+extern crate oath2_derive;
+
+#[cfg(test)]
+extern crate proptest;
 
+#[macro_use]
+pub mod err;
+
+#[cfg(feature = "controller")]
+pub mod apps;
+#[cfg(feature = "controller")]
 pub mod ctl;
 pub mod ds;
-pub mod err;
@@ -1,39 +1,272 @@
+extern crate clap;
+#[macro_use]
 extern crate log;
 extern crate oath2;
 extern crate simple_logger;
 
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use oath2::ctl;
+use oath2::ctl::switch::IncomingMsg;
 use oath2::ds;
+use oath2::ds::addr::MacAddr;
+use oath2::ds::flow_mod::FlowMod;
+use oath2::ds::flow_parser::parse_flow_mod;
+use oath2::ds::packet_in::PacketIn;
+use oath2::ds::ports::{PortNo, PortNumber};
+
+/// Idle timeout of the flows installed by the learning switch.
+const LEARNED_FLOW_IDLE_TIMEOUT: u16 = 60;
 
 pub fn main() {
-    simple_logger::init().expect("could not init logger");
-    ctl::start_controller("192.168.73.1:6653", |msg| {
-        //handle packet in here
-        //this is a simple hub implementation
-        // get packet in payload from msg
-        if let ds::OfPayload::PacketIn(packet_in) = msg.msg.payload() {
-            let mut actions = Vec::new();
-            //flood action
-            actions.push(Into::<ds::actions::ActionHeader>::into(
-                ds::actions::PayloadOutput {
-                    port: ds::ports::PortNumber::Reserved(ds::ports::PortNo::Flood),
-                    max_len: 0,
-                },
-            ));
-            let payload_packet_out = ds::packet_out::PacketOut::new(
-                packet_in.buffer_id,
-                ds::ports::PortNo::Controller.into(),
-                actions,
-                packet_in.ethernet_frame.clone(),
-            );
-
-            let response = ds::OfMsg::generate(
-                *msg.msg.header().xid(),
-                ds::OfPayload::PacketOut(payload_packet_out),
-            );
-            msg.reply_ch
-                .send(response)
-                .expect("could not send packet_in response");
+    let matches = App::new("oath2")
+        .about("OpenFlow controller")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .value_name("ADDR")
+                .default_value("0.0.0.0:6653")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Address the controller listens on, may be given several times"),
+        )
+        .arg(
+            Arg::with_name("max-version")
+                .long("max-version")
+                .value_name("VERSION")
+                .possible_values(&["1.0", "1.1", "1.2", "1.3", "1.4", "1.5"])
+                .help("Highest OpenFlow version negotiated with switches"),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .value_name("LEVEL")
+                .possible_values(&["error", "warn", "info", "debug", "trace"])
+                .default_value("warn")
+                .help("Messages below this level are not logged"),
+        )
+        .subcommand(
+            SubCommand::with_name("hub").about("Floods every packet sent to the controller"),
+        )
+        .subcommand(
+            SubCommand::with_name("learning-switch")
+                .about("Learns the ports of mac addresses and installs flows to them"),
+        )
+        .subcommand(SubCommand::with_name("dump").about("Prints every message switches send"))
+        .subcommand(
+            SubCommand::with_name("flows")
+                .about("Manages the flows of connecting switches")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("install")
+                        .about("Installs the flows of the file on every switch that connects")
+                        .arg(
+                            Arg::with_name("file")
+                                .required(true)
+                                .help("One flow per line, lines starting with # are ignored"),
+                        ),
+                ),
+        )
+        .get_matches();
+
+    let level = matches
+        .value_of("log-level")
+        .unwrap()
+        .parse()
+        .expect("invalid log level");
+    simple_logger::init_with_level(level).expect("could not init logger");
+
+    let addrs: Vec<&str> = matches.values_of("listen").unwrap().collect();
+    let config = ctl::ControllerConfig {
+        max_version: matches.value_of("max-version").map(parse_version),
+        ..Default::default()
+    };
+
+    let result = match matches.subcommand() {
+        ("hub", _) => ctl::start_controller_on(&addrs, config, hub),
+        ("learning-switch", _) => {
+            let table: MacTable = Mutex::new(HashMap::new());
+            let handler = ctl::with_state(Arc::new(table), learning_switch);
+            ctl::start_controller_on(&addrs, config, handler)
+        }
+        ("dump", _) => ctl::start_controller_on(&addrs, config, dump),
+        ("flows", Some(flows)) => match flows.subcommand() {
+            ("install", Some(install)) => install_flows(&addrs, config, install),
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    };
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn parse_version(version: &str) -> ds::Version {
+    match version {
+        "1.0" => ds::Version::V1_0,
+        "1.1" => ds::Version::V1_1,
+        "1.2" => ds::Version::V1_2,
+        "1.3" => ds::Version::V1_3,
+        "1.4" => ds::Version::V1_4,
+        _ => ds::Version::V1_5,
+    }
+}
+
+/// floods every packet in
+fn hub(msg: IncomingMsg) {
+    if let ds::OfPayload::PacketIn(ref packet_in) = *msg.msg.payload() {
+        flood(&ctl::SwitchContext::new(&msg), *msg.msg.header().xid(), packet_in);
+    }
+}
+
+fn flood_actions() -> Vec<ds::actions::ActionHeader> {
+    vec![ds::actions::PayloadOutput {
+        port: PortNumber::Reserved(PortNo::Flood),
+        max_len: 0,
+    }
+    .into()]
+}
+
+fn flood(ctx: &ctl::SwitchContext, xid: u32, packet_in: &PacketIn) {
+    // the handle knows if the switch buffered the packet, it is missing
+    // if the packet in arrived before the features reply
+    if let Some(ref switch) = ctx.switch {
+        if let Err(err) = switch.reply_packet_out(packet_in, flood_actions()) {
+            error!("Could not flood packet: {}", err);
+        }
+        return;
+    }
+    let packet_out = ds::packet_out::PacketOut::new(
+        packet_in.buffer_id,
+        PortNo::Controller.into(),
+        flood_actions(),
+        packet_in.ethernet_frame.clone(),
+    );
+    let response = ds::OfMsg::generate(xid, ds::OfPayload::PacketOut(packet_out));
+    if ctx.reply_ch.send(response).is_err() {
+        error!("Could not flood packet, the connection is closed.");
+    }
+}
+
+/// ports of the learned addresses per datapath
+type MacTable = Mutex<HashMap<(u64, MacAddr), PortNumber>>;
+
+/// learns the port of the source address of every packet in, packets to known
+/// addresses are sent with a flow to the destination, all others are flooded
+fn learning_switch(table: &MacTable, ctx: &ctl::SwitchContext, msg: ds::OfMsg) {
+    let packet_in = match *msg.payload() {
+        ds::OfPayload::PacketIn(ref packet_in) => packet_in,
+        _ => return,
+    };
+    let switch = match ctx.switch {
+        Some(ref switch) => switch.clone(),
+        None => return flood(ctx, *msg.header().xid(), packet_in),
+    };
+    let frame = &packet_in.ethernet_frame;
+    // the ethernet header starts with the destination and source address
+    if frame.len() < 12 {
+        return;
+    }
+    let dst = MacAddr::from_slice(&frame[0..6]).unwrap();
+    let src = MacAddr::from_slice(&frame[6..12]).unwrap();
+    let in_port = packet_in.in_port();
+
+    let out_port = {
+        let mut table = table.lock().unwrap();
+        if let Some(in_port) = in_port {
+            table.insert((switch.datapath_id(), src), in_port);
+        }
+        table.get(&(switch.datapath_id(), dst)).cloned()
+    };
+    let sent = match out_port {
+        Some(out_port) => parse_flow_mod(&format!(
+            "priority=1,idle_timeout={},eth_dst={},actions=output:{}",
+            LEARNED_FLOW_IDLE_TIMEOUT, dst, out_port
+        ))
+        .and_then(|flow_mod| switch.reply_flow_mod(packet_in, flow_mod)),
+        None => switch.reply_packet_out(packet_in, flood_actions()),
+    };
+    if let Err(err) = sent {
+        error!("Could not forward packet to {}: {}", dst, err);
+    }
+}
+
+/// prints every message passed to the handler
+fn dump(msg: IncomingMsg) {
+    match msg.switch {
+        Some(ref switch) => println!("{:#x} {:?}", switch.datapath_id(), msg.msg),
+        None => println!("- {:?}", msg.msg),
+    }
+}
+
+/// installs the flows of the file whenever a switch (re)connects
+fn install_flows(
+    addrs: &[&str],
+    mut config: ctl::ControllerConfig,
+    args: &ArgMatches,
+) -> oath2::err::Result<()> {
+    let path = args.value_of("file").unwrap();
+    let flows = match read_flows(path) {
+        Ok(flows) => flows,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    };
+    info!(
+        "Installing {} flows from {} on connecting switches.",
+        flows.len(),
+        path
+    );
+    config.on_connect = Some(Arc::new(move |switch: &Arc<ctl::SwitchHandle>| {
+        let switch = switch.clone();
+        let flows = flows.clone();
+        // install_flows waits for barrier replies, which are read by the
+        // thread that runs this callback
+        thread::spawn(move || match switch.install_flows(flows) {
+            Ok(report) => {
+                for (index, error) in report.errors.iter() {
+                    eprintln!(
+                        "{:#x}: flow {} failed: {:?}",
+                        switch.datapath_id(),
+                        index + 1,
+                        error
+                    );
+                }
+                println!(
+                    "{:#x}: installed {} of {} flows",
+                    switch.datapath_id(),
+                    report.sent - report.errors.len(),
+                    report.sent
+                );
+            }
+            Err(err) => eprintln!("{:#x}: {}", switch.datapath_id(), err),
+        });
+    }));
+    ctl::start_controller_on(addrs, config, |_| ())
+}
+
+/// reads one flow per line, empty lines and lines starting with # are skipped
+fn read_flows(path: &str) -> Result<Vec<FlowMod>, String> {
+    let file = File::open(path).map_err(|err| format!("{}: {}", path, err))?;
+    let mut flows = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|err| format!("{}: {}", path, err))?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
         }
-    }).expect("error in controller");
+        let flow =
+            parse_flow_mod(line).map_err(|err| format!("{}:{}: {}", path, number + 1, err))?;
+        flows.push(flow);
+    }
+    Ok(flows)
 }